@@ -0,0 +1,102 @@
+extern crate cauldron;
+
+use cauldron::audio::AudioSegment;
+use cauldron::batch;
+use cauldron::format::DynamicBufReader;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::fs;
+use std::path::PathBuf;
+
+/// A mono 16 bit WAV file holding `samples` zero samples.
+fn wav_file(samples: u32) -> Vec<u8> {
+    let data_len = samples * 2;
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(36 + data_len).to_le_bytes());
+    file.extend_from_slice(b"WAVEfmt ");
+    file.extend_from_slice(&16u32.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&44_100u32.to_le_bytes());
+    file.extend_from_slice(&88_200u32.to_le_bytes());
+    file.extend_from_slice(&2u16.to_le_bytes());
+    file.extend_from_slice(&16u16.to_le_bytes());
+    file.extend_from_slice(b"data");
+    file.extend_from_slice(&data_len.to_le_bytes());
+    file.resize(file.len() + data_len as usize, 0);
+    file
+}
+
+/// Writes `count` small files to a fresh temporary directory.
+fn generate_files(count: usize) -> (PathBuf, Vec<PathBuf>) {
+    let dir = std::env::temp_dir().join(format!("cauldron-batch-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let wav = wav_file(4096);
+    let paths = (0..count)
+        .map(|i| {
+            let path = dir.join(format!("{}.wav", i));
+            fs::write(&path, &wav).unwrap();
+            path
+        })
+        .collect();
+    (dir, paths)
+}
+
+fn bench_probe_all(c: &mut Criterion) {
+    let (dir, paths) = generate_files(2000);
+
+    let mut group = c.benchmark_group("batch");
+    for &parallelism in &[1, 4, 8] {
+        group.bench_function(format!("probe_all_{}_threads", parallelism), |b| {
+            b.iter(|| batch::probe_all(black_box(paths.clone()), parallelism))
+        });
+    }
+    group.finish();
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Compares the ways of finding the format of many small files, on one thread.
+fn bench_probe_small_files(c: &mut Criterion) {
+    let (dir, paths) = generate_files(1000);
+
+    let mut group = c.benchmark_group("probe_1000_files");
+    group.bench_function("audio_segment_read", |b| {
+        b.iter(|| {
+            for path in &paths {
+                black_box(AudioSegment::read(path.to_str().unwrap()).unwrap());
+            }
+        })
+    });
+    group.bench_function("probe", |b| {
+        b.iter(|| {
+            for path in &paths {
+                black_box(batch::probe(path).unwrap());
+            }
+        })
+    });
+    group.bench_function("probe_with_buffer", |b| {
+        let mut buffer = Vec::new();
+        b.iter(|| {
+            for path in &paths {
+                black_box(batch::probe_with_buffer(path, &mut buffer).unwrap());
+            }
+        })
+    });
+    group.bench_function("detect_format", |b| {
+        let mut buffer = Vec::new();
+        b.iter(|| {
+            for path in &paths {
+                let file = fs::File::open(path).unwrap();
+                let mut input = DynamicBufReader::with_buffer(file, std::mem::take(&mut buffer));
+                black_box(cauldron::detect_format(&mut input).unwrap());
+                buffer = input.recycle();
+            }
+        })
+    });
+    group.finish();
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+criterion_group!(benches, bench_probe_all, bench_probe_small_files);
+criterion_main!(benches);
@@ -1,19 +1,75 @@
 extern crate cauldron;
 
-use cauldron::audio::AudioSegment;
+use cauldron::audio::{AudioSegment, DecodeOptions};
+use cauldron::codecs::FormatFlag;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::path::Path;
 use std::time::Duration;
 
 fn decode(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
     let mut audio_seg = AudioSegment::read(filename)?;
     let mut samples = audio_seg.samples::<i16>()?;
 
-    loop {
-        match samples.next() {
-            None => break,
-            Some(_) => {}
-        }
-    }
+    while samples.next().is_some() {}
+
+    Ok(())
+}
+
+fn decode_left_channel(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut audio_seg = AudioSegment::read(filename)?;
+    let mut samples = audio_seg.channel_samples::<i16>(&[0])?;
+
+    while samples.next().is_some() {}
+
+    Ok(())
+}
+
+/// Decodes to 32 bit samples, which hold those of more than 16 bits
+fn decode_i32(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut audio_seg = AudioSegment::read(filename)?;
+    let mut samples = audio_seg.samples::<i32>()?;
+
+    while samples.next().is_some() {}
+
+    Ok(())
+}
+
+/// Decodes with `DecodeStats` collection, to compare its overhead with `decode`
+fn decode_with_stats(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let options = DecodeOptions {
+        decode_stats: true,
+        ..Default::default()
+    };
+    let mut audio_seg = AudioSegment::read_with_options(filename, FormatFlag::FLAC, options)?;
+    let mut samples = audio_seg.samples::<i16>()?;
+
+    while samples.next().is_some() {}
+    drop(samples);
+    black_box(audio_seg.decode_stats());
+
+    Ok(())
+}
+
+/// Decodes with `Instrumentation`, its overhead against `decode_i32` should stay below 1%
+fn decode_instrumented(filename: &str, flag: FormatFlag) -> Result<(), Box<dyn std::error::Error>> {
+    let options = DecodeOptions {
+        instrumentation: true,
+        ..Default::default()
+    };
+    let mut audio_seg = AudioSegment::read_with_options(filename, flag, options)?;
+    let mut samples = audio_seg.samples::<i32>()?;
+
+    while samples.next().is_some() {}
+    drop(samples);
+    black_box(audio_seg.instrumentation());
+
+    Ok(())
+}
+
+/// Decodes without samples, to compare `verify` with `decode`
+fn verify(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut audio_seg = AudioSegment::read(filename)?;
+    black_box(audio_seg.verify()?);
 
     Ok(())
 }
@@ -27,8 +83,61 @@ fn bench_decode(c: &mut Criterion) {
     group.bench_function("decode_flac", |b| {
         b.iter(|| decode(black_box("benchmark/MLKDream.flac")))
     });
+    group.bench_function("decode_flac_stats", |b| {
+        b.iter(|| decode_with_stats(black_box("benchmark/MLKDream.flac")))
+    });
+    group.bench_function("decode_flac_i32", |b| {
+        b.iter(|| decode_i32(black_box("benchmark/MLKDream.flac")))
+    });
+    group.bench_function("decode_flac_instrumented", |b| {
+        b.iter(|| decode_instrumented(black_box("benchmark/MLKDream.flac"), FormatFlag::FLAC))
+    });
+    group.bench_function("decode_flac_left_channel", |b| {
+        b.iter(|| decode_left_channel(black_box("benchmark/MLKDream.flac")))
+    });
+    group.bench_function("verify_flac", |b| {
+        b.iter(|| verify(black_box("benchmark/MLKDream.flac")))
+    });
+    group.finish();
+}
+
+/// The fixtures `cargo test` generates, see `benches/README.md`
+const GENERATED: [&str; 5] = [
+    "stereo_24_96k.wav",
+    "stereo_24_96k.flac",
+    "surround_51_16.wav",
+    "octo_16_48k.flac",
+    "stereo_16_44k_lpc32.flac",
+];
+
+fn bench_generated(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generated");
+    group.sample_size(20).measurement_time(Duration::new(10, 0));
+    for name in &GENERATED {
+        let filename = format!("benchmark/generated/{}", name);
+        if !Path::new(&filename).exists() {
+            eprintln!(
+                "skipping {}, run `CAULDRON_BENCH_FIXTURES=benchmark/generated \
+                 cargo test --all-features generate_bench_fixtures` to generate it",
+                filename
+            );
+            continue;
+        }
+        group.bench_function(format!("decode_{}", name.replace('.', "_")), |b| {
+            b.iter(|| decode_i32(black_box(&filename)))
+        });
+        let flag = if name.ends_with(".flac") {
+            FormatFlag::FLAC
+        } else {
+            FormatFlag::WAV
+        };
+        let instrumented = format!("decode_{}_instrumented", name.replace('.', "_"));
+        group.bench_function(instrumented, |b| {
+            b.iter(|| decode_instrumented(black_box(&filename), flag))
+        });
+    }
     group.finish();
 }
 
-criterion_group!(benches, bench_decode);
+criterion_group!(benches, bench_decode, bench_generated);
 criterion_main!(benches);
@@ -0,0 +1,44 @@
+//! Converts an audio file to WAV
+//!
+//! ```text
+//! cargo run --example cauldron-convert -- input.flac out.wav
+//! ffmpeg -i input.ogg -f wav - | cargo run --example cauldron-convert -- - out.wav
+//! ```
+//!
+//! An input of `-` is read from standard input, its format is detected from
+//! the first bytes.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::process;
+
+use cauldron::audio::AudioSegment;
+use cauldron::dither::Dither;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: cauldron-convert <input|-> <output.wav>");
+        process::exit(2);
+    }
+
+    if let Err(err) = convert(&args[0], &args[1]) {
+        eprintln!("cauldron-convert: {}", err);
+        process::exit(1);
+    }
+}
+
+fn convert(input: &str, output: &str) -> cauldron::Result<()> {
+    let mut segment = if input == "-" {
+        AudioSegment::read_detected(io::stdin())?
+    } else {
+        AudioSegment::read(input)?
+    };
+    eprintln!("{}", segment);
+
+    let codec = segment.default_wav_codec();
+    let mut writer = BufWriter::new(File::create(output)?);
+    let samples = segment.write_wav(&mut writer, codec, Dither::default())?;
+    eprintln!("wrote {} samples to {}", samples, output);
+    Ok(())
+}
@@ -0,0 +1,259 @@
+/// A source of bytes, read one at a time
+pub trait ByteSource {
+    /// The error returned if no byte can be read
+    type Error;
+
+    /// Reads the next byte.
+    fn read_u8(&mut self) -> Result<u8, Self::Error>;
+}
+
+impl<B: ByteSource + ?Sized> ByteSource for &mut B {
+    type Error = B::Error;
+
+    #[inline(always)]
+    fn read_u8(&mut self) -> Result<u8, B::Error> {
+        (**self).read_u8()
+    }
+}
+
+/// The error of reading past the end of a byte slice
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EndOfInput;
+
+impl core::fmt::Display for EndOfInput {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("Failed to read enough bytes.")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EndOfInput {}
+
+impl ByteSource for &[u8] {
+    type Error = EndOfInput;
+
+    #[inline(always)]
+    fn read_u8(&mut self) -> Result<u8, EndOfInput> {
+        match self.split_first() {
+            Some((byte, rest)) => {
+                *self = rest;
+                Ok(*byte)
+            }
+            None => Err(EndOfInput),
+        }
+    }
+}
+
+/// Wraps a `ByteSource` to facilitate reading that is not byte-aligned.
+pub struct BitReader<B: ByteSource> {
+    /// The source where bits are read from.
+    source: B,
+    /// Data read from the source, but not yet fully consumed.
+    data: u8,
+    /// The number of bits of `data` that have not been consumed.
+    bits_left: u32,
+}
+
+impl<B: ByteSource> BitReader<B> {
+    /// creates a new bit reader
+    pub fn new(source: B) -> BitReader<B> {
+        BitReader {
+            source,
+            data: 0,
+            bits_left: 0,
+        }
+    }
+
+    /// Returns the source, the bits left of a partially read byte are lost
+    pub fn into_inner(self) -> B {
+        self.source
+    }
+    /// Returns true if no bits are left and input is in byte aligned state
+    #[inline(always)]
+    pub fn is_aligned(&self) -> bool {
+        self.bits_left == 0
+    }
+
+    /// Reads a single bit
+    #[inline(always)]
+    pub fn read_bit(&mut self) -> Result<bool, B::Error> {
+        // If no bits are left, we will need to read the next byte.
+        let result = if self.bits_left == 0 {
+            let fresh_byte = self.source.read_u8()?;
+
+            // What remains later are the 7 least significant bits.
+            self.data = fresh_byte << 1;
+            self.bits_left = 7;
+
+            // What we report is the most significant bit of the fresh byte.
+            fresh_byte & 0b1000_0000
+        } else {
+            // Consume the most significant bit of the buffer byte.
+            let bit = self.data & 0b1000_0000;
+            self.data <<= 1;
+            self.bits_left -= 1;
+            bit
+        };
+
+        Ok(result != 0)
+    }
+
+    /// Reads at most 8 bits.
+    #[inline(always)]
+    pub fn read_len_u8(&mut self, bits: u32) -> Result<u8, B::Error> {
+        // If not enough bits left, we will need to read the next byte.
+        let result = if self.bits_left < bits {
+            // Most significant bits are shifted to the right position already.
+            let msb = self.data;
+
+            // Read a single byte.
+            self.data = self.source.read_u8()?;
+
+            // From the next byte, we take the additional bits that we need.
+            // Those start at the most significant bit, so we need to shift so
+            // that it does not overlap with what we have already.
+            let lsb = (self.data & Self::mask_u8(bits - self.bits_left)) >> self.bits_left;
+
+            // Shift out the bits that we have consumed.
+            self.data = Self::shift_left(self.data, bits - self.bits_left);
+            self.bits_left = 8 - (bits - self.bits_left);
+
+            msb | lsb
+        } else {
+            let result = self.data & Self::mask_u8(bits);
+
+            // Shift out the bits that we have consumed.
+            self.data <<= bits;
+            self.bits_left -= bits;
+
+            result
+        };
+
+        // The resulting data is padded with zeros in the least significant
+        // bits, but we want to pad in the most significant bits, so shift.
+        Ok(Self::shift_right(result, 8 - bits))
+    }
+
+    /// Reads at most 16 bits.
+    #[inline(always)]
+    pub fn read_len_u16(&mut self, bits: u32) -> Result<u16, B::Error> {
+        // Note: the following is not the most efficient implementation
+        // possible, but it avoids duplicating the complexity of `read_len_u8`.
+
+        if bits <= 8 {
+            let result = self.read_len_u8(bits)?;
+            Ok(result as u16)
+        } else {
+            // First read the 8 most significant bits, then read what is left.
+            let msb = self.read_len_u8(8)? as u16;
+            let lsb = self.read_len_u8(bits - 8)? as u16;
+            Ok((msb << (bits - 8)) | lsb)
+        }
+    }
+
+    /// Reads at most 32 bits.
+    ///
+    /// This is only checked in debug builds, callers validate `bits` where it is read.
+    #[inline(always)]
+    pub fn read_len_u32(&mut self, bits: u32) -> Result<u32, B::Error> {
+        // As with read_len_u8, this only makes sense if we read <= 32 bits.
+        debug_assert!(bits <= 32);
+
+        // Note: the following is not the most efficient implementation
+        // possible, but it avoids duplicating the complexity of `read_len_u8`.
+
+        if bits <= 16 {
+            let result = self.read_len_u16(bits)?;
+            Ok(result as u32)
+        } else {
+            // First read the 16 most significant bits, then read what is left.
+            let msb = self.read_len_u16(16)? as u32;
+            let lsb = self.read_len_u16(bits - 16)? as u32;
+            Ok((msb << (bits - 16)) | lsb)
+        }
+    }
+
+    /// Reads bits until a 1 is read, and returns the number of zeros read.
+    /// See here https://en.wikipedia.org/wiki/Unary_coding
+    #[inline(always)]
+    pub fn read_unary(&mut self) -> Result<u32, B::Error> {
+        // Count the zeroes already present in the buffer
+        // (counting from the most significant bit).
+        let mut n = self.data.leading_zeros();
+
+        // If the number of zeros plus the one following it was not more than
+        // the bytes left, then there is no need to look further.
+        if n < self.bits_left {
+            // save the bits left in data
+            self.data <<= n + 1;
+            self.bits_left -= n + 1;
+        } else {
+            // counter the case when no bits are left and data = 0
+            n = self.bits_left;
+
+            // Continue reading bytes until we encounter a one.
+            loop {
+                let fresh_byte = self.source.read_u8()?;
+                let zeros = fresh_byte.leading_zeros();
+                n += zeros;
+                if zeros < 8 {
+                    // We consumed the zeros, plus the one following it.
+                    self.bits_left = 8 - (zeros + 1);
+                    if zeros == 7 {
+                        self.data = 0;
+                    } else {
+                        self.data = fresh_byte << (zeros + 1);
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(n)
+    }
+
+    #[inline(always)]
+    pub fn skip_len_u8(&mut self, bits: u32) -> Result<(), B::Error> {
+        // If not enough bits left, we will need to read the next byte.
+        if self.bits_left < bits {
+            // Read a single byte.
+            self.data = self.source.read_u8()?;
+
+            // Shift out the bits that we have consumed.
+            self.data = Self::shift_left(self.data, bits - self.bits_left);
+            self.bits_left = 8 - (bits - self.bits_left);
+        } else {
+            // Shift out the bits that we have consumed.
+            self.data <<= bits;
+            self.bits_left -= bits;
+        }
+
+        Ok(())
+    }
+
+    // Generates a bitmask with 1s in the `bits` most significant bits.
+    #[inline(always)]
+    fn mask_u8(bits: u32) -> u8 {
+        debug_assert!(bits <= 8);
+
+        Self::shift_left(0xff, 8 - bits)
+    }
+
+    fn shift_left(x: u8, shift: u32) -> u8 {
+        debug_assert!(shift <= 8);
+
+        // We cannot shift a u8 by 8 or more, because Rust panics when shifting by
+        // the integer width. But we can definitely shift a u32.
+        ((x as u16) << shift) as u8
+    }
+
+    /// Right shift that does not panic when shifting by the integer width.
+    #[inline(always)]
+    fn shift_right(x: u8, shift: u32) -> u8 {
+        debug_assert!(shift <= 8);
+
+        // We cannot shift a u8 by 8 or more, because Rust panics when shifting by
+        // the integer width. But we can definitely shift a u32.
+        ((x as u32) >> shift) as u8
+    }
+}
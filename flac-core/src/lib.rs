@@ -0,0 +1,61 @@
+//! The core of the FLAC decoder of cauldron, without any dependency on `std`.
+//!
+//! It decodes the subframes of a frame from a `BitReader` over any
+//! `ByteSource`, e.g. a byte slice or a DMA buffer. The frame header, the
+//! checksums and the channel decorrelation are left to the caller, cauldron
+//! decodes whole streams on top of it.
+//!
+//! The crate is `no_std` unless the `std` feature is enabled, which only adds
+//! an implementation of `std::error::Error`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+mod bits;
+mod subframe;
+
+pub use bits::{BitReader, ByteSource, EndOfInput};
+pub use subframe::{
+    decode_constant, decode_fixed_linear, decode_lpc, decode_verbatim, ResidualStats,
+};
+
+use core::fmt;
+
+/// The errors of decoding a subframe, `E` is the error of the `ByteSource`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    /// The byte source failed.
+    Source(E),
+    /// The subframe is malformed.
+    Parse(&'static str),
+    /// The subframe uses a feature the decoder does not support.
+    Unsupported(&'static str),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Source(ref err) => err.fmt(f),
+            Error::Parse(msg) => write!(f, "Malformed stream encountered: {}", msg),
+            Error::Unsupported(msg) => write!(f, "Unsupported codec encountered: {}", msg),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Source(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The coding of one partition of a FLAC residual
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RicePartition {
+    /// residuals are rice coded with the given parameter
+    Rice(u32),
+    /// residuals are stored unencoded with the given number of bits
+    Escaped(u32),
+}
@@ -0,0 +1,610 @@
+use core::cmp;
+use core::num::Wrapping;
+
+#[cfg(test)]
+use crate::EndOfInput;
+use crate::{BitReader, ByteSource, Error, RicePartition};
+
+/// Receives the coding of a residual while it is decoded
+pub trait ResidualStats {
+    /// Called with the partition order before the partitions are decoded.
+    fn partition_order(&mut self, order: u8);
+
+    /// Called with the coding of each partition, in stream order.
+    fn partition(&mut self, partition: RicePartition);
+}
+
+type Result<T, B> = core::result::Result<T, Error<<B as ByteSource>::Error>>;
+
+/// For each sample in buffer value is same
+/// https://xiph.org/flac/format.html#subframe_constant
+pub fn decode_constant<B: ByteSource>(
+    bits: &mut BitReader<B>,
+    fr_bps: u32,
+    buffer: &mut [i32],
+) -> Result<(), B> {
+    check_bits_per_sample::<B>(fr_bps)?;
+    let sample = extend_sign_u32(bits.read_len_u32(fr_bps).map_err(Error::Source)?, fr_bps);
+
+    for b in buffer.iter_mut() {
+        *b = sample;
+    }
+    Ok(())
+}
+
+/// Samples are stored without any encoding
+/// https://xiph.org/flac/format.html#subframe_verbatim
+#[cold]
+pub fn decode_verbatim<B: ByteSource>(
+    bits: &mut BitReader<B>,
+    fr_bps: u32,
+    buffer: &mut [i32],
+) -> Result<(), B> {
+    check_bits_per_sample::<B>(fr_bps)?;
+    for b in buffer.iter_mut() {
+        *b = extend_sign_u32(bits.read_len_u32(fr_bps).map_err(Error::Source)?, fr_bps);
+    }
+    Ok(())
+}
+
+/// Fails unless `fr_bps` bits per sample are read into an `i32`
+///
+/// Subframes have from 1 bit per sample, with all others wasted, to 33 bits
+/// for the side channel of 32 bit audio, which is not supported.
+#[inline(always)]
+fn check_bits_per_sample<B: ByteSource>(fr_bps: u32) -> Result<(), B> {
+    match fr_bps {
+        0 => Err(Error::Parse("invalid subframe, no bits per sample left")),
+        1..=32 => Ok(()),
+        _ => Err(Error::Unsupported(
+            "subframes of more than 32 bits per sample are not supported",
+        )),
+    }
+}
+
+/// A prediction polynomial is used
+/// https://xiph.org/flac/format.html#subframe_fixed
+///
+/// If `predict` is false the residual is only consumed, and buffer is left
+/// with the unpredicted residual.
+pub fn decode_fixed_linear<B: ByteSource>(
+    bits: &mut BitReader<B>,
+    fr_bps: u32,
+    order: usize,
+    buffer: &mut [i32],
+    predict: bool,
+    stats: Option<&mut dyn ResidualStats>,
+) -> Result<(), B> {
+    // The length of the buffer must be greater than order
+    // because the number of warm-up samples is equal to order, and at least
+    // one sample has to be predicted.
+    if order >= buffer.len() {
+        return Err(Error::Parse(
+            "invalid fixed subframe, order is not less than block size",
+        ));
+    }
+    // There are order * bits per sample unencoded warm-up sample bits.
+    decode_verbatim(bits, fr_bps, &mut buffer[..order])?;
+
+    // decode residual
+    decode_residual(bits, buffer.len(), &mut buffer[order..], stats)?;
+
+    // based on polynomial fix the samples
+    if predict {
+        fixed_predict(order, buffer);
+    }
+
+    Ok(())
+}
+
+/// https://xiph.org/flac/format.html#subframe_lpc
+///
+/// If `predict` is false the residual is only consumed, as for `decode_fixed_linear`.
+pub fn decode_lpc<B: ByteSource>(
+    bits: &mut BitReader<B>,
+    fr_bps: u32,
+    order: usize,
+    buffer: &mut [i32],
+    predict: bool,
+    stats: Option<&mut dyn ResidualStats>,
+) -> Result<(), B> {
+    // The length of the buffer must be greater than order
+    // because the number of warm-up samples is equal to order, and at least
+    // one sample has to be predicted.
+    if order >= buffer.len() {
+        return Err(Error::Parse(
+            "invalid lpc subframe, order is not less than block size",
+        ));
+    }
+    // There are order * bits per sample unencoded warm-up sample bits.
+    decode_verbatim(bits, fr_bps, &mut buffer[..order])?;
+
+    let qlpc_precision = bits.read_len_u8(4).map_err(Error::Source)? as u32 + 1;
+    if qlpc_precision > 15 {
+        return Err(Error::Parse("invalid lpc subframe, qlpc value invalid"));
+    }
+    let qlpc_shift = extend_sign_u16(bits.read_len_u8(5).map_err(Error::Source)? as u16, 5);
+
+    // The spec does allow the qlp shift to be negative, but in real it happens
+    // very less, hence not supported for now.
+    if qlpc_shift < 0 {
+        return Err(Error::Unsupported(
+            "negative quantized linear predictor coefficient shift not supported",
+        ));
+    }
+
+    // Now read the lpc coefficients
+    let mut coefficients = [0; 32];
+    for coef in coefficients[..order].iter_mut().rev() {
+        // We can safely read into a u16, qlpc_precision is at most 15.
+        *coef = extend_sign_u16(
+            bits.read_len_u16(qlpc_precision).map_err(Error::Source)?,
+            qlpc_precision,
+        );
+    }
+
+    // decode residual
+    decode_residual(bits, buffer.len(), &mut buffer[order..], stats)?;
+
+    if !predict {
+        return Ok(());
+    }
+
+    if order <= 12 {
+        predict_lpc_low_order(&coefficients[..order], qlpc_shift, buffer);
+    } else {
+        predict_lpc_high_order(&coefficients[..order], qlpc_shift, buffer);
+    }
+
+    Ok(())
+}
+
+/// Given a signed two's complement integer in the `bits` least significant
+/// bits of `val`, extends the sign bit to a valid 32-bit signed integer.
+#[inline(always)]
+fn extend_sign_u32(val: u32, bits: u32) -> i32 {
+    // First shift the value so the desired sign bit is the actual sign bit,
+    // then convert to a signed integer, and then do an arithmetic shift back,
+    // which will extend the sign bit.
+    ((val << (32 - bits)) as i32) >> (32 - bits)
+}
+
+/// Given a signed two's complement integer in the `bits` least significant
+/// bits of `val`, extends the sign bit to a valid 16-bit signed integer.
+#[inline(always)]
+fn extend_sign_u16(val: u16, bits: u32) -> i16 {
+    // First shift the value so the desired sign bit is the actual sign bit,
+    // then convert to a signed integer, and then do an arithmetic shift back,
+    // which will extend the sign bit.
+    ((val << (16 - bits)) as i16) >> (16 - bits)
+}
+
+/// Decodes a signed number from Rice coding to the two's complement.
+///
+/// The Rice coding used by FLAC operates on unsigned integers, but the
+/// residual is signed. The mapping is done as follows:
+///
+///  0 -> 0
+/// -1 -> 1
+///  1 -> 2
+/// -2 -> 3
+///  2 -> 4
+///  etc.
+///
+/// This function takes the unsigned value and converts it into a signed
+/// number.
+#[inline(always)]
+fn rice_to_signed(val: u32) -> i32 {
+    // The following bit-level hackery compiles to only four instructions on
+    // x64. It is equivalent to the following code:
+    //
+    //   if val & 1 == 1 {
+    //       -1 - (val / 2) as i32
+    //   } else {
+    //       (val / 2) as i32
+    //   }
+    //
+    let half = (val >> 1) as i32;
+    let extended_bit_0 = ((val << 31) as i32) >> 31;
+    half ^ extended_bit_0
+}
+
+#[test]
+fn test_rice_to_signed() {
+    assert_eq!(rice_to_signed(0), 0);
+    assert_eq!(rice_to_signed(1), -1);
+    assert_eq!(rice_to_signed(2), 1);
+    assert_eq!(rice_to_signed(3), -2);
+    assert_eq!(rice_to_signed(4), 2);
+}
+
+fn fixed_predict(order: usize, buffer: &mut [i32]) {
+    // The Fixed Predictor is just a hard-coded version of the Linear Predictor up to order 4 and
+    // with fixed coefficients. Some cases may be simplified such as orders 0 and 1. For orders 2
+    // through 4, use the same IIR-style algorithm as the Linear Predictor. Samples out of range
+    // wrap around like those of the Linear Predictor, instead of overflowing.
+    match order {
+        // A 0th order predictor always predicts 0, and therefore adds nothing to
+        // any sample in buffer.
+        0 => (),
+        // A 1st order predictor always returns the previous sample since the polynomial is:
+        // s(i) = 1*s(i),
+        1 => {
+            for i in 1..buffer.len() {
+                buffer[i] = buffer[i].wrapping_add(buffer[i - 1]);
+            }
+        }
+        // A 2nd order predictor uses the polynomial: s(i) = 2*s(i-1) - 1*s(i-2).
+        2 => {
+            for i in 2..buffer.len() {
+                let a = Wrapping(-1) * Wrapping(i64::from(buffer[i - 2]));
+                let b = Wrapping(2) * Wrapping(i64::from(buffer[i - 1]));
+                buffer[i] = buffer[i].wrapping_add((a + b).0 as i32);
+            }
+        }
+        // A 3rd order predictor uses the polynomial: s(i) = 3*s(i-1) - 3*s(i-2) + 1*s(i-3).
+        3 => {
+            for i in 3..buffer.len() {
+                let a = Wrapping(1) * Wrapping(i64::from(buffer[i - 3]));
+                let b = Wrapping(-3) * Wrapping(i64::from(buffer[i - 2]));
+                let c = Wrapping(3) * Wrapping(i64::from(buffer[i - 1]));
+                buffer[i] = buffer[i].wrapping_add((a + b + c).0 as i32);
+            }
+        }
+        // A 4th order predictor uses the polynomial:
+        // s(i) = 4*s(i-1) - 6*s(i-2) + 4*s(i-3) - 1*s(i-4).
+        4 => {
+            for i in 4..buffer.len() {
+                let a = Wrapping(-1) * Wrapping(i64::from(buffer[i - 4]));
+                let b = Wrapping(4) * Wrapping(i64::from(buffer[i - 3]));
+                let c = Wrapping(-6) * Wrapping(i64::from(buffer[i - 2]));
+                let d = Wrapping(4) * Wrapping(i64::from(buffer[i - 1]));
+                buffer[i] = buffer[i].wrapping_add((a + b + c + d).0 as i32);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Decodes the residual of a predicted subframe
+///
+/// `buffer` holds the samples after the warm-up samples, `block_size` counts
+/// the warm-up samples as well.
+fn decode_residual<B: ByteSource>(
+    bits: &mut BitReader<B>,
+    block_size: usize,
+    buffer: &mut [i32],
+    mut stats: Option<&mut dyn ResidualStats>,
+) -> Result<(), B> {
+    let param_width = match bits.read_len_u8(2).map_err(Error::Source)? {
+        0 => 4u32,
+        1 => 5u32,
+        _ => return Err(Error::Unsupported("Encountered reserved bits in residual")),
+    };
+
+    let partition_order = bits.read_len_u8(4).map_err(Error::Source)?;
+
+    // there are at most 2^16 - 1 samples in the block
+    let num_partitions = 1usize << partition_order;
+
+    // In general, all partitions have the same number of samples such that the
+    // sum of all partition lengths equal the block length. Thus, the number of samples
+    // in a partition can therefore be calculated with block_size / 2^order.
+    let num_samples_per_partition = block_size >> partition_order;
+
+    // total samples from each partition should be total block size
+    // So block size should be multiple of 2^order
+    if block_size & (num_partitions - 1) != 0 {
+        return Err(Error::Parse("invalid partition order in residual"));
+    }
+    let num_warm_up = match block_size.checked_sub(buffer.len()) {
+        Some(num_warm_up) => num_warm_up,
+        None => return Err(Error::Parse("residual is longer than the block")),
+    };
+
+    // first partition contains (num_samples_per_partition - num of warm up samples) >= 0
+    // samples, it must not be negative
+    let first_len = match num_samples_per_partition.checked_sub(num_warm_up) {
+        Some(len) => len,
+        None => return Err(Error::Parse("invalid residual, partition order too large")),
+    };
+
+    if let Some(ref mut stats) = stats {
+        stats.partition_order(partition_order);
+    }
+
+    // finally decode rice on each 2^order partitions
+    {
+        let escape_param = (1 << param_width) - 1;
+        let mut start = 0;
+        let mut len = first_len;
+        for _ in 0..num_partitions {
+            let rice_param = bits.read_len_u8(param_width).map_err(Error::Source)? as u32;
+            let partition = decode_rice_partition(
+                bits,
+                rice_param,
+                escape_param,
+                &mut buffer[start..start + len],
+            )?;
+            if let Some(ref mut stats) = stats {
+                stats.partition(partition);
+            }
+            start += len;
+            len = num_samples_per_partition;
+        }
+    }
+
+    Ok(())
+}
+
+fn decode_rice_partition<B: ByteSource>(
+    bits: &mut BitReader<B>,
+    rice_param: u32,
+    escape_param: u32,
+    buffer: &mut [i32],
+) -> Result<RicePartition, B> {
+    // If rice param is 1111 or 11111 then stream is rice encoded else
+    // it is binary encoded.
+    if rice_param < escape_param {
+        // rice encoded
+        //
+        // Depending on the number of bits, at most two or three bytes need to be
+        // read, so the code below is split into two cases for efficiency
+        if rice_param <= 8 {
+            for sample in buffer.iter_mut() {
+                let q = bits.read_unary().map_err(Error::Source)?;
+                let r = bits.read_len_u8(rice_param).map_err(Error::Source)? as u32;
+                *sample = rice_to_signed((q << rice_param) | r);
+            }
+        } else if rice_param <= 16 {
+            for sample in buffer.iter_mut() {
+                let q = bits.read_unary().map_err(Error::Source)?;
+                let r = bits.read_len_u16(rice_param).map_err(Error::Source)? as u32;
+                *sample = rice_to_signed((q << rice_param) | r);
+            }
+        } else {
+            for sample in buffer.iter_mut() {
+                let q = bits.read_unary().map_err(Error::Source)?;
+                let r = bits.read_len_u32(rice_param).map_err(Error::Source)?;
+                *sample = rice_to_signed((q << rice_param) | r);
+            }
+        }
+        Ok(RicePartition::Rice(rice_param))
+    } else {
+        // binary encoded
+        let residual_bits = bits.read_len_u8(5).map_err(Error::Source)? as u32;
+
+        // Residuals of 0 bits are all 0, and take no bits at all.
+        if residual_bits == 0 {
+            buffer.iter_mut().for_each(|sample| *sample = 0);
+            return Ok(RicePartition::Escaped(0));
+        }
+
+        // Read each binary encoded residual and store in buffer.
+        for sample in buffer.iter_mut() {
+            *sample = extend_sign_u32(
+                bits.read_len_u32(residual_bits).map_err(Error::Source)?,
+                residual_bits,
+            );
+        }
+        Ok(RicePartition::Escaped(residual_bits))
+    }
+}
+
+/// Apply LPC prediction for subframes with LPC order of at most 12.
+///
+/// This function takes advantage of the upper bound on the order. Virtually all
+/// files that occur in the wild are subset-compliant files, which have an order
+/// of at most 12, so it makes sense to optimize for this.
+fn predict_lpc_low_order(raw_coefficients: &[i16], qlp_shift: i16, buffer: &mut [i32]) {
+    // The decoded residuals are 25 bits at most (assuming subset FLAC of at
+    // most 24 bits per sample, but there is the delta encoding for channels).
+    // The coefficients are 16 bits at most, so their product is 41 bits. In
+    // practice the predictor order does not exceed 12, so adding 12 numbers of
+    // 41 bits each requires at most 53 bits. Therefore, do all intermediate
+    // computations as i64.
+    //
+    // If the actual order is less than 12, simply set the early coefficients to 0.
+    let order = raw_coefficients.len();
+    let coefficients = {
+        let mut buf = [0i64; 12];
+        for (b, c) in buf[12 - order..].iter_mut().zip(raw_coefficients) {
+            *b = *c as i64;
+        }
+        buf
+    };
+
+    // The linear prediction is essentially an inner product of the known
+    // samples with the coefficients, followed by a shift. To be able to do an
+    // inner product of 12 elements at a time, we must first have 12 samples.
+    // If the predictor order is less, first predict the few samples after the
+    // warm-up samples.
+    let left = cmp::min(12, buffer.len()) - order;
+    for i in 0..left {
+        let prediction = raw_coefficients
+            .iter()
+            .zip(&buffer[i..order + i])
+            .map(|(&c, &s)| c as i64 * s as i64)
+            .sum::<i64>()
+            >> qlp_shift;
+        // adding linear prediction to residual decoded buffer
+        buffer[order + i] = (prediction + buffer[order + i] as i64) as i32;
+    }
+
+    if buffer.len() <= 12 {
+        return;
+    }
+
+    // At this point, buffer[0..12] has been predicted. For the rest of the
+    // buffer we can do inner products of 12 samples. This reduces the amount of
+    // conditional code, and improves performance significantly.
+    let mut sum;
+    for i in 12..buffer.len() {
+        sum = 0;
+        for j in 0..12 {
+            sum += buffer[i - 12 + j] as i64 * coefficients[j]
+        }
+        // adding linear prediction to residual decoded buffer
+        buffer[i] = ((sum >> qlp_shift) + buffer[i] as i64) as i32;
+    }
+}
+
+#[test]
+fn test_predict_lpc_low_order() {
+    let coef = [-77, 164, -219, 146, 38, 161, -895, 1151];
+    let shift = 9;
+    let mut buffer = [
+        3590, 3465, 2979, 2237, 1692, 1411, 900, 476, 188, -189, 49, 3, 37, 150, -353, -49,
+    ];
+    let result = [
+        3590, 3465, 2979, 2237, 1692, 1411, 900, 476, 187, -255, -688, -1146, -1455, -1428, -1567,
+        -1717,
+    ];
+
+    predict_lpc_low_order(&coef, shift, &mut buffer);
+
+    assert_eq!(buffer, result);
+}
+
+/// Apply LPC prediction for non-subset subframes, with LPC order > 12.
+fn predict_lpc_high_order(raw_coefficients: &[i16], qlp_shift: i16, buffer: &mut [i32]) {
+    // This function lifts the order restrictions of `predict_lpc_low_order`.
+    // Orders up to 32 add 5 bits to the products of 41 bits, so the
+    // intermediate computations fit in i64 here too.
+    //
+    // The coefficients are padded with zeros in front to the next multiple of
+    // 8, so that the inner product is done in chunks of 8 without a remainder.
+    let order = raw_coefficients.len();
+    let len = order.next_multiple_of(8);
+    let mut padded = [0i64; 32];
+    for (c, raw) in padded[len - order..len].iter_mut().zip(raw_coefficients) {
+        *c = *raw as i64;
+    }
+    let coefficients = &padded[..len];
+
+    // The first `order` samples are stored as-is. The samples before there
+    // are enough for the padded inner product are predicted one by one.
+    for i in order..cmp::min(len, buffer.len()) {
+        let prediction = coefficients[len - order..]
+            .iter()
+            .zip(&buffer[i - order..i])
+            .map(|(&c, &s)| c * s as i64)
+            .sum::<i64>()
+            >> qlp_shift;
+        buffer[i] = (prediction + buffer[i] as i64) as i32;
+    }
+
+    // The products are accumulated in 8 independent sums, which the compiler
+    // can vectorize.
+    for i in len..buffer.len() {
+        let mut sums = [0i64; 8];
+        for (c, s) in coefficients
+            .chunks_exact(8)
+            .zip(buffer[i - len..i].chunks_exact(8))
+        {
+            for k in 0..8 {
+                sums[k] += c[k] * s[k] as i64;
+            }
+        }
+        let prediction = sums.iter().sum::<i64>() >> qlp_shift;
+        // adding linear prediction to residual decoded buffer
+        buffer[i] = (prediction + buffer[i] as i64) as i32;
+    }
+}
+
+#[test]
+fn test_predict_lpc_high_order_matches_low_order() {
+    let coef = [
+        -77, 164, -219, 146, 38, 161, -895, 1151, -412, 907, -1290, 1733,
+    ];
+    let mut signal = [0; 64];
+    for (i, s) in signal.iter_mut().enumerate() {
+        *s = ((i as i32 * 2731) % 7001) - 3500;
+    }
+    // every order of the low order path, with buffers shorter than the
+    // padded coefficients of the high order path too
+    for order in 1..=12 {
+        for &len in &[order, order + 1, 15, 16, 17, 64] {
+            let mut low = signal;
+            let mut high = signal;
+            predict_lpc_low_order(&coef[12 - order..], 10, &mut low[..len]);
+            predict_lpc_high_order(&coef[12 - order..], 10, &mut high[..len]);
+            assert_eq!(low, high, "order {}, {} samples", order, len);
+        }
+    }
+}
+
+#[test]
+fn test_predict_lpc_high_order() {
+    let coef = [
+        -1683, 1794, -1126, -1408, -759, 860, 1687, -1026, 32, -1901, 950, -1843, -1590, -670, 84,
+        -647, 1453, -1767, 141, 828, 1869, 1611, -80, -481, 462, -1997, 221, 1093, 1201, -1483,
+        149, -1962,
+    ];
+    let shift = 12;
+    let mut buffer = [
+        1815, 17886, 20658, 25236, -16699, 24825, -11137, -16784, -755, -24659, -8099, -22087,
+        -27696, 4436, -22665, 17688, -19545, -1240, -28683, -1729, -26767, -27493, 6408, -6864,
+        -15138, -19301, 671, -9706, 17738, 2269, -19024, 5038, 6, 282, 289, 289, -216, -267, -3,
+        -66,
+    ];
+    let mut result = buffer;
+    result[32..].copy_from_slice(&[3270, 9688, -7891, -8598, 5814, 10079, -15870, 56183]);
+
+    predict_lpc_high_order(&coef, shift, &mut buffer);
+
+    assert_eq!(buffer, result);
+}
+
+#[test]
+fn test_decode_from_slice() {
+    let mut bits = BitReader::new(&[0b1110_0000, 0b1000_0001, 0b0111_1111][..]);
+    let mut buffer = [0; 4];
+    decode_constant(&mut bits, 4, &mut buffer).unwrap();
+    assert_eq!(buffer, [-2; 4]);
+
+    // 4 bits are left of the first byte, the slice ends in the fourth sample
+    let mut buffer = [0; 3];
+    decode_verbatim(&mut bits, 5, &mut buffer[..2]).unwrap();
+    assert_eq!(buffer[..2], [1, 0]);
+    assert_eq!(
+        decode_verbatim(&mut bits, 5, &mut buffer),
+        Err(Error::Source(EndOfInput))
+    );
+}
+
+#[test]
+fn test_invalid_bits_per_sample() {
+    let data = [0xff; 8];
+    let mut buffer = [0; 1];
+    for &(fr_bps, is_parse_error) in &[(0, true), (33, false)] {
+        let mut bits = BitReader::new(&data[..]);
+        let result = decode_verbatim(&mut bits, fr_bps, &mut buffer);
+        assert_eq!(matches!(result, Err(Error::Parse(_))), is_parse_error);
+        assert!(result.is_err());
+        let mut bits = BitReader::new(&data[..]);
+        assert!(decode_constant(&mut bits, fr_bps, &mut buffer).is_err());
+    }
+    let mut bits = BitReader::new(&data[..]);
+    decode_verbatim(&mut bits, 32, &mut buffer).unwrap();
+    assert_eq!(buffer, [-1]);
+}
+
+#[test]
+fn test_escaped_partition_of_zero_bits() {
+    // rice coding, partition order 0, escape code and residuals of 0 bits
+    let data = [0b0000_0011, 0b1100_0000];
+    let mut bits = BitReader::new(&data[..]);
+    let mut buffer = [7; 4];
+    decode_fixed_linear(&mut bits, 16, 0, &mut buffer, true, None).unwrap();
+    assert_eq!(buffer, [0; 4]);
+}
+
+#[test]
+fn test_fixed_prediction_wraps() {
+    let mut buffer = [i32::MAX, 1, 1];
+    fixed_predict(1, &mut buffer);
+    assert_eq!(buffer, [i32::MAX, i32::MIN, i32::MIN + 1]);
+    let mut buffer = [i32::MIN, i32::MAX, i32::MAX, i32::MAX, 0];
+    fixed_predict(4, &mut buffer);
+}
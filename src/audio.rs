@@ -1,13 +1,39 @@
 //! `audio` is the main module for audio decoders.
+//!
+//! # Threads
+//!
+//! An `AudioSegment` owns its input and decoding state, it is `Send` so it can
+//! be moved to a decoding thread, but not `Sync`. What is known about a stream
+//! without decoding it, like the `AudioInfo`, is `Clone + Send + Sync`. To share
+//! it, split the segment with `AudioSegment::into_parts` into a
+//! `StreamDescription`, which is cheap to clone and can outlive the decoding,
+//! and a `Decoder` producing the samples.
 
 use bitflags::bitflags;
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use super::dither::{Dither, Requantizer};
+use super::format::{self, Format};
+use super::hash::{self, HashAlgo};
 use super::io::{
-    AudioInputStream, AudioReader, AudioSamplesIterator, IntoAudioInputStream, Sample,
+    frames_of, planar_len, read_only_stream, seekable_stream, AudioInputStream, AudioReader,
+    AudioSamplesIterator, IntoAudioInputStream, Sample, WriteBuffer,
 };
-use super::{codecs, errors, Result};
-use super::{flac, mp3, wav};
+use super::loudness::{self, Loudness};
+use super::overview::{self, MinMax};
+use super::pictures;
+use super::process::Pipeline;
+use super::stats::{
+    DecodeStats, FileStats, FrameStats, Instrumentation, InstrumentationHandle, SampleGap,
+    SubsetViolation, VerifyReport,
+};
+use super::stereo::{self, StereoStats};
+use super::tracks::{self, TrackRange};
+use super::{codecs, errors, g711, Result};
 
 bitflags! {
     /// Channels is a bit mask of all channels contained in a signal.
@@ -49,9 +75,47 @@ impl Channels {
     }
 }
 
+/// the short names of the speaker positions, as FFmpeg calls them, in the order of the bits
+const CHANNEL_NAMES: [(Channels, &str); 26] = [
+    (Channels::FRONT_LEFT, "FL"),
+    (Channels::FRONT_RIGHT, "FR"),
+    (Channels::FRONT_CENTRE, "FC"),
+    (Channels::BACK_LEFT, "BL"),
+    (Channels::BACK_CENTRE, "BC"),
+    (Channels::BACK_RIGHT, "BR"),
+    (Channels::LFE1, "LFE"),
+    (Channels::FRONT_LEFT_CENTRE, "FLC"),
+    (Channels::FRONT_RIGHT_CENTRE, "FRC"),
+    (Channels::BACK_LEFT_CENTRE, "BLC"),
+    (Channels::BACK_RIGHT_CENTRE, "BRC"),
+    (Channels::FRONT_LEFT_WIDE, "WL"),
+    (Channels::FRONT_RIGHT_WIDE, "WR"),
+    (Channels::FRONT_LEFT_HIGH, "FLH"),
+    (Channels::FRONT_CENTRE_HIGH, "FCH"),
+    (Channels::FRONT_RIGHT_HIGH, "FRH"),
+    (Channels::LFE2, "LFE2"),
+    (Channels::SIDE_LEFT, "SL"),
+    (Channels::SIDE_RIGHT, "SR"),
+    (Channels::TOP_CENTRE, "TC"),
+    (Channels::TOP_FRONT_LEFT, "TFL"),
+    (Channels::TOP_FRONT_CENTRE, "TFC"),
+    (Channels::TOP_FRONT_RIGHT, "TFR"),
+    (Channels::TOP_BACK_LEFT, "TBL"),
+    (Channels::TOP_BACK_CENTRE, "TBC"),
+    (Channels::TOP_BACK_RIGHT, "TBR"),
+];
+
+/// Lists the speaker positions, e.g. `FL+FR+LFE`, or `none`
 impl fmt::Display for Channels {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:#032b}", self.bits)
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names = CHANNEL_NAMES.iter().filter(|(c, _)| self.contains(*c));
+        for (i, (_, name)) in names.enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { "+" }, name)?;
+        }
+        Ok(())
     }
 }
 
@@ -82,6 +146,54 @@ pub enum ChannelLayout {
 }
 
 impl ChannelLayout {
+    /// Returns the layout of 1 to 8 channels stored without speaker positions
+    ///
+    /// These are the channel assignments of FLAC, which players assume for
+    /// WAV files with a plain fmt chunk as well.
+    #[cfg(any(feature = "wav", feature = "flac"))]
+    pub(crate) fn of_count(channels: usize) -> Option<ChannelLayout> {
+        match channels {
+            1 => Some(ChannelLayout::Mono),
+            2 => Some(ChannelLayout::Stereo),
+            3 => Some(ChannelLayout::ThreePointZero),
+            4 => Some(ChannelLayout::Quad),
+            5 => Some(ChannelLayout::FivePointZero),
+            6 => Some(ChannelLayout::FivePointOne),
+            7 => Some(ChannelLayout::SixPointOneBack),
+            8 => Some(ChannelLayout::SevenPointOne),
+            _ => None,
+        }
+    }
+
+    /// Returns the layout of the speakers in `channels`
+    ///
+    /// The layout of exactly these speakers is preferred, e.g. 2.1 over 3.0.
+    /// Other speakers get the layout of their count, 7 of them 6.1 if there
+    /// are side speakers and 6.1(back) otherwise. `None` for more than 8 channels.
+    #[cfg(feature = "wav")]
+    pub(crate) fn of_channels(channels: Channels) -> Option<ChannelLayout> {
+        const LAYOUTS: [ChannelLayout; 10] = [
+            ChannelLayout::Mono,
+            ChannelLayout::Stereo,
+            ChannelLayout::TwoPointOne,
+            ChannelLayout::ThreePointZero,
+            ChannelLayout::Quad,
+            ChannelLayout::FivePointZero,
+            ChannelLayout::FivePointOne,
+            ChannelLayout::SixPointOne,
+            ChannelLayout::SixPointOneBack,
+            ChannelLayout::SevenPointOne,
+        ];
+        if let Some(&layout) = LAYOUTS.iter().find(|l| l.into_channels() == channels) {
+            return Some(layout);
+        }
+        let sides = Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+        match channels.count() {
+            7 if channels.intersects(sides) => Some(ChannelLayout::SixPointOne),
+            count => ChannelLayout::of_count(count),
+        }
+    }
+
     /// Converts a channel `ChannelLayout` into a `Channels` bit mask.
     pub fn into_channels(self) -> Channels {
         match self {
@@ -146,14 +258,53 @@ impl ChannelLayout {
     }
 }
 
+/// The common name of the layout, e.g. `stereo` or `5.1`
 impl fmt::Display for ChannelLayout {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        let name = match self {
+            ChannelLayout::Mono => "mono",
+            ChannelLayout::Stereo => "stereo",
+            ChannelLayout::TwoPointOne => "2.1",
+            ChannelLayout::ThreePointZero => "3.0",
+            ChannelLayout::Quad => "quad",
+            ChannelLayout::FivePointZero => "5.0",
+            ChannelLayout::FivePointOne => "5.1",
+            ChannelLayout::SixPointOne => "6.1",
+            ChannelLayout::SixPointOneBack => "6.1(back)",
+            ChannelLayout::SevenPointOne => "7.1",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The highest sample rate in Hz a stream may declare
+///
+/// Higher rates are taken as corrupt headers and rejected by all readers.
+pub const MAX_SAMPLE_RATE: u32 = 6_144_000;
+
+/// The sample rate in Hz above which readers warn of an unusual stream
+pub const WARN_SAMPLE_RATE: u32 = 768_000;
+
+/// Checks the sample rate declared by a stream's headers against the bounds
+///
+/// 0 and rates above `MAX_SAMPLE_RATE` are parse errors, rates above
+/// `WARN_SAMPLE_RATE` only warn.
+#[cfg(any(feature = "wav", feature = "flac"))]
+pub(crate) fn check_sample_rate(sample_rate: u32) -> Result<()> {
+    if sample_rate == 0 {
+        return errors::parse_error("sample rate is 0");
     }
+    if sample_rate > MAX_SAMPLE_RATE {
+        return errors::parse_error("sample rate is above 6144 kHz");
+    }
+    if sample_rate > WARN_SAMPLE_RATE {
+        tracing::warn!(sample_rate, "unusually high sample rate");
+    }
+    Ok(())
 }
 
 /// AudioInfo stored in a container format's headers and metadata
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct AudioInfo {
     /// Codec of the audio
     pub codec_type: codecs::CodecType,
@@ -164,184 +315,3303 @@ pub struct AudioInfo {
     /// The length of the encoded stream in number of frames.
     pub total_samples: u64,
 
-    /// The number of bits per one decoded audio sample.
+    /// The number of bits per one decoded audio sample, 0 for lossy codecs.
     pub bits_per_sample: u32,
 
+    /// The number of bits one sample takes in the stream, 0 unless it is fixed.
+    ///
+    /// WAV files may store samples of fewer valid bits in wider containers,
+    /// e.g. 24 in 32 bits, whose valid bits are the most significant ones.
+    /// G.711 samples take 8 bits and decode to 16. Codecs of frames, like
+    /// FLAC and MP3, have no container.
+    pub container_bits: u32,
+
+    /// The type of the decoded samples, `SampleFormat::F32` for lossy codecs.
+    pub sample_format: codecs::SampleFormat,
+
     /// A list of in-order channels.
     pub channels: Channels,
 
     /// The channel layout.
     pub channel_layout: ChannelLayout,
+
+    /// Extents and checksum from the FLAC STREAMINFO block, only set for FLAC streams.
+    pub flac_stream_info: Option<FlacStreamInfo>,
+
+    /// The number of inter-channel samples the encoder added at the start, if known.
+    pub encoder_delay: Option<u32>,
+
+    /// The number of inter-channel samples the encoder added at the end, if known.
+    pub encoder_padding: Option<u32>,
+}
+
+impl AudioInfo {
+    /// Returns the sample type which holds the decoded samples as they are
+    ///
+    /// This is the type of `sample_format`: floats stay floats, which includes
+    /// lossy codecs like MP3, `S16` gets `i16` and `S24` and `S32` get `i32`.
+    /// Only unsigned 8 bit PCM gets `u8`, as signed 8 bit samples would wrap
+    /// around. Any other type may fail the bits per sample check of
+    /// `Sample::from_i32` or lose precision.
+    pub fn recommended_sample_type(&self) -> format::SampleType {
+        use format::SampleType;
+        match self.sample_format {
+            codecs::SampleFormat::U8 => SampleType::U8,
+            codecs::SampleFormat::S16 => SampleType::I16,
+            codecs::SampleFormat::S24 | codecs::SampleFormat::S32 => SampleType::I32,
+            codecs::SampleFormat::F32 => SampleType::F32,
+            codecs::SampleFormat::F64 => SampleType::F64,
+        }
+    }
+
+    /// Writes the parameters of the samples, e.g. `44.1 kHz 16-bit stereo`
+    ///
+    /// Parameters which are not known, like the sample rate of a raw MP3 stream
+    /// before its first frame, are left out.
+    fn write_parameters(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.sample_rate != 0 {
+            write!(f, " {} kHz", self.sample_rate as f64 / 1000.0)?;
+        }
+        match (self.sample_format.is_float(), self.bits_per_sample) {
+            (_, 0) => {}
+            (false, bits) => write!(f, " {}-bit", bits)?,
+            (true, bits) => write!(f, " {}-bit float", bits)?,
+        }
+        if self.channels.is_empty() {
+            Ok(())
+        } else if self.channels == self.channel_layout.into_channels() {
+            write!(f, " {}", self.channel_layout)
+        } else {
+            write!(f, " {} channels ({})", self.channels.count(), self.channels)
+        }
+    }
 }
 
+/// A one-line summary, e.g. `flac 44.1 kHz 16-bit stereo`, and every field with `{:#}`
 impl fmt::Display for AudioInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !f.alternate() {
+            write!(f, "{}", self.codec_type)?;
+            return self.write_parameters(f);
+        }
         writeln!(f, "| CodecType:             {}", self.codec_type)?;
         writeln!(f, "| Sample Rate:           {}", self.sample_rate)?;
-        writeln!(f, "| Bits per Sample:       {}", self.bits_per_sample)?;
-        writeln!(f, "| Channel(s):            {}", self.channels.count())?;
+        if self.bits_per_sample == 0 {
+            writeln!(f, "| Bits per Sample:       - (lossy)")?;
+        } else {
+            writeln!(f, "| Bits per Sample:       {}", self.bits_per_sample)?;
+        }
+        writeln!(f, "| Sample Format:         {}", self.sample_format)?;
+        writeln!(
+            f,
+            "| Channel(s):            {} ({})",
+            self.channels.count(),
+            self.channels
+        )?;
         writeln!(f, "| Channel Layout:        {:?}", self.channel_layout)?;
 
         Ok(())
     }
 }
 
-/// Type for sample iterator returned by `AudioSegment`
-pub type SampleIterator<'a, S> = Box<dyn AudioSamplesIterator<S> + 'a>;
+/// Properties of a FLAC stream stored in its STREAMINFO block
+/// https://xiph.org/flac/format.html#metadata_block_streaminfo
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FlacStreamInfo {
+    /// The minimum block size in samples used in the stream.
+    pub min_block_size: u16,
 
-/// `AudioSegment` is returned to user to perform various operations and get
-/// decoded stream, audio info or encode to different format.
-pub struct AudioSegment {
-    /// codec flag
-    codec_flag: codecs::FormatFlag,
+    /// The maximum block size in samples used in the stream.
+    pub max_block_size: u16,
 
-    /// audio info stored in a container format's headers and metadata
-    info: AudioInfo,
+    /// The minimum frame size in bytes used in the stream, 0 if unknown.
+    pub min_frame_size: u32,
 
-    /// audio reader
-    reader: Box<dyn AudioReader>,
+    /// The maximum frame size in bytes used in the stream, 0 if unknown.
+    pub max_frame_size: u32,
 
-    /// flag is set when samples iterator is returned
-    is_buffer_used: bool,
+    /// MD5 signature of the unencoded audio data, all zeros if not computed by the encoder.
+    pub md5: [u8; 16],
 }
 
-impl AudioSegment {
-    //noinspection TodoComment
-    //noinspection TodoComment
-    /// Constructs a new `AudioSegment`.
+impl fmt::Display for FlacStreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "| Block Size:            {} - {}",
+            self.min_block_size, self.max_block_size
+        )?;
+        writeln!(
+            f,
+            "| Frame Size:            {} - {}",
+            self.min_frame_size, self.max_frame_size
+        )?;
+        write!(f, "| MD5:                   ")?;
+        for byte in &self.md5 {
+            write!(f, "{:02x}", byte)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// A FLAC metadata block which was skipped while reading the header
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SkippedMetadata {
+    /// the block type, e.g. 1 for PADDING or 6 for PICTURE
+    pub block_type: u8,
+
+    /// offset of the block header from the start of the stream
+    pub offset: u64,
+
+    /// length of the block without its 4 byte header
+    pub len: u32,
+}
+
+/// A FLAC APPLICATION block, holding data of the application registered for `id`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApplicationBlock {
+    /// the registered application id, e.g. `b"CUES"` for CUETools
+    pub id: [u8; 4],
+
+    /// the data following the id
+    pub data: Vec<u8>,
+}
+
+/// The header bytes as they are stored, kept with `DecodeOptions::keep_raw_headers`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawHeaders {
+    /// the payload of the WAV fmt chunk, without its 8 byte header
+    pub fmt_chunk: Option<Vec<u8>>,
+
+    /// the FLAC metadata blocks in stream order, STREAMINFO first
+    pub metadata_blocks: Vec<RawMetadataBlock>,
+
+    /// the WAV chunks which are not decoded, in stream order
+    pub unknown_chunks: Vec<RawChunk>,
+}
+
+impl RawHeaders {
+    /// largest payload kept, longer chunks and blocks are listed without theirs
+    pub const MAX_PAYLOAD_LEN: u32 = 16 * 1024;
+}
+
+/// A FLAC metadata block as it is stored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawMetadataBlock {
+    /// the block type, e.g. 0 for STREAMINFO
+    pub block_type: u8,
+
+    /// offset of the block header from the start of the stream
+    pub offset: u64,
+
+    /// length of the block without its 4 byte header
+    pub len: u32,
+
+    /// the block without its header, `None` if longer than `RawHeaders::MAX_PAYLOAD_LEN`
+    pub payload: Option<Vec<u8>>,
+}
+
+/// A WAV chunk as it is stored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawChunk {
+    /// the four character code of the chunk
+    pub id: [u8; 4],
+
+    /// offset of the chunk header from the start of the stream
+    pub offset: u64,
+
+    /// length of the chunk without its 8 byte header and pad byte
+    pub len: u32,
+
+    /// the chunk without its header, `None` if longer than `RawHeaders::MAX_PAYLOAD_LEN`
+    pub payload: Option<Vec<u8>>,
+}
+
+/// A marker in the audio, e.g. a chapter, from the `cue ` chunk of a WAV file
+#[derive(Clone, Debug, PartialEq)]
+pub struct CuePoint {
+    /// id of the cue point, unique within the file
+    pub id: u32,
+
+    /// position of the cue point in inter-channel samples
+    pub sample_offset: u32,
+
+    /// the index of a cue sheet track, whose number is `id`, `None` for other cue points
     ///
-    /// # Example
+    /// Index 1 starts the track, index 0 starts the pregap before it.
+    pub index: Option<u8>,
+
+    /// the name of the cue point from the `labl` chunk in the `adtl` list, if any
+    pub label: Option<String>,
+
+    /// the comment on the cue point from the `note` chunk in the `adtl` list, if any
+    pub note: Option<String>,
+}
+
+/// A loop of a sampler from the `smpl` chunk of a WAV file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampleLoop {
+    /// id of the cue point marking the loop
+    pub cue_id: u32,
+
+    /// 0 loops forward, 1 alternates forward and backward, 2 loops backward
+    pub loop_type: u32,
+
+    /// first inter-channel sample of the loop
+    pub start: u32,
+
+    /// last inter-channel sample of the loop, it is played as well
+    pub end: u32,
+
+    /// number of times to play the loop, 0 means forever
+    pub play_count: u32,
+}
+
+/// The length of a stream found by `AudioSegment::scan_length`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScanResult {
+    /// number of inter-channel samples
+    pub frames: u64,
+
+    /// playing time of the frames
+    pub duration: Duration,
+
+    /// sample rate of the frames in Hz, 0 if the stream has none
+    pub sample_rate: u32,
+}
+
+/// Where decoding goes on after the last block decoded in full, see `AudioSamplesIterator::last_good_position`
+///
+/// A block is a frame of FLAC and a single sample of WAV.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamPosition {
+    /// index of the inter-channel sample the next block starts in
+    pub frame: u64,
+
+    /// offset of the next block in bytes from the start of the input
+    pub offset: u64,
+}
+
+impl ScanResult {
+    pub(crate) fn new(frames: u64, sample_rate: u32) -> ScanResult {
+        ScanResult {
+            frames,
+            duration: crate::io::frames_duration(frames, sample_rate),
+            sample_rate,
+        }
+    }
+}
+
+/// Options controlling how an `AudioSegment` is decoded
+///
+/// The defaults decode as fast as possible, everything else is opt-in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodeOptions {
+    /// collect `FrameStats` for every decoded frame, FLAC only
+    pub frame_stats: bool,
+
+    /// sum up the statistics of all decoded frames into `DecodeStats`, FLAC and MP3
     ///
-    /// ```
-    /// use cauldron::audio::AudioSegment;
-    /// use cauldron::codecs::FormatFlag;
+    /// Unlike `frame_stats` this takes no memory per frame.
+    pub decode_stats: bool,
+
+    /// largest accepted length of a single metadata block in bytes, FLAC only
     ///
-    /// match AudioSegment::read("tests/samples/wav/test-s16le-44100Hz-mono.wav") {
-    ///   Ok(f)  => f,
-    ///   Err(e) => panic!("Couldn't open example file: {}", e)
-    /// };
-    /// ```
+    /// Longer blocks are rejected as corrupt instead of being skipped.
+    pub max_metadata_block_len: u32,
 
-    /// read audio file from file path and returns `AudioSegment`
+    /// largest number of chunks or metadata blocks read by the header, WAV and FLAC
     ///
-    /// Determines the format from the file extension
+    /// Longer headers are rejected with `Error::ParseError`, so that a file made
+    /// of countless tiny chunks can not keep `read_header` busy.
+    pub max_header_chunks: u32,
+
+    /// largest number of bytes the header may span, WAV and FLAC
     ///
-    /// TODO: use audio metadata to determine the format
-    pub fn read(filename: &str) -> Result<AudioSegment> {
-        let flag = AudioSegment::get_format_flag(filename)?;
+    /// This includes the chunks and metadata blocks which are skipped.
+    pub max_header_len: u64,
 
-        AudioSegment::read_with_format(filename, flag)
-    }
+    /// drop the samples the encoder added as delay and padding, on by default
+    ///
+    /// The padding at the end is only dropped if the length of the stream is known.
+    pub trim_encoder_padding: bool,
 
-    /// Read audio file from file path and returns `AudioSegment`
+    /// return the channels of each inter-channel sample in the canonical order
     ///
-    /// You can pass file path as `String, &str or &std::path::Path`
+    /// Streams store their channels in the order of their format, for WAV and
+    /// FLAC that is the order of the WAVEFORMATEXTENSIBLE speaker positions,
+    /// where e.g. the LFE channel follows the front center. The canonical order
+    /// is the order of the bits of `Channels`, see `AudioSegment::channel_order`.
+    pub reorder_channels: bool,
+
+    /// return silence for the samples missing between two frames, FLAC only
     ///
-    /// ```
-    /// use cauldron::audio::AudioSegment;
-    /// use cauldron::codecs::FormatFlag;
+    /// The gaps are reported by `AudioSegment::sample_gaps` either way, filling
+    /// them keeps the timing of the following samples.
+    pub fill_gaps_with_silence: bool,
+
+    /// what to do with FLAC frames of another channel count than STREAMINFO
     ///
-    /// match AudioSegment::read_with_format(
-    ///     std::path::Path::new("tests/samples/wav/test-s16le-44100Hz-mono.wav"), FormatFlag::WAV) {
-    ///   Ok(f)  => f,
-    ///   Err(e) => panic!("Couldn't open example file: {}", e)
-    /// };
-    /// ```
+    /// Broken encoders write e.g. mono frames into a stream declared stereo.
+    /// Like the sample rate and bits per sample below, frames are compared
+    /// against the header they follow, so chained streams are no mismatch.
+    /// Frames of more channels than the header are rejected or skipped before
+    /// they are decoded, unless frames are trusted.
+    pub channels_mismatch: Mismatch,
+
+    /// what to do with FLAC frames of another sample rate than STREAMINFO
+    pub sample_rate_mismatch: Mismatch,
+
+    /// what to do with FLAC frames of other bits per sample than STREAMINFO
+    pub bits_per_sample_mismatch: Mismatch,
+
+    /// keep the header bytes as they are stored, see `AudioSegment::raw_headers`
     ///
-    /// Irrespective of file extension, it uses the provided format flag
-    pub fn read_with_format<I: IntoAudioInputStream>(
-        data: I,
-        flag: codecs::FormatFlag,
-    ) -> Result<AudioSegment> {
-        return AudioSegment::create_audio_segment(data.into_stream()?, flag);
+    /// Off by default, the bytes take memory besides the parsed header.
+    pub keep_raw_headers: bool,
+
+    /// accept WAV fmt chunks whose redundant fields are wrong, with a warning
+    ///
+    /// The block align of the plain formats and the byte rate follow from the
+    /// other fields, and many encoders store them wrong. Off by default, such
+    /// files are rejected with `Error::ParseError`.
+    pub lenient_fmt: bool,
+
+    /// decode FLAC frames whose headers hold reserved values, with a warning
+    ///
+    /// Buggy encoders set e.g. the reserved bit after the bits per sample,
+    /// which the reference decoder only warns about. Such frames are decoded
+    /// as it decodes them, see `raw::flac::decode_next_frame_lenient`. Off by
+    /// default, such frames fail with `Error::Unsupported`, or as corrupt for
+    /// a reserved sample rate code.
+    pub lenient_reserved_bits: bool,
+
+    /// what to do with NaN and infinite samples of float WAV files
+    ///
+    /// Broken exports contain them, and a single one turns the RMS, loudness
+    /// or any filter after it into NaN. By default NaN is replaced with 0
+    /// and infinities with full scale, so conversions to integers and the
+    /// analyses see finite samples only.
+    pub non_finite_samples: NonFinite,
+
+    /// count the blocks, frames, bytes and time of decoding, see `AudioSegment::instrumentation`
+    ///
+    /// The counters are updated once per block, off by default.
+    pub instrumentation: bool,
+
+    /// largest number of bytes of embedded pictures kept while reading the
+    /// header of an input which can not seek, see `AudioSegment::export_pictures`
+    ///
+    /// Inputs which can seek are read again for the pictures. The others pass
+    /// them while reading the header, so only the ones kept can be exported.
+    /// 0 by default, which keeps none.
+    pub max_buffered_pictures_len: u64,
+
+    /// largest number of bytes the input is read ahead by, see `DecodeOptions::small_footprint`
+    ///
+    /// Clamped between 1kb and the default of 32kb, and rounded down to a
+    /// power of 2. Headers may still peek further, the buffer shrinks back
+    /// once their bytes are consumed.
+    pub max_read_ahead_len: usize,
+
+    /// largest number of inter-channel samples of a FLAC frame
+    ///
+    /// Larger frames fail with `Error::Unsupported` before they are decoded,
+    /// so that no buffer is allocated for them. The default is the largest
+    /// block size FLAC allows, 65535.
+    pub max_block_size: u32,
+
+    /// shrink the block buffer back to the block size of STREAMINFO after a larger FLAC frame
+    ///
+    /// The buffer is reused for every frame and otherwise keeps the size of
+    /// the largest frame so far. Off by default.
+    pub shrink_block_buffers: bool,
+}
+
+/// How FLAC decoding handles frames disagreeing with STREAMINFO
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Mismatch {
+    /// fail with `Error::InconsistentFrame`
+    #[default]
+    Error,
+    /// warn and take the value of the frame as a change of the stream parameters
+    ///
+    /// `AudioSegment::events` tells about the change, the samples are
+    /// interleaved by the channels of each frame.
+    TrustFrames,
+    /// warn and keep the value of STREAMINFO
+    ///
+    /// Missing channels are returned as silence, frames of more channels are
+    /// skipped without decoding them, which leaves a gap of their samples, see
+    /// `DecodeOptions::fill_gaps_with_silence`. Samples of other bits per
+    /// sample are scaled as if they had those of STREAMINFO.
+    TrustStreamInfo,
+}
+
+/// How decoding handles NaN and infinite float samples
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum NonFinite {
+    /// decode NaN as 0 and infinities as full scale, `1.0` or `-1.0`, with a warning
+    #[default]
+    Replace,
+    /// fail with `Error::NonFiniteSample`, decoding continues after it
+    Error,
+}
+
+impl DecodeOptions {
+    /// default for `max_metadata_block_len`, large enough for embedded cover art
+    pub const DEFAULT_MAX_METADATA_BLOCK_LEN: u32 = 8 * 1024 * 1024;
+
+    /// default for `max_header_chunks`, real files have a few dozen at most
+    pub const DEFAULT_MAX_HEADER_CHUNKS: u32 = 10_000;
+
+    /// default for `max_header_len`, room for several pieces of cover art
+    pub const DEFAULT_MAX_HEADER_LEN: u64 = 64 * 1024 * 1024;
+
+    /// default for `max_read_ahead_len`
+    pub const DEFAULT_MAX_READ_AHEAD_LEN: usize = 32 * 1024;
+
+    /// default for `max_block_size`, the largest block size of FLAC
+    pub const DEFAULT_MAX_BLOCK_SIZE: u32 = 65535;
+
+    /// Returns the options for decoding with little memory, e.g. on embedded devices
+    ///
+    /// The input is read ahead by 4kb, FLAC frames may be as large as the
+    /// 4608 samples of the streamable subset, and block buffers shrink back
+    /// to the block size of STREAMINFO after larger frames. Decoding a stereo
+    /// 16 bit FLAC stream of 4096 sample blocks was measured to allocate at
+    /// most 65kb with the default options, 32kb of which are the read-ahead,
+    /// and 37kb with these, most of which is the 32kb block buffer. A frame of
+    /// 65535 samples, which would take 2mb for 8 channels, fails instead.
+    pub fn small_footprint() -> DecodeOptions {
+        DecodeOptions {
+            max_read_ahead_len: 4 * 1024,
+            max_block_size: 4608,
+            shrink_block_buffers: true,
+            ..DecodeOptions::default()
+        }
     }
+}
 
-    fn create_audio_segment(
-        input: AudioInputStream,
-        format_flag: codecs::FormatFlag,
-    ) -> Result<AudioSegment> {
-        let mut read_res: Box<dyn AudioReader> = match format_flag {
-            codecs::FormatFlag::WAV => wav::WavReader::new(input)?,
-            codecs::FormatFlag::FLAC => flac::FlacReader::new(input)?,
-            codecs::FormatFlag::MP3 => mp3::Mp3Reader::new(input)?,
-            _ => return errors::unsupported_error("Codec flag not supported"),
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            frame_stats: false,
+            decode_stats: false,
+            max_metadata_block_len: DecodeOptions::DEFAULT_MAX_METADATA_BLOCK_LEN,
+            max_header_chunks: DecodeOptions::DEFAULT_MAX_HEADER_CHUNKS,
+            max_header_len: DecodeOptions::DEFAULT_MAX_HEADER_LEN,
+            trim_encoder_padding: true,
+            reorder_channels: false,
+            fill_gaps_with_silence: false,
+            channels_mismatch: Mismatch::Error,
+            sample_rate_mismatch: Mismatch::Error,
+            bits_per_sample_mismatch: Mismatch::Error,
+            keep_raw_headers: false,
+            lenient_fmt: false,
+            lenient_reserved_bits: false,
+            non_finite_samples: NonFinite::Replace,
+            instrumentation: false,
+            max_buffered_pictures_len: 0,
+            max_read_ahead_len: DecodeOptions::DEFAULT_MAX_READ_AHEAD_LEN,
+            max_block_size: DecodeOptions::DEFAULT_MAX_BLOCK_SIZE,
+            shrink_block_buffers: false,
+        }
+    }
+}
+
+/// Options controlling how `AudioSegment::write_wav_with_options` writes a file
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct WavOptions {
+    /// the speaker positions of the channels, `None` for the ones of the segment
+    ///
+    /// It has to hold as many channels as the segment.
+    pub channels: Option<Channels>,
+
+    /// write the basic fmt chunk even where WAVEFORMATEXTENSIBLE is due
+    ///
+    /// The basic fmt chunk has no channel mask and no number of valid bits,
+    /// this crate reads such files with up to eight channels, in the speaker
+    /// positions FLAC assigns to their number.
+    pub force_basic_fmt: bool,
+
+    /// how much audio `WavWriter` writes between patches of the sizes, `None` for one second
+    ///
+    /// `write_wav` writes the sizes once, after the audio.
+    pub patch_interval: Option<Duration>,
+
+    /// the source channel of each channel `write_wav` writes, `None` to keep them as they are
+    ///
+    /// WAVEFORMATEXTENSIBLE stores the channels in the order of the bits of
+    /// the channel mask. Without `channels` the file keeps the speaker
+    /// positions of the source channels, which requires the map to keep them
+    /// in their order, e.g. to drop channels. A map which reorders or repeats
+    /// channels needs the speaker positions of the output in `channels`.
+    pub channel_map: Option<ChannelMap>,
+}
+
+/// The source channel of each output channel, see `WavOptions::channel_map`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelMap {
+    sources: [usize; ChannelMap::MAX_CHANNELS],
+    len: usize,
+}
+
+impl ChannelMap {
+    /// the most output channels a map can have, one per bit of `Channels`
+    pub const MAX_CHANNELS: usize = 32;
+
+    /// Creates a map from the index of the source channel of each output channel
+    ///
+    /// Source channels may be repeated or left out. Fails with
+    /// `Error::Unsupported` for no output channels or more than `MAX_CHANNELS`.
+    pub fn new(sources: &[usize]) -> Result<ChannelMap> {
+        if sources.is_empty() || sources.len() > ChannelMap::MAX_CHANNELS {
+            return errors::unsupported_error("a channel map needs 1 to 32 output channels");
+        }
+        let mut map = ChannelMap {
+            sources: [0; ChannelMap::MAX_CHANNELS],
+            len: sources.len(),
         };
+        map.sources[..sources.len()].copy_from_slice(sources);
+        Ok(map)
+    }
 
-        Ok(AudioSegment {
-            codec_flag: format_flag,
-            info: read_res.read_header()?,
-            reader: read_res,
-            is_buffer_used: false,
-        })
+    /// Returns the index of the source channel of each output channel
+    pub fn sources(&self) -> &[usize] {
+        &self.sources[..self.len]
     }
 
-    /// returns audio info as `AudioInfo`
+    /// Returns the speaker positions of the output channels, taken from the `source` channels
+    ///
+    /// Fails with `Error::Unsupported` if the output does not keep the order of
+    /// the source channels.
+    #[cfg(feature = "wav")]
+    fn speakers(&self, source: Channels) -> Result<Channels> {
+        let order = stream_channel_order(source);
+        if self.sources().windows(2).any(|pair| pair[0] >= pair[1]) {
+            return errors::unsupported_error(
+                "a channel map reordering or repeating channels needs the channels of the output",
+            );
+        }
+        Ok(self
+            .sources()
+            .iter()
+            .fold(Channels::empty(), |speakers, &i| speakers | order[i]))
+    }
+}
+
+/// What is known about a stream before decoding it, shareable between threads
+///
+/// Cloning only bumps reference counts.
+#[derive(Clone, Debug)]
+pub struct StreamDescription {
+    format: codecs::FormatFlag,
+    info: Arc<AudioInfo>,
+    header_len: u64,
+    cue_points: Arc<[CuePoint]>,
+}
+
+impl StreamDescription {
+    /// Returns the format of the stream
+    pub fn format(&self) -> codecs::FormatFlag {
+        self.format
+    }
+
+    /// Returns the audio info from the headers
     pub fn info(&self) -> &AudioInfo {
         &self.info
     }
 
-    /// returns number of channels in the audio
-    pub fn number_channels(&self) -> usize {
-        self.info.channels.count()
+    /// Returns the number of bytes of the header, see `AudioSegment::header_len`
+    pub fn header_len(&self) -> u64 {
+        self.header_len
     }
 
-    /// Returns the duration of the audio file in seconds
-    ///
-    /// duration = (total_samples / no_channels) / sampling_rate
-    pub fn duration(&self) -> f32 {
-        self.info.total_samples as f32
-            / (self.number_channels() as u32 * self.info.sample_rate) as f32
+    /// Returns the cue points of a WAV file, see `AudioSegment::cue_points`
+    pub fn cue_points(&self) -> &[CuePoint] {
+        &self.cue_points
     }
+}
 
-    /// Returns bitrate of the audio in kbps
-    pub fn bitrate(&self) -> u32 {
-        (self.info.sample_rate / 1000) * self.info.bits_per_sample * self.number_channels() as u32
-    }
+/// The decoding half of an `AudioSegment` split by `AudioSegment::into_parts`
+pub struct Decoder {
+    segment: AudioSegment,
+}
 
-    /// Returns an channel interleaved iterator on samples
+impl Decoder {
+    /// Returns an channel interleaved iterator on samples, see `AudioSegment::samples`
     pub fn samples<'a, S: Sample + 'a>(&'a mut self) -> Result<SampleIterator<'a, S>> {
-        if self.is_buffer_used {
-            return errors::unsupported_error("requesting iterator again");
-        }
-        self.is_buffer_used = true;
-        let itr = match self.codec_flag {
-            codecs::FormatFlag::WAV => wav::WavSamplesIterator::new(&mut self.reader, &self.info),
-            codecs::FormatFlag::FLAC => {
-                flac::FlacSamplesIterator::new(&mut self.reader, &self.info)
-            }
-            codecs::FormatFlag::MP3 => mp3::Mp3SamplesIterator::new(&mut self.reader, &self.info),
-            _ => unreachable!(),
-        };
-        Ok(itr)
+        self.segment.samples()
     }
 
-    fn get_format_flag(filename: &str) -> Result<codecs::FormatFlag> {
-        let extension = match filename.split('.').last() {
-            Some(ex) => ex,
-            None => return errors::unsupported_error("no decoder flag found for given file"),
-        };
-        match extension {
-            "wav" => Ok(codecs::FormatFlag::WAV),
-            "flac" => Ok(codecs::FormatFlag::FLAC),
-            "mp3" => Ok(codecs::FormatFlag::MP3),
-            "aac" => Ok(codecs::FormatFlag::AAC),
-            "ogg" => Ok(codecs::FormatFlag::VORBIS),
-            "raw" => Ok(codecs::FormatFlag::PCM),
-            "pcm" => Ok(codecs::FormatFlag::PCM),
-            _ => errors::unsupported_error("no decoder flag found for given file"),
-        }
+    /// Returns an iterator on the selected channels, see `AudioSegment::channel_samples`
+    pub fn channel_samples<'a, S: Sample + 'a>(
+        &'a mut self,
+        channels: &[usize],
+    ) -> Result<SampleIterator<'a, S>> {
+        self.segment.channel_samples(channels)
     }
-}
 
+    /// Returns the statistics of every frame decoded so far
+    pub fn frame_stats(&self) -> &[FrameStats] {
+        self.segment.frame_stats()
+    }
+
+    /// Returns the statistics summed up over the frames decoded so far
+    pub fn decode_stats(&self) -> &DecodeStats {
+        self.segment.decode_stats()
+    }
+
+    /// Returns the throughput of the decoding so far, see `AudioSegment::instrumentation`
+    pub fn instrumentation(&self) -> Option<Instrumentation> {
+        self.segment.instrumentation()
+    }
+
+    /// Returns the gaps between the frames decoded so far, see `AudioSegment::sample_gaps`
+    pub fn sample_gaps(&self) -> &[SampleGap] {
+        self.segment.sample_gaps()
+    }
+
+    /// Returns the effective bits per sample, see `AudioSegment::effective_bits_per_sample`
+    pub fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        self.segment.effective_bits_per_sample()
+    }
+
+    /// Joins the parts into a segment again
+    pub fn into_segment(self) -> AudioSegment {
+        self.segment
+    }
+}
+
+/// An iterator on the samples of an `AudioSegment` it owns, see `AudioSegment::into_samples`
+///
+/// Unlike a `SampleIterator` it borrows nothing, so it can be moved to
+/// another thread, stored in a struct or returned from a function.
+pub struct OwnedSamples<S: Sample + 'static> {
+    /// borrows `*segment`, hence it is dropped first
+    samples: Option<SampleIterator<'static, S>>,
+    /// a leaked `Box`, only ever accessed through `samples` until it is
+    /// freed, null once `into_segment` took it
+    segment: *mut AudioSegment,
+    info: AudioInfo,
+}
+
+// SAFETY: the segment and the iterator borrowing it are both `Send`, and
+// reachable through `OwnedSamples` only, which moves them to another thread
+// together.
+unsafe impl<S: Sample + 'static> Send for OwnedSamples<S> {}
+
+impl<S: Sample + 'static> OwnedSamples<S> {
+    fn new(segment: AudioSegment) -> Result<OwnedSamples<S>> {
+        let info = segment.info.clone();
+        let segment = Box::into_raw(Box::new(segment));
+        // SAFETY: the box is freed by `into_segment` or `drop` only after the
+        // iterator is dropped, nothing else accesses the segment before.
+        match unsafe { (*segment).samples::<S>() } {
+            Ok(samples) => Ok(OwnedSamples {
+                samples: Some(samples),
+                segment,
+                info,
+            }),
+            Err(error) => {
+                // SAFETY: the box was leaked above and nothing borrows it
+                drop(unsafe { Box::from_raw(segment) });
+                Err(error)
+            }
+        }
+    }
+
+    fn samples(&mut self) -> &mut SampleIterator<'static, S> {
+        self.samples
+            .as_mut()
+            .expect("the iterator lives as long as the segment")
+    }
+
+    /// Returns the audio info of the segment
+    pub fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    /// Stops decoding and returns the segment, e.g. for the statistics of the decoded frames
+    pub fn into_segment(mut self) -> AudioSegment {
+        self.samples = None;
+        let segment = std::mem::replace(&mut self.segment, std::ptr::null_mut());
+        // SAFETY: the iterator borrowing the segment is gone, and `drop` does
+        // not free it again
+        *unsafe { Box::from_raw(segment) }
+    }
+}
+
+impl<S: Sample + 'static> Drop for OwnedSamples<S> {
+    fn drop(&mut self) {
+        self.samples = None;
+        if !self.segment.is_null() {
+            // SAFETY: the iterator borrowing the segment is gone
+            drop(unsafe { Box::from_raw(self.segment) });
+        }
+    }
+}
+
+impl<S: Sample + 'static> AudioSamplesIterator<S> for OwnedSamples<S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        self.samples().next()
+    }
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.samples().seek_frame(frame)
+    }
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        self.samples.as_ref()?.effective_bits_per_sample()
+    }
+    fn samples_left(&self) -> Option<u64> {
+        self.samples.as_ref()?.samples_left()
+    }
+    fn remaining_frames(&self) -> Option<u64> {
+        self.samples.as_ref()?.remaining_frames()
+    }
+    fn sample_rate(&self) -> Option<u32> {
+        self.samples.as_ref()?.sample_rate()
+    }
+    fn take_stream_change(&mut self) -> Option<AudioInfo> {
+        self.samples().take_stream_change()
+    }
+    fn last_good_position(&self) -> Option<StreamPosition> {
+        self.samples.as_ref()?.last_good_position()
+    }
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        self.samples().resume_after_io_error()
+    }
+    fn read_planar(&mut self, out: &mut [&mut [S]]) -> Result<usize> {
+        self.samples().read_planar(out)
+    }
+}
+
+impl<S: Sample + 'static> Iterator for OwnedSamples<S> {
+    type Item = Result<S>;
+
+    fn next(&mut self) -> Option<Result<S>> {
+        AudioSamplesIterator::next(self)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.samples {
+            Some(ref samples) => samples.size_hint(),
+            None => (0, Some(0)),
+        }
+    }
+}
+
+/// Type for sample iterator returned by `AudioSegment`
+pub type SampleIterator<'a, S> = Box<dyn AudioSamplesIterator<S> + 'a>;
+
+/// An item returned by `AudioSegment::events`
+#[derive(Clone, Debug)]
+pub enum Event<S> {
+    /// the next sample, channel interleaved
+    Sample(S),
+    /// the stream continues with different parameters from the next sample on
+    ///
+    /// The info holds the new sample rate, channels and bits per sample, the
+    /// total number of samples is 0 if it is not known.
+    StreamChanged(AudioInfo),
+}
+
+/// Iterator on the samples of a stream and the changes of its parameters
+pub struct Events<'a, S: Sample> {
+    samples: SampleIterator<'a, S>,
+    /// the sample to return after a change
+    pending: Option<S>,
+}
+
+impl<'a, S: Sample> Iterator for Events<'a, S> {
+    type Item = Result<Event<S>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(sample) = self.pending.take() {
+            return Some(Ok(Event::Sample(sample)));
+        }
+        let sample = otry!(self.samples.next()?);
+        match self.samples.take_stream_change() {
+            Some(info) => {
+                self.pending = Some(sample);
+                Some(Ok(Event::StreamChanged(info)))
+            }
+            None => Some(Ok(Event::Sample(sample))),
+        }
+    }
+}
+
+/// `AudioSegment` is returned to user to perform various operations and get
+/// decoded stream, audio info or encode to different format.
+pub struct AudioSegment {
+    /// the format creating the iterators
+    format: Arc<dyn Format>,
+
+    /// audio info stored in a container format's headers and metadata
+    info: AudioInfo,
+
+    /// audio reader
+    reader: Box<dyn AudioReader>,
+
+    /// flag is set when samples iterator is returned
+    is_buffer_used: bool,
+
+    /// set by `events`, whose iterator returns the changes of the stream parameters
+    stream_changes: bool,
+
+    /// number of bytes consumed while reading the header
+    header_len: u64,
+
+    /// options the segment was created with
+    options: DecodeOptions,
+
+    /// statistics of the frames decoded so far, if enabled
+    frame_stats: Vec<FrameStats>,
+
+    /// statistics summed up over the frames decoded so far, if enabled
+    decode_stats: DecodeStats,
+    instrumentation: Option<InstrumentationHandle>,
+
+    /// fewest wasted bits of each channel in the frames decoded so far, empty
+    /// before the first frame
+    wasted_bits: Vec<u32>,
+
+    /// discontinuities of the sample numbers of the frames decoded so far
+    sample_gaps: Vec<SampleGap>,
+}
+
+impl AudioSegment {
+    //noinspection TodoComment
+    /// read audio file from file path and returns `AudioSegment`
+    ///
+    /// Determines the format from the file extension
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cauldron::audio::AudioSegment;
+    ///
+    /// match AudioSegment::read("tests/samples/wav/test-s16le-44100Hz-mono.wav") {
+    ///   Ok(f)  => f,
+    ///   Err(e) => panic!("Couldn't open example file: {}", e)
+    /// };
+    /// ```
+    ///
+    /// If the file does not start with the magic bytes of that format, the other
+    /// enabled formats are tried in the order WAV, FLAC, MP3, followed by the
+    /// formats added with `format::register`, which rescues files with a wrong
+    /// extension. `Error::UnrecognizedFormat` lists the formats tried if none
+    /// matched.
+    ///
+    /// TODO: use audio metadata to determine the format
+    pub fn read(filename: &str) -> Result<AudioSegment> {
+        let first = format::find(AudioSegment::get_format_flag(filename)?)?;
+        AudioSegment::probe_formats(filename.into_stream()?, first)
+    }
+
+    /// Reads `input` as `first`, trying the other formats if its magic bytes are missing
+    pub(crate) fn probe_formats(
+        mut input: AudioInputStream,
+        first: Arc<dyn Format>,
+    ) -> Result<AudioSegment> {
+        let flag = first.flag();
+        let mut tried = Vec::new();
+        let fallbacks = format::formats().into_iter().filter(|f| f.flag() != flag);
+        for format in std::iter::once(first).chain(fallbacks) {
+            let options = DecodeOptions::default();
+            let mut reader = format.create_reader(input, options)?;
+            match reader.read_header() {
+                Ok(info) => return Ok(AudioSegment::new(format, info, reader, options)),
+                // the readers only peek at the magic bytes, so the input is untouched
+                Err(errors::Error::MissingMagic(_)) if reader.buffer().position() == 0 => {
+                    tried.push(format.flag());
+                    input = reader.into_buffer();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(errors::Error::UnrecognizedFormat(tried))
+    }
+
+    /// Reads `data` as the format detected from its first bytes, see `detect_format`
+    ///
+    /// This suits inputs without a file name, e.g. standard input:
+    ///
+    /// ```no_run
+    /// use cauldron::audio::AudioSegment;
+    ///
+    /// let segment = AudioSegment::read_detected(std::io::stdin()).unwrap();
+    /// println!("{}", segment.info());
+    /// ```
+    pub fn read_detected<I: IntoAudioInputStream>(data: I) -> Result<AudioSegment> {
+        let mut input = data.into_stream()?;
+        let flag = format::detect_format(&mut input)?;
+        AudioSegment::create_audio_segment(input, flag, DecodeOptions::default())
+    }
+
+    /// Read audio file from file path and returns `AudioSegment`
+    ///
+    /// You can pass file path as `String, &str or &std::path::Path`
+    ///
+    /// ```no_run
+    /// use cauldron::audio::AudioSegment;
+    /// use cauldron::codecs::FormatFlag;
+    ///
+    /// match AudioSegment::read_with_format(
+    ///     std::path::Path::new("tests/samples/wav/test-s16le-44100Hz-mono.wav"), FormatFlag::WAV) {
+    ///   Ok(f)  => f,
+    ///   Err(e) => panic!("Couldn't open example file: {}", e)
+    /// };
+    /// ```
+    ///
+    /// Irrespective of file extension, it uses the provided format flag
+    pub fn read_with_format<I: IntoAudioInputStream>(
+        data: I,
+        flag: codecs::FormatFlag,
+    ) -> Result<AudioSegment> {
+        AudioSegment::read_with_options(data, flag, DecodeOptions::default())
+    }
+
+    /// Same as `read_with_format`, but decodes according to `options`
+    pub fn read_with_options<I: IntoAudioInputStream>(
+        data: I,
+        flag: codecs::FormatFlag,
+        options: DecodeOptions,
+    ) -> Result<AudioSegment> {
+        AudioSegment::create_audio_segment(data.into_stream()?, flag, options)
+    }
+
+    /// Reads audio of the format `flag` starting `byte_offset` bytes into `data`
+    ///
+    /// This reads audio embedded in other data, e.g. a WAV file inside another
+    /// container; `format::scan_for_audio` finds the offset if it is unknown.
+    /// The bytes before are skipped, by seeking if `data` can seek. Positions
+    /// like `header_len` and the offsets in seek indexes count from the start of
+    /// `data`, not of the audio.
+    pub fn read_at_offset<I: IntoAudioInputStream>(
+        data: I,
+        byte_offset: u64,
+        flag: codecs::FormatFlag,
+    ) -> Result<AudioSegment> {
+        let mut input = data.into_stream()?;
+        input.skip_ahead(byte_offset)?;
+        AudioSegment::create_audio_segment(input, flag, DecodeOptions::default())
+    }
+
+    /// Reads audio from any byte source and returns `AudioSegment`
+    ///
+    /// The source is only ever read forward, it is never seeked, so network
+    /// streams and pipes can be decoded directly. Reads may return any number
+    /// of bytes, and reads failing with `io::ErrorKind::Interrupted` are
+    /// retried; every other error, e.g. a timeout of a stalled connection, is
+    /// passed back to the caller.
+    ///
+    /// An empty source, or one not starting with the magic bytes of the format,
+    /// fails with `Error::MissingMagic`. A file holding only a header, e.g. a
+    /// WAV file with an empty data chunk, or a FLAC file with STREAMINFO but no
+    /// frames, is valid: it has `total_samples` 0 and no samples. A FLAC file
+    /// holding only the `fLaC` marker, and a WAV file without a `fmt ` or
+    /// `data` chunk, fail with `Error::ParseError`.
+    ///
+    /// ```
+    /// use cauldron::audio::AudioSegment;
+    /// use cauldron::codecs::FormatFlag;
+    /// use cauldron::errors::Error;
+    ///
+    /// let garbage: &[u8] = b"not an audio file";
+    /// assert!(AudioSegment::from_reader(garbage, FormatFlag::FLAC).is_err());
+    ///
+    /// # #[cfg(feature = "wav")]
+    /// # {
+    /// let empty: &[u8] = b"";
+    /// let result = AudioSegment::from_reader(empty, FormatFlag::WAV);
+    /// assert!(matches!(result, Err(Error::MissingMagic(_))));
+    /// # }
+    /// ```
+    pub fn from_reader<R: io::Read + Send + 'static>(
+        reader: R,
+        flag: codecs::FormatFlag,
+    ) -> Result<AudioSegment> {
+        AudioSegment::from_reader_with_options(reader, flag, DecodeOptions::default())
+    }
+
+    /// Same as `from_reader`, but decodes according to `options`
+    pub fn from_reader_with_options<R: io::Read + Send + 'static>(
+        reader: R,
+        flag: codecs::FormatFlag,
+        options: DecodeOptions,
+    ) -> Result<AudioSegment> {
+        AudioSegment::create_audio_segment(read_only_stream(reader), flag, options)
+    }
+
+    /// Reads audio from a byte source supporting seeks and returns `AudioSegment`
+    ///
+    /// Unlike `from_reader`, this allows `scan_length`. The current position of
+    /// `reader` is taken as the start of the audio file.
+    pub fn from_seekable_reader<R: io::Read + io::Seek + Send + 'static>(
+        reader: R,
+        flag: codecs::FormatFlag,
+    ) -> Result<AudioSegment> {
+        let input = seekable_stream(reader)?;
+        AudioSegment::create_audio_segment(input, flag, DecodeOptions::default())
+    }
+
+    fn create_audio_segment(
+        mut input: AudioInputStream,
+        format_flag: codecs::FormatFlag,
+        options: DecodeOptions,
+    ) -> Result<AudioSegment> {
+        input.set_max_capacity(options.max_read_ahead_len);
+        let format = format::find(format_flag)?;
+        let mut reader = format.create_reader(input, options)?;
+        let info = reader.read_header()?;
+        Ok(AudioSegment::new(format, info, reader, options))
+    }
+
+    /// Creates the segment from a reader which has read the header
+    fn new(
+        format: Arc<dyn Format>,
+        info: AudioInfo,
+        mut read_res: Box<dyn AudioReader>,
+        options: DecodeOptions,
+    ) -> AudioSegment {
+        let header_len = read_res.buffer().position();
+        let instrumentation = if options.instrumentation {
+            let handle = InstrumentationHandle::default();
+            handle.set_sample_rate(info.sample_rate);
+            Some(handle)
+        } else {
+            None
+        };
+
+        AudioSegment {
+            format,
+            info,
+            reader: read_res,
+            is_buffer_used: false,
+            stream_changes: false,
+            header_len,
+            options,
+            frame_stats: Vec::new(),
+            decode_stats: DecodeStats::default(),
+            instrumentation,
+            wasted_bits: Vec::new(),
+            sample_gaps: Vec::new(),
+        }
+    }
+
+    /// returns audio info as `AudioInfo`
+    pub fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    /// Returns the format the segment is decoded as
+    pub fn format(&self) -> codecs::FormatFlag {
+        self.format.flag()
+    }
+
+    /// Consumes the segment, returning its audio info and input
+    #[cfg(feature = "batch")]
+    pub(crate) fn into_info_and_inner(self) -> (AudioInfo, AudioInputStream) {
+        (self.info, self.reader.into_buffer())
+    }
+
+    /// Moves decoding to a thread of its own
+    ///
+    /// The thread decodes about `buffer_frames` inter-channel samples ahead of
+    /// the `SampleReceiver` and waits while that many are buffered. Decode
+    /// errors are returned by the receiver, after which the thread idles until
+    /// a seek or until it is stopped. Dropping either the handle or the
+    /// receiver stops the thread.
+    ///
+    /// Like `samples`, this fails if a sample iterator was returned already.
+    #[cfg(feature = "threads")]
+    pub fn into_background_decoder<S: Sample + 'static>(
+        self,
+        buffer_frames: usize,
+    ) -> Result<(
+        crate::background::DecodeHandle,
+        crate::background::SampleReceiver<S>,
+    )> {
+        if self.is_buffer_used {
+            return errors::unsupported_error("requesting iterator again");
+        }
+        crate::background::spawn(self, buffer_frames)
+    }
+
+    /// Returns an channel interleaved iterator on samples which owns the segment
+    ///
+    /// This is `samples` for iterators which have to outlive a borrow of the
+    /// segment, e.g. to decode on a worker thread while the samples are used
+    /// on another one. `OwnedSamples::into_segment` returns the segment again.
+    pub fn into_samples<S: Sample + 'static>(self) -> Result<OwnedSamples<S>> {
+        OwnedSamples::new(self)
+    }
+
+    /// Splits the segment into the description of the stream and the decoding state
+    ///
+    /// The description holds the cue points known at this point, see `cue_points`.
+    pub fn into_parts(self) -> (StreamDescription, Decoder) {
+        let description = StreamDescription {
+            format: self.format.flag(),
+            info: Arc::new(self.info.clone()),
+            header_len: self.header_len,
+            cue_points: self.cue_points().into(),
+        };
+        (description, Decoder { segment: self })
+    }
+
+    /// Returns the input, e.g. to hand a stream of an unsupported codec to another library
+    ///
+    /// The input is positioned after the header if no samples were read, include
+    /// the bytes buffered ahead of that position with `AudioInputStream::into_parts`:
+    /// `into_inner` of the stream drops them.
+    pub fn into_inner(self) -> AudioInputStream {
+        self.reader.into_buffer()
+    }
+
+    /// Returns the number of bytes consumed from the input while reading the header
+    ///
+    /// The encoded audio data starts at this offset, which is useful to account for
+    /// when the input is fetched with range requests.
+    pub fn header_len(&self) -> u64 {
+        self.header_len
+    }
+
+    /// returns the FLAC STREAMINFO extents and MD5 signature, `None` for other formats
+    pub fn flac_stream_info(&self) -> Option<&FlacStreamInfo> {
+        self.info.flac_stream_info.as_ref()
+    }
+
+    /// Returns the statistics of every frame decoded so far
+    ///
+    /// Always empty unless the segment was created with `DecodeOptions::frame_stats`.
+    pub fn frame_stats(&self) -> &[FrameStats] {
+        &self.frame_stats
+    }
+
+    /// Returns the statistics of the frames decoded so far aggregated over the file
+    pub fn file_stats(&self) -> FileStats {
+        FileStats::from_frames(&self.frame_stats)
+    }
+
+    /// Returns the statistics summed up over the frames decoded so far
+    ///
+    /// Always empty unless the segment was created with `DecodeOptions::decode_stats`.
+    pub fn decode_stats(&self) -> &DecodeStats {
+        &self.decode_stats
+    }
+
+    /// Returns the throughput of the decoding so far
+    ///
+    /// `None` unless the segment was created with `DecodeOptions::instrumentation`.
+    pub fn instrumentation(&self) -> Option<Instrumentation> {
+        self.instrumentation
+            .as_ref()
+            .map(InstrumentationHandle::get)
+    }
+
+    /// Returns a handle reading the throughput while the samples are iterated, see `instrumentation`
+    pub fn instrumentation_handle(&self) -> Option<InstrumentationHandle> {
+        self.instrumentation.clone()
+    }
+
+    /// Returns the discontinuities of the sample numbers of the frames decoded so far
+    ///
+    /// Every FLAC frame states the index of its first sample, which follows the
+    /// last sample of the previous frame unless frames went missing. A chained
+    /// stream starts counting at 0 again. Always empty for the other formats.
+    pub fn sample_gaps(&self) -> &[SampleGap] {
+        &self.sample_gaps
+    }
+
+    /// Returns the bits per sample of each channel without the wasted bits
+    ///
+    /// FLAC encoders signal bits which are zero in every sample of a subframe,
+    /// e.g. for 16 bit audio stored with 24 bits. This is the bit depth actually
+    /// used by all frames decoded so far, `None` before the first frame and for
+    /// the other formats.
+    pub fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        effective_bits(&self.wasted_bits, self.info.bits_per_sample)
+    }
+
+    /// Returns the FLAC metadata blocks skipped by the header, e.g. PADDING
+    ///
+    /// Blocks which can be large, like PADDING reserving space for tags or
+    /// embedded pictures, are skipped by seeking if the input can seek. Always
+    /// empty for the other formats.
+    pub fn skipped_metadata(&self) -> Vec<SkippedMetadata> {
+        self.reader.skipped_metadata()
+    }
+
+    /// Returns the FLAC APPLICATION blocks, e.g. the data CUETools stores there
+    ///
+    /// Their size is bounded by `DecodeOptions::max_metadata_block_len` like
+    /// the one of every metadata block. Always empty for the other formats.
+    pub fn application_blocks(&self) -> Vec<ApplicationBlock> {
+        self.reader.application_blocks()
+    }
+
+    /// Returns the header bytes as they are stored, `None` unless
+    /// `DecodeOptions::keep_raw_headers` is set
+    ///
+    /// These are the WAV fmt chunk and the chunks which are not decoded, or all
+    /// FLAC metadata blocks. Like cue points, WAV chunks after the audio data
+    /// are only known once all samples have been read.
+    pub fn raw_headers(&self) -> Option<RawHeaders> {
+        self.reader.raw_headers()
+    }
+
+    /// Returns the cue points of a WAV file, with their labels
+    ///
+    /// Cue and `adtl` list chunks can be stored before or after the audio data, the
+    /// ones after it are only known once all samples have been read.
+    pub fn cue_points(&self) -> Vec<CuePoint> {
+        self.reader.cue_points()
+    }
+
+    /// Returns the tracks starting at `cues`, e.g. the cue points of a cue sheet
+    ///
+    /// Cue points with an `index` of 1, and ones without an index, start a
+    /// track; index 0 starts its pregap, which `pregap` attaches to the
+    /// previous track or the track itself. The tracks are ordered by their
+    /// start and leave no gaps: the first one starts at the first sample and
+    /// the last one ends with the stream, so that nothing is lost at the
+    /// joins. Other indexes are ignored.
+    pub fn split_tracks(&self, cues: &[CuePoint], pregap: tracks::Pregap) -> Vec<TrackRange> {
+        let total_frames = match self.info.total_samples {
+            0 => None,
+            total => Some(total / self.number_channels() as u64),
+        };
+        tracks::split_tracks(cues, pregap, total_frames)
+    }
+
+    /// Decodes the audio once and writes each of `tracks` as a WAV file to the writer `create` returns for it
+    ///
+    /// The samples are written as decoded, so that the tracks concatenated
+    /// decode to the same samples as the whole audio. This takes integer
+    /// audio of 16, 24 or 32 bits, unsigned 8 bit and 32 bit float audio,
+    /// other bit depths fail with `Error::Unsupported`, as do tracks which are
+    /// not ordered or overlap. Samples between tracks are skipped. Like
+    /// `samples` this consumes the stream.
+    ///
+    /// Returns the number of inter-channel samples written for each track.
+    #[cfg(feature = "wav")]
+    pub fn export_tracks<W, F>(&mut self, tracks: &[TrackRange], create: F) -> Result<Vec<u64>>
+    where
+        W: io::Write + io::Seek,
+        F: FnMut(&TrackRange) -> io::Result<W>,
+    {
+        tracks::segment_export_tracks(self, tracks, create)
+    }
+
+    /// Writes the embedded pictures, e.g. the cover art, to files in `dir` without decoding audio
+    ///
+    /// These are the images of FLAC PICTURE blocks and of the APIC frames of
+    /// ID3v2 tags, at the start of MP3 files or in `id3 ` chunks of WAV files.
+    /// The files are named `picture-1.png`, `picture-2.jpg` and so on, in stream
+    /// order with the extension following the MIME type, `bin` if it is not a
+    /// known image type. Pictures which only link to an image are left out.
+    ///
+    /// The images are copied a few kilobytes at a time, an input which can
+    /// seek is read again where they are stored and left where it was. Of
+    /// inputs which can not seek only the pictures kept while reading the
+    /// header, up to `DecodeOptions::max_buffered_pictures_len`, and an ID3v2
+    /// tag still ahead of the position are found.
+    ///
+    /// Returns the paths of the written files.
+    pub fn export_pictures(&mut self, dir: &Path) -> Result<Vec<PathBuf>> {
+        pictures::export_pictures(self.reader.as_mut(), dir)
+    }
+
+    /// Returns the sampler loops of a WAV file
+    ///
+    /// Like cue points, a `smpl` chunk after the audio data is only known once
+    /// all samples have been read.
+    pub fn sample_loops(&self) -> Vec<SampleLoop> {
+        self.reader.sample_loops()
+    }
+
+    /// Finds the length of the audio by walking the whole stream
+    ///
+    /// This is for streams whose headers do not tell the length, e.g. FLAC with
+    /// an unknown total sample count, MP3 without a Xing header or WAV written
+    /// while streaming. Only frame headers are parsed, FLAC and MP3 block sizes
+    /// are summed up, and for WAV the data chunk is measured up to the end of
+    /// the file. The position in the stream is restored afterwards.
+    ///
+    /// Scanning consumes the stream, hence it fails with `Error::Unsupported`
+    /// for inputs which can not seek, e.g. those of `from_reader`.
+    pub fn scan_length(&mut self) -> Result<ScanResult> {
+        if !self.reader.buffer().is_seekable() {
+            return errors::unsupported_error("scanning the length needs a seekable input");
+        }
+
+        let position = self.reader.buffer().position();
+        self.reader
+            .buffer()
+            .seek(io::SeekFrom::Start(self.header_len))?;
+        let result = self.reader.scan_length(&self.info);
+        self.reader.buffer().seek(io::SeekFrom::Start(position))?;
+        result
+    }
+
+    /// Indexes the frames of a FLAC stream, so that its samples iterators can seek anywhere
+    ///
+    /// FLAC frames do not store their length and files often have no SEEKTABLE,
+    /// so the whole stream is scanned like `scan_length` does, and the position
+    /// in the stream is restored afterwards. Use `export_seek_index` to keep the
+    /// index for the next time the file is opened. Fails with
+    /// `Error::Unsupported` for inputs which can not seek and for other formats.
+    pub fn build_seek_index(&mut self) -> Result<()> {
+        if !self.reader.buffer().is_seekable() {
+            return errors::unsupported_error("indexing the stream needs a seekable input");
+        }
+
+        let position = self.reader.buffer().position();
+        self.reader
+            .buffer()
+            .seek(io::SeekFrom::Start(self.header_len))?;
+        let result = self.reader.build_seek_index(&self.info);
+        self.reader.buffer().seek(io::SeekFrom::Start(position))?;
+        result
+    }
+
+    /// Returns the seek index built or loaded, encoded for `load_seek_index`
+    pub fn export_seek_index(&self) -> Option<Vec<u8>> {
+        self.reader.export_seek_index()
+    }
+
+    /// Loads a seek index of `export_seek_index`, or builds a new one if it does not fit
+    ///
+    /// The index starts with the length of the file and the length and MD5
+    /// signature of STREAMINFO, an index of another file or of a file which
+    /// was changed since is not trusted. Returns false if the index was
+    /// rejected and the stream scanned instead, errors are those of
+    /// `build_seek_index`.
+    pub fn load_seek_index(&mut self, index: &[u8]) -> Result<bool> {
+        if !self.reader.buffer().is_seekable() {
+            return errors::unsupported_error("indexing the stream needs a seekable input");
+        }
+        match self.reader.load_seek_index(index, &self.info) {
+            Ok(()) => Ok(true),
+            Err(errors::Error::ParseError(reason)) => {
+                tracing::warn!(reason, "seek index rejected, scanning the stream");
+                self.build_seek_index().map(|()| false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Checks that the whole stream decodes, without returning its samples
+    ///
+    /// This is for checking the integrity of many files. Every frame is decoded,
+    /// FLAC frames are checked against their CRCs and the whole stream against
+    /// the MD5 signature of STREAMINFO, MP3 frames have to agree on the sample
+    /// rate and channels, and WAV data chunks have to be as long as they claim.
+    /// No samples are converted, which makes this faster than reading them.
+    ///
+    /// Like `samples` this consumes the stream, the damage found is counted in
+    /// the report while errors of the input fail it.
+    pub fn verify(&mut self) -> Result<VerifyReport> {
+        if self.is_buffer_used {
+            return errors::unsupported_error("requesting iterator again");
+        }
+        self.is_buffer_used = true;
+        self.reader.verify(&self.info)
+    }
+
+    /// Checks that a FLAC stream is within the streamable subset, without returning its samples
+    ///
+    /// Hardware players often only play the subset, this is a check before
+    /// copying files to them. Every frame header and subframe header is
+    /// checked and each parameter outside of the subset is returned with the
+    /// index of its frame, an empty list means the stream is subset. The
+    /// residuals are read to get to the next subframe, but no samples are
+    /// reconstructed. Damaged frames fail the check with their error, which
+    /// `verify` is for.
+    ///
+    /// Like `samples` this consumes the stream. Other formats fail with
+    /// `Error::Unsupported`.
+    pub fn check_subset(&mut self) -> Result<Vec<SubsetViolation>> {
+        if self.is_buffer_used {
+            return errors::unsupported_error("requesting iterator again");
+        }
+        self.is_buffer_used = true;
+        self.reader.check_subset(&self.info)
+    }
+
+    /// Returns the minimum, maximum and RMS of each channel in `buckets` parts of equal length
+    ///
+    /// This is what a waveform overview draws, e.g. one bucket per pixel. The
+    /// samples are reduced while decoding, integer ones without converting
+    /// them. The result holds `buckets * channels` entries, all channels of
+    /// the first bucket first. Streams of unknown length are scanned first,
+    /// which needs a seekable input. For a zoomed view pass a trimmed
+    /// `Pipeline` to `overview::overview`.
+    ///
+    /// Like `samples` this consumes the stream.
+    pub fn overview(&mut self, buckets: usize) -> Result<Vec<MinMax>> {
+        overview::segment_overview(self, buckets)
+    }
+
+    /// Measures the integrated loudness and the true peak of the audio after ITU-R BS.1770
+    ///
+    /// Like `samples` this consumes the stream.
+    pub fn loudness(&mut self) -> Result<Loudness> {
+        loudness::segment_loudness(self)
+    }
+
+    /// Returns a digest of the decoded samples, equal only for equal audio
+    ///
+    /// The samples of `AudioInfo::recommended_sample_type` are hashed after a
+    /// header of the sample type, rate, channels and bits per sample, the
+    /// `hash` module documents the layout. A test comparing it against a known
+    /// digest catches any change to the decoded samples. Like `samples` this
+    /// consumes the stream.
+    pub fn decode_hash(&mut self, algo: HashAlgo) -> Result<[u8; 32]> {
+        hash::segment_decode_hash(self, algo)
+    }
+
+    /// Compares the left and the right channel, their correlation tells how well they mix to mono
+    ///
+    /// With a `window`, e.g. of 400 ms like a phase meter, the statistics are
+    /// returned for each part of that length too. The samples are decoded as
+    /// `f64`. Fails with `Error::Unsupported` for mono audio. Like `samples`
+    /// this consumes the stream.
+    pub fn stereo_analysis(&mut self, window: Option<std::time::Duration>) -> Result<StereoStats> {
+        stereo::segment_analysis(self, window)
+    }
+
+    /// Returns a pipeline bringing the audio to `target_lufs`, e.g. -23 for EBU R128
+    ///
+    /// This decodes the audio twice: the first pass measures its loudness, the
+    /// pipeline returned decodes it again with a `gain_db` stage. The gain is
+    /// reduced so that the true peak stays at or below `true_peak_ceiling_db`,
+    /// then the audio is quieter than the target. Stages added to the pipeline
+    /// which change the loudness, e.g. `downmix`, are not accounted for.
+    ///
+    /// Fails with `Error::Unsupported` for inputs which can not seek, and for
+    /// audio without loudness, e.g. silence.
+    pub fn normalize_loudness(
+        &mut self,
+        target_lufs: f64,
+        true_peak_ceiling_db: f64,
+    ) -> Result<Pipeline<'_>> {
+        if !self.reader.buffer().is_seekable() {
+            return errors::unsupported_error("normalizing the loudness needs a seekable input");
+        }
+        let measured = self.loudness()?;
+        let gain = loudness::normalization_gain(&measured, target_lufs, true_peak_ceiling_db)?;
+        self.restart()?;
+        Ok(self.process().gain_db(gain as f32))
+    }
+
+    /// Returns to the first sample, so that the samples can be requested again
+    ///
+    /// The statistics of the frames decoded before are dropped, they would be
+    /// counted twice.
+    fn restart(&mut self) -> Result<()> {
+        self.reader
+            .buffer()
+            .seek(io::SeekFrom::Start(self.header_len))?;
+        self.is_buffer_used = false;
+        self.frame_stats.clear();
+        self.decode_stats = DecodeStats::default();
+        self.wasted_bits.clear();
+        self.sample_gaps.clear();
+        Ok(())
+    }
+
+    /// Returns the channels in the order of the interleaved samples
+    ///
+    /// WAV and FLAC streams store their channels in the order of the
+    /// WAVEFORMATEXTENSIBLE speaker positions, front left, front right, front
+    /// center, LFE, back left, back right and so on. With
+    /// `DecodeOptions::reorder_channels` they are returned in the canonical
+    /// order instead, which is the order of the bits of `Channels`.
+    pub fn channel_order(&self) -> Vec<Channels> {
+        let mut order = stream_channel_order(self.info.channels);
+        if self.options.reorder_channels {
+            order.sort_by_key(|channel| channel.bits());
+        }
+        order
+    }
+
+    /// returns number of channels in the audio
+    pub fn number_channels(&self) -> usize {
+        self.info.channels.count()
+    }
+
+    /// Returns the playing time of the audio file
+    ///
+    /// duration = (total_samples / no_channels) / sampling_rate
+    ///
+    /// It is exact to the nanosecond for any length. It is zero if the header
+    /// does not tell the length, like the one of MP3, see `scan_length`, its
+    /// sample rate or its channels.
+    pub fn duration(&self) -> Duration {
+        let channels = self.number_channels() as u64;
+        if channels == 0 {
+            return Duration::ZERO;
+        }
+        crate::io::frames_duration(self.info.total_samples / channels, self.info.sample_rate)
+    }
+
+    /// Returns bitrate of the decoded audio in kbps
+    ///
+    /// It is 0 for lossy codecs, whose samples have no bit depth. The bitrates
+    /// of their frames are counted by `decode_stats`.
+    pub fn bitrate(&self) -> u32 {
+        (self.info.sample_rate / 1000) * self.info.bits_per_sample * self.number_channels() as u32
+    }
+
+    /// Returns the codec `write_wav` keeps all of the audio in
+    ///
+    /// Integer samples get the PCM codec of their `SampleFormat`, G.711 its
+    /// own codec and float samples 32 bit floats. Signed samples of up to 8
+    /// bits get unsigned 8 bit PCM, the only 8 bit PCM of WAV. Lossy codecs
+    /// get 16 bit PCM, their precision does not justify more.
+    pub fn default_wav_codec(&self) -> codecs::CodecType {
+        use codecs::{CodecType, SampleFormat};
+        let codec = self.info.codec_type;
+        if codec == CodecType::CODEC_TYPE_PCM_ALAW || codec == CodecType::CODEC_TYPE_PCM_MULAW {
+            return codec;
+        }
+        match (self.info.sample_format, self.info.bits_per_sample) {
+            (SampleFormat::U8, _) | (SampleFormat::S16, 0..=8) => CodecType::CODEC_TYPE_PCM_U8,
+            (SampleFormat::S16, _) => CodecType::CODEC_TYPE_PCM_S16LE,
+            (SampleFormat::S24, _) => CodecType::CODEC_TYPE_PCM_S24LE,
+            (SampleFormat::S32, _) => CodecType::CODEC_TYPE_PCM_S32LE,
+            (_, 0) => CodecType::CODEC_TYPE_PCM_S16LE,
+            (_, _) => CodecType::CODEC_TYPE_PCM_F32LE,
+        }
+    }
+
+    /// Returns an channel interleaved iterator on samples
+    pub fn samples<'a, S: Sample + 'a>(&'a mut self) -> Result<SampleIterator<'a, S>> {
+        if self.is_buffer_used {
+            return errors::unsupported_error("requesting iterator again");
+        }
+        self.is_buffer_used = true;
+        self.samples_iterator(None)
+    }
+
+    /// Returns an channel interleaved iterator on samples of `AudioInfo::recommended_sample_type`
+    ///
+    /// This is for callers which want the audio without picking a sample type
+    /// for every file, match on the returned variant to get the samples.
+    pub fn samples_auto(&mut self) -> Result<format::TypedSamples<'_>> {
+        let sample_type = self.info.recommended_sample_type();
+        Ok(typed_samples!(sample_type, S => self.samples::<S>()?))
+    }
+
+    /// Returns an channel interleaved iterator on samples, telling when the stream
+    /// parameters change
+    ///
+    /// Chained streams, e.g. FLAC files appended to each other or MP3 files glued
+    /// together, may change the sample rate, channels or bits per sample at a
+    /// frame boundary. `samples` decodes FLAC ones starting with a new header all
+    /// the same, with nothing to tell where a change happens, FLAC frames
+    /// changing the parameters without one are handled as the `Mismatch`
+    /// options of `DecodeOptions` tell. It stops MP3 ones with
+    /// `Error::InconsistentFrame` at the first frame changing the parameters,
+    /// its samples would play at the wrong rate. `info` always describes the
+    /// start of the stream, and channel selection and reordering of
+    /// `DecodeOptions` keep working on its channels.
+    pub fn events<'a, S: Sample + 'a>(&'a mut self) -> Result<Events<'a, S>> {
+        self.stream_changes = true;
+        Ok(Events {
+            samples: self.samples()?,
+            pending: None,
+        })
+    }
+
+    /// Returns an iterator on the samples of the selected channels only
+    ///
+    /// `channels` holds the indices of the channels to keep, the samples of those
+    /// channels are returned interleaved in stream order.
+    ///
+    /// For FLAC, subframes of the other channels are only parsed for their length,
+    /// without reconstructing the samples, which saves work on frames with
+    /// independently coded channels. Left/side, right/side and mid/side coded
+    /// frames still require both channels to be decoded.
+    pub fn channel_samples<'a, S: Sample + 'a>(
+        &'a mut self,
+        channels: &[usize],
+    ) -> Result<SampleIterator<'a, S>> {
+        if self.is_buffer_used {
+            return errors::unsupported_error("requesting iterator again");
+        }
+        // with reordering the indices are in the canonical order
+        let stream_index = if self.options.reorder_channels {
+            canonical_map(&stream_channel_order(self.info.channels), None)
+        } else {
+            Vec::new()
+        };
+        let mut mask = 0u8;
+        for &channel in channels {
+            if channel >= self.number_channels() {
+                return errors::unsupported_error("selected channel does not exist");
+            }
+            mask |= 1 << stream_index.get(channel).copied().unwrap_or(channel);
+        }
+        self.is_buffer_used = true;
+        self.samples_iterator(Some(mask))
+    }
+
+    /// Returns an channel interleaved iterator which repeats the audio
+    ///
+    /// At the end of the stream decoding continues at the start of the first
+    /// loop of a WAV `smpl` chunk, if there is one, or at the start of the audio
+    /// otherwise. The audio is played `passes` times, the first pass included,
+    /// or forever if `passes` is `None`.
+    ///
+    /// Restarting needs a seekable input, FLAC streams can only be looped from
+    /// their start unless they were indexed with `build_seek_index`.
+    pub fn looped<'a, S: Sample + 'a>(
+        &'a mut self,
+        passes: Option<usize>,
+    ) -> Result<SampleIterator<'a, S>> {
+        if !self.reader.buffer().is_seekable() {
+            return errors::unsupported_error("looping needs a seekable input");
+        }
+        // the smpl chunk usually follows the audio data
+        let position = self.reader.buffer().position();
+        self.reader.read_trailer();
+        self.reader.buffer().seek(io::SeekFrom::Start(position))?;
+
+        let loop_start = self
+            .reader
+            .sample_loops()
+            .first()
+            .map_or(0, |l| l.start as u64);
+        let samples = self.samples()?;
+        Ok(Box::new(Looped {
+            samples,
+            passes_left: passes.map(|p| p.saturating_sub(1)),
+            loop_start,
+            pass_has_samples: false,
+        }))
+    }
+
+    /// Returns a builder chaining processing stages onto the samples
+    ///
+    /// For example the samples downmixed to stereo, resampled to 48 kHz and
+    /// attenuated by 3 dB are returned by
+    /// `segment.process().downmix(ChannelLayout::Stereo).resample(48_000).gain_db(-3.0).build::<f32>()`.
+    /// See the `process` module for the order the stages are applied in.
+    pub fn process(&mut self) -> Pipeline<'_> {
+        Pipeline::new(self)
+    }
+
+    /// Decodes the audio and writes it as raw interleaved PCM with `bits` per sample
+    ///
+    /// Samples are little endian, 8 bit samples are unsigned like in WAV. They
+    /// are scaled from the bit depth of the stream, and requantized with `dither`
+    /// if `bits` is lower. The dither noise is always seeded the same, so the
+    /// output is reproducible. Float streams are not supported.
+    ///
+    /// Returns the number of samples written.
+    pub fn write_pcm<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+        bits: u16,
+        dither: Dither,
+    ) -> Result<u64> {
+        let codec = match bits {
+            8 => codecs::CodecType::CODEC_TYPE_PCM_U8,
+            16 => codecs::CodecType::CODEC_TYPE_PCM_S16LE,
+            24 => codecs::CodecType::CODEC_TYPE_PCM_S24LE,
+            32 => codecs::CodecType::CODEC_TYPE_PCM_S32LE,
+            _ => return errors::unsupported_error("bits per sample must be 8, 16, 24 or 32"),
+        };
+        self.write_encoded(writer, codec, dither)
+    }
+
+    /// Decodes the audio and writes it interleaved in `codec`
+    ///
+    /// `codec` is one of the integer PCM codecs written by `write_pcm`, or
+    /// `CODEC_TYPE_PCM_ALAW` or `CODEC_TYPE_PCM_MULAW` for G.711 telephony
+    /// audio. Those take the samples requantized to 16 bits with `dither`, see
+    /// the `g711` module. Float samples, e.g. the ones of MP3, are scaled to
+    /// the integer range of `codec` and clipped.
+    /// `CODEC_TYPE_PCM_F32LE` writes the samples as returned by `samples::<f32>`,
+    /// whatever the type of the stream.
+    ///
+    /// Returns the number of samples written.
+    pub fn write_encoded<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+        codec: codecs::CodecType,
+        dither: Dither,
+    ) -> Result<u64> {
+        self.write_mapped(writer, codec, dither, None)
+    }
+
+    /// Same as `write_encoded`, but writes the channels of `map`
+    fn write_mapped<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+        codec: codecs::CodecType,
+        dither: Dither,
+        map: Option<ChannelMap>,
+    ) -> Result<u64> {
+        let bits = match codec {
+            codecs::CodecType::CODEC_TYPE_PCM_U8 => 8,
+            codecs::CodecType::CODEC_TYPE_PCM_S16LE
+            | codecs::CodecType::CODEC_TYPE_PCM_ALAW
+            | codecs::CodecType::CODEC_TYPE_PCM_MULAW => 16,
+            codecs::CodecType::CODEC_TYPE_PCM_S24LE => 24,
+            codecs::CodecType::CODEC_TYPE_PCM_S32LE => 32,
+            codecs::CodecType::CODEC_TYPE_PCM_F32LE => return self.write_f32(writer, map),
+            _ => {
+                return errors::unsupported_error(
+                    "only integer PCM, 32 bit float, A-law and mu-law can be written",
+                )
+            }
+        };
+        let encode: Option<fn(i16) -> u8> = match codec {
+            codecs::CodecType::CODEC_TYPE_PCM_ALAW => Some(g711::encode_alaw),
+            codecs::CodecType::CODEC_TYPE_PCM_MULAW => Some(g711::encode_ulaw),
+            _ => None,
+        };
+
+        use std::io::Write;
+        let mut writer = io::BufWriter::new(writer);
+        let mut written = 0u64;
+        let channels = self.number_channels();
+        if self.info.sample_format.is_float() {
+            let samples: Box<dyn Iterator<Item = Result<f64>>> = if self.info.sample_format
+                == codecs::SampleFormat::F64
+            {
+                Box::new(mapped(self.samples::<f64>()?, channels, map))
+            } else {
+                Box::new(mapped(self.samples::<f32>()?, channels, map).map(|s| s.map(f64::from)))
+            };
+            let scale = (1u64 << (bits - 1)) as f64;
+            for sample in samples {
+                let sample = (sample? * scale).round().clamp(-scale, scale - 1.0) as i32;
+                match encode {
+                    Some(encode) => writer.write_u8(encode(sample as i16))?,
+                    None => sample.write_pcm(&mut writer, bits)?,
+                }
+                written += 1;
+            }
+            writer.flush()?;
+            return Ok(written);
+        }
+
+        let output_channels = map.map_or(channels, |map| map.sources().len());
+        let mut requantizer = Requantizer::new(
+            dither,
+            self.info.bits_per_sample,
+            bits as u32,
+            output_channels,
+            DITHER_SEED,
+        )?;
+        let offset = self.info.sample_format.offset();
+
+        for sample in mapped(self.samples::<i32>()?, channels, map) {
+            let channel = (written % output_channels as u64) as usize;
+            let sample = requantizer.requantize(sample? - offset, channel);
+            match encode {
+                Some(encode) => writer.write_u8(encode(sample as i16))?,
+                None => sample.write_pcm(&mut writer, bits)?,
+            }
+            written += 1;
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Writes the samples as 32 bit floats, see `write_encoded`
+    fn write_f32<W: io::Write>(&mut self, writer: &mut W, map: Option<ChannelMap>) -> Result<u64> {
+        use std::io::Write;
+        let mut writer = io::BufWriter::new(writer);
+        let mut written = 0u64;
+        let channels = self.number_channels();
+        for sample in mapped(self.samples::<f32>()?, channels, map) {
+            writer.write_le_f32(sample?)?;
+            written += 1;
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Decodes the audio and writes it as a WAV file in `codec`
+    ///
+    /// The samples are encoded like by `write_encoded`. Formats other than
+    /// integer PCM have a fact chunk with the number of inter-channel samples.
+    /// The sizes are written once the audio is, hence `writer` has to seek.
+    /// Raw MP3 streams are scanned for the sample rate of their frames, which
+    /// fails with `Error::Unsupported` if the input can not seek.
+    ///
+    /// Returns the number of samples written.
+    #[cfg(feature = "wav")]
+    pub fn write_wav<W: io::Write + io::Seek>(
+        &mut self,
+        writer: &mut W,
+        codec: codecs::CodecType,
+        dither: Dither,
+    ) -> Result<u64> {
+        self.write_wav_with_options(writer, codec, dither, WavOptions::default())
+    }
+
+    /// Same as `write_wav`, but writes the header according to `options`
+    ///
+    /// The fmt chunk is WAVEFORMATEXTENSIBLE, with the number of valid bits
+    /// and the channel mask of the speaker positions, for more than two
+    /// channels, more than 16 bits per sample, or speakers other than front
+    /// left for one and front left and right for two channels. Other files
+    /// get the basic fmt chunk, as do all if `options.force_basic_fmt` is set.
+    /// `options.channel_map` selects, reorders or repeats the channels written.
+    #[cfg(feature = "wav")]
+    pub fn write_wav_with_options<W: io::Write + io::Seek>(
+        &mut self,
+        writer: &mut W,
+        codec: codecs::CodecType,
+        dither: Dither,
+        options: WavOptions,
+    ) -> Result<u64> {
+        if let Some(map) = options.channel_map {
+            if map.sources().iter().any(|&i| i >= self.number_channels()) {
+                return errors::unsupported_error(
+                    "the channel map refers to a channel the audio lacks",
+                );
+            }
+        }
+        let (speakers, output_channels) = match (options.channels, options.channel_map) {
+            (Some(speakers), map) => (
+                speakers,
+                map.map_or(self.number_channels(), |map| map.sources().len()),
+            ),
+            (None, Some(map)) => (map.speakers(self.info.channels)?, map.sources().len()),
+            (None, None) => (self.info.channels, self.number_channels()),
+        };
+        if speakers.count() != output_channels {
+            return errors::unsupported_error("the channels do not match the number of channels");
+        }
+        // raw MP3 headers leave the sample rate to the frames
+        let sample_rate = match self.info.sample_rate {
+            0 if self.reader.buffer().is_seekable() => self.scan_length()?.sample_rate,
+            sample_rate => sample_rate,
+        };
+        if sample_rate == 0 {
+            return errors::unsupported_error("the sample rate of the stream is unknown");
+        }
+        let start = writer.stream_position()?;
+        let header = crate::wav::write::Header::new(codec, speakers, sample_rate, options)?;
+        header.write(writer, 0)?;
+        let written = self.write_mapped(writer, codec, dither, options.channel_map)?;
+        let end = writer.stream_position()?;
+        writer.seek(io::SeekFrom::Start(start))?;
+        header.write(writer, written)?;
+        writer.seek(io::SeekFrom::Start(end))?;
+        header.write_padding(writer, written)?;
+        Ok(written)
+    }
+
+    /// Decodes the audio and writes it as a FLAC stream
+    ///
+    /// The stream has the sample rate, channels and bits per sample of the
+    /// audio, and integer audio decodes from it to the samples it has here.
+    /// Unsigned 8 bit samples are made signed, audio of fewer than 4 bits is
+    /// scaled to 4 bits, and float audio, e.g. MP3, is written with 24 bits,
+    /// requantized with `dither`. FLAC holds at most 8 channels, more fail with
+    /// `Error::Unsupported`. It stores no speaker positions, readers assume the
+    /// ones of the number of channels, e.g. 5.1 for 6 channels.
+    ///
+    /// The number of inter-channel samples, the frame sizes and the MD5
+    /// signature in STREAMINFO are written once the audio is, hence `writer`
+    /// has to seek. Raw MP3 streams are scanned for their sample rate like by
+    /// `write_wav`. STREAMINFO is the only metadata block written, the
+    /// pictures and the other metadata of the source are dropped, see
+    /// `export_pictures` to keep the pictures.
+    ///
+    /// Returns the number of samples written.
+    #[cfg(feature = "flac")]
+    pub fn write_flac<W: io::Write + io::Seek>(
+        &mut self,
+        writer: &mut W,
+        dither: Dither,
+    ) -> Result<u64> {
+        let channels = self.number_channels();
+        if channels > 8 {
+            return errors::unsupported_error("FLAC holds at most 8 channels");
+        }
+        // raw MP3 headers leave the sample rate to the frames
+        let sample_rate = match self.info.sample_rate {
+            0 if self.reader.buffer().is_seekable() => self.scan_length()?.sample_rate,
+            sample_rate => sample_rate,
+        };
+        if sample_rate == 0 {
+            return errors::unsupported_error("the sample rate of the stream is unknown");
+        }
+        let (source_bits, bits) = match self.info.sample_format.is_float() {
+            false => (self.info.bits_per_sample, self.info.bits_per_sample.max(4)),
+            true => (32, 24),
+        };
+        let total_frames = self.info.total_samples / channels.max(1) as u64;
+        let mut encoder =
+            crate::flac::write::Encoder::new(writer, sample_rate, channels, bits, total_frames)?;
+        let mut requantizer = Requantizer::new(dither, source_bits, bits, channels, DITHER_SEED)?;
+
+        // floats are scaled to 32 bits first
+        let scale = |sample: f64| {
+            (sample * 2_147_483_648.0)
+                .round()
+                .clamp(i32::MIN as f64, i32::MAX as f64) as i32
+        };
+        let samples: Box<dyn Iterator<Item = Result<i32>>> = match self.info.sample_format {
+            codecs::SampleFormat::F64 => {
+                Box::new(self.samples::<f64>()?.map(move |s| s.map(scale)))
+            }
+            codecs::SampleFormat::F32 => Box::new(
+                self.samples::<f32>()?
+                    .map(move |s| s.map(|s| scale(s as f64))),
+            ),
+            _ => {
+                let offset = self.info.sample_format.offset();
+                Box::new(self.samples::<i32>()?.map(move |s| s.map(|s| s - offset)))
+            }
+        };
+
+        let mut chunk = Vec::with_capacity(crate::flac::write::BLOCK_SIZE * channels);
+        let mut written = 0u64;
+        for sample in samples {
+            let channel = (written % channels as u64) as usize;
+            chunk.push(requantizer.requantize(sample?, channel));
+            written += 1;
+            if chunk.len() == chunk.capacity() {
+                encoder.write_samples(&chunk)?;
+                chunk.clear();
+            }
+        }
+        encoder.write_samples(&chunk)?;
+        encoder.finish()?;
+        Ok(written)
+    }
+
+    /// Decodes the audio and writes it as a FLAC file at `path`, see `write_flac`
+    ///
+    /// Float audio is requantized to 24 bits with `Dither::Tpdf`.
+    #[cfg(feature = "flac")]
+    pub fn export_flac(&mut self, path: &Path) -> Result<u64> {
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_flac(&mut file, Dither::Tpdf)
+    }
+
+    fn samples_iterator<'a, S: Sample + 'a>(
+        &'a mut self,
+        channel_mask: Option<u8>,
+    ) -> Result<SampleIterator<'a, S>> {
+        let context = format::DecodeContext {
+            reader: &mut self.reader,
+            info: &self.info,
+            options: self.options,
+            channel_mask,
+            stream_changes: self.stream_changes,
+            frame_stats: if self.options.frame_stats {
+                Some(&mut self.frame_stats)
+            } else {
+                None
+            },
+            decode_stats: if self.options.decode_stats {
+                Some(&mut self.decode_stats)
+            } else {
+                None
+            },
+            wasted_bits: &mut self.wasted_bits,
+            sample_gaps: &mut self.sample_gaps,
+            instrumentation: self.instrumentation.clone(),
+        };
+        let mut itr = match S::from_typed(self.format.samples(context, S::sample_type())?) {
+            Some(itr) => itr,
+            None => return errors::unsupported_error("format returned samples of another type"),
+        };
+
+        let number_channels = self.info.channels.count() as u32;
+        // FLAC selects the channels while decoding, the other formats afterwards
+        if let (Some(channel_mask), false) = (channel_mask, self.format.selects_channels()) {
+            itr = Box::new(SelectedChannels {
+                samples: itr,
+                number_channels,
+                channel_mask,
+                current_channel: 0,
+            });
+        }
+
+        if self.options.reorder_channels {
+            let map = canonical_map(&stream_channel_order(self.info.channels), channel_mask);
+            if map.iter().enumerate().any(|(i, c)| i != *c) {
+                itr = Box::new(Reordered {
+                    samples: itr,
+                    channels: map.len(),
+                    frame: Vec::with_capacity(map.len()),
+                    next_channel: map.len(),
+                    map,
+                });
+            }
+        }
+
+        let has_padding = self.info.encoder_delay.is_some() || self.info.encoder_padding.is_some();
+        if self.options.trim_encoder_padding && has_padding {
+            let selected_channels = channel_mask.map_or(number_channels, |m| m.count_ones());
+            itr = Box::new(TrimmedSamples::new(itr, &self.info, selected_channels));
+        }
+
+        Ok(itr)
+    }
+
+    pub(crate) fn get_format_flag(filename: &str) -> Result<codecs::FormatFlag> {
+        let extension = match filename.split('.').next_back() {
+            Some(ex) => ex,
+            None => return errors::unsupported_error("no decoder flag found for given file"),
+        };
+        if let Some(flag) = format::find_extension(extension) {
+            return Ok(flag);
+        }
+        match extension {
+            "wav" => Ok(codecs::FormatFlag::WAV),
+            "flac" => Ok(codecs::FormatFlag::FLAC),
+            "mp3" => Ok(codecs::FormatFlag::MP3),
+            "aac" => Ok(codecs::FormatFlag::AAC),
+            "ogg" => Ok(codecs::FormatFlag::VORBIS),
+            "opus" => Ok(codecs::FormatFlag::OPUS),
+            "aif" | "aiff" | "aifc" => Ok(codecs::FormatFlag::AIFF),
+            "caf" => Ok(codecs::FormatFlag::CAF),
+            "w64" => Ok(codecs::FormatFlag::W64),
+            "raw" => Ok(codecs::FormatFlag::PCM),
+            "pcm" => Ok(codecs::FormatFlag::PCM),
+            _ => errors::unsupported_error("no decoder flag found for given file"),
+        }
+    }
+}
+
+/// seed of the dither noise of `AudioSegment::write_pcm`
+const DITHER_SEED: u32 = 0x2545_f491;
+
+/// The speaker positions of WAVEFORMATEXTENSIBLE, in the order their channels are stored
+///
+/// FLAC defines the same order for its channel assignments.
+pub(crate) const STREAM_CHANNEL_ORDER: [Channels; 18] = [
+    Channels::FRONT_LEFT,
+    Channels::FRONT_RIGHT,
+    Channels::FRONT_CENTRE,
+    Channels::LFE1,
+    Channels::BACK_LEFT,
+    Channels::BACK_RIGHT,
+    Channels::FRONT_LEFT_CENTRE,
+    Channels::FRONT_RIGHT_CENTRE,
+    Channels::BACK_CENTRE,
+    Channels::SIDE_LEFT,
+    Channels::SIDE_RIGHT,
+    Channels::TOP_CENTRE,
+    Channels::TOP_FRONT_LEFT,
+    Channels::TOP_FRONT_CENTRE,
+    Channels::TOP_FRONT_RIGHT,
+    Channels::TOP_BACK_LEFT,
+    Channels::TOP_BACK_CENTRE,
+    Channels::TOP_BACK_RIGHT,
+];
+
+/// Returns the channels of a stream in the order they are stored
+///
+/// Channels without a WAV speaker position follow in the order of their bits.
+pub(crate) fn stream_channel_order(channels: Channels) -> Vec<Channels> {
+    let mut order: Vec<Channels> = STREAM_CHANNEL_ORDER
+        .iter()
+        .copied()
+        .filter(|channel| channels.contains(*channel))
+        .collect();
+    for bit in 0..32 {
+        let channel = Channels::from_bits_truncate(1 << bit);
+        if !channel.is_empty() && channels.contains(channel) && !order.contains(&channel) {
+            order.push(channel);
+        }
+    }
+    order
+}
+
+/// Returns for each channel in the canonical order its index in the frames of the stream
+///
+/// Only the channels in `channel_mask` are kept, the indices count these only.
+fn canonical_map(stream_order: &[Channels], channel_mask: Option<u8>) -> Vec<usize> {
+    let selected: Vec<Channels> = stream_order
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| channel_mask.is_none_or(|mask| (mask >> i) & 1 == 1))
+        .map(|(_, channel)| *channel)
+        .collect();
+    let mut map: Vec<usize> = (0..selected.len()).collect();
+    map.sort_by_key(|&i| selected[i].bits());
+    map
+}
+
+/// Subtracts the wasted bits of each channel, `None` if nothing was decoded yet
+pub(crate) fn effective_bits(wasted_bits: &[u32], bits_per_sample: u32) -> Option<Vec<u32>> {
+    if wasted_bits.is_empty() {
+        return None;
+    }
+    Some(
+        wasted_bits
+            .iter()
+            .map(|w| bits_per_sample.saturating_sub(*w))
+            .collect(),
+    )
+}
+
+/// Returns the channels of `map` of the interleaved `samples` of `channels` channels
+fn mapped<'a, S: Sample + 'a>(
+    samples: SampleIterator<'a, S>,
+    channels: usize,
+    map: Option<ChannelMap>,
+) -> SampleIterator<'a, S> {
+    match map {
+        Some(map) => Box::new(Reordered {
+            samples,
+            channels,
+            map: map.sources().to_vec(),
+            frame: Vec::with_capacity(channels),
+            next_channel: map.sources().len(),
+        }),
+        None => samples,
+    }
+}
+
+/// Drops the samples of the channels not in `channel_mask` from an interleaved iterator
+struct SelectedChannels<'a, S: Sample> {
+    samples: SampleIterator<'a, S>,
+    number_channels: u32,
+    channel_mask: u8,
+    current_channel: u32,
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for SelectedChannels<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        loop {
+            let sample = self.samples.next()?;
+            // the sample failing is read again after an IO error
+            if sample.is_err() {
+                return Some(sample);
+            }
+            let channel = self.current_channel;
+            self.current_channel = (self.current_channel + 1) % self.number_channels;
+            if (self.channel_mask >> channel) & 1 == 1 {
+                return Some(sample);
+            }
+        }
+    }
+
+    /// Reads the dropped channels into scratch buffers, keeping a planar read of `samples`
+    fn read_planar(&mut self, out: &mut [&mut [S]]) -> Result<usize> {
+        // the dropped channels after the inter-channel sample returned last
+        while self.current_channel != 0 {
+            match self.samples.next() {
+                Some(sample) => sample.map(drop)?,
+                None => return Ok(0),
+            }
+            self.current_channel = (self.current_channel + 1) % self.number_channels;
+        }
+        let frames = planar_len(out)?;
+        let selected: Vec<bool> = (0..self.number_channels)
+            .map(|c| (self.channel_mask >> c) & 1 == 1)
+            .collect();
+        let dropped = selected.iter().filter(|&&s| !s).count();
+        if selected.len() - dropped != out.len() {
+            return errors::unsupported_error("number of channel buffers does not match");
+        }
+        if frames == 0 {
+            return Ok(0);
+        }
+        let mut scratch = vec![S::from_i32(0, 8)?; frames * dropped];
+        let mut scratch = scratch.chunks_mut(frames);
+        let mut out = out.iter_mut();
+        let mut all: Vec<&mut [S]> = Vec::with_capacity(selected.len());
+        for selected in selected {
+            let buffer = match selected {
+                true => out.next().map(|o| &mut **o),
+                false => scratch.next(),
+            };
+            all.extend(buffer);
+        }
+        self.samples.read_planar(&mut all)
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.samples.seek_frame(frame)?;
+        self.current_channel = 0;
+        Ok(())
+    }
+    fn samples_left(&self) -> Option<u64> {
+        let left = self.samples.samples_left()?;
+        let n = self.number_channels as u64;
+        let selected = |channels: std::ops::Range<u64>| {
+            channels
+                .filter(|c| (self.channel_mask >> (c % n)) & 1 == 1)
+                .count() as u64
+        };
+        let from = self.current_channel as u64;
+        Some(left / n * selected(0..n) + selected(from..from + left % n))
+    }
+    fn remaining_frames(&self) -> Option<u64> {
+        let selected = (0..self.number_channels)
+            .filter(|c| (self.channel_mask >> c) & 1 == 1)
+            .count();
+        Some(frames_of(self.samples_left()?, selected as u64))
+    }
+    fn sample_rate(&self) -> Option<u32> {
+        self.samples.sample_rate()
+    }
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        self.samples.effective_bits_per_sample()
+    }
+    fn take_stream_change(&mut self) -> Option<AudioInfo> {
+        self.samples.take_stream_change()
+    }
+    fn last_good_position(&self) -> Option<StreamPosition> {
+        self.samples.last_good_position()
+    }
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        self.samples.resume_after_io_error()
+    }
+}
+
+/// Permutes the channels of every inter-channel sample of an interleaved iterator
+///
+/// Channels may also be repeated or left out of `map`.
+struct Reordered<'a, S: Sample> {
+    samples: SampleIterator<'a, S>,
+    /// number of channels of the stream
+    channels: usize,
+    /// index in the stream frame of each returned channel
+    map: Vec<usize>,
+    frame: Vec<S>,
+    /// index in `map` of the next channel to return
+    next_channel: usize,
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for Reordered<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        if self.next_channel == self.map.len() {
+            // the samples read before an error are kept for the retry
+            while self.frame.len() < self.channels {
+                match self.samples.next() {
+                    Some(sample) => self.frame.push(otry!(sample)),
+                    None if self.frame.is_empty() => return None,
+                    None => {
+                        return Some(errors::parse_error(
+                            "stream ended within an inter-channel sample",
+                        ))
+                    }
+                }
+            }
+            self.next_channel = 0;
+        }
+        self.next_channel += 1;
+        let sample = self.frame[self.map[self.next_channel - 1]];
+        if self.next_channel == self.map.len() {
+            self.frame.clear();
+        }
+        Some(Ok(sample))
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.samples.seek_frame(frame)?;
+        self.frame.clear();
+        self.next_channel = self.map.len();
+        Ok(())
+    }
+    fn samples_left(&self) -> Option<u64> {
+        let (returned, read) = match self.map.len() - self.next_channel {
+            // an inter-channel sample read in part before an error
+            0 => (0, self.frame.len()),
+            left => (left, 0),
+        };
+        let stream = self.samples.samples_left()? + read as u64;
+        Some(returned as u64 + stream / self.channels as u64 * self.map.len() as u64)
+    }
+    fn remaining_frames(&self) -> Option<u64> {
+        Some(frames_of(self.samples_left()?, self.map.len() as u64))
+    }
+    fn sample_rate(&self) -> Option<u32> {
+        self.samples.sample_rate()
+    }
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        let bits = self.samples.effective_bits_per_sample()?;
+        if bits.len() != self.channels {
+            return Some(bits);
+        }
+        Some(self.map.iter().map(|&i| bits[i]).collect())
+    }
+    fn take_stream_change(&mut self) -> Option<AudioInfo> {
+        self.samples.take_stream_change()
+    }
+    fn last_good_position(&self) -> Option<StreamPosition> {
+        self.samples.last_good_position()
+    }
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        self.samples.resume_after_io_error()
+    }
+}
+
+/// Drops the encoder delay and padding from an interleaved iterator
+struct TrimmedSamples<'a, S: Sample> {
+    samples: SampleIterator<'a, S>,
+    /// number of samples still to drop at the start
+    skip: u64,
+    /// number of samples still to return, `None` if the length is unknown
+    remaining: Option<u64>,
+    /// encoder delay in inter-channel samples
+    delay: u64,
+    /// number of channels returned by `samples`
+    channels: u32,
+    /// number of samples returned in total, `None` if the length is unknown
+    total: Option<u64>,
+}
+
+impl<'a, S: Sample> TrimmedSamples<'a, S> {
+    /// `channels` is the number of channels returned by `samples`
+    fn new(samples: SampleIterator<'a, S>, info: &AudioInfo, channels: u32) -> Self {
+        let delay = info.encoder_delay.unwrap_or(0) as u64;
+        let padding = info.encoder_padding.unwrap_or(0) as u64;
+
+        // Without the length the padding could only be found by buffering the
+        // tail of the stream, so it is kept.
+        let remaining = if info.total_samples > 0 {
+            let frames = info.total_samples / info.channels.count().max(1) as u64;
+            Some(frames.saturating_sub(delay + padding) * channels as u64)
+        } else {
+            None
+        };
+
+        TrimmedSamples {
+            samples,
+            skip: delay * channels as u64,
+            remaining,
+            delay,
+            channels,
+            total: remaining,
+        }
+    }
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for TrimmedSamples<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        while self.skip > 0 {
+            if let Err(err) = self.samples.next()? {
+                return Some(Err(err));
+            }
+            self.skip -= 1;
+        }
+
+        if self.remaining == Some(0) {
+            return None;
+        }
+        let sample = self.samples.next()?;
+        // the sample failing is read again after an IO error
+        if let (Some(remaining), Ok(_)) = (self.remaining.as_mut(), &sample) {
+            *remaining -= 1;
+        }
+        Some(sample)
+    }
+
+    fn read_planar(&mut self, out: &mut [&mut [S]]) -> Result<usize> {
+        while self.skip > 0 {
+            match self.samples.next() {
+                Some(sample) => sample.map(drop)?,
+                None => return Ok(0),
+            }
+            self.skip -= 1;
+        }
+        let frames = planar_len(out)?;
+        let read = match self.remaining {
+            Some(remaining) if remaining < (frames * out.len()) as u64 => {
+                let frames = (remaining / out.len() as u64) as usize;
+                let mut out: Vec<&mut [S]> = out.iter_mut().map(|o| &mut o[..frames]).collect();
+                self.samples.read_planar(&mut out)?
+            }
+            _ => self.samples.read_planar(out)?,
+        };
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= (read * out.len()) as u64;
+        }
+        Ok(read)
+    }
+
+    /// `frame` is counted after the delay
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.samples.seek_frame(self.delay + frame)?;
+        self.skip = 0;
+        let consumed = frame * self.channels as u64;
+        self.remaining = self.total.map(|total| total.saturating_sub(consumed));
+        Ok(())
+    }
+    /// Only known if the inner iterator knows it, `remaining` may count past its end
+    fn samples_left(&self) -> Option<u64> {
+        let left = self.samples.samples_left()?.saturating_sub(self.skip);
+        Some(self.remaining.map_or(left, |remaining| remaining.min(left)))
+    }
+    fn remaining_frames(&self) -> Option<u64> {
+        Some(frames_of(self.samples_left()?, self.channels as u64))
+    }
+    fn sample_rate(&self) -> Option<u32> {
+        self.samples.sample_rate()
+    }
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        self.samples.effective_bits_per_sample()
+    }
+    fn take_stream_change(&mut self) -> Option<AudioInfo> {
+        self.samples.take_stream_change()
+    }
+    fn last_good_position(&self) -> Option<StreamPosition> {
+        self.samples.last_good_position()
+    }
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        self.samples.resume_after_io_error()
+    }
+}
+
+/// Repeats an iterator by seeking back once it is exhausted
+struct Looped<'a, S: Sample> {
+    samples: SampleIterator<'a, S>,
+    /// number of passes left after the current one, `None` repeats forever
+    passes_left: Option<usize>,
+    /// inter-channel sample the passes after the first start at
+    loop_start: u64,
+    /// set once the current pass returned a sample, an empty pass ends the loop
+    pass_has_samples: bool,
+}
+
+impl<'a, S: Sample> Looped<'a, S> {
+    /// Seeks back to the start of the next pass, false if there is none
+    fn next_pass(&mut self) -> Result<bool> {
+        match self.passes_left {
+            Some(0) => return Ok(false),
+            Some(ref mut passes) => *passes -= 1,
+            None => {}
+        }
+        if !self.pass_has_samples {
+            return Ok(false);
+        }
+        self.pass_has_samples = false;
+        self.samples.seek_frame(self.loop_start)?;
+        Ok(true)
+    }
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for Looped<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        loop {
+            if let Some(sample) = self.samples.next() {
+                self.pass_has_samples = true;
+                return Some(sample);
+            }
+            if !otry!(self.next_pass()) {
+                return None;
+            }
+        }
+    }
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        self.samples.effective_bits_per_sample()
+    }
+    fn take_stream_change(&mut self) -> Option<AudioInfo> {
+        self.samples.take_stream_change()
+    }
+    fn last_good_position(&self) -> Option<StreamPosition> {
+        self.samples.last_good_position()
+    }
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        self.samples.resume_after_io_error()
+    }
+
+    /// Fills `out` across the passes, the end of a pass is no end of the stream
+    fn read_planar(&mut self, out: &mut [&mut [S]]) -> Result<usize> {
+        let frames = planar_len(out)?;
+        let mut written = 0;
+        while written < frames {
+            let mut rest: Vec<&mut [S]> = out.iter_mut().map(|o| &mut o[written..]).collect();
+            match self.samples.read_planar(&mut rest)? {
+                0 if !self.next_pass()? => break,
+                0 => {}
+                read => {
+                    self.pass_has_samples = true;
+                    written += read;
+                }
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// A one-line summary, e.g. `FLAC 44.1 kHz 16-bit stereo, 3:25, 812 kbps`
+///
+/// The duration and bitrate are left out if they are not known. `{:#}` prints
+/// the audio info, the FLAC STREAMINFO and both on several lines instead.
 impl fmt::Display for AudioSegment {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "AudioInfo:\n{}\n", self.info)?;
+        if !f.alternate() {
+            write!(f, "{}", self.format())?;
+            self.info.write_parameters(f)?;
+            if self.info.total_samples != 0 && self.info.sample_rate != 0 {
+                let duration = self.duration();
+                if duration.as_secs() < 60 {
+                    write!(f, ", {:.2} s", duration.as_secs_f64())?;
+                } else {
+                    let seconds = duration.as_secs();
+                    match seconds / 3600 {
+                        0 => write!(f, ", {}:{:02}", seconds / 60, seconds % 60)?,
+                        hours => write!(
+                            f,
+                            ", {}:{:02}:{:02}",
+                            hours,
+                            seconds / 60 % 60,
+                            seconds % 60
+                        )?,
+                    }
+                }
+            }
+            if self.bitrate() != 0 {
+                write!(f, ", {} kbps", self.bitrate())?;
+            }
+            return Ok(());
+        }
+        write!(f, "AudioInfo:\n{:#}\n", self.info)?;
+        if let Some(stream_info) = self.flac_stream_info() {
+            write!(f, "FlacStreamInfo:\n{}\n", stream_info)?;
+        }
         write!(
             f,
             "duration: {}s, bitrate: {} kb/s",
-            self.duration(),
+            self.duration().as_secs_f64(),
             self.bitrate()
         )?;
         Ok(())
     }
 }
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac", feature = "mp3"))]
+fn test_read_probes_mislabeled_files() {
+    let mut stream = crate::testing::FlacStream::new(22_050, 1, 16);
+    stream.frame(&[vec![4; 16]]);
+
+    let dir = std::env::temp_dir();
+    let mislabeled = dir.join(format!("cauldron-probe-{}.wav", std::process::id()));
+    std::fs::write(&mislabeled, stream.build()).unwrap();
+    let garbage = dir.join(format!("cauldron-probe-{}.flac", std::process::id()));
+    std::fs::write(&garbage, b"neither wav, flac nor mp3").unwrap();
+
+    let segment = AudioSegment::read(mislabeled.to_str().unwrap());
+    let not_audio = AudioSegment::read(garbage.to_str().unwrap());
+    std::fs::remove_file(&mislabeled).unwrap();
+    std::fs::remove_file(&garbage).unwrap();
+
+    let mut segment = segment.unwrap();
+    assert_eq!(
+        segment.info().codec_type,
+        codecs::CodecType::CODEC_TYPE_FLAC
+    );
+    assert_eq!(segment.header_len(), 42);
+    let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![4; 16]);
+
+    match not_audio {
+        Err(err @ errors::Error::UnrecognizedFormat(_)) => {
+            assert_eq!(err.to_string(), "Unrecognized format, tried FLAC, WAV, MP3");
+        }
+        _ => panic!("expected the format to be unrecognized"),
+    }
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_trim_encoder_padding() {
+    let left: Vec<i32> = (0..32).collect();
+    let right: Vec<i32> = (100..132).collect();
+    let mut stream = crate::testing::FlacStream::new(44_100, 2, 16);
+    stream
+        .frame(&[left[..16].to_vec(), right[..16].to_vec()])
+        .frame(&[left[16..].to_vec(), right[16..].to_vec()]);
+
+    let decode = |stream: &crate::testing::FlacStream, options, channels: Option<&[usize]>| {
+        let input = std::io::Cursor::new(stream.build());
+        let mut segment =
+            AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+                .unwrap();
+        segment.info.encoder_delay = Some(3);
+        segment.info.encoder_padding = Some(2);
+        let samples = match channels {
+            Some(channels) => segment.channel_samples::<i32>(channels).unwrap(),
+            None => segment.samples::<i32>().unwrap(),
+        };
+        samples.map(|s| s.unwrap()).collect::<Vec<i32>>()
+    };
+    let interleave = |range: std::ops::Range<usize>| {
+        range
+            .flat_map(|i| vec![left[i], right[i]])
+            .collect::<Vec<i32>>()
+    };
+
+    let options = DecodeOptions::default();
+    assert_eq!(decode(&stream, options, None), interleave(3..30));
+    assert_eq!(decode(&stream, options, Some(&[1])), right[3..30].to_vec());
+
+    let untrimmed = DecodeOptions {
+        trim_encoder_padding: false,
+        ..Default::default()
+    };
+    assert_eq!(decode(&stream, untrimmed, None), interleave(0..32));
+
+    // planar reads are trimmed the same, in parts or at once
+    let input = std::io::Cursor::new(stream.build());
+    let mut segment = AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap();
+    segment.info.encoder_delay = Some(3);
+    segment.info.encoder_padding = Some(2);
+    let mut samples = segment.samples::<i32>().unwrap();
+    let (mut l, mut r) = (vec![0; 20], vec![0; 20]);
+    assert_eq!(samples.read_planar(&mut [&mut l, &mut r]).unwrap(), 20);
+    assert_eq!((&l[..], &r[..]), (&left[3..23], &right[3..23]));
+    assert_eq!(samples.read_planar(&mut [&mut l, &mut r]).unwrap(), 7);
+    assert_eq!((&l[..7], &r[..7]), (&left[23..30], &right[23..30]));
+    assert_eq!(samples.read_planar(&mut [&mut l, &mut r]).unwrap(), 0);
+
+    // without the length only the delay is dropped
+    stream.total_samples = 0;
+    assert_eq!(decode(&stream, options, None), interleave(3..32));
+}
+
+#[test]
+#[cfg(not(feature = "mp3"))]
+fn test_disabled_format_is_unsupported() {
+    let input: &[u8] = b"ID3";
+    match AudioSegment::from_reader(input, codecs::FormatFlag::MP3) {
+        Err(errors::Error::Unsupported(msg)) => assert!(msg.contains("`mp3` feature")),
+        _ => panic!("expected MP3 to be unsupported"),
+    }
+}
+
+#[test]
+fn test_parts_are_shareable() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>() {}
+    assert_send_sync::<AudioInfo>();
+    assert_send_sync::<StreamDescription>();
+    assert_send::<AudioSegment>();
+    assert_send::<Decoder>();
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_into_parts() {
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![3; 16]]);
+    let input = std::io::Cursor::new(stream.build());
+    let segment = AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap();
+
+    let (description, mut decoder) = segment.into_parts();
+    let shared = description.clone();
+    let handle = std::thread::spawn(move || shared.info().sample_rate);
+    let samples: Vec<i16> = decoder.samples().unwrap().map(|s| s.unwrap()).collect();
+    drop(decoder);
+
+    assert_eq!(handle.join().unwrap(), 44_100);
+    assert_eq!(samples, vec![3; 16]);
+    assert_eq!(description.format(), codecs::FormatFlag::FLAC);
+    assert_eq!(description.header_len(), 42);
+    assert_eq!(description.info().total_samples, 16);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_into_inner() {
+    use std::io::Read;
+
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![3; 16]]);
+    let data = stream.build();
+    let segment =
+        AudioSegment::from_reader(std::io::Cursor::new(data.clone()), codecs::FormatFlag::FLAC)
+            .unwrap();
+    let header_len = segment.header_len() as usize;
+
+    // the buffered bytes and the source hold the rest of the input
+    let (mut rest, mut source) = segment.into_inner().into_parts();
+    source.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &data[header_len..]);
+}
+
+#[cfg(all(test, feature = "wav"))]
+fn looping_wav(smpl: Option<u32>) -> Vec<u8> {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let data: Vec<u8> = (0..40i16).flat_map(|s| (s * 100).to_le_bytes()).collect();
+    let mut chunks = vec![
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 44_100)),
+        riff_chunk(b"data", &data),
+    ];
+    if let Some(start) = smpl {
+        let mut payload = vec![0; 28];
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        for value in &[7u32, 0, start, 19, 0, 0] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        chunks.push(riff_chunk(b"smpl", &payload));
+    }
+    wav_file(&chunks)
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_looped_wav() {
+    let input = std::io::Cursor::new(looping_wav(None));
+    let mut segment = AudioSegment::from_seekable_reader(input, codecs::FormatFlag::WAV).unwrap();
+    let looped: Vec<i16> = segment
+        .looped(Some(2))
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    let once: Vec<i16> = (0..40).map(|s| s * 100).collect();
+    assert_eq!(looped, [&once[..], &once[..]].concat());
+
+    let input = std::io::Cursor::new(looping_wav(None));
+    let mut segment = AudioSegment::from_seekable_reader(input, codecs::FormatFlag::WAV).unwrap();
+    let forever = segment.looped::<i16>(None).unwrap();
+    assert_eq!(forever.take(1000).count(), 1000);
+
+    let input = std::io::Cursor::new(looping_wav(None));
+    let mut segment = AudioSegment::from_reader(input, codecs::FormatFlag::WAV).unwrap();
+    assert!(segment.looped::<i16>(Some(2)).is_err());
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_looped_wav_from_loop_start() {
+    let input = std::io::Cursor::new(looping_wav(Some(5)));
+    let mut segment = AudioSegment::from_seekable_reader(input, codecs::FormatFlag::WAV).unwrap();
+    let looped: Vec<i16> = segment
+        .looped(Some(3))
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+
+    let once: Vec<i16> = (0..40).map(|s| s * 100).collect();
+    // the loop starts at stereo frame 5, i.e. sample 10
+    let tail = &once[10..];
+    assert_eq!(looped, [&once[..], tail, tail].concat());
+    assert_eq!(segment.sample_loops()[0].cue_id, 7);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_looped_flac() {
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    let first: Vec<i32> = (0..16).collect();
+    let second: Vec<i32> = (0..16).map(|s| -s).collect();
+    let once = [first.clone(), second.clone()].concat();
+    stream.frame(&[first]).frame(&[second]);
+    let data = stream.build();
+    let input = std::io::Cursor::new(data.clone());
+    let mut segment = AudioSegment::from_seekable_reader(input, codecs::FormatFlag::FLAC).unwrap();
+
+    let looped: Vec<i32> = segment
+        .looped(Some(2))
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(looped, [&once[..], &once[..]].concat());
+
+    // a planar read continues in the next pass
+    let input = std::io::Cursor::new(data);
+    let mut segment = AudioSegment::from_seekable_reader(input, codecs::FormatFlag::FLAC).unwrap();
+    let mut samples = segment.looped::<i32>(Some(2)).unwrap();
+    let mut out = vec![0; 40];
+    assert_eq!(samples.read_planar(&mut [&mut out]).unwrap(), 40);
+    assert_eq!(out, [&once[..], &once[..8]].concat());
+    assert_eq!(samples.read_planar(&mut [&mut out]).unwrap(), 24);
+    assert_eq!(&out[..24], &once[8..]);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_read_planar_of_selected_channels() {
+    // each sample holds its frame and channel
+    let mut stream = crate::testing::FlacStream::new(8_000, 3, 16);
+    stream.frame(&[
+        (0..6).map(|frame| 10 * frame).collect(),
+        (0..6).map(|frame| 10 * frame + 1).collect(),
+        (0..6).map(|frame| 10 * frame + 2).collect(),
+    ]);
+    let input = std::io::Cursor::new(stream.build());
+    let mut segment = AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap();
+    let mut samples = segment.channel_samples::<i16>(&[0, 1]).unwrap();
+    assert_eq!(samples.next().unwrap().unwrap(), 0);
+    assert_eq!(samples.next().unwrap().unwrap(), 1);
+
+    // the dropped channel of the frame returned last is skipped first
+    let (mut first, mut second) = (vec![0; 4], vec![0; 4]);
+    assert_eq!(
+        samples.read_planar(&mut [&mut first, &mut second]).unwrap(),
+        4
+    );
+    assert_eq!(
+        (first, second),
+        (vec![10, 20, 30, 40], vec![11, 21, 31, 41])
+    );
+    let mut one = vec![0; 4];
+    assert!(samples.read_planar(&mut [&mut one]).is_err());
+    let (mut first, mut second) = (vec![0; 4], vec![0; 4]);
+    assert_eq!(
+        samples.read_planar(&mut [&mut first, &mut second]).unwrap(),
+        1
+    );
+    assert_eq!((first[0], second[0]), (50, 51));
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_write_pcm() {
+    use crate::testing::FlacStream;
+
+    let left: Vec<i32> = (0..16).map(|i| i * 300_000 - 4_000_000).collect();
+    let right: Vec<i32> = (0..16).map(|i| 8_388_607 - i * 77).collect();
+    let mut flac = FlacStream::new(48_000, 2, 24);
+    flac.frame(&[left.clone(), right.clone()]);
+    let write = |bits, dither| {
+        let mut segment =
+            AudioSegment::from_reader(io::Cursor::new(flac.build()), codecs::FormatFlag::FLAC)
+                .unwrap();
+        let mut out = Vec::new();
+        assert_eq!(segment.write_pcm(&mut out, bits, dither).unwrap(), 32);
+        out
+    };
+
+    let expected: Vec<u8> = (0..16)
+        .flat_map(|i| [left[i], right[i]])
+        .flat_map(|s| s.to_le_bytes()[..3].to_vec())
+        .collect();
+    assert_eq!(write(24, Dither::Tpdf), expected);
+
+    let expected: Vec<u8> = (0..16)
+        .flat_map(|i| [left[i], right[i]])
+        .flat_map(|s| (((s + 128) >> 8).min(32_767) as i16).to_le_bytes())
+        .collect();
+    assert_eq!(write(16, Dither::None), expected);
+    let dithered = write(16, Dither::Tpdf);
+    assert_eq!(dithered.len(), expected.len());
+    assert_eq!(dithered, write(16, Dither::Tpdf));
+
+    let mut segment =
+        AudioSegment::from_reader(io::Cursor::new(flac.build()), codecs::FormatFlag::FLAC).unwrap();
+    assert!(segment
+        .write_pcm(&mut Vec::new(), 12, Dither::None)
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_write_wav_g711() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    // a mono sweep over the whole range, of an odd number of samples
+    let samples: Vec<i16> = (0..1001).map(|i| (i * 65 - 32_500) as i16).collect();
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"data", &data),
+    ]);
+
+    // the quantization errors documented in the g711 module
+    let alaw_bound: fn(i32) -> i32 = |x| x.abs() / 32 + 16;
+    let ulaw_bound: fn(i32) -> i32 = |x| (x.abs() + 132) / 32;
+    for (codec, format_tag, bound) in [
+        (codecs::CodecType::CODEC_TYPE_PCM_ALAW, 6u16, alaw_bound),
+        (codecs::CodecType::CODEC_TYPE_PCM_MULAW, 7u16, ulaw_bound),
+    ]
+    .iter()
+    {
+        let mut segment =
+            AudioSegment::from_reader(io::Cursor::new(file.clone()), codecs::FormatFlag::WAV)
+                .unwrap();
+        let mut out = io::Cursor::new(Vec::new());
+        assert_eq!(
+            segment.write_wav(&mut out, *codec, Dither::None).unwrap(),
+            1001
+        );
+        let out = out.into_inner();
+        assert_eq!(u16::from_le_bytes([out[20], out[21]]), *format_tag);
+        assert_eq!(&out[38..42], b"fact");
+        assert_eq!(
+            u32::from_le_bytes([out[46], out[47], out[48], out[49]]),
+            1001
+        );
+        // RIFF header, fmt, fact and data chunk with the pad byte
+        assert_eq!(out.len(), 12 + 26 + 12 + 8 + 1002);
+
+        let mut segment =
+            AudioSegment::from_reader(io::Cursor::new(out), codecs::FormatFlag::WAV).unwrap();
+        assert_eq!(segment.info().codec_type, *codec);
+        assert_eq!(segment.info().total_samples, 1001);
+        let decoded: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded.len(), samples.len());
+        for (x, y) in samples.iter().zip(&decoded) {
+            let (x, y) = (*x as i32, *y as i32);
+            assert!((x - y).abs() <= bound(x), "{} decoded as {}", x, y);
+        }
+    }
+
+    // the G.711 samples match the encoder, whatever the source sample type
+    let floats: Vec<u8> = samples
+        .iter()
+        .flat_map(|s| (*s as f32 / 32_768.0).to_le_bytes())
+        .collect();
+    let mut fmt = pcm16_fmt(1, 8_000);
+    fmt[0] = 3;
+    fmt[8..12].copy_from_slice(&32_000u32.to_le_bytes());
+    fmt[12] = 4;
+    fmt[14] = 32;
+    let file = wav_file(&[riff_chunk(b"fmt ", &fmt), riff_chunk(b"data", &floats)]);
+    let mut segment =
+        AudioSegment::from_reader(io::Cursor::new(file), codecs::FormatFlag::WAV).unwrap();
+    let mut out = Vec::new();
+    let codec = codecs::CodecType::CODEC_TYPE_PCM_MULAW;
+    assert_eq!(
+        segment
+            .write_encoded(&mut out, codec, Dither::None)
+            .unwrap(),
+        1001
+    );
+    let expected: Vec<u8> = samples.iter().map(|s| g711::encode_ulaw(*s)).collect();
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_recommended_sample_type() {
+    use codecs::CodecType;
+    use format::SampleType;
+
+    let info = |codec_type, bits_per_sample| AudioInfo {
+        codec_type,
+        sample_rate: 44_100,
+        total_samples: 0,
+        bits_per_sample,
+        container_bits: bits_per_sample,
+        sample_format: codecs::SampleFormat::of_codec(codec_type, bits_per_sample),
+        channels: Channels::FRONT_LEFT,
+        channel_layout: ChannelLayout::Mono,
+        flac_stream_info: None,
+        encoder_delay: None,
+        encoder_padding: None,
+    };
+    let cases = [
+        (CodecType::CODEC_TYPE_PCM_U8, 8, SampleType::U8),
+        (CodecType::CODEC_TYPE_PCM_U8_PLANAR, 8, SampleType::U8),
+        (CodecType::CODEC_TYPE_PCM_S8, 8, SampleType::I16),
+        (CodecType::CODEC_TYPE_PCM_S16LE, 16, SampleType::I16),
+        (CodecType::CODEC_TYPE_PCM_S16BE, 16, SampleType::I16),
+        (CodecType::CODEC_TYPE_PCM_U16LE, 16, SampleType::I16),
+        (CodecType::CODEC_TYPE_PCM_S24LE, 20, SampleType::I32),
+        (CodecType::CODEC_TYPE_PCM_S24LE, 24, SampleType::I32),
+        (CodecType::CODEC_TYPE_PCM_U24BE, 24, SampleType::I32),
+        (CodecType::CODEC_TYPE_PCM_S32LE, 32, SampleType::I32),
+        (CodecType::CODEC_TYPE_PCM_ALAW, 16, SampleType::I16),
+        (CodecType::CODEC_TYPE_PCM_MULAW, 16, SampleType::I16),
+        (CodecType::CODEC_TYPE_PCM_F32LE, 32, SampleType::F32),
+        (CodecType::CODEC_TYPE_PCM_F32BE_PLANAR, 32, SampleType::F32),
+        (CodecType::CODEC_TYPE_PCM_F64LE, 64, SampleType::F64),
+        (CodecType::CODEC_TYPE_PCM_F64BE, 64, SampleType::F64),
+        (CodecType::CODEC_TYPE_FLAC, 4, SampleType::I16),
+        (CodecType::CODEC_TYPE_FLAC, 8, SampleType::I16),
+        (CodecType::CODEC_TYPE_FLAC, 12, SampleType::I16),
+        (CodecType::CODEC_TYPE_FLAC, 16, SampleType::I16),
+        (CodecType::CODEC_TYPE_FLAC, 17, SampleType::I32),
+        (CodecType::CODEC_TYPE_FLAC, 24, SampleType::I32),
+        (CodecType::CODEC_TYPE_FLAC, 32, SampleType::I32),
+        // lossy codecs decode to floats and have no bit depth
+        (CodecType::CODEC_TYPE_MP3, 0, SampleType::F32),
+        (CodecType::CODEC_TYPE_AAC, 0, SampleType::F32),
+        (CodecType::CODEC_TYPE_VORBIS, 0, SampleType::F32),
+        // integers of an unknown width
+        (CodecType::CODEC_TYPE_NULL, 0, SampleType::I32),
+    ];
+    for &(codec, bits, expected) in cases.iter() {
+        assert_eq!(
+            info(codec, bits).recommended_sample_type(),
+            expected,
+            "{} at {} bits",
+            codec,
+            bits
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_samples_auto() {
+    use crate::testing::FlacStream;
+    use format::TypedSamples;
+
+    // every bit depth decodes to its recommended type
+    for &bits in [8u32, 12, 16, 20, 24].iter() {
+        let max = (1 << (bits - 1)) - 1;
+        let samples = vec![-max - 1, -1, 0, 1, max];
+        let mut flac = FlacStream::new(44_100, 1, bits);
+        flac.frame(std::slice::from_ref(&samples));
+        let mut segment =
+            AudioSegment::from_reader(io::Cursor::new(flac.build()), codecs::FormatFlag::FLAC)
+                .unwrap();
+        let decoded: Vec<i32> = match segment.samples_auto().unwrap() {
+            TypedSamples::I16(samples) => samples.map(|s| s.unwrap() as i32).collect(),
+            TypedSamples::I32(samples) => {
+                assert!(bits > 16, "{} bits decoded to i32", bits);
+                samples.map(|s| s.unwrap()).collect()
+            }
+            _ => panic!("{} bit integers decoded to another type", bits),
+        };
+        assert_eq!(decoded, samples, "{} bits", bits);
+    }
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_into_samples_on_a_thread() {
+    use crate::testing::FlacStream;
+
+    fn assert_send<T: Send>() {}
+    assert_send::<OwnedSamples<i32>>();
+
+    let mut flac = FlacStream::new(44_100, 2, 16);
+    for f in 0..4 {
+        let left: Vec<i32> = (0..64).map(|i| f * 64 + i).collect();
+        let right: Vec<i32> = left.iter().map(|s| -s).collect();
+        flac.frame(&[left, right]);
+    }
+    let data = flac.build();
+    let expected: Vec<i32> =
+        AudioSegment::from_reader(io::Cursor::new(data.clone()), codecs::FormatFlag::FLAC)
+            .unwrap()
+            .samples()
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect();
+
+    let options = DecodeOptions {
+        frame_stats: true,
+        ..Default::default()
+    };
+    let segment = AudioSegment::from_reader_with_options(
+        io::Cursor::new(data.clone()),
+        codecs::FormatFlag::FLAC,
+        options,
+    )
+    .unwrap();
+    let samples = segment.into_samples::<i32>().unwrap();
+    assert_eq!(samples.info().channels.count(), 2);
+    let worker = std::thread::spawn(move || {
+        let mut samples = samples;
+        let decoded: Vec<i32> = samples.by_ref().map(|s| s.unwrap()).collect();
+        (decoded, samples.into_segment())
+    });
+    let (decoded, segment) = worker.join().unwrap();
+    assert_eq!(decoded, expected);
+    assert_eq!(segment.frame_stats().len(), 4);
+    // the segment gave out its samples already
+    assert!(segment.into_samples::<i32>().is_err());
+
+    // dropped part way through
+    let segment =
+        AudioSegment::from_reader(io::Cursor::new(data), codecs::FormatFlag::FLAC).unwrap();
+    let mut samples = segment.into_samples::<i16>().unwrap();
+    assert_eq!(Iterator::next(&mut samples).unwrap().unwrap(), 0);
+    assert_eq!(samples.samples_left(), Some(511));
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn test_float_samples_agree_across_formats() {
+    use crate::testing::{pcm_fmt, riff_chunk, wav_file, FlacStream};
+
+    let fmt = |format_tag: u16, bits: u16| {
+        let mut fmt = pcm_fmt(2, 44_100, bits);
+        fmt[..2].copy_from_slice(&format_tag.to_le_bytes());
+        fmt
+    };
+    let wav = |fmt: Vec<u8>, data: Vec<u8>| {
+        wav_file(&[riff_chunk(b"fmt ", &fmt), riff_chunk(b"data", &data)])
+    };
+    fn decode<S: Sample>(file: &[u8], format: codecs::FormatFlag) -> Vec<S> {
+        let mut segment =
+            AudioSegment::from_reader(io::Cursor::new(file.to_vec()), format).unwrap();
+        segment.samples().unwrap().map(|s| s.unwrap()).collect()
+    }
+
+    // the same 24 bit signal stored as integers of WAV and FLAC and as floats
+    let left: Vec<i32> = (0..1000)
+        .map(|i| i * 8_377 % (1 << 24) - (1 << 23))
+        .collect();
+    let right: Vec<i32> = left.iter().map(|s| -s - 1).collect();
+    let interleaved: Vec<i32> = left
+        .iter()
+        .zip(&right)
+        .flat_map(|(&l, &r)| [l, r])
+        .collect();
+    let s24 = interleaved
+        .iter()
+        .flat_map(|s| s.to_le_bytes()[..3].to_vec())
+        .collect();
+    let f32_data = interleaved
+        .iter()
+        .flat_map(|&s| (s as f32 / 8_388_608.0).to_le_bytes())
+        .collect();
+    let s24_in_32 = interleaved
+        .iter()
+        .flat_map(|s| (s << 8).to_le_bytes())
+        .collect();
+    // 24 valid bits of 32 bit containers, in the channel mask of stereo
+    let mut extensible = fmt(0xfffe, 32);
+    extensible.extend_from_slice(&[22, 0, 24, 0, 3, 0, 0, 0]);
+    extensible.extend_from_slice(&crate::wav::chunks::KSDATAFORMAT_SUBTYPE_PCM);
+    let mut flac = FlacStream::new(44_100, 2, 24);
+    flac.frame(&[left.clone(), right.clone()]);
+
+    let expected: Vec<f64> = interleaved
+        .iter()
+        .map(|&s| s as f64 / 8_388_608.0)
+        .collect();
+    for (file, format) in [
+        (wav(fmt(1, 24), s24), codecs::FormatFlag::WAV),
+        (wav(extensible, s24_in_32), codecs::FormatFlag::WAV),
+        (wav(fmt(3, 32), f32_data), codecs::FormatFlag::WAV),
+        (flac.build(), codecs::FormatFlag::FLAC),
+    ] {
+        let f32s = decode::<f32>(&file, format);
+        assert!(
+            f32s.iter().map(|&s| s as f64).eq(expected.iter().copied()),
+            "{:?}",
+            format
+        );
+        assert_eq!(decode::<f64>(&file, format), expected, "{:?}", format);
+    }
+
+    // unsigned 8 bit samples are offset by 128, like 16 bit samples shifted down
+    let u8_data: Vec<u8> = (0..=255).collect();
+    let s16_data = u8_data
+        .iter()
+        .flat_map(|&s| ((s as i16 - 128) << 8).to_le_bytes())
+        .collect();
+    let (u8_file, s16_file) = (wav(fmt(1, 8), u8_data), wav(fmt(1, 16), s16_data));
+    let u8_f32s = decode::<f32>(&u8_file, codecs::FormatFlag::WAV);
+    assert_eq!(
+        (u8_f32s[0], u8_f32s[128], u8_f32s[255]),
+        (-1.0, 0.0, 127.0 / 128.0)
+    );
+    assert_eq!(u8_f32s, decode::<f32>(&s16_file, codecs::FormatFlag::WAV));
+    assert_eq!(
+        decode::<f64>(&u8_file, codecs::FormatFlag::WAV),
+        decode::<f64>(&s16_file, codecs::FormatFlag::WAV)
+    );
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_read_at_offset() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let mut data = vec![0xff; 12_345];
+    data.extend(wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"data", &[1, 0, 2, 0, 3, 0]),
+    ]));
+    let mut segment = AudioSegment::read_at_offset(
+        read_only_stream(io::Cursor::new(data)),
+        12_345,
+        codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    assert_eq!(segment.info().sample_rate, 8_000);
+    assert_eq!(segment.header_len(), 12_345 + 44);
+    let samples: Vec<i16> = segment.samples().unwrap().map(Result::unwrap).collect();
+    assert_eq!(samples, [1, 2, 3]);
+
+    let data = vec![0; 100];
+    assert!(AudioSegment::read_at_offset(
+        read_only_stream(io::Cursor::new(data)),
+        200,
+        codecs::FormatFlag::WAV
+    )
+    .is_err());
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn test_write_flac_channels_and_u8() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let write = |file: Vec<u8>| {
+        let mut segment =
+            AudioSegment::from_reader(io::Cursor::new(file), codecs::FormatFlag::WAV).unwrap();
+        let mut out = io::Cursor::new(Vec::new());
+        segment
+            .write_flac(&mut out, Dither::None)
+            .map(|_| out.into_inner())
+    };
+    for &(channels, ok) in &[(8usize, true), (9, false)] {
+        let speakers = STREAM_CHANNEL_ORDER[..channels]
+            .iter()
+            .fold(Channels::empty(), |all, &speaker| all | speaker);
+        let mut writer = crate::WavWriter::new(
+            io::Cursor::new(Vec::new()),
+            codecs::CodecType::CODEC_TYPE_PCM_S16LE,
+            speakers,
+            44_100,
+            WavOptions::default(),
+        )
+        .unwrap();
+        writer.write_samples(&vec![7i32; channels * 10]).unwrap();
+        let file = writer.finalize().unwrap().into_inner();
+        assert_eq!(write(file).is_ok(), ok, "{} channels", channels);
+    }
+
+    // unsigned 8 bit samples become signed ones
+    let mut fmt = pcm16_fmt(1, 8_000);
+    fmt[8..12].copy_from_slice(&8_000u32.to_le_bytes());
+    fmt[12..14].copy_from_slice(&1u16.to_le_bytes());
+    fmt[14..16].copy_from_slice(&8u16.to_le_bytes());
+    let data: Vec<u8> = (0..=255).collect();
+    let file = wav_file(&[riff_chunk(b"fmt ", &fmt), riff_chunk(b"data", &data)]);
+    let flac = write(file).unwrap();
+    let mut segment =
+        AudioSegment::from_reader(io::Cursor::new(flac), codecs::FormatFlag::FLAC).unwrap();
+    assert_eq!(segment.info().bits_per_sample, 8);
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, (-128..128).collect::<Vec<i32>>());
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_write_flac_drops_metadata() {
+    use crate::testing::FlacStream;
+
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream
+        .metadata_block(6, vec![0; 64])
+        .metadata_block(1, vec![0; 16])
+        .frame(&[vec![7; 16]]);
+    let mut segment =
+        AudioSegment::from_reader(io::Cursor::new(stream.build()), codecs::FormatFlag::FLAC)
+            .unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    assert_eq!(segment.write_flac(&mut out, Dither::None).unwrap(), 16);
+    let flac = out.into_inner();
+
+    // the marker and the last metadata block, STREAMINFO, then the frames
+    assert_eq!(&flac[..5], b"fLaC\x80");
+    assert_eq!(&flac[42..44], &[0xff, 0xf8]);
+    let mut segment =
+        AudioSegment::from_reader(io::Cursor::new(flac), codecs::FormatFlag::FLAC).unwrap();
+    assert!(segment.skipped_metadata().is_empty());
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![7; 16]);
+}
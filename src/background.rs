@@ -0,0 +1,422 @@
+//! The `background` module decodes a segment on its own thread.
+//!
+//! It is enabled by the `threads` feature. The decoding thread runs ahead of
+//! the consumer by a bounded number of samples, e.g. to keep a real-time
+//! playback callback from ever waiting for the decoder.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle, Thread};
+
+use crate::audio::AudioSegment;
+use crate::io::{AudioSamplesIterator, Sample};
+use crate::{errors, Result};
+
+/// Number of chunks the samples ahead of the consumer are split into
+const CHUNKS: usize = 4;
+
+/// A message of the decoding thread, tagged with the generation it was decoded in
+enum Message<S> {
+    Samples(u64, Vec<S>),
+    Error(u64, errors::Error),
+    /// the stream ended, a seek may restart it
+    End(u64),
+}
+
+/// State shared by the handle, the receiver and the decoding thread
+struct Shared {
+    /// incremented by every seek and by stop, messages of older generations
+    /// are dropped by the receiver
+    generation: AtomicU64,
+    /// set when the thread has to exit, by stop or when the receiver is dropped
+    stopped: AtomicBool,
+    /// inter-channel sample to continue at, changed with `generation`
+    seek: Mutex<Option<u64>>,
+}
+
+/// Controls the decoding thread of `AudioSegment::into_background_decoder`
+///
+/// Dropping the handle stops the thread, like `stop`.
+pub struct DecodeHandle {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DecodeHandle {
+    /// Continues decoding at the inter-channel sample `frame`
+    ///
+    /// All samples decoded before are dropped, the receiver returns the ones
+    /// from `frame` on. This works after the end of the stream as well. A
+    /// failed seek, e.g. on an input which can not seek, is returned by the
+    /// receiver as an error.
+    pub fn seek(&self, frame: u64) {
+        let mut seek = self.shared.seek.lock().unwrap();
+        *seek = Some(frame);
+        self.shared.generation.fetch_add(1, Ordering::AcqRel);
+        drop(seek);
+        self.wake();
+    }
+
+    /// Stops decoding and waits for the thread to exit
+    ///
+    /// The receiver drops the samples it has not returned yet and ends.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn wake(&self) {
+        if let Some(ref thread) = self.thread {
+            thread.thread().unpark();
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.shared.stopped.store(true, Ordering::Release);
+        self.shared.generation.fetch_add(1, Ordering::AcqRel);
+        self.wake();
+        if let Some(thread) = self.thread.take() {
+            // a panic of the decoder was already sent to the receiver
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DecodeHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Returns the samples decoded by the thread of a `DecodeHandle`
+///
+/// The samples are channel interleaved, as for `AudioSegment::samples`.
+pub struct SampleReceiver<S: Sample> {
+    receiver: Receiver<Message<S>>,
+    shared: Arc<Shared>,
+    decoder: Thread,
+    /// the chunk samples are returned from, with its generation
+    chunk: Vec<S>,
+    generation: u64,
+    position: usize,
+    /// generation in which the stream ended or failed, nothing follows until a seek
+    ended: Option<u64>,
+    /// an error which followed samples already returned by `read_samples_into`
+    pending_error: Option<errors::Error>,
+}
+
+impl<S: Sample> SampleReceiver<S> {
+    /// Fills `out` with the next samples and returns how many were written
+    ///
+    /// Blocks until `out` is full or the stream ended, so less than `out.len()`
+    /// samples are only written at the end of the stream, and 0 after it.
+    pub fn read_samples_into(&mut self, out: &mut [S]) -> Result<usize> {
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+        let mut written = 0;
+        while written < out.len() {
+            match self.next_sample() {
+                Some(Ok(sample)) => {
+                    out[written] = sample;
+                    written += 1;
+                }
+                Some(Err(error)) if written == 0 => return Err(error),
+                Some(Err(error)) => {
+                    self.pending_error = Some(error);
+                    break;
+                }
+                None => break,
+            }
+        }
+        Ok(written)
+    }
+
+    fn next_sample(&mut self) -> Option<Result<S>> {
+        loop {
+            let generation = self.shared.generation.load(Ordering::Acquire);
+            if self.generation == generation && self.position < self.chunk.len() {
+                self.position += 1;
+                return Some(Ok(self.chunk[self.position - 1]));
+            }
+            if self.ended == Some(generation) {
+                return None;
+            }
+
+            let message = self.receiver.recv().ok()?;
+            // a chunk was taken out of the channel, the thread may continue
+            self.decoder.unpark();
+            match message {
+                Message::Samples(g, chunk) if g == generation => {
+                    self.chunk = chunk;
+                    self.generation = g;
+                    self.position = 0;
+                }
+                Message::Error(g, error) if g == generation => {
+                    self.ended = Some(g);
+                    return Some(Err(error));
+                }
+                Message::End(g) if g == generation => {
+                    self.ended = Some(g);
+                    return None;
+                }
+                // decoded before the last seek
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<S: Sample> AudioSamplesIterator<S> for SampleReceiver<S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        if let Some(error) = self.pending_error.take() {
+            return Some(Err(error));
+        }
+        self.next_sample()
+    }
+}
+
+impl<S: Sample> Iterator for SampleReceiver<S> {
+    type Item = Result<S>;
+
+    fn next(&mut self) -> Option<Result<S>> {
+        AudioSamplesIterator::next(self)
+    }
+}
+
+impl<S: Sample> Drop for SampleReceiver<S> {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Release);
+        self.decoder.unpark();
+    }
+}
+
+/// Spawns the decoding thread, see `AudioSegment::into_background_decoder`
+pub(crate) fn spawn<S: Sample + 'static>(
+    segment: AudioSegment,
+    buffer_frames: usize,
+) -> Result<(DecodeHandle, SampleReceiver<S>)> {
+    let channels = segment.number_channels().max(1);
+    let chunk_len = buffer_frames.div_ceil(CHUNKS).max(1) * channels;
+    let (sender, receiver) = mpsc::sync_channel(CHUNKS - 1);
+    let shared = Arc::new(Shared {
+        generation: AtomicU64::new(0),
+        stopped: AtomicBool::new(false),
+        seek: Mutex::new(None),
+    });
+
+    let thread_shared = Arc::clone(&shared);
+    let thread = thread::Builder::new()
+        .name("cauldron-decoder".to_string())
+        .spawn(move || decode(segment, chunk_len, &sender, &thread_shared))?;
+
+    let receiver = SampleReceiver {
+        receiver,
+        shared: Arc::clone(&shared),
+        decoder: thread.thread().clone(),
+        chunk: Vec::new(),
+        generation: 0,
+        position: 0,
+        ended: None,
+        pending_error: None,
+    };
+    let handle = DecodeHandle {
+        shared,
+        thread: Some(thread),
+    };
+    Ok((handle, receiver))
+}
+
+/// The decoding thread, runs until it is stopped or the receiver is dropped
+fn decode<S: Sample>(
+    mut segment: AudioSegment,
+    chunk_len: usize,
+    sender: &SyncSender<Message<S>>,
+    shared: &Shared,
+) {
+    let mut samples = match segment.samples::<S>() {
+        Ok(samples) => samples,
+        Err(error) => {
+            let _ = sender.send(Message::Error(0, error));
+            return;
+        }
+    };
+
+    let mut generation = 0;
+    // false after the end of the stream or an error, until the next seek
+    let mut decoding = true;
+    loop {
+        if let Some(frame) = shared.seek.lock().unwrap().take() {
+            generation = shared.generation.load(Ordering::Acquire);
+            decoding = true;
+            if let Err(error) = samples.seek_frame(frame) {
+                decoding = false;
+                if !send(sender, shared, Message::Error(generation, error)) {
+                    return;
+                }
+            }
+        }
+        if shared.stopped.load(Ordering::Acquire) {
+            return;
+        }
+        if !decoding {
+            thread::park();
+            continue;
+        }
+
+        let message = match panic::catch_unwind(AssertUnwindSafe(|| fill(&mut *samples, chunk_len)))
+        {
+            Ok(Ok(chunk)) if chunk.is_empty() => Message::End(generation),
+            Ok(Ok(chunk)) => Message::Samples(generation, chunk),
+            Ok(Err(error)) => Message::Error(generation, error),
+            Err(_) => {
+                let _ = send(
+                    sender,
+                    shared,
+                    Message::Error(
+                        generation,
+                        errors::Error::ParseError("decoder panicked on the file"),
+                    ),
+                );
+                return;
+            }
+        };
+        decoding = matches!(message, Message::Samples(..));
+        if !send(sender, shared, message) {
+            return;
+        }
+    }
+}
+
+/// Decodes up to `len` samples, less only at the end of the stream
+fn fill<S: Sample>(samples: &mut dyn AudioSamplesIterator<S>, len: usize) -> Result<Vec<S>> {
+    let mut chunk = Vec::with_capacity(len);
+    while chunk.len() < len {
+        match samples.next() {
+            Some(sample) => chunk.push(sample?),
+            None => break,
+        }
+    }
+    Ok(chunk)
+}
+
+/// Sends a message, waiting while the channel is full
+///
+/// Returns false if the thread has to exit.
+fn send<S>(sender: &SyncSender<Message<S>>, shared: &Shared, mut message: Message<S>) -> bool {
+    loop {
+        if shared.stopped.load(Ordering::Acquire) {
+            return false;
+        }
+        match sender.try_send(message) {
+            Ok(()) => return true,
+            Err(TrySendError::Full(m)) => {
+                message = m;
+                // woken by the receiver taking a chunk, or by a seek or stop
+                thread::park();
+                // a seek makes the message stale, drop it and decode again
+                if shared.seek.lock().unwrap().is_some() {
+                    return true;
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => return false,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "wav"))]
+fn wav_segment(frames: i16) -> AudioSegment {
+    use crate::codecs::FormatFlag;
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let data: Vec<u8> = (0..frames * 2).flat_map(|s| s.to_le_bytes()).collect();
+    let wav = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 44_100)),
+        riff_chunk(b"data", &data),
+    ]);
+    AudioSegment::from_seekable_reader(std::io::Cursor::new(wav), FormatFlag::WAV).unwrap()
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_background_decoder() {
+    // the buffer is much smaller than the stream, so the thread has to wait
+    let (handle, receiver) = wav_segment(1000)
+        .into_background_decoder::<i16>(16)
+        .unwrap();
+    let samples: Vec<i16> = receiver.map(|s| s.unwrap()).collect();
+    assert_eq!(samples, (0..2000).collect::<Vec<i16>>());
+    handle.stop();
+
+    let (_handle, mut receiver) = wav_segment(10).into_background_decoder::<i16>(4).unwrap();
+    let mut out = [0; 15];
+    assert_eq!(receiver.read_samples_into(&mut out).unwrap(), 15);
+    assert_eq!(out[14], 14);
+    assert_eq!(receiver.read_samples_into(&mut out).unwrap(), 5);
+    assert_eq!(receiver.read_samples_into(&mut out).unwrap(), 0);
+    assert!(Iterator::next(&mut receiver).is_none());
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_background_decoder_seek() {
+    let (handle, mut receiver) = wav_segment(1000)
+        .into_background_decoder::<i16>(64)
+        .unwrap();
+    let mut out = [0; 10];
+    receiver.read_samples_into(&mut out).unwrap();
+    assert_eq!(out[9], 9);
+
+    // buffered samples are dropped
+    handle.seek(500);
+    receiver.read_samples_into(&mut out).unwrap();
+    assert_eq!(out[0], 1000);
+
+    // the stream can be restarted after its end
+    assert_eq!(receiver.by_ref().count(), 990);
+    handle.seek(999);
+    let tail: Vec<i16> = receiver.by_ref().map(|s| s.unwrap()).collect();
+    assert_eq!(tail, vec![1998, 1999]);
+
+    handle.seek(2000);
+    assert!(Iterator::next(&mut receiver).unwrap().is_err());
+    assert!(Iterator::next(&mut receiver).is_none());
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_background_decoder_error() {
+    use crate::codecs::FormatFlag;
+
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![1; 16]]).frame(&[vec![2; 16]]);
+    let mut data = stream.build();
+    // break the CRC of the second frame
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    let segment = AudioSegment::from_reader(std::io::Cursor::new(data), FormatFlag::FLAC).unwrap();
+
+    // the error reaches the receiver after the samples of the first frame
+    let (_handle, mut receiver) = segment.into_background_decoder::<i32>(4).unwrap();
+    let mut out = [0; 32];
+    assert_eq!(receiver.read_samples_into(&mut out).unwrap(), 16);
+    assert!(receiver.read_samples_into(&mut out).is_err());
+    assert_eq!(receiver.read_samples_into(&mut out).unwrap(), 0);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_background_decoder_shutdown() {
+    // dropping the receiver lets the waiting thread exit, `stop` would hang otherwise
+    let (handle, mut receiver) = wav_segment(1000).into_background_decoder::<i16>(8).unwrap();
+    Iterator::next(&mut receiver).unwrap().unwrap();
+    drop(receiver);
+    handle.stop();
+
+    // dropping the handle ends the receiver
+    let (handle, mut receiver) = wav_segment(1000).into_background_decoder::<i16>(8).unwrap();
+    drop(handle);
+    assert!(receiver.by_ref().count() < 2000);
+    assert!(Iterator::next(&mut receiver).is_none());
+}
@@ -0,0 +1,195 @@
+//! The `batch` module reads the headers of many files in parallel.
+//!
+//! It is enabled by the `batch` feature and uses plain `std::thread`s.
+
+use std::fs::File;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::audio::{AudioInfo, AudioSegment};
+use crate::codecs::FormatFlag;
+use crate::errors;
+use crate::format;
+use crate::io::{AudioInputStream, SeekableSource};
+use crate::Result;
+
+/// What is known about a file after reading its header
+#[derive(Debug)]
+pub struct ProbeResult {
+    /// the format the file was recognized as
+    pub format: FormatFlag,
+
+    /// the audio info from the header
+    pub info: AudioInfo,
+
+    /// number of bytes of the header
+    pub header_len: u64,
+}
+
+/// Reads the header of a single file, no samples are decoded
+///
+/// The format is found like `AudioSegment::read` does. A WAV file in an
+/// unsupported encoding fails with `Error::UnsupportedWaveFormat`, which still
+/// holds the fields of its fmt chunk.
+pub fn probe(path: &Path) -> Result<ProbeResult> {
+    probe_with_buffer(path, &mut Vec::new())
+}
+
+/// Same as `probe`, but reads the file through the allocation of `buffer`
+///
+/// The buffer is handed back in `buffer` once the header is read, so probing
+/// many files with the same one allocates it only once. Its contents are
+/// ignored. If the header of the file can not be read, `buffer` is left empty.
+pub fn probe_with_buffer(path: &Path, buffer: &mut Vec<u8>) -> Result<ProbeResult> {
+    let filename = match path.to_str() {
+        Some(filename) => filename,
+        None => return errors::unsupported_error("path is not valid unicode"),
+    };
+    let first = format::find(AudioSegment::get_format_flag(filename)?)?;
+    let source = SeekableSource::new(File::open(path)?)?;
+    let input = AudioInputStream::with_buffer(Box::new(source), mem::take(buffer));
+    let segment = AudioSegment::probe_formats(input, first)?;
+    let format = segment.format();
+    let header_len = segment.header_len();
+    let (info, input) = segment.into_info_and_inner();
+    *buffer = input.recycle();
+    Ok(ProbeResult {
+        format,
+        info,
+        header_len,
+    })
+}
+
+/// Probes all `paths` with at most `parallelism` threads
+///
+/// Returns one result per path, in the order of `paths`. Every file is probed
+/// on its own, an error or even a panic of the decoder on one file is reported
+/// for that file only. A `parallelism` of 0 is treated as 1.
+pub fn probe_all<I, P>(paths: I, parallelism: usize) -> Vec<(PathBuf, Result<ProbeResult>)>
+where
+    I: IntoIterator<Item = P>,
+    P: Into<PathBuf>,
+{
+    let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+    let results: Vec<Mutex<Option<Result<ProbeResult>>>> =
+        paths.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+
+    let workers = parallelism.clamp(1, paths.len().max(1));
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                // every worker reads all of its files through the same buffer
+                let mut buffer = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let path = match paths.get(i) {
+                        Some(path) => path,
+                        None => break,
+                    };
+                    let probed = || probe_with_buffer(path, &mut buffer);
+                    let result = panic::catch_unwind(AssertUnwindSafe(probed))
+                        .unwrap_or_else(|_| errors::parse_error("decoder panicked on the file"));
+                    *results[i].lock().unwrap() = Some(result);
+                }
+            });
+        }
+    });
+
+    paths
+        .into_iter()
+        .zip(results)
+        .map(|(path, result)| {
+            let result = result.into_inner().unwrap();
+            (path, result.expect("every path is probed"))
+        })
+        .collect()
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn test_probe_all() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file, FlacStream};
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("cauldron-batch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let data = vec![0u8; 64];
+    let wav = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 8_000)),
+        riff_chunk(b"data", &data),
+    ]);
+    let mut flac = FlacStream::new(22_050, 1, 16);
+    flac.frame(&[vec![1; 16]]);
+
+    let mut paths = Vec::new();
+    for i in 0..12 {
+        let (name, bytes) = match i % 3 {
+            0 => (format!("{}.wav", i), wav.clone()),
+            1 => (format!("{}.flac", i), flac.build()),
+            _ => (format!("{}.wav", i), b"corrupt".to_vec()),
+        };
+        fs::write(dir.join(&name), bytes).unwrap();
+        paths.push(dir.join(name));
+    }
+    paths.push(dir.join("missing.flac"));
+
+    let results = probe_all(paths.clone(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(results.len(), paths.len());
+    for (i, (path, result)) in results.iter().enumerate() {
+        assert_eq!(path, &paths[i]);
+        match (i % 3, result) {
+            _ if i == 12 => assert!(matches!(result, Err(errors::Error::IoError(_)))),
+            (0, Ok(probe)) => {
+                assert_eq!(probe.format, FormatFlag::WAV);
+                assert_eq!(probe.info.sample_rate, 8_000);
+            }
+            (1, Ok(probe)) => {
+                assert_eq!(probe.format, FormatFlag::FLAC);
+                assert_eq!(probe.header_len, 42);
+            }
+            (2, Err(_)) => {}
+            _ => panic!("unexpected result for {:?}: {:?}", path, result),
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_probe_with_buffer() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let dir = std::env::temp_dir().join(format!("cauldron-scratch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.wav");
+    std::fs::write(
+        &path,
+        wav_file(&[
+            riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+            riff_chunk(b"data", &[0; 16]),
+        ]),
+    )
+    .unwrap();
+
+    let mut buffer = Vec::with_capacity(32 * 1024);
+    let ptr = buffer.as_ptr();
+    for _ in 0..3 {
+        let probe = probe_with_buffer(&path, &mut buffer).unwrap();
+        assert_eq!(probe.info.sample_rate, 8_000);
+        assert_eq!(probe.header_len, 44);
+        // the same allocation comes back every time
+        assert_eq!(buffer.as_ptr(), ptr);
+    }
+
+    let corrupt = dir.join("b.wav");
+    std::fs::write(&corrupt, b"corrupt").unwrap();
+    assert!(probe_with_buffer(&corrupt, &mut buffer).is_err());
+    assert!(buffer.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
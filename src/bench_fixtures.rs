@@ -0,0 +1,151 @@
+//! Generates the fixtures of the benchmarks.
+//!
+//! These cover what the recorded benchmark files do not, 24 bit samples at a
+//! high sample rate and more than two channels. The FLAC files are encoded by
+//! `testing::FlacStream` with LPC subframes, the WAV files are the FLAC files
+//! written by `AudioSegment::write_wav`. Being generated by the test suite
+//! keeps them out of the repository. They are written to the directory of
+//! `CAULDRON_BENCH_FIXTURES`, e.g. `benchmark/generated` where the benchmarks
+//! read them, and to a temporary directory which is removed again otherwise.
+#![cfg(all(feature = "flac", feature = "wav"))]
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::audio::AudioSegment;
+use crate::codecs::{CodecType, FormatFlag};
+use crate::dither::Dither;
+use crate::md5::Md5;
+use crate::testing::{FlacStream, FrameSpec};
+
+/// seconds of audio in each fixture
+const SECONDS: usize = 3;
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Returns `SECONDS` of a chord of two sines and some noise per channel
+///
+/// The sines differ among the channels, so that no two channels hold the same.
+fn signal(sample_rate: u32, channels: usize, bits_per_sample: u32) -> Vec<Vec<i32>> {
+    let full_scale = (1i64 << (bits_per_sample - 1)) as f64;
+    let mut seed = 0x2545_f491u32;
+    (0..channels)
+        .map(|channel| {
+            let low = 110.0 * (channel + 1) as f64;
+            let high = 1_000.0 + 370.0 * channel as f64;
+            (0..SECONDS * sample_rate as usize)
+                .map(|i| {
+                    let t = i as f64 / sample_rate as f64;
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    let noise = (seed >> 8) as f64 / (1u32 << 24) as f64 - 0.5;
+                    let tau = 2.0 * std::f64::consts::PI;
+                    let value =
+                        0.5 * (tau * low * t).sin() + 0.25 * (tau * high * t).sin() + 0.01 * noise;
+                    (value * full_scale) as i32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Encodes `channels` as FLAC of `bits_per_sample` bits in LPC subframes of
+/// `order`, with their MD5 signature
+fn encode_flac(
+    sample_rate: u32,
+    bits_per_sample: u32,
+    order: usize,
+    channels: &[Vec<i32>],
+) -> Vec<u8> {
+    let mut stream = FlacStream::new(sample_rate, channels.len() as u8, bits_per_sample);
+    stream.min_block_size = BLOCK_SIZE as u16;
+    let len = channels[0].len();
+    for (number, start) in (0..len).step_by(BLOCK_SIZE).enumerate() {
+        let end = (start + BLOCK_SIZE).min(len);
+        let block: Vec<Vec<i32>> = channels.iter().map(|c| c[start..end].to_vec()).collect();
+        stream.push_frame(&FrameSpec::lpc(
+            number as u64,
+            bits_per_sample,
+            order,
+            &block,
+        ));
+    }
+
+    let mut md5 = Md5::new();
+    let bytes = bits_per_sample as usize / 8;
+    for i in 0..len {
+        for channel in channels {
+            md5.update(&channel[i].to_le_bytes()[..bytes]);
+        }
+    }
+    stream.md5 = md5.finish();
+    stream.build()
+}
+
+/// Writes the FLAC stream `flac` as a WAV file in `codec`, and checks it decodes to the same
+fn write_wav(flac: &[u8], codec: CodecType) -> Vec<u8> {
+    let read =
+        |data: Vec<u8>, format| AudioSegment::from_seekable_reader(Cursor::new(data), format);
+    let mut segment = read(flac.to_vec(), FormatFlag::FLAC).unwrap();
+    let mut wav = Cursor::new(Vec::new());
+    segment.write_wav(&mut wav, codec, Dither::None).unwrap();
+    let wav = wav.into_inner();
+
+    let mut original = read(flac.to_vec(), FormatFlag::FLAC).unwrap();
+    let mut written = read(wav.clone(), FormatFlag::WAV).unwrap();
+    assert_eq!(written.info().channels, original.info().channels);
+    assert!(
+        crate::compare(&mut original, &mut written, 0.0)
+            .unwrap()
+            .nulls
+    );
+    wav
+}
+
+#[test]
+fn generate_bench_fixtures() {
+    let (dir, keep) = match std::env::var_os("CAULDRON_BENCH_FIXTURES") {
+        Some(dir) => (PathBuf::from(dir), true),
+        None => (
+            std::env::temp_dir().join(format!("cauldron-bench-{}", std::process::id())),
+            false,
+        ),
+    };
+    std::fs::create_dir_all(&dir).unwrap();
+    let save = |name: &str, data: &[u8]| std::fs::write(dir.join(name), data).unwrap();
+
+    let stereo_24 = encode_flac(96_000, 24, 8, &signal(96_000, 2, 24));
+    let mut segment =
+        AudioSegment::from_seekable_reader(Cursor::new(stereo_24.clone()), FormatFlag::FLAC)
+            .unwrap();
+    assert!(segment.verify().unwrap().is_ok());
+    save("stereo_24_96k.flac", &stereo_24);
+    save(
+        "stereo_24_96k.wav",
+        &write_wav(&stereo_24, CodecType::CODEC_TYPE_PCM_S24LE),
+    );
+
+    // 6 channels are 5.1 in FLAC, and keep their speakers in WAVEFORMATEXTENSIBLE
+    let surround = encode_flac(48_000, 16, 8, &signal(48_000, 6, 16));
+    save(
+        "surround_51_16.wav",
+        &write_wav(&surround, CodecType::CODEC_TYPE_PCM_S16LE),
+    );
+
+    let octo = encode_flac(48_000, 16, 8, &signal(48_000, 8, 16));
+    let mut segment =
+        AudioSegment::from_seekable_reader(Cursor::new(octo.clone()), FormatFlag::FLAC).unwrap();
+    assert!(segment.verify().unwrap().is_ok());
+    save("octo_16_48k.flac", &octo);
+
+    // the order of `flac -8 -e -l 32`, beyond the 12 of the FLAC subset
+    let high_order = encode_flac(44_100, 16, 32, &signal(44_100, 2, 16));
+    let mut segment =
+        AudioSegment::from_seekable_reader(Cursor::new(high_order.clone()), FormatFlag::FLAC)
+            .unwrap();
+    assert!(segment.verify().unwrap().is_ok());
+    save("stereo_16_44k_lpc32.flac", &high_order);
+
+    if !keep {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
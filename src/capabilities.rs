@@ -0,0 +1,230 @@
+//! What the linked build of the crate supports, e.g. to grey out formats a UI
+//! can not open.
+//!
+//! Everything is fixed when the crate is compiled, by its features. Formats
+//! added with `format::register` are not part of it.
+
+use crate::codecs::{CodecType, FormatFlag};
+use crate::format;
+
+/// How far a samples iterator of a format can `seek_frame`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Seeking {
+    /// not at all
+    None,
+    /// back to the first sample only
+    ToStart,
+    /// to any inter-channel sample once the stream is indexed with
+    /// `AudioSegment::build_seek_index`, back to the first sample before
+    Indexed,
+    /// to any inter-channel sample
+    Anywhere,
+}
+
+/// A built-in format which is compiled in
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FormatCapabilities {
+    pub format: FormatFlag,
+
+    /// file extensions of the format, in lower case
+    pub extensions: Vec<String>,
+
+    /// codecs decoded in files of the format
+    pub codecs: Vec<CodecType>,
+
+    /// seeking on seekable inputs
+    pub seeking: Seeking,
+}
+
+/// The formats and features of the linked build, see `capabilities`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Capabilities {
+    /// version of the crate
+    pub version: &'static str,
+
+    /// the formats which are compiled in, in the order they are probed
+    pub formats: Vec<FormatCapabilities>,
+
+    /// codecs `AudioSegment::write_wav` writes, none without the `wav` feature
+    pub wav_encoding: Vec<CodecType>,
+
+    /// `AudioSegment::write_flac`, with the `flac` feature
+    pub flac_encoding: bool,
+
+    /// `process::Pipeline::resample`, which needs no feature
+    pub resampling: bool,
+
+    /// the Ogg demuxer of the `ogg` feature
+    pub ogg_demuxer: bool,
+
+    /// `hound::write_with_hound`, with the `hound` feature
+    pub hound_writing: bool,
+
+    /// `cauldron::batch`, with the `batch` feature
+    pub batch: bool,
+
+    /// `cauldron::background`, with the `threads` feature
+    pub background_decoding: bool,
+
+    /// `serde::Serialize` of the stats and of these capabilities
+    pub serde: bool,
+}
+
+impl Capabilities {
+    /// Returns the capabilities of `format`, `None` if it is not compiled in
+    pub fn format(&self, format: FormatFlag) -> Option<&FormatCapabilities> {
+        self.formats.iter().find(|f| f.format == format)
+    }
+
+    /// Returns true if files of `format` in `codec` are decoded
+    pub fn decodes(&self, format: FormatFlag, codec: CodecType) -> bool {
+        self.format(format)
+            .is_some_and(|f| f.codecs.contains(&codec))
+    }
+}
+
+/// the PCM codecs of WAV files, which `Sample::read_pcm` reads
+const WAV_PCM: [CodecType; 8] = [
+    CodecType::CODEC_TYPE_PCM_U8,
+    CodecType::CODEC_TYPE_PCM_S16LE,
+    CodecType::CODEC_TYPE_PCM_S24LE,
+    CodecType::CODEC_TYPE_PCM_S32LE,
+    CodecType::CODEC_TYPE_PCM_F32LE,
+    CodecType::CODEC_TYPE_PCM_F64LE,
+    CodecType::CODEC_TYPE_PCM_ALAW,
+    CodecType::CODEC_TYPE_PCM_MULAW,
+];
+
+/// Returns the formats, codecs and features the crate was compiled with
+///
+/// ```
+/// use cauldron::codecs::{CodecType, FormatFlag};
+///
+/// let capabilities = cauldron::capabilities();
+/// if cfg!(feature = "flac") {
+///     assert!(capabilities.decodes(FormatFlag::FLAC, CodecType::CODEC_TYPE_FLAC));
+/// }
+/// ```
+pub fn capabilities() -> Capabilities {
+    let formats = format::builtin()
+        .iter()
+        .map(|format| {
+            let flag = format.flag();
+            let (codecs, seeking) = match flag {
+                FormatFlag::WAV => {
+                    let mut codecs = WAV_PCM.to_vec();
+                    // MPEG layer 3 in WAV is decoded by the MP3 decoder
+                    if cfg!(feature = "mp3") {
+                        codecs.push(CodecType::CODEC_TYPE_MP3);
+                    }
+                    (codecs, Seeking::Anywhere)
+                }
+                // without an index the frame holding a sample is not known
+                FormatFlag::FLAC => (vec![CodecType::CODEC_TYPE_FLAC], Seeking::Indexed),
+                // the frame holding a sample is found by reading the frame headers from the start
+                FormatFlag::MP3 => (vec![CodecType::CODEC_TYPE_MP3], Seeking::Anywhere),
+                _ => (Vec::new(), Seeking::None),
+            };
+            FormatCapabilities {
+                format: flag,
+                extensions: format.extensions().iter().map(|e| e.to_string()).collect(),
+                codecs,
+                seeking,
+            }
+        })
+        .collect();
+
+    let wav_encoding = if cfg!(feature = "wav") {
+        vec![
+            CodecType::CODEC_TYPE_PCM_U8,
+            CodecType::CODEC_TYPE_PCM_S16LE,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+            CodecType::CODEC_TYPE_PCM_S32LE,
+            CodecType::CODEC_TYPE_PCM_F32LE,
+            CodecType::CODEC_TYPE_PCM_ALAW,
+            CodecType::CODEC_TYPE_PCM_MULAW,
+        ]
+    } else {
+        Vec::new()
+    };
+
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        formats,
+        wav_encoding,
+        flac_encoding: cfg!(feature = "flac"),
+        resampling: true,
+        ogg_demuxer: cfg!(feature = "ogg"),
+        hound_writing: cfg!(feature = "hound"),
+        batch: cfg!(feature = "batch"),
+        background_decoding: cfg!(feature = "threads"),
+        serde: cfg!(feature = "serde"),
+    }
+}
+
+#[test]
+fn test_formats_follow_features() {
+    let capabilities = capabilities();
+    let enabled = [
+        (FormatFlag::WAV, cfg!(feature = "wav")),
+        (FormatFlag::FLAC, cfg!(feature = "flac")),
+        (FormatFlag::MP3, cfg!(feature = "mp3")),
+    ];
+    for &(format, enabled) in &enabled {
+        assert_eq!(capabilities.format(format).is_some(), enabled, "{}", format);
+    }
+    assert!(capabilities.format(FormatFlag::AAC).is_none());
+    assert_eq!(capabilities.wav_encoding.is_empty(), !cfg!(feature = "wav"));
+    assert_eq!(capabilities.flac_encoding, cfg!(feature = "flac"));
+    assert_eq!(capabilities.batch, cfg!(feature = "batch"));
+    assert_eq!(capabilities.hound_writing, cfg!(feature = "hound"));
+    assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_wav_codecs() {
+    let capabilities = capabilities();
+    let wav = capabilities.format(FormatFlag::WAV).unwrap();
+    assert_eq!(wav.extensions, vec!["wav"]);
+    assert_eq!(wav.seeking, Seeking::Anywhere);
+    assert!(capabilities.decodes(FormatFlag::WAV, CodecType::CODEC_TYPE_PCM_ALAW));
+    assert_eq!(
+        capabilities.decodes(FormatFlag::WAV, CodecType::CODEC_TYPE_MP3),
+        cfg!(feature = "mp3")
+    );
+    // every codec written is read again
+    for codec in &capabilities.wav_encoding {
+        assert!(wav.codecs.contains(codec), "{:?}", codec);
+    }
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac", feature = "mp3"))]
+fn test_default_features() {
+    let capabilities = capabilities();
+    let formats: Vec<FormatFlag> = capabilities.formats.iter().map(|f| f.format).collect();
+    assert_eq!(
+        formats,
+        vec![FormatFlag::WAV, FormatFlag::FLAC, FormatFlag::MP3]
+    );
+    assert_eq!(
+        capabilities.format(FormatFlag::FLAC).unwrap().seeking,
+        Seeking::Indexed
+    );
+    assert_eq!(
+        capabilities.format(FormatFlag::MP3).unwrap().seeking,
+        Seeking::Anywhere
+    );
+    assert!(!capabilities.decodes(FormatFlag::MP3, CodecType::CODEC_TYPE_FLAC));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_capabilities_serialize() {
+    fn assert_serialize<T: serde::Serialize>() {}
+    assert_serialize::<Capabilities>();
+}
@@ -3,20 +3,130 @@
 use std::fmt;
 
 /// Format flag to specify when reading audio
-#[derive(PartialEq, Eq, Debug)]
+///
+/// Flags exist for containers which can not be read yet, so that they can be
+/// named before a decoder exists, and more will be added.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum FormatFlag {
     /// aac
-    AAC = 0,
+    AAC,
     /// flac
-    FLAC = 1,
+    FLAC,
     /// mp3 - mpeg layer 3
-    MP3 = 2,
+    MP3,
     /// raw audio
-    PCM = 3,
+    PCM,
     /// wave audio
-    WAV = 4,
+    WAV,
     /// vorbis or ogg
-    VORBIS = 5,
+    VORBIS,
+    /// opus in ogg
+    OPUS,
+    /// audio interchange file format, including AIFF-C
+    AIFF,
+    /// core audio format
+    CAF,
+    /// sony wave64
+    W64,
+    /// a format added with `format::register`, numbered by its implementation
+    Custom(u32),
+}
+
+/// PCM little endian codecs of WAV and its relatives
+const WAV_CODECS: [CodecType; 9] = [
+    CodecType::CODEC_TYPE_PCM_U8,
+    CodecType::CODEC_TYPE_PCM_S16LE,
+    CodecType::CODEC_TYPE_PCM_S24LE,
+    CodecType::CODEC_TYPE_PCM_S32LE,
+    CodecType::CODEC_TYPE_PCM_F32LE,
+    CodecType::CODEC_TYPE_PCM_F64LE,
+    CodecType::CODEC_TYPE_PCM_ALAW,
+    CodecType::CODEC_TYPE_PCM_MULAW,
+    CodecType::CODEC_TYPE_MP3,
+];
+
+/// PCM big endian codecs of AIFF and AIFF-C
+const AIFF_CODECS: [CodecType; 8] = [
+    CodecType::CODEC_TYPE_PCM_S8,
+    CodecType::CODEC_TYPE_PCM_S16BE,
+    CodecType::CODEC_TYPE_PCM_S24BE,
+    CodecType::CODEC_TYPE_PCM_S32BE,
+    CodecType::CODEC_TYPE_PCM_F32BE,
+    CodecType::CODEC_TYPE_PCM_F64BE,
+    CodecType::CODEC_TYPE_PCM_ALAW,
+    CodecType::CODEC_TYPE_PCM_MULAW,
+];
+
+/// PCM of either byte order and the lossy codecs CAF stores
+const CAF_CODECS: [CodecType; 17] = [
+    CodecType::CODEC_TYPE_PCM_S8,
+    CodecType::CODEC_TYPE_PCM_S16LE,
+    CodecType::CODEC_TYPE_PCM_S16BE,
+    CodecType::CODEC_TYPE_PCM_S24LE,
+    CodecType::CODEC_TYPE_PCM_S24BE,
+    CodecType::CODEC_TYPE_PCM_S32LE,
+    CodecType::CODEC_TYPE_PCM_S32BE,
+    CodecType::CODEC_TYPE_PCM_F32LE,
+    CodecType::CODEC_TYPE_PCM_F32BE,
+    CodecType::CODEC_TYPE_PCM_F64LE,
+    CodecType::CODEC_TYPE_PCM_F64BE,
+    CodecType::CODEC_TYPE_PCM_ALAW,
+    CodecType::CODEC_TYPE_PCM_MULAW,
+    CodecType::CODEC_TYPE_MP3,
+    CodecType::CODEC_TYPE_AAC,
+    CodecType::CODEC_TYPE_FLAC,
+    CodecType::CODEC_TYPE_OPUS,
+];
+
+/// Interleaved PCM codecs, raw audio may be any of them
+const PCM_CODECS: [CodecType; 20] = [
+    CodecType::CODEC_TYPE_PCM_S32LE,
+    CodecType::CODEC_TYPE_PCM_S32BE,
+    CodecType::CODEC_TYPE_PCM_S24LE,
+    CodecType::CODEC_TYPE_PCM_S24BE,
+    CodecType::CODEC_TYPE_PCM_S16LE,
+    CodecType::CODEC_TYPE_PCM_S16BE,
+    CodecType::CODEC_TYPE_PCM_S8,
+    CodecType::CODEC_TYPE_PCM_U32LE,
+    CodecType::CODEC_TYPE_PCM_U32BE,
+    CodecType::CODEC_TYPE_PCM_U24LE,
+    CodecType::CODEC_TYPE_PCM_U24BE,
+    CodecType::CODEC_TYPE_PCM_U16LE,
+    CodecType::CODEC_TYPE_PCM_U16BE,
+    CodecType::CODEC_TYPE_PCM_U8,
+    CodecType::CODEC_TYPE_PCM_F32LE,
+    CodecType::CODEC_TYPE_PCM_F32BE,
+    CodecType::CODEC_TYPE_PCM_F64LE,
+    CodecType::CODEC_TYPE_PCM_F64BE,
+    CodecType::CODEC_TYPE_PCM_ALAW,
+    CodecType::CODEC_TYPE_PCM_MULAW,
+];
+
+impl FormatFlag {
+    /// Returns the codecs the container may carry, empty for `Custom`
+    ///
+    /// This describes the container, not what can be decoded, which is
+    /// returned by `capabilities`.
+    pub fn codecs(self) -> &'static [CodecType] {
+        match self {
+            FormatFlag::AAC => &[CodecType::CODEC_TYPE_AAC],
+            FormatFlag::FLAC => &[CodecType::CODEC_TYPE_FLAC],
+            FormatFlag::MP3 => &[CodecType::CODEC_TYPE_MP3],
+            FormatFlag::PCM => &PCM_CODECS,
+            FormatFlag::WAV | FormatFlag::W64 => &WAV_CODECS,
+            FormatFlag::VORBIS => &[
+                CodecType::CODEC_TYPE_VORBIS,
+                CodecType::CODEC_TYPE_OPUS,
+                CodecType::CODEC_TYPE_FLAC,
+            ],
+            FormatFlag::OPUS => &[CodecType::CODEC_TYPE_OPUS],
+            FormatFlag::AIFF => &AIFF_CODECS,
+            FormatFlag::CAF => &CAF_CODECS,
+            FormatFlag::Custom(_) => &[],
+        }
+    }
 }
 
 impl fmt::Display for FormatFlag {
@@ -28,6 +138,8 @@ impl fmt::Display for FormatFlag {
 /// A `CodecType` is a unique identifier used to identify a specific codec.
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum CodecType {
     /// Null decoder, simply discards all data.
     CODEC_TYPE_NULL = 0,
@@ -121,6 +233,80 @@ pub enum CodecType {
     CODEC_TYPE_AAC,
     /// Vorbis
     CODEC_TYPE_VORBIS,
+    /// Opus
+    CODEC_TYPE_OPUS,
+}
+
+/// The type of the samples a codec decodes to
+///
+/// Integers are named after the container holding their bits once decoded,
+/// which need not be their bit depth: FLAC's 12 bits are `S16` and its 20
+/// bits `S24`, and so are 20 valid bits in a 24 or 32 bit WAV container.
+/// A-law and µ-law expand to `S16`. Lossy codecs decode to floats, their
+/// samples have no bit depth and their `AudioInfo::bits_per_sample` is 0.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SampleFormat {
+    /// unsigned 8 bit integers, only of unsigned PCM
+    U8,
+    /// signed integers of up to 16 bits, signed 8 bit ones included
+    S16,
+    /// signed integers of 17 to 24 bits
+    S24,
+    /// signed integers of 25 to 32 bits, or of an unknown width
+    S32,
+    /// 32 bit floats
+    F32,
+    /// 64 bit floats
+    F64,
+}
+
+impl SampleFormat {
+    /// Returns the type of the samples of `bits_per_sample` bits `codec` decodes to
+    pub fn of_codec(codec: CodecType, bits_per_sample: u32) -> SampleFormat {
+        match codec {
+            CodecType::CODEC_TYPE_PCM_F32LE
+            | CodecType::CODEC_TYPE_PCM_F32LE_PLANAR
+            | CodecType::CODEC_TYPE_PCM_F32BE
+            | CodecType::CODEC_TYPE_PCM_F32BE_PLANAR
+            | CodecType::CODEC_TYPE_MP3
+            | CodecType::CODEC_TYPE_AAC
+            | CodecType::CODEC_TYPE_VORBIS
+            | CodecType::CODEC_TYPE_OPUS => SampleFormat::F32,
+            CodecType::CODEC_TYPE_PCM_F64LE
+            | CodecType::CODEC_TYPE_PCM_F64LE_PLANAR
+            | CodecType::CODEC_TYPE_PCM_F64BE
+            | CodecType::CODEC_TYPE_PCM_F64BE_PLANAR => SampleFormat::F64,
+            CodecType::CODEC_TYPE_PCM_U8 | CodecType::CODEC_TYPE_PCM_U8_PLANAR => SampleFormat::U8,
+            // G.711 stores 8 bits, which expand to 16
+            CodecType::CODEC_TYPE_PCM_ALAW | CodecType::CODEC_TYPE_PCM_MULAW => SampleFormat::S16,
+            _ => match bits_per_sample {
+                1..=16 => SampleFormat::S16,
+                17..=24 => SampleFormat::S24,
+                _ => SampleFormat::S32,
+            },
+        }
+    }
+
+    /// Returns true for float samples
+    pub fn is_float(self) -> bool {
+        self == SampleFormat::F32 || self == SampleFormat::F64
+    }
+
+    /// Returns the integer sample of silence, which the samples are centered around
+    ///
+    /// Unsigned 8 bit samples are centered around 128, signed ones around 0.
+    pub fn offset(self) -> i32 {
+        match self {
+            SampleFormat::U8 => 128,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
 /// convert codec type to string
@@ -168,6 +354,7 @@ pub fn codec_to_str(codec_type: &CodecType) -> &str {
         CodecType::CODEC_TYPE_MP3 => "mp3",
         CodecType::CODEC_TYPE_AAC => "aac",
         CodecType::CODEC_TYPE_VORBIS => "vorbis",
+        CodecType::CODEC_TYPE_OPUS => "opus",
         CodecType::CODEC_TYPE_NULL => "unknown",
     }
 }
@@ -177,3 +364,138 @@ impl fmt::Display for CodecType {
         write!(f, "{}", codec_to_str(self))
     }
 }
+
+/// The fmt chunk of a WAV file in an encoding which can not be decoded
+///
+/// Returned by `Error::UnsupportedWaveFormat`, so that e.g. a GSM file can
+/// still be reported as such.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WaveFormat {
+    /// the format tag, as registered in mmreg.h
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bytes_per_second: u32,
+    pub block_align: u16,
+    /// bits per sample, 0 for many compressed formats
+    pub bits_per_sample: u16,
+}
+
+impl WaveFormat {
+    /// Returns the name of the format, if the tag is a known one
+    pub fn name(&self) -> Option<&'static str> {
+        wave_format_name(self.format_tag)
+    }
+}
+
+impl fmt::Display for WaveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WAV format tag {:#06x}", self.format_tag)?;
+        if let Some(name) = self.name() {
+            write!(f, " ({})", name)?;
+        }
+        write!(f, ", {} Hz, {} channel(s)", self.sample_rate, self.channels)
+    }
+}
+
+/// Returns the name of a WAV format tag, `None` for tags not in the table
+pub fn wave_format_name(format_tag: u16) -> Option<&'static str> {
+    Some(match format_tag {
+        0x0001 => "PCM",
+        0x0002 => "Microsoft ADPCM",
+        0x0003 => "IEEE float",
+        0x0006 => "A-law",
+        0x0007 => "mu-law",
+        0x0011 => "IMA ADPCM",
+        0x0022 => "DSP Group TrueSpeech",
+        0x0031 => "GSM 6.10",
+        0x0040 => "G.721 ADPCM",
+        0x0050 => "MPEG audio",
+        0x0055 => "MPEG Layer 3",
+        0x0092 => "Dolby AC-3 SPDIF",
+        0x00ff => "AAC",
+        0x0160 => "Windows Media Audio 1",
+        0x0161 => "Windows Media Audio 2",
+        0x0162 => "Windows Media Audio Pro",
+        0x0163 => "Windows Media Audio Lossless",
+        0x0200 => "Creative ADPCM",
+        0x1610 => "HE-AAC",
+        0x2000 => "Dolby AC-3",
+        0x2001 => "DTS",
+        0xf1ac => "FLAC",
+        0xfffe => "extensible",
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_format_codecs() {
+    use std::collections::HashSet;
+
+    let flags: HashSet<FormatFlag> = [FormatFlag::WAV, FormatFlag::W64, FormatFlag::WAV]
+        .iter()
+        .copied()
+        .collect();
+    assert_eq!(flags.len(), 2);
+    assert_eq!(FormatFlag::WAV.codecs(), FormatFlag::W64.codecs());
+    assert_eq!(FormatFlag::FLAC.codecs(), [CodecType::CODEC_TYPE_FLAC]);
+    assert!(FormatFlag::VORBIS
+        .codecs()
+        .contains(&CodecType::CODEC_TYPE_OPUS));
+    assert!(FormatFlag::AIFF
+        .codecs()
+        .iter()
+        .all(|c| !codec_to_str(c).ends_with("le")));
+    assert!(FormatFlag::Custom(1).codecs().is_empty());
+    for flag in &[FormatFlag::PCM, FormatFlag::AIFF, FormatFlag::CAF] {
+        let codecs: HashSet<_> = flag.codecs().iter().collect();
+        assert_eq!(
+            codecs.len(),
+            flag.codecs().len(),
+            "{} lists a codec twice",
+            flag
+        );
+    }
+}
+
+#[test]
+fn test_sample_format_of_codec() {
+    let cases = [
+        (CodecType::CODEC_TYPE_PCM_U8, 8, SampleFormat::U8),
+        (CodecType::CODEC_TYPE_PCM_S8, 8, SampleFormat::S16),
+        (CodecType::CODEC_TYPE_PCM_S16LE, 16, SampleFormat::S16),
+        // valid bits below the container get the format of the valid bits
+        (CodecType::CODEC_TYPE_PCM_S16LE, 12, SampleFormat::S16),
+        (CodecType::CODEC_TYPE_PCM_S24LE, 20, SampleFormat::S24),
+        (CodecType::CODEC_TYPE_PCM_S32LE, 24, SampleFormat::S24),
+        (CodecType::CODEC_TYPE_PCM_S32LE, 32, SampleFormat::S32),
+        // G.711 expands to 16 bits
+        (CodecType::CODEC_TYPE_PCM_ALAW, 16, SampleFormat::S16),
+        (CodecType::CODEC_TYPE_PCM_MULAW, 8, SampleFormat::S16),
+        (CodecType::CODEC_TYPE_FLAC, 8, SampleFormat::S16),
+        (CodecType::CODEC_TYPE_FLAC, 12, SampleFormat::S16),
+        (CodecType::CODEC_TYPE_FLAC, 20, SampleFormat::S24),
+        (CodecType::CODEC_TYPE_FLAC, 24, SampleFormat::S24),
+        (CodecType::CODEC_TYPE_FLAC, 32, SampleFormat::S32),
+        (CodecType::CODEC_TYPE_PCM_F32LE, 32, SampleFormat::F32),
+        (CodecType::CODEC_TYPE_PCM_F64BE, 64, SampleFormat::F64),
+        (CodecType::CODEC_TYPE_MP3, 0, SampleFormat::F32),
+        // integers of an unknown width
+        (CodecType::CODEC_TYPE_NULL, 0, SampleFormat::S32),
+    ];
+    for &(codec, bits, expected) in cases.iter() {
+        let format = SampleFormat::of_codec(codec, bits);
+        assert_eq!(
+            format,
+            expected,
+            "{} of {} bits",
+            codec_to_str(&codec),
+            bits
+        );
+        assert_eq!(
+            format.is_float(),
+            expected == SampleFormat::F32 || expected == SampleFormat::F64
+        );
+        assert_eq!(format.offset() == 128, expected == SampleFormat::U8);
+    }
+}
@@ -0,0 +1,240 @@
+//! Sample by sample comparison of two decoded segments.
+
+use crate::audio::{AudioInfo, AudioSegment, SampleIterator};
+use crate::codecs::CodecType;
+use crate::errors;
+use crate::io::{AudioSamplesIterator, Sample};
+use crate::Result;
+
+/// The outcome of `compare`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompareReport {
+    /// number of inter-channel samples present in both segments
+    pub frames: u64,
+
+    /// number of inter-channel samples only present in the longer segment
+    pub trailing_frames: u64,
+
+    /// index of the first inter-channel sample in which the segments differ at all
+    ///
+    /// If the shorter segment is a prefix of the longer one, this is the end of
+    /// the shorter segment.
+    pub first_difference: Option<u64>,
+
+    /// largest absolute difference of two samples, relative to full scale
+    pub max_abs_difference: f64,
+
+    /// true if the segments have the same length and no difference exceeds the tolerance
+    pub nulls: bool,
+}
+
+/// Decodes both segments in lockstep and compares their samples
+///
+/// The segments must have the same sample rate and number of channels.
+/// Differences are measured relative to full scale, i.e. samples are mapped
+/// to `[-1.0, 1.0)`, and `tolerance` is on the same scale.
+///
+/// If both segments hold integer samples of the same bit depth, the samples
+/// are compared as integers without any float conversion, so with a tolerance
+/// of `0.0` the segments only null if they are bit identical.
+///
+/// Both segments must not have returned a sample iterator yet.
+pub fn compare(
+    a: &mut AudioSegment,
+    b: &mut AudioSegment,
+    tolerance: f64,
+) -> Result<CompareReport> {
+    if a.info().sample_rate != b.info().sample_rate {
+        return errors::unsupported_error("segments have different sample rates");
+    }
+    if a.number_channels() != b.number_channels() {
+        return errors::unsupported_error("segments have different numbers of channels");
+    }
+    let channels = a.number_channels() as u64;
+
+    let mut report = match (integer_bits(a.info()), integer_bits(b.info())) {
+        (Some(bits_a), Some(bits_b)) if bits_a == bits_b => {
+            let scale = full_scale(bits_a);
+            lockstep(
+                a.samples::<i32>()?,
+                b.samples::<i32>()?,
+                channels,
+                |x, y| (x as i64 - y as i64).abs() as f64 / scale,
+            )?
+        }
+        _ => lockstep(normalized(a)?, normalized(b)?, channels, |x, y| {
+            (x - y).abs()
+        })?,
+    };
+
+    report.nulls = report.trailing_frames == 0 && report.max_abs_difference <= tolerance;
+    Ok(report)
+}
+
+/// Returns the bit depth of integer samples, `None` for float samples.
+fn integer_bits(info: &AudioInfo) -> Option<u32> {
+    match info.codec_type {
+        CodecType::CODEC_TYPE_PCM_F32LE | CodecType::CODEC_TYPE_PCM_F64LE => None,
+        _ => Some(info.bits_per_sample),
+    }
+}
+
+fn full_scale(bits: u32) -> f64 {
+    (1u64 << (bits.max(1) - 1)) as f64
+}
+
+/// Returns the samples of `segment` mapped to `[-1.0, 1.0)`.
+fn normalized(segment: &mut AudioSegment) -> Result<SampleIterator<'_, f64>> {
+    let codec = segment.info().codec_type;
+    let scale = full_scale(segment.info().bits_per_sample);
+    let offset = segment.info().sample_format.offset() as f64;
+    Ok(match codec {
+        CodecType::CODEC_TYPE_PCM_F32LE => Box::new(Normalized {
+            samples: segment.samples::<f32>()?,
+            offset: 0.0,
+            scale: 1.0,
+        }),
+        CodecType::CODEC_TYPE_PCM_F64LE => segment.samples::<f64>()?,
+        _ => Box::new(Normalized {
+            samples: segment.samples::<i32>()?,
+            offset,
+            scale,
+        }),
+    })
+}
+
+/// Maps samples to `(sample - offset) / scale`
+struct Normalized<'a, S: Sample> {
+    samples: SampleIterator<'a, S>,
+    offset: f64,
+    scale: f64,
+}
+
+impl<'a, S: Sample + Into<f64>> AudioSamplesIterator<f64> for Normalized<'a, S> {
+    fn next(&mut self) -> Option<Result<f64>> {
+        let sample = otry!(self.samples.next()?);
+        Some(Ok((sample.into() - self.offset) / self.scale))
+    }
+}
+
+/// Walks both iterators, `difference` returns the absolute difference of two samples.
+fn lockstep<S: Sample + PartialEq>(
+    mut a: SampleIterator<S>,
+    mut b: SampleIterator<S>,
+    channels: u64,
+    difference: impl Fn(S, S) -> f64,
+) -> Result<CompareReport> {
+    let mut report = CompareReport {
+        frames: 0,
+        trailing_frames: 0,
+        first_difference: None,
+        max_abs_difference: 0.0,
+        nulls: false,
+    };
+
+    let mut index = 0u64;
+    loop {
+        let (x, y) = match (a.next(), b.next()) {
+            (Some(x), Some(y)) => (x?, y?),
+            (None, None) => break,
+            (Some(x), None) => {
+                report.trailing_frames = trailing_frames(index, channels, x, &mut a)?;
+                break;
+            }
+            (None, Some(y)) => {
+                report.trailing_frames = trailing_frames(index, channels, y, &mut b)?;
+                break;
+            }
+        };
+        if x != y {
+            if report.first_difference.is_none() {
+                report.first_difference = Some(index / channels);
+            }
+            report.max_abs_difference = report.max_abs_difference.max(difference(x, y));
+        }
+        index += 1;
+    }
+
+    if report.trailing_frames > 0 && report.first_difference.is_none() {
+        report.first_difference = Some(index / channels);
+    }
+    report.frames = index / channels;
+    Ok(report)
+}
+
+/// Consumes the rest of the longer segment, which ended at sample `index` of the shorter one.
+fn trailing_frames<S: Sample>(
+    index: u64,
+    channels: u64,
+    first: Result<S>,
+    rest: &mut SampleIterator<S>,
+) -> Result<u64> {
+    first?;
+    let mut end = index + 1;
+    for sample in rest {
+        sample?;
+        end += 1;
+    }
+    Ok(end / channels - index / channels)
+}
+
+#[cfg(all(test, feature = "wav", feature = "flac"))]
+fn segments(
+    samples: &[i32],
+    flac_samples: &[i32],
+    sample_rate: u32,
+) -> (AudioSegment, AudioSegment) {
+    use crate::codecs::FormatFlag;
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file, FlacStream};
+    use std::io::Cursor;
+
+    let data: Vec<u8> = samples
+        .iter()
+        .flat_map(|s| (*s as i16).to_le_bytes())
+        .collect();
+    let wav = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 44_100)),
+        riff_chunk(b"data", &data),
+    ]);
+    let mut flac = FlacStream::new(sample_rate, 1, 16);
+    flac.frame(&[flac_samples.to_vec()]);
+
+    (
+        AudioSegment::from_reader(Cursor::new(wav), FormatFlag::WAV).unwrap(),
+        AudioSegment::from_reader(Cursor::new(flac.build()), FormatFlag::FLAC).unwrap(),
+    )
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn test_compare_integer_segments() {
+    let samples: Vec<i32> = (0..32).map(|i| i * 1000 - 16_000).collect();
+
+    let (mut a, mut b) = segments(&samples, &samples, 44_100);
+    let report = compare(&mut a, &mut b, 0.0).unwrap();
+    assert_eq!(report.frames, 32);
+    assert_eq!(report.first_difference, None);
+    assert!(report.nulls);
+
+    let mut changed = samples.clone();
+    changed[20] += 3;
+    changed[25] -= 8;
+    let (mut a, mut b) = segments(&samples, &changed, 44_100);
+    let report = compare(&mut a, &mut b, 0.0).unwrap();
+    assert_eq!(report.first_difference, Some(20));
+    assert_eq!(report.max_abs_difference, 8.0 / 32_768.0);
+    assert!(!report.nulls);
+    let (mut a, mut b) = segments(&samples, &changed, 44_100);
+    assert!(compare(&mut a, &mut b, 0.001).unwrap().nulls);
+
+    // a prefix differs at its end and never nulls
+    let (mut a, mut b) = segments(&samples, &samples[..16], 44_100);
+    let report = compare(&mut a, &mut b, 1.0).unwrap();
+    assert_eq!(report.frames, 16);
+    assert_eq!(report.trailing_frames, 16);
+    assert_eq!(report.first_difference, Some(16));
+    assert!(!report.nulls);
+
+    let (mut a, mut b) = segments(&samples, &samples, 48_000);
+    assert!(compare(&mut a, &mut b, 0.0).is_err());
+}
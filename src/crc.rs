@@ -1,9 +1,22 @@
+//! The `crc` module computes the checksums of FLAC frames and Ogg pages.
+//!
+//! The frame header is protected by a CRC-8 with the polynomial
+//! x^8 + x^2 + x^1 + x^0, the whole frame by a CRC-16 with the polynomial
+//! x^16 + x^15 + x^2 + x^0, both starting at 0. The `update` functions allow
+//! computing them piece by piece, e.g. while a frame is still being read.
+//!
+//! Ogg pages have a CRC-32 with the polynomial 0x04c11db7, starting at 0 and
+//! without reflection.
+
 // Credit:
 // Lookup Tables are taken from the libflac source.
 // https://github.com/xiph/flac/blob/master/src/libFLAC/crc.c
 
+#[cfg(feature = "flac")]
 use crate::io::ReadBuffer;
+#[cfg(feature = "flac")]
 use crate::Result;
+#[cfg(feature = "flac")]
 use std::io;
 
 // CRC-8, poly = x^8 + x^2 + x^1 + x^0
@@ -52,11 +65,78 @@ const CRC16_TABLE: [u16; 256] = [
     0x0208, 0x820d, 0x8207, 0x0202,
 ];
 
-pub struct Crc8Reader<'r, 'a, ReadBuffer> {
+// CRC-32, poly = 0x04c11db7
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the FLAC frame header CRC-8 of `data`
+pub fn crc8_flac(data: &[u8]) -> u8 {
+    crc8_update(0, data)
+}
+
+/// Continues the CRC-8 `crc` of the preceding bytes with `data`
+pub fn crc8_update(crc: u8, data: &[u8]) -> u8 {
+    data.iter().fold(crc, |crc, byte| crc8_byte(crc, *byte))
+}
+
+/// Returns the FLAC frame CRC-16 of `data`
+pub fn crc16_flac(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+/// Continues the CRC-16 `crc` of the preceding bytes with `data`
+pub fn crc16_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, byte| crc16_byte(crc, *byte))
+}
+
+/// Returns the Ogg page CRC-32 of `data`
+pub fn crc32_ogg(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continues the CRC-32 `crc` of the preceding bytes with `data`
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, byte| {
+        (crc << 8) ^ CRC32_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+#[inline(always)]
+fn crc8_byte(crc: u8, byte: u8) -> u8 {
+    CRC8_TABLE[(crc ^ byte) as usize]
+}
+
+#[inline(always)]
+fn crc16_byte(crc: u16, byte: u8) -> u16 {
+    (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+}
+
+#[cfg(feature = "flac")]
+pub(crate) struct Crc8Reader<'r, 'a, ReadBuffer> {
     state: u8,
     crc16reader: &'a mut Crc16Reader<'r, ReadBuffer>,
 }
 
+#[cfg(feature = "flac")]
 impl<'r, 'a, R: ReadBuffer> Crc8Reader<'r, 'a, R> {
     /// Creates new CRC 8 reader with initial value 0
     pub fn new(crc16reader: &'a mut Crc16Reader<'r, R>) -> Crc8Reader<'r, 'a, R> {
@@ -77,27 +157,27 @@ impl<'r, 'a, R: ReadBuffer> Crc8Reader<'r, 'a, R> {
 
     pub fn read_u8(&mut self) -> Result<u8> {
         let ds = self.crc16reader.read_u8()?;
-        self.state = CRC8_TABLE[(self.state ^ ds) as usize];
+        self.state = crc8_byte(self.state, ds);
         Ok(ds)
     }
 
     pub fn read_be_u16(&mut self) -> Result<u16> {
         let ds = self.crc16reader.read_be_u16()?;
-        for byte in &ds.to_be_bytes() {
-            self.state = CRC8_TABLE[(self.state ^ byte) as usize];
-        }
+        self.state = crc8_update(self.state, &ds.to_be_bytes());
         Ok(ds)
     }
 }
 
-pub struct Crc16Reader<'r, ReadBuffer> {
+#[cfg(feature = "flac")]
+pub(crate) struct Crc16Reader<'r, ReadBuffer> {
     state: u16,
     input: &'r mut ReadBuffer,
 }
 
+#[cfg(feature = "flac")]
 impl<'r, R: ReadBuffer> Crc16Reader<'r, R> {
     /// Creates new CRC 16 reader with initial value 0
-    pub fn new(input: &mut R) -> Crc16Reader<R> {
+    pub fn new(input: &mut R) -> Crc16Reader<'_, R> {
         Crc16Reader { state: 0, input }
     }
 
@@ -107,6 +187,7 @@ impl<'r, R: ReadBuffer> Crc16Reader<'r, R> {
     }
 }
 
+#[cfg(feature = "flac")]
 impl<'r, R: ReadBuffer> ReadBuffer for Crc16Reader<'r, R> {
     #[inline(always)]
     fn read_into(&mut self, _buf: &mut [u8]) -> io::Result<()> {
@@ -126,7 +207,7 @@ impl<'r, R: ReadBuffer> ReadBuffer for Crc16Reader<'r, R> {
     #[inline(always)]
     fn read_u8(&mut self) -> io::Result<u8> {
         let byte = self.input.read_u8()?;
-        self.state = (self.state << 8) ^ CRC16_TABLE[((self.state >> 8) as u8 ^ byte) as usize];
+        self.state = crc16_byte(self.state, byte);
         Ok(byte)
     }
 
@@ -137,9 +218,7 @@ impl<'r, R: ReadBuffer> ReadBuffer for Crc16Reader<'r, R> {
     #[inline(always)]
     fn read_be_u16(&mut self) -> io::Result<u16> {
         let ds = self.input.read_be_u16()?;
-        for byte in &ds.to_be_bytes() {
-            self.state = (self.state << 8) ^ CRC16_TABLE[((self.state >> 8) as u8 ^ byte) as usize];
-        }
+        self.state = crc16_update(self.state, &ds.to_be_bytes());
         Ok(ds)
     }
 
@@ -163,3 +242,46 @@ impl<'r, R: ReadBuffer> ReadBuffer for Crc16Reader<'r, R> {
         unimplemented!();
     }
 }
+
+#[test]
+fn test_check_values() {
+    // the usual check input of CRC catalogues
+    assert_eq!(crc8_flac(b"123456789"), 0xf4);
+    assert_eq!(crc16_flac(b"123456789"), 0xfee8);
+    assert_eq!(crc32_ogg(b"123456789"), 0x89a1_897f);
+    assert_eq!(crc8_flac(&[]), 0);
+    assert_eq!(crc16_flac(&[]), 0);
+    assert_eq!(crc32_ogg(&[]), 0);
+}
+
+#[test]
+fn test_flac_example_frame() {
+    // the frame of the first example stream in appendix D of RFC 9639
+    let frame = [
+        0xff, 0xf8, 0x69, 0x18, 0x00, 0x00, 0xbf, 0x03, 0x58, 0xfd, 0x03, 0x12, 0x8b, 0xaa, 0x9a,
+    ];
+    assert_eq!(crc8_flac(&frame[..6]), frame[6]);
+    assert_eq!(crc16_flac(&frame[..13]), 0xaa9a);
+    // a frame including its CRC has a CRC of 0
+    assert_eq!(crc8_flac(&frame[..7]), 0);
+    assert_eq!(crc16_flac(&frame), 0);
+
+    // computing it piece by piece gives the same result
+    let crc = frame[..13].chunks(4).fold(0, crc16_update);
+    assert_eq!(crc, 0xaa9a);
+    assert_eq!(crc8_update(crc8_flac(&frame[..3]), &frame[3..6]), 0xbf);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_readers_match_slices() {
+    let data: Vec<u8> = (0..=255).collect();
+    let mut input = &data[..];
+    let mut crc16 = Crc16Reader::new(&mut input);
+    let mut crc8 = Crc8Reader::new(&mut crc16);
+    crc8.read_u8().unwrap();
+    crc8.read_be_u16().unwrap();
+    assert_eq!(crc8.crc(), crc8_flac(&data[..3]));
+    while crc16.read_u8().is_ok() {}
+    assert_eq!(crc16.crc(), crc16_flac(&data));
+}
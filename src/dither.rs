@@ -0,0 +1,187 @@
+//! The `dither` module reduces the bit depth of integer samples.
+//!
+//! Dropping the low bits of quiet material turns the rounding error into
+//! distortion correlated with the signal. Adding a little noise before
+//! rounding decorrelates the error, it becomes a constant noise floor instead.
+
+use crate::errors;
+use crate::Result;
+
+/// How samples are rounded when their bit depth is reduced
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// round to the nearest value, the error is at most half a LSB
+    None,
+    /// add triangular noise of 1 LSB amplitude before rounding
+    ///
+    /// The error is at most 1.5 LSB, its mean is zero whatever the signal.
+    #[default]
+    Tpdf,
+    /// like `Tpdf`, feeding the error back to move the noise to high frequencies
+    ///
+    /// This is a first order highpass, a single sample may be off by up to 3 LSB.
+    NoiseShaped,
+}
+
+/// Converts samples from one bit depth to another
+///
+/// Samples are scaled to the target bit depth, i.e. their full scale is kept.
+/// Widening only shifts them, narrowing dithers and rounds them as selected by
+/// `Dither`, clipping at full scale. The noise is pseudo random from a seed,
+/// so the same seed always gives the same output.
+pub struct Requantizer {
+    dither: Dither,
+    source_bits: u32,
+    target_bits: u32,
+    /// state of the xorshift generator, never 0
+    state: u32,
+    /// last rounding error per channel for noise shaping, in source units
+    errors: Vec<i64>,
+}
+
+impl Requantizer {
+    /// Creates a requantizer for `channels` interleaved channels
+    ///
+    /// Both bit depths must be between 1 and 32.
+    pub fn new(
+        dither: Dither,
+        source_bits: u32,
+        target_bits: u32,
+        channels: usize,
+        seed: u32,
+    ) -> Result<Requantizer> {
+        if !(1..=32).contains(&source_bits) || !(1..=32).contains(&target_bits) {
+            return errors::unsupported_error("bits per sample must be between 1 and 32");
+        }
+        Ok(Requantizer {
+            dither,
+            source_bits,
+            target_bits,
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+            errors: vec![0; channels],
+        })
+    }
+
+    /// Converts a sample of `channel`, which must be less than the number of channels
+    pub fn requantize(&mut self, sample: i32, channel: usize) -> i32 {
+        if self.target_bits >= self.source_bits {
+            return ((sample as i64) << (self.target_bits - self.source_bits)) as i32;
+        }
+        let shift = self.source_bits - self.target_bits;
+        let one = 1i64 << shift;
+
+        let mut value = sample as i64;
+        if self.dither == Dither::NoiseShaped {
+            value -= self.errors[channel];
+        }
+        let noise = match self.dither {
+            Dither::None => 0,
+            Dither::Tpdf | Dither::NoiseShaped => self.uniform(shift) - self.uniform(shift),
+        };
+
+        let max = (1i64 << (self.target_bits - 1)) - 1;
+        let quantized = ((value + noise + one / 2) >> shift).clamp(-max - 1, max);
+        if self.dither == Dither::NoiseShaped {
+            // clipping would otherwise feed back a huge error
+            self.errors[channel] = ((quantized << shift) - value).clamp(-2 * one, 2 * one);
+        }
+        quantized as i32
+    }
+
+    /// Returns a uniformly distributed value in `0..(1 << bits)`, `bits` is 1 to 31.
+    fn uniform(&mut self, bits: u32) -> i64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> (32 - bits)) as i64
+    }
+}
+
+#[cfg(test)]
+fn quiet_sine(amplitude: f64, len: usize) -> Vec<f64> {
+    (0..len)
+        .map(|i| amplitude * (i as f64 * 2.0 * std::f64::consts::PI * 997.0 / 48_000.0).sin())
+        .collect()
+}
+
+#[test]
+fn test_tpdf_dither() {
+    // a sine of 2.3 LSB at 16 bits, given at 24 bits
+    let ideal = quiet_sine(2.3, 48_000);
+    let samples: Vec<i32> = ideal.iter().map(|x| (x * 256.0).round() as i32).collect();
+    let requantize = |dither, seed| {
+        let mut requantizer = Requantizer::new(dither, 24, 16, 1, seed).unwrap();
+        let out: Vec<i32> = samples
+            .iter()
+            .map(|s| requantizer.requantize(*s, 0))
+            .collect();
+        out
+    };
+
+    let rounded = requantize(Dither::None, 1);
+    let dithered = requantize(Dither::Tpdf, 1);
+    assert_eq!(dithered, requantize(Dither::Tpdf, 1));
+    assert_ne!(dithered, requantize(Dither::Tpdf, 2));
+    assert_ne!(dithered, rounded);
+    for (i, x) in ideal.iter().enumerate() {
+        assert!((rounded[i] as f64 - x).abs() <= 0.5 + 1.0 / 256.0);
+        assert!((dithered[i] as f64 - x).abs() <= 1.5 + 1.0 / 256.0);
+    }
+}
+
+#[test]
+fn test_dither_keeps_signal_below_one_lsb() {
+    // rounding a sine of 0.4 LSB gives silence, a dithered one is noisy but still there
+    let ideal = quiet_sine(0.4, 48_000);
+    let samples: Vec<i32> = ideal.iter().map(|x| (x * 256.0).round() as i32).collect();
+    let amplitude = |dither| {
+        let mut requantizer = Requantizer::new(dither, 24, 16, 1, 7).unwrap();
+        let correlation: f64 = samples
+            .iter()
+            .zip(&ideal)
+            .map(|(s, x)| requantizer.requantize(*s, 0) as f64 * x)
+            .sum();
+        correlation / ideal.iter().map(|x| x * x).sum::<f64>() * 0.4
+    };
+
+    assert_eq!(amplitude(Dither::None), 0.0);
+    assert!((amplitude(Dither::Tpdf) - 0.4).abs() < 0.05);
+    assert!((amplitude(Dither::NoiseShaped) - 0.4).abs() < 0.05);
+}
+
+#[test]
+fn test_noise_shaping() {
+    // the shaped error has almost no low frequency content, its sum over a block stays small
+    let ideal = quiet_sine(5.7, 48_000);
+    let samples: Vec<i32> = ideal.iter().map(|x| (x * 256.0).round() as i32).collect();
+    let max_block_error = |dither| {
+        let mut requantizer = Requantizer::new(dither, 24, 16, 1, 3).unwrap();
+        let errors: Vec<f64> = samples
+            .iter()
+            .map(|s| requantizer.requantize(*s, 0) as f64 - *s as f64 / 256.0)
+            .collect();
+        errors
+            .chunks(64)
+            .map(|block| block.iter().sum::<f64>().abs())
+            .fold(0.0, f64::max)
+    };
+
+    assert!(max_block_error(Dither::NoiseShaped) < 4.0);
+    assert!(max_block_error(Dither::Tpdf) > 4.0);
+}
+
+#[test]
+fn test_requantize_range() {
+    let mut widen = Requantizer::new(Dither::Tpdf, 16, 24, 2, 0).unwrap();
+    assert_eq!(widen.requantize(-32_768, 1), -8_388_608);
+    assert_eq!(widen.requantize(5, 0), 5 * 256);
+
+    // full scale clips instead of wrapping around
+    let mut narrow = Requantizer::new(Dither::NoiseShaped, 24, 8, 2, 0).unwrap();
+    for _ in 0..100 {
+        assert_eq!(narrow.requantize(8_388_607, 0), 127);
+        assert!((-128..=-127).contains(&narrow.requantize(-8_388_608, 1)));
+    }
+    assert!(Requantizer::new(Dither::None, 0, 16, 1, 0).is_err());
+    assert!(Requantizer::new(Dither::None, 24, 33, 1, 0).is_err());
+}
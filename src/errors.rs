@@ -4,6 +4,7 @@ use std::error;
 use std::fmt;
 use std::io;
 
+use super::codecs::{FormatFlag, WaveFormat};
 use super::Result;
 
 /// `Error` provides an enumeration of all possible errors reported by Sonata.
@@ -15,6 +16,55 @@ pub enum Error {
     ParseError(&'static str),
     /// An unsupported codec is passed.
     Unsupported(&'static str),
+    /// The stream does not start with the magic bytes of the format.
+    MissingMagic(&'static str),
+    /// The stream matched none of the formats tried, in the given order.
+    UnrecognizedFormat(Vec<FormatFlag>),
+    /// A WAV file is in an encoding which can not be decoded, given by its fmt chunk.
+    UnsupportedWaveFormat(WaveFormat),
+    /// A frame is malformed, e.g. fails its CRC, `offset` is the byte offset of its sync code.
+    CorruptFrame { offset: u64, reason: &'static str },
+    /// Frame number `frame`, counted from 0, has other parameters than the frames before it.
+    InconsistentFrame {
+        frame: u64,
+        expected: StreamParameters,
+        found: StreamParameters,
+    },
+    /// Sample number `sample`, counted from 0 over all channels, is NaN or infinite.
+    ///
+    /// Only returned with `NonFinite::Error`, see `DecodeOptions::non_finite_samples`.
+    NonFiniteSample { sample: u64 },
+    /// Track number `track` of a playlist, counted from 0, can not be joined to the ones before it.
+    ///
+    /// See `playlist::Player::open`.
+    TrackMismatch {
+        track: usize,
+        expected: StreamParameters,
+        found: StreamParameters,
+    },
+}
+
+/// The parameters frames of the same stream have to agree on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StreamParameters {
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// 0 for lossy codecs, like `AudioInfo::bits_per_sample`
+    pub bits_per_sample: u32,
+}
+
+impl fmt::Display for StreamParameters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} Hz with {} channel(s)",
+            self.sample_rate, self.channels
+        )?;
+        if self.bits_per_sample > 0 {
+            write!(f, " of {} bits", self.bits_per_sample)?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Error {
@@ -23,6 +73,43 @@ impl fmt::Display for Error {
             Error::IoError(ref err) => err.fmt(f),
             Error::ParseError(ref msg) => write!(f, "Malformed stream encountered: {}", msg),
             Error::Unsupported(ref codec) => write!(f, "Unsupported codec encountered: {}", codec),
+            Error::MissingMagic(ref msg) => write!(f, "Missing magic bytes: {}", msg),
+            Error::UnrecognizedFormat(ref tried) => {
+                write!(f, "Unrecognized format, tried")?;
+                for (i, flag) in tried.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { "" } else { "," }, flag)?;
+                }
+                Ok(())
+            }
+            Error::UnsupportedWaveFormat(ref format) => {
+                write!(f, "Unsupported codec encountered: {}", format)
+            }
+            Error::CorruptFrame { offset, reason } => {
+                write!(f, "Malformed frame at byte {}: {}", offset, reason)
+            }
+            Error::InconsistentFrame {
+                frame,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Malformed stream encountered: frame {} is {}, the stream is {}",
+                frame, found, expected
+            ),
+            Error::NonFiniteSample { sample } => write!(
+                f,
+                "Malformed stream encountered: sample {} is NaN or infinite",
+                sample
+            ),
+            Error::TrackMismatch {
+                track,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Tracks can not be joined: track {} is {}, the playlist is {}",
+                track, found, expected
+            ),
         }
     }
 }
@@ -33,6 +120,13 @@ impl error::Error for Error {
             Error::IoError(ref err) => Some(err),
             Error::ParseError(_) => None,
             Error::Unsupported(_) => None,
+            Error::MissingMagic(_) => None,
+            Error::UnrecognizedFormat(_) => None,
+            Error::UnsupportedWaveFormat(_) => None,
+            Error::CorruptFrame { .. } => None,
+            Error::InconsistentFrame { .. } => None,
+            Error::NonFiniteSample { .. } => None,
+            Error::TrackMismatch { .. } => None,
         }
     }
 }
@@ -43,11 +137,45 @@ impl From<io::Error> for Error {
     }
 }
 
+/// Translates the errors of `hound::WavWriter`, see `write_with_hound`
+#[cfg(feature = "hound")]
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Error {
+        match err {
+            hound::Error::IoError(err) => Error::IoError(err),
+            hound::Error::FormatError(msg) => Error::ParseError(msg),
+            hound::Error::TooWide => Error::Unsupported("the sample is too wide for the WavSpec"),
+            hound::Error::UnfinishedSample => {
+                Error::ParseError("the stream ended within an inter-channel sample")
+            }
+            hound::Error::Unsupported => Error::Unsupported("the WavSpec can not be written"),
+            hound::Error::InvalidSampleFormat => {
+                Error::Unsupported("the samples do not match the sample format of the WavSpec")
+            }
+        }
+    }
+}
+
+impl From<cauldron_flac_core::Error<io::Error>> for Error {
+    fn from(err: cauldron_flac_core::Error<io::Error>) -> Error {
+        match err {
+            cauldron_flac_core::Error::Source(err) => Error::IoError(err),
+            cauldron_flac_core::Error::Parse(msg) => Error::ParseError(msg),
+            cauldron_flac_core::Error::Unsupported(msg) => Error::Unsupported(msg),
+        }
+    }
+}
+
 /// function to create a decode error.
 pub fn parse_error<T>(desc: &'static str) -> Result<T> {
     Err(Error::ParseError(desc))
 }
 
+/// function to create a missing magic error.
+pub fn missing_magic_error<T>(desc: &'static str) -> Result<T> {
+    Err(Error::MissingMagic(desc))
+}
+
 /// function to create an unsupported codec error.
 pub fn unsupported_error<T>(codec: &'static str) -> Result<T> {
     Err(Error::Unsupported(codec))
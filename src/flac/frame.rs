@@ -1,11 +1,22 @@
+use std::cmp;
 use std::fmt;
 
 use crate::crc::{Crc16Reader, Crc8Reader};
-use crate::io::{BitStream, ReadBuffer};
+use crate::io::{AudioInputStream, BitStream, ReadBuffer, Rewind};
+use crate::stats::{FrameStats, SubframeKind, SubframeStats};
 use crate::{audio, errors, Result};
 
 use super::decoder;
 
+/// Channel mask to decode every channel of a frame
+pub const ALL_CHANNELS: u8 = 0xff;
+
+/// The reason of `Error::CorruptFrame` for a frame header failing its CRC-8
+pub const HEADER_CRC_MISMATCH: &str = "CRC match failed, Invalid frame";
+
+/// The reason of `Error::CorruptFrame` for a frame failing its CRC-16
+pub const FRAME_CRC_MISMATCH: &str = "frame CRC mismatch";
+
 enum BlockStrategy {
     Fixed,
     Variable,
@@ -40,6 +51,12 @@ struct FrameHeader {
     pub sample_rate: u32,
     pub channel_type: ChannelType,
     pub bits_per_sample: u32,
+    /// the reserved value a lenient header was read despite
+    pub reserved: Option<&'static str>,
+    /// the sample rate is the one of STREAMINFO
+    pub sample_rate_from_stream_info: bool,
+    /// the bits per sample are the ones of STREAMINFO
+    pub bits_per_sample_from_stream_info: bool,
 }
 
 impl FrameHeader {
@@ -60,7 +77,13 @@ enum SubFrameType {
 }
 
 /// represent a block of decoded samples from a frame
-#[allow(dead_code)]
+///
+/// The samples are stored per channel, one channel after another, and already
+/// decorrelated, i.e. stereo frames hold the left and right channel.
+///
+/// This channel-major layout is guaranteed: sample `i` of channel `c` is at
+/// index `c * total_samples() + i` of `into_buffer()`, and the buffer holds
+/// exactly `num_channels() * total_samples()` samples.
 pub struct Block {
     /// index of the first sample of this block w.r.t total samples
     first_sample_index: u64,
@@ -70,57 +93,179 @@ pub struct Block {
     no_channels: u32,
     /// bits pr sample
     bits_per_sample: u32,
+    /// sample rate of the frame
+    sample_rate: u32,
     /// decoded samples with channels one after another
     buffer: Vec<i32>,
+    /// number of trailing zero bits known for every sample of a channel
+    wasted_bits: [u32; MAX_CHANNELS],
 }
 
 impl Block {
-    fn new(sample_index: u64, block_size: u32, bps: u32, buffer: Vec<i32>) -> Block {
+    fn new(
+        sample_index: u64,
+        block_size: u32,
+        bps: u32,
+        sample_rate: u32,
+        buffer: Vec<i32>,
+        wasted_bits: [u32; MAX_CHANNELS],
+    ) -> Block {
         Block {
             first_sample_index: sample_index,
             block_size,
             no_channels: buffer.len() as u32 / block_size,
             bits_per_sample: bps,
+            sample_rate,
             buffer,
+            wasted_bits,
         }
     }
 
+    /// returns a block without samples, to start decoding with
     pub fn empty() -> Block {
         Block {
             first_sample_index: 0,
             block_size: 0,
             no_channels: 0,
             bits_per_sample: 0,
+            sample_rate: 0,
             buffer: Vec::with_capacity(0),
+            wasted_bits: [0; MAX_CHANNELS],
         }
     }
 
+    /// returns a block of `block_size` zero samples, reusing the allocation of `buffer`
+    pub fn silence(
+        sample_index: u64,
+        block_size: u32,
+        channels: u32,
+        bps: u32,
+        sample_rate: u32,
+        mut buffer: Vec<i32>,
+    ) -> Block {
+        buffer.clear();
+        buffer.resize((channels * block_size) as usize, 0);
+        Block::new(
+            sample_index,
+            block_size,
+            bps,
+            sample_rate,
+            buffer,
+            [0; MAX_CHANNELS],
+        )
+    }
+
+    /// returns the index of the first inter-channel sample of this block in the stream
+    #[inline(always)]
+    pub fn first_sample_index(&self) -> u64 {
+        self.first_sample_index
+    }
+
+    /// returns the number of inter-channel samples in this block
     #[inline(always)]
     pub fn total_samples(&self) -> u32 {
         self.block_size
     }
 
+    /// returns the number of channels in this block
     #[inline(always)]
     pub fn num_channels(&self) -> u32 {
         self.no_channels
     }
 
+    /// returns the bits per sample of the frame
     #[inline(always)]
     pub fn bits_per_sample(&self) -> u32 {
         self.bits_per_sample
     }
 
+    /// returns the sample rate of the frame
+    #[inline(always)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// returns the number of wasted bits of a channel
+    ///
+    /// These are the trailing zero bits every sample of the channel has, as
+    /// signalled by the subframes. Constant subframes count the zero bits of
+    /// their value, so a silent channel has all bits wasted.
+    ///
+    /// Panics if the channel is out of range.
+    #[inline(always)]
+    pub fn wasted_bits(&self, channel: u32) -> u32 {
+        assert!(channel < self.no_channels, "channel out of range");
+        self.wasted_bits[channel as usize]
+    }
+
     /// returns the underlying buffer which stores sample
     #[inline(always)]
     pub fn into_buffer(self) -> Vec<i32> {
         self.buffer
     }
 
+    /// returns the `total_samples()` samples of one channel
+    ///
+    /// Panics if the channel is out of range.
+    #[inline(always)]
+    pub fn channel(&self, channel: u32) -> &[i32] {
+        assert!(channel < self.no_channels, "channel out of range");
+        let start = channel as usize * self.block_size as usize;
+        &self.buffer[start..start + self.block_size as usize]
+    }
+
+    /// returns the decoded sample, or `None` if the channel or sample index is out of range
+    #[inline(always)]
+    pub fn try_get_sample(&self, channel: u32, sample: u32) -> Option<i32> {
+        if channel < self.no_channels && sample < self.block_size {
+            Some(self.get_sample(channel, sample))
+        } else {
+            None
+        }
+    }
+
     /// return the decoded sample from the buffer
+    ///
+    /// The indices are only checked in debug builds, an out-of-range sample
+    /// index may return a sample of the next channel in release builds. Use
+    /// `try_get_sample` for indices which are not known to be valid.
     #[inline(always)]
     pub fn get_sample(&self, current_channel: u32, samples_read: u32) -> i32 {
+        debug_assert!(current_channel < self.no_channels, "channel out of range");
+        debug_assert!(samples_read < self.block_size, "sample out of range");
         self.buffer[current_channel as usize * self.block_size as usize + samples_read as usize]
     }
+
+    /// Relabels the samples as having `bps` bits per sample, the samples keep their values
+    pub fn set_bits_per_sample(&mut self, bps: u32) {
+        self.bits_per_sample = bps;
+    }
+
+    /// Drops the channels from `channels` on, or adds silent ones up to it
+    pub fn set_num_channels(&mut self, channels: u32) {
+        // the channels are stored one after another, resizing drops or adds whole ones
+        self.buffer.resize((channels * self.block_size) as usize, 0);
+        for ch in self.no_channels..channels {
+            self.wasted_bits[ch as usize] = 0;
+        }
+        self.no_channels = channels;
+    }
+
+    /// Replaces `bytes` with the samples as the MD5 signature of STREAMINFO covers them
+    ///
+    /// The samples are interleaved and little endian, in the whole bytes their
+    /// bits per sample take.
+    pub fn signature_bytes(&self, bytes: &mut Vec<u8>) {
+        let width = self.bits_per_sample.div_ceil(8) as usize;
+        bytes.clear();
+        bytes.reserve(self.buffer.len() * width);
+        for i in 0..self.block_size {
+            for ch in 0..self.no_channels {
+                let sample = self.get_sample(ch, i).to_le_bytes();
+                bytes.extend_from_slice(&sample[..width]);
+            }
+        }
+    }
 }
 
 /// Converts a buffer with left samples and a side channel in-place to left ++ right.
@@ -217,11 +362,17 @@ fn read_utf8_coded_int<R: ReadBuffer>(crc_reader: &mut Crc8Reader<R>) -> Result<
 }
 
 // See https://xiph.org/flac/format.html#frame_header for header info
+//
+// If `lenient`, reserved values which the reference decoder gets past are
+// read as it reads them, and noted in `FrameHeader::reserved`.
 fn read_frame_header<R: ReadBuffer>(
     crc_reader: &mut Crc8Reader<R>,
     audio_info: &audio::AudioInfo,
     sync_code: u16,
+    lenient: bool,
 ) -> Result<FrameHeader> {
+    let mut reserved = None;
+
     // check sync code
     // The first 14 bits must be 11111111111110.
     if sync_code & 0b1111_1111_1111_1100 != 0b1111_1111_1111_1000 {
@@ -231,7 +382,10 @@ fn read_frame_header<R: ReadBuffer>(
     // According to format spec, next value must be 0, 1 is reserved for future use
     // when format will get changed, hence throwing unsupported when encountering it
     if sync_code & 0b0000_0000_0000_0010 != 0 {
-        return errors::unsupported_error("invalid frame header, encountered reserved value");
+        if !lenient {
+            return errors::unsupported_error("invalid frame header, encountered reserved value");
+        }
+        reserved = Some("the reserved bit after the sync code is set");
     }
 
     // The final bit determines the blocking strategy.
@@ -248,9 +402,18 @@ fn read_frame_header<R: ReadBuffer>(
     let mut read_bs_last = 0u8;
 
     match bs_sr >> 4 {
-        0b0000 => {
-            return errors::unsupported_error("invalid frame header, encountered reserved value")
-        }
+        0b0000 => match audio_info.flac_stream_info {
+            // the block size of a stream of fixed size blocks
+            Some(ref stream_info) if lenient => {
+                block_size = stream_info.max_block_size;
+                reserved = Some("the block size code is reserved");
+            }
+            _ => {
+                return errors::unsupported_error(
+                    "invalid frame header, encountered reserved value",
+                )
+            }
+        },
         0b0001 => block_size = 192,
         n if (0b0010..=0b0101).contains(&n) => block_size = 576 * (1 << (n - 2) as usize),
         0b0110 => read_bs_last = 1, // read 8 bit at end of header
@@ -278,6 +441,10 @@ fn read_frame_header<R: ReadBuffer>(
         0b1100 => read_sr_last = 1, // Read 8bit sample rate from end of header.
         0b1101 => read_sr_last = 2, // Read 16bit sample rate from end of header.
         0b1110 => read_sr_last = 3, // Read 16bit sample rate in tens from end of header.
+        _ if lenient => {
+            sample_rate = audio_info.sample_rate;
+            reserved = Some("the sample rate code is reserved");
+        }
         _ => return errors::parse_error("invalid frame header"),
     }
 
@@ -292,7 +459,8 @@ fn read_frame_header<R: ReadBuffer>(
         _ => return errors::unsupported_error("invalid frame header, encountered reserved value"),
     };
     // The next three bits indicate bits per sample.
-    let bps = match (ch_bps_r & 0b0000_1110) >> 1 {
+    let bps_code = (ch_bps_r & 0b0000_1110) >> 1;
+    let bps = match bps_code {
         0b000 => audio_info.bits_per_sample,
         0b001 => 8,
         0b010 => 12,
@@ -304,7 +472,10 @@ fn read_frame_header<R: ReadBuffer>(
 
     // The last bit is reserved and should have value 0 .
     if ch_bps_r & 0b0000_0001 != 0 {
-        return errors::unsupported_error("invalid frame header, encountered reserved value");
+        if !lenient {
+            return errors::unsupported_error("invalid frame header, encountered reserved value");
+        }
+        reserved = Some("the reserved bit after the bits per sample is set");
     }
 
     let block_type = match blocking_strategy {
@@ -336,7 +507,7 @@ fn read_frame_header<R: ReadBuffer>(
     // read the 8bit crc and match it with computed crc
     let crc_computed = crc_reader.crc();
     if crc_computed != crc_reader.get_input().read_u8()? {
-        return errors::parse_error("CRC match failed, Invalid frame");
+        return errors::parse_error(HEADER_CRC_MISMATCH);
     }
 
     Ok(FrameHeader {
@@ -345,9 +516,164 @@ fn read_frame_header<R: ReadBuffer>(
         sample_rate,
         channel_type,
         bits_per_sample: bps,
+        reserved,
+        sample_rate_from_stream_info: bs_sr & 0b00001111 == 0,
+        bits_per_sample_from_stream_info: bps_code == 0,
     })
 }
 
+/// FLAC frames hold at most 8 channels.
+const MAX_CHANNELS: usize = 8;
+
+/// The longest possible frame header including sync code and CRC-8.
+const MAX_FRAME_HEADER_LEN: usize = 16;
+
+/// Counts the samples of all frames from the current position to the end of the stream.
+///
+/// Only frame headers are parsed. As frames do not store their length, the stream
+/// is searched for sync codes, and a header only counts if its CRC-8 matches and
+/// it continues the frame or sample numbering. This rules out sync codes which
+/// happen to appear in the coded audio.
+///
+/// `lenient` accepts the headers with reserved values `decode_next_frame_lenient` decodes.
+pub fn scan_frames(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+) -> Result<u64> {
+    walk_frames(input, audio_info, lenient, |_, _| {})
+}
+
+/// Returns the offset and the first inter-channel sample of each frame from the current position
+///
+/// The frames are found like `scan_frames` finds them, the samples are counted
+/// from the first frame found.
+pub fn index_frames(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+) -> Result<Vec<(u64, u64)>> {
+    let mut frames = Vec::new();
+    walk_frames(input, audio_info, lenient, |offset, sample| {
+        frames.push((offset, sample))
+    })?;
+    Ok(frames)
+}
+
+/// `scan_frames`, calling `on_frame` with the offset and first sample of every frame counted
+fn walk_frames<F: FnMut(u64, u64)>(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+    mut on_frame: F,
+) -> Result<u64> {
+    let mut frames = 0u64;
+    let mut samples = 0u64;
+    // frame or sample number of the first frame, streams may be cut
+    let mut first_number = None;
+
+    while let Some(frame_header) = find_frame_header(input, audio_info, lenient)? {
+        let (number, expected) = match frame_header.block_type {
+            BlockType::FrameNumber(fno) => (fno as u64, frames),
+            BlockType::SampleNumber(sno) => (sno, samples),
+        };
+        let first_number = *first_number.get_or_insert(number);
+        if number == first_number + expected {
+            on_frame(input.position(), samples);
+            frames += 1;
+            samples += frame_header.block_size as u64;
+            input.skip_bytes(2)?;
+        } else {
+            input.skip_bytes(1)?;
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Skips to the next frame, after a corrupt one
+///
+/// `input` is left at the next sync code followed by a header whose CRC-8
+/// matches. Returns false if there is none until the end of the stream.
+pub fn skip_to_next_frame(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+) -> Result<bool> {
+    Ok(find_frame_header(input, audio_info, lenient)?.is_some())
+}
+
+/// Searches the stream for a sync code followed by a valid frame header
+///
+/// `input` is left at the sync code, the header is only peeked at.
+fn find_frame_header(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+) -> Result<Option<FrameHeader>> {
+    loop {
+        // skip ahead to the next candidate sync code
+        let window = input.peek(4096)?;
+        if window.len() < 2 {
+            return Ok(None);
+        }
+        match (0..window.len() - 1).find(|&i| window[i] == 0xff && window[i + 1] & 0xfe == 0xf8) {
+            Some(i) => input.skip_bytes(i)?,
+            None => {
+                // the last byte may be the start of a sync code
+                let n = window.len() - 1;
+                input.skip_bytes(n)?;
+                continue;
+            }
+        }
+
+        if let Some(frame_header) = peek_frame_header(input, audio_info, lenient)? {
+            return Ok(Some(frame_header));
+        }
+        input.skip_bytes(1)?;
+    }
+}
+
+/// Returns the header of the frame `input` is at, without consuming it
+///
+/// `None` if there is no valid header, e.g. one failing its CRC-8.
+fn peek_frame_header(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+) -> Result<Option<FrameHeader>> {
+    let mut header = [0u8; MAX_FRAME_HEADER_LEN];
+    let window = input.peek(MAX_FRAME_HEADER_LEN)?;
+    header[..window.len()].copy_from_slice(window);
+    let mut header = &header[..window.len()];
+
+    let mut crc16reader = Crc16Reader::new(&mut header);
+    let mut crc8reader = Crc8Reader::new(&mut crc16reader);
+    let frame_header = crc8reader
+        .read_be_u16()
+        .and_then(|sync_code| read_frame_header(&mut crc8reader, audio_info, sync_code, lenient));
+    Ok(frame_header.ok())
+}
+
+/// Returns if the header of the frame `input` is at leaves the sample rate and
+/// the bits per sample to STREAMINFO, without consuming it
+///
+/// `None` if there is no valid header, decoding the frame tells why.
+pub(crate) fn peek_stream_info_fields(
+    input: &mut AudioInputStream,
+    audio_info: &audio::AudioInfo,
+    lenient: bool,
+) -> Result<Option<(bool, bool)>> {
+    Ok(
+        peek_frame_header(input, audio_info, lenient)?.map(|header| {
+            (
+                header.sample_rate_from_stream_info,
+                header.bits_per_sample_from_stream_info,
+            )
+        }),
+    )
+}
+
 // fix current buffer capacity to accommodate total samples for this block
 fn correct_buffer_len(mut buffer: Vec<i32>, new_len: usize) -> Vec<i32> {
     if buffer.len() != new_len {
@@ -360,11 +686,17 @@ fn correct_buffer_len(mut buffer: Vec<i32>, new_len: usize) -> Vec<i32> {
     buffer
 }
 
+/// Decodes a subframe into buffer, if `reconstruct` is false the subframe is
+/// only consumed and buffer is left with garbage.
+///
+/// Returns the number of trailing zero bits of every decoded sample.
 fn decode_subframe<R: ReadBuffer>(
     bitstream: &mut BitStream<R>,
     bps: u32,
     buffer: &mut [i32],
-) -> Result<()> {
+    reconstruct: bool,
+    stats: Option<&mut FrameStats>,
+) -> Result<u32> {
     // read the padding bit
     if bitstream.read_bit()? {
         return errors::parse_error("subframe sync code incorrect");
@@ -400,38 +732,181 @@ fn decode_subframe<R: ReadBuffer>(
     } else {
         0
     };
-    if wasted_bps > bps {
+    if wasted_bps >= bps {
         return errors::parse_error("subframe has no non-wasted bits");
     }
 
     let sf_bps = bps - wasted_bps;
 
+    let mut sf_stats = stats.as_ref().map(|_| {
+        let kind = match subframe_type {
+            SubFrameType::Constant => SubframeKind::Constant,
+            SubFrameType::Verbatim => SubframeKind::Verbatim,
+            SubFrameType::FixedLinear(order) => SubframeKind::Fixed(order),
+            SubFrameType::Lpc(order) => SubframeKind::Lpc(order),
+        };
+        SubframeStats::new(kind, wasted_bps)
+    });
+
+    // the value of a constant subframe may have more zero bits than signalled
+    let mut zero_bits = wasted_bps;
     match subframe_type {
-        SubFrameType::Constant => decoder::decode_constant::<R>(bitstream, sf_bps, buffer)?,
-        SubFrameType::Verbatim => decoder::decode_verbatim::<R>(bitstream, sf_bps, buffer)?,
-        SubFrameType::FixedLinear(order) => {
-            decoder::decode_fixed_linear::<R>(bitstream, sf_bps, order as usize, buffer)?
-        }
-        SubFrameType::Lpc(order) => {
-            decoder::decode_lpc::<R>(bitstream, sf_bps, order as usize, buffer)?
+        SubFrameType::Constant => {
+            decoder::decode_constant::<R>(bitstream, sf_bps, buffer)?;
+            if let Some(value) = buffer.first() {
+                zero_bits = cmp::min(bps, wasted_bps + value.trailing_zeros());
+            }
         }
+        SubFrameType::Verbatim => decoder::decode_verbatim::<R>(bitstream, sf_bps, buffer)?,
+        SubFrameType::FixedLinear(order) => decoder::decode_fixed_linear::<R>(
+            bitstream,
+            sf_bps,
+            order as usize,
+            buffer,
+            reconstruct,
+            sf_stats.as_mut(),
+        )?,
+        SubFrameType::Lpc(order) => decoder::decode_lpc::<R>(
+            bitstream,
+            sf_bps,
+            order as usize,
+            buffer,
+            reconstruct,
+            sf_stats.as_mut(),
+        )?,
     }
 
-    if wasted_bps > 0 {
+    if let (Some(stats), Some(sf_stats)) = (stats, sf_stats) {
+        stats.subframes.push(sf_stats);
+    }
+
+    if wasted_bps > 0 && reconstruct {
         for s in buffer {
             // make a no panic left shift i.e *s = s << wasted_bps
             *s = s.wrapping_shl(wasted_bps);
         }
     }
 
-    Ok(())
+    Ok(zero_bits)
 }
 
-pub fn decode_next_frame<R: ReadBuffer>(
+/// Decodes the next frame of a FLAC stream
+///
+/// `input` must be positioned at the sync code of a frame, i.e. after the
+/// metadata blocks or right after the previous frame. `audio_info` provides the
+/// values which frame headers may defer to STREAMINFO, and `block_buffer` is
+/// reused for the samples if it is large enough, pass `Block::into_buffer` of
+/// the previous block to avoid allocations.
+///
+/// Only the channels set in `channel_mask` are reconstructed for frames with
+/// independent channels, the samples of the others are garbage. Use
+/// `ALL_CHANNELS` to decode every channel. A mask of 0 reconstructs no channel,
+/// also not of stereo frames, which only reads the frame and checks its CRCs.
+/// If `stats` is set, it is filled with the statistics of the frame.
+///
+/// Returns `None` at the end of the stream. A malformed frame, e.g. one failing
+/// its CRC, gives `Error::CorruptFrame` with the offset of its sync code, and
+/// `input` is returned to right after the sync code, so that the next frame
+/// can be searched from there. After any other error the position of `input`
+/// is undefined.
+pub fn decode_next_frame<R: Rewind>(
     input: &mut R,
-    mut block_buffer: Vec<i32>,
+    block_buffer: Vec<i32>,
+    audio_info: &audio::AudioInfo,
+    channel_mask: u8,
+    stats: Option<&mut FrameStats>,
+) -> Option<Result<Block>> {
+    next_frame(input, block_buffer, audio_info, channel_mask, stats, false)
+}
+
+/// Same as `decode_next_frame`, but decodes frames whose headers hold reserved values
+///
+/// Like the reference decoder, the reserved bits after the sync code and
+/// after the bits per sample are ignored, and the reserved block size and
+/// sample rate codes stand for the ones of STREAMINFO. Such frames are logged
+/// as a warning. Reserved channel assignments and bits per sample still fail
+/// with `Error::Unsupported`.
+pub fn decode_next_frame_lenient<R: Rewind>(
+    input: &mut R,
+    block_buffer: Vec<i32>,
     audio_info: &audio::AudioInfo,
+    channel_mask: u8,
+    stats: Option<&mut FrameStats>,
 ) -> Option<Result<Block>> {
+    next_frame(input, block_buffer, audio_info, channel_mask, stats, true)
+}
+
+/// Decodes the next frame like `decode_next_frame`, or `decode_next_frame_lenient` if `lenient`
+pub(crate) fn next_frame<R: Rewind>(
+    input: &mut R,
+    block_buffer: Vec<i32>,
+    audio_info: &audio::AudioInfo,
+    channel_mask: u8,
+    stats: Option<&mut FrameStats>,
+    lenient: bool,
+) -> Option<Result<Block>> {
+    let block = next_frame_checked(
+        input,
+        block_buffer,
+        audio_info,
+        channel_mask,
+        stats,
+        lenient,
+        |_, _| Ok(true),
+    );
+    block.map(|result| result.map(|block| block.expect("no frame is skipped")))
+}
+
+/// Decodes the next frame like `next_frame`, if `check` accepts the parameters its header declares
+///
+/// `check` is called with the parameters and block size of the parsed header
+/// before any subframe is read, so that e.g. a frame of more channels than
+/// expected is not decoded into a buffer of them. Gives `Ok(None)` if `check`
+/// returns false, `input` is then right after the header of the skipped frame.
+pub(crate) fn next_frame_checked<R: Rewind, F>(
+    input: &mut R,
+    block_buffer: Vec<i32>,
+    audio_info: &audio::AudioInfo,
+    channel_mask: u8,
+    stats: Option<&mut FrameStats>,
+    lenient: bool,
+    check: F,
+) -> Option<Result<Option<Block>>>
+where
+    F: FnOnce(&errors::StreamParameters, u32) -> Result<bool>,
+{
+    let offset = input.position();
+    match decode_frame(
+        input,
+        block_buffer,
+        audio_info,
+        channel_mask,
+        stats,
+        lenient,
+        check,
+    )? {
+        Err(errors::Error::ParseError(reason)) => {
+            // the sync code is 2 bytes
+            otry!(input.rewind(offset + 2));
+            Some(Err(errors::Error::CorruptFrame { offset, reason }))
+        }
+        result => Some(result),
+    }
+}
+
+/// Decodes the next frame like `next_frame_checked`, from any input
+fn decode_frame<R: ReadBuffer, F>(
+    input: &mut R,
+    mut block_buffer: Vec<i32>,
+    audio_info: &audio::AudioInfo,
+    channel_mask: u8,
+    mut stats: Option<&mut FrameStats>,
+    lenient: bool,
+    check: F,
+) -> Option<Result<Option<Block>>>
+where
+    F: FnOnce(&errors::StreamParameters, u32) -> Result<bool>,
+{
     // create crc16 reader
     let mut crc16reader = Crc16Reader::new(input);
 
@@ -440,9 +915,31 @@ pub fn decode_next_frame<R: ReadBuffer>(
     let mut crc8reader = Crc8Reader::new(&mut crc16reader);
     let sync_code = match crc8reader.read_be_u16() {
         Ok(sync_code) => sync_code,
-        Err(_) => return None,
+        Err(errors::Error::IoError(ref error))
+            if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+        {
+            return None
+        }
+        // e.g. a timeout of a network-backed input, which may be retried
+        Err(error) => return Some(Err(error)),
+    };
+    let frame_header = otry!(read_frame_header(
+        &mut crc8reader,
+        audio_info,
+        sync_code,
+        lenient
+    ));
+    if let Some(reserved) = frame_header.reserved {
+        tracing::warn!("FLAC frame header: {}, decoding the frame anyway", reserved);
+    }
+    let parameters = errors::StreamParameters {
+        sample_rate: frame_header.sample_rate,
+        channels: frame_header.number_channels() as u32,
+        bits_per_sample: frame_header.bits_per_sample,
     };
-    let frame_header = otry!(read_frame_header(&mut crc8reader, audio_info, sync_code));
+    if !otry!(check(&parameters, frame_header.block_size as u32)) {
+        return Some(Ok(None));
+    }
 
     let bs = frame_header.block_size as usize;
     let total_samples = frame_header.number_channels() as usize * bs;
@@ -452,82 +949,206 @@ pub fn decode_next_frame<R: ReadBuffer>(
     let mut bitstream = BitStream::new(&mut crc16reader);
 
     // decode subframes and fill buffer
+    let mut wasted_bits = [0; MAX_CHANNELS];
+    let reconstruct = channel_mask != 0;
     match frame_header.channel_type {
         ChannelType::Independent(n_ch) => {
+            // Only independent channels can be reconstructed on their own, the
+            // stereo decorrelation below needs both channels.
             for ch in 0..n_ch as usize {
-                otry!(decode_subframe(
+                wasted_bits[ch] = otry!(decode_subframe(
                     &mut bitstream,
                     frame_header.bits_per_sample,
-                    &mut block_buffer[ch * bs..(ch + 1) * bs]
+                    &mut block_buffer[ch * bs..(ch + 1) * bs],
+                    channel_mask & (1 << ch) != 0,
+                    stats.as_deref_mut()
                 ));
             }
         }
         ChannelType::LeftSideStereo => {
             // The side channel has one extra bit per sample.
-            otry!(decode_subframe(
+            let first = otry!(decode_subframe(
                 &mut bitstream,
                 frame_header.bits_per_sample,
-                &mut block_buffer[..bs]
+                &mut block_buffer[..bs],
+                reconstruct,
+                stats.as_deref_mut()
             ));
-            otry!(decode_subframe(
+            let second = otry!(decode_subframe(
                 &mut bitstream,
                 frame_header.bits_per_sample + 1,
-                &mut block_buffer[bs..bs * 2]
+                &mut block_buffer[bs..bs * 2],
+                reconstruct,
+                stats.as_deref_mut()
             ));
 
             // Then decode the side channel into the right channel.
-            decode_left_side(&mut block_buffer[..bs * 2]);
+            if reconstruct {
+                decode_left_side(&mut block_buffer[..bs * 2]);
+            }
+            // right = left - side keeps the zero bits both have
+            wasted_bits[0] = first;
+            wasted_bits[1] = cmp::min(first, second);
         }
         ChannelType::RightSideStereo => {
             // The side channel has one extra bit per sample.
-            otry!(decode_subframe(
+            let first = otry!(decode_subframe(
                 &mut bitstream,
                 frame_header.bits_per_sample + 1,
-                &mut block_buffer[..bs]
+                &mut block_buffer[..bs],
+                reconstruct,
+                stats.as_deref_mut()
             ));
-            otry!(decode_subframe(
+            let second = otry!(decode_subframe(
                 &mut bitstream,
                 frame_header.bits_per_sample,
-                &mut block_buffer[bs..bs * 2]
+                &mut block_buffer[bs..bs * 2],
+                reconstruct,
+                stats.as_deref_mut()
             ));
 
             // Then decode the side channel into the left channel.
-            decode_right_side(&mut block_buffer[..bs * 2]);
+            if reconstruct {
+                decode_right_side(&mut block_buffer[..bs * 2]);
+            }
+            wasted_bits[0] = cmp::min(first, second);
+            wasted_bits[1] = second;
         }
         ChannelType::MidSideStereo => {
             // Decode mid as the first channel, then side with one
             // extra bits per sample.
-            otry!(decode_subframe(
+            let first = otry!(decode_subframe(
                 &mut bitstream,
                 frame_header.bits_per_sample,
-                &mut block_buffer[..bs]
+                &mut block_buffer[..bs],
+                reconstruct,
+                stats.as_deref_mut()
             ));
-            otry!(decode_subframe(
+            let second = otry!(decode_subframe(
                 &mut bitstream,
                 frame_header.bits_per_sample + 1,
-                &mut block_buffer[bs..bs * 2]
+                &mut block_buffer[bs..bs * 2],
+                reconstruct,
+                stats.as_deref_mut()
             ));
 
             // Then decode mid-side channel into left-right.
-            decode_mid_side(&mut block_buffer[..bs * 2]);
+            if reconstruct {
+                decode_mid_side(&mut block_buffer[..bs * 2]);
+            }
+            // (2 * mid + side) / 2 loses one zero bit of the side channel
+            let zero_bits = cmp::min(first, second.saturating_sub(1));
+            wasted_bits[0] = zero_bits;
+            wasted_bits[1] = zero_bits;
         }
     }
 
     // check crc-16
     // match calculated crc == encoded crc
     if crc16reader.crc() != otry!(crc16reader.read_be_u16()) {
-        return Some(errors::parse_error("frame CRC mismatch"));
+        return Some(errors::parse_error(FRAME_CRC_MISMATCH));
     }
 
+    // For fixed blocking all frames but the last one have the nominal block size,
+    // which STREAMINFO stores as the max block size. Using the size of the frame
+    // itself would misplace a short last frame.
     let frame_fsi = match frame_header.block_type {
-        BlockType::FrameNumber(fno) => frame_header.block_size as u64 * fno as u64,
+        BlockType::FrameNumber(fno) => {
+            let nominal_block_size = match audio_info.flac_stream_info {
+                Some(ref stream_info) => stream_info.max_block_size,
+                None => frame_header.block_size,
+            };
+            nominal_block_size as u64 * fno as u64
+        }
         BlockType::SampleNumber(sno) => sno,
     };
 
-    Some(Ok(Block::new(
+    if let Some(stats) = stats {
+        stats.first_sample_index = frame_fsi;
+        stats.block_size = frame_header.block_size as u32;
+        stats.effective_bits_per_sample = wasted_bits[..frame_header.number_channels() as usize]
+            .iter()
+            .map(|w| frame_header.bits_per_sample - w)
+            .collect();
+    }
+
+    Some(Ok(Some(Block::new(
         frame_fsi,
         frame_header.block_size as u32,
         frame_header.bits_per_sample,
+        frame_header.sample_rate,
         block_buffer,
-    )))
+        wasted_bits,
+    ))))
+}
+
+#[test]
+fn test_block_accessors() {
+    let block = Block::new(
+        0,
+        3,
+        16,
+        44_100,
+        vec![1, 2, 3, -1, -2, -3],
+        [0; MAX_CHANNELS],
+    );
+    assert_eq!(block.channel(0), &[1, 2, 3]);
+    assert_eq!(block.channel(1), &[-1, -2, -3]);
+    assert_eq!(block.try_get_sample(1, 2), Some(-3));
+    assert_eq!(block.try_get_sample(0, 3), None);
+    assert_eq!(block.try_get_sample(2, 0), None);
+    assert_eq!(Block::empty().try_get_sample(0, 0), None);
+    assert!(std::panic::catch_unwind(|| block.channel(2).len()).is_err());
+}
+
+#[test]
+fn test_reserved_header_values() {
+    use crate::testing::{FlacStream, FrameSpec};
+    type Edit = fn(&mut Vec<u8>);
+
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream.frame(&[(0..16).collect()]);
+    let info = crate::audio::AudioSegment::from_reader(
+        std::io::Cursor::new(stream.build()),
+        crate::codecs::FormatFlag::FLAC,
+    )
+    .unwrap()
+    .info()
+    .clone();
+    let spec = FrameSpec::verbatim(0, 16, &[(0..16).collect()]);
+    let decode = |edit: Edit, lenient| {
+        let frame = spec.encode_with_header(edit);
+        let mut input = std::io::Cursor::new(frame);
+        next_frame(&mut input, Vec::new(), &info, ALL_CHANNELS, None, lenient).unwrap()
+    };
+
+    // the reserved bit after the sync code, and the one after the bits per sample
+    let sync_bit: Edit = |header| header[1] |= 0b10;
+    let bps_bit: Edit = |header| header[3] |= 0b1;
+    // the reserved sample rate code, the rate of STREAMINFO is taken
+    let sample_rate: Edit = |header| header[2] |= 0b1111;
+    // the reserved block size code, without the 16 bit block size at the end
+    let block_size: Edit = |header| {
+        header[2] &= 0b1111;
+        header.truncate(header.len() - 2);
+    };
+    let edits: [(Edit, bool); 4] = [
+        (sync_bit, false),
+        (bps_bit, false),
+        (sample_rate, true),
+        (block_size, false),
+    ];
+    for (edit, corrupt) in edits.iter() {
+        match decode(*edit, false) {
+            Err(errors::Error::Unsupported(_)) if !corrupt => {}
+            Err(errors::Error::CorruptFrame { .. }) if *corrupt => {}
+            other => panic!(
+                "expected the strict mode to fail, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+        let block = decode(*edit, true).unwrap();
+        assert_eq!(block.sample_rate(), 44_100);
+        assert_eq!(block.channel(0), &(0..16).collect::<Vec<i32>>()[..]);
+    }
 }
@@ -1,28 +1,133 @@
 mod decoder;
-mod frame;
+pub(crate) mod frame;
+mod seek_index;
+pub(crate) mod write;
 
-use super::io::{AudioInputStream, AudioReader, AudioSamplesIterator, ReadBuffer, Sample};
+use super::audio::Mismatch;
+use super::errors::StreamParameters;
+use super::format::{DecodeContext, Format, SampleType, TypedSamples};
+use super::io::{
+    self, AudioInputStream, AudioReader, AudioSamplesIterator, ReadBuffer, ResettableDecoder,
+    Rewind, Sample,
+};
+use super::md5::Md5;
+use super::pictures::{self, PictureContainer, PictureSource};
+use super::stats::{
+    DecodeStats, FrameStats, InstrumentationHandle, SampleGap, SubframeKind, SubsetParameter,
+    SubsetViolation, VerifyReport,
+};
 use super::{audio, codecs, errors, Result};
+use std::time::Instant;
 
 const FLAC_MARKER: &[u8; 4] = b"fLaC";
 
+/// The FLAC format, channels which are not selected are not reconstructed
+pub struct FlacFormat;
+
+impl Format for FlacFormat {
+    fn flag(&self) -> codecs::FormatFlag {
+        codecs::FormatFlag::FLAC
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["flac"]
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        header.starts_with(FLAC_MARKER)
+    }
+
+    fn matches_embedded(&self, bytes: &[u8]) -> bool {
+        // the first metadata block is always the 34 bytes of STREAMINFO
+        bytes.len() >= 8
+            && bytes.starts_with(FLAC_MARKER)
+            && bytes[4] & 0x7f == 0
+            && bytes[5..8] == [0, 0, 34]
+    }
+
+    fn create_reader(
+        &self,
+        input: AudioInputStream,
+        options: audio::DecodeOptions,
+    ) -> Result<Box<dyn AudioReader>> {
+        Ok(FlacReader::new(input, options)?)
+    }
+
+    fn samples<'a>(
+        &self,
+        context: DecodeContext<'a>,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>> {
+        Ok(typed_samples!(sample_type, S => FlacSamplesIterator::<S>::new(context)))
+    }
+
+    fn selects_channels(&self) -> bool {
+        true
+    }
+}
+
 pub struct FlacReader {
     reader: AudioInputStream,
-    block_size: (u16, u16),
-    frame_size: (u32, u32),
-    md5: [u8; 16],
+    /// set once `read_header` was called
+    header_read: bool,
+    options: audio::DecodeOptions,
+    /// position of the first frame
+    frames_start: u64,
+    /// the metadata blocks skipped by the header
+    skipped: Vec<audio::SkippedMetadata>,
+    /// the APPLICATION blocks of the header
+    applications: Vec<audio::ApplicationBlock>,
+    /// the PICTURE blocks of the header
+    pictures: Vec<PictureSource>,
+    /// the metadata blocks as stored, if `DecodeOptions::keep_raw_headers` is set
+    raw_headers: Option<audio::RawHeaders>,
+    /// the frames of the stream, once built or loaded
+    seek_index: Option<seek_index::SeekIndex>,
 }
 
 impl FlacReader {
-    pub fn new(reader: AudioInputStream) -> Result<Box<Self>> {
+    pub fn new(reader: AudioInputStream, options: audio::DecodeOptions) -> Result<Box<Self>> {
         Ok(Box::new(FlacReader {
             reader,
-            block_size: (0, 0),
-            frame_size: (0, 0),
-            md5: [0u8; 16],
+            header_read: false,
+            options,
+            frames_start: 0,
+            skipped: Vec::new(),
+            applications: Vec::new(),
+            pictures: Vec::new(),
+            raw_headers: if options.keep_raw_headers {
+                Some(audio::RawHeaders::default())
+            } else {
+                None
+            },
+            seek_index: None,
         }))
     }
 
+    /// Returns what a seek index of this stream is valid for, the position is kept
+    fn fingerprint(&mut self, info: &audio::AudioInfo) -> Result<seek_index::Fingerprint> {
+        let position = self.reader.position();
+        let input_len = self.reader.seek(std::io::SeekFrom::End(0))?;
+        self.reader.seek(std::io::SeekFrom::Start(position))?;
+        Ok(seek_index::Fingerprint::new(
+            info,
+            input_len,
+            self.frames_start,
+        ))
+    }
+
+    // https://xiph.org/flac/format.html#metadata_block_application
+    fn read_application(&mut self, length: u32) -> Result<()> {
+        if length < 4 {
+            return errors::parse_error("application block is shorter than its id");
+        }
+        let mut id = [0u8; 4];
+        self.reader.read_into(&mut id)?;
+        let data = self.reader.read_bytes(length as usize - 4)?;
+        self.applications.push(audio::ApplicationBlock { id, data });
+        Ok(())
+    }
+
     // https://xiph.org/flac/format.html#metadata_block_streaminfo
     fn read_stream_info(&mut self, length: u32) -> Result<audio::AudioInfo> {
         if length != 34 {
@@ -30,19 +135,26 @@ impl FlacReader {
         }
 
         // read block size
-        // min block size should be 16 and must not be greater than max block size
-        self.block_size = (self.reader.read_be_u16()?, self.reader.read_be_u16()?);
-        if self.block_size.0 < 16 {
+        // min block size should be 16 and must not be greater than max block size.
+        // The max block size is stored in 16 bits, so it can never exceed the
+        // maximum of 65535 allowed by the spec.
+        //
+        // The last block may be shorter than 16 samples, so a stream made of a
+        // single short block has equal min and max block sizes below 16.
+        let min_block_size = self.reader.read_be_u16()?;
+        let max_block_size = self.reader.read_be_u16()?;
+        if min_block_size == 0 || (min_block_size < 16 && min_block_size != max_block_size) {
             return errors::parse_error("block size must be at least 16");
         }
-        if self.block_size.0 > self.block_size.1 {
+        if min_block_size > max_block_size {
             return errors::parse_error("inconsistent block size, min block size > max block size");
         }
 
         // read frame size
         // if frame size values are known min frame size should not be greater than max frame size
-        self.frame_size = (self.reader.read_be_u24()?, self.reader.read_be_u24()?);
-        if self.frame_size.0 > 0 && self.frame_size.1 > 0 && self.frame_size.1 < self.frame_size.0 {
+        let min_frame_size = self.reader.read_be_u24()?;
+        let max_frame_size = self.reader.read_be_u24()?;
+        if min_frame_size > 0 && max_frame_size > 0 && max_frame_size < min_frame_size {
             return errors::parse_error("inconsistent frame size, max frame size < min frame size");
         }
 
@@ -53,16 +165,17 @@ impl FlacReader {
         // Make the value from the first 16 bits, and then the
         // 4 most significant bits of the next byte
         let sample_rate = (sample_rate_msb as u32) << 4 | (sample_rate_lsb as u32) >> 4;
-        if sample_rate == 0 || sample_rate > 655350 {
+        audio::check_sample_rate(sample_rate)?;
+        if sample_rate > 655350 {
             return errors::parse_error("sampling rate must be less than 655350");
         }
 
         // no of channels [3 bits]
         let no_channels = ((sample_rate_lsb >> 1) & 0b0000_0111) + 1;
-        if !(1..=8).contains(&no_channels) {
-            return errors::parse_error("number of channels must be between 1 and 8");
-        }
-        let channel_layout = num_channels_to_channel_layout(no_channels);
+        let channel_layout = match audio::ChannelLayout::of_count(no_channels as usize) {
+            Some(channel_layout) => channel_layout,
+            None => return errors::parse_error("number of channels must be between 1 and 8"),
+        };
 
         // read bits per sample [5 bits]
         let bps_bits = self.reader.read_u8()?;
@@ -76,29 +189,65 @@ impl FlacReader {
             ((bps_bits & 0b0000_1111) as u64) << 32 | (self.reader.read_be_u32()? as u64);
 
         // read md5 signature [128 bits or 16 bytes]
-        self.reader.read_into(&mut self.md5)?;
+        let mut md5 = [0u8; 16];
+        self.reader.read_into(&mut md5)?;
 
         Ok(audio::AudioInfo {
             codec_type: codecs::CodecType::CODEC_TYPE_FLAC,
             sample_rate,
             total_samples: total_frames * no_channels as u64,
             bits_per_sample: bits_per_sample as u32,
+            container_bits: 0,
+            sample_format: codecs::SampleFormat::of_codec(
+                codecs::CodecType::CODEC_TYPE_FLAC,
+                bits_per_sample as u32,
+            ),
             channels: channel_layout.into_channels(),
             channel_layout,
+            flac_stream_info: Some(audio::FlacStreamInfo {
+                min_block_size,
+                max_block_size,
+                min_frame_size,
+                max_frame_size,
+                md5,
+            }),
+            encoder_delay: None,
+            encoder_padding: None,
         })
     }
 }
 
 impl AudioReader for FlacReader {
     fn read_header(&mut self) -> Result<audio::AudioInfo> {
-        if FLAC_MARKER != &(self.reader.read_bytes(4)?)[..] {
-            return errors::parse_error("no fLaC tag Found");
+        if self.header_read {
+            return errors::unsupported_error("header has already been read");
         }
+        self.header_read = true;
+
+        // The marker is only peeked at, so that a different reader can still be
+        // tried on the same input if it is missing.
+        if &FLAC_MARKER[..] != self.reader.peek(4)? {
+            return errors::missing_magic_error("no fLaC tag Found");
+        }
+        let start = self.reader.position();
+        self.reader.skip_bytes(4)?;
 
         let mut is_last = false;
-        let mut info = errors::parse_error::<audio::AudioInfo>("no stream_info block found");
+        let mut info = None;
+        let mut blocks = 0u32;
 
         while !is_last {
+            blocks += 1;
+            if blocks > self.options.max_header_chunks {
+                return errors::parse_error(
+                    "header has more metadata blocks than max_header_chunks",
+                );
+            }
+            // an input holding nothing but the marker has no header to be cut short
+            if info.is_none() && self.reader.peek(1)?.is_empty() {
+                return errors::parse_error("no stream_info block found");
+            }
+            let offset = self.reader.position();
             let header_byte = self.reader.read_u8()?;
 
             // The first bit specifies whether this is the last block and
@@ -108,108 +257,2425 @@ impl AudioReader for FlacReader {
             let block_type = header_byte & 0x7f;
             let metadata_length = self.reader.read_be_u24()?;
 
+            // A corrupt length would otherwise make us skip up to 16 MB of the
+            // stream before failing.
+            if metadata_length > self.options.max_metadata_block_len {
+                return errors::parse_error("metadata block length exceeds the limit");
+            }
+            // checked before skipping the block, which may take long on unseekable input
+            let end = self.reader.position() + metadata_length as u64;
+            if end - start > self.options.max_header_len {
+                return errors::parse_error("header is longer than max_header_len");
+            }
+            if let Some(ref mut raw) = self.raw_headers {
+                let payload = if metadata_length <= audio::RawHeaders::MAX_PAYLOAD_LEN {
+                    Some(self.reader.peek(metadata_length as usize)?.to_vec())
+                } else {
+                    None
+                };
+                raw.metadata_blocks.push(audio::RawMetadataBlock {
+                    block_type,
+                    offset,
+                    len: metadata_length,
+                    payload,
+                });
+            }
+
             match block_type {
-                0 => info = self.read_stream_info(metadata_length),
-                127 => info = errors::parse_error("invalid metadata block"),
-                _ => self.reader.skip_bytes(metadata_length as usize)?,
+                0 => info = Some(self.read_stream_info(metadata_length)?),
+                2 => self.read_application(metadata_length)?,
+                // Type 127 is invalid, but once a good stream_info was read there is
+                // enough to decode the frames, so only reject it before that.
+                127 if info.is_none() => return errors::parse_error("invalid metadata block"),
+                _ => {
+                    self.skipped.push(audio::SkippedMetadata {
+                        block_type,
+                        offset,
+                        len: metadata_length,
+                    });
+                    let len = metadata_length as u64;
+                    let keep = block_type == 6
+                        && pictures::keep_payload(
+                            self.reader.is_seekable(),
+                            &self.pictures,
+                            len,
+                            self.options.max_buffered_pictures_len,
+                        );
+                    let payload = if keep {
+                        Some(self.reader.read_bytes(metadata_length as usize)?)
+                    } else {
+                        None
+                    };
+                    if block_type == 6 {
+                        self.pictures.push(PictureSource {
+                            container: PictureContainer::FlacPicture,
+                            offset: offset + 4,
+                            len,
+                            payload,
+                        });
+                    }
+                    if !keep {
+                        // PADDING may reserve megabytes, which are not worth reading
+                        self.reader.skip_ahead(len)?;
+                    }
+                }
             }
         }
 
-        info
+        self.frames_start = self.reader.position();
+        match info {
+            Some(info) => Ok(info),
+            None => errors::parse_error("no stream_info block found"),
+        }
     }
 
     fn buffer(&mut self) -> &mut AudioInputStream {
         &mut self.reader
     }
+
+    fn into_buffer(self: Box<Self>) -> AudioInputStream {
+        self.reader
+    }
+
+    fn scan_length(&mut self, info: &audio::AudioInfo) -> Result<audio::ScanResult> {
+        let samples =
+            frame::scan_frames(&mut self.reader, info, self.options.lenient_reserved_bits)?;
+        Ok(audio::ScanResult::new(samples, info.sample_rate))
+    }
+
+    fn verify(&mut self, info: &audio::AudioInfo) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let mut info = info.clone();
+        let mut signature = StreamSignature::new(&info);
+        let mut buffer = Vec::new();
+        let mut bytes = Vec::new();
+        loop {
+            match self.read_chained_header() {
+                Some(Ok(next)) => {
+                    signature.finish(&mut report);
+                    signature = StreamSignature::new(&next);
+                    info = next;
+                }
+                Some(Err(error)) => return Err(error),
+                None => {}
+            }
+            let block = frame::next_frame(
+                &mut self.reader,
+                buffer,
+                &info,
+                frame::ALL_CHANNELS,
+                None,
+                self.options.lenient_reserved_bits,
+            );
+            match block {
+                Some(Ok(block)) => {
+                    report.frames += 1;
+                    report.samples += block.total_samples() as u64;
+                    if block.sample_rate() != info.sample_rate
+                        || block.num_channels() != info.channels.count() as u32
+                        || block.bits_per_sample() != info.bits_per_sample
+                    {
+                        report.inconsistent_frames += 1;
+                    }
+                    signature.update(&block, &mut bytes);
+                    buffer = block.into_buffer();
+                }
+                Some(Err(errors::Error::CorruptFrame { reason, .. })) => {
+                    if reason == frame::HEADER_CRC_MISMATCH || reason == frame::FRAME_CRC_MISMATCH {
+                        report.crc_failures += 1;
+                    } else {
+                        report.malformed_frames += 1;
+                    }
+                    buffer = Vec::new();
+                    if !frame::skip_to_next_frame(
+                        &mut self.reader,
+                        &info,
+                        self.options.lenient_reserved_bits,
+                    )? {
+                        break;
+                    }
+                }
+                Some(Err(errors::Error::IoError(ref error)))
+                    if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    report.truncated = true;
+                    break;
+                }
+                Some(Err(error)) => return Err(error),
+                None => break,
+            }
+        }
+        signature.finish(&mut report);
+        Ok(report)
+    }
+
+    fn check_subset(&mut self, info: &audio::AudioInfo) -> Result<Vec<SubsetViolation>> {
+        let lenient = self.options.lenient_reserved_bits;
+        let mut violations = Vec::new();
+        let mut info = info.clone();
+        let mut buffer = Vec::new();
+        let mut frame = 0u64;
+        loop {
+            if let Some(next) = self.read_chained_header() {
+                info = next?;
+            }
+            let from_stream_info =
+                frame::peek_stream_info_fields(&mut self.reader, &info, lenient)?;
+            let mut stats = FrameStats::default();
+            // no channel is reconstructed, the subframes are only read
+            let block = match frame::next_frame(
+                &mut self.reader,
+                buffer,
+                &info,
+                0,
+                Some(&mut stats),
+                lenient,
+            ) {
+                Some(block) => block?,
+                None => break,
+            };
+            let sample_rate = block.sample_rate();
+            let (sample_rate_from_stream_info, bits_from_stream_info) =
+                from_stream_info.unwrap_or((false, false));
+            let mut parameters = subset_violations(&stats, sample_rate);
+            if sample_rate_from_stream_info {
+                parameters.push(SubsetParameter::SampleRateFromStreamInfo);
+            }
+            if bits_from_stream_info {
+                parameters.push(SubsetParameter::BitsPerSampleFromStreamInfo);
+            }
+            violations.extend(parameters.into_iter().map(|parameter| SubsetViolation {
+                frame,
+                sample_rate,
+                parameter,
+            }));
+            buffer = block.into_buffer();
+            frame += 1;
+        }
+        Ok(violations)
+    }
+
+    /// Seeks to the start of a frame, any other sample needs `seek_block`
+    fn seek_frame(&mut self, frame: u64, info: &audio::AudioInfo) -> Result<()> {
+        if self.seek_block(frame, info)? != frame {
+            return errors::unsupported_error("FLAC can only seek to the start of a frame");
+        }
+        Ok(())
+    }
+
+    /// Seeks to the start, or with a seek index to any frame
+    fn seek_block(&mut self, frame: u64, _info: &audio::AudioInfo) -> Result<u64> {
+        if frame == 0 {
+            self.reader
+                .seek(std::io::SeekFrom::Start(self.frames_start))?;
+            return Ok(0);
+        }
+        // without an index the frame containing a sample is not known
+        let point = match self.seek_index.as_ref().and_then(|index| index.find(frame)) {
+            Some(point) => point,
+            None => return errors::unsupported_error("FLAC can only seek to the start"),
+        };
+        self.reader.seek(std::io::SeekFrom::Start(point.offset))?;
+        // the fingerprint matched, but the file may still have been rewritten
+        let sync = self.reader.peek(2)?;
+        if sync[0] != 0xff || sync[1] & 0xfe != 0xf8 {
+            return errors::parse_error("no FLAC frame at the offset of the seek index");
+        }
+        Ok(point.sample)
+    }
+
+    fn build_seek_index(&mut self, info: &audio::AudioInfo) -> Result<()> {
+        let fingerprint = self.fingerprint(info)?;
+        let frames =
+            frame::index_frames(&mut self.reader, info, self.options.lenient_reserved_bits)?;
+        self.seek_index = Some(seek_index::SeekIndex::new(fingerprint, &frames));
+        Ok(())
+    }
+
+    fn export_seek_index(&self) -> Option<Vec<u8>> {
+        self.seek_index.as_ref().map(seek_index::SeekIndex::encode)
+    }
+
+    fn load_seek_index(&mut self, index: &[u8], info: &audio::AudioInfo) -> Result<()> {
+        let fingerprint = self.fingerprint(info)?;
+        self.seek_index = Some(seek_index::SeekIndex::decode(index, &fingerprint)?);
+        Ok(())
+    }
+
+    fn skipped_metadata(&self) -> Vec<audio::SkippedMetadata> {
+        self.skipped.clone()
+    }
+
+    fn application_blocks(&self) -> Vec<audio::ApplicationBlock> {
+        self.applications.clone()
+    }
+
+    fn picture_sources(&mut self) -> Result<Vec<PictureSource>> {
+        Ok(self.pictures.clone())
+    }
+
+    fn raw_headers(&self) -> Option<audio::RawHeaders> {
+        self.raw_headers.clone()
+    }
+
+    /// Reads the header of a FLAC stream appended to the current one
+    fn read_chained_header(&mut self) -> Option<Result<audio::AudioInfo>> {
+        match self.reader.peek(4) {
+            Ok(marker) if marker == &FLAC_MARKER[..] => {}
+            _ => return None,
+        }
+        // seeking to the start still goes to the first stream
+        let frames_start = self.frames_start;
+        self.header_read = false;
+        let info = self.read_header();
+        self.frames_start = frames_start;
+        Some(info)
+    }
 }
 
-fn num_channels_to_channel_layout(channels: u8) -> audio::ChannelLayout {
-    match channels {
-        1 => audio::ChannelLayout::Mono,
-        2 => audio::ChannelLayout::Stereo,
-        3 => audio::ChannelLayout::ThreePointZero,
-        4 => audio::ChannelLayout::Quad,
-        5 => audio::ChannelLayout::FivePointZero,
-        6 => audio::ChannelLayout::FivePointOne,
-        7 => audio::ChannelLayout::SixPointOneBack,
-        8 => audio::ChannelLayout::SevenPointOne,
-        _ => unreachable!(),
+/// Returns the parameters of a frame of `stats` outside of the streamable subset
+///
+/// The limits of block size and LPC order are tighter up to 48 kHz.
+fn subset_violations(stats: &FrameStats, sample_rate: u32) -> Vec<SubsetParameter> {
+    let is_low_rate = sample_rate <= 48_000;
+    let mut parameters = Vec::new();
+    let max_block_size = if is_low_rate { 4608 } else { 16384 };
+    if stats.block_size > max_block_size {
+        parameters.push(SubsetParameter::BlockSize(stats.block_size));
+    }
+    for (subframe, stats) in stats.subframes.iter().enumerate() {
+        match stats.kind {
+            SubframeKind::Lpc(order) if is_low_rate && order > 12 => {
+                parameters.push(SubsetParameter::LpcOrder { subframe, order })
+            }
+            _ => {}
+        }
+        match stats.partition_order {
+            Some(order) if order > 8 => {
+                parameters.push(SubsetParameter::PartitionOrder { subframe, order })
+            }
+            _ => {}
+        }
+    }
+    parameters
+}
+
+/// The MD5 signature of a stream, computed while verifying it
+struct StreamSignature {
+    /// the signature of STREAMINFO, `None` if it is not set
+    expected: Option<[u8; 16]>,
+    md5: Md5,
+}
+
+impl StreamSignature {
+    fn new(info: &audio::AudioInfo) -> StreamSignature {
+        let expected = info
+            .flac_stream_info
+            .as_ref()
+            .map(|stream_info| stream_info.md5)
+            .filter(|md5| md5 != &[0; 16]);
+        StreamSignature {
+            expected,
+            md5: Md5::new(),
+        }
+    }
+
+    /// Adds the samples of `block`, `bytes` is a buffer to reuse
+    fn update(&mut self, block: &frame::Block, bytes: &mut Vec<u8>) {
+        if self.expected.is_some() {
+            block.signature_bytes(bytes);
+            self.md5.update(bytes);
+        }
+    }
+
+    /// Compares the signature, a stream without one leaves the report as it is
+    fn finish(&mut self, report: &mut VerifyReport) {
+        if let Some(expected) = self.expected.take() {
+            let md5 = std::mem::take(&mut self.md5);
+            let matched = md5.finish() == expected;
+            report.md5_matched = Some(matched && report.md5_matched != Some(false));
+        }
     }
 }
 
 pub struct FlacSamplesIterator<'r, S: Sample + 'r> {
     reader: &'r mut Box<dyn AudioReader + 'static>,
-    audio_info: &'r audio::AudioInfo,
+    audio_info: audio::AudioInfo,
+    // copied from the header, so that only the reader and the statistics are borrowed
     current_block: frame::Block,
     samples_read: u32,
     current_channel: u32,
     has_failed: bool,
     // flag is set when decoder fails anywhere and buffer should return None
+    stats: Option<&'r mut Vec<FrameStats>>,
+    // collects the statistics of every decoded frame if set
+    decode_stats: Option<&'r mut DecodeStats>,
+    // sums up the statistics of the decoded frames if set
+    wasted_bits: &'r mut Vec<u32>,
+    // fewest wasted bits of each channel in the decoded frames
+    channel_mask: u8,
+    // bit n is set if channel n is returned
+    changed_info: Option<audio::AudioInfo>,
+    // parameters of the stream decoded now, if they differ from `audio_info`
+    stream_change: Option<audio::AudioInfo>,
+    // a change not yet taken by `take_stream_change`
+    sample_gaps: &'r mut Vec<SampleGap>,
+    // discontinuities of the sample numbers of the decoded frames
+    next_sample: u64,
+    // index of the inter-channel sample the next frame should start with
+    options: audio::DecodeOptions,
+    // `fill_gaps_with_silence` and the handling of frames disagreeing with STREAMINFO
+    frames: u64,
+    // number of frames decoded so far
+    mismatch_warned: bool,
+    // set once a frame disagreeing with STREAMINFO was warned about
+    silence: u64,
+    // number of silent inter-channel samples still to return before `pending_block`
+    pending_block: Option<frame::Block>,
+    // the frame following a gap which is filled
+    samples_returned: u64,
+    // number of samples returned by `next` and `read_planar`
+    good_position: audio::StreamPosition,
+    // the position after the last frame decoded in full
+    io_failed: bool,
+    // set if `has_failed` is for an IO error, which `resume_after_io_error` retries
+    instrumentation: Option<InstrumentationHandle>,
+    // counts the decoded frames and the time spent decoding them if set
     phantom: std::marker::PhantomData<S>,
 }
 
+/// largest block of silence returned at once while filling a gap
+const SILENCE_BLOCK_SIZE: u64 = 4096;
+
 impl<'r, S: Sample + 'r> FlacSamplesIterator<'r, S> {
-    pub fn new(
-        reader: &'r mut Box<dyn AudioReader + 'static>,
-        info: &'r audio::AudioInfo,
-    ) -> Box<dyn AudioSamplesIterator<S> + 'r> {
+    pub fn new(context: DecodeContext<'r>) -> Box<Self> {
+        let offset = context.reader.buffer().position();
         Box::new(FlacSamplesIterator::<S> {
-            reader,
-            audio_info: info,
+            reader: context.reader,
+            audio_info: context.info.clone(),
             current_block: frame::Block::empty(),
             samples_read: 0,
             current_channel: 0,
             has_failed: false,
+            stats: context.frame_stats,
+            decode_stats: context.decode_stats,
+            wasted_bits: context.wasted_bits,
+            channel_mask: context.channel_mask.unwrap_or(frame::ALL_CHANNELS),
+            changed_info: None,
+            stream_change: None,
+            sample_gaps: context.sample_gaps,
+            next_sample: 0,
+            options: context.options,
+            frames: 0,
+            mismatch_warned: false,
+            silence: 0,
+            pending_block: None,
+            samples_returned: 0,
+            good_position: audio::StreamPosition { frame: 0, offset },
+            io_failed: false,
+            instrumentation: context.instrumentation,
             phantom: std::marker::PhantomData,
         })
     }
 }
 
+impl<'r, S: Sample + 'r> FlacSamplesIterator<'r, S> {
+    /// Returns the parameters of the stream decoded now
+    fn info(&self) -> &audio::AudioInfo {
+        self.changed_info.as_ref().unwrap_or(&self.audio_info)
+    }
+
+    /// Continues decoding with the parameters of `info`
+    fn change_stream(&mut self, info: audio::AudioInfo) {
+        // the wasted bits of different channels or bit depths make no sense together
+        self.wasted_bits.clear();
+        self.changed_info = Some(info.clone());
+        self.stream_change = Some(info);
+    }
+
+    /// Replaces `current_block` with the next block of silence before `pending_block`
+    fn fill_gap(&mut self) {
+        let pending = self
+            .pending_block
+            .as_ref()
+            .expect("a gap is filled before a block");
+        let block_size = self.silence.min(SILENCE_BLOCK_SIZE);
+        let block = std::mem::replace(&mut self.current_block, frame::Block::empty());
+        self.current_block = frame::Block::silence(
+            pending.first_sample_index() - self.silence,
+            block_size as u32,
+            pending.num_channels(),
+            pending.bits_per_sample(),
+            pending.sample_rate(),
+            block.into_buffer(),
+        );
+        self.silence -= block_size;
+    }
+
+    /// Handles a frame whose sample rate, channels or bits per sample differ from the
+    /// current parameters, as the `Mismatch` options tell
+    ///
+    /// Returns the block with the parameters to continue with, those trusted
+    /// from the frame change the parameters of the stream.
+    fn check_frame(&mut self, mut block: frame::Block) -> Result<frame::Block> {
+        let info = self.info().clone();
+        let expected = StreamParameters {
+            sample_rate: info.sample_rate,
+            channels: info.channels.count() as u32,
+            bits_per_sample: info.bits_per_sample,
+        };
+        let found = StreamParameters {
+            sample_rate: block.sample_rate(),
+            channels: block.num_channels(),
+            bits_per_sample: block.bits_per_sample(),
+        };
+        if found == expected {
+            return Ok(block);
+        }
+
+        let options = &self.options;
+        let checks = [
+            (
+                expected.channels != found.channels,
+                options.channels_mismatch,
+            ),
+            (
+                expected.sample_rate != found.sample_rate,
+                options.sample_rate_mismatch,
+            ),
+            (
+                expected.bits_per_sample != found.bits_per_sample,
+                options.bits_per_sample_mismatch,
+            ),
+        ];
+        let frame = self.frames - 1;
+        if checks.contains(&(true, Mismatch::Error)) {
+            return Err(errors::Error::InconsistentFrame {
+                frame,
+                expected,
+                found,
+            });
+        }
+        // every frame of a broken encoder may disagree, once is enough
+        if !self.mismatch_warned {
+            self.mismatch_warned = true;
+            tracing::warn!(frame, "FLAC frame is {}, the stream is {}", found, expected);
+        }
+
+        let mut trusted = expected;
+        if options.channels_mismatch == Mismatch::TrustFrames {
+            trusted.channels = found.channels;
+        }
+        if options.sample_rate_mismatch == Mismatch::TrustFrames {
+            trusted.sample_rate = found.sample_rate;
+        }
+        if options.bits_per_sample_mismatch == Mismatch::TrustFrames {
+            trusted.bits_per_sample = found.bits_per_sample;
+        }
+        block.set_num_channels(trusted.channels);
+        block.set_bits_per_sample(trusted.bits_per_sample);
+        if trusted != expected {
+            // frames hold 1 to 8 channels
+            let channel_layout = audio::ChannelLayout::of_count(trusted.channels as usize)
+                .unwrap_or(audio::ChannelLayout::Mono);
+            self.change_stream(audio::AudioInfo {
+                sample_rate: trusted.sample_rate,
+                // unknown, like in a STREAMINFO block
+                total_samples: 0,
+                bits_per_sample: trusted.bits_per_sample,
+                channels: channel_layout.into_channels(),
+                channel_layout,
+                ..info
+            });
+        }
+        Ok(block)
+    }
+
+    /// Returns the check of the header of a frame, made before it is decoded into a buffer
+    ///
+    /// A frame of more channels than the stream fails with
+    /// `Error::InconsistentFrame`, or is skipped if the `channels_mismatch`
+    /// option trusts STREAMINFO, which leaves a gap of its samples. Frames
+    /// are trusted with any number of channels if the option says so. A frame
+    /// larger than `max_block_size` fails with `Error::Unsupported`.
+    fn frame_header_check(&self) -> impl FnOnce(&StreamParameters, u32) -> Result<bool> {
+        let info = self.info();
+        let expected = StreamParameters {
+            sample_rate: info.sample_rate,
+            channels: info.channels.count() as u32,
+            bits_per_sample: info.bits_per_sample,
+        };
+        let mismatch = self.options.channels_mismatch;
+        // the default takes any block size a frame header can hold
+        let max_block_size = match self.options.max_block_size {
+            size if size >= audio::DecodeOptions::DEFAULT_MAX_BLOCK_SIZE => u32::MAX,
+            size => size,
+        };
+        let frame = self.frames;
+        move |found: &StreamParameters, block_size: u32| {
+            if block_size > max_block_size {
+                return errors::unsupported_error("FLAC frame is larger than max_block_size");
+            }
+            // fewer channels are checked after decoding, by `check_frame`
+            if mismatch == Mismatch::TrustFrames || found.channels <= expected.channels {
+                return Ok(true);
+            }
+            if mismatch == Mismatch::Error {
+                return Err(errors::Error::InconsistentFrame {
+                    frame,
+                    expected,
+                    found: *found,
+                });
+            }
+            tracing::warn!(
+                frame,
+                "skipping FLAC frame of {}, the stream is {}",
+                found,
+                expected
+            );
+            Ok(false)
+        }
+    }
+
+    /// Decodes the next frame into `current_block`, `None` at the end of the stream
+    ///
+    /// A chained stream starting with a new header changes the parameters of
+    /// the stream, a frame disagreeing with them is handled by `check_frame`. A frame not starting at the sample after
+    /// the previous frame is recorded as a gap, which is filled with silence
+    /// first if `fill_gaps` is set.
+    fn decode_block(&mut self) -> Option<Result<()>> {
+        if self.silence > 0 {
+            self.fill_gap();
+            return Some(Ok(()));
+        }
+        if let Some(block) = self.pending_block.take() {
+            self.current_block = block;
+            return Some(Ok(()));
+        }
+
+        match self.reader.read_chained_header() {
+            Some(Ok(info)) => {
+                self.next_sample = 0;
+                self.change_stream(info);
+                self.good_position.offset = self.reader.buffer().position();
+            }
+            Some(Err(error)) => return self.fail(error),
+            None => {}
+        }
+
+        // Replace the current block with an empty one so that we may
+        // reuse the current buffer to decode again.
+        let current_block = std::mem::replace(&mut self.current_block, frame::Block::empty());
+        let mut buffer = current_block.into_buffer();
+
+        let collect = self.stats.is_some() || self.decode_stats.is_some();
+        let mut frame_stats = if collect {
+            Some(FrameStats::default())
+        } else {
+            None
+        };
+        if self.options.shrink_block_buffers {
+            let info = self.info();
+            let max_block_size = info
+                .flac_stream_info
+                .as_ref()
+                .map_or(0, |s| s.max_block_size);
+            let len = max_block_size as usize * info.channels.count();
+            if buffer.capacity() > len {
+                buffer.clear();
+                buffer.shrink_to(len);
+            }
+        }
+        let lenient = self.options.lenient_reserved_bits;
+        let (decoded, started) = loop {
+            let info = match self.changed_info {
+                Some(ref info) => info,
+                None => &self.audio_info,
+            };
+            let started = match self.instrumentation {
+                Some(_) => Some((Instant::now(), self.reader.buffer().position())),
+                None => None,
+            };
+            let check = self.frame_header_check();
+            match frame::next_frame_checked(
+                self.reader.buffer(),
+                buffer,
+                info,
+                self.channel_mask,
+                frame_stats.as_mut(),
+                lenient,
+                check,
+            ) {
+                Some(Ok(Some(block))) => break (Some(Ok(block)), started),
+                Some(Ok(None)) => {}
+                Some(Err(error)) => break (Some(Err(error)), started),
+                None => break (None, started),
+            }
+            // skipped by the check, the next frame is searched from after its header
+            self.frames += 1;
+            buffer = Vec::new();
+            match frame::skip_to_next_frame(self.reader.buffer(), info, lenient) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(error) => return self.fail(error),
+            }
+        };
+        match decoded {
+            Some(Ok(next_block)) => {
+                if let (Some(handle), Some((start, offset))) = (&self.instrumentation, started) {
+                    let bytes = self.reader.buffer().position() - offset;
+                    let frames = next_block.total_samples() as u64;
+                    handle.record(frames, bytes, start.elapsed());
+                }
+                self.frames += 1;
+                let next_block = match self.check_frame(next_block) {
+                    Ok(block) => block,
+                    Err(error) => return self.fail(error),
+                };
+                let channels = next_block.num_channels();
+                if self.wasted_bits.is_empty() {
+                    self.wasted_bits.resize(channels as usize, u32::MAX);
+                }
+                for (ch, wasted) in (0..channels).zip(self.wasted_bits.iter_mut()) {
+                    *wasted = (*wasted).min(next_block.wasted_bits(ch));
+                }
+                let first_sample = next_block.first_sample_index();
+                let next_sample = first_sample + next_block.total_samples() as u64;
+                if first_sample != self.next_sample {
+                    self.sample_gaps.push(SampleGap {
+                        expected: self.next_sample,
+                        found: first_sample,
+                    });
+                }
+                self.good_position.frame += next_block.total_samples() as u64;
+                if self.options.fill_gaps_with_silence && first_sample > self.next_sample {
+                    self.silence = first_sample - self.next_sample;
+                    self.good_position.frame += self.silence;
+                    self.pending_block = Some(next_block);
+                    self.fill_gap();
+                } else {
+                    self.current_block = next_block;
+                }
+                self.next_sample = next_sample;
+                self.good_position.offset = self.reader.buffer().position();
+                if let (Some(stats), Some(frame_stats)) = (&mut self.decode_stats, &frame_stats) {
+                    stats.add_flac_frame(frame_stats);
+                }
+                if let (Some(stats), Some(frame_stats)) = (&mut self.stats, frame_stats) {
+                    stats.push(frame_stats);
+                }
+                Some(Ok(()))
+            }
+            Some(Err(error)) => self.fail(error),
+            None => None,
+        }
+    }
+
+    /// Stops the iteration with `error`, an IO error can be resumed after
+    fn fail(&mut self, error: errors::Error) -> Option<Result<()>> {
+        self.has_failed = true;
+        self.io_failed = matches!(error, errors::Error::IoError(_));
+        Some(Err(error))
+    }
+
+    /// Returns the number of channels of STREAMINFO which are selected
+    fn selected_in_stream(&self) -> u64 {
+        let channels = self.audio_info.channels.count() as u32;
+        (0..channels)
+            .filter(|c| (self.channel_mask >> c) & 1 == 1)
+            .count() as u64
+    }
+
+    /// Returns the channels of the current block which are selected
+    fn selected_channels(&self) -> impl Iterator<Item = u32> {
+        let mask = self.channel_mask;
+        (0..self.current_block.num_channels()).filter(move |c| (mask >> c) & 1 == 1)
+    }
+}
+
+impl<'r, S: Sample> ResettableDecoder for FlacSamplesIterator<'r, S> {
+    /// Drops the block decoded last, FLAC frames are decoded on their own
+    fn reset(&mut self) {
+        self.current_block = frame::Block::empty();
+        self.samples_read = 0;
+        self.current_channel = 0;
+        self.silence = 0;
+        self.pending_block = None;
+    }
+}
+
 impl<'r, S: Sample> AudioSamplesIterator<S> for FlacSamplesIterator<'r, S> {
     fn next(&mut self) -> Option<Result<S>> {
         if self.has_failed {
             return None;
         }
 
-        self.current_channel += 1;
+        // skip the channels which are not selected
+        loop {
+            self.current_channel += 1;
 
-        if self.current_channel >= self.current_block.num_channels() {
-            self.current_channel = 0;
-            self.samples_read += 1;
+            if self.current_channel >= self.current_block.num_channels() {
+                self.current_channel = 0;
+                self.samples_read += 1;
 
-            // we read last sample, decode next block
-            if self.samples_read >= self.current_block.total_samples() {
-                self.samples_read = 0;
-
-                // Replace the current block with an empty one so that we may
-                // reuse the current buffer to decode again.
-                let current_block =
-                    std::mem::replace(&mut self.current_block, frame::Block::empty());
-
-                match frame::decode_next_frame(
-                    self.reader.buffer(),
-                    current_block.into_buffer(),
-                    self.audio_info,
-                ) {
-                    Some(Ok(next_block)) => {
-                        self.current_block = next_block;
-                    }
-                    Some(Err(error)) => {
-                        self.has_failed = true;
-                        return Some(Err(error));
-                    }
-                    _ => {
-                        return None;
-                    }
+                // we read last sample, decode next block
+                if self.samples_read >= self.current_block.total_samples() {
+                    self.samples_read = 0;
+                    otry!(self.decode_block()?);
                 }
             }
+
+            if (self.channel_mask >> self.current_channel) & 1 == 1 {
+                break;
+            }
         }
 
         // else just return next sample
+        self.samples_returned += 1;
         Some(Sample::from_i32(
             self.current_block
                 .get_sample(self.current_channel, self.samples_read),
             self.current_block.bits_per_sample(),
         ))
     }
+
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        audio::effective_bits(self.wasted_bits, self.info().bits_per_sample)
+    }
+
+    /// Known from STREAMINFO, until the parameters of the stream change
+    fn samples_left(&self) -> Option<u64> {
+        let info = &self.audio_info;
+        if info.total_samples == 0 || self.changed_info.is_some() {
+            return None;
+        }
+        let channels = info.channels.count() as u64;
+        let total = info.total_samples / channels * self.selected_in_stream();
+        Some(total.saturating_sub(self.samples_returned))
+    }
+
+    fn remaining_frames(&self) -> Option<u64> {
+        Some(io::frames_of(
+            self.samples_left()?,
+            self.selected_in_stream(),
+        ))
+    }
+
+    /// Known from STREAMINFO, until the parameters of the stream change
+    fn sample_rate(&self) -> Option<u32> {
+        match self.changed_info {
+            Some(_) => None,
+            None => Some(self.audio_info.sample_rate),
+        }
+    }
+
+    fn take_stream_change(&mut self) -> Option<audio::AudioInfo> {
+        self.stream_change.take()
+    }
+
+    fn last_good_position(&self) -> Option<audio::StreamPosition> {
+        Some(self.good_position)
+    }
+
+    /// Decodes the frame at `last_good_position` again, the block before it is done
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        if !(self.has_failed && self.io_failed) {
+            return Ok(());
+        }
+        self.reader.buffer().rewind(self.good_position.offset)?;
+        self.reset();
+        self.has_failed = false;
+        self.io_failed = false;
+        Ok(())
+    }
+
+    /// Decodes from the frame holding `frame`, which needs a seek index unless it is 0
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        let start = self.reader.seek_block(frame, &self.audio_info)?;
+        self.reset();
+        self.has_failed = false;
+        self.changed_info = None;
+        self.stream_change = None;
+        self.next_sample = start;
+        self.good_position = audio::StreamPosition {
+            frame: start,
+            offset: self.reader.buffer().position(),
+        };
+        self.io_failed = false;
+        self.frames = 0;
+
+        // decode up to `frame`, as if the samples before it were read
+        let mut skip = frame - start;
+        while skip > 0 {
+            match self.decode_block() {
+                Some(result) => result?,
+                None => break,
+            }
+            let n = skip.min(self.current_block.total_samples() as u64);
+            if n > 0 {
+                self.samples_read = n as u32 - 1;
+                self.current_channel = self.selected_channels().last().unwrap_or(0);
+            }
+            skip -= n;
+        }
+        self.samples_returned = frame * self.selected_in_stream();
+        Ok(())
+    }
+
+    /// Copies the channels of the decoded blocks directly, without interleaving.
+    fn read_planar(&mut self, out: &mut [&mut [S]]) -> Result<usize> {
+        let frames = io::planar_len(out)?;
+        if self.has_failed {
+            return Ok(0);
+        }
+        // `current_channel` is the last selected channel after a complete
+        // inter-channel sample, the block is empty before the first and after
+        // the last frame
+        let last_channel = self.selected_channels().last();
+        if last_channel.is_some() && last_channel != Some(self.current_channel) {
+            return errors::unsupported_error("planar read within an inter-channel sample");
+        }
+
+        let mut written = 0;
+        // index of the next inter-channel sample in the current block
+        let mut next = self.samples_read + 1;
+        while written < frames {
+            if next >= self.current_block.total_samples() {
+                match self.decode_block() {
+                    Some(result) => result?,
+                    None => break,
+                }
+                next = 0;
+            }
+            if self.selected_channels().count() != out.len() {
+                return errors::unsupported_error("number of channel buffers does not match");
+            }
+
+            let n = (frames - written).min((self.current_block.total_samples() - next) as usize);
+            let bits_per_sample = self.current_block.bits_per_sample();
+            for (channel, out) in self.selected_channels().zip(out.iter_mut()) {
+                let samples = &self.current_block.channel(channel)[next as usize..][..n];
+                for (o, s) in out[written..written + n].iter_mut().zip(samples) {
+                    *o = S::from_i32(*s, bits_per_sample)?;
+                }
+            }
+            written += n;
+            self.samples_returned += (n * out.len()) as u64;
+            next += n as u32;
+            self.samples_read = next - 1;
+            self.current_channel = self.selected_channels().last().unwrap_or(0);
+        }
+
+        Ok(written)
+    }
+}
+
+#[test]
+fn test_decode_from_chunked_stream() {
+    use crate::testing::{ChunkedReader, FlacStream};
+
+    // samples of the left and right channel, the last frame is a short one
+    let left: Vec<i32> = (0..84).map(|i| i * 300 - 12_000).collect();
+    let right: Vec<i32> = (0..84).map(|i| 9_000 - i * i).collect();
+
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    stream
+        .metadata_block(1, vec![0; 5000])
+        .metadata_block(2, b"test".to_vec());
+    for (l, r) in left.chunks(32).zip(right.chunks(32)) {
+        stream.frame(&[l.to_vec(), r.to_vec()]);
+    }
+    let data = stream.build();
+    let frames_len = stream.frames.len() as u64;
+
+    for seed in 0..8 {
+        let reader = ChunkedReader::new(data.clone(), seed);
+        let mut segment = audio::AudioSegment::from_reader(reader, codecs::FormatFlag::FLAC)
+            .expect("header should parse from a chunked stream");
+        assert_eq!(segment.header_len(), data.len() as u64 - frames_len);
+        assert_eq!(segment.info().total_samples, 168);
+
+        let samples: Vec<i32> = segment
+            .samples::<i32>()
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect();
+        let expected: Vec<i32> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(&l, &r)| vec![l, r])
+            .collect();
+        assert_eq!(samples, expected);
+    }
+}
+
+#[test]
+fn test_stream_info_is_exposed() {
+    let mut stream = crate::testing::FlacStream::new(48_000, 1, 16);
+    stream.md5 = *b"0123456789abcdef";
+    stream.frame(&[vec![7; 64]]).frame(&[vec![-7; 20]]);
+    let data = stream.build();
+
+    let segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::FLAC)
+            .unwrap();
+    let stream_info = segment.flac_stream_info().unwrap();
+    assert_eq!(stream_info.min_block_size, 16);
+    assert_eq!(stream_info.max_block_size, 64);
+    assert_eq!(stream_info.md5, *b"0123456789abcdef");
+    assert!(format!("{:#}", segment).contains("30313233343536373839616263646566"));
+}
+
+#[test]
+fn test_first_sample_index_of_short_last_frame() {
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream
+        .frame(&[vec![1; 64]])
+        .frame(&[vec![2; 64]])
+        .frame(&[vec![3; 10]]);
+    let data = stream.build();
+
+    let input = crate::io::seekable_stream(std::io::Cursor::new(data)).unwrap();
+    let mut reader = FlacReader::new(input, Default::default()).unwrap();
+    let info = reader.read_header().unwrap();
+
+    let mut blocks = Vec::new();
+    while let Some(block) = frame::decode_next_frame(
+        reader.buffer(),
+        Vec::new(),
+        &info,
+        frame::ALL_CHANNELS,
+        None,
+    ) {
+        blocks.push(block.unwrap());
+    }
+    let indices: Vec<u64> = blocks.iter().map(|b| b.first_sample_index()).collect();
+    assert_eq!(indices, vec![0, 64, 128]);
+
+    let last = blocks.last().unwrap();
+    assert_eq!(
+        last.first_sample_index() + last.total_samples() as u64,
+        info.total_samples
+    );
+}
+
+#[test]
+fn test_frame_stats() {
+    use crate::stats::{FrameStats, RicePartition, SubframeKind};
+    use crate::testing::{FlacStream, FrameSpec, Subframe};
+
+    // the left channel has 2 wasted bits
+    let left: Vec<i32> = (0..32).map(|i| (i * i - 100) * 4).collect();
+    let right: Vec<i32> = (0..32).map(|i| 50 - 3 * i).collect();
+    let mut spec = FrameSpec::verbatim(0, 16, &[left.clone(), right.clone()]);
+    spec.wasted_bits = vec![2, 0];
+    spec.subframes = vec![
+        Subframe::Fixed {
+            order: 2,
+            partition_order: 2,
+            rice_param: 3,
+            samples: left.clone(),
+        },
+        Subframe::Fixed {
+            order: 1,
+            partition_order: 0,
+            rice_param: 1,
+            samples: right.clone(),
+        },
+    ];
+
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    stream.push_frame(&spec);
+    stream.push_frame(&FrameSpec::verbatim(1, 16, &[vec![5; 16], vec![-5; 16]]));
+    let mut spec = FrameSpec::verbatim(2, 16, &[vec![9; 16], vec![1; 16]]);
+    spec.subframes[0] = Subframe::Constant(9);
+    stream.push_frame(&spec);
+    let data = stream.build();
+
+    let mut expected = Vec::new();
+    for i in 0..32 {
+        expected.extend_from_slice(&[left[i], right[i]]);
+    }
+    expected.extend((0..16).flat_map(|_| vec![5, -5]));
+    expected.extend((0..16).flat_map(|_| vec![9, 1]));
+
+    // nothing is collected by default
+    let mut segment = audio::AudioSegment::from_reader(
+        std::io::Cursor::new(data.clone()),
+        codecs::FormatFlag::FLAC,
+    )
+    .unwrap();
+    assert_eq!(segment.samples::<i32>().unwrap().count(), expected.len());
+    assert!(segment.frame_stats().is_empty());
+
+    let options = audio::DecodeOptions {
+        frame_stats: true,
+        ..Default::default()
+    };
+    let mut segment = audio::AudioSegment::from_reader_with_options(
+        std::io::Cursor::new(data),
+        codecs::FormatFlag::FLAC,
+        options,
+    )
+    .unwrap();
+    let decoded: Vec<i32> = segment
+        .samples::<i32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(decoded, expected);
+
+    let frames: &[FrameStats] = segment.frame_stats();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[0].first_sample_index, 0);
+    assert_eq!(frames[0].block_size, 32);
+    assert_eq!(frames[2].first_sample_index, 64);
+
+    let left_stats = &frames[0].subframes[0];
+    assert_eq!(left_stats.kind, SubframeKind::Fixed(2));
+    assert_eq!(left_stats.wasted_bits, 2);
+    assert_eq!(left_stats.partition_order, Some(2));
+    assert_eq!(left_stats.rice_partitions, vec![RicePartition::Rice(3); 4]);
+    let right_stats = &frames[0].subframes[1];
+    assert_eq!(right_stats.kind, SubframeKind::Fixed(1));
+    assert_eq!(right_stats.wasted_bits, 0);
+    assert_eq!(right_stats.rice_partitions, vec![RicePartition::Rice(1)]);
+    assert_eq!(frames[1].subframes[0].kind, SubframeKind::Verbatim);
+    assert_eq!(frames[1].subframes[0].partition_order, None);
+    assert_eq!(frames[2].subframes[0].kind, SubframeKind::Constant);
+
+    let file_stats = segment.file_stats();
+    assert_eq!(file_stats.frames, 3);
+    assert_eq!(file_stats.constant_subframes, 1);
+    assert_eq!(file_stats.verbatim_subframes, 3);
+    assert_eq!(file_stats.fixed_subframes, 2);
+    assert_eq!(file_stats.lpc_subframes, 0);
+    assert_eq!(file_stats.wasted_bits_subframes, 1);
+    assert_eq!(file_stats.max_partition_order, 2);
+}
+
+#[test]
+fn test_unknown_metadata_blocks() {
+    use crate::testing::FlacStream;
+
+    let read = |data: Vec<u8>, options: audio::DecodeOptions| {
+        audio::AudioSegment::from_reader_with_options(
+            std::io::Cursor::new(data),
+            codecs::FormatFlag::FLAC,
+            options,
+        )
+    };
+    let samples: Vec<i32> = (0..32).collect();
+
+    // an unknown and an invalid block between stream_info and the first frame
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream
+        .metadata_block(42, vec![0xaa; 100])
+        .metadata_block(127, vec![0xbb; 7])
+        .frame(std::slice::from_ref(&samples));
+    let mut segment = read(stream.build(), Default::default()).unwrap();
+    assert_eq!(segment.header_len(), 4 + 4 + 34 + 4 + 100 + 4 + 7);
+    let decoded: Vec<i32> = segment
+        .samples::<i32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(decoded, samples);
+
+    // the invalid block type before stream_info is an error
+    let mut data = b"fLaC".to_vec();
+    data.extend_from_slice(&[127, 0, 0, 7]);
+    data.extend_from_slice(&[0xbb; 7]);
+    data.extend_from_slice(&stream.build()[4..]);
+    assert!(read(data, Default::default()).is_err());
+
+    // block lengths are bounded by the configured limit
+    let options = audio::DecodeOptions {
+        max_metadata_block_len: 99,
+        ..Default::default()
+    };
+    match read(stream.build(), options) {
+        Err(errors::Error::ParseError(_)) => {}
+        _ => panic!("expected the oversized block to be rejected"),
+    }
+
+    // a corrupt length is rejected before skipping any data
+    let mut data = stream.build();
+    data[4 + 4 + 34 + 1..4 + 4 + 34 + 4].copy_from_slice(&[0xff; 3]);
+    match read(data, Default::default()) {
+        Err(errors::Error::ParseError(_)) => {}
+        _ => panic!("expected the corrupt length to be rejected"),
+    }
+
+    // so are the number of blocks and the length of the header
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    for _ in 0..20 {
+        stream.metadata_block(42, vec![0; 10]);
+    }
+    stream.frame(std::slice::from_ref(&samples));
+    assert!(read(stream.build(), Default::default()).is_ok());
+    let error = |options| match read(stream.build(), options) {
+        Err(errors::Error::ParseError(msg)) => msg,
+        _ => panic!("expected the header to be rejected"),
+    };
+    let options = audio::DecodeOptions {
+        max_header_chunks: 20,
+        ..Default::default()
+    };
+    assert!(error(options).contains("max_header_chunks"));
+    let options = audio::DecodeOptions {
+        max_header_len: 200,
+        ..Default::default()
+    };
+    assert!(error(options).contains("max_header_len"));
+}
+
+#[test]
+fn test_channel_samples() {
+    use crate::testing::{FlacStream, FrameSpec, Subframe};
+
+    let left: Vec<i32> = (0..32).map(|i| i * 7 - 100).collect();
+    let right: Vec<i32> = (0..32).map(|i| 300 - i * i).collect();
+
+    let mut independent = FrameSpec::verbatim(0, 16, &[left.clone(), right.clone()]);
+    independent.subframes = vec![
+        Subframe::Fixed {
+            order: 2,
+            partition_order: 1,
+            rice_param: 4,
+            samples: left.clone(),
+        },
+        Subframe::Fixed {
+            order: 1,
+            partition_order: 0,
+            rice_param: 5,
+            samples: right.clone(),
+        },
+    ];
+    // the second frame is left/side coded, the side channel has an extra bit
+    let side: Vec<i32> = left.iter().zip(&right).map(|(l, r)| l - r).collect();
+    let mut left_side = FrameSpec::verbatim(1, 16, &[left.clone(), side]);
+    left_side.channel_assignment = 0b1000;
+    left_side.subframe_bps = vec![16, 17];
+
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    stream.push_frame(&independent).push_frame(&left_side);
+    let data = stream.build();
+
+    let read = |channels: &[usize]| -> Vec<i32> {
+        let mut segment = audio::AudioSegment::from_reader(
+            std::io::Cursor::new(data.clone()),
+            codecs::FormatFlag::FLAC,
+        )
+        .unwrap();
+        segment
+            .channel_samples::<i32>(channels)
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect()
+    };
+
+    let twice = |samples: &[i32]| samples.iter().chain(samples).cloned().collect::<Vec<_>>();
+    assert_eq!(read(&[0]), twice(&left));
+    assert_eq!(read(&[1]), twice(&right));
+    assert_eq!(read(&[0, 1]).len(), 128);
+
+    let mut segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::FLAC)
+            .unwrap();
+    assert!(segment.channel_samples::<i32>(&[2]).is_err());
+}
+
+#[test]
+fn test_read_header_twice() {
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![3; 16]]);
+    let input = crate::io::seekable_stream(std::io::Cursor::new(stream.build())).unwrap();
+    let mut reader = FlacReader::new(input, Default::default()).unwrap();
+
+    let info = reader.read_header().unwrap();
+    assert_eq!(info.sample_rate, 44_100);
+    match reader.read_header() {
+        Err(errors::Error::Unsupported(_)) => {}
+        _ => panic!("expected the second call to fail"),
+    }
+    // the audio data is left untouched
+    let block = frame::decode_next_frame(
+        reader.buffer(),
+        Vec::new(),
+        &info,
+        frame::ALL_CHANNELS,
+        None,
+    );
+    assert_eq!(block.unwrap().unwrap().get_sample(0, 15), 3);
+}
+
+#[test]
+fn test_scan_length() {
+    use crate::testing::FlacStream;
+
+    // -8 is coded as 0xfff8, a sync code within the audio data
+    let mut stream = FlacStream::new(8_000, 1, 16);
+    stream
+        .frame(&[vec![-8; 64]])
+        .frame(&[vec![-8; 64]])
+        .frame(&[vec![-8; 36]]);
+    // the encoder did not know the length
+    stream.total_samples = 0;
+    let data = stream.build();
+
+    let mut segment = audio::AudioSegment::from_seekable_reader(
+        std::io::Cursor::new(data.clone()),
+        codecs::FormatFlag::FLAC,
+    )
+    .unwrap();
+    assert_eq!(segment.info().total_samples, 0);
+    let scan = segment.scan_length().unwrap();
+    assert_eq!(scan.frames, 164);
+    assert_eq!(scan.duration, std::time::Duration::from_micros(20_500));
+
+    // the position is restored
+    let samples: Vec<i32> = segment
+        .samples::<i32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(samples, vec![-8; 164]);
+
+    let mut segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::FLAC)
+            .unwrap();
+    match segment.scan_length() {
+        Err(errors::Error::Unsupported(_)) => {}
+        _ => panic!("expected scanning a forward only input to fail"),
+    }
+}
+
+#[test]
+fn test_seek_index() {
+    use crate::testing::FlacStream;
+
+    // eight stereo frames of 64 samples, and a short last one
+    let mut stream = FlacStream::new(8_000, 2, 16);
+    for f in 0..9 {
+        let len = if f == 8 { 20 } else { 64 };
+        let left: Vec<i32> = (0..len).map(|i| f * 1_000 + i).collect();
+        let right: Vec<i32> = left.iter().map(|s| -s).collect();
+        stream.frame(&[left, right]);
+    }
+    let data = stream.build();
+    let open = |data: &[u8]| {
+        audio::AudioSegment::from_seekable_reader(
+            std::io::Cursor::new(data.to_vec()),
+            codecs::FormatFlag::FLAC,
+        )
+        .unwrap()
+    };
+    let all: Vec<i32> = open(&data)
+        .samples::<i32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(all.len(), 2 * 532);
+
+    let mut segment = open(&data);
+    assert!(segment.export_seek_index().is_none());
+    match segment.samples::<i32>().unwrap().seek_frame(100) {
+        Err(errors::Error::Unsupported(_)) => {}
+        other => panic!("expected seeking without an index to fail, got {:?}", other),
+    }
+    let mut segment = open(&data);
+    segment.build_seek_index().unwrap();
+    let index = segment.export_seek_index().unwrap();
+
+    // a loaded index seeks anywhere, within frames too
+    let mut segment = open(&data);
+    assert!(segment.load_seek_index(&index).unwrap());
+    assert_eq!(segment.export_seek_index().unwrap(), index);
+    let mut samples = segment.samples::<i32>().unwrap();
+    for &frame in &[300u64, 64, 0, 511, 512, 531, 1] {
+        samples.seek_frame(frame).unwrap();
+        assert_eq!(samples.samples_left(), Some(2 * (532 - frame)));
+        let rest: Vec<i32> = samples.by_ref().map(|s| s.unwrap()).collect();
+        assert_eq!(rest, &all[2 * frame as usize..], "seeking to {}", frame);
+    }
+    samples.seek_frame(200).unwrap();
+    let mut left = vec![0; 100];
+    let mut right = vec![0; 100];
+    assert_eq!(
+        samples.read_planar(&mut [&mut left, &mut right]).unwrap(),
+        100
+    );
+    assert_eq!(left[0], all[400]);
+    assert_eq!(right[99], all[2 * 299 + 1]);
+
+    // an index of a file as long is rejected by its MD5 signature, and rebuilt
+    stream.md5 = [7; 16];
+    let mut segment = open(&stream.build());
+    assert!(!segment.load_seek_index(&index).unwrap());
+    let rebuilt = segment.export_seek_index().unwrap();
+    assert_ne!(rebuilt, index);
+    assert_eq!(rebuilt[49..], index[49..]);
+    let mut samples = segment.samples::<i32>().unwrap();
+    samples.seek_frame(300).unwrap();
+    assert_eq!(samples.next().unwrap().unwrap(), all[600]);
+}
+
+#[test]
+fn test_read_planar() {
+    use crate::testing::FlacStream;
+
+    let left: Vec<i32> = (0..84).map(|i| i * 300 - 12_000).collect();
+    let right: Vec<i32> = (0..84).map(|i| 9_000 - i * i).collect();
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    for (l, r) in left.chunks(32).zip(right.chunks(32)) {
+        stream.frame(&[l.to_vec(), r.to_vec()]);
+    }
+    let segment = || {
+        let input = std::io::Cursor::new(stream.build());
+        audio::AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap()
+    };
+
+    // buffers of 10 samples cross the frame boundaries
+    let mut segment_all = segment();
+    let mut samples = segment_all.samples::<i32>().unwrap();
+    let (mut l, mut r) = (vec![0; 10], vec![0; 10]);
+    let mut planar = (Vec::new(), Vec::new());
+    loop {
+        let n = samples.read_planar(&mut [&mut l, &mut r]).unwrap();
+        planar.0.extend_from_slice(&l[..n]);
+        planar.1.extend_from_slice(&r[..n]);
+        if n < 10 {
+            break;
+        }
+    }
+    assert_eq!(planar, (left.clone(), right.clone()));
+    assert_eq!(samples.read_planar(&mut [&mut l, &mut r]).unwrap(), 0);
+    assert!(samples.next().is_none());
+
+    // planar reads continue after whole inter-channel samples taken with `next`
+    let mut segment_mixed = segment();
+    let mut samples = segment_mixed.samples::<f32>().unwrap();
+    let first: Vec<f32> = (0..4).map(|_| samples.next().unwrap().unwrap()).collect();
+    assert_eq!(first[2], left[1] as f32 / 32_768.0);
+    let (mut l, mut r) = (vec![0.0; 40], vec![0.0; 40]);
+    assert_eq!(samples.read_planar_f32(&mut [&mut l, &mut r]).unwrap(), 40);
+    assert_eq!(l[0], left[2] as f32 / 32_768.0);
+    assert_eq!(r[39], right[41] as f32 / 32_768.0);
+    assert_eq!(samples.next().unwrap().unwrap(), left[42] as f32 / 32_768.0);
+    assert!(samples.read_planar_f32(&mut [&mut l, &mut r]).is_err());
+
+    let mut segment_right = segment();
+    let mut samples = segment_right.channel_samples::<i32>(&[1]).unwrap();
+    let mut r = vec![0; 100];
+    assert_eq!(samples.read_planar(&mut [&mut r]).unwrap(), 84);
+    assert_eq!(r[..84], right[..]);
+
+    let mut segment_wrong = segment();
+    let mut samples = segment_wrong.samples::<i32>().unwrap();
+    assert!(samples.read_planar(&mut [&mut r]).is_err());
+}
+
+/// A mono frame of `samples` as a fixed subframe of `order`.
+#[cfg(test)]
+fn fixed_frame(
+    number: u64,
+    order: u8,
+    partition_order: u8,
+    samples: &[i32],
+) -> crate::testing::FrameSpec {
+    let mut spec = crate::testing::FrameSpec::verbatim(number, 16, &[samples.to_vec()]);
+    spec.subframes = vec![crate::testing::Subframe::Fixed {
+        order,
+        partition_order,
+        rice_param: 2,
+        samples: samples.to_vec(),
+    }];
+    spec
+}
+
+#[cfg(test)]
+fn decode_all(stream: &crate::testing::FlacStream) -> Result<Vec<i32>> {
+    let input = std::io::Cursor::new(stream.build());
+    let mut segment = audio::AudioSegment::from_reader(input, codecs::FormatFlag::FLAC)?;
+    let samples = segment.samples::<i32>()?;
+    samples.collect()
+}
+
+#[test]
+fn test_corrupt_frame_offset() {
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![1; 16]]);
+    let second_frame = stream.build().len();
+    stream.frame(&[vec![2; 16]]);
+    let third_frame = stream.build().len();
+    stream.frame(&[vec![3; 16]]);
+    let mut data = stream.build();
+    // flip a bit of the last sample of the second frame, before its CRC-16
+    data[third_frame - 3] ^= 0x10;
+
+    // the bytes of a source which can not seek are still buffered
+    let input = crate::io::read_only_stream(std::io::Cursor::new(data.clone()));
+    let mut reader = FlacReader::new(input, Default::default()).unwrap();
+    let info = reader.read_header().unwrap();
+    let decode = |reader: &mut FlacReader| {
+        frame::decode_next_frame(
+            reader.buffer(),
+            Vec::new(),
+            &info,
+            frame::ALL_CHANNELS,
+            None,
+        )
+    };
+    assert!(decode(&mut reader).unwrap().is_ok());
+    match decode(&mut reader) {
+        Some(Err(errors::Error::CorruptFrame { offset, reason })) => {
+            assert_eq!(offset, second_frame as u64);
+            assert_eq!(reason, "frame CRC mismatch");
+        }
+        _ => panic!("expected the second frame to be corrupt"),
+    }
+    assert_eq!(reader.buffer().position(), second_frame as u64 + 2);
+
+    // the offset is reported by the samples iterator as well
+    let input = std::io::Cursor::new(data);
+    let mut segment = audio::AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap();
+    let error = segment
+        .samples::<i32>()
+        .unwrap()
+        .find_map(|s| s.err())
+        .unwrap();
+    match error {
+        errors::Error::CorruptFrame { offset, .. } => assert_eq!(offset, second_frame as u64),
+        e => panic!("unexpected error {}", e),
+    }
+}
+
+#[test]
+fn test_order_must_be_less_than_block_size() {
+    let samples = [3, 5, 7, 9];
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&fixed_frame(0, 4, 0, &samples));
+    assert!(decode_all(&stream).is_err());
+
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&fixed_frame(0, 3, 0, &samples));
+    assert_eq!(decode_all(&stream).unwrap(), samples);
+}
+
+#[test]
+fn test_partition_order_too_large() {
+    let samples: Vec<i32> = (0..16).map(|i| i * 3).collect();
+    // 8 partitions of 2 samples, the first can not hold the 4 warm-up samples
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&fixed_frame(0, 4, 3, &samples));
+    assert!(decode_all(&stream).is_err());
+
+    // the block size is not a multiple of the 32 partitions
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&fixed_frame(0, 1, 5, &samples));
+    assert!(decode_all(&stream).is_err());
+}
+
+#[test]
+fn test_short_final_block() {
+    let first: Vec<i32> = (0..32).map(|i| i * i).collect();
+    let last = [4, -2, 7, 1, 0];
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream
+        .push_frame(&fixed_frame(0, 2, 1, &first))
+        .push_frame(&fixed_frame(1, 2, 0, &last));
+    assert_eq!(
+        decode_all(&stream).unwrap(),
+        [&first[..], &last[..]].concat()
+    );
+
+    // a stream of a single short block has its length as min and max block size
+    stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&fixed_frame(0, 2, 0, &last));
+    stream.min_block_size = 5;
+    assert_eq!(decode_all(&stream).unwrap(), last);
+
+    // but otherwise blocks of less than 16 samples are not allowed
+    stream.min_block_size = 4;
+    assert!(decode_all(&stream).is_err());
+}
+
+#[test]
+fn test_effective_bits_per_sample() {
+    use crate::testing::{FlacStream, FrameSpec, Subframe};
+
+    // 16 bit audio in a 24 bit stream, every sample has 8 wasted bits
+    let left: Vec<i32> = (0..32).map(|i| (i * 37 - 500) << 8).collect();
+    let right: Vec<i32> = (0..32).map(|i| (900 - i * i) << 8).collect();
+    let mut spec = FrameSpec::verbatim(0, 24, &[left.clone(), right]);
+    spec.wasted_bits = vec![8, 8];
+    spec.subframes[0] = Subframe::Fixed {
+        order: 2,
+        partition_order: 1,
+        rice_param: 4,
+        samples: left,
+    };
+    let mut stream = FlacStream::new(44_100, 2, 24);
+    stream.push_frame(&spec);
+    // a silent right channel does not lower its bit depth
+    let mut spec = FrameSpec::verbatim(1, 24, &[vec![3 << 9; 32], vec![0; 32]]);
+    spec.wasted_bits = vec![9, 0];
+    spec.subframes[1] = Subframe::Constant(0);
+    stream.push_frame(&spec);
+
+    let options = audio::DecodeOptions {
+        frame_stats: true,
+        ..Default::default()
+    };
+    let input = std::io::Cursor::new(stream.build());
+    let mut segment =
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap();
+    assert_eq!(segment.effective_bits_per_sample(), None);
+    {
+        let mut samples = segment.samples::<i32>().unwrap();
+        samples.next().unwrap().unwrap();
+        assert_eq!(samples.effective_bits_per_sample(), Some(vec![16, 16]));
+        samples.for_each(drop);
+    }
+    assert_eq!(segment.effective_bits_per_sample(), Some(vec![16, 16]));
+
+    let frames: Vec<_> = segment
+        .frame_stats()
+        .iter()
+        .map(|f| f.effective_bits_per_sample.clone())
+        .collect();
+    assert_eq!(frames, vec![vec![16, 16], vec![15, 0]]);
+    assert_eq!(segment.file_stats().effective_bits_per_sample, vec![16, 16]);
+}
+
+#[test]
+fn test_decode_stats() {
+    use crate::testing::{FlacStream, FrameSpec, Subframe};
+
+    let samples: Vec<i32> = (0..32).map(|i| i * i - 300).collect();
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    for (number, partition_order) in [(0, 1), (1, 3)].iter() {
+        let mut spec = FrameSpec::verbatim(*number, 16, &[samples.clone(), samples.clone()]);
+        spec.subframes[0] = Subframe::Fixed {
+            order: 2,
+            partition_order: *partition_order,
+            rice_param: 3,
+            samples: samples.clone(),
+        };
+        stream.push_frame(&spec);
+    }
+    let mut spec = FrameSpec::verbatim(2, 16, &[vec![4; 16], vec![5; 16]]);
+    spec.subframes[1] = Subframe::Constant(5);
+    stream.push_frame(&spec);
+
+    let decode = |options| {
+        let input = std::io::Cursor::new(stream.build());
+        let mut segment =
+            audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+                .unwrap();
+        segment.samples::<i32>().unwrap().for_each(drop);
+        segment
+    };
+
+    // nothing is collected by default
+    assert_eq!(
+        *decode(Default::default()).decode_stats(),
+        DecodeStats::default()
+    );
+
+    let segment = decode(audio::DecodeOptions {
+        decode_stats: true,
+        ..Default::default()
+    });
+    assert!(segment.frame_stats().is_empty());
+    let stats = segment.decode_stats();
+    assert_eq!(stats.frames, 3);
+    assert_eq!(stats.block_sizes.get(&32), Some(&2));
+    assert_eq!(stats.block_sizes.get(&16), Some(&1));
+    assert_eq!(stats.constant_subframes, 1);
+    assert_eq!(stats.verbatim_subframes, 3);
+    assert_eq!(stats.fixed_orders.get(&2), Some(&2));
+    assert!(stats.lpc_orders.is_empty());
+    // 2 + 8 partitions, all with rice parameter 3
+    assert_eq!(stats.rice_parameters.get(&3), Some(&10));
+    assert_eq!(stats.average_partition_order(), Some(2.0));
+    assert!(stats.bitrates.is_empty());
+
+    let summary = format!("{}", stats);
+    assert!(summary.contains("| Block Sizes:           16: 1, 32: 2"));
+    assert!(summary.contains("| Avg Partition Order:   2.00"));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_decode_stats_serialize() {
+    fn assert_serialize<T: serde::Serialize>() {}
+    assert_serialize::<DecodeStats>();
+}
+
+#[test]
+fn test_chained_streams() {
+    use crate::audio::Event;
+    use crate::testing::{FlacStream, FrameSpec};
+
+    let mut first = FlacStream::new(44_100, 2, 16);
+    first
+        .frame(&[vec![1; 16], vec![2; 16]])
+        .frame(&[vec![3; 16], vec![4; 16]]);
+    let mut second = FlacStream::new(48_000, 1, 24);
+    second.frame(&[vec![100_000; 20]]);
+    let mut data = first.build();
+    data.extend(second.build());
+
+    let mut segment = audio::AudioSegment::from_reader(
+        std::io::Cursor::new(data.clone()),
+        codecs::FormatFlag::FLAC,
+    )
+    .unwrap();
+    let events: Vec<Event<i32>> = segment.events().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(events.len(), 64 + 1 + 20);
+    match &events[64] {
+        Event::StreamChanged(info) => {
+            assert_eq!(info.sample_rate, 48_000);
+            assert_eq!(info.channels.count(), 1);
+            assert_eq!(info.bits_per_sample, 24);
+        }
+        event => panic!("expected a stream change, got {:?}", event),
+    }
+    let samples: Vec<i32> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Sample(s) => Some(*s),
+            Event::StreamChanged(_) => None,
+        })
+        .collect();
+    assert_eq!(&samples[30..34], &[1, 2, 3, 4]);
+    assert_eq!(&samples[64..], &[100_000; 20][..]);
+    assert_eq!(segment.info().sample_rate, 44_100);
+
+    // without events the samples of both streams are returned all the same
+    let mut segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::FLAC)
+            .unwrap();
+    let plain: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(plain, samples);
+
+    // a frame coding a different sample rate changes the stream too, if trusted
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![5; 16]]);
+    let mut spec = FrameSpec::verbatim(1, 16, &[vec![6; 16]]);
+    spec.sample_rate_code = 0b1010;
+    stream.push_frame(&spec);
+    let options = audio::DecodeOptions {
+        sample_rate_mismatch: audio::Mismatch::TrustFrames,
+        ..Default::default()
+    };
+    let mut segment = audio::AudioSegment::from_reader_with_options(
+        std::io::Cursor::new(stream.build()),
+        codecs::FormatFlag::FLAC,
+        options,
+    )
+    .unwrap();
+    let changes: Vec<u32> = segment
+        .events::<i32>()
+        .unwrap()
+        .filter_map(|e| match e.unwrap() {
+            Event::StreamChanged(info) => Some(info.sample_rate),
+            Event::Sample(_) => None,
+        })
+        .collect();
+    assert_eq!(changes, vec![48_000]);
+}
+
+#[test]
+fn test_sample_gaps() {
+    use crate::testing::{FlacStream, FrameSpec};
+
+    // the frame numbered 2 was dropped
+    let mut stream = FlacStream::new(8_000, 1, 16);
+    for (number, value) in [(0, 1), (1, 2), (3, 4)] {
+        stream.push_frame(&FrameSpec::verbatim(number, 16, &[vec![value; 16]]));
+    }
+    let data = stream.build();
+    let gap = SampleGap {
+        expected: 32,
+        found: 48,
+    };
+
+    let mut segment = audio::AudioSegment::from_reader(
+        std::io::Cursor::new(data.clone()),
+        codecs::FormatFlag::FLAC,
+    )
+    .unwrap();
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples.len(), 48);
+    assert_eq!(samples[32], 4);
+    assert_eq!(segment.sample_gaps(), &[gap]);
+    assert_eq!(gap.missing_samples(), 16);
+
+    let options = audio::DecodeOptions {
+        fill_gaps_with_silence: true,
+        ..Default::default()
+    };
+    let mut segment = audio::AudioSegment::from_reader_with_options(
+        std::io::Cursor::new(data),
+        codecs::FormatFlag::FLAC,
+        options,
+    )
+    .unwrap();
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    let mut expected = vec![1; 16];
+    expected.extend(vec![2; 16]);
+    expected.extend(vec![0; 16]);
+    expected.extend(vec![4; 16]);
+    assert_eq!(samples, expected);
+    assert_eq!(segment.sample_gaps(), &[gap]);
+}
+
+#[test]
+fn test_skip_padding() {
+    use crate::testing::FlacStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// a file counting the bytes read from it
+    struct CountingFile(std::io::Cursor<Vec<u8>>, Arc<AtomicUsize>);
+
+    impl std::io::Read for CountingFile {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.read(buf)?;
+            self.1.fetch_add(n, Ordering::Relaxed);
+            Ok(n)
+        }
+    }
+
+    impl std::io::Seek for CountingFile {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    let mut cuesheet = b"CUES".to_vec();
+    cuesheet.extend_from_slice(b"REM ACCURATERIPID");
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream
+        .metadata_block(2, cuesheet)
+        .metadata_block(1, vec![0; 8 * 1024 * 1024])
+        .frame(&[vec![7; 16]]);
+    let data = stream.build();
+
+    let read = Arc::new(AtomicUsize::new(0));
+    let file = CountingFile(std::io::Cursor::new(data.clone()), read.clone());
+    let mut segment = audio::AudioSegment::from_seekable_reader(file, codecs::FormatFlag::FLAC)
+        .expect("header should parse");
+    // the padding is seeked over, not read
+    assert!(read.load(Ordering::Relaxed) < 64 * 1024);
+    assert_eq!(
+        segment.skipped_metadata(),
+        vec![audio::SkippedMetadata {
+            block_type: 1,
+            offset: 42 + 4 + 21,
+            len: 8 * 1024 * 1024,
+        }]
+    );
+    assert_eq!(
+        segment.application_blocks(),
+        vec![audio::ApplicationBlock {
+            id: *b"CUES",
+            data: b"REM ACCURATERIPID".to_vec(),
+        }]
+    );
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![7; 16]);
+
+    // a file ending within the padding is still found to be truncated
+    let truncated = std::io::Cursor::new(data[..1_000_000].to_vec());
+    assert!(
+        audio::AudioSegment::from_seekable_reader(truncated, codecs::FormatFlag::FLAC).is_err()
+    );
+
+    // inputs which can not seek read the padding
+    let mut segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::FLAC)
+            .unwrap();
+    assert_eq!(segment.skipped_metadata().len(), 1);
+    assert_eq!(segment.samples::<i32>().unwrap().count(), 16);
+}
+
+#[test]
+fn test_verify() {
+    let channels = [
+        vec![vec![1, -2, 300, 4], vec![-5, 6, 7, -800]],
+        vec![vec![9; 4], vec![-10; 4]],
+    ];
+    let mut stream = crate::testing::FlacStream::new(44_100, 2, 16);
+    stream.min_block_size = 4;
+    let mut signature = Vec::new();
+    for samples in &channels {
+        stream.frame(samples);
+        for (left, right) in samples[0].iter().zip(&samples[1]) {
+            signature.extend_from_slice(&(*left as i16).to_le_bytes());
+            signature.extend_from_slice(&(*right as i16).to_le_bytes());
+        }
+    }
+    let third_frame = stream.frames.len();
+    stream.frame(&[vec![11; 4], vec![12; 4]]);
+    for _ in 0..4 {
+        signature.extend_from_slice(&11i16.to_le_bytes());
+        signature.extend_from_slice(&12i16.to_le_bytes());
+    }
+    stream.md5 = crate::md5::md5(&signature);
+
+    let verify = |data: Vec<u8>| {
+        let input = std::io::Cursor::new(data);
+        let mut segment = audio::AudioSegment::from_reader(input, codecs::FormatFlag::FLAC)?;
+        segment.verify()
+    };
+    let report = verify(stream.build()).unwrap();
+    assert_eq!(report.frames, 3);
+    assert_eq!(report.samples, 12);
+    assert_eq!(report.md5_matched, Some(true));
+    assert!(report.is_ok());
+
+    // the frames after a corrupt one are still checked
+    let mut data = stream.build();
+    let frames_start = data.len() - stream.frames.len();
+    data[frames_start + third_frame - 3] ^= 0x10;
+    let report = verify(data).unwrap();
+    assert_eq!(report.frames, 2);
+    assert_eq!(report.crc_failures, 1);
+    assert_eq!(report.md5_matched, Some(false));
+    assert!(!report.is_ok());
+
+    let mut data = stream.build();
+    data.truncate(data.len() - 3);
+    let report = verify(data).unwrap();
+    assert_eq!(report.frames, 2);
+    assert!(report.truncated);
+
+    // a signature left at zero is not checked
+    stream.md5 = [0; 16];
+    let report = verify(stream.build()).unwrap();
+    assert_eq!(report.md5_matched, None);
+    assert!(report.is_ok());
+}
+
+#[test]
+fn test_stream_info_mismatch() {
+    use crate::audio::{DecodeOptions, Event, Mismatch};
+    use crate::testing::FlacStream;
+
+    // a broken encoder declaring stereo and writing mono frames
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    stream.frame(&[vec![1; 16]]).frame(&[vec![2; 16]]);
+    let read = |options: DecodeOptions| {
+        let input = std::io::Cursor::new(stream.build());
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap()
+    };
+
+    let mut segment = read(DecodeOptions::default());
+    match segment.samples::<i32>().unwrap().next() {
+        Some(Err(errors::Error::InconsistentFrame {
+            frame,
+            expected,
+            found,
+        })) => {
+            assert_eq!(frame, 0);
+            assert_eq!(expected.channels, 2);
+            assert_eq!(found.channels, 1);
+            assert_eq!(found.bits_per_sample, 16);
+        }
+        _ => panic!("expected the first frame to be inconsistent"),
+    }
+
+    // the frames decide how the samples are interleaved
+    let mut segment = read(DecodeOptions {
+        channels_mismatch: Mismatch::TrustFrames,
+        ..Default::default()
+    });
+    let events: Vec<Event<i32>> = segment.events().unwrap().map(|e| e.unwrap()).collect();
+    match &events[0] {
+        Event::StreamChanged(info) => assert_eq!(info.channels.count(), 1),
+        event => panic!("expected a stream change, got {:?}", event),
+    }
+    assert_eq!(events.len(), 1 + 32);
+
+    // or STREAMINFO does, with a silent second channel
+    let mut segment = read(DecodeOptions {
+        channels_mismatch: Mismatch::TrustStreamInfo,
+        ..Default::default()
+    });
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples.len(), 64);
+    assert_eq!(&samples[30..34], &[1, 0, 2, 0]);
+
+    // the other parameters are still checked
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&crate::testing::FrameSpec::verbatim(
+        0,
+        24,
+        &[vec![1 << 8; 16]],
+    ));
+    let options = DecodeOptions {
+        channels_mismatch: Mismatch::TrustFrames,
+        ..Default::default()
+    };
+    let input = std::io::Cursor::new(stream.build());
+    let mut segment =
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap();
+    assert!(segment.samples::<i32>().unwrap().next().unwrap().is_err());
+
+    // samples of 24 bits taken as 16 bits keep their values
+    let options = DecodeOptions {
+        bits_per_sample_mismatch: Mismatch::TrustStreamInfo,
+        ..Default::default()
+    };
+    let input = std::io::Cursor::new(stream.build());
+    let mut segment =
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap();
+    let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![1 << 8; 16]);
+}
+
+#[test]
+fn test_frame_of_more_channels_than_stream_info() {
+    use crate::audio::{DecodeOptions, Mismatch};
+    use crate::testing::FlacStream;
+
+    // a corrupt frame declaring 8 independent channels in a stereo stream
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    stream
+        .frame(&[vec![1; 16], vec![-1; 16]])
+        .frame(&vec![vec![5; 16]; 8])
+        .frame(&[vec![3; 16], vec![-3; 16]]);
+    let data = stream.build();
+    let read = |options: DecodeOptions| {
+        let input = std::io::Cursor::new(data.clone());
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap()
+    };
+
+    // the frame is rejected before it is decoded, after the samples before it
+    let mut segment = read(DecodeOptions::default());
+    let results: Vec<Result<i32>> = segment.samples().unwrap().collect();
+    assert_eq!(results.len(), 33);
+    assert!(results[..32].iter().all(|s| s.is_ok()));
+    match &results[32] {
+        Err(errors::Error::InconsistentFrame {
+            frame,
+            expected,
+            found,
+        }) => {
+            assert_eq!(*frame, 1);
+            assert_eq!(expected.channels, 2);
+            assert_eq!(found.channels, 8);
+        }
+        other => panic!(
+            "expected the second frame to be inconsistent, got {:?}",
+            other
+        ),
+    }
+
+    // trusting STREAMINFO skips it, the stereo interleaving is kept
+    for fill_gaps_with_silence in [false, true] {
+        let mut segment = read(DecodeOptions {
+            channels_mismatch: Mismatch::TrustStreamInfo,
+            fill_gaps_with_silence,
+            ..Default::default()
+        });
+        let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+        let mut expected = [1, -1].repeat(16);
+        if fill_gaps_with_silence {
+            expected.extend(vec![0; 32]);
+        }
+        expected.extend([3, -3].repeat(16));
+        assert_eq!(samples, expected);
+        assert_eq!(segment.sample_gaps().len(), 1);
+        assert_eq!(segment.info().channels.count(), 2);
+    }
+
+    // trusting frames decodes it as a change of the stream
+    let mut segment = read(DecodeOptions {
+        channels_mismatch: Mismatch::TrustFrames,
+        ..Default::default()
+    });
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples.len(), 32 + 8 * 16 + 32);
+    assert_eq!(&samples[32..40], &[5; 8]);
+}
+
+#[test]
+fn test_frame_of_more_channels_is_checked_before_its_subframes() {
+    use crate::audio::{DecodeOptions, Mismatch};
+    use crate::testing::{FlacStream, FrameSpec};
+
+    // the subframes of the 8 channel frame are garbage, only its header is valid
+    let spec = FrameSpec::verbatim(1, 16, &vec![vec![5; 16]; 8]);
+    let mut header_len = 0;
+    let mut broken = spec.encode_with_header(|header| header_len = header.len() + 1);
+    for byte in &mut broken[header_len..] {
+        *byte = 0xff;
+    }
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    stream.frame(&[vec![1; 16], vec![-1; 16]]);
+    stream.frames.extend_from_slice(&broken);
+    stream.push_frame(&FrameSpec::verbatim(2, 16, &[vec![3; 16], vec![-3; 16]]));
+    let data = stream.build();
+    let read = |options: DecodeOptions| {
+        let input = std::io::Cursor::new(data.clone());
+        let options = DecodeOptions {
+            frame_stats: true,
+            ..options
+        };
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap()
+    };
+
+    // inconsistent rather than corrupt, the subframes are not decoded
+    let mut segment = read(DecodeOptions::default());
+    let results: Vec<Result<i32>> = segment.samples().unwrap().collect();
+    assert_eq!(results.len(), 33);
+    assert!(matches!(
+        results[32],
+        Err(errors::Error::InconsistentFrame { frame: 1, .. })
+    ));
+
+    let mut segment = read(DecodeOptions {
+        channels_mismatch: Mismatch::TrustStreamInfo,
+        ..Default::default()
+    });
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, [[1, -1].repeat(16), [3, -3].repeat(16)].concat());
+    assert_eq!(segment.frame_stats().len(), 2);
+    assert_eq!(segment.sample_gaps().len(), 1);
+}
+
+#[test]
+fn test_sample_rate_zero() {
+    let mut stream = crate::testing::FlacStream::new(0, 1, 16);
+    stream.frame(&[vec![1, 2, 3, 4]]);
+    match audio::AudioSegment::from_reader(
+        std::io::Cursor::new(stream.build()),
+        codecs::FormatFlag::FLAC,
+    ) {
+        Err(errors::Error::ParseError(_)) => {}
+        other => panic!(
+            "expected a parse error, got {:?}",
+            other.map(|s| s.duration())
+        ),
+    }
+}
+
+#[test]
+fn test_adversarial_subframe_bits() {
+    use crate::testing::{FlacStream, FrameSpec};
+
+    // the side channel of 32 bit audio has 33 bits per sample
+    let mut left_side = FrameSpec::verbatim(0, 32, &[vec![1, 2], vec![3, 4]]);
+    left_side.channel_assignment = 0b1000;
+    left_side.subframe_bps = vec![32, 33];
+    let mut stream = FlacStream::new(44_100, 2, 32);
+    stream.push_frame(&left_side);
+    match decode_all(&stream) {
+        Err(errors::Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported subframe, got {:?}", other),
+    }
+
+    // all bits of a subframe are wasted
+    let mut wasted = FrameSpec::verbatim(0, 16, &[vec![0; 2]]);
+    wasted.wasted_bits = vec![16];
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream.push_frame(&wasted);
+    match decode_all(&stream) {
+        Err(errors::Error::CorruptFrame { reason, .. }) => assert!(reason.contains("wasted")),
+        other => panic!("expected a corrupt frame, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_instrumentation() {
+    use crate::testing::FlacStream;
+
+    let block: Vec<i32> = (0..256).collect();
+    let data = FlacStream::new(8_000, 2, 16)
+        .frame(&[block.clone(), block.clone()])
+        .frame(&[block.clone(), block])
+        .build();
+    let decode = |options| {
+        let input = std::io::Cursor::new(data.clone());
+        audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+            .unwrap()
+    };
+
+    let mut segment = decode(Default::default());
+    segment.samples::<i16>().unwrap().for_each(drop);
+    assert!(segment.instrumentation().is_none());
+
+    let mut segment = decode(audio::DecodeOptions {
+        instrumentation: true,
+        ..Default::default()
+    });
+    let handle = segment.instrumentation_handle().unwrap();
+    assert_eq!(handle.get().blocks, 0);
+    assert_eq!(handle.get().real_time_factor(), None);
+    let mut samples = segment.samples::<i16>().unwrap();
+    samples.next();
+    let first = handle.get();
+    assert_eq!((first.blocks, first.frames), (1, 256));
+    samples.for_each(drop);
+
+    let instrumentation = segment.instrumentation().unwrap();
+    assert_eq!(instrumentation.blocks, 2);
+    assert_eq!(instrumentation.frames, 512);
+    assert_eq!(instrumentation.sample_rate, 8_000);
+    assert_eq!(instrumentation.audio_duration().as_millis(), 64);
+    assert_eq!(
+        instrumentation.bytes,
+        data.len() as u64 - segment.header_len()
+    );
+    assert!(instrumentation.decode_time > std::time::Duration::ZERO);
+    assert!(format!("{}", instrumentation).starts_with("512 frames in 2 blocks"));
+}
+
+#[test]
+fn test_long_stream_durations() {
+    use std::time::Duration;
+
+    // STREAMINFO of a 24 hour recording of 8 channels at 192 kHz, with a single frame
+    let read = |total_frames: u64| {
+        let mut stream = crate::testing::FlacStream::new(192_000, 8, 24);
+        stream.frame(&vec![vec![0; 16]; 8]);
+        stream.total_samples = total_frames;
+        audio::AudioSegment::from_reader(
+            std::io::Cursor::new(stream.build()),
+            codecs::FormatFlag::FLAC,
+        )
+        .unwrap()
+    };
+    let day = 24 * 3600 * 192_000;
+    let mut segment = read(day);
+    assert_eq!(segment.info().total_samples, 8 * 16_588_800_000);
+    assert_eq!(segment.duration(), Duration::from_secs(86_400));
+    assert!(format!("{}", segment).contains(", 24:00:00"));
+    let samples = segment.samples::<i32>().unwrap();
+    assert_eq!(samples.samples_left(), Some(8 * day));
+    assert_eq!(
+        samples.remaining_duration(),
+        Some(Duration::from_secs(86_400))
+    );
+    drop(samples);
+    let scan = audio::ScanResult::new(day, 192_000);
+    assert_eq!(scan.duration, Duration::from_secs(86_400));
+
+    // a single frame after 8 hours, which an f32 of seconds loses
+    let segment = read(8 * 3600 * 192_000 + 1);
+    assert_eq!(segment.duration(), Duration::new(28_800, 5_208));
+}
+
+#[test]
+fn test_empty_and_header_only_files() {
+    let read = |file: Vec<u8>, seekable| {
+        let flag = codecs::FormatFlag::FLAC;
+        if seekable {
+            audio::AudioSegment::from_seekable_reader(std::io::Cursor::new(file), flag)
+        } else {
+            audio::AudioSegment::from_reader(std::io::Cursor::new(file), flag)
+        }
+    };
+    for &seekable in &[false, true] {
+        for file in &[Vec::new(), b"not a flac file at all".to_vec()] {
+            assert!(matches!(
+                read(file.clone(), seekable),
+                Err(errors::Error::MissingMagic(_))
+            ));
+        }
+
+        // STREAMINFO without any frames
+        let header = crate::testing::FlacStream::new(44_100, 2, 16).build();
+        let mut segment = read(header, seekable).unwrap();
+        assert_eq!(segment.info().total_samples, 0);
+        assert!(segment.samples::<i32>().unwrap().next().is_none());
+
+        assert!(matches!(
+            read(b"fLaC".to_vec(), seekable),
+            Err(errors::Error::ParseError(_))
+        ));
+    }
+}
+
+#[test]
+fn test_lenient_reserved_bits() {
+    // a second frame with the reserved bit after the bits per sample set
+    let mut stream = crate::testing::FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![1; 16]]);
+    let mut spec = crate::testing::FrameSpec::verbatim(1, 16, &[vec![2; 16]]);
+    spec.number = 1;
+    let frame = spec.encode_with_header(|header| header[3] |= 1);
+    stream.frames.extend_from_slice(&frame);
+    stream.total_samples = 32;
+
+    let decode = |lenient_reserved_bits| {
+        let options = audio::DecodeOptions {
+            lenient_reserved_bits,
+            ..Default::default()
+        };
+        let mut segment = audio::AudioSegment::from_reader_with_options(
+            std::io::Cursor::new(stream.build()),
+            codecs::FormatFlag::FLAC,
+            options,
+        )
+        .unwrap();
+        let samples: Result<Vec<i32>> = segment.samples::<i32>().unwrap().collect();
+        samples
+    };
+    assert!(matches!(decode(false), Err(errors::Error::Unsupported(_))));
+    let mut expected = vec![1; 16];
+    expected.extend_from_slice(&[2; 16]);
+    assert_eq!(decode(true).unwrap(), expected);
+}
+
+#[test]
+fn test_small_footprint() {
+    use crate::audio::DecodeOptions;
+    use crate::testing::FlacStream;
+
+    // STREAMINFO declares 16 sample blocks, the second frame is larger
+    let mut stream = FlacStream::new(44_100, 2, 16);
+    let block = |len: usize, value: i32| vec![vec![value; len], vec![-value; len]];
+    stream
+        .frame(&block(16, 1))
+        .frame(&block(4_000, 2))
+        .frame(&block(16, 3));
+    stream.max_block_size = 16;
+    let read = |options: DecodeOptions| {
+        let input = std::io::Cursor::new(stream.build());
+        let segment =
+            audio::AudioSegment::from_reader_with_options(input, codecs::FormatFlag::FLAC, options)
+                .unwrap();
+        segment
+            .into_samples::<i32>()
+            .unwrap()
+            .collect::<Result<Vec<i32>>>()
+    };
+
+    let expected = read(DecodeOptions::default()).unwrap();
+    assert_eq!(expected.len(), 2 * 4_032);
+    assert_eq!(read(DecodeOptions::small_footprint()).unwrap(), expected);
+
+    // the oversized frame is rejected before it is decoded
+    let options = DecodeOptions {
+        max_block_size: 1_000,
+        ..DecodeOptions::small_footprint()
+    };
+    match read(options) {
+        Err(errors::Error::Unsupported(reason)) => assert!(reason.contains("max_block_size")),
+        result => panic!("expected the second frame to be rejected, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_check_subset() {
+    use crate::stats::{SubsetParameter, SubsetViolation};
+    use crate::testing::{FlacStream, FrameSpec, Subframe};
+
+    // frames of a ramp, which the predictors below predict exactly
+    let build = |sample_rate: u32, sample_rate_code: u8| {
+        let ramp = |len: usize| (0..len as i32).collect::<Vec<i32>>();
+        // a verbatim frame of `len` samples, unless `subframe` is given
+        let frame = |number: u64, subframe: Option<Subframe>, len: usize| {
+            let mut spec = FrameSpec::verbatim(number, 16, &[ramp(len)]);
+            spec.sample_rate_code = sample_rate_code;
+            if let Some(subframe) = subframe {
+                spec.subframes = vec![subframe];
+            }
+            spec
+        };
+        let mut coefficients = vec![0; 14];
+        coefficients[..2].copy_from_slice(&[2, -1]);
+        let lpc = Subframe::Lpc {
+            coefficients,
+            precision: 4,
+            shift: 0,
+            partition_order: 0,
+            rice_param: 0,
+            samples: ramp(64),
+        };
+        let fixed = Subframe::Fixed {
+            order: 2,
+            partition_order: 9,
+            rice_param: 0,
+            samples: ramp(5_120),
+        };
+        let mut stream = FlacStream::new(sample_rate, 1, 16);
+        stream
+            .push_frame(&frame(0, None, 64))
+            .push_frame(&frame(1, Some(lpc), 64))
+            .push_frame(&frame(2, Some(fixed), 5_120));
+        let mut last = frame(3, None, 64);
+        last.sample_rate_code = 0;
+        stream.push_frame(&last);
+        stream.build()
+    };
+    let check = |flac: Vec<u8>| {
+        let input = std::io::Cursor::new(flac);
+        let mut segment =
+            audio::AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap();
+        segment.check_subset().unwrap()
+    };
+    let violation = |frame: u64, sample_rate: u32, parameter: SubsetParameter| SubsetViolation {
+        frame,
+        sample_rate,
+        parameter,
+    };
+
+    let violations = check(build(44_100, 0b1001));
+    assert_eq!(
+        violations,
+        [
+            violation(
+                1,
+                44_100,
+                SubsetParameter::LpcOrder {
+                    subframe: 0,
+                    order: 14
+                }
+            ),
+            violation(2, 44_100, SubsetParameter::BlockSize(5_120)),
+            violation(
+                2,
+                44_100,
+                SubsetParameter::PartitionOrder {
+                    subframe: 0,
+                    order: 9
+                }
+            ),
+            violation(3, 44_100, SubsetParameter::SampleRateFromStreamInfo),
+        ]
+    );
+    assert_eq!(
+        violations[0].to_string(),
+        "frame 1: LPC order 14 in subframe 0"
+    );
+
+    // above 48 kHz the block size and LPC order may be larger
+    let violations = check(build(96_000, 0b1011));
+    assert_eq!(
+        violations,
+        [
+            violation(
+                2,
+                96_000,
+                SubsetParameter::PartitionOrder {
+                    subframe: 0,
+                    order: 9
+                }
+            ),
+            violation(3, 96_000, SubsetParameter::SampleRateFromStreamInfo),
+        ]
+    );
 }
@@ -0,0 +1,212 @@
+//! An index of the frames of a FLAC stream, for seeking without a SEEKTABLE.
+//!
+//! Building an index scans the whole stream, so it can be exported and loaded
+//! again later. The encoding starts with a fingerprint of the stream, the
+//! length of the input, the end of the header, the length from STREAMINFO and
+//! its MD5 signature, and an index whose fingerprint differs is rejected:
+//!
+//! | bytes | field                                          |
+//! |-------|------------------------------------------------|
+//! | 4     | `fLIX`                                         |
+//! | 1     | version, 1                                     |
+//! | 8     | length of the input                            |
+//! | 8     | offset of the first frame                      |
+//! | 8     | total samples of STREAMINFO, of all channels   |
+//! | 16    | MD5 signature of STREAMINFO                    |
+//! | 4     | number of frames                               |
+//! | 16 n  | offset and first inter-channel sample of each  |
+//!
+//! All numbers are little endian.
+
+use crate::{audio, errors, Result};
+
+const MAGIC: &[u8; 4] = b"fLIX";
+
+const VERSION: u8 = 1;
+
+/// length of the encoding before the frames
+const HEADER_LEN: usize = 4 + 1 + 8 + 8 + 8 + 16 + 4;
+
+/// What an index is valid for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub input_len: u64,
+    pub frames_start: u64,
+    pub total_samples: u64,
+    pub md5: [u8; 16],
+}
+
+impl Fingerprint {
+    pub fn new(info: &audio::AudioInfo, input_len: u64, frames_start: u64) -> Fingerprint {
+        Fingerprint {
+            input_len,
+            frames_start,
+            total_samples: info.total_samples,
+            md5: info.flac_stream_info.as_ref().map_or([0; 16], |s| s.md5),
+        }
+    }
+}
+
+/// The frame which a seek starts decoding at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SeekPoint {
+    /// offset of the sync code of the frame in the input
+    pub offset: u64,
+    /// index of the first inter-channel sample of the frame
+    pub sample: u64,
+}
+
+/// The offset of every frame of a stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeekIndex {
+    fingerprint: Fingerprint,
+    /// in the order of the stream
+    points: Vec<SeekPoint>,
+}
+
+impl SeekIndex {
+    /// `frames` are the offsets and first samples of `frame::index_frames`
+    pub fn new(fingerprint: Fingerprint, frames: &[(u64, u64)]) -> SeekIndex {
+        let points = frames
+            .iter()
+            .map(|&(offset, sample)| SeekPoint { offset, sample })
+            .collect();
+        SeekIndex {
+            fingerprint,
+            points,
+        }
+    }
+
+    /// Returns the last frame starting at or before the inter-channel sample `frame`
+    pub fn find(&self, frame: u64) -> Option<SeekPoint> {
+        let after = self.points.partition_point(|p| p.sample <= frame);
+        after.checked_sub(1).map(|i| self.points[i])
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + 16 * self.points.len());
+        let fingerprint = &self.fingerprint;
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&fingerprint.input_len.to_le_bytes());
+        bytes.extend_from_slice(&fingerprint.frames_start.to_le_bytes());
+        bytes.extend_from_slice(&fingerprint.total_samples.to_le_bytes());
+        bytes.extend_from_slice(&fingerprint.md5);
+        bytes.extend_from_slice(&(self.points.len() as u32).to_le_bytes());
+        for point in &self.points {
+            bytes.extend_from_slice(&point.offset.to_le_bytes());
+            bytes.extend_from_slice(&point.sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes an index of `encode`, which has to be of the stream of `expected`
+    ///
+    /// Any index which does not fit fails with `Error::ParseError`. The frames
+    /// have to start after each other between the header and the end of the
+    /// input, nothing else is trusted.
+    pub fn decode(bytes: &[u8], expected: &Fingerprint) -> Result<SeekIndex> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return errors::parse_error("not a FLAC seek index");
+        }
+        if bytes[4] != VERSION {
+            return errors::parse_error("FLAC seek index of an unknown version");
+        }
+        let u64_at = |at: usize| {
+            let mut field = [0u8; 8];
+            field.copy_from_slice(&bytes[at..at + 8]);
+            u64::from_le_bytes(field)
+        };
+        let mut md5 = [0u8; 16];
+        md5.copy_from_slice(&bytes[29..45]);
+        let fingerprint = Fingerprint {
+            input_len: u64_at(5),
+            frames_start: u64_at(13),
+            total_samples: u64_at(21),
+            md5,
+        };
+        if fingerprint != *expected {
+            return errors::parse_error("the seek index is of another stream");
+        }
+
+        let count = u32::from_le_bytes([bytes[45], bytes[46], bytes[47], bytes[48]]) as usize;
+        if bytes.len() != HEADER_LEN + 16 * count {
+            return errors::parse_error("the seek index is truncated");
+        }
+        let points: Vec<SeekPoint> = (0..count)
+            .map(|i| SeekPoint {
+                offset: u64_at(HEADER_LEN + 16 * i),
+                sample: u64_at(HEADER_LEN + 16 * i + 8),
+            })
+            .collect();
+        let in_input = |p: &SeekPoint| {
+            p.offset >= fingerprint.frames_start && p.offset < fingerprint.input_len
+        };
+        let ordered = points
+            .windows(2)
+            .all(|w| w[0].offset < w[1].offset && w[0].sample < w[1].sample);
+        if !ordered || !points.iter().all(in_input) {
+            return errors::parse_error("the seek index has frames out of order");
+        }
+        Ok(SeekIndex {
+            fingerprint,
+            points,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_index() -> (SeekIndex, Fingerprint) {
+    let fingerprint = Fingerprint {
+        input_len: 10_000,
+        frames_start: 42,
+        total_samples: 8_192,
+        md5: *b"0123456789abcdef",
+    };
+    let frames = [(42, 0), (1_000, 4_096), (5_000, 8_192)];
+    (SeekIndex::new(fingerprint, &frames), fingerprint)
+}
+
+#[test]
+fn test_round_trip() {
+    let (index, fingerprint) = test_index();
+    let bytes = index.encode();
+    assert_eq!(bytes.len(), HEADER_LEN + 3 * 16);
+    assert_eq!(SeekIndex::decode(&bytes, &fingerprint).unwrap(), index);
+
+    assert_eq!(index.find(0).unwrap().offset, 42);
+    assert_eq!(index.find(4_095).unwrap().offset, 42);
+    assert_eq!(index.find(4_096).unwrap().offset, 1_000);
+    assert_eq!(index.find(100_000).unwrap().sample, 8_192);
+}
+
+#[test]
+fn test_rejected_indexes() {
+    let (index, fingerprint) = test_index();
+    let bytes = index.encode();
+    let rejected =
+        |bytes: &[u8], fingerprint: &Fingerprint| match SeekIndex::decode(bytes, fingerprint) {
+            Err(errors::Error::ParseError(msg)) => msg,
+            other => panic!("expected the index to be rejected, got {:?}", other),
+        };
+
+    // another length of the input, or another MD5 signature
+    let mut longer = fingerprint;
+    longer.input_len += 1;
+    assert!(rejected(&bytes, &longer).contains("another stream"));
+    let mut corrupt = bytes.clone();
+    corrupt[30] ^= 1;
+    assert!(rejected(&corrupt, &fingerprint).contains("another stream"));
+
+    assert!(rejected(&bytes[..bytes.len() - 1], &fingerprint).contains("truncated"));
+    assert!(rejected(&bytes[1..], &fingerprint).contains("not a FLAC seek index"));
+    // a frame beyond the end of the input
+    let mut beyond = bytes.clone();
+    let last = HEADER_LEN + 2 * 16;
+    beyond[last..last + 8].copy_from_slice(&20_000u64.to_le_bytes());
+    assert!(rejected(&beyond, &fingerprint).contains("out of order"));
+
+    let mut newer = bytes;
+    newer[4] = 2;
+    assert!(rejected(&newer, &fingerprint).contains("unknown version"));
+}
@@ -0,0 +1,670 @@
+//! A FLAC encoder predicting with the fixed polynomials.
+//!
+//! Every frame but the last holds `BLOCK_SIZE` inter-channel samples. Each
+//! subframe is the smallest of a constant, verbatim or fixed predictor
+//! subframe, with the residual Rice coded in the partitioning estimated the
+//! smallest. Stereo frames pick the smallest of the four channel assignments.
+//! https://xiph.org/flac/format.html
+
+use std::convert::TryFrom;
+use std::io;
+
+use crate::crc::{crc16_flac, crc8_flac};
+use crate::md5::Md5;
+use crate::{errors, Result};
+
+/// number of inter-channel samples of every frame but the last
+pub(crate) const BLOCK_SIZE: usize = 4096;
+
+/// highest order of the fixed predictors
+const MAX_FIXED_ORDER: usize = 4;
+
+/// highest partition order tried for the residual
+const MAX_PARTITION_ORDER: u32 = 8;
+
+/// highest Rice parameter of the 4 bit parameters, 15 is the escape code
+const MAX_RICE_PARAM: u32 = 14;
+
+/// highest Rice parameter of the 5 bit parameters, 31 is the escape code
+const MAX_RICE2_PARAM: u32 = 30;
+
+/// Writes values most significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// bits not yet written to `bytes`, in the low `pending` bits
+    accumulator: u64,
+    pending: u32,
+}
+
+impl BitWriter {
+    /// Writes the `bits` least significant bits of `value`, at most 32
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = self.accumulator << bits | (value & ((1 << bits) - 1));
+        self.pending += bits;
+        while self.pending >= 8 {
+            self.pending -= 8;
+            self.bytes.push((self.accumulator >> self.pending) as u8);
+        }
+    }
+
+    /// Writes `n` zeros followed by a one
+    fn write_unary(&mut self, mut n: u64) {
+        while n >= 32 {
+            self.write(0, 32);
+            n -= 32;
+        }
+        self.write(1, n as u32 + 1);
+    }
+
+    /// Pads with zeros up to the next byte boundary
+    fn align(&mut self) {
+        if self.pending > 0 {
+            self.write(0, 8 - self.pending);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.bytes.clear();
+        self.pending = 0;
+    }
+}
+
+/// How the channels of a frame are stored
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ChannelAssignment {
+    Independent,
+    LeftSide,
+    RightSide,
+    MidSide,
+}
+
+/// A subframe chosen for a channel of a frame
+#[derive(Debug, PartialEq)]
+enum Subframe {
+    Constant(i32),
+    Verbatim,
+    Fixed {
+        order: usize,
+        /// the residual of the prediction, wrapped to 32 bits like the decoder does
+        residual: Vec<i32>,
+        partition_order: u32,
+        /// the Rice parameter of each partition
+        params: Vec<u32>,
+    },
+}
+
+/// A subframe with the samples it encodes
+struct PlannedSubframe {
+    subframe: Subframe,
+    /// the samples shifted right by `wasted_bits`
+    samples: Vec<i32>,
+    bits_per_sample: u32,
+    wasted_bits: u32,
+    /// the estimated size in bits
+    cost: u64,
+}
+
+/// Writes a FLAC stream of raw integer samples
+///
+/// The stream starts with a STREAMINFO block declaring `total_frames`, which
+/// `finish` corrects to the inter-channel samples written, together with the
+/// frame sizes and the MD5 signature. Hence `writer` has to seek.
+pub(crate) struct Encoder<W: io::Write + io::Seek> {
+    writer: W,
+    /// position of the `fLaC` marker in `writer`
+    start: u64,
+    sample_rate: u32,
+    channels: usize,
+    bits_per_sample: u32,
+    /// interleaved samples of the frame not yet written
+    pending: Vec<i32>,
+    /// number of frames written so far
+    frame_number: u64,
+    /// number of inter-channel samples written so far
+    written: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    md5: Md5,
+    /// bytes of the frame being written
+    bits: BitWriter,
+}
+
+impl<W: io::Write + io::Seek> Encoder<W> {
+    /// Writes the marker and the STREAMINFO block of a stream
+    ///
+    /// FLAC holds 1 to 8 channels of 4 to 32 bits per sample, at a sample rate
+    /// of up to 655350 Hz.
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+        total_frames: u64,
+    ) -> Result<Encoder<W>> {
+        if !(1..=8).contains(&channels) {
+            return errors::unsupported_error("FLAC holds 1 to 8 channels");
+        }
+        if !(4..=32).contains(&bits_per_sample) {
+            return errors::unsupported_error("FLAC holds 4 to 32 bits per sample");
+        }
+        if sample_rate == 0 || sample_rate > 655_350 {
+            return errors::unsupported_error("FLAC holds sample rates of 1 to 655350 Hz");
+        }
+        let start = writer.stream_position()?;
+        let mut encoder = Encoder {
+            writer,
+            start,
+            sample_rate,
+            channels,
+            bits_per_sample,
+            pending: Vec::with_capacity(BLOCK_SIZE * channels),
+            frame_number: 0,
+            written: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+            md5: Md5::new(),
+            bits: BitWriter::default(),
+        };
+        encoder.writer.write_all(b"fLaC")?;
+        let stream_info = encoder.stream_info(total_frames, [0; 16]);
+        encoder.writer.write_all(&stream_info)?;
+        Ok(encoder)
+    }
+
+    /// Encodes interleaved `samples`, each in the range of the bits per sample
+    pub fn write_samples(&mut self, mut samples: &[i32]) -> Result<()> {
+        let block_len = BLOCK_SIZE * self.channels;
+        while !samples.is_empty() {
+            let n = samples.len().min(block_len - self.pending.len());
+            self.pending.extend_from_slice(&samples[..n]);
+            samples = &samples[n..];
+            if self.pending.len() == block_len {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the last frame and the final STREAMINFO block
+    ///
+    /// Returns the writer, positioned after the stream, and the number of
+    /// inter-channel samples written.
+    pub fn finish(mut self) -> Result<(W, u64)> {
+        // a partial inter-channel sample is dropped
+        let whole = self.pending.len() / self.channels * self.channels;
+        self.pending.truncate(whole);
+        if !self.pending.is_empty() {
+            self.write_frame()?;
+        }
+        let end = self.writer.stream_position()?;
+        let md5 = std::mem::take(&mut self.md5).finish();
+        let stream_info = self.stream_info(self.written, md5);
+        self.writer.seek(io::SeekFrom::Start(self.start + 4))?;
+        self.writer.write_all(&stream_info)?;
+        self.writer.seek(io::SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok((self.writer, self.written))
+    }
+
+    /// Returns the STREAMINFO block, the last one of the metadata
+    fn stream_info(&self, total_frames: u64, md5: [u8; 16]) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.write(1, 1);
+        bits.write(0, 7);
+        bits.write(34, 24);
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(self.min_frame_size as u64, 24);
+        bits.write(self.max_frame_size as u64, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(self.bits_per_sample as u64 - 1, 5);
+        // a length which does not fit is unknown
+        let total_frames = if total_frames >> 36 == 0 {
+            total_frames
+        } else {
+            0
+        };
+        bits.write(total_frames >> 32, 4);
+        bits.write(total_frames & 0xffff_ffff, 32);
+        bits.bytes.extend_from_slice(&md5);
+        bits.bytes
+    }
+
+    /// Encodes the pending samples as a frame
+    fn write_frame(&mut self) -> Result<()> {
+        if self.frame_number >= 1 << 31 {
+            return errors::unsupported_error("audio is too long for a FLAC stream");
+        }
+        let block_size = self.pending.len() / self.channels;
+        let width = self.bits_per_sample.div_ceil(8) as usize;
+        let mut signature = Vec::with_capacity(self.pending.len() * width);
+        for sample in &self.pending {
+            signature.extend_from_slice(&sample.to_le_bytes()[..width]);
+        }
+        self.md5.update(&signature);
+
+        let channels: Vec<Vec<i32>> = (0..self.channels)
+            .map(|ch| {
+                self.pending[ch..]
+                    .iter()
+                    .step_by(self.channels)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        let bps = self.bits_per_sample;
+        let (assignment, subframes) = if self.channels == 2 && bps < 32 {
+            plan_stereo(&channels[0], &channels[1], bps)
+        } else {
+            let subframes = channels.iter().map(|c| plan_subframe(c, bps)).collect();
+            (ChannelAssignment::Independent, subframes)
+        };
+
+        self.bits.clear();
+        self.bits.write(0b1111_1111_1111_1000, 16);
+        let (size_code, size_bits) = match block_size {
+            BLOCK_SIZE => (0b1100, 0),
+            1..=256 => (0b0110, 8),
+            _ => (0b0111, 16),
+        };
+        self.bits.write(size_code, 4);
+        // the sample rate and the sample size are the ones of STREAMINFO
+        self.bits.write(0, 4);
+        let assignment_code = match assignment {
+            ChannelAssignment::Independent => self.channels as u64 - 1,
+            ChannelAssignment::LeftSide => 0b1000,
+            ChannelAssignment::RightSide => 0b1001,
+            ChannelAssignment::MidSide => 0b1010,
+        };
+        self.bits.write(assignment_code, 4);
+        self.bits.write(0, 4);
+        write_utf8_coded(&mut self.bits, self.frame_number);
+        self.bits.write(block_size as u64 - 1, size_bits);
+        let crc = crc8_flac(&self.bits.bytes);
+        self.bits.write(crc as u64, 8);
+
+        for subframe in &subframes {
+            write_subframe(&mut self.bits, subframe);
+        }
+        self.bits.align();
+        let crc = crc16_flac(&self.bits.bytes);
+        self.bits.write(crc as u64, 16);
+
+        self.writer.write_all(&self.bits.bytes)?;
+        let frame_size = self.bits.bytes.len() as u32;
+        if self.frame_number == 0 || frame_size < self.min_frame_size {
+            self.min_frame_size = frame_size;
+        }
+        self.max_frame_size = self.max_frame_size.max(frame_size);
+        self.frame_number += 1;
+        self.written += block_size as u64;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Writes `value` with the UTF-8 like coding of FLAC frame numbers
+fn write_utf8_coded(bits: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        bits.write(value, 8);
+        return;
+    }
+    // number of 6 bit continuation bytes needed
+    let mut extra = 1;
+    while value >> (6 * extra) >= 1 << (6 - extra) {
+        extra += 1;
+    }
+    let lead = (0xff00u32 >> (extra + 1)) as u64 & 0xff;
+    bits.write(lead | (value >> (6 * extra)), 8);
+    for i in (0..extra).rev() {
+        bits.write(0b1000_0000 | ((value >> (6 * i)) & 0b0011_1111), 8);
+    }
+}
+
+/// Chooses the channel assignment of a stereo frame and its subframes
+fn plan_stereo(
+    left: &[i32],
+    right: &[i32],
+    bits_per_sample: u32,
+) -> (ChannelAssignment, Vec<PlannedSubframe>) {
+    let side: Vec<i32> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+    let mid: Vec<i32> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+    let left = plan_subframe(left, bits_per_sample);
+    let right = plan_subframe(right, bits_per_sample);
+    // the side channel takes a bit more
+    let side = plan_subframe(&side, bits_per_sample + 1);
+    let mid = plan_subframe(&mid, bits_per_sample);
+
+    let costs = [
+        left.cost + right.cost,
+        left.cost + side.cost,
+        side.cost + right.cost,
+        mid.cost + side.cost,
+    ];
+    let best = (0..4).min_by_key(|&i| costs[i]).unwrap_or(0);
+    match best {
+        0 => (ChannelAssignment::Independent, vec![left, right]),
+        1 => (ChannelAssignment::LeftSide, vec![left, side]),
+        2 => (ChannelAssignment::RightSide, vec![side, right]),
+        _ => (ChannelAssignment::MidSide, vec![mid, side]),
+    }
+}
+
+/// Chooses the smallest subframe for `source` of `bits_per_sample`
+fn plan_subframe(source: &[i32], bits_per_sample: u32) -> PlannedSubframe {
+    // subframe header: padding, type and wasted bits flag
+    let header = 8u64;
+    let first = source[0];
+    if source.iter().all(|&s| s == first) {
+        return PlannedSubframe {
+            subframe: Subframe::Constant(first),
+            samples: Vec::new(),
+            bits_per_sample,
+            wasted_bits: 0,
+            cost: header + bits_per_sample as u64,
+        };
+    }
+
+    let wasted_bits = source
+        .iter()
+        .fold(0i32, |bits, &s| bits | s)
+        .trailing_zeros()
+        .min(bits_per_sample - 1);
+    let samples: Vec<i32> = source.iter().map(|s| s >> wasted_bits).collect();
+    let bps = bits_per_sample - wasted_bits;
+    let header = header + wasted_bits as u64;
+
+    let mut best = PlannedSubframe {
+        subframe: Subframe::Verbatim,
+        cost: header + samples.len() as u64 * bps as u64,
+        samples: Vec::new(),
+        bits_per_sample: bps,
+        wasted_bits,
+    };
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+        let residual = match fixed_residual(order, &samples) {
+            Some(residual) => residual,
+            None => continue,
+        };
+        let (partition_order, params, residual_cost) = plan_residual(&residual, samples.len());
+        let cost = header + (order as u64 * bps as u64) + residual_cost;
+        if cost < best.cost {
+            best.cost = cost;
+            best.subframe = Subframe::Fixed {
+                order,
+                residual,
+                partition_order,
+                params,
+            };
+        }
+    }
+    best.samples = samples;
+    best
+}
+
+/// Returns the residual of the fixed predictor of `order`, `None` if it overflows 32 bits
+fn fixed_residual(order: usize, samples: &[i32]) -> Option<Vec<i32>> {
+    let coefficients: &[i64] = match order {
+        0 => &[],
+        1 => &[1],
+        2 => &[2, -1],
+        3 => &[3, -3, 1],
+        _ => &[4, -6, 4, -1],
+    };
+    (order..samples.len())
+        .map(|i| {
+            let prediction: i64 = coefficients
+                .iter()
+                .enumerate()
+                .map(|(j, c)| c * samples[i - j - 1] as i64)
+                .sum();
+            i32::try_from(samples[i] as i64 - prediction).ok()
+        })
+        .collect()
+}
+
+/// Folds a signed residual into the unsigned value which is Rice coded
+fn zigzag(residual: i32) -> u32 {
+    ((residual << 1) ^ (residual >> 31)) as u32
+}
+
+/// Chooses the partition order and the Rice parameters of `residual`
+///
+/// Returns them with the estimated size of the coded residual in bits.
+fn plan_residual(residual: &[i32], block_size: usize) -> (u32, Vec<u32>, u64) {
+    let order = block_size - residual.len();
+    let mut max_order = 0;
+    while max_order < MAX_PARTITION_ORDER
+        && block_size % (2 << max_order) == 0
+        && block_size >> (max_order + 1) >= order
+    {
+        max_order += 1;
+    }
+    // sums of the folded residual in the partitions of the highest order,
+    // merged pairwise for the lower ones
+    let partition_len = block_size >> max_order;
+    let mut sums: Vec<u64> = Vec::with_capacity(1 << max_order);
+    let mut lens: Vec<u64> = Vec::with_capacity(1 << max_order);
+    let mut start = 0;
+    for p in 0..1usize << max_order {
+        let len = if p == 0 {
+            partition_len - order
+        } else {
+            partition_len
+        };
+        let sum = residual[start..start + len]
+            .iter()
+            .map(|&r| zigzag(r) as u64)
+            .sum();
+        sums.push(sum);
+        lens.push(len as u64);
+        start += len;
+    }
+
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    let mut partition_order = max_order;
+    loop {
+        let (params, bits): (Vec<u32>, Vec<u64>) = sums
+            .iter()
+            .zip(&lens)
+            .map(|(&sum, &len)| rice_param(sum, len))
+            .unzip();
+        let param_bits = if params.iter().any(|&p| p > MAX_RICE_PARAM) {
+            5
+        } else {
+            4
+        };
+        let cost = 6 + bits.iter().sum::<u64>() + param_bits * params.len() as u64;
+        if best.as_ref().is_none_or(|best| cost < best.2) {
+            best = Some((partition_order, params, cost));
+        }
+        if partition_order == 0 {
+            break;
+        }
+        partition_order -= 1;
+        sums = sums.chunks(2).map(|pair| pair[0] + pair[1]).collect();
+        lens = lens.chunks(2).map(|pair| pair[0] + pair[1]).collect();
+    }
+    best.unwrap_or((0, vec![0], 0))
+}
+
+/// Returns the Rice parameter for a partition of `len` values summing to `sum`,
+/// with the estimated size of the partition in bits
+fn rice_param(sum: u64, len: u64) -> (u32, u64) {
+    (0..=MAX_RICE2_PARAM)
+        .map(|param| (param, len * (param as u64 + 1) + (sum >> param)))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+fn write_subframe(bits: &mut BitWriter, planned: &PlannedSubframe) {
+    let bps = planned.bits_per_sample;
+    let type_code = match planned.subframe {
+        Subframe::Constant(_) => 0b00_0000,
+        Subframe::Verbatim => 0b00_0001,
+        Subframe::Fixed { order, .. } => 0b00_1000 | order as u64,
+    };
+    let has_wasted_bits = planned.wasted_bits > 0;
+    bits.write(type_code << 1 | has_wasted_bits as u64, 8);
+    if has_wasted_bits {
+        bits.write_unary(planned.wasted_bits as u64 - 1);
+    }
+    let write_sample = |bits: &mut BitWriter, sample: i32| {
+        let mask = if bps == 32 { u32::MAX } else { (1 << bps) - 1 };
+        bits.write((sample as u32 & mask) as u64, bps);
+    };
+    match planned.subframe {
+        Subframe::Constant(value) => write_sample(bits, value),
+        Subframe::Verbatim => {
+            for &sample in &planned.samples {
+                write_sample(bits, sample);
+            }
+        }
+        Subframe::Fixed {
+            order,
+            ref residual,
+            partition_order,
+            ref params,
+        } => {
+            for &sample in &planned.samples[..order] {
+                write_sample(bits, sample);
+            }
+            let param_bits = if params.iter().any(|&p| p > MAX_RICE_PARAM) {
+                bits.write(0b01, 2);
+                5
+            } else {
+                bits.write(0b00, 2);
+                4
+            };
+            bits.write(partition_order as u64, 4);
+            let partition_len = planned.samples.len() >> partition_order;
+            let mut start = 0;
+            for (p, &param) in params.iter().enumerate() {
+                let len = if p == 0 {
+                    partition_len - order
+                } else {
+                    partition_len
+                };
+                bits.write(param as u64, param_bits);
+                for &r in &residual[start..start + len] {
+                    let folded = zigzag(r) as u64;
+                    bits.write_unary(folded >> param);
+                    bits.write(folded, param);
+                }
+                start += len;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn encode(samples: &[i32], sample_rate: u32, channels: usize, bits: u32) -> Vec<u8> {
+    let out = io::Cursor::new(Vec::new());
+    let mut encoder = Encoder::new(out, sample_rate, channels, bits, 0).unwrap();
+    encoder.write_samples(samples).unwrap();
+    encoder.finish().unwrap().0.into_inner()
+}
+
+#[cfg(test)]
+fn decode(file: Vec<u8>) -> (crate::audio::AudioInfo, Vec<i32>) {
+    let mut segment = crate::audio::AudioSegment::from_seekable_reader(
+        io::Cursor::new(file),
+        crate::codecs::FormatFlag::FLAC,
+    )
+    .unwrap();
+    let info = segment.info().clone();
+    let samples = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    (info, samples)
+}
+
+#[test]
+fn test_round_trip() {
+    // a tone, noise, silence and a frame of a partial block
+    let mut seed = 1u32;
+    let samples: Vec<i32> = (0..2 * BLOCK_SIZE as i32 * 3 + 2 * 1000)
+        .map(|i| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let frame = i / 2;
+            match frame / BLOCK_SIZE as i32 {
+                0 => ((frame as f64 * 0.05).sin() * 20_000.0) as i32 * (1 - 2 * (i % 2)),
+                1 => (seed >> 16) as i32 - 32_768,
+                _ => 0,
+            }
+        })
+        .collect();
+    let file = encode(&samples, 44_100, 2, 16);
+    let (info, decoded) = decode(file.clone());
+    assert_eq!(decoded, samples);
+    assert_eq!(info.total_samples, samples.len() as u64);
+    let stream_info = info.flac_stream_info.unwrap();
+    let bytes: Vec<u8> = samples
+        .iter()
+        .flat_map(|&s| (s as i16).to_le_bytes())
+        .collect();
+    assert_eq!(stream_info.md5, crate::md5::md5(&bytes));
+    assert!(stream_info.min_frame_size > 0);
+    assert!(stream_info.max_frame_size >= stream_info.min_frame_size);
+    // the tone and the silence compress
+    assert!(file.len() < samples.len() * 2 * 3 / 4);
+}
+
+#[test]
+fn test_round_trip_bit_depths() {
+    for &(bits, channels) in &[
+        (4u32, 1usize),
+        (8, 2),
+        (12, 3),
+        (20, 2),
+        (24, 6),
+        (32, 2),
+        (32, 1),
+    ] {
+        let max = ((1i64 << (bits - 1)) - 1) as i32;
+        let min = (-(1i64 << (bits - 1))) as i32;
+        let samples: Vec<i32> = (0..channels * 5000)
+            .map(|i| match i % 7 {
+                0 => max,
+                1 => min,
+                _ => ((i as i64 * 7919) % (max as i64 + 1)) as i32,
+            })
+            .collect();
+        let (info, decoded) = decode(encode(&samples, 96_000, channels, bits));
+        assert_eq!(info.bits_per_sample, bits);
+        assert_eq!(info.channels.count(), channels);
+        assert_eq!(decoded, samples, "{} bits, {} channels", bits, channels);
+    }
+}
+
+#[test]
+fn test_plan_subframe() {
+    assert_eq!(plan_subframe(&[5; 64], 16).subframe, Subframe::Constant(5));
+
+    let ramp: Vec<i32> = (0..64).map(|i| i * 3).collect();
+    assert!(matches!(
+        plan_subframe(&ramp, 16).subframe,
+        Subframe::Fixed { order: 2, .. }
+    ));
+
+    // samples of even values waste a bit
+    let even: Vec<i32> = (0..64).map(|i| (i * 7 % 13) * 2).collect();
+    assert_eq!(plan_subframe(&even, 16).wasted_bits, 1);
+}
+
+#[test]
+fn test_rejected_parameters() {
+    let new = |rate, channels, bits| {
+        Encoder::new(io::Cursor::new(Vec::new()), rate, channels, bits, 0).err()
+    };
+    assert!(new(44_100, 9, 16).is_some());
+    assert!(new(44_100, 0, 16).is_some());
+    assert!(new(44_100, 2, 3).is_some());
+    assert!(new(44_100, 2, 33).is_some());
+    assert!(new(700_000, 2, 16).is_some());
+    assert!(new(44_100, 8, 32).is_none());
+}
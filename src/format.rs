@@ -0,0 +1,539 @@
+//! The `format` module connects container formats to `AudioSegment`.
+//!
+//! A `Format` creates the reader of its container and the iterators decoding
+//! its samples. WAV, FLAC and MP3 are formats like any other, they are always
+//! known if their feature is enabled. Further formats are added with
+//! `register`, after which `AudioSegment` reads them by their `FormatFlag` or
+//! file extension and all of its iterators, exports and processing work on
+//! them.
+//!
+//! # Example
+//!
+//! A format storing 16 bit mono samples after the magic bytes `TOY1` and the
+//! sample rate:
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use cauldron::audio::{AudioInfo, AudioSegment, ChannelLayout};
+//! use cauldron::codecs::{CodecType, FormatFlag, SampleFormat};
+//! use cauldron::errors;
+//! use cauldron::format::{
+//!     self, AudioInputStream, AudioReader, AudioSamplesIterator, DecodeContext, Format,
+//!     ReadBuffer, SampleType, TypedSamples,
+//! };
+//! use cauldron::Result;
+//!
+//! struct ToyReader(AudioInputStream);
+//!
+//! impl AudioReader for ToyReader {
+//!     fn read_header(&mut self) -> Result<AudioInfo> {
+//!         if self.0.peek(4)? != b"TOY1" {
+//!             return Err(errors::Error::MissingMagic("no TOY1 tag found"));
+//!         }
+//!         self.0.skip_bytes(4)?;
+//!         Ok(AudioInfo {
+//!             codec_type: CodecType::CODEC_TYPE_PCM_S16LE,
+//!             sample_rate: self.0.read_le_u32()?,
+//!             total_samples: 0,
+//!             bits_per_sample: 16,
+//!             container_bits: 16,
+//!             sample_format: SampleFormat::S16,
+//!             channels: ChannelLayout::Mono.into_channels(),
+//!             channel_layout: ChannelLayout::Mono,
+//!             flac_stream_info: None,
+//!             encoder_delay: None,
+//!             encoder_padding: None,
+//!         })
+//!     }
+//!     fn buffer(&mut self) -> &mut AudioInputStream {
+//!         &mut self.0
+//!     }
+//!     fn into_buffer(self: Box<Self>) -> AudioInputStream {
+//!         self.0
+//!     }
+//! }
+//!
+//! struct ToySamples<'a>(&'a mut Box<dyn AudioReader>);
+//!
+//! impl<'a> AudioSamplesIterator<i32> for ToySamples<'a> {
+//!     fn next(&mut self) -> Option<Result<i32>> {
+//!         self.0.buffer().read_le_i16().ok().map(|s| Ok(s as i32))
+//!     }
+//! }
+//!
+//! struct Toy;
+//!
+//! impl Format for Toy {
+//!     fn flag(&self) -> FormatFlag {
+//!         FormatFlag::Custom(1)
+//!     }
+//!     fn extensions(&self) -> &[&str] {
+//!         &["toy"]
+//!     }
+//!     fn create_reader(
+//!         &self,
+//!         input: AudioInputStream,
+//!         _options: cauldron::audio::DecodeOptions,
+//!     ) -> Result<Box<dyn AudioReader>> {
+//!         Ok(Box::new(ToyReader(input)))
+//!     }
+//!     fn samples<'a>(
+//!         &self,
+//!         context: DecodeContext<'a>,
+//!         sample_type: SampleType,
+//!     ) -> Result<TypedSamples<'a>> {
+//!         let samples = Box::new(ToySamples(context.reader));
+//!         TypedSamples::convert_i32(samples, 16, sample_type)
+//!     }
+//! }
+//!
+//! format::register(Arc::new(Toy)).unwrap();
+//! let data: &[u8] = b"TOY1\x40\x1f\x00\x00\x00\x40\x00\xc0";
+//! let mut segment = AudioSegment::from_reader(data, FormatFlag::Custom(1)).unwrap();
+//! assert_eq!(segment.info().sample_rate, 8_000);
+//! let samples: Vec<f32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+//! assert_eq!(samples, vec![0.5, -0.5]);
+//! ```
+
+use std::cmp;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use crate::audio::{AudioInfo, DecodeOptions, SampleIterator};
+use crate::codecs::FormatFlag;
+use crate::errors;
+use crate::stats::{DecodeStats, FrameStats, InstrumentationHandle, SampleGap};
+use crate::Result;
+
+pub use crate::io::{
+    AudioInputStream, AudioReader, AudioSamplesIterator, DynamicBufReader, MediaSource, ReadBuffer,
+    ResettableDecoder, Sample,
+};
+
+/// A container format `AudioSegment` can decode
+pub trait Format: Send + Sync {
+    /// Returns the flag selecting the format, e.g. `FormatFlag::Custom`
+    fn flag(&self) -> FormatFlag;
+
+    /// Returns the file extensions `AudioSegment::read` takes for this format
+    fn extensions(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Returns true if `header` starts like a stream of this format
+    ///
+    /// `header` holds the first `DETECT_LEN` bytes of the stream, fewer if it
+    /// is shorter. Only `detect_format` asks this, a format which does not
+    /// implement it is never detected by it but can still be read.
+    fn matches(&self, _header: &[u8]) -> bool {
+        false
+    }
+
+    /// Returns true if `bytes`, taken from anywhere in a stream, start with a stream of this format
+    ///
+    /// `scan_for_audio` asks this at every offset with at least `SCAN_LOOKAHEAD`
+    /// bytes unless the stream ends before, so magic bytes which may occur by
+    /// chance should be verified further. Defaults to `matches`.
+    fn matches_embedded(&self, bytes: &[u8]) -> bool {
+        self.matches(bytes)
+    }
+
+    /// Creates the reader of the format on `input`, its header is read afterwards
+    ///
+    /// The reader should fail with `Error::MissingMagic` without consuming any
+    /// byte if the input is not of its format, so that other formats can be
+    /// tried on it.
+    fn create_reader(
+        &self,
+        input: AudioInputStream,
+        options: DecodeOptions,
+    ) -> Result<Box<dyn AudioReader>>;
+
+    /// Creates the iterator on the samples read by `context.reader`
+    ///
+    /// The iterator has to return samples of `sample_type`, see
+    /// `TypedSamples::convert_i32` for formats decoding to a single one.
+    fn samples<'a>(
+        &self,
+        context: DecodeContext<'a>,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>>;
+
+    /// Returns true if the iterators return only the channels of `context.channel_mask`
+    ///
+    /// Otherwise `AudioSegment` drops the other channels from all of them.
+    fn selects_channels(&self) -> bool {
+        false
+    }
+}
+
+/// What a `Format` decodes the samples of a segment with
+pub struct DecodeContext<'a> {
+    /// the reader created by the format, after reading the header
+    pub reader: &'a mut Box<dyn AudioReader>,
+    /// the info returned by the header
+    pub info: &'a AudioInfo,
+    pub options: DecodeOptions,
+    /// bit n is set if channel n is to be returned, `None` for all channels
+    pub channel_mask: Option<u8>,
+    /// true if the caller takes changes of the stream parameters, see `AudioSegment::events`
+    ///
+    /// Otherwise MP3 fails on a frame changing them.
+    pub stream_changes: bool,
+    /// the statistics of every frame, if enabled by `DecodeOptions`
+    #[cfg_attr(not(feature = "flac"), allow(dead_code))]
+    pub(crate) frame_stats: Option<&'a mut Vec<FrameStats>>,
+    /// the statistics summed up over the frames, if enabled by `DecodeOptions`
+    #[cfg_attr(not(any(feature = "flac", feature = "mp3")), allow(dead_code))]
+    pub(crate) decode_stats: Option<&'a mut DecodeStats>,
+    #[cfg_attr(not(feature = "flac"), allow(dead_code))]
+    pub(crate) wasted_bits: &'a mut Vec<u32>,
+    #[cfg_attr(not(feature = "flac"), allow(dead_code))]
+    pub(crate) sample_gaps: &'a mut Vec<SampleGap>,
+    /// the counters of the decoding throughput, if enabled by `DecodeOptions`
+    pub(crate) instrumentation: Option<InstrumentationHandle>,
+}
+
+/// The types samples can be decoded to, one for each `Sample`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleType {
+    U8,
+    I16,
+    I32,
+    F32,
+    F64,
+}
+
+/// An iterator on samples of one of the `SampleType`s
+pub enum TypedSamples<'a> {
+    U8(SampleIterator<'a, u8>),
+    I16(SampleIterator<'a, i16>),
+    I32(SampleIterator<'a, i32>),
+    F32(SampleIterator<'a, f32>),
+    F64(SampleIterator<'a, f64>),
+}
+
+impl<'a> TypedSamples<'a> {
+    /// Converts integer samples of `bits` bits to `sample_type`
+    pub fn convert_i32(
+        samples: SampleIterator<'a, i32>,
+        bits: u32,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>> {
+        if sample_type == SampleType::I32 {
+            return Ok(TypedSamples::I32(samples));
+        }
+        Ok(typed_samples!(sample_type, S => Box::new(FromI32::<S> {
+            samples,
+            bits,
+            phantom: std::marker::PhantomData,
+        })))
+    }
+
+    /// Converts float samples to `sample_type`
+    pub fn convert_f32(
+        samples: SampleIterator<'a, f32>,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>> {
+        if sample_type == SampleType::F32 {
+            return Ok(TypedSamples::F32(samples));
+        }
+        Ok(typed_samples!(sample_type, S => Box::new(FromF32::<S> {
+            samples,
+            phantom: std::marker::PhantomData,
+        })))
+    }
+}
+
+struct FromI32<'a, S> {
+    samples: SampleIterator<'a, i32>,
+    bits: u32,
+    phantom: std::marker::PhantomData<S>,
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for FromI32<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        let sample = otry!(self.samples.next()?);
+        Some(S::from_i32(sample, self.bits))
+    }
+}
+
+struct FromF32<'a, S> {
+    samples: SampleIterator<'a, f32>,
+    phantom: std::marker::PhantomData<S>,
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for FromF32<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        let sample = otry!(self.samples.next()?);
+        Some(S::from_f32(sample))
+    }
+}
+
+/// The number of bytes `detect_format` looks at
+pub const DETECT_LEN: usize = 256;
+
+/// Returns the format of the stream read by `input`, without consuming any of it
+///
+/// Only the first `DETECT_LEN` bytes are peeked at and checked by `Format::matches`
+/// of the enabled formats, followed by the registered ones. Nothing is parsed
+/// beyond that, so this is much cheaper than creating an `AudioSegment` when
+/// only the format is wanted. Fails with `Error::UnrecognizedFormat` listing the
+/// formats tried if none matched.
+///
+/// ```
+/// use cauldron::codecs::FormatFlag;
+/// use cauldron::format::DynamicBufReader;
+///
+/// # #[cfg(feature = "flac")]
+/// # {
+/// let mut input = DynamicBufReader::new(&b"fLaC\0\0\0\x22"[..]);
+/// assert_eq!(cauldron::detect_format(&mut input).unwrap(), FormatFlag::FLAC);
+/// # }
+/// ```
+pub fn detect_format<R: io::Read>(input: &mut DynamicBufReader<R>) -> Result<FormatFlag> {
+    let header = input.peek(DETECT_LEN)?;
+    let mut tried = Vec::new();
+    for format in formats() {
+        if format.matches(header) {
+            return Ok(format.flag());
+        }
+        tried.push(format.flag());
+    }
+    Err(errors::Error::UnrecognizedFormat(tried))
+}
+
+/// The number of bytes `scan_for_audio` passes to `Format::matches_embedded` at least
+pub const SCAN_LOOKAHEAD: usize = 4 * 1024;
+
+/// The number of bytes `scan_for_audio` peeks at once
+const SCAN_CHUNK: usize = 16 * 1024;
+
+/// Searches the next `max_scan_bytes` bytes of `input` for the start of an audio stream
+///
+/// This finds audio embedded at an unknown offset, e.g. in a firmware blob or
+/// after a proprietary header. Every offset is checked by
+/// `Format::matches_embedded` of the enabled formats, followed by the
+/// registered ones, which verify more than the magic bytes. The bytes before
+/// the match are consumed, so the stream can be read as the returned format
+/// right away, and the offset of the match from the start of the stream is
+/// returned. Only the buffer of `input` is used, however far the scan goes.
+/// Fails with `Error::UnrecognizedFormat` listing the formats tried if none
+/// matched, the stream is left at the end of the scanned bytes then.
+///
+/// ```
+/// use cauldron::audio::AudioSegment;
+/// use cauldron::codecs::FormatFlag;
+/// use cauldron::format::{self, DynamicBufReader};
+///
+/// # #[cfg(feature = "flac")]
+/// # {
+/// let data: &[u8] = b"firmware header\0fLaC\0\0\0\x22";
+/// let mut input = DynamicBufReader::new(data);
+/// let (offset, flag) = format::scan_for_audio(&mut input, 1024).unwrap();
+/// assert_eq!((offset, flag), (16, FormatFlag::FLAC));
+/// # }
+/// ```
+pub fn scan_for_audio<R: io::Read>(
+    input: &mut DynamicBufReader<R>,
+    max_scan_bytes: u64,
+) -> Result<(u64, FormatFlag)> {
+    let formats = formats();
+    let last = input.position().saturating_add(max_scan_bytes);
+    loop {
+        let scannable = (last - input.position()).saturating_add(1);
+        let window = input.peek(SCAN_CHUNK)?;
+        let at_end = window.len() < SCAN_CHUNK;
+        // offsets closer to the end are checked with the next chunk
+        let checked = if at_end {
+            window.len()
+        } else {
+            SCAN_CHUNK - SCAN_LOOKAHEAD
+        };
+        let checked = cmp::min(checked as u64, scannable) as usize;
+        let found = (0..checked).find_map(|offset| {
+            let bytes = &window[offset..];
+            formats
+                .iter()
+                .find(|f| f.matches_embedded(bytes))
+                .map(|f| (offset, f.flag()))
+        });
+        if let Some((offset, flag)) = found {
+            input.skip_bytes(offset)?;
+            return Ok((input.position(), flag));
+        }
+        input.skip_bytes(checked)?;
+        if at_end || input.position() > last {
+            return Err(errors::Error::UnrecognizedFormat(
+                formats.iter().map(|f| f.flag()).collect(),
+            ));
+        }
+    }
+}
+
+/// the formats added by `register`, in the order they were added
+static REGISTERED: RwLock<Vec<Arc<dyn Format>>> = RwLock::new(Vec::new());
+
+/// Adds a format, which is used by every `AudioSegment` created afterwards
+///
+/// Fails with `Error::Unsupported` if a format with the same flag is known
+/// already, built-in ones included.
+pub fn register(format: Arc<dyn Format>) -> Result<()> {
+    let mut registered = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+    let flag = format.flag();
+    if builtin()
+        .iter()
+        .chain(registered.iter())
+        .any(|f| f.flag() == flag)
+    {
+        return errors::unsupported_error("a format with this flag is registered already");
+    }
+    registered.push(format);
+    Ok(())
+}
+
+/// Returns the built-in formats which are enabled, in the order they are probed
+pub(crate) fn builtin() -> Vec<Arc<dyn Format>> {
+    vec![
+        #[cfg(feature = "wav")]
+        Arc::new(crate::wav::WavFormat),
+        #[cfg(feature = "flac")]
+        Arc::new(crate::flac::FlacFormat),
+        #[cfg(feature = "mp3")]
+        Arc::new(crate::mp3::Mp3Format),
+    ]
+}
+
+/// Returns every known format, the built-in ones first
+pub(crate) fn formats() -> Vec<Arc<dyn Format>> {
+    let mut formats = builtin();
+    formats.extend(
+        REGISTERED
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned(),
+    );
+    formats
+}
+
+/// Returns the format of `flag`
+pub(crate) fn find(flag: FormatFlag) -> Result<Arc<dyn Format>> {
+    if let Some(format) = formats().into_iter().find(|f| f.flag() == flag) {
+        return Ok(format);
+    }
+    match flag {
+        FormatFlag::WAV => errors::unsupported_error("WAV support requires the `wav` feature"),
+        FormatFlag::FLAC => errors::unsupported_error("FLAC support requires the `flac` feature"),
+        FormatFlag::MP3 => errors::unsupported_error("MP3 support requires the `mp3` feature"),
+        _ => errors::unsupported_error("Codec flag not supported"),
+    }
+}
+
+/// Returns the flag of the format taking the file extension `extension`
+pub(crate) fn find_extension(extension: &str) -> Option<FormatFlag> {
+    formats()
+        .iter()
+        .find(|f| f.extensions().contains(&extension))
+        .map(|f| f.flag())
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac", feature = "mp3"))]
+fn test_detect_format() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file, FlacStream};
+
+    let wav = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"data", &[0; 4]),
+    ]);
+    let flac = FlacStream::new(8_000, 1, 16).build();
+    let streams: [(&[u8], FormatFlag); 4] = [
+        (&wav, FormatFlag::WAV),
+        (&flac, FormatFlag::FLAC),
+        (b"ID3\x04\x00", FormatFlag::MP3),
+        (&[0xff, 0xfb, 0x90, 0x00], FormatFlag::MP3),
+    ];
+    for (data, flag) in streams.iter() {
+        let mut input = crate::io::read_only_stream(std::io::Cursor::new(data.to_vec()));
+        assert_eq!(detect_format(&mut input).unwrap(), *flag);
+        // nothing was consumed, the stream can be read as the detected format
+        assert_eq!(input.position(), 0);
+        let mut reader = find(*flag)
+            .unwrap()
+            .create_reader(input, DecodeOptions::default());
+        assert!(reader.as_mut().unwrap().read_header().is_ok());
+    }
+
+    let mut input = DynamicBufReader::new(&b"RIFF\0\0\0\0AVI "[..]);
+    match detect_format(&mut input) {
+        Err(errors::Error::UnrecognizedFormat(tried)) => assert_eq!(
+            tried[..3],
+            [FormatFlag::WAV, FormatFlag::FLAC, FormatFlag::MP3]
+        ),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac", feature = "mp3"))]
+fn test_scan_for_audio() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file, FlacStream};
+
+    let wav = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"data", &[1, 0, 2, 0]),
+    ]);
+    let mut flac = FlacStream::new(8_000, 1, 16);
+    flac.frame(&[vec![1, 2, 3, 4]]);
+    let flac = flac.build();
+    // two MPEG1 layer 3 frames of 128 kbps at 44.1 kHz, 417 bytes each
+    let mut mp3 = Vec::new();
+    for _ in 0..2 {
+        mp3.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        mp3.extend_from_slice(&[0; 413]);
+    }
+    mp3.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+
+    // magic bytes which are not followed by a stream, then bytes without any
+    let mut decoys = b"RIFF\0\0\0\0AVI fLaC\0\0\0\x10ID3\xff".to_vec();
+    decoys.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00, 0xff, 0xfb]);
+    let streams: [(&[u8], FormatFlag); 3] = [
+        (&wav, FormatFlag::WAV),
+        (&flac, FormatFlag::FLAC),
+        (&mp3, FormatFlag::MP3),
+    ];
+    for (data, flag) in streams.iter() {
+        for &offset in &[0, 1, 4_095, SCAN_CHUNK - SCAN_LOOKAHEAD + 1, 20_001] {
+            let filler = (0..).map(|i| (i % 251) as u8);
+            let mut prefix: Vec<u8> = decoys.iter().copied().chain(filler).take(offset).collect();
+            prefix.extend_from_slice(data);
+            let mut input = crate::io::read_only_stream(std::io::Cursor::new(prefix));
+            assert_eq!(
+                scan_for_audio(&mut input, 1 << 20).unwrap(),
+                (offset as u64, *flag)
+            );
+            let mut segment = crate::audio::AudioSegment::read_with_format(input, *flag).unwrap();
+            assert!(segment.samples::<f32>().is_ok());
+        }
+    }
+
+    // the offset of the stream is the last one scanned
+    let mut data = vec![0; 5_000];
+    data.extend_from_slice(&flac);
+    let mut input = DynamicBufReader::new(&data[..]);
+    match scan_for_audio(&mut input, 4_999) {
+        Err(errors::Error::UnrecognizedFormat(tried)) => assert_eq!(
+            tried[..3],
+            [FormatFlag::WAV, FormatFlag::FLAC, FormatFlag::MP3]
+        ),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(input.position(), 5_000);
+    let mut input = DynamicBufReader::new(&data[..]);
+    assert_eq!(
+        scan_for_audio(&mut input, 5_000).unwrap(),
+        (5_000, FormatFlag::FLAC)
+    );
+}
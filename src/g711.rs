@@ -0,0 +1,125 @@
+//! The `g711` module converts between 16 bit samples and G.711 A-law and µ-law.
+//!
+//! Both companding laws store a sample in a byte, as a sign, a 3 bit segment
+//! and a 4 bit step within the segment. The steps double from one segment to
+//! the next, so the quantization error grows with the magnitude of the sample,
+//! it stays below 1/32 of it outside of the first segments. The encoders
+//! follow ITU-T G.711 and agree with the reference implementation bit for bit.
+
+/// added to the magnitude before µ-law encoding, so the segments start at powers of two
+const ULAW_BIAS: i32 = 0x84;
+
+/// the largest magnitude µ-law can encode, larger ones are clipped
+const ULAW_CLIP: i32 = 32_635;
+
+/// Encodes a sample in µ-law
+///
+/// The error of `decode_ulaw(encode_ulaw(x))` is at most `(|x| + 132) / 32`
+/// for magnitudes up to 32635, which is where µ-law clips.
+pub fn encode_ulaw(sample: i16) -> u8 {
+    let sign = if sample < 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(ULAW_CLIP) + ULAW_BIAS;
+    // the biased magnitude is at least 0x84, its highest bit gives the segment
+    let exponent = 7 - ((magnitude >> 7) as u8).leading_zeros();
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+/// Decodes a µ-law sample, the result is at most 32124 in magnitude
+pub fn decode_ulaw(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = ((byte >> 4) & 0x07) as u32;
+    let mantissa = (byte & 0x0f) as i32;
+    // the middle of the step, removing the bias again
+    let magnitude = (((mantissa << 3) + ULAW_BIAS) << exponent) - ULAW_BIAS;
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Encodes a sample in A-law
+///
+/// A-law keeps 13 bits of the sample. The error of
+/// `decode_alaw(encode_alaw(x))` is at most `|x| / 32 + 16`.
+pub fn encode_alaw(sample: i16) -> u8 {
+    // the even bits are inverted, the sign bit is set for positive samples
+    let (mask, magnitude) = if sample >= 0 {
+        (0xd5, (sample >> 3) as i32)
+    } else {
+        (0x55, -((sample >> 3) as i32) - 1)
+    };
+    // the magnitude has at most 12 bits, segment n ends at 2^(n + 5) - 1
+    let segment = (32 - (magnitude as u32 | 0x1f).leading_zeros()) - 5;
+    let step = if segment < 2 {
+        (magnitude >> 1) & 0x0f
+    } else {
+        (magnitude >> segment) & 0x0f
+    };
+    ((segment << 4) as u8 | step as u8) ^ mask
+}
+
+/// Decodes an A-law sample, the result is at most 32256 in magnitude
+pub fn decode_alaw(byte: u8) -> i16 {
+    let byte = byte ^ 0x55;
+    let segment = ((byte & 0x70) >> 4) as u32;
+    // the middle of the step
+    let mut magnitude = ((byte & 0x0f) as i32) << 4;
+    magnitude += match segment {
+        0 => 8,
+        _ => 0x108,
+    };
+    if segment > 1 {
+        magnitude <<= segment - 1;
+    }
+    if byte & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+#[test]
+fn test_ulaw_round_trip() {
+    for x in -ULAW_CLIP..=ULAW_CLIP {
+        let decoded = decode_ulaw(encode_ulaw(x as i16)) as i32;
+        assert!(
+            (decoded - x).abs() <= (x.abs() + ULAW_BIAS) / 32,
+            "{} decoded as {}",
+            x,
+            decoded
+        );
+    }
+    assert_eq!(decode_ulaw(encode_ulaw(i16::MAX)), 32_124);
+    assert_eq!(decode_ulaw(encode_ulaw(i16::MIN)), -32_124);
+
+    // every code decodes to a value which encodes to it again, but the negative zero
+    for byte in 0..=255u8 {
+        if byte != 0x7f {
+            assert_eq!(encode_ulaw(decode_ulaw(byte)), byte);
+        }
+    }
+    assert_eq!(encode_ulaw(0), 0xff);
+}
+
+#[test]
+fn test_alaw_round_trip() {
+    for x in i16::MIN as i32..=i16::MAX as i32 {
+        let decoded = decode_alaw(encode_alaw(x as i16)) as i32;
+        assert!(
+            (decoded - x).abs() <= x.abs() / 32 + 16,
+            "{} decoded as {}",
+            x,
+            decoded
+        );
+    }
+    assert_eq!(decode_alaw(encode_alaw(i16::MAX)), 32_256);
+    assert_eq!(decode_alaw(encode_alaw(i16::MIN)), -32_256);
+
+    for byte in 0..=255u8 {
+        assert_eq!(encode_alaw(decode_alaw(byte)), byte);
+    }
+    assert_eq!(encode_alaw(0), 0xd5);
+    assert_eq!(encode_alaw(-1), 0x55);
+}
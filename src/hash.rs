@@ -0,0 +1,121 @@
+//! Digests of the decoded samples, for checking that decoding still returns
+//! exactly the samples it did, without keeping the samples around.
+//!
+//! The digest covers a header of 22 bytes followed by the samples of
+//! `AudioInfo::recommended_sample_type`, interleaved, each little endian in
+//! the size of its type, floats as their IEEE 754 bits:
+//!
+//! | bytes    | content                                               |
+//! |----------|-------------------------------------------------------|
+//! | 0 - 11   | `cauldron-pcm` in ASCII                               |
+//! | 12       | 1, the version of this layout                         |
+//! | 13       | the sample type, 0 `u8`, 1 `i16`, 2 `i32`, 3 `f32`, 4 `f64` |
+//! | 14 - 17  | the sample rate in Hz, little endian                  |
+//! | 18 - 19  | the number of channels, little endian                 |
+//! | 20 - 21  | the bits per sample, little endian                    |
+//!
+//! Audio differing in any of these hashes differently even if the bytes of
+//! its samples are the same.
+
+use crate::audio::{AudioSegment, SampleIterator};
+use crate::format::{SampleType, TypedSamples};
+use crate::io::Sample;
+use crate::sha256::Sha256;
+use crate::Result;
+
+/// number of inter-channel samples read at once
+const CHUNK_FRAMES: usize = 4096;
+
+/// the first bytes of the hashed header
+const MAGIC: &[u8; 12] = b"cauldron-pcm";
+
+/// the version of the layout of the hashed bytes
+const VERSION: u8 = 1;
+
+/// The digest `AudioSegment::decode_hash` computes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HashAlgo {
+    Sha256,
+}
+
+/// A sample type as it is hashed
+trait HashedSample: Sample {
+    const ZERO: Self;
+    const TAG: u8;
+    fn extend_le(self, bytes: &mut Vec<u8>);
+}
+
+macro_rules! hashed_sample {
+    ($sample:ty, $tag:expr) => {
+        impl HashedSample for $sample {
+            const ZERO: $sample = 0 as $sample;
+            const TAG: u8 = $tag;
+            fn extend_le(self, bytes: &mut Vec<u8>) {
+                bytes.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+    };
+}
+
+hashed_sample!(u8, 0);
+hashed_sample!(i16, 1);
+hashed_sample!(i32, 2);
+hashed_sample!(f32, 3);
+hashed_sample!(f64, 4);
+
+/// `AudioSegment::decode_hash`
+pub(crate) fn segment_decode_hash(segment: &mut AudioSegment, algo: HashAlgo) -> Result<[u8; 32]> {
+    let HashAlgo::Sha256 = algo;
+    let info = segment.info().clone();
+    let channels = segment.number_channels().max(1);
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header.push(match info.recommended_sample_type() {
+        SampleType::U8 => u8::TAG,
+        SampleType::I16 => i16::TAG,
+        SampleType::I32 => i32::TAG,
+        SampleType::F32 => f32::TAG,
+        SampleType::F64 => f64::TAG,
+    });
+    header.extend_from_slice(&info.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(channels as u16).to_le_bytes());
+    header.extend_from_slice(&(info.bits_per_sample as u16).to_le_bytes());
+
+    let mut sha256 = Sha256::new();
+    sha256.update(&header);
+    match segment.samples_auto()? {
+        TypedSamples::U8(samples) => hash_samples(samples, channels, &mut sha256)?,
+        TypedSamples::I16(samples) => hash_samples(samples, channels, &mut sha256)?,
+        TypedSamples::I32(samples) => hash_samples(samples, channels, &mut sha256)?,
+        TypedSamples::F32(samples) => hash_samples(samples, channels, &mut sha256)?,
+        TypedSamples::F64(samples) => hash_samples(samples, channels, &mut sha256)?,
+    }
+    Ok(sha256.finish())
+}
+
+/// Hashes the `samples` of `channels` channels, a block of frames at a time
+fn hash_samples<S: HashedSample>(
+    mut samples: SampleIterator<'_, S>,
+    channels: usize,
+    sha256: &mut Sha256,
+) -> Result<()> {
+    let mut buffers = vec![vec![S::ZERO; CHUNK_FRAMES]; channels];
+    let mut bytes = Vec::with_capacity(CHUNK_FRAMES * channels * std::mem::size_of::<S>());
+    loop {
+        let read = {
+            let mut out: Vec<&mut [S]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+            samples.read_planar(&mut out)?
+        };
+        bytes.clear();
+        for frame in 0..read {
+            for buffer in &buffers {
+                buffer[frame].extend_le(&mut bytes);
+            }
+        }
+        sha256.update(&bytes);
+        if read < CHUNK_FRAMES {
+            return Ok(());
+        }
+    }
+}
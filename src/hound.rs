@@ -0,0 +1,111 @@
+//! The `hound` module writes decoded audio with the `hound` crate.
+//!
+//! It is enabled by the `hound` feature, for code built around
+//! `hound::WavWriter`. The samples are streamed from the segment to the
+//! writer, nothing is decoded ahead.
+
+use std::path::Path;
+
+use crate::audio::{AudioInfo, AudioSegment};
+use crate::codecs::SampleFormat;
+use crate::dither::{Dither, Requantizer};
+use crate::errors;
+use crate::Result;
+
+/// Returns the `hound::WavSpec` to write audio described by `info` without losing precision
+///
+/// Integer samples keep their bits per sample, rounded up to the 8, 16, 24
+/// or 32 bits hound can write, e.g. 20 bit FLAC is written as 24 bit
+/// samples, and A-law and mu-law as the 16 bit samples they decode to. Float
+/// samples of any width, e.g. the ones of MP3, are written as 32 bit
+/// floats, the only float format of hound. More than two channels are
+/// written by hound as WAVEFORMATEXTENSIBLE.
+pub fn wav_spec(info: &AudioInfo) -> Result<::hound::WavSpec> {
+    let channels = info.channels.count();
+    if channels == 0 || channels > u16::MAX as usize {
+        return errors::unsupported_error("the number of channels can not be written by hound");
+    }
+    if info.sample_rate == 0 {
+        return errors::unsupported_error("the sample rate of the stream is unknown");
+    }
+    let (bits_per_sample, sample_format) = match info.sample_format {
+        SampleFormat::F32 | SampleFormat::F64 => (32, ::hound::SampleFormat::Float),
+        _ if (1..=32).contains(&info.bits_per_sample) => {
+            let bits = info.bits_per_sample.div_ceil(8) * 8;
+            (bits as u16, ::hound::SampleFormat::Int)
+        }
+        _ => return errors::unsupported_error("the bits per sample can not be written by hound"),
+    };
+    Ok(::hound::WavSpec {
+        channels: channels as u16,
+        sample_rate: info.sample_rate,
+        bits_per_sample,
+        sample_format,
+    })
+}
+
+/// Decodes `segment` and writes it to a WAV file at `path` with `hound::WavWriter`
+///
+/// The file is written in the spec of `wav_spec`, or in `spec_override`,
+/// which has to have the channels of the segment. Integer samples are
+/// requantized to the bits per sample of the spec without dither, float
+/// samples are scaled to its integer range and clipped, like
+/// `AudioSegment::write_encoded` does. Integer samples written as floats are
+/// the ones of `samples::<f32>`. Errors of hound are translated to the ones
+/// of this crate, see `errors::Error`.
+pub fn write_with_hound<P: AsRef<Path>>(
+    segment: &mut AudioSegment,
+    path: P,
+    spec_override: Option<::hound::WavSpec>,
+) -> Result<()> {
+    let spec = match spec_override {
+        Some(spec) => spec,
+        None => wav_spec(segment.info())?,
+    };
+    if spec.channels as usize != segment.number_channels() {
+        return errors::unsupported_error("the channels of the spec do not match the segment");
+    }
+    // hound panics for other floats instead of failing
+    let supported = match spec.sample_format {
+        ::hound::SampleFormat::Float => spec.bits_per_sample == 32,
+        ::hound::SampleFormat::Int => matches!(spec.bits_per_sample, 8 | 16 | 24 | 32),
+    };
+    if !supported {
+        return errors::unsupported_error(
+            "hound writes 8, 16, 24 or 32 bit integers or 32 bit floats",
+        );
+    }
+
+    let mut writer = ::hound::WavWriter::create(path, spec)?;
+    if spec.sample_format == ::hound::SampleFormat::Float {
+        for sample in segment.samples::<f32>()? {
+            writer.write_sample(sample?)?;
+        }
+        return Ok(writer.finalize()?);
+    }
+
+    let info = segment.info().clone();
+    let bits = spec.bits_per_sample as u32;
+    if info.sample_format.is_float() {
+        let samples: Box<dyn Iterator<Item = Result<f64>>> =
+            if info.sample_format == SampleFormat::F64 {
+                Box::new(segment.samples::<f64>()?)
+            } else {
+                Box::new(segment.samples::<f32>()?.map(|s| s.map(f64::from)))
+            };
+        let scale = (1u64 << (bits - 1)) as f64;
+        for sample in samples {
+            let sample = (sample? * scale).round().clamp(-scale, scale - 1.0) as i32;
+            writer.write_sample(sample)?;
+        }
+        return Ok(writer.finalize()?);
+    }
+
+    let channels = spec.channels as usize;
+    let mut requantizer = Requantizer::new(Dither::None, info.bits_per_sample, bits, channels, 0)?;
+    let offset = info.sample_format.offset();
+    for (i, sample) in segment.samples::<i32>()?.enumerate() {
+        writer.write_sample(requantizer.requantize(sample? - offset, i % channels))?;
+    }
+    Ok(writer.finalize()?)
+}
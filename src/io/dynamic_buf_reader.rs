@@ -1,13 +1,20 @@
 use std::cmp;
 use std::io;
 
+use super::read::{not_enough_bytes, rewind_ahead, ReadBuffer, Rewind};
+use super::MediaSource;
+
 /// A buffer reader with dynamic cache size. Cache grows from 8kb to max 32kb.
+///
+/// The buffer is allocated at 8kb and only grows when reads or peeks need more,
+/// so a reader only looking at a header never allocates the full 32kb. The
+/// growth of reads can be capped lower with `set_max_capacity`.
 pub struct DynamicBufReader<R> {
     /// The source reader.
     inner: R,
 
     /// The combined read-ahead/rewind buffer filled from the inner reader.
-    buf: Box<[u8]>,
+    buf: Vec<u8>,
 
     /// The index of the next readable byte in buf.
     pos: usize,
@@ -18,31 +25,177 @@ pub struct DynamicBufReader<R> {
     /// The capacity of the read-ahead buffer at this moment. Grows exponentially as more sequential
     /// reads are serviced.
     cur_capacity: usize,
+
+    /// The capacity sequential reads grow the buffer to at most.
+    max_capacity: usize,
+
+    /// The number of bytes handed out to the consumer so far, i.e. the offset of the next
+    /// readable byte from the start of the stream.
+    position: u64,
 }
 
 #[allow(dead_code)]
 impl<R: io::Read> DynamicBufReader<R> {
     /// The maximum capacity of the read-ahead buffer. Must be a power-of-2.
-    const MAX_CAPACITY: usize = 32 * 1024;
+    pub(crate) const MAX_CAPACITY: usize = 32 * 1024;
 
     /// The initial capacity of the read-ahead buffer. Must be less than MAX_CAPACITY, and a
     /// power-of-2.
     const INIT_CAPACITY: usize = 8 * 1024;
 
+    /// The smallest capacity `set_max_capacity` accepts.
+    pub const MIN_CAPACITY: usize = 1024;
+
+    /// The most bytes `unread` pushes back at once.
+    pub const MAX_UNREAD: usize = 16 * 1024;
+
     pub fn new(source: R) -> Self {
+        DynamicBufReader::with_buffer(source, Vec::new())
+    }
+
+    /// Creates the reader with the allocation of `buffer`, e.g. one returned by `recycle`
+    ///
+    /// The contents of `buffer` are ignored. Reusing it across many short
+    /// streams saves allocating and growing a buffer for each of them.
+    pub fn with_buffer(source: R, mut buffer: Vec<u8>) -> Self {
+        let len = buffer
+            .capacity()
+            .clamp(Self::INIT_CAPACITY, Self::MAX_CAPACITY);
+        buffer.resize(len, 0);
         DynamicBufReader {
             inner: source,
             cur_capacity: Self::INIT_CAPACITY,
-            buf: vec![0u8; Self::MAX_CAPACITY].into_boxed_slice(),
+            max_capacity: Self::MAX_CAPACITY,
+            buf: buffer,
             pos: 0,
             end_pos: 0,
+            position: 0,
+        }
+    }
+
+    /// Caps the buffer sequential reads fill at `capacity` bytes, e.g. to save memory
+    ///
+    /// `capacity` is clamped between `MIN_CAPACITY` and 32kb, and rounded down
+    /// to a power of 2. A larger buffer
+    /// is shrunk right away if the bytes read ahead fit, otherwise once they
+    /// are consumed. Peeks still take up to 32kb, the buffer is shrunk back
+    /// after them the same way.
+    pub fn set_max_capacity(&mut self, capacity: usize) {
+        let capacity = capacity.clamp(Self::MIN_CAPACITY, Self::MAX_CAPACITY);
+        self.max_capacity = 1 << (usize::BITS - 1 - capacity.leading_zeros());
+        self.cur_capacity = cmp::min(self.cur_capacity, self.max_capacity);
+        if self.end_pos - self.pos <= self.max_capacity {
+            self.buf.copy_within(self.pos..self.end_pos, 0);
+            self.end_pos -= self.pos;
+            self.pos = 0;
+            self.shrink();
         }
     }
 
+    /// Drops the source and returns the buffer, to be passed to `with_buffer`
+    pub fn recycle(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Returns the source, the bytes read ahead into the buffer are lost.
     pub fn into_inner(self) -> R {
         self.inner
     }
 
+    /// Returns the bytes read ahead but not consumed yet, and the source
+    ///
+    /// Reading the returned bytes and then the source continues right where the
+    /// consumer stopped.
+    pub fn into_parts(self) -> (Vec<u8>, R) {
+        (self.buf[self.pos..self.end_pos].to_vec(), self.inner)
+    }
+
+    /// Returns the number of bytes consumed from the start of the stream.
+    ///
+    /// Bytes that were read ahead into the buffer but not yet handed out are not counted.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the next `n` bytes without consuming them, fewer at the end of the stream.
+    ///
+    /// `n` must not exceed the maximum capacity of 32kb.
+    pub fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        assert!(
+            n <= Self::MAX_CAPACITY,
+            "can not peek more than the buffer capacity"
+        );
+
+        if self.end_pos - self.pos < n {
+            self.grow(n);
+            // move the buffered bytes to the front to make room for the rest
+            self.buf.copy_within(self.pos..self.end_pos, 0);
+            self.end_pos -= self.pos;
+            self.pos = 0;
+
+            while self.end_pos < n {
+                match self.inner.read(&mut self.buf[self.end_pos..]) {
+                    Ok(0) => break,
+                    Ok(nread) => self.end_pos += nread,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        let end = cmp::min(self.pos + n, self.end_pos);
+        Ok(&self.buf[self.pos..end])
+    }
+
+    /// Pushes `bytes` back in front of the unconsumed bytes, so that `read` and `peek` return them first
+    ///
+    /// The position goes back by their length as if they were never consumed,
+    /// so they are normally the bytes just read. Bytes pushed back by several
+    /// calls are returned in the reverse order of the calls. Fails with
+    /// `io::ErrorKind::InvalidInput` for more than `MAX_UNREAD` bytes, or more
+    /// than were consumed from the start of the stream.
+    pub fn unread(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let n = bytes.len();
+        if n > Self::MAX_UNREAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can not unread more than 16kb at once",
+            ));
+        }
+        if n as u64 > self.position {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can not unread before the start of the stream",
+            ));
+        }
+        if n > self.pos {
+            // move the buffered bytes back to make room in front of them
+            let buffered = self.end_pos - self.pos;
+            self.grow(n + buffered);
+            self.buf.copy_within(self.pos..self.end_pos, n);
+            self.pos = n;
+            self.end_pos = n + buffered;
+        }
+        self.pos -= n;
+        self.buf[self.pos..self.pos + n].copy_from_slice(bytes);
+        self.position -= n as u64;
+        Ok(())
+    }
+
+    /// Grows the buffer to hold at least `n` bytes, keeping the buffered ones
+    fn grow(&mut self, n: usize) {
+        if self.buf.len() < n {
+            self.buf.resize(n.next_power_of_two(), 0);
+        }
+    }
+
+    /// Shrinks the buffer to the maximum capacity if it is larger, the buffered bytes must fit
+    fn shrink(&mut self) {
+        if self.buf.len() > self.max_capacity {
+            self.buf.truncate(self.max_capacity);
+            self.buf.shrink_to_fit();
+        }
+    }
+
     #[inline]
     fn discard_buffer(&mut self) {
         self.pos = 0;
@@ -56,10 +209,13 @@ impl<R: io::Read> DynamicBufReader<R> {
         // Branch using `>=` instead of the more correct `==`
         // to tell the compiler that the pos..cap slice is always valid.
         if self.pos >= self.end_pos {
+            // drop what a peek grew the buffer to beyond the cap
+            self.shrink();
+            self.grow(self.cur_capacity);
             self.end_pos = self.inner.read(&mut self.buf[0..self.cur_capacity])?;
             self.pos = 0;
 
-            if self.cur_capacity < Self::MAX_CAPACITY {
+            if self.cur_capacity < self.max_capacity {
                 self.cur_capacity *= 2;
             }
         }
@@ -67,20 +223,194 @@ impl<R: io::Read> DynamicBufReader<R> {
     }
 }
 
+impl<R: MediaSource> DynamicBufReader<R> {
+    /// Returns true if the source supports seeking.
+    pub fn is_seekable(&self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    /// Seeks the source, and drops the read-ahead buffer.
+    ///
+    /// Returns the new offset from the start of the stream.
+    pub fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        // the source is ahead of the consumer by the buffered bytes
+        let pos = match pos {
+            io::SeekFrom::Current(offset) => {
+                io::SeekFrom::Start((self.position as i64 + offset) as u64)
+            }
+            pos => pos,
+        };
+        self.discard_buffer();
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+
+    /// Consumes the next `n` bytes, seeking over them if the source can seek
+    ///
+    /// Skips beyond the buffered bytes and the size of the buffer seek instead
+    /// of reading, so that skipping megabytes of a file takes no time. Fails
+    /// with `io::ErrorKind::UnexpectedEof` like `skip_bytes` if the stream ends
+    /// before, in which case the position is left at its end.
+    pub fn skip_ahead(&mut self, n: u64) -> io::Result<()> {
+        let buffered = (self.end_pos - self.pos) as u64;
+        if !self.is_seekable() || n < buffered + Self::MAX_CAPACITY as u64 {
+            return self.skip_bytes(n as usize);
+        }
+        let target = self.position + n;
+        if self.seek(io::SeekFrom::End(0))? < target {
+            return Err(not_enough_bytes());
+        }
+        self.seek(io::SeekFrom::Start(target)).map(drop)
+    }
+}
+
+impl<R: MediaSource> Rewind for DynamicBufReader<R> {
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns to `position`, seeking only if it is before the buffered bytes.
+    ///
+    /// The bytes consumed since the buffer was last filled are still in it, so
+    /// this works even if the source can not seek when the distance is short.
+    fn rewind(&mut self, position: u64) -> io::Result<()> {
+        let back = match self.position.checked_sub(position) {
+            Some(back) => back,
+            None => return Err(rewind_ahead()),
+        };
+        if back <= self.pos as u64 {
+            self.pos -= back as usize;
+            self.position = position;
+            return Ok(());
+        }
+        self.seek(io::SeekFrom::Start(position)).map(drop)
+    }
+}
+
 impl<R: io::Read> io::Read for DynamicBufReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // If we don't have any buffered data and we're doing a massive read
         // (larger than our internal buffer), bypass our internal buffer
         // entirely.
-        if self.pos == self.end_pos && buf.len() >= self.buf.len() {
+        if self.pos == self.end_pos && buf.len() >= self.max_capacity {
             self.discard_buffer();
-            return self.inner.read(buf);
+            self.shrink();
+            let nread = self.inner.read(buf)?;
+            self.position += nread as u64;
+            return Ok(nread);
         }
         let nread = {
             let mut rem = self.fill_buf()?;
             rem.read(buf)?
         };
         self.pos = cmp::min(self.pos + nread, self.end_pos);
+        self.position += nread as u64;
         Ok(nread)
     }
 }
+
+#[test]
+fn test_lazy_buffer() {
+    use std::io::Read;
+
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    let mut reader = DynamicBufReader::new(&data[..]);
+    assert_eq!(reader.peek(12).unwrap(), &data[..12]);
+    assert_eq!(reader.buf.len(), 8 * 1024);
+
+    // peeking further than the initial capacity grows the buffer
+    assert_eq!(reader.peek(10_000).unwrap(), &data[..10_000]);
+    assert_eq!(reader.buf.len(), 16 * 1024);
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+    let buffer = reader.recycle();
+    assert_eq!(buffer.len(), 32 * 1024);
+
+    // a recycled buffer is used as it is
+    let ptr = buffer.as_ptr();
+    let mut reader = DynamicBufReader::with_buffer(&data[..], buffer);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+    let buffer = reader.recycle();
+    assert_eq!(buffer.as_ptr(), ptr);
+}
+
+#[test]
+fn test_unread() {
+    use std::io::Read;
+
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut reader = DynamicBufReader::new(&data[..]);
+    let read = |reader: &mut DynamicBufReader<&[u8]>, n: usize| {
+        let mut out = vec![0; n];
+        reader.read_exact(&mut out).unwrap();
+        out
+    };
+
+    // bytes just read come back first, also across the refill after 8kb, which
+    // leaves fewer bytes in front of the buffered ones than are pushed back
+    assert_eq!(read(&mut reader, 8_190), &data[..8_190]);
+    let across = read(&mut reader, 4);
+    assert_eq!(across, &data[8_190..8_194]);
+    reader.unread(&across).unwrap();
+    assert_eq!(reader.position(), 8_190);
+    assert_eq!(reader.peek(6).unwrap(), &data[8_190..8_196]);
+
+    // several unreads are read in reverse order, ahead of the buffered bytes
+    reader.unread(b"cd").unwrap();
+    reader.unread(b"ab").unwrap();
+    assert_eq!(reader.position(), 8_186);
+    assert_eq!(
+        reader.peek(5).unwrap(),
+        [b'a', b'b', b'c', b'd', data[8_190]]
+    );
+    assert_eq!(
+        read(&mut reader, 6),
+        [b'a', b'b', b'c', b'd', data[8_190], data[8_191]]
+    );
+    assert_eq!(reader.position(), 8_192);
+
+    // within the bytes consumed since the refill they are copied in place
+    assert_eq!(read(&mut reader, 8_195), &data[8_192..16_387]);
+    reader.unread(&data[16_000..16_387]).unwrap();
+    assert_eq!(reader.position(), 16_000);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, &data[16_000..]);
+
+    // unreading too much fails and leaves the stream as it is
+    let mut reader = DynamicBufReader::new(&data[..]);
+    assert_eq!(read(&mut reader, 10), &data[..10]);
+    let error = reader.unread(&data[..11]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let large = vec![0; DynamicBufReader::<&[u8]>::MAX_UNREAD + 1];
+    assert!(reader.unread(&large).is_err());
+    assert_eq!(reader.position(), 10);
+    assert_eq!(read(&mut reader, 2), &data[10..12]);
+}
+
+#[test]
+fn test_max_capacity() {
+    use std::io::Read;
+
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+    let mut reader = DynamicBufReader::new(&data[..]);
+    reader.set_max_capacity(5_000);
+    assert_eq!(reader.buf.len(), 4 * 1024);
+
+    let mut out = vec![0; 30_000];
+    reader.read_exact(&mut out).unwrap();
+    assert_eq!(out, &data[..30_000]);
+    assert_eq!(reader.buf.len(), 4 * 1024);
+
+    // a peek grows the buffer for as long as its bytes are buffered
+    assert_eq!(reader.peek(20_000).unwrap(), &data[30_000..50_000]);
+    assert_eq!(reader.buf.len(), 32 * 1024);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, &data[30_000..]);
+    assert_eq!(reader.buf.len(), 4 * 1024);
+}
@@ -0,0 +1,91 @@
+use std::io;
+
+/// A `MediaSource` provides the bytes of an `AudioInputStream`, seeking is optional.
+pub trait MediaSource: io::Read + Send {
+    /// Returns true if `seek` is supported.
+    fn is_seekable(&self) -> bool;
+
+    /// Seeks to an offset, offsets are relative to where the source started.
+    ///
+    /// Returns the new offset from the start of the source.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64>;
+}
+
+impl<M: MediaSource + ?Sized> MediaSource for Box<M> {
+    fn is_seekable(&self) -> bool {
+        (**self).is_seekable()
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        (**self).seek(pos)
+    }
+}
+
+/// A source which can only be read forward, e.g. a pipe or a network stream.
+pub struct ReadOnlySource<R> {
+    inner: R,
+}
+
+impl<R: io::Read + Send> ReadOnlySource<R> {
+    pub fn new(inner: R) -> Self {
+        ReadOnlySource { inner }
+    }
+}
+
+impl<R: io::Read> io::Read for ReadOnlySource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: io::Read + Send> MediaSource for ReadOnlySource<R> {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::other("source does not support seeking"))
+    }
+}
+
+/// A source supporting seeks, e.g. a file.
+pub struct SeekableSource<R> {
+    inner: R,
+    /// offset of the inner reader when the source was created
+    start: u64,
+}
+
+impl<R: io::Read + io::Seek + Send> SeekableSource<R> {
+    /// Creates the source, the current position of `inner` becomes its start.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let start = inner.stream_position()?;
+        Ok(SeekableSource { inner, start })
+    }
+}
+
+impl<R: io::Read> io::Read for SeekableSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: io::Read + io::Seek + Send> MediaSource for SeekableSource<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            io::SeekFrom::Start(offset) => io::SeekFrom::Start(self.start + offset),
+            pos => pos,
+        };
+        let offset = self.inner.seek(pos)?;
+        if offset < self.start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the source",
+            ));
+        }
+        Ok(offset - self.start)
+    }
+}
@@ -1,17 +1,38 @@
 mod dynamic_buf_reader;
+mod media_source;
 mod read;
 mod write;
 
 use std::io;
+use std::time::Duration;
 
-use super::codecs::CodecType;
-use super::{audio, errors, utils, Result};
+use super::codecs::{CodecType, SampleFormat};
+use super::format::{SampleType, TypedSamples};
+use super::stats::{SubsetViolation, VerifyReport};
+use super::{audio, errors, g711, utils, Result};
 
 pub use dynamic_buf_reader::DynamicBufReader;
-pub use read::{BitStream, ReadBuffer};
+pub use media_source::{MediaSource, ReadOnlySource, SeekableSource};
+#[cfg(any(feature = "flac", feature = "mp3"))]
+pub use read::BitStream;
+pub use read::{ReadBuffer, Rewind};
 pub use write::WriteBuffer;
 
-pub type AudioInputStream = DynamicBufReader<Box<dyn io::Read + Send>>;
+pub type AudioInputStream = DynamicBufReader<Box<dyn MediaSource>>;
+
+/// Creates a stream reading `reader` forward only.
+pub fn read_only_stream<R: io::Read + Send + 'static>(reader: R) -> AudioInputStream {
+    AudioInputStream::new(Box::new(ReadOnlySource::new(reader)))
+}
+
+/// Creates a stream which can seek in `reader`, starting at its current position.
+pub fn seekable_stream<R: io::Read + io::Seek + Send + 'static>(
+    reader: R,
+) -> io::Result<AudioInputStream> {
+    Ok(AudioInputStream::new(Box::new(SeekableSource::new(
+        reader,
+    )?)))
+}
 
 pub trait IntoAudioInputStream {
     fn into_stream(self) -> Result<AudioInputStream>;
@@ -20,21 +41,38 @@ pub trait IntoAudioInputStream {
 impl IntoAudioInputStream for String {
     fn into_stream(self) -> Result<AudioInputStream> {
         let file = std::fs::File::open(self)?;
-        Ok(AudioInputStream::new(Box::new(file)))
+        Ok(seekable_stream(file)?)
     }
 }
 
 impl IntoAudioInputStream for &str {
     fn into_stream(self) -> Result<AudioInputStream> {
         let file = std::fs::File::open(self)?;
-        Ok(AudioInputStream::new(Box::new(file)))
+        Ok(seekable_stream(file)?)
     }
 }
 
 impl IntoAudioInputStream for &std::path::Path {
     fn into_stream(self) -> Result<AudioInputStream> {
         let file = std::fs::File::open(self)?;
-        Ok(AudioInputStream::new(Box::new(file)))
+        Ok(seekable_stream(file)?)
+    }
+}
+
+/// Takes a stream as it is, e.g. one positioned by `format::scan_for_audio`
+impl IntoAudioInputStream for AudioInputStream {
+    fn into_stream(self) -> Result<AudioInputStream> {
+        Ok(self)
+    }
+}
+
+/// Reads standard input forward only, e.g. audio piped into the program
+///
+/// The owned handle is taken because a `StdinLock` can not leave its thread.
+/// It locks standard input for each read, which is once per buffer refill.
+impl IntoAudioInputStream for io::Stdin {
+    fn into_stream(self) -> Result<AudioInputStream> {
+        Ok(read_only_stream(self))
     }
 }
 
@@ -48,17 +86,69 @@ impl IntoAudioInputStream for &std::path::Path {
 /// will be sufficient.
 pub trait Sample: Sized + Copy + Send {
     /// Reads the audio sample from the data buffer
+    ///
+    /// Floats are normalized the same for every codec: signed integers by
+    /// 2^(bits - 1), unsigned 8 bit samples are offset by 128 first, and float
+    /// samples are passed through unchanged.
     fn read_pcm<R: ReadBuffer>(reader: &mut R, codec: CodecType) -> Result<Self>;
 
+    /// Reads an integer sample of `bits` valid bits from a little endian container of `container_bits`
+    ///
+    /// The valid bits are the most significant ones of the container, the
+    /// padding below them is dropped. The sample has the scale of `bits`, as
+    /// `from_i32` converts it.
+    #[inline(always)]
+    fn read_pcm_in_container<R: ReadBuffer>(
+        reader: &mut R,
+        container_bits: u32,
+        bits: u32,
+    ) -> Result<Self> {
+        let container = match container_bits {
+            16 => reader.read_le_i16()? as i32,
+            24 => reader.read_le_i24()?,
+            32 => reader.read_le_i32()?,
+            _ => return errors::unsupported_error("unsupported sample container"),
+        };
+        Self::from_i32(container >> (container_bits - bits), bits)
+    }
+
     /// Writes the audio sample to the data buffer
     fn write_pcm<W: WriteBuffer>(self, writer: &mut W, bits: u16) -> Result<()>;
 
+    /// Converts an integer sample of `bits` bits per sample
+    ///
+    /// Integer types take any depth that fits them unchanged, floats are
+    /// normalized by 2^(bits - 1) for any depth from 4 to 32 bits.
     fn from_i32(value: i32, bits: u32) -> Result<Self>;
 
     fn from_f32(value: f32) -> Result<Self>;
+
+    /// Returns what a NaN or infinite sample is replaced with, `None` if it is finite
+    ///
+    /// NaN becomes 0 and infinities full scale, integers are always finite.
+    fn non_finite_replacement(self) -> Option<Self> {
+        None
+    }
+
+    /// Returns the tag of this sample type, which selects the iterator of a `Format`
+    fn sample_type() -> SampleType;
+
+    /// Takes the iterator out of `samples` if it returns this sample type
+    fn from_typed(samples: TypedSamples<'_>) -> Option<audio::SampleIterator<'_, Self>>;
 }
 
 impl Sample for u8 {
+    fn sample_type() -> SampleType {
+        SampleType::U8
+    }
+
+    fn from_typed<'a>(samples: TypedSamples<'a>) -> Option<audio::SampleIterator<'a, u8>> {
+        match samples {
+            TypedSamples::U8(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
     #[inline(always)]
     fn read_pcm<R: ReadBuffer>(reader: &mut R, codec: CodecType) -> Result<u8> {
         match codec {
@@ -93,11 +183,24 @@ impl Sample for u8 {
 }
 
 impl Sample for i16 {
+    fn sample_type() -> SampleType {
+        SampleType::I16
+    }
+
+    fn from_typed<'a>(samples: TypedSamples<'a>) -> Option<audio::SampleIterator<'a, i16>> {
+        match samples {
+            TypedSamples::I16(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
     #[inline(always)]
     fn read_pcm<R: ReadBuffer>(reader: &mut R, codec: CodecType) -> Result<i16> {
         match codec {
             CodecType::CODEC_TYPE_PCM_U8 => Ok(reader.read_u8().map(|x| x as i16)?),
             CodecType::CODEC_TYPE_PCM_S16LE => Ok(reader.read_le_i16()?),
+            CodecType::CODEC_TYPE_PCM_ALAW => Ok(g711::decode_alaw(reader.read_u8()?)),
+            CodecType::CODEC_TYPE_PCM_MULAW => Ok(g711::decode_ulaw(reader.read_u8()?)),
             _ => errors::unsupported_error("unsupported for i16"),
         }
     }
@@ -128,6 +231,17 @@ impl Sample for i16 {
 }
 
 impl Sample for i32 {
+    fn sample_type() -> SampleType {
+        SampleType::I32
+    }
+
+    fn from_typed<'a>(samples: TypedSamples<'a>) -> Option<audio::SampleIterator<'a, i32>> {
+        match samples {
+            TypedSamples::I32(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
     #[inline(always)]
     fn read_pcm<R: ReadBuffer>(reader: &mut R, codec: CodecType) -> Result<i32> {
         match codec {
@@ -135,16 +249,18 @@ impl Sample for i32 {
             CodecType::CODEC_TYPE_PCM_S16LE => Ok(reader.read_le_i16().map(|x| x as i32)?),
             CodecType::CODEC_TYPE_PCM_S24LE => Ok(reader.read_le_i24()?),
             CodecType::CODEC_TYPE_PCM_S32LE => Ok(reader.read_le_i32()?),
+            CodecType::CODEC_TYPE_PCM_ALAW => Ok(g711::decode_alaw(reader.read_u8()?) as i32),
+            CodecType::CODEC_TYPE_PCM_MULAW => Ok(g711::decode_ulaw(reader.read_u8()?) as i32),
             _ => errors::unsupported_error("unsupported for i32"),
         }
     }
 
     fn write_pcm<W: WriteBuffer>(self, writer: &mut W, bits: u16) -> Result<()> {
         match bits {
-            8 => Ok(writer.write_u8(utils::u8_from_signed(utils::narrow_to_i8(self as i32)?))?),
+            8 => Ok(writer.write_u8(utils::u8_from_signed(utils::narrow_to_i8(self)?))?),
             16 => Ok(writer.write_le_i16(utils::narrow_to_i16(self)?)?),
             24 => Ok(writer.write_le_i24(utils::narrow_to_i24(self)?)?),
-            32 => Ok(writer.write_le_i32(self as i32)?),
+            32 => Ok(writer.write_le_i32(self)?),
             _ => errors::unsupported_error::<()>(""),
         }
     }
@@ -161,14 +277,33 @@ impl Sample for i32 {
 }
 
 impl Sample for f32 {
+    fn sample_type() -> SampleType {
+        SampleType::F32
+    }
+
+    fn from_typed<'a>(samples: TypedSamples<'a>) -> Option<audio::SampleIterator<'a, f32>> {
+        match samples {
+            TypedSamples::F32(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
     #[inline(always)]
     fn read_pcm<R: ReadBuffer>(reader: &mut R, codec: CodecType) -> Result<f32> {
         match codec {
-            CodecType::CODEC_TYPE_PCM_U8 => Ok(reader.read_u8().map(|x| x as f32 / 255.0)?),
+            CodecType::CODEC_TYPE_PCM_U8 => {
+                Ok((reader.read_u8()? as i32 - SampleFormat::U8.offset()) as f32 / 128.0)
+            }
             CodecType::CODEC_TYPE_PCM_S16LE => Ok(reader.read_le_i16()? as f32 / 32_768.0),
-            CodecType::CODEC_TYPE_PCM_S24LE => Ok(reader.read_le_i24()? as f32 / 2_147_483_648.0),
+            CodecType::CODEC_TYPE_PCM_S24LE => Ok(reader.read_le_i24()? as f32 / 8_388_608.0),
             CodecType::CODEC_TYPE_PCM_S32LE => Ok(reader.read_le_i32()? as f32 / 2_147_483_648.0),
             CodecType::CODEC_TYPE_PCM_F32LE => Ok(reader.read_le_f32()?),
+            CodecType::CODEC_TYPE_PCM_ALAW => {
+                Ok(g711::decode_alaw(reader.read_u8()?) as f32 / 32_768.0)
+            }
+            CodecType::CODEC_TYPE_PCM_MULAW => {
+                Ok(g711::decode_ulaw(reader.read_u8()?) as f32 / 32_768.0)
+            }
             _ => errors::unsupported_error("unsupported for f32"),
         }
     }
@@ -183,9 +318,7 @@ impl Sample for f32 {
     #[inline(always)]
     fn from_i32(value: i32, bits: u32) -> Result<f32> {
         match bits {
-            16 => Ok(value as f32 / 32_768.0),
-            24 => Ok(value as f32 / 2_147_483_648.0),
-            32 => Ok(value as f32 / 2_147_483_648.0),
+            4..=32 => Ok(value as f32 / (1u64 << (bits - 1)) as f32),
             _ => errors::unsupported_error("unsupported bits per sample for f32"),
         }
     }
@@ -194,18 +327,48 @@ impl Sample for f32 {
     fn from_f32(value: f32) -> Result<f32> {
         Ok(value)
     }
+
+    #[inline(always)]
+    fn non_finite_replacement(self) -> Option<f32> {
+        if self.is_finite() {
+            None
+        } else if self.is_nan() {
+            Some(0.0)
+        } else {
+            Some(self.signum())
+        }
+    }
 }
 
 impl Sample for f64 {
+    fn sample_type() -> SampleType {
+        SampleType::F64
+    }
+
+    fn from_typed<'a>(samples: TypedSamples<'a>) -> Option<audio::SampleIterator<'a, f64>> {
+        match samples {
+            TypedSamples::F64(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
     #[inline(always)]
     fn read_pcm<R: ReadBuffer>(reader: &mut R, codec: CodecType) -> Result<Self> {
         match codec {
-            CodecType::CODEC_TYPE_PCM_U8 => Ok(reader.read_u8().map(|x| x as f64 / 255.0)?),
+            CodecType::CODEC_TYPE_PCM_U8 => {
+                Ok((reader.read_u8()? as i32 - SampleFormat::U8.offset()) as f64 / 128.0)
+            }
             CodecType::CODEC_TYPE_PCM_S16LE => Ok(reader.read_le_i16()? as f64 / 32_768.0),
-            CodecType::CODEC_TYPE_PCM_S24LE => Ok(reader.read_le_i24()? as f64 / 2_147_483_648.0),
+            CodecType::CODEC_TYPE_PCM_S24LE => Ok(reader.read_le_i24()? as f64 / 8_388_608.0),
             CodecType::CODEC_TYPE_PCM_S32LE => Ok(reader.read_le_i32()? as f64 / 2_147_483_648.0),
-            CodecType::CODEC_TYPE_PCM_F32LE => Ok(reader.read_le_f32()? as f64 / f32::MAX as f64),
+            CodecType::CODEC_TYPE_PCM_F32LE => Ok(reader.read_le_f32()? as f64),
             CodecType::CODEC_TYPE_PCM_F64LE => Ok(reader.read_le_f64()?),
+            CodecType::CODEC_TYPE_PCM_ALAW => {
+                Ok(g711::decode_alaw(reader.read_u8()?) as f64 / 32_768.0)
+            }
+            CodecType::CODEC_TYPE_PCM_MULAW => {
+                Ok(g711::decode_ulaw(reader.read_u8()?) as f64 / 32_768.0)
+            }
             _ => errors::unsupported_error("unsupported for f32"),
         }
     }
@@ -221,11 +384,9 @@ impl Sample for f64 {
     #[inline(always)]
     fn from_i32(value: i32, bits: u32) -> Result<Self> {
         match bits {
-            16 => Ok(value as f64 / 32_768.0),
-            24 => Ok(value as f64 / 2_147_483_648.0),
-            32 => Ok(value as f64 / 2_147_483_648.0),
+            4..=32 => Ok(value as f64 / (1u64 << (bits - 1)) as f64),
             64 => Ok(value as f64 / (i64::MAX as f64 + 1.0)),
-            _ => errors::unsupported_error("unsupported bits per sample for f32"),
+            _ => errors::unsupported_error("unsupported bits per sample for f64"),
         }
     }
 
@@ -233,21 +394,306 @@ impl Sample for f64 {
     fn from_f32(value: f32) -> Result<Self> {
         Ok(value.into())
     }
+
+    #[inline(always)]
+    fn non_finite_replacement(self) -> Option<Self> {
+        if self.is_finite() {
+            None
+        } else if self.is_nan() {
+            Some(0.0)
+        } else {
+            Some(self.signum())
+        }
+    }
 }
 
 /// A `AudioReader` is a container demuxer. It provides methods to probe a media container for
 /// information and access the streams encapsulated in the container.
+///
+/// Readers are `Send`, so that an `AudioSegment` can be moved to another thread.
 pub trait AudioReader: Send {
     /// Reads the header and initializes audio info
+    ///
+    /// The header can only be read once, since the reader never seeks back. Any
+    /// further call fails with `Error::Unsupported`, even if the first one failed.
     fn read_header(&mut self) -> Result<audio::AudioInfo>;
 
     /// Returns the buffer for the iterator
     fn buffer(&mut self) -> &mut AudioInputStream;
+
+    /// Returns the input back, e.g. to try a different reader on it
+    fn into_buffer(self: Box<Self>) -> AudioInputStream;
+
+    /// Reads the metadata stored after the audio data, called once all samples are read
+    ///
+    /// Seekable inputs may read it ahead of the samples, so it must only be
+    /// read on the first call.
+    fn read_trailer(&mut self) {}
+
+    /// Returns the number of bytes left of the audio data, `None` if it runs to the end of the stream
+    ///
+    /// Containers wrapping the frames of another format, like MP3 in WAV, limit
+    /// the frame decoder of that format to their audio data with it.
+    fn data_remaining(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// Counts the samples from the start of the audio data to the end of the stream
+    ///
+    /// The stream is positioned at the start of the audio data, i.e. right after the
+    /// header, and may be left anywhere.
+    fn scan_length(&mut self, _info: &audio::AudioInfo) -> Result<audio::ScanResult> {
+        errors::unsupported_error("scanning the length is not supported for this format")
+    }
+
+    /// Decodes the audio data from its start to the end of the stream without returning samples
+    ///
+    /// The stream is positioned at the start of the audio data, i.e. right after
+    /// the header, and may be left anywhere. Errors of the input fail the
+    /// verification, damage of the stream is counted in the report.
+    fn verify(&mut self, _info: &audio::AudioInfo) -> Result<VerifyReport> {
+        errors::unsupported_error("verifying is not supported for this format")
+    }
+
+    /// Checks the frames from the current position on against the streamable FLAC subset
+    ///
+    /// The stream may be left anywhere, like `verify` leaves it.
+    fn check_subset(&mut self, _info: &audio::AudioInfo) -> Result<Vec<SubsetViolation>> {
+        errors::unsupported_error("the subset check is only supported for FLAC")
+    }
+
+    /// Returns the cue points found so far
+    fn cue_points(&self) -> Vec<audio::CuePoint> {
+        Vec::new()
+    }
+
+    /// Returns the sampler loops found so far
+    fn sample_loops(&self) -> Vec<audio::SampleLoop> {
+        Vec::new()
+    }
+
+    /// Returns the metadata blocks the header skipped
+    fn skipped_metadata(&self) -> Vec<audio::SkippedMetadata> {
+        Vec::new()
+    }
+
+    /// Returns the FLAC APPLICATION blocks of the header
+    fn application_blocks(&self) -> Vec<audio::ApplicationBlock> {
+        Vec::new()
+    }
+
+    /// Returns where the embedded pictures are stored, see `AudioSegment::export_pictures`
+    ///
+    /// This may look for them beyond the header, an input which can seek is
+    /// left at its position.
+    fn picture_sources(&mut self) -> Result<Vec<crate::pictures::PictureSource>> {
+        Ok(Vec::new())
+    }
+
+    /// Returns the header bytes kept with `DecodeOptions::keep_raw_headers`
+    fn raw_headers(&self) -> Option<audio::RawHeaders> {
+        None
+    }
+
+    /// Positions the input at the inter-channel sample `frame` of the audio data
+    ///
+    /// The decoding state of a samples iterator has to be reset by the caller.
+    fn seek_frame(&mut self, _frame: u64, _info: &audio::AudioInfo) -> Result<()> {
+        errors::unsupported_error("seeking is not supported for this format")
+    }
+
+    /// Positions the input at the start of the block holding the inter-channel sample `frame`
+    ///
+    /// Returns the first inter-channel sample of the block, the samples
+    /// iterator decodes the block and skips to `frame`. The default seeks to
+    /// `frame` itself, formats whose blocks can not be found without an index
+    /// seek with one.
+    fn seek_block(&mut self, frame: u64, info: &audio::AudioInfo) -> Result<u64> {
+        self.seek_frame(frame, info)?;
+        Ok(frame)
+    }
+
+    /// Indexes the blocks of the audio data for `seek_block`
+    ///
+    /// The stream is positioned at the start of the audio data, i.e. right after
+    /// the header, and may be left anywhere.
+    fn build_seek_index(&mut self, _info: &audio::AudioInfo) -> Result<()> {
+        errors::unsupported_error("seek indexes are not supported for this format")
+    }
+
+    /// Returns the index of `build_seek_index` or `load_seek_index` encoded, to be loaded again later
+    fn export_seek_index(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Loads an index of `export_seek_index`, which fails if it is not of this stream
+    ///
+    /// The position of the stream is kept.
+    fn load_seek_index(&mut self, _index: &[u8], _info: &audio::AudioInfo) -> Result<()> {
+        errors::unsupported_error("seek indexes are not supported for this format")
+    }
+
+    /// Reads the header of a stream chained to the current one at the current position
+    ///
+    /// Returns `None` if no new header starts there, which the default always does.
+    fn read_chained_header(&mut self) -> Option<Result<audio::AudioInfo>> {
+        None
+    }
 }
 
 /// Returns a lazy iterator on audio samples
+///
+/// Iterators are `Send` like readers, but need not be `Sync`.
 pub trait AudioSamplesIterator<S: Sample>: Send {
     fn next(&mut self) -> Option<Result<S>>;
+
+    /// Continues the iteration at the inter-channel sample `frame`
+    ///
+    /// This needs a seekable input, the default fails with `Error::Unsupported`.
+    fn seek_frame(&mut self, _frame: u64) -> Result<()> {
+        errors::unsupported_error("seeking is not supported for this format")
+    }
+
+    /// Returns the bits per sample of each channel of the stream, without the
+    /// bits wasted in every frame decoded so far
+    ///
+    /// Only FLAC signals wasted bits, the default returns `None`, as do FLAC
+    /// iterators before the first frame.
+    fn effective_bits_per_sample(&self) -> Option<Vec<u32>> {
+        None
+    }
+
+    /// Returns the number of samples `next` still returns, if the stream tells it
+    ///
+    /// WAV files which can seek and FLAC streams with a length in STREAMINFO
+    /// know it, the default returns `None`. It sets the `size_hint` of the
+    /// iterator, which is no `ExactSizeIterator` since the others do not.
+    fn samples_left(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the number of inter-channel samples `next` still returns, if the stream tells it
+    ///
+    /// An inter-channel sample returned in part counts as one. It is known
+    /// where `samples_left` is, MP3 streams would need the frame count of a
+    /// Xing header, which is not read yet. Meant for progress bars, it is
+    /// correct after any number of samples and after `seek_frame`.
+    fn remaining_frames(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the sample rate of the samples `next` returns, if it is known up front
+    fn sample_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the playing time of the `remaining_frames`
+    fn remaining_duration(&self) -> Option<Duration> {
+        let frames = self.remaining_frames()?;
+        let rate = self.sample_rate().filter(|&rate| rate > 0)?;
+        Some(frames_duration(frames, rate))
+    }
+
+    /// Returns the new parameters of the stream if they changed before the sample
+    /// returned last by `next`
+    ///
+    /// Every change is returned once. Only FLAC and MP3 detect changes, the
+    /// default returns `None`.
+    fn take_stream_change(&mut self) -> Option<audio::AudioInfo> {
+        None
+    }
+
+    /// Returns the position after the last block decoded in full
+    ///
+    /// A block failing with `Error::IoError` is not applied in part, so that
+    /// `resume_after_io_error` can read it again. Only FLAC and WAV track it,
+    /// the default returns `None`.
+    fn last_good_position(&self) -> Option<audio::StreamPosition> {
+        None
+    }
+
+    /// Reads the block which failed with `Error::IoError` again on the next call to `next`
+    ///
+    /// After an IO error `next` returns `None` until this is called, e.g. once
+    /// a network-backed input is reachable again. The input returns to
+    /// `last_good_position`, which needs it to be seekable unless the bytes
+    /// are still buffered. Does nothing if the iterator did not fail with an
+    /// IO error, the default fails with `Error::Unsupported`.
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        errors::unsupported_error("resuming after an IO error is not supported for this format")
+    }
+
+    /// Reads the next samples into one buffer per channel, non-interleaved
+    ///
+    /// `out` holds a slice for each channel returned by the iterator, all of
+    /// the same length. Returns the number of inter-channel samples written,
+    /// which is less than the length of the slices only at the end of the
+    /// stream. It must not be called after `next` returned only part of the
+    /// channels of an inter-channel sample.
+    fn read_planar(&mut self, out: &mut [&mut [S]]) -> Result<usize> {
+        let frames = planar_len(out)?;
+        for frame in 0..frames {
+            for (channel, out) in out.iter_mut().enumerate() {
+                match self.next() {
+                    Some(sample) => out[frame] = sample?,
+                    None if channel == 0 => return Ok(frame),
+                    None => {
+                        return errors::parse_error("stream ended within an inter-channel sample")
+                    }
+                }
+            }
+        }
+        Ok(frames)
+    }
+}
+
+/// A decoder whose state carries over from one block to the next
+///
+/// The state is built from the blocks decoded so far, e.g. the bit reservoir
+/// of MP3 or the overlap of transform codecs. `AudioSamplesIterator::seek_frame`
+/// and the other jumps in the stream reset it, so that the blocks after the
+/// jump do not build on the ones before. Decoders without such state, like
+/// the one of FLAC, only drop the block decoded last.
+pub trait ResettableDecoder {
+    /// Discards the state of the blocks decoded so far, the input is kept where it is
+    fn reset(&mut self);
+}
+
+impl<'r> dyn AudioSamplesIterator<f32> + 'r {
+    /// `read_planar` for `f32` samples, e.g. to feed DSP code working on planar buffers
+    pub fn read_planar_f32(&mut self, out: &mut [&mut [f32]]) -> Result<usize> {
+        self.read_planar(out)
+    }
+}
+
+/// Returns the number of inter-channel samples `samples` of `channels` channels touch
+pub(crate) fn frames_of(samples: u64, channels: u64) -> u64 {
+    samples.div_ceil(channels.max(1))
+}
+
+/// Returns the playing time of `frames` inter-channel samples, zero if `sample_rate` is
+///
+/// Whole seconds and the remainder are computed apart in integers, so the
+/// nanoseconds are exact for any number of frames.
+pub(crate) fn frames_duration(frames: u64, sample_rate: u32) -> Duration {
+    if sample_rate == 0 {
+        return Duration::ZERO;
+    }
+    let rate = sample_rate as u64;
+    let nanos = (frames % rate) * 1_000_000_000 / rate;
+    Duration::from_secs(frames / rate) + Duration::from_nanos(nanos)
+}
+
+/// Returns the common length of the per channel buffers passed to `read_planar`.
+pub(crate) fn planar_len<S>(out: &[&mut [S]]) -> Result<usize> {
+    let frames = match out.first() {
+        Some(first) => first.len(),
+        None => return errors::unsupported_error("no channel buffers passed"),
+    };
+    if out.iter().any(|o| o.len() != frames) {
+        return errors::unsupported_error("channel buffers differ in length");
+    }
+    Ok(frames)
 }
 
 impl<'r, S: Sample> Iterator for dyn AudioSamplesIterator<S> + 'r {
@@ -256,4 +702,13 @@ impl<'r, S: Sample> Iterator for dyn AudioSamplesIterator<S> + 'r {
     fn next(&mut self) -> Option<Result<S>> {
         self.next()
     }
+
+    /// Exact if `samples_left` is known, `(0, None)` otherwise
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.samples_left().map(std::convert::TryFrom::try_from) {
+            Some(Ok(left)) => (left, Some(left)),
+            Some(Err(_)) => (usize::MAX, None),
+            None => (0, None),
+        }
+    }
 }
@@ -1,6 +1,9 @@
 use std::cmp;
 use std::io;
 
+#[cfg(any(feature = "flac", feature = "mp3"))]
+use cauldron_flac_core::{BitReader, ByteSource};
+
 /// Extends the functionality of `io::Read` with additional methods
 pub trait ReadBuffer {
     /// Reads as many bytes as `buf` is long.
@@ -90,30 +93,21 @@ impl<R: io::Read> ReadBuffer for R {
     fn read_into(&mut self, buf: &mut [u8]) -> io::Result<()> {
         let mut n = 0;
         while n < buf.len() {
-            let progress = self.read(&mut buf[n..])?;
-            if progress > 0 {
-                n += progress;
-            } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Failed to read enough bytes.",
-                ));
+            match self.read(&mut buf[n..]) {
+                Ok(0) => return Err(not_enough_bytes()),
+                Ok(progress) => n += progress,
+                // A signal interrupted the read before any data arrived, which
+                // is not a failure of the stream itself, so just try again.
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
         }
         Ok(())
     }
 
-    //noinspection RsExternalLinter
     #[inline(always)]
     fn read_bytes(&mut self, n: usize) -> io::Result<Vec<u8>> {
-        // We allocate a runtime fixed size buffer, and we are going to read
-        // into it, so zeroing or filling the buffer is a waste. This method
-        // is safe, because the contents of the buffer are only exposed when
-        // they have been overwritten completely by the read.
-        let mut buf = Vec::with_capacity(n);
-        unsafe {
-            buf.set_len(n);
-        }
+        let mut buf = vec![0u8; n];
         self.read_into(&mut buf[..])?;
         Ok(buf)
     }
@@ -124,18 +118,20 @@ impl<R: io::Read> ReadBuffer for R {
         // the result. 1024 is a tradeoff between doing a lot of calls, and
         // using too much stack space. This method is not in a hot path, so it
         // can afford to do this.
+        //
+        // Every read asks for at most what is left to skip, so the input is
+        // never consumed past the `n` bytes, and any error other than an
+        // interrupt is handed back to the caller right away instead of being
+        // retried, so a stalled stream that times out surfaces here.
         let mut n_read = 0;
         let mut buf = [0u8; 1024];
         while n_read < n {
             let end = cmp::min(n - n_read, 1024);
-            let progress = self.read(&mut buf[0..end])?;
-            if progress > 0 {
-                n_read += progress;
-            } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Failed to read enough bytes.",
-                ));
+            match self.read(&mut buf[0..end]) {
+                Ok(0) => return Err(not_enough_bytes()),
+                Ok(progress) => n_read += progress,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
         }
         Ok(())
@@ -197,211 +193,116 @@ impl<R: io::Read> ReadBuffer for R {
     }
 }
 
+/// A `ReadBuffer` which knows its position and can return to an earlier one
+pub trait Rewind: ReadBuffer {
+    /// Returns the number of bytes consumed from the start of the input.
+    fn position(&self) -> u64;
+
+    /// Returns to `position`, which must not be after the current position.
+    fn rewind(&mut self, position: u64) -> io::Result<()>;
+}
+
+impl<T: AsRef<[u8]>> Rewind for io::Cursor<T> {
+    fn position(&self) -> u64 {
+        io::Cursor::position(self)
+    }
+
+    fn rewind(&mut self, position: u64) -> io::Result<()> {
+        if position > io::Cursor::position(self) {
+            return Err(rewind_ahead());
+        }
+        self.set_position(position);
+        Ok(())
+    }
+}
+
+/// The error returned when asked to rewind to a position not read yet.
+pub(crate) fn rewind_ahead() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "can not rewind ahead of the position",
+    )
+}
+
+/// The error returned when the input ends before a read could be completed.
+pub(crate) fn not_enough_bytes() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Failed to read enough bytes.")
+}
+
 /// Wraps a `BufferReader` to facilitate reading that is not byte-aligned.
+///
+/// This is the `BitReader` of `cauldron_flac_core` on a `ReadBuffer`.
+#[cfg(any(feature = "flac", feature = "mp3"))]
 pub struct BitStream<'r, R: ReadBuffer> {
-    /// The source where bits are read from.
-    reader: &'r mut R,
-    /// Data read from the reader, but not yet fully consumed.
-    data: u8,
-    /// The number of bits of `data` that have not been consumed.
-    bits_left: u32,
+    bits: BitReader<ReadBytes<'r, R>>,
+}
+
+/// Reads the bytes of a `BitStream`
+#[cfg(any(feature = "flac", feature = "mp3"))]
+pub struct ReadBytes<'r, R: ReadBuffer>(&'r mut R);
+
+#[cfg(any(feature = "flac", feature = "mp3"))]
+impl<'r, R: ReadBuffer> ByteSource for ReadBytes<'r, R> {
+    type Error = io::Error;
+
+    #[inline(always)]
+    fn read_u8(&mut self) -> io::Result<u8> {
+        self.0.read_u8()
+    }
 }
 
+#[cfg(any(feature = "flac", feature = "mp3"))]
 impl<'r, R: ReadBuffer> BitStream<'r, R> {
     /// creates a new bitstream reader
-    pub fn new(reader: &mut R) -> BitStream<R> {
+    pub fn new(reader: &mut R) -> BitStream<'_, R> {
         BitStream {
-            reader,
-            data: 0,
-            bits_left: 0,
+            bits: BitReader::new(ReadBytes(reader)),
         }
     }
 
+    /// Returns the underlying `BitReader`, e.g. for the FLAC subframe decoder
+    #[cfg(feature = "flac")]
+    #[inline(always)]
+    pub fn bits(&mut self) -> &mut BitReader<ReadBytes<'r, R>> {
+        &mut self.bits
+    }
+
     /// Returns true if no bits are left and input is in byte aligned state
+    #[cfg(feature = "mp3")]
     #[inline(always)]
     pub fn is_aligned(&self) -> bool {
-        self.bits_left == 0
+        self.bits.is_aligned()
     }
 
     /// Reads a single bit
     #[inline(always)]
     pub fn read_bit(&mut self) -> io::Result<bool> {
-        // If no bits are left, we will need to read the next byte.
-        let result = if self.bits_left == 0 {
-            let fresh_byte = self.reader.read_u8()?;
-
-            // What remains later are the 7 least significant bits.
-            self.data = fresh_byte << 1;
-            self.bits_left = 7;
-
-            // What we report is the most significant bit of the fresh byte.
-            fresh_byte & 0b1000_0000
-        } else {
-            // Consume the most significant bit of the buffer byte.
-            let bit = self.data & 0b1000_0000;
-            self.data <<= 1;
-            self.bits_left = self.bits_left - 1;
-            bit
-        };
-
-        Ok(result != 0)
+        self.bits.read_bit()
     }
 
     /// Reads at most 8 bits.
     #[inline(always)]
     pub fn read_len_u8(&mut self, bits: u32) -> io::Result<u8> {
-        // If not enough bits left, we will need to read the next byte.
-        let result = if self.bits_left < bits {
-            // Most significant bits are shifted to the right position already.
-            let msb = self.data;
-
-            // Read a single byte.
-            self.data = self.reader.read_u8()?;
-
-            // From the next byte, we take the additional bits that we need.
-            // Those start at the most significant bit, so we need to shift so
-            // that it does not overlap with what we have already.
-            let lsb =
-                (self.data & BitStream::<R>::mask_u8(bits - self.bits_left)) >> self.bits_left;
-
-            // Shift out the bits that we have consumed.
-            self.data = BitStream::<R>::shift_left(self.data, bits - self.bits_left);
-            self.bits_left = 8 - (bits - self.bits_left);
-
-            msb | lsb
-        } else {
-            let result = self.data & BitStream::<R>::mask_u8(bits);
-
-            // Shift out the bits that we have consumed.
-            self.data = self.data << bits;
-            self.bits_left = self.bits_left - bits;
-
-            result
-        };
-
-        // The resulting data is padded with zeros in the least significant
-        // bits, but we want to pad in the most significant bits, so shift.
-        Ok(BitStream::<R>::shift_right(result, 8 - bits))
+        self.bits.read_len_u8(bits)
     }
 
     /// Reads at most 16 bits.
+    #[cfg(feature = "mp3")]
     #[inline(always)]
     pub fn read_len_u16(&mut self, bits: u32) -> io::Result<u16> {
-        // Note: the following is not the most efficient implementation
-        // possible, but it avoids duplicating the complexity of `read_len_u8`.
-
-        if bits <= 8 {
-            let result = self.read_len_u8(bits)?;
-            Ok(result as u16)
-        } else {
-            // First read the 8 most significant bits, then read what is left.
-            let msb = self.read_len_u8(8)? as u16;
-            let lsb = self.read_len_u8(bits - 8)? as u16;
-            Ok((msb << (bits - 8)) | lsb)
-        }
-    }
-
-    /// Reads at most 32 bits.
-    #[inline(always)]
-    pub fn read_len_u32(&mut self, bits: u32) -> io::Result<u32> {
-        // As with read_len_u8, this only makes sense if we read <= 32 bits.
-        debug_assert!(bits <= 32);
-
-        // Note: the following is not the most efficient implementation
-        // possible, but it avoids duplicating the complexity of `read_len_u8`.
-
-        if bits <= 16 {
-            let result = self.read_len_u16(bits)?;
-            Ok(result as u32)
-        } else {
-            // First read the 16 most significant bits, then read what is left.
-            let msb = self.read_len_u16(16)? as u32;
-            let lsb = self.read_len_u16(bits - 16)? as u32;
-            Ok((msb << (bits - 16)) | lsb)
-        }
+        self.bits.read_len_u16(bits)
     }
 
     /// Reads bits until a 1 is read, and returns the number of zeros read.
-    /// See here https://en.wikipedia.org/wiki/Unary_coding
+    #[cfg(feature = "flac")]
     #[inline(always)]
     pub fn read_unary(&mut self) -> io::Result<u32> {
-        // Count the zeroes already present in the buffer
-        // (counting from the most significant bit).
-        let mut n = self.data.leading_zeros();
-
-        // If the number of zeros plus the one following it was not more than
-        // the bytes left, then there is no need to look further.
-        if n < self.bits_left {
-            // save the bits left in data
-            self.data = self.data << (n + 1);
-            self.bits_left = self.bits_left - (n + 1);
-        } else {
-            // counter the case when no bits are left and data = 0
-            n = self.bits_left;
-
-            // Continue reading bytes until we encounter a one.
-            loop {
-                let fresh_byte = self.reader.read_u8()?;
-                let zeros = fresh_byte.leading_zeros();
-                n = n + zeros;
-                if zeros < 8 {
-                    // We consumed the zeros, plus the one following it.
-                    self.bits_left = 8 - (zeros + 1);
-                    if zeros == 7 {
-                        self.data = 0;
-                    } else {
-                        self.data = fresh_byte << (zeros + 1);
-                    }
-                    break;
-                }
-            }
-        }
-
-        Ok(n)
+        self.bits.read_unary()
     }
 
+    #[cfg(feature = "mp3")]
     #[inline(always)]
     pub fn skip_len_u8(&mut self, bits: u32) -> io::Result<()> {
-        // If not enough bits left, we will need to read the next byte.
-        if self.bits_left < bits {
-            // Read a single byte.
-            self.data = self.reader.read_u8()?;
-
-            // Shift out the bits that we have consumed.
-            self.data = BitStream::<R>::shift_left(self.data, bits - self.bits_left);
-            self.bits_left = 8 - (bits - self.bits_left);
-        } else {
-            // Shift out the bits that we have consumed.
-            self.data = self.data << bits;
-            self.bits_left = self.bits_left - bits;
-        }
-
-        Ok(())
-    }
-
-    // Generates a bitmask with 1s in the `bits` most significant bits.
-    #[inline(always)]
-    fn mask_u8(bits: u32) -> u8 {
-        debug_assert!(bits <= 8);
-
-        BitStream::<R>::shift_left(0xff, 8 - bits)
-    }
-
-    fn shift_left(x: u8, shift: u32) -> u8 {
-        debug_assert!(shift <= 8);
-
-        // We cannot shift a u8 by 8 or more, because Rust panics when shifting by
-        // the integer width. But we can definitely shift a u32.
-        ((x as u16) << shift) as u8
-    }
-
-    /// Right shift that does not panic when shifting by the integer width.
-    #[inline(always)]
-    fn shift_right(x: u8, shift: u32) -> u8 {
-        debug_assert!(shift <= 8);
-
-        // We cannot shift a u8 by 8 or more, because Rust panics when shifting by
-        // the integer width. But we can definitely shift a u32.
-        ((x as u32) >> shift) as u8
+        self.bits.skip_len_u8(bits)
     }
 }
@@ -1,6 +1,5 @@
-use num_traits::ToPrimitive;
 use std::io;
-use std::io::{ErrorKind, Write};
+use std::io::Write;
 
 /// Extends the functionality of `io::Write` with additional methods.
 pub trait WriteBuffer: Write {
@@ -104,16 +103,10 @@ where
 
     #[inline(always)]
     fn write_le_f32(&mut self, x: f32) -> io::Result<()> {
-        let u = x.to_u32().ok_or_else(|| {
-            io::Error::new(ErrorKind::InvalidData, "Failed to convert f32 to u32")
-        })?;
-        self.write_le_u32(u)
+        self.write_le_u32(x.to_bits())
     }
 
     fn write_le_f64(&mut self, x: f64) -> io::Result<()> {
-        let u = x.to_u64().ok_or_else(|| {
-            io::Error::new(ErrorKind::InvalidData, "Failed to convert f64 to u64")
-        })?;
-        self.write_le_u64(u)
+        self.write_le_u64(x.to_bits())
     }
 }
@@ -14,6 +14,16 @@
 //!
 //! let samples: Vec<i32> = audio_segment.samples().map(|r| r.unwrap()).collect();
 //! ```
+//!
+//! # Threads
+//!
+//! Decoding needs `&mut` access, so the decoding types are `Send` but not
+//! `Sync`: an `AudioSegment`, a `Decoder`, a `SampleIterator`, an
+//! `OwnedSamples` and a `playlist::Player` can be moved to another thread,
+//! and shared behind a `Mutex`. Readers passed to `AudioSegment::from_reader`
+//! have to be `Send` for this. The descriptions of a stream, e.g.
+//! `AudioInfo`, `StreamDescription` and `DecodeOptions`, and `errors::Error`
+//! are `Send` and `Sync`. Both promises are checked when the crate is compiled.
 
 // #[macro_use]
 macro_rules! otry {
@@ -25,19 +35,118 @@ macro_rules! otry {
     };
 }
 
+/// Creates the `TypedSamples` variant of `$sample_type`, `$make` is evaluated
+/// with `$S` naming its sample type.
+macro_rules! typed_samples {
+    ($sample_type:expr, $S:ident => $make:expr) => {{
+        use crate::format::{SampleType, TypedSamples};
+        match $sample_type {
+            SampleType::U8 => {
+                type $S = u8;
+                TypedSamples::U8($make)
+            }
+            SampleType::I16 => {
+                type $S = i16;
+                TypedSamples::I16($make)
+            }
+            SampleType::I32 => {
+                type $S = i32;
+                TypedSamples::I32($make)
+            }
+            SampleType::F32 => {
+                type $S = f32;
+                TypedSamples::F32($make)
+            }
+            SampleType::F64 => {
+                type $S = f64;
+                TypedSamples::F64($make)
+            }
+        }
+    }};
+}
+
 pub mod audio;
+#[cfg(feature = "threads")]
+pub mod background;
+#[cfg(feature = "batch")]
+pub mod batch;
 pub mod codecs;
+pub mod crc;
+pub mod dither;
 pub mod errors;
+pub mod format;
+pub mod g711;
+pub mod hash;
+#[cfg(feature = "hound")]
+pub mod hound;
+pub mod loudness;
+pub mod md5;
+pub mod overview;
+pub mod pictures;
+pub mod playlist;
+pub mod process;
+pub mod raw;
+pub mod sha256;
+pub mod stats;
+pub mod stereo;
+pub mod tracks;
+
+pub use capabilities::{capabilities, Capabilities, FormatCapabilities, Seeking};
+pub use compare::{compare, CompareReport};
+pub use format::{detect_format, scan_for_audio};
+#[cfg(feature = "wav")]
+pub use wav::write::WavWriter;
 
 // private modules
-mod crc;
+mod capabilities;
+mod compare;
 mod io;
 mod utils;
 
+#[cfg(test)]
+mod bench_fixtures;
+#[cfg(test)]
+mod testing;
+
 // codec modules
+#[cfg(feature = "flac")]
 mod flac;
+#[cfg(feature = "mp3")]
 mod mp3;
+#[cfg(feature = "ogg")]
+mod ogg;
+#[cfg(feature = "wav")]
 mod wav;
 
 /// A type for result generated by Cauldron
 pub type Result<T> = std::result::Result<T, errors::Error>;
+
+// The threading promises of the crate docs, a type losing one fails to compile.
+const _: fn() = || {
+    fn send<T: Send>() {}
+    fn send_sync<T: Send + Sync>() {}
+
+    send::<audio::AudioSegment>();
+    send::<audio::Decoder>();
+    send::<audio::OwnedSamples<i32>>();
+    send::<audio::SampleIterator<'static, f32>>();
+    send::<audio::Events<'static, i16>>();
+    send::<playlist::Player<f32>>();
+    #[cfg(feature = "flac")]
+    send::<flac::FlacSamplesIterator<'static, i32>>();
+    #[cfg(feature = "mp3")]
+    send::<mp3::Mp3SamplesIterator<'static, f32>>();
+    #[cfg(feature = "wav")]
+    send::<wav::WavSamplesIterator<'static, i16>>();
+    #[cfg(feature = "threads")]
+    send::<background::DecodeHandle>();
+    #[cfg(feature = "threads")]
+    send::<background::SampleReceiver<f32>>();
+
+    send_sync::<audio::AudioInfo>();
+    send_sync::<audio::StreamDescription>();
+    send_sync::<audio::DecodeOptions>();
+    send_sync::<stats::InstrumentationHandle>();
+    send_sync::<playlist::PlaylistTrack>();
+    send_sync::<errors::Error>();
+};
@@ -0,0 +1,487 @@
+//! Loudness of decoded audio after ITU-R BS.1770, as EBU R128 normalizes it.
+//!
+//! The integrated loudness is the gated mean of the K-weighted power of
+//! 400 ms blocks overlapping by 75 %. Blocks below -70 LUFS are dropped, then
+//! those more than 10 LU below the mean of the rest. The true peak is the
+//! largest sample of the audio oversampled four times.
+
+use std::f64::consts::PI;
+
+use crate::audio::{AudioSegment, Channels, SampleIterator};
+use crate::errors;
+use crate::Result;
+
+/// number of inter-channel samples read at once
+const CHUNK_FRAMES: usize = 4096;
+
+/// blocks below this loudness are silence
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// blocks this far below the mean loudness are dropped
+const RELATIVE_GATE: f64 = -10.0;
+
+/// true peaks are found between the samples oversampled this many times
+const OVERSAMPLING: usize = 4;
+
+/// taps of each phase of the interpolation filter
+const TAPS_PER_PHASE: usize = 12;
+
+/// The loudness of audio, see `AudioSegment::loudness`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    /// integrated loudness in LUFS, `f64::NEG_INFINITY` for audio shorter than
+    /// a block or silent
+    pub integrated: f64,
+
+    /// largest sample of the oversampled audio in dB relative to full scale
+    pub true_peak_db: f64,
+}
+
+/// Returns the gain in dB which brings `loudness` to `target_lufs`, without a
+/// true peak above `true_peak_ceiling_db`
+///
+/// Fails with `Error::Unsupported` for silent audio, which no gain makes louder.
+pub fn normalization_gain(
+    loudness: &Loudness,
+    target_lufs: f64,
+    true_peak_ceiling_db: f64,
+) -> Result<f64> {
+    if !loudness.integrated.is_finite() {
+        return errors::unsupported_error("silent audio can not be normalized");
+    }
+    let gain = target_lufs - loudness.integrated;
+    Ok(gain.min(true_peak_ceiling_db - loudness.true_peak_db))
+}
+
+/// Measures the loudness of the interleaved `samples` of the channels `channels`, in `[-1.0, 1.0)`
+///
+/// `channels` tells the speaker of each channel: LFE channels do not count,
+/// back and side channels count 1.5 dB more than front ones.
+pub fn measure(
+    mut samples: SampleIterator<'_, f32>,
+    channels: &[Channels],
+    sample_rate: u32,
+) -> Result<Loudness> {
+    if channels.is_empty() {
+        return errors::unsupported_error("loudness needs at least one channel");
+    }
+    if sample_rate == 0 {
+        return errors::unsupported_error("the sample rate of the stream is unknown");
+    }
+
+    let weights: Vec<f64> = channels.iter().map(|&c| channel_weight(c)).collect();
+    let mut filters = vec![KWeighting::new(sample_rate as f64); channels.len()];
+    let mut peaks = vec![TruePeak::new(); channels.len()];
+    // the power of 100 ms each, four of them make a block
+    let step = (sample_rate as usize).div_ceil(10);
+    let mut step_power = vec![0.0; channels.len()];
+    let mut step_frames = 0;
+    let mut steps: Vec<f64> = Vec::new();
+
+    let mut buffers = vec![vec![0.0f32; CHUNK_FRAMES]; channels.len()];
+    loop {
+        let read = {
+            let mut out: Vec<&mut [f32]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+            samples.read_planar(&mut out)?
+        };
+        let mut start = 0;
+        while start < read {
+            let run = (step - step_frames).min(read - start);
+            for (channel, buffer) in buffers.iter().enumerate() {
+                for &sample in &buffer[start..start + run] {
+                    let sample = sample as f64;
+                    peaks[channel].push(sample);
+                    let weighted = filters[channel].process(sample);
+                    step_power[channel] += weighted * weighted;
+                }
+            }
+            start += run;
+            step_frames += run;
+            if step_frames == step {
+                let power: f64 = step_power.iter().zip(&weights).map(|(p, w)| p * w).sum();
+                steps.push(power / step as f64);
+                step_power.iter_mut().for_each(|p| *p = 0.0);
+                step_frames = 0;
+            }
+        }
+        if read < CHUNK_FRAMES {
+            break;
+        }
+    }
+
+    let blocks: Vec<f64> = steps
+        .windows(4)
+        .map(|w| w.iter().sum::<f64>() / 4.0)
+        .collect();
+    let peak = peaks.iter().map(TruePeak::peak).fold(0.0, f64::max);
+    Ok(Loudness {
+        integrated: gated_loudness(&blocks),
+        true_peak_db: 20.0 * peak.log10(),
+    })
+}
+
+/// `AudioSegment::loudness`
+pub(crate) fn segment_loudness(segment: &mut AudioSegment) -> Result<Loudness> {
+    let channels = segment.channel_order();
+    let sample_rate = segment.info().sample_rate;
+    measure(segment.samples::<f32>()?, &channels, sample_rate)
+}
+
+/// Returns the weight of the power of `channel` in the loudness
+fn channel_weight(channel: Channels) -> f64 {
+    let surround =
+        Channels::BACK_LEFT | Channels::BACK_RIGHT | Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+    if channel.intersects(Channels::LFE1 | Channels::LFE2) {
+        0.0
+    } else if channel.intersects(surround) {
+        1.41
+    } else {
+        1.0
+    }
+}
+
+/// Returns the loudness of the mean power `power`
+fn power_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Returns the loudness of the blocks of mean power `blocks` passing both gates
+fn gated_loudness(blocks: &[f64]) -> f64 {
+    let mean = |blocks: &mut dyn Iterator<Item = &f64>| {
+        let (sum, n) = blocks.fold((0.0, 0), |(sum, n), p| (sum + p, n + 1));
+        if n == 0 {
+            0.0
+        } else {
+            sum / n as f64
+        }
+    };
+    let audible = |p: &&f64| power_loudness(**p) > ABSOLUTE_GATE;
+    let relative_gate = power_loudness(mean(&mut blocks.iter().filter(audible))) + RELATIVE_GATE;
+    let power = mean(
+        &mut blocks
+            .iter()
+            .filter(audible)
+            .filter(|p| power_loudness(**p) > relative_gate),
+    );
+    power_loudness(power)
+}
+
+/// A biquad filter in direct form I
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The K-weighting of BS.1770, a high shelf modelling the head and a highpass
+///
+/// The coefficients are derived for any sample rate from the analog filters
+/// the 48 kHz ones of the standard are designed from.
+#[derive(Clone, Copy)]
+struct KWeighting {
+    shelf: Biquad,
+    highpass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> KWeighting {
+        let k = (PI * 1_681.974_450_955_533 / sample_rate).tan();
+        let q = 0.707_175_236_955_419_6;
+        let vh = 10f64.powf(3.999_843_853_973_347 / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        };
+
+        let k = (PI * 38.135_470_876_024_44 / sample_rate).tan();
+        let q = 0.500_327_037_323_877_3;
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        };
+        KWeighting { shelf, highpass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.highpass.process(self.shelf.process(x))
+    }
+}
+
+/// The largest sample of one channel oversampled by a windowed sinc
+#[derive(Clone)]
+struct TruePeak {
+    /// the last `TAPS_PER_PHASE` samples, the latest last
+    history: [f64; TAPS_PER_PHASE],
+    /// the taps of each phase between two samples
+    phases: [[f64; TAPS_PER_PHASE]; OVERSAMPLING - 1],
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new() -> TruePeak {
+        let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING - 1];
+        for (phase, taps) in phases.iter_mut().enumerate() {
+            *taps = interpolation_phase(phase + 1);
+        }
+        TruePeak {
+            history: [0.0; TAPS_PER_PHASE],
+            phases,
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, sample: f64) {
+        self.history.rotate_left(1);
+        self.history[TAPS_PER_PHASE - 1] = sample;
+        self.peak = self.peak.max(sample.abs());
+        for taps in &self.phases {
+            let value: f64 = self.history.iter().zip(taps).map(|(s, t)| s * t).sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+
+    fn peak(&self) -> f64 {
+        self.peak
+    }
+}
+
+/// Returns the taps interpolating at `phase / OVERSAMPLING` between the two
+/// samples in the middle of the history
+///
+/// A Hann windowed sinc, scaled to a gain of 1 for DC.
+fn interpolation_phase(phase: usize) -> [f64; TAPS_PER_PHASE] {
+    let mut taps = [0.0; TAPS_PER_PHASE];
+    let center = (TAPS_PER_PHASE / 2 - 1) as f64 + phase as f64 / OVERSAMPLING as f64;
+    let half_width = TAPS_PER_PHASE as f64 / 2.0;
+    for (i, tap) in taps.iter_mut().enumerate() {
+        let t = i as f64 - center;
+        let sinc = if t == 0.0 {
+            1.0
+        } else {
+            (PI * t).sin() / (PI * t)
+        };
+        let window = 0.5 + 0.5 * (PI * t / half_width).cos();
+        *tap = sinc * window;
+    }
+    let sum: f64 = taps.iter().sum();
+    taps.iter_mut().for_each(|t| *t /= sum);
+    taps
+}
+
+/// Returns `seconds` of a sine of `frequency` Hz with a peak of `peak_db` on each of `channels` channels
+#[cfg(test)]
+fn sine(sample_rate: u32, channels: usize, seconds: f64, frequency: f64, peak_db: f64) -> Vec<f32> {
+    let amplitude = 10f64.powf(peak_db / 20.0);
+    let frames = (sample_rate as f64 * seconds) as usize;
+    (0..frames)
+        .flat_map(|i| {
+            let t = i as f64 / sample_rate as f64;
+            let sample = (amplitude * (2.0 * PI * frequency * t).sin()) as f32;
+            std::iter::repeat_n(sample, channels)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn measure_interleaved(samples: Vec<f32>, channels: &[Channels], sample_rate: u32) -> Loudness {
+    let samples: SampleIterator<'static, f32> = Box::new(crate::testing::VecSamples::new(samples));
+    measure(samples, channels, sample_rate).unwrap()
+}
+
+#[test]
+fn test_stereo_sine_reads_its_level() {
+    // EBU Tech 3341 case 1, a 1 kHz sine of -23 dBFS on both channels is -23 LUFS
+    let stereo = [Channels::FRONT_LEFT, Channels::FRONT_RIGHT];
+    for &sample_rate in &[44_100, 48_000] {
+        let loudness = measure_interleaved(
+            sine(sample_rate, 2, 2.0, 1_000.0, -23.0),
+            &stereo,
+            sample_rate,
+        );
+        assert!(
+            (loudness.integrated + 23.0).abs() < 0.1,
+            "{}",
+            loudness.integrated
+        );
+        assert!((loudness.true_peak_db + 23.0).abs() < 0.1);
+    }
+
+    // the LFE does not count, surround channels count more
+    let loudness = measure_interleaved(
+        sine(48_000, 2, 2.0, 1_000.0, -23.0),
+        &[Channels::FRONT_LEFT, Channels::LFE1],
+        48_000,
+    );
+    assert!((loudness.integrated + 26.0).abs() < 0.1);
+    let loudness = measure_interleaved(
+        sine(48_000, 2, 2.0, 1_000.0, -23.0),
+        &[Channels::FRONT_LEFT, Channels::SIDE_LEFT],
+        48_000,
+    );
+    assert!((loudness.integrated + 26.0 - 10.0 * 2.41f64.log10()).abs() < 0.1);
+}
+
+#[test]
+fn test_true_peak_between_samples() {
+    // a sine at a quarter of the sample rate, sampled 45° off its peaks
+    let samples: Vec<f32> = (0..4_800)
+        .map(|i| (0.5 * (PI / 2.0 * i as f64 + PI / 4.0).sin()) as f32)
+        .collect();
+    let sample_peak = samples.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+    assert!((20.0 * sample_peak.log10() + 9.03).abs() < 0.01);
+    let loudness = measure_interleaved(samples, &[Channels::FRONT_CENTRE], 48_000);
+    assert!(
+        (loudness.true_peak_db + 6.02).abs() < 0.3,
+        "{}",
+        loudness.true_peak_db
+    );
+}
+
+#[test]
+fn test_gates() {
+    let mono = [Channels::FRONT_CENTRE];
+    // silence and audio shorter than a block have no loudness
+    let silence = measure_interleaved(vec![0.0; 48_000], &mono, 48_000);
+    assert_eq!(silence.integrated, f64::NEG_INFINITY);
+    let short = measure_interleaved(sine(48_000, 1, 0.3, 1_000.0, -20.0), &mono, 48_000);
+    assert_eq!(short.integrated, f64::NEG_INFINITY);
+    assert!(normalization_gain(&silence, -23.0, -1.0).is_err());
+
+    // the silence in the middle is gated away, however long it is
+    let with_gap = |seconds: usize| {
+        let mut samples = sine(48_000, 1, 2.0, 1_000.0, -20.0);
+        samples.extend(vec![0.0; 48_000 * seconds]);
+        samples.extend(sine(48_000, 1, 2.0, 1_000.0, -20.0));
+        measure_interleaved(samples, &mono, 48_000)
+    };
+    let loudness = with_gap(1);
+    assert!((loudness.integrated - with_gap(10).integrated).abs() < 1e-9);
+    // only the blocks in part on the sines are quieter
+    assert!((loudness.integrated + 23.01).abs() < 0.5);
+
+    // the gain stops at the ceiling
+    let gain = normalization_gain(&loudness, -10.0, -15.0).unwrap();
+    assert!((gain - (-15.0 - loudness.true_peak_db)).abs() < 1e-9);
+    let gain = normalization_gain(&loudness, -30.0, -1.0).unwrap();
+    assert!((gain - (-30.0 - loudness.integrated)).abs() < 1e-9);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_normalize_loudness() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let stereo = [Channels::FRONT_LEFT, Channels::FRONT_RIGHT];
+    let samples = sine(48_000, 2, 2.0, 1_000.0, -15.0);
+    let data: Vec<u8> = samples
+        .iter()
+        .flat_map(|&s| ((s * 32_768.0) as i16).to_le_bytes())
+        .collect();
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 48_000)),
+        riff_chunk(b"data", &data),
+    ]);
+    let segment = || {
+        AudioSegment::from_seekable_reader(
+            std::io::Cursor::new(file.clone()),
+            crate::codecs::FormatFlag::WAV,
+        )
+        .unwrap()
+    };
+
+    let mut normalized = segment();
+    let pipeline = normalized.normalize_loudness(-23.0, -1.0).unwrap();
+    let loudness = measure(pipeline.build::<f32>().unwrap(), &stereo, 48_000).unwrap();
+    assert!(
+        (loudness.integrated + 23.0).abs() < 0.2,
+        "{}",
+        loudness.integrated
+    );
+
+    // 10 dB louder would peak at -5 dBTP
+    let mut limited = segment();
+    let pipeline = limited.normalize_loudness(-5.0, -10.0).unwrap();
+    let loudness = measure(pipeline.build::<f32>().unwrap(), &stereo, 48_000).unwrap();
+    assert!(loudness.true_peak_db <= -10.0 + 0.01);
+    assert!((loudness.integrated + 10.0).abs() < 0.2);
+
+    let mut unseekable = AudioSegment::from_reader(
+        std::io::Cursor::new(file.clone()),
+        crate::codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    assert!(unseekable.normalize_loudness(-23.0, -1.0).is_err());
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn test_loudness_of_pcm_depths() {
+    use crate::codecs::FormatFlag;
+    use crate::testing::{pcm_fmt, riff_chunk, wav_file, FlacStream};
+
+    // the sine of Tech 3341 case 1 reads -23 LUFS at any depth it is stored in
+    let samples = sine(48_000, 2, 2.0, 1_000.0, -23.0);
+    let quantized = |bits: u32| -> Vec<i32> {
+        let scale = (1 << (bits - 1)) as f32;
+        samples
+            .iter()
+            .map(|&s| (s * scale).round() as i32)
+            .collect()
+    };
+    let wav = |bits: u16| {
+        let data: Vec<u8> = quantized(bits as u32)
+            .iter()
+            .flat_map(|s| s.to_le_bytes()[..bits as usize / 8].to_vec())
+            .collect();
+        wav_file(&[
+            riff_chunk(b"fmt ", &pcm_fmt(2, 48_000, bits)),
+            riff_chunk(b"data", &data),
+        ])
+    };
+    let mut flac = FlacStream::new(48_000, 2, 24);
+    for frame in quantized(24).chunks(2 * 4096) {
+        let left = frame.iter().step_by(2).copied().collect();
+        let right = frame.iter().skip(1).step_by(2).copied().collect();
+        flac.frame(&[left, right]);
+    }
+    for (file, format) in [
+        (wav(16), FormatFlag::WAV),
+        (wav(24), FormatFlag::WAV),
+        (flac.build(), FormatFlag::FLAC),
+    ] {
+        let mut segment = AudioSegment::from_reader(std::io::Cursor::new(file), format).unwrap();
+        let loudness = segment.loudness().unwrap();
+        assert!(
+            (loudness.integrated + 23.0).abs() < 0.1,
+            "{} bits of {:?}: {}",
+            segment.info().bits_per_sample,
+            format,
+            loudness.integrated
+        );
+        assert!((loudness.true_peak_db + 23.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,173 @@
+//! The `md5` module computes the MD5 signature FLAC stores in STREAMINFO.
+//!
+//! The signature covers the decoded samples, interleaved and little endian,
+//! each in as many whole bytes as its bits per sample take. `Md5` takes them
+//! piece by piece, e.g. one frame at a time.
+
+// Credit:
+// The algorithm and the test vectors are those of RFC 1321.
+// https://www.rfc-editor.org/rfc/rfc1321
+
+// the left rotations of each of the 64 steps
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+// floor(abs(sin(i + 1)) * 2^32)
+const SINES: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// An MD5 signature computed piece by piece
+#[derive(Clone, Debug)]
+pub struct Md5 {
+    state: [u32; 4],
+    /// bytes of an incomplete block
+    block: [u8; 64],
+    /// number of bytes taken so far
+    len: u64,
+}
+
+impl Default for Md5 {
+    fn default() -> Md5 {
+        Md5::new()
+    }
+}
+
+impl Md5 {
+    /// Starts the signature of an empty message
+    pub fn new() -> Md5 {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            block: [0; 64],
+            len: 0,
+        }
+    }
+
+    /// Continues the signature with `data`
+    pub fn update(&mut self, mut data: &[u8]) {
+        let filled = (self.len % 64) as usize;
+        self.len += data.len() as u64;
+        if filled > 0 {
+            let n = data.len().min(64 - filled);
+            self.block[filled..filled + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            if filled + n < 64 {
+                return;
+            }
+            let block = self.block;
+            self.process(&block);
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.process(block);
+        }
+        let rest = blocks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+    }
+
+    /// Returns the signature of all data taken
+    pub fn finish(mut self) -> [u8; 16] {
+        let bits = self.len.wrapping_mul(8);
+        // pad with a single 1 bit and zeros up to 56 bytes of the last block
+        let padding = (119 - (self.len % 64) as usize) % 64 + 1;
+        let mut tail = [0u8; 72];
+        tail[0] = 0x80;
+        tail[padding..padding + 8].copy_from_slice(&bits.to_le_bytes());
+        self.update(&tail[..padding + 8]);
+
+        let mut digest = [0u8; 16];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(&self.state) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    fn process(&mut self, block: &[u8]) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(SINES[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        for (state, value) in self.state.iter_mut().zip(&[a, b, c, d]) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// Returns the MD5 signature of `data`
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    md5.update(data);
+    md5.finish()
+}
+
+#[cfg(test)]
+fn hex(digest: [u8; 16]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn test_rfc_1321_vectors() {
+    let vectors: [(&str, &str); 7] = [
+        ("", "d41d8cd98f00b204e9800998ecf8427e"),
+        ("a", "0cc175b9c0f1b6a831c399e269772661"),
+        ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+        ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+        (
+            "abcdefghijklmnopqrstuvwxyz",
+            "c3fcd3d76192e4007dfb496cca67e13b",
+        ),
+        (
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+            "d174ab98d277d9f5a5611c2c9f419d9f",
+        ),
+        (
+            "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+            "57edf4a22be3c955ac49da2e2107b67a",
+        ),
+    ];
+    for (message, digest) in &vectors {
+        assert_eq!(hex(md5(message.as_bytes())), *digest, "{:?}", message);
+    }
+}
+
+#[test]
+fn test_update_in_pieces() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    for piece in &[1, 3, 63, 64, 65, 500] {
+        let mut md5 = Md5::new();
+        for chunk in data.chunks(*piece) {
+            md5.update(chunk);
+        }
+        assert_eq!(md5.finish(), self::md5(&data), "pieces of {}", piece);
+    }
+}
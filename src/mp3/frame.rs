@@ -1,4 +1,4 @@
-use crate::io::{BitStream, ReadBuffer};
+use crate::io::{BitStream, ReadBuffer, ResettableDecoder, Rewind};
 use crate::{errors, Result};
 
 use super::types::*;
@@ -16,24 +16,36 @@ static BIT_RATES_MPEG2_L3: [u32; 15] = [
 ];
 
 /// represent a block of decoded samples from a frame
+///
+/// The samples are stored per channel, one channel after another.
+///
+/// This channel-major layout is guaranteed: sample `i` of channel `c` is at
+/// index `c * total_samples() + i` of `into_buffer()`, and the buffer holds
+/// exactly `num_channels() * total_samples()` samples.
 pub struct Block {
     /// number of channel independent samples in this block
     block_size: u32,
     /// number of channels in this block
     no_channels: u32,
-    /// bits pr sample
-    bits_per_sample: u32,
+    /// bitrate of the frame in bits per second
+    bitrate: u32,
+    /// sample rate of the frame
+    sample_rate: u32,
     /// decoded samples with channels one after another
     buffer: Vec<f32>,
+    /// the bytes of the main data following the audio data
+    ancillary: Vec<u8>,
 }
 
 impl Block {
-    fn new(block_size: u32, bps: u32, buffer: Vec<f32>) -> Block {
+    fn new(block_size: u32, bitrate: u32, sample_rate: u32, buffer: Vec<f32>) -> Block {
         Block {
             block_size,
             no_channels: buffer.len() as u32 / block_size,
-            bits_per_sample: bps,
+            bitrate,
+            sample_rate,
             buffer,
+            ancillary: Vec::new(),
         }
     }
 
@@ -41,8 +53,10 @@ impl Block {
         Block {
             block_size: 0,
             no_channels: 0,
-            bits_per_sample: 0,
+            bitrate: 0,
+            sample_rate: 0,
             buffer: Vec::with_capacity(0),
+            ancillary: Vec::new(),
         }
     }
 
@@ -56,9 +70,36 @@ impl Block {
         self.no_channels
     }
 
+    /// returns 0, the samples are floats which have no bit depth
     #[inline(always)]
     pub fn bits_per_sample(&self) -> u32 {
-        self.bits_per_sample
+        0
+    }
+
+    /// returns the bitrate of the frame in bits per second
+    #[inline(always)]
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    /// returns the sample rate of the frame
+    #[inline(always)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// returns the ancillary data of the frame, the bytes after its audio data
+    ///
+    /// The audio data of a frame spans the `part2_3_length` bits of its side
+    /// info, counted from where `main_data_begin` points. The bytes from its
+    /// end to the end of the frame are returned here, broadcast streams hide
+    /// e.g. text in them. A frame after this one may take the last of them as
+    /// the start of its main data, which makes them no ancillary data after
+    /// all; only the ones before its `main_data_begin` are. Empty for a frame
+    /// padded to its end, and for a frame decoded as silence after a reset.
+    #[inline(always)]
+    pub fn ancillary(&self) -> &[u8] {
+        &self.ancillary
     }
 
     /// returns the underlying buffer which stores sample
@@ -67,27 +108,120 @@ impl Block {
         self.buffer
     }
 
+    /// returns the `total_samples()` samples of one channel
+    ///
+    /// Panics if the channel is out of range.
+    #[inline(always)]
+    pub fn channel(&self, channel: u32) -> &[f32] {
+        assert!(channel < self.no_channels, "channel out of range");
+        let start = channel as usize * self.block_size as usize;
+        &self.buffer[start..start + self.block_size as usize]
+    }
+
+    /// returns the decoded sample, or `None` if the channel or sample index is out of range
+    #[inline(always)]
+    pub fn try_get_sample(&self, channel: u32, sample: u32) -> Option<f32> {
+        if channel < self.no_channels && sample < self.block_size {
+            Some(self.get_sample(channel, sample))
+        } else {
+            None
+        }
+    }
+
     /// return the decoded sample from the buffer
+    ///
+    /// The indices are only checked in debug builds, see `try_get_sample`.
     #[inline(always)]
     pub fn get_sample(&self, current_channel: u32, samples_read: u32) -> f32 {
+        debug_assert!(current_channel < self.no_channels, "channel out of range");
+        debug_assert!(samples_read < self.block_size, "sample out of range");
         self.buffer[current_channel as usize * self.block_size as usize + samples_read as usize]
     }
 }
 
+/// The largest bit reservoir a valid stream can require.
+///
+/// `main_data_begin` points at most 511 bytes back, and the main data of a frame
+/// is smaller than the largest layer 3 frame, 1441 bytes at 320 kb/s and 32 kHz
+/// with padding.
+pub const MAX_RESERVOIR_LEN: usize = 511 + 1441;
+
 /// Used for Internal decoding
 ///
-/// Keep bit reservoir
+/// Keep bit reservoir. The main data of a frame may start in earlier frames, so
+/// one state must be used for all frames of a stream, in order. Call `reset`
+/// after seeking or when the frames are not consecutive.
 pub struct DecoderState {
-    frame_buffer: [u8; 2048],
+    /// grows on demand up to `max_len`
+    frame_buffer: Vec<u8>,
     frame_buffer_len: usize,
+    max_len: usize,
+    /// sample rate and channels of the last frame
+    format: Option<(u32, usize)>,
+    /// set by `reset` until a frame finds its main data in the reservoir
+    priming: bool,
+}
+
+impl Default for DecoderState {
+    fn default() -> Self {
+        DecoderState::new()
+    }
 }
 
 impl DecoderState {
     pub fn new() -> Self {
+        DecoderState::with_max_len(MAX_RESERVOIR_LEN)
+    }
+
+    /// Creates a state whose reservoir never grows beyond `max_len` bytes
+    ///
+    /// Frames needing more fail to decode, which for a limit below
+    /// `MAX_RESERVOIR_LEN` may happen for valid streams. Larger limits are capped
+    /// to `MAX_RESERVOIR_LEN`.
+    pub fn with_max_len(max_len: usize) -> Self {
         DecoderState {
-            frame_buffer: [0; 2048],
+            frame_buffer: Vec::new(),
             frame_buffer_len: 0,
+            max_len: max_len.min(MAX_RESERVOIR_LEN),
+            format: None,
+            priming: false,
+        }
+    }
+
+    /// Discards the bit reservoir, e.g. after seeking to another frame
+    ///
+    /// The frames decoded next can not refer back to the main data of the
+    /// frames before the reset. Up to the first frame whose main data is in
+    /// the reservoir again, those referring back further than it reaches are
+    /// decoded as silence instead of failing. These priming frames are the
+    /// only difference to decoding from the start, the reservoir reaches back
+    /// 511 bytes at most, two frames at 128 kb/s. A new state has no priming
+    /// frames, the first frame of a stream can not refer back.
+    pub fn reset(&mut self) {
+        self.frame_buffer_len = 0;
+        self.format = None;
+        self.priming = true;
+    }
+
+    /// Keeps the main data of a priming frame in the reservoir for the frames after it
+    fn keep_main_data<R: ReadBuffer>(
+        &mut self,
+        input: &mut R,
+        main_data_size: usize,
+    ) -> Result<()> {
+        if main_data_size > self.max_len {
+            return errors::parse_error("main_data exceeds the bit reservoir limit");
         }
+        let kept = self.frame_buffer_len.min(self.max_len - main_data_size);
+        self.frame_buffer
+            .copy_within(self.frame_buffer_len - kept..self.frame_buffer_len, 0);
+        let len = kept + main_data_size;
+        if len > self.frame_buffer.len() {
+            self.frame_buffer.resize(len, 0);
+        }
+        input.read_into(&mut self.frame_buffer[kept..len])?;
+        self.frame_buffer_len = len;
+        Ok(())
     }
 
     fn fill_reservoir_buffer<R: ReadBuffer>(
@@ -97,8 +231,11 @@ impl DecoderState {
         main_data_size: usize,
     ) -> Result<&[u8]> {
         let main_data_actual_size = main_data_begin + main_data_size;
-        if main_data_actual_size > 2048 {
-            return errors::parse_error("main_data length greater than reservoir buffer");
+        if main_data_actual_size > self.max_len {
+            return errors::parse_error("main_data exceeds the bit reservoir limit");
+        }
+        if main_data_actual_size > self.frame_buffer.len() {
+            self.frame_buffer.resize(main_data_actual_size, 0);
         }
 
         // shift the actual used data to start of the buffer
@@ -122,6 +259,12 @@ impl DecoderState {
     }
 }
 
+impl ResettableDecoder for DecoderState {
+    fn reset(&mut self) {
+        DecoderState::reset(self);
+    }
+}
+
 fn sync_frame<R: ReadBuffer>(input: &mut R) -> Result<u32> {
     let mut sync = 0u32;
 
@@ -270,7 +413,7 @@ fn read_granule_channel_side_info<R: ReadBuffer>(
             granule_channel_info.table_select[i] = bs.read_len_u8(5)?;
         }
         for i in 0..3 {
-            granule_channel_info.subblock_gain[i] = bs.read_len_u8(3)? as u8;
+            granule_channel_info.subblock_gain[i] = bs.read_len_u8(3)?;
         }
         // region count set in terms of long block cb's/bands
         // r1 set so r0+r1+1 = 21 (lookup produces 576 bands)
@@ -370,14 +513,27 @@ fn read_side_info<R: ReadBuffer>(input: &mut R, frame_header: &FrameHeader) -> R
 /// |
 /// |___Granule1
 /// |   |....
+///
+/// Returns false for a priming frame after `DecoderState::reset`, whose main
+/// data starts before the reservoir. Otherwise `ancillary` is replaced with the
+/// bytes following the audio data.
 fn read_main_data<R: ReadBuffer>(
     input: &mut R,
     decoder_state: &mut DecoderState,
     frame_header: &FrameHeader,
     frame_info: &mut FrameInfo,
-) -> Result<()> {
+    ancillary: &mut Vec<u8>,
+) -> Result<bool> {
     let main_data_size = frame_header.frame_size - frame_header.side_data_len();
 
+    if decoder_state.priming {
+        if frame_info.main_data_begin as usize > decoder_state.frame_buffer_len {
+            decoder_state.keep_main_data(input, main_data_size)?;
+            return Ok(false);
+        }
+        decoder_state.priming = false;
+    }
+
     // fill the decoder state buffer with main_data bytes
     let buffer = decoder_state.fill_reservoir_buffer(
         input,
@@ -401,7 +557,18 @@ fn read_main_data<R: ReadBuffer>(
         }
     }
 
-    Ok(())
+    // the scale factors and Huffman coded bits of all granules are followed by the ancillary data
+    let audio_bits: usize = frame_info.granules[..frame_header.num_granules()]
+        .iter()
+        .flat_map(|granule| &granule.channels[..frame_header.num_channels()])
+        .map(|channel| channel.part2_3_length as usize)
+        .sum();
+    ancillary.clear();
+    if let Some(bytes) = buffer.get(audio_bits.div_ceil(8)..) {
+        ancillary.extend_from_slice(bytes);
+    }
+
+    Ok(true)
 }
 
 fn read_mpeg1_scale_factors(_buffer: &[u8], _channel_info: &mut GranuleChannel) -> Result<()> {
@@ -416,6 +583,212 @@ fn read_mpeg2_scale_factors(
     Ok(())
 }
 
+/// Skips the frames before the one holding the inter-channel sample `frame`
+///
+/// Only the frame headers are parsed, like `scan_frames` does. `input` is left
+/// at the sync word of that frame. Returns the first inter-channel sample of
+/// the frame, or the number of samples of the stream if it ends before.
+pub(crate) fn skip_frames<R: Rewind>(input: &mut R, frame: u64) -> Result<u64> {
+    let mut samples = 0u64;
+    while let Ok(header) = sync_frame(input) {
+        let sync = input.position() - 4;
+        let frame_header = match read_header(input, header) {
+            Ok(frame_header) => frame_header,
+            Err(_) => continue,
+        };
+        let frame_samples = 576 * frame_header.num_granules() as u64;
+        if samples + frame_samples > frame {
+            input.rewind(sync)?;
+            break;
+        }
+        if input.skip_bytes(frame_header.frame_size).is_err() {
+            break;
+        }
+        samples += frame_samples;
+    }
+    Ok(samples)
+}
+
+/// Counts the samples of all frames from the current position to the end of the stream.
+///
+/// Only the frame headers are parsed, the frame data is skipped. Returns the number
+/// of samples per channel and the sample rate of the last frame.
+pub fn scan_frames<R: ReadBuffer>(input: &mut R) -> Result<(u64, u32)> {
+    let mut samples = 0u64;
+    let mut sample_rate = 0;
+
+    while let Ok(header) = sync_frame(input) {
+        let frame_header = match read_header(input, header) {
+            Ok(frame_header) => frame_header,
+            // not a frame header after all, search for the next sync word
+            Err(_) => continue,
+        };
+        // a truncated last frame does not count
+        if input.skip_bytes(frame_header.frame_size).is_err() {
+            break;
+        }
+        samples += 576 * frame_header.num_granules() as u64;
+        sample_rate = frame_header.sample_rate;
+    }
+
+    Ok((samples, sample_rate))
+}
+
+/// Returns true if `bytes` start with a frame header followed by the header of the next frame
+///
+/// A sync word occurs by chance in any binary data, two valid headers exactly
+/// a frame apart hardly do.
+pub(crate) fn starts_with_frames(bytes: &[u8]) -> bool {
+    let next_frame = |offset: usize| -> Option<usize> {
+        let header = bytes.get(offset..offset + 4)?;
+        let header = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        if header & 0xffe0_0000 != 0xffe0_0000 {
+            return None;
+        }
+        let mut input = std::io::Cursor::new(&bytes[offset + 4..]);
+        let frame_header = read_header(&mut input, header).ok()?;
+        Some(offset + 4 + input.position() as usize + frame_header.frame_size)
+    };
+    next_frame(0).and_then(next_frame).is_some()
+}
+
+/// What the frame a stream starts with tells about the stream
+pub(crate) struct FirstFrame {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// number of inter-channel samples of every frame
+    pub frame_samples: u64,
+    /// set if the frame holds a Xing or Info header instead of audio
+    pub info: Option<InfoHeader>,
+}
+
+/// A Xing or Info header, which encoders write into the first frame
+pub(crate) struct InfoHeader {
+    /// number of frames of the stream, the one of the header not counted
+    pub frames: Option<u32>,
+    /// encoder delay and padding in inter-channel samples, from the LAME extension
+    pub gapless: Option<(u32, u32)>,
+}
+
+/// Parses the frame `bytes` start with, `None` if they start with none
+///
+/// Only the header is parsed, and the Xing or Info header of a frame holding
+/// one. A frame cut off by the end of `bytes` has no Info header.
+pub(crate) fn read_first_frame(bytes: &[u8]) -> Option<FirstFrame> {
+    let header = bytes.get(..4)?;
+    let header = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    if header & 0xffe0_0000 != 0xffe0_0000 {
+        return None;
+    }
+    let mut input = std::io::Cursor::new(&bytes[4..]);
+    let frame_header = read_header(&mut input, header).ok()?;
+    let tag = 4 + input.position() as usize + frame_header.side_data_len();
+    Some(FirstFrame {
+        sample_rate: frame_header.sample_rate,
+        channels: frame_header.num_channels(),
+        frame_samples: 576 * frame_header.num_granules() as u64,
+        info: read_info_header(bytes.get(tag..)?),
+    })
+}
+
+/// Parses a Xing or Info header and the LAME extension following it
+fn read_info_header(bytes: &[u8]) -> Option<InfoHeader> {
+    let be_u32 = |offset: usize| -> Option<u32> {
+        let b = bytes.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if !matches!(bytes.get(..4), Some(b"Xing") | Some(b"Info")) {
+        return None;
+    }
+    let flags = be_u32(4)?;
+    let mut offset = 8;
+    let frames = if flags & 1 != 0 {
+        offset += 4;
+        Some(be_u32(offset - 4)?)
+    } else {
+        None
+    };
+    // the byte count, the seek table and the quality indicator
+    for &(flag, len) in &[(2, 4), (4, 100), (8, 4)] {
+        if flags & flag != 0 {
+            offset += len;
+        }
+    }
+    // the encoder version, 12 bytes of other fields, then 12 bits each of delay and padding
+    let lame = bytes.get(offset..offset + 24);
+    let gapless = match lame {
+        Some(lame) if matches!(&lame[..4], b"LAME" | b"Lavf" | b"Lavc") => {
+            let delay = (lame[21] as u32) << 4 | (lame[22] as u32) >> 4;
+            let padding = ((lame[22] & 0x0f) as u32) << 8 | lame[23] as u32;
+            Some((delay, padding))
+        }
+        _ => None,
+    };
+    Some(InfoHeader { frames, gapless })
+}
+
+/// The place of a frame in a stream, for cutting it without decoding
+pub(crate) struct FrameSpan {
+    /// offset of the frame header
+    pub offset: usize,
+    /// length of the whole frame
+    pub len: usize,
+    /// length of the header and the CRC, the side info follows them
+    pub header_len: usize,
+    /// length of the side info
+    pub side_info_len: usize,
+    /// number of bytes of the main data stored in preceding frames
+    pub main_data_begin: usize,
+    /// the side info of MPEG1 has a wider `main_data_begin`
+    pub is_mpeg1: bool,
+    pub has_crc: bool,
+}
+
+impl FrameSpan {
+    /// Returns the number of bytes following the side info, the frame's part of the reservoir
+    pub fn main_data_len(&self) -> usize {
+        self.len - self.header_len - self.side_info_len
+    }
+}
+
+/// Finds all complete frames of `data`
+///
+/// Like `scan_frames` only the headers and the side info are parsed, bytes
+/// which look like a frame sync but are no valid frame header are skipped.
+pub(crate) fn frame_spans(data: &[u8]) -> Vec<FrameSpan> {
+    let mut input = std::io::Cursor::new(data);
+    let mut spans = Vec::new();
+
+    while let Ok(header) = sync_frame(&mut input) {
+        let offset = input.position() as usize - 4;
+        let span = read_header(&mut input, header).and_then(|frame_header| {
+            let frame_info = read_side_info(&mut input, &frame_header)?;
+            let header_len = if frame_header.crc.is_some() { 6 } else { 4 };
+            Ok(FrameSpan {
+                offset,
+                len: header_len + frame_header.frame_size,
+                header_len,
+                side_info_len: frame_header.side_data_len(),
+                main_data_begin: frame_info.main_data_begin as usize,
+                is_mpeg1: frame_header.version == MPEGVersion::MPEG1,
+                has_crc: frame_header.crc.is_some(),
+            })
+        });
+        match span {
+            // a truncated last frame does not count
+            Ok(span) if offset + span.len > data.len() => break,
+            Ok(span) => {
+                input.set_position((offset + span.len) as u64);
+                spans.push(span);
+            }
+            // not a frame header after all, search for the next sync word
+            Err(_) => input.set_position(offset as u64 + 1),
+        }
+    }
+
+    spans
+}
+
 /// takes input stream and returns a block of pcm samples
 ///
 /// -----------------    ----------------     --------------------
@@ -429,6 +802,12 @@ fn read_mpeg2_scale_factors(
 /// ---------------------------------------------------------
 ///
 /// Each frame contains 1152 pcm encoded samples.
+///
+/// `input` is scanned forward to the next sync word, so it should be positioned
+/// at a frame header, e.g. after an ID3v2 tag. The same `decoder_state` must be
+/// passed for consecutive frames, reset it after seeking. Frames referring back
+/// before a reset are decoded as silence, see `DecoderState::reset`. Returns
+/// `None` at the end of the stream.
 pub fn decode_next_frame<R: ReadBuffer>(
     input: &mut R,
     decoder_state: &mut DecoderState,
@@ -442,21 +821,211 @@ pub fn decode_next_frame<R: ReadBuffer>(
     let frame_header = otry!(read_header(input, header));
     let mut frame_info = otry!(read_side_info(input, &frame_header));
 
-    // allocate block buffer if empty
-    if block_buffer.is_empty() {
-        block_buffer = vec![0.0; 576 * frame_header.num_granules() * frame_header.num_channels()];
+    // A different format starts a different stream, e.g. of files glued
+    // together, whose frames can not refer to the reservoir of the previous one.
+    let format = (frame_header.sample_rate, frame_header.num_channels());
+    if decoder_state.format != Some(format) {
+        decoder_state.format = Some(format);
+        decoder_state.frame_buffer_len = 0;
     }
 
-    otry!(read_main_data(
+    block_buffer.resize(
+        576 * frame_header.num_granules() * frame_header.num_channels(),
+        0.0,
+    );
+
+    let mut ancillary = Vec::new();
+    let has_main_data = otry!(read_main_data(
         input,
         decoder_state,
         &frame_header,
-        &mut frame_info
+        &mut frame_info,
+        &mut ancillary
     ));
+    if !has_main_data {
+        block_buffer.iter_mut().for_each(|s| *s = 0.0);
+    }
 
-    Some(Ok(Block::new(
+    let mut block = Block::new(
         576 * frame_header.num_granules() as u32,
-        32,
+        frame_header.bitrate,
+        frame_header.sample_rate,
         block_buffer,
-    )))
+    );
+    block.ancillary = ancillary;
+    Some(Ok(block))
+}
+
+#[test]
+fn test_reservoir_shifts_across_frames() {
+    let mut state = DecoderState::new();
+
+    // the first frame can not refer back
+    let mut input: &[u8] = &[1, 2, 3, 4, 5, 6];
+    assert_eq!(
+        state.fill_reservoir_buffer(&mut input, 0, 6).unwrap(),
+        &[1, 2, 3, 4, 5, 6]
+    );
+
+    // the following ones start with the tail of the reservoir
+    let mut input: &[u8] = &[7, 8, 9];
+    assert_eq!(
+        state.fill_reservoir_buffer(&mut input, 2, 3).unwrap(),
+        &[5, 6, 7, 8, 9]
+    );
+    let mut input: &[u8] = &[10];
+    assert_eq!(
+        state.fill_reservoir_buffer(&mut input, 5, 1).unwrap(),
+        &[5, 6, 7, 8, 9, 10]
+    );
+    let mut input: &[u8] = &[11, 12];
+    assert_eq!(
+        state.fill_reservoir_buffer(&mut input, 0, 2).unwrap(),
+        &[11, 12]
+    );
+
+    // a frame can not refer back further than the reservoir
+    let mut input: &[u8] = &[13];
+    assert!(state.fill_reservoir_buffer(&mut input, 3, 1).is_err());
+}
+
+#[test]
+fn test_reservoir_limit() {
+    let mut state = DecoderState::with_max_len(8);
+    let mut input: &[u8] = &[0; 16];
+    assert!(state.fill_reservoir_buffer(&mut input, 0, 8).is_ok());
+    match state.fill_reservoir_buffer(&mut input, 4, 5) {
+        Err(crate::errors::Error::ParseError(msg)) => assert!(msg.contains("limit")),
+        _ => panic!("expected the reservoir limit to be hit"),
+    }
+
+    // the limit is capped by the spec
+    let mut state = DecoderState::with_max_len(usize::MAX);
+    let mut input: &[u8] = &[0; MAX_RESERVOIR_LEN + 1];
+    assert!(state
+        .fill_reservoir_buffer(&mut input, 0, MAX_RESERVOIR_LEN)
+        .is_ok());
+    let mut input: &[u8] = &[0; MAX_RESERVOIR_LEN + 1];
+    assert!(state
+        .fill_reservoir_buffer(&mut input, 0, MAX_RESERVOIR_LEN + 1)
+        .is_err());
+}
+
+#[test]
+fn test_block_accessors() {
+    let block = Block::new(2, 128_000, 44_100, vec![0.5, 0.25, -0.5, -0.25]);
+    assert_eq!(block.channel(1), &[-0.5, -0.25]);
+    assert_eq!(block.try_get_sample(0, 1), Some(0.25));
+    assert_eq!(block.try_get_sample(0, 2), None);
+    assert_eq!(block.try_get_sample(2, 0), None);
+}
+
+#[test]
+fn test_reset_decodes_priming_frames_as_silence() {
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz mono frames of 417 bytes, each but the
+    // first starting its main data 100 bytes back in the previous one
+    let mut data = Vec::new();
+    for i in 0..4 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.push(if i == 0 { 0 } else { 100 >> 1 });
+        data.extend_from_slice(&[0; 412]);
+    }
+    let frame = |i: usize| &data[417 * i..];
+
+    // frames from the middle of the stream need those before them
+    let mut state = DecoderState::new();
+    let mut input = frame(2);
+    assert!(decode_next_frame(&mut input, &mut state, Vec::new())
+        .unwrap()
+        .is_err());
+
+    // after a reset the frame is silent instead, the one after it decodes
+    let mut state = DecoderState::new();
+    let mut input = frame(0);
+    let fresh: Vec<Block> = (0..4)
+        .map(|_| {
+            decode_next_frame(&mut input, &mut state, vec![0.5; 1152])
+                .unwrap()
+                .unwrap()
+        })
+        .collect();
+    state.reset();
+    let mut input = frame(2);
+    let priming = decode_next_frame(&mut input, &mut state, vec![0.5; 1152])
+        .unwrap()
+        .unwrap();
+    assert_eq!(priming.total_samples(), fresh[2].total_samples());
+    assert!(priming.channel(0).iter().all(|&s| s == 0.0));
+    let block = decode_next_frame(&mut input, &mut state, vec![0.5; 1152])
+        .unwrap()
+        .unwrap();
+    assert_eq!(block.channel(0), fresh[3].channel(0));
+    assert!(!state.priming);
+}
+
+#[test]
+fn test_ancillary_data() {
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz mono frames of 417 bytes, with 396
+    // bytes of main data after the 17 bytes of side info
+    let frame = |main_data_begin: u16, part2_3_lengths: [u16; 2], tail: &[u8]| {
+        let mut side_info = crate::testing::BitWriter::new();
+        side_info.write(main_data_begin as u64, 9);
+        // the private bits and scfsi
+        side_info.write(0, 5 + 4);
+        for &len in &part2_3_lengths {
+            side_info.write(len as u64, 12);
+            side_info.write(0, 59 - 12);
+        }
+        let mut data = vec![0xff, 0xfb, 0x90, 0xc0];
+        data.extend_from_slice(&side_info.into_bytes());
+        data.resize(417 - tail.len(), 0x55);
+        data.extend_from_slice(tail);
+        data
+    };
+    let mut data = frame(0, [1_000, 2_000], b"ancillary text of 21b");
+    data.extend_from_slice(&frame(0, [1_000, 1_168], &[]));
+    // this one starts its main data 25 bytes back, in the previous frame
+    data.extend_from_slice(&frame(25, [421 * 8, 0], &[]));
+
+    let mut state = DecoderState::new();
+    let mut input = &data[..];
+    let mut next = || {
+        decode_next_frame(&mut input, &mut state, Vec::new())
+            .unwrap()
+            .unwrap()
+    };
+    // 3000 bits of audio data leave 396 - 375 bytes
+    assert_eq!(next().ancillary(), b"ancillary text of 21b");
+    assert_eq!(next().ancillary(), &[0x55; 396 - 271][..]);
+    assert!(next().ancillary().is_empty());
+}
+
+#[test]
+fn test_read_first_frame() {
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz mono, a Xing header with all fields
+    let mut data = vec![0xff, 0xfb, 0x90, 0xc0];
+    data.extend_from_slice(&[0; 17]);
+    data.extend_from_slice(b"Xing\x00\x00\x00\x0f\x00\x00\x00\x07");
+    data.extend_from_slice(&[0; 108]);
+    data.extend_from_slice(b"LAME3.100");
+    data.extend_from_slice(&[0; 12]);
+    // a delay of 576 and padding of 1000
+    data.extend_from_slice(&[0x24, 0x03, 0xe8]);
+
+    let first = read_first_frame(&data).unwrap();
+    assert_eq!((first.sample_rate, first.channels), (44_100, 1));
+    assert_eq!(first.frame_samples, 1152);
+    let info = first.info.unwrap();
+    assert_eq!(info.frames, Some(7));
+    assert_eq!(info.gapless, Some((576, 1000)));
+
+    // without the LAME extension the delay is unknown
+    data.truncate(4 + 17 + 12 + 108);
+    let info = read_first_frame(&data).unwrap().info.unwrap();
+    assert_eq!((info.frames, info.gapless), (Some(7), None));
+
+    // a frame of audio
+    data.truncate(4 + 17);
+    assert!(read_first_frame(&data).unwrap().info.is_none());
+    assert!(read_first_frame(&data[1..]).is_none());
 }
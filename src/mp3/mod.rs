@@ -1,65 +1,435 @@
-mod frame;
+pub(crate) mod frame;
+pub(crate) mod split;
 mod types;
 
-use super::io::{AudioInputStream, AudioReader, AudioSamplesIterator, Sample};
-use super::{audio, codecs, Result};
+use std::io;
+use std::time::Instant;
+
+use super::errors::{Error, StreamParameters};
+use super::format::{DecodeContext, Format, SampleType, TypedSamples};
+use super::io::{
+    AudioInputStream, AudioReader, AudioSamplesIterator, ReadBuffer, ResettableDecoder, Sample,
+};
+use super::pictures::{PictureContainer, PictureSource};
+use super::stats::{DecodeStats, InstrumentationHandle, VerifyReport};
+use super::{audio, codecs, errors, Result};
+
+/// bytes of the first frame peeked at by `read_header`, which covers a Xing
+/// header with all its fields and the LAME extension after it
+const FIRST_FRAME_LEN: usize = 192;
+
+/// inter-channel samples the synthesis filterbank delays the audio by
+const DECODER_DELAY: u32 = 529;
+
+/// The MP3 format, a stream of MPEG audio frames
+pub struct Mp3Format;
+
+impl Format for Mp3Format {
+    fn flag(&self) -> codecs::FormatFlag {
+        codecs::FormatFlag::MP3
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["mp3"]
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        has_magic(header)
+    }
+
+    fn matches_embedded(&self, bytes: &[u8]) -> bool {
+        is_id3v2_header(bytes) || frame::starts_with_frames(bytes)
+    }
+
+    fn create_reader(
+        &self,
+        input: AudioInputStream,
+        _options: audio::DecodeOptions,
+    ) -> Result<Box<dyn AudioReader>> {
+        Ok(Mp3Reader::new(input)?)
+    }
+
+    fn samples<'a>(
+        &self,
+        context: DecodeContext<'a>,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>> {
+        Ok(
+            typed_samples!(sample_type, S => Mp3SamplesIterator::<S>::new(
+                context.reader,
+                context.info.clone(),
+                context.decode_stats,
+                context.stream_changes,
+                context.instrumentation
+            )),
+        )
+    }
+}
 
 pub struct Mp3Reader {
     reader: AudioInputStream,
+    /// set once `read_header` was called
+    header_read: bool,
+    /// the ID3v2 tag the stream starts with
+    id3_tag: Option<PictureSource>,
+    /// offset of the stream, before its ID3v2 tag
+    start: u64,
 }
 
 impl Mp3Reader {
     pub fn new(reader: AudioInputStream) -> Result<Box<Self>> {
-        Ok(Box::new(Mp3Reader { reader }))
+        Ok(Box::new(Mp3Reader {
+            reader,
+            header_read: false,
+            id3_tag: None,
+            start: 0,
+        }))
     }
 }
 
 impl AudioReader for Mp3Reader {
     fn read_header(&mut self) -> Result<audio::AudioInfo> {
-        Ok(audio::AudioInfo {
+        if self.header_read {
+            return errors::unsupported_error("header has already been read");
+        }
+        self.header_read = true;
+
+        let offset = self.reader.position();
+        self.start = offset;
+        let header = self.reader.peek(10)?;
+        if !has_magic(header) {
+            return errors::missing_magic_error("no ID3 tag or frame sync found");
+        }
+        let tag_len = id3v2_tag_len(header);
+        if is_id3v2_header(header) {
+            self.id3_tag = Some(PictureSource {
+                container: PictureContainer::Id3v2,
+                offset,
+                len: tag_len as u64,
+                payload: None,
+            });
+        }
+
+        let mut info = audio::AudioInfo {
             codec_type: codecs::CodecType::CODEC_TYPE_MP3,
             sample_rate: 0,
             total_samples: 0,
             bits_per_sample: 0,
+            container_bits: 0,
+            sample_format: codecs::SampleFormat::F32,
             channels: audio::ChannelLayout::Mono.into_channels(),
             channel_layout: audio::ChannelLayout::Mono,
-        })
+            flac_stream_info: None,
+            encoder_delay: None,
+            encoder_padding: None,
+        };
+
+        // Only a Xing or Info header tells about the stream, the parameters of
+        // other frames are taken from the first decoded one. Tags too long to
+        // be peeked at leave it unknown.
+        let end = tag_len + FIRST_FRAME_LEN;
+        if end <= AudioInputStream::MAX_CAPACITY {
+            let bytes = self.reader.peek(end)?;
+            let first = bytes.get(tag_len..).and_then(frame::read_first_frame);
+            if let Some(frame::FirstFrame {
+                sample_rate,
+                channels,
+                frame_samples,
+                info: Some(header),
+            }) = first
+            {
+                let layout = if channels == 1 {
+                    audio::ChannelLayout::Mono
+                } else {
+                    audio::ChannelLayout::Stereo
+                };
+                info.sample_rate = sample_rate;
+                info.channels = layout.into_channels();
+                info.channel_layout = layout;
+                // the frame of the header decodes to silence, after it come `frames`
+                if let Some(frames) = header.frames {
+                    info.total_samples = (frames as u64 + 1) * frame_samples * channels as u64;
+                }
+                // LAME counts neither the frame of the header nor the delay of the decoder
+                if let Some((delay, padding)) = header.gapless {
+                    info.encoder_delay = Some(frame_samples as u32 + delay + DECODER_DELAY);
+                    info.encoder_padding = Some(padding.saturating_sub(DECODER_DELAY));
+                }
+            }
+        }
+
+        Ok(info)
     }
 
     fn buffer(&mut self) -> &mut AudioInputStream {
         &mut self.reader
     }
+
+    fn into_buffer(self: Box<Self>) -> AudioInputStream {
+        self.reader
+    }
+
+    fn scan_length(&mut self, _info: &audio::AudioInfo) -> Result<audio::ScanResult> {
+        skip_id3v2_tag(&mut self.reader)?;
+        let (samples, sample_rate) = frame::scan_frames(&mut self.reader)?;
+        Ok(audio::ScanResult::new(samples, sample_rate))
+    }
+
+    /// Seeks to the start of a frame, any other sample needs `seek_block`
+    fn seek_frame(&mut self, frame: u64, info: &audio::AudioInfo) -> Result<()> {
+        if self.seek_block(frame, info)? != frame {
+            return errors::unsupported_error("MP3 can only seek to the start of a frame");
+        }
+        Ok(())
+    }
+
+    /// Seeks to the frame holding `frame`, found by reading the frame headers from the start
+    fn seek_block(&mut self, frame: u64, _info: &audio::AudioInfo) -> Result<u64> {
+        self.reader.seek(io::SeekFrom::Start(self.start))?;
+        skip_id3v2_tag(&mut self.reader)?;
+        frame::skip_frames(&mut self.reader, frame)
+    }
+
+    fn verify(&mut self, _info: &audio::AudioInfo) -> Result<VerifyReport> {
+        skip_id3v2_tag(&mut self.reader)?;
+        verify_frames(&mut self.reader)
+    }
+
+    fn picture_sources(&mut self) -> Result<Vec<PictureSource>> {
+        Ok(self.id3_tag.iter().cloned().collect())
+    }
+}
+
+/// Decodes all frames from the current position for `AudioReader::verify`
+///
+/// The first frame sets the sample rate and channels, later frames changing
+/// them are counted as inconsistent. A malformed frame is counted and the
+/// decoding goes on at the next sync word.
+pub(crate) fn verify_frames<R: ReadBuffer>(input: &mut R) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut decoder_state = frame::DecoderState::new();
+    let mut buffer = Vec::new();
+    let mut parameters = None;
+    loop {
+        match frame::decode_next_frame(input, &mut decoder_state, std::mem::take(&mut buffer)) {
+            Some(Ok(block)) => {
+                report.frames += 1;
+                report.samples += block.total_samples() as u64;
+                let found = (block.sample_rate(), block.num_channels());
+                if *parameters.get_or_insert(found) != found {
+                    report.inconsistent_frames += 1;
+                }
+                buffer = block.into_buffer();
+            }
+            Some(Err(Error::ParseError(_))) => report.malformed_frames += 1,
+            Some(Err(Error::IoError(ref error)))
+                if error.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                report.truncated = true;
+                break;
+            }
+            Some(Err(error)) => return Err(error),
+            None => break,
+        }
+    }
+    Ok(report)
+}
+
+/// Skips an ID3v2 tag at the current position, its data could contain false frame syncs.
+/// https://id3.org/id3v2.4.0-structure
+/// Returns true if `bytes` start with an ID3v2 tag or right with the sync word of a frame
+fn has_magic(bytes: &[u8]) -> bool {
+    let is_id3 = bytes.starts_with(b"ID3");
+    let is_sync = bytes.len() >= 2 && bytes[0] == 0xff && bytes[1] & 0xe0 == 0xe0;
+    is_id3 || is_sync
+}
+
+/// Returns true if `bytes` start with an ID3v2 tag header with valid version and size fields
+fn is_id3v2_header(bytes: &[u8]) -> bool {
+    bytes.len() >= 10
+        && bytes.starts_with(b"ID3")
+        && (2..=4).contains(&bytes[3])
+        && bytes[4] != 0xff
+        && bytes[6..10].iter().all(|b| b & 0x80 == 0)
+}
+
+fn skip_id3v2_tag(reader: &mut AudioInputStream) -> Result<()> {
+    let len = id3v2_tag_len(reader.peek(10)?);
+    reader.skip_bytes(len)?;
+    Ok(())
+}
+
+/// Returns the length of the ID3v2 tag `bytes` start with, 0 if they have none
+fn id3v2_tag_len(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[..3] != b"ID3" {
+        return 0;
+    }
+    // the size is a 28 bit synchsafe integer, excluding the header and footer
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |size, b| size << 7 | (b & 0x7f) as usize);
+    let has_footer = bytes[5] & 0x10 != 0;
+    10 + size + if has_footer { 10 } else { 0 }
 }
 
 pub struct Mp3SamplesIterator<'r, S: Sample + 'r> {
     reader: &'r mut Box<dyn AudioReader + 'static>,
-    _audio_info: &'r audio::AudioInfo,
+    /// the parameters of the header
+    audio_info: audio::AudioInfo,
+    /// parameters of the stream decoded now, if they differ from `audio_info`
+    changed_info: Option<audio::AudioInfo>,
+    /// a change not yet taken by `take_stream_change`
+    stream_change: Option<audio::AudioInfo>,
+    /// true if changes are returned, otherwise the first one is an error
+    stream_changes: bool,
+    /// number of frames decoded so far
+    frames: u64,
     phantom: std::marker::PhantomData<S>,
     current_block: frame::Block,
     decoder_state: frame::DecoderState,
     samples_read: u32,
     current_channel: u32,
     has_failed: bool,
+    decode_stats: Option<&'r mut DecodeStats>,
+    /// counts the decoded frames and the time spent decoding them if set
+    instrumentation: Option<InstrumentationHandle>,
 }
 
 impl<'r, S: Sample + 'r> Mp3SamplesIterator<'r, S> {
     pub fn new(
         reader: &'r mut Box<dyn AudioReader + 'static>,
-        info: &'r audio::AudioInfo,
+        info: audio::AudioInfo,
+        decode_stats: Option<&'r mut DecodeStats>,
+        stream_changes: bool,
+        instrumentation: Option<InstrumentationHandle>,
     ) -> Box<Self> {
         Box::new(Mp3SamplesIterator::<S> {
             reader,
-            _audio_info: info,
+            audio_info: info,
+            changed_info: None,
+            stream_change: None,
+            stream_changes,
+            frames: 0,
             phantom: std::marker::PhantomData,
             current_block: frame::Block::empty(),
             decoder_state: frame::DecoderState::new(),
             samples_read: 0,
             current_channel: 0,
             has_failed: false,
+            decode_stats,
+            instrumentation,
         })
     }
 }
 
+impl<'r, S: Sample + 'r> Mp3SamplesIterator<'r, S> {
+    /// Takes the sample rate and channels of `block` as the parameters of the stream
+    ///
+    /// The header of a raw MP3 stream states neither, so the first frame sets
+    /// them without signalling a change. A later frame changing them is an
+    /// error, unless the changes are returned.
+    fn update_stream(&mut self, block: &frame::Block) -> Result<()> {
+        let info = self.changed_info.as_ref().unwrap_or(&self.audio_info);
+        let is_known = info.sample_rate != 0;
+        let expected = StreamParameters {
+            sample_rate: info.sample_rate,
+            channels: info.channels.count() as u32,
+            bits_per_sample: 0,
+        };
+        let found = StreamParameters {
+            sample_rate: block.sample_rate(),
+            channels: block.num_channels(),
+            bits_per_sample: 0,
+        };
+        if expected == found {
+            return Ok(());
+        }
+        if is_known && !self.stream_changes {
+            return Err(Error::InconsistentFrame {
+                frame: self.frames - 1,
+                expected,
+                found,
+            });
+        }
+        let channel_layout = if block.num_channels() == 1 {
+            audio::ChannelLayout::Mono
+        } else {
+            audio::ChannelLayout::Stereo
+        };
+        let info = audio::AudioInfo {
+            sample_rate: block.sample_rate(),
+            total_samples: 0,
+            channels: channel_layout.into_channels(),
+            channel_layout,
+            ..info.clone()
+        };
+        if is_known {
+            self.stream_change = Some(info.clone());
+        }
+        self.changed_info = Some(info);
+        Ok(())
+    }
+}
+
+impl<'r, S: Sample + 'r> Mp3SamplesIterator<'r, S> {
+    /// Decodes the next frame into `current_block`, `None` at the end of the stream
+    fn decode_block(&mut self) -> Option<Result<()>> {
+        let current_block = std::mem::replace(&mut self.current_block, frame::Block::empty());
+        let buffer = current_block.into_buffer();
+        let started = match self.instrumentation {
+            Some(_) => Some((Instant::now(), self.reader.buffer().position())),
+            None => None,
+        };
+        let decoded = match self.reader.data_remaining() {
+            Some(remaining) => frame::decode_next_frame(
+                &mut io::Read::take(self.reader.buffer(), remaining),
+                &mut self.decoder_state,
+                buffer,
+            ),
+            None => frame::decode_next_frame::<AudioInputStream>(
+                self.reader.buffer(),
+                &mut self.decoder_state,
+                buffer,
+            ),
+        };
+        match decoded {
+            Some(Ok(next_block)) => {
+                if let (Some(handle), Some((start, offset))) = (&self.instrumentation, started) {
+                    let bytes = self.reader.buffer().position() - offset;
+                    let frames = next_block.total_samples() as u64;
+                    handle.record(frames, bytes, start.elapsed());
+                    handle.set_sample_rate(next_block.sample_rate());
+                }
+                if let Some(ref mut stats) = self.decode_stats {
+                    stats.add_mp3_frame(next_block.total_samples(), next_block.bitrate());
+                }
+                self.frames += 1;
+                if let Err(error) = self.update_stream(&next_block) {
+                    self.has_failed = true;
+                    return Some(Err(error));
+                }
+                self.current_block = next_block;
+                Some(Ok(()))
+            }
+            Some(Err(error)) => {
+                self.has_failed = true;
+                Some(Err(error))
+            }
+            None => None,
+        }
+    }
+}
+
+impl<'r, S: Sample + 'r> ResettableDecoder for Mp3SamplesIterator<'r, S> {
+    /// Resets the bit reservoir and drops the frame decoded last
+    fn reset(&mut self) {
+        self.decoder_state.reset();
+        self.current_block = frame::Block::empty();
+        self.samples_read = 0;
+        self.current_channel = 0;
+    }
+}
+
 impl<'r, S: Sample> AudioSamplesIterator<S> for Mp3SamplesIterator<'r, S> {
     fn next(&mut self) -> Option<Result<S>> {
         if self.has_failed {
@@ -75,26 +445,7 @@ impl<'r, S: Sample> AudioSamplesIterator<S> for Mp3SamplesIterator<'r, S> {
             // we read last sample, decode next block
             if self.samples_read >= self.current_block.total_samples() {
                 self.samples_read = 0;
-
-                let current_block =
-                    std::mem::replace(&mut self.current_block, frame::Block::empty());
-
-                match frame::decode_next_frame::<AudioInputStream>(
-                    self.reader.buffer(),
-                    &mut self.decoder_state,
-                    current_block.into_buffer(),
-                ) {
-                    Some(Ok(next_block)) => {
-                        self.current_block = next_block;
-                    }
-                    Some(Err(error)) => {
-                        self.has_failed = true;
-                        return Some(Err(error));
-                    }
-                    _ => {
-                        return None;
-                    }
-                }
+                otry!(self.decode_block()?);
             }
         }
 
@@ -104,4 +455,273 @@ impl<'r, S: Sample> AudioSamplesIterator<S> for Mp3SamplesIterator<'r, S> {
                 .get_sample(self.current_channel, self.samples_read),
         ))
     }
+
+    /// Decodes from the frame holding `frame`, found by reading the frame headers from the start
+    ///
+    /// The bit reservoir is reset, so up to two frames after the seek may
+    /// decode as silence, see `raw::mp3::DecoderState::reset`.
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        // the frames of MP3 in WAV are not found by the WAV reader
+        if self.reader.data_remaining().is_some() {
+            return errors::unsupported_error("seeking is not supported for MP3 in WAV");
+        }
+        let start = self.reader.seek_block(frame, &self.audio_info)?;
+        self.reset();
+        self.has_failed = false;
+        self.frames = 0;
+
+        // decode up to `frame`, as if the samples before it were read
+        let mut skip = frame - start;
+        while skip > 0 {
+            match self.decode_block() {
+                Some(result) => result?,
+                None => break,
+            }
+            let n = skip.min(self.current_block.total_samples() as u64);
+            self.samples_read = n as u32 - 1;
+            self.current_channel = self.current_block.num_channels() - 1;
+            skip -= n;
+        }
+        Ok(())
+    }
+
+    fn take_stream_change(&mut self) -> Option<audio::AudioInfo> {
+        self.stream_change.take()
+    }
+}
+
+#[test]
+fn test_read_header_twice() {
+    let input = crate::io::seekable_stream(std::io::Cursor::new(vec![0xff, 0xfb])).unwrap();
+    let mut reader = Mp3Reader::new(input).unwrap();
+
+    assert!(reader.read_header().is_ok());
+    match reader.read_header() {
+        Err(crate::errors::Error::Unsupported(_)) => {}
+        _ => panic!("expected the second call to fail"),
+    }
+}
+
+#[test]
+fn test_scan_length() {
+    // an ID3v2 tag holding something that looks like a frame sync
+    let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x04".to_vec();
+    data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz, mono, frames of 417 bytes
+    for _ in 0..3 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.extend_from_slice(&[0; 413]);
+    }
+    // a truncated frame at the end
+    data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0, 0, 0]);
+
+    let mut segment = audio::AudioSegment::from_seekable_reader(
+        std::io::Cursor::new(data),
+        codecs::FormatFlag::MP3,
+    )
+    .unwrap();
+    let scan = segment.scan_length().unwrap();
+    assert_eq!(scan.frames, 3 * 1152);
+    assert_eq!(scan.duration, std::time::Duration::from_nanos(78_367_346));
+    assert_eq!(scan.sample_rate, 44_100);
+}
+
+#[test]
+fn test_decode_stats() {
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz, mono, frames of 417 bytes without main data
+    let mut data = Vec::new();
+    for _ in 0..3 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.extend_from_slice(&[0; 413]);
+    }
+
+    let options = audio::DecodeOptions {
+        decode_stats: true,
+        ..Default::default()
+    };
+    let mut segment = audio::AudioSegment::from_reader_with_options(
+        std::io::Cursor::new(data),
+        codecs::FormatFlag::MP3,
+        options,
+    )
+    .unwrap();
+    assert_eq!(segment.samples::<f32>().unwrap().count(), 3 * 1152);
+
+    let stats = segment.decode_stats();
+    assert_eq!(stats.frames, 3);
+    assert_eq!(stats.block_sizes.get(&1152), Some(&3));
+    assert_eq!(stats.bitrates.get(&128_000), Some(&3));
+    assert_eq!(stats.average_partition_order(), None);
+    assert!(format!("{}", stats).contains("128000: 3"));
+}
+
+#[test]
+fn test_stream_change() {
+    use crate::audio::Event;
+
+    // MPEG1 layer 3, 128 kb/s, mono frames without main data, two at 44.1kHz
+    // of 417 bytes, then two at 48kHz of 384 bytes
+    let mut data = Vec::new();
+    for _ in 0..2 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.extend_from_slice(&[0; 413]);
+    }
+    for _ in 0..2 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x94, 0xc0]);
+        data.extend_from_slice(&[0; 380]);
+    }
+
+    let mut segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::MP3)
+            .unwrap();
+    let events: Vec<Event<f32>> = segment.events().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(events.len(), 4 * 1152 + 1);
+    // the first frame sets the parameters a raw stream has no header for
+    match &events[2 * 1152] {
+        Event::StreamChanged(info) => assert_eq!(info.sample_rate, 48_000),
+        event => panic!("expected a stream change, got {:?}", event),
+    }
+}
+
+#[test]
+fn test_inconsistent_frame() {
+    use crate::errors::Error;
+
+    // two MP3 files glued together, two mono frames at 44.1kHz, then two at 48kHz
+    let mut data = Vec::new();
+    for _ in 0..2 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.extend_from_slice(&[0; 413]);
+    }
+    for _ in 0..2 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x94, 0xc0]);
+        data.extend_from_slice(&[0; 380]);
+    }
+
+    let mut segment =
+        audio::AudioSegment::from_reader(std::io::Cursor::new(data), codecs::FormatFlag::MP3)
+            .unwrap();
+    let mut samples = segment.samples::<f32>().unwrap();
+    for _ in 0..2 * 1152 {
+        assert!(samples.next().unwrap().is_ok());
+    }
+    match samples.next() {
+        Some(Err(Error::InconsistentFrame {
+            frame,
+            expected,
+            found,
+        })) => {
+            assert_eq!(frame, 2);
+            assert_eq!(
+                expected,
+                StreamParameters {
+                    sample_rate: 44_100,
+                    channels: 1,
+                    bits_per_sample: 0,
+                }
+            );
+            assert_eq!(
+                found,
+                StreamParameters {
+                    sample_rate: 48_000,
+                    channels: 1,
+                    bits_per_sample: 0,
+                }
+            );
+        }
+        other => panic!("expected an inconsistent frame, got {:?}", other.is_some()),
+    }
+    assert!(samples.next().is_none());
+}
+
+#[test]
+fn test_verify() {
+    // two mono frames at 44.1kHz, then one at 48kHz, and a truncated one
+    let mut data = Vec::new();
+    for _ in 0..2 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.extend_from_slice(&[0; 413]);
+    }
+    data.extend_from_slice(&[0xff, 0xfb, 0x94, 0xc0]);
+    data.extend_from_slice(&[0; 380]);
+    let full = data.len();
+    data.extend_from_slice(&[0xff, 0xfb, 0x94, 0xc0]);
+    data.extend_from_slice(&[0; 100]);
+
+    let verify = |data: &[u8]| {
+        let input = std::io::Cursor::new(data.to_vec());
+        let mut segment = audio::AudioSegment::from_reader(input, codecs::FormatFlag::MP3)?;
+        segment.verify()
+    };
+    let report = verify(&data[..full]).unwrap();
+    assert_eq!(report.frames, 3);
+    assert_eq!(report.samples, 3 * 1152);
+    assert_eq!(report.inconsistent_frames, 1);
+    assert_eq!(report.md5_matched, None);
+    assert!(!report.is_ok());
+
+    let report = verify(&data).unwrap();
+    assert_eq!(report.frames, 3);
+    assert!(report.truncated);
+}
+
+#[test]
+fn test_empty_and_header_only_files() {
+    let read = |file: Vec<u8>, seekable| {
+        let flag = crate::codecs::FormatFlag::MP3;
+        if seekable {
+            crate::audio::AudioSegment::from_seekable_reader(std::io::Cursor::new(file), flag)
+        } else {
+            crate::audio::AudioSegment::from_reader(std::io::Cursor::new(file), flag)
+        }
+    };
+    for &seekable in &[false, true] {
+        for file in &[Vec::new(), b"not an mp3 file at all".to_vec()] {
+            assert!(matches!(
+                read(file.clone(), seekable),
+                Err(crate::errors::Error::MissingMagic(_))
+            ));
+        }
+
+        // an empty ID3v2 tag without any frames after it
+        let header = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+        let mut segment = read(header, seekable).unwrap();
+        assert_eq!(segment.info().total_samples, 0);
+        assert!(segment.samples::<i16>().unwrap().next().is_none());
+    }
+}
+
+#[test]
+fn test_seek_frame() {
+    // 128 kb/s mono frames of 417 bytes after an ID3v2 tag, which but the
+    // first refer back 100 bytes into the bit reservoir
+    let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x04".to_vec();
+    data.extend_from_slice(&[0; 4]);
+    for i in 0..10 {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.push(if i == 0 { 0 } else { 100 >> 1 });
+        data.extend_from_slice(&[0; 412]);
+    }
+    let open = || {
+        audio::AudioSegment::from_seekable_reader(
+            std::io::Cursor::new(data.clone()),
+            codecs::FormatFlag::MP3,
+        )
+        .unwrap()
+    };
+    let all: Vec<f32> = open()
+        .samples::<f32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(all.len(), 10 * 1152);
+
+    // decoding on after a seek matches a fresh reader, the priming frame is silent
+    let mut segment = open();
+    let mut samples = segment.samples::<f32>().unwrap();
+    for &frame in &[5 * 1152 + 100, 1152, 0, 9 * 1152 + 1151, 3 * 1152] {
+        samples.seek_frame(frame).unwrap();
+        let rest: Vec<f32> = samples.by_ref().map(|s| s.unwrap()).collect();
+        assert_eq!(rest, &all[frame as usize..], "seeking to {}", frame);
+    }
 }
@@ -0,0 +1,209 @@
+//! Cutting MP3 streams on frame boundaries, without decoding and reencoding them.
+
+use std::io;
+use std::ops::Range;
+
+use super::frame::{self, FrameSpan};
+use crate::{crc, errors, Result};
+
+/// Cuts the MP3 stream of `input` into one piece per range of frames
+///
+/// The ranges count the audio frames from 0, i.e. without a Xing or Info
+/// frame, and are cut off at the end of the stream. Each piece is a stream
+/// of its own: the ID3v2 tag and the Xing or Info frame are left out, the
+/// latter since its frame count and seek table would be wrong.
+///
+/// The main data of a frame may start in the preceding frames, the bit
+/// reservoir. Hence a piece starting within the stream begins with the
+/// frames holding the reservoir of its first frame. The reservoir offset of
+/// the first of them is cleared, so that it decodes, but to wrong samples.
+/// Players mix these priming frames, usually one or two, into the end of the
+/// previous piece, and tools cutting sample exactly have to drop them.
+///
+/// Fails with `Error::Unsupported` for a range ending before it starts.
+pub fn split<R: io::Read>(mut input: R, ranges: &[Range<u64>]) -> Result<Vec<Vec<u8>>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let tag_len = super::id3v2_tag_len(&data).min(data.len());
+    let data = &data[tag_len..];
+    let mut spans = frame::frame_spans(data);
+    if matches!(spans.first(), Some(span) if is_info_frame(data, span)) {
+        spans.remove(0);
+    }
+
+    ranges
+        .iter()
+        .map(|range| {
+            if range.end < range.start {
+                return errors::unsupported_error("a split range ends before it starts");
+            }
+            let end = range.end.min(spans.len() as u64) as usize;
+            let start = (range.start as usize).min(end);
+            if start == end {
+                return Ok(Vec::new());
+            }
+
+            let first = first_priming_frame(&spans, start);
+            let mut piece = Vec::new();
+            for (i, span) in spans[first..end].iter().enumerate() {
+                let bytes = &data[span.offset..span.offset + span.len];
+                if i == 0 && span.main_data_begin != 0 {
+                    piece.extend_from_slice(&without_reservoir(bytes, span));
+                } else {
+                    piece.extend_from_slice(bytes);
+                }
+            }
+            Ok(piece)
+        })
+        .collect()
+}
+
+/// Returns true if the frame holds a Xing or Info header instead of audio
+fn is_info_frame(data: &[u8], span: &FrameSpan) -> bool {
+    let tag = span.offset + span.header_len + span.side_info_len;
+    matches!(data.get(tag..tag + 4), Some(b"Xing") | Some(b"Info"))
+}
+
+/// Returns the index of the frame a piece starting at frame `start` has to begin with
+///
+/// The frames before `start` have to hold its reservoir, and the second of
+/// them has to find its own reservoir in the first one, whose reservoir
+/// offset is cleared. The following frames refer to the same bytes as in
+/// the whole stream.
+fn first_priming_frame(spans: &[FrameSpan], start: usize) -> usize {
+    let mut first = start;
+    let mut available = 0;
+    while first > 0
+        && (available < spans[start].main_data_begin
+            || (first < start && spans[first + 1].main_data_begin > spans[first].main_data_len()))
+    {
+        first -= 1;
+        available += spans[first].main_data_len();
+    }
+    first
+}
+
+/// Returns the frame `bytes` with a reservoir offset of 0, and its CRC updated
+fn without_reservoir(bytes: &[u8], span: &FrameSpan) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    let side_info = span.header_len;
+    // main_data_begin takes the first 9 bits of the side info of MPEG1, 8 otherwise
+    bytes[side_info] = 0;
+    if span.is_mpeg1 {
+        bytes[side_info + 1] &= 0x7f;
+    }
+    if span.has_crc {
+        // the CRC covers the last two bytes of the header and the side info
+        let crc = crc::crc16_update(0xffff, &bytes[2..4]);
+        let crc = crc::crc16_update(crc, &bytes[side_info..side_info + span.side_info_len]);
+        bytes[4..6].copy_from_slice(&crc.to_be_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+fn frame_with_reservoir(main_data_begin: u16, fill: u8) -> Vec<u8> {
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz, mono, frames of 417 bytes
+    let mut frame = vec![0xff, 0xfb, 0x90, 0xc0];
+    let mut side_info = [0; 17];
+    side_info[0] = (main_data_begin >> 1) as u8;
+    side_info[1] = ((main_data_begin & 1) << 7) as u8;
+    frame.extend_from_slice(&side_info);
+    frame.extend_from_slice(&[fill; 396]);
+    frame
+}
+
+#[cfg(test)]
+fn verify(data: Vec<u8>) -> crate::stats::VerifyReport {
+    let mut segment = crate::audio::AudioSegment::from_reader(
+        std::io::Cursor::new(data),
+        crate::codecs::FormatFlag::MP3,
+    )
+    .unwrap();
+    segment.verify().unwrap()
+}
+
+#[test]
+fn test_split_on_frame_boundaries() {
+    // ten frames, all but the first taking 100 bytes of the reservoir
+    let frames: Vec<Vec<u8>> = (0..10)
+        .map(|i| frame_with_reservoir(if i == 0 { 0 } else { 100 }, i as u8))
+        .collect();
+    let stream = frames.concat();
+
+    let pieces = split(&stream[..], &[0..3, 3..6, 6..20]).unwrap();
+    assert_eq!(pieces.len(), 3);
+    assert_eq!(pieces[0], frames[..3].concat());
+    // the later pieces start with the frame holding their reservoir
+    assert_eq!(pieces[1][417..], frames[3..6].concat()[..]);
+    assert_eq!(
+        pieces[1][..417],
+        [&frames[2][..4], &[0, 0], &frames[2][6..]].concat()[..]
+    );
+    assert_eq!(pieces[2].len(), 5 * 417);
+
+    // without its priming frame the reservoir of a piece is missing
+    assert_eq!(verify(frames[3..6].concat()).frames, 0);
+
+    // the pieces decode each and joined, to the frames of the stream and the priming ones
+    for (piece, frames) in pieces.iter().zip(&[3, 4, 5]) {
+        let report = verify(piece.clone());
+        assert!(report.is_ok());
+        assert_eq!(report.frames, *frames);
+    }
+    let report = verify(pieces.concat());
+    assert!(report.is_ok());
+    assert_eq!(report.frames, 10 + 2);
+
+    assert_eq!(
+        split(&stream[..], &[12..14, 9..9]).unwrap(),
+        vec![Vec::new(), Vec::new()]
+    );
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = split(&stream[..], &[0..2, 4..2]);
+    assert!(reversed.is_err());
+}
+
+#[test]
+fn test_split_leaves_out_tag_and_info_frame() {
+    let mut stream = b"ID3\x04\x00\x00\x00\x00\x00\x04".to_vec();
+    stream.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+    let mut info = frame_with_reservoir(0, 0);
+    info[21..25].copy_from_slice(b"Info");
+    stream.extend_from_slice(&info);
+    let audio = vec![frame_with_reservoir(0, 1), frame_with_reservoir(0, 2)];
+    stream.extend_from_slice(&audio.concat());
+
+    assert_eq!(split(&stream[..], &[0..1, 1..2]).unwrap(), audio);
+}
+
+#[test]
+fn test_primed_frame_crc() {
+    // the frame of `frame_with_reservoir` with a CRC after its header
+    let mut frame = frame_with_reservoir(200, 7);
+    frame[1] = 0xfa;
+    frame.insert(4, 0);
+    frame.insert(4, 0);
+    frame.truncate(417);
+    let span = FrameSpan {
+        offset: 0,
+        len: 417,
+        header_len: 6,
+        side_info_len: 17,
+        main_data_begin: 200,
+        is_mpeg1: true,
+        has_crc: true,
+    };
+
+    // the CRC-16 of MPEG audio, x^16 + x^15 + x^2 + x^0 starting at 0xffff
+    assert_eq!(crc::crc16_update(0xffff, b"123456789"), 0xaee7);
+
+    let primed = without_reservoir(&frame, &span);
+    assert_eq!(&primed[6..8], &[0, 0]);
+    let mut crc_input = primed[2..4].to_vec();
+    crc_input.extend_from_slice(&primed[6..23]);
+    let crc = crc::crc16_update(0xffff, &crc_input);
+    assert_eq!(&primed[4..6], &crc.to_be_bytes());
+    assert_eq!(&primed[23..], &frame[23..]);
+}
@@ -76,12 +76,13 @@ impl FrameHeader {
     }
 
     pub fn is_intensity_stereo(&self) -> bool {
-        match self.channel_mode {
+        matches!(
+            self.channel_mode,
             ChannelMode::JointStereo {
-                intensity: true, ..
-            } => true,
-            _ => false,
-        }
+                intensity: true,
+                ..
+            }
+        )
     }
 }
 
@@ -105,6 +106,7 @@ pub struct Granule {
     pub channels: [GranuleChannel; 2],
 }
 
+#[allow(dead_code)]
 pub struct GranuleChannel {
     /// number of bits for scalefactors[part2] and huffman data[part3]
     pub part2_3_length: u16,
@@ -0,0 +1,402 @@
+//! The Ogg demuxer, splitting a physical stream into the packets of its logical streams.
+//!
+//! No codec is attached yet, the packets are returned as they are. Chained
+//! streams, e.g. of internet radio, are logical streams one after another,
+//! each beginning with a page flagged as the first one and, unless it is cut
+//! off, ending with a page flagged as the last one. Streams beginning
+//! together are multiplexed into one link of the chain.
+
+pub(crate) mod page;
+
+use std::collections::{HashMap, VecDeque};
+
+use self::page::Page;
+use crate::io::ReadBuffer;
+use crate::{errors, Result};
+
+/// A packet of a logical stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct OggPacket {
+    /// serial number of the logical stream
+    pub serial: u32,
+    /// the position after the packet, for the last packet completed on a page
+    pub granule_position: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+/// What is known about a logical stream, kept up to date while demuxing
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogicalStream {
+    pub serial: u32,
+    /// index of the link of the chain the stream belongs to, from 0
+    pub link: u32,
+    /// number of pages read
+    pub pages: u64,
+    /// number of pages missing, found by gaps of the page sequence numbers
+    pub lost_pages: u64,
+    /// granule position of the first page completing a packet
+    pub first_granule_position: Option<u64>,
+    /// granule position of the latest page completing a packet
+    pub last_granule_position: Option<u64>,
+    /// true once the page flagged as the last one was read
+    pub is_complete: bool,
+}
+
+/// An item returned by `OggDemuxer`
+#[derive(Clone, Debug, PartialEq)]
+pub enum OggEvent {
+    /// A logical stream begins, its packets follow
+    StreamStarted {
+        serial: u32,
+        link: u32,
+    },
+    Packet(OggPacket),
+    /// A logical stream ended, with its last page or cut off by the next link
+    StreamEnded(LogicalStream),
+}
+
+/// Returns the packets of an Ogg stream, and the boundaries of its logical streams
+///
+/// A page of a new logical stream once the streams of the current link
+/// have data pages starts a new link. Streams of the current link which
+/// did not end are cut off, their `StreamEnded` is not `is_complete`. The
+/// streams of the last link still open at the end of the input are cut off
+/// the same way.
+///
+/// A packet interrupted by a lost page is dropped. Pages of a stream which
+/// did not begin are an error, as are damaged pages.
+pub struct OggDemuxer<R: ReadBuffer> {
+    input: R,
+    streams: HashMap<u32, LogicalStream>,
+    /// the sequence number of the next page of each stream
+    sequences: HashMap<u32, u32>,
+    /// packet data continued on the next page of a stream
+    partial: HashMap<u32, Vec<u8>>,
+    events: VecDeque<OggEvent>,
+    link: u32,
+    /// true once a link has begun
+    has_link: bool,
+    /// true once a page not beginning a stream was read in the current link
+    link_has_data: bool,
+    is_done: bool,
+}
+
+impl<R: ReadBuffer> OggDemuxer<R> {
+    pub fn new(input: R) -> OggDemuxer<R> {
+        OggDemuxer {
+            input,
+            streams: HashMap::new(),
+            sequences: HashMap::new(),
+            partial: HashMap::new(),
+            events: VecDeque::new(),
+            link: 0,
+            has_link: false,
+            link_has_data: false,
+            is_done: false,
+        }
+    }
+
+    /// Returns the logical stream of `serial`, if it began and did not end yet
+    pub fn stream(&self, serial: u32) -> Option<&LogicalStream> {
+        self.streams.get(&serial)
+    }
+
+    /// Returns the index of the current link of the chain
+    pub fn link(&self) -> u32 {
+        self.link
+    }
+
+    fn push_page(&mut self, page: Page) -> Result<()> {
+        if page.is_first {
+            self.begin_stream(&page)?;
+        } else {
+            self.link_has_data = true;
+        }
+
+        let stream = match self.streams.get_mut(&page.serial) {
+            Some(stream) => stream,
+            None => return errors::parse_error("Ogg page of a logical stream which did not begin"),
+        };
+        let expected = self
+            .sequences
+            .insert(page.serial, page.sequence.wrapping_add(1));
+        if let Some(expected) = expected.filter(|&expected| page.sequence > expected) {
+            stream.lost_pages += (page.sequence - expected) as u64;
+            self.partial.remove(&page.serial);
+        }
+        stream.pages += 1;
+        if page.granule_position.is_some() {
+            stream.first_granule_position = stream.first_granule_position.or(page.granule_position);
+            stream.last_granule_position = page.granule_position;
+        }
+
+        let (packets, is_open) = page.packets();
+        let completed = packets.len() - is_open as usize;
+        let partial = self.partial.remove(&page.serial);
+        for (i, packet) in packets.into_iter().enumerate() {
+            let mut data = match (i, &partial) {
+                // the continuation of a packet whose start was lost is dropped
+                (0, None) if page.is_continued => continue,
+                (0, Some(start)) if page.is_continued => start.clone(),
+                _ => Vec::new(),
+            };
+            data.extend_from_slice(packet);
+            if i >= completed {
+                self.partial.insert(page.serial, data);
+                break;
+            }
+            self.events.push_back(OggEvent::Packet(OggPacket {
+                serial: page.serial,
+                granule_position: if i + 1 == completed {
+                    page.granule_position
+                } else {
+                    None
+                },
+                data,
+            }));
+        }
+
+        if page.is_last {
+            self.end_stream(page.serial, true);
+        }
+        Ok(())
+    }
+
+    fn begin_stream(&mut self, page: &Page) -> Result<()> {
+        if self.link_has_data || !self.has_link {
+            // a new link cuts off the streams of the previous one
+            let mut open: Vec<u32> = self.streams.keys().copied().collect();
+            open.sort_unstable();
+            for serial in open {
+                self.end_stream(serial, false);
+            }
+            if self.has_link {
+                self.link += 1;
+            }
+            self.has_link = true;
+            self.link_has_data = false;
+        } else if self.streams.contains_key(&page.serial) {
+            return errors::parse_error("two Ogg logical streams have the same serial number");
+        }
+
+        self.streams.insert(
+            page.serial,
+            LogicalStream {
+                serial: page.serial,
+                link: self.link,
+                pages: 0,
+                lost_pages: 0,
+                first_granule_position: None,
+                last_granule_position: None,
+                is_complete: false,
+            },
+        );
+        self.events.push_back(OggEvent::StreamStarted {
+            serial: page.serial,
+            link: self.link,
+        });
+        Ok(())
+    }
+
+    fn end_stream(&mut self, serial: u32, is_complete: bool) {
+        self.partial.remove(&serial);
+        self.sequences.remove(&serial);
+        if let Some(mut stream) = self.streams.remove(&serial) {
+            stream.is_complete = is_complete;
+            self.events.push_back(OggEvent::StreamEnded(stream));
+        }
+    }
+}
+
+impl<R: ReadBuffer> Iterator for OggDemuxer<R> {
+    type Item = Result<OggEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.events.is_empty() && !self.is_done {
+            match page::read_page(&mut self.input) {
+                Ok(Some(page)) => {
+                    if let Err(error) = self.push_page(page) {
+                        self.is_done = true;
+                        return Some(Err(error));
+                    }
+                }
+                Ok(None) => {
+                    self.is_done = true;
+                    let mut open: Vec<u32> = self.streams.keys().copied().collect();
+                    open.sort_unstable();
+                    for serial in open {
+                        self.end_stream(serial, false);
+                    }
+                }
+                Err(error) => {
+                    self.is_done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        self.events.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+fn demux(pages: &[crate::testing::OggPage]) -> Vec<OggEvent> {
+    let bytes: Vec<u8> = pages.iter().flat_map(|page| page.encode()).collect();
+    OggDemuxer::new(&bytes[..])
+        .map(|event| event.unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+fn packet(serial: u32, granule_position: Option<u64>, data: &[u8]) -> OggEvent {
+    OggEvent::Packet(OggPacket {
+        serial,
+        granule_position,
+        data: data.to_vec(),
+    })
+}
+
+#[cfg(test)]
+fn ended(serial: u32, link: u32, pages: u64, granules: (u64, u64), is_complete: bool) -> OggEvent {
+    OggEvent::StreamEnded(LogicalStream {
+        serial,
+        link,
+        pages,
+        lost_pages: 0,
+        first_granule_position: Some(granules.0),
+        last_granule_position: Some(granules.1),
+        is_complete,
+    })
+}
+
+/// Returns the pages of a logical stream with a header packet and two data pages
+#[cfg(test)]
+fn logical_stream(serial: u32, granule_start: u64) -> Vec<crate::testing::OggPage> {
+    use crate::testing::OggPage;
+
+    let mut header = OggPage::new(serial, 0);
+    header.is_first = true;
+    header.granule_position = Some(0);
+    header.packet(b"header");
+    let mut first = OggPage::new(serial, 1);
+    first.granule_position = Some(granule_start + 100);
+    first.packet(b"a").packet(b"b");
+    let mut last = OggPage::new(serial, 2);
+    last.is_last = true;
+    last.granule_position = Some(granule_start + 150);
+    last.packet(b"c");
+    vec![header, first, last]
+}
+
+#[test]
+fn test_chained_streams() {
+    let mut pages = logical_stream(11, 0);
+    pages.extend(logical_stream(22, 5_000));
+
+    assert_eq!(
+        demux(&pages),
+        vec![
+            OggEvent::StreamStarted {
+                serial: 11,
+                link: 0
+            },
+            packet(11, Some(0), b"header"),
+            packet(11, None, b"a"),
+            packet(11, Some(100), b"b"),
+            packet(11, Some(150), b"c"),
+            ended(11, 0, 3, (0, 150), true),
+            OggEvent::StreamStarted {
+                serial: 22,
+                link: 1
+            },
+            packet(22, Some(0), b"header"),
+            packet(22, None, b"a"),
+            packet(22, Some(5_100), b"b"),
+            packet(22, Some(5_150), b"c"),
+            ended(22, 1, 3, (0, 5_150), true),
+        ]
+    );
+}
+
+#[test]
+fn test_multiplexed_streams() {
+    // two streams beginning together form one link, their pages interleaved
+    let (a, b) = (logical_stream(1, 0), logical_stream(2, 0));
+    let pages = vec![
+        a[0].clone(),
+        b[0].clone(),
+        a[1].clone(),
+        b[1].clone(),
+        b[2].clone(),
+        a[2].clone(),
+    ];
+    let events = demux(&pages);
+    assert_eq!(events[2], OggEvent::StreamStarted { serial: 2, link: 0 });
+    assert_eq!(events[events.len() - 3], ended(2, 0, 3, (0, 150), true));
+    assert_eq!(events[events.len() - 1], ended(1, 0, 3, (0, 150), true));
+
+    // two beginning streams with the same serial number
+    let pages = [a[0].clone(), a[0].clone()];
+    let bytes: Vec<u8> = pages.iter().flat_map(|page| page.encode()).collect();
+    let mut demuxer = OggDemuxer::new(&bytes[..]);
+    assert!(demuxer.any(|event| event.is_err()));
+    assert!(demuxer.next().is_none());
+}
+
+#[test]
+fn test_cut_off_streams() {
+    // radio restarting its encoder, without ending the stream
+    let mut pages = logical_stream(3, 0);
+    pages.pop();
+    pages.extend(logical_stream(4, 0));
+    pages.pop();
+
+    let events = demux(&pages);
+    assert_eq!(events[4], ended(3, 0, 2, (0, 100), false));
+    assert_eq!(events[5], OggEvent::StreamStarted { serial: 4, link: 1 });
+    // the end of the input cuts off the last one
+    assert_eq!(events.last(), Some(&ended(4, 1, 2, (0, 100), false)));
+}
+
+#[test]
+fn test_packets_across_pages() {
+    use crate::testing::OggPage;
+
+    let mut pages = logical_stream(5, 0);
+    pages.truncate(1);
+    let long: Vec<u8> = (0..700u32).map(|i| i as u8).collect();
+    let mut page = OggPage::new(5, 1);
+    page.packet(b"x").open_packet(&long[..510]);
+    pages.push(page);
+    let mut page = OggPage::new(5, 2);
+    page.is_continued = true;
+    page.granule_position = Some(42);
+    page.packet(&long[510..]);
+    pages.push(page);
+
+    let events = demux(&pages);
+    assert_eq!(events[2], packet(5, None, b"x"));
+    assert_eq!(events[3], packet(5, Some(42), &long));
+
+    // a lost page drops the packet it continues
+    pages.remove(1);
+    let mut page = OggPage::new(5, 3);
+    page.granule_position = Some(50);
+    page.packet(b"y");
+    pages.push(page);
+    let events = demux(&pages);
+    assert_eq!(events[2], packet(5, Some(50), b"y"));
+    match &events[3] {
+        OggEvent::StreamEnded(stream) => {
+            assert_eq!(stream.lost_pages, 1);
+            assert_eq!(stream.pages, 3);
+            assert_eq!(stream.first_granule_position, Some(0));
+        }
+        event => panic!("expected the end of the stream, got {:?}", event),
+    }
+
+    // pages of a stream which never began
+    let mut page = OggPage::new(6, 0);
+    page.packet(b"z");
+    let bytes = page.encode();
+    assert!(OggDemuxer::new(&bytes[..]).next().unwrap().is_err());
+}
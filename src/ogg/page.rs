@@ -0,0 +1,157 @@
+use crate::crc;
+use crate::io::ReadBuffer;
+use crate::{errors, Result};
+
+/// The page header is followed by as many lacing values as the page has segments
+pub const HEADER_LEN: usize = 27;
+
+/// One page of an Ogg stream
+///
+/// https://www.rfc-editor.org/rfc/rfc3533#section-6
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    /// the first packet of the page continues the last one of the previous page
+    pub is_continued: bool,
+    /// the page begins its logical stream
+    pub is_first: bool,
+    /// the page ends its logical stream
+    pub is_last: bool,
+    /// position of the last packet completed on the page, in units of its
+    /// codec, `None` if no packet is completed on it
+    pub granule_position: Option<u64>,
+    /// serial number of the logical stream the page belongs to
+    pub serial: u32,
+    /// number of the page in its logical stream
+    pub sequence: u32,
+    /// lacing values, each segment of 255 bytes is continued by the next one
+    pub segments: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl Page {
+    /// Returns the packets of the page, and whether the last one is continued on the next page
+    ///
+    /// The first packet is the end of the one of the previous page if the
+    /// page `is_continued`.
+    pub fn packets(&self) -> (Vec<&[u8]>, bool) {
+        let mut packets = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        for &segment in &self.segments {
+            end += segment as usize;
+            if segment < 255 {
+                packets.push(&self.data[start..end]);
+                start = end;
+            }
+        }
+        let is_open = self.segments.last() == Some(&255);
+        if is_open {
+            packets.push(&self.data[start..end]);
+        }
+        (packets, is_open)
+    }
+}
+
+/// Reads the page at the current position of `input`, `None` at the end of the input
+///
+/// The page has to start right there, with the capture pattern "OggS", and
+/// its CRC has to match.
+pub fn read_page<R: ReadBuffer>(input: &mut R) -> Result<Option<Page>> {
+    let mut header = [0u8; HEADER_LEN];
+    match input.read_u8() {
+        Ok(byte) => header[0] = byte,
+        Err(ref error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+    input.read_into(&mut header[1..])?;
+    if &header[..4] != b"OggS" {
+        return errors::parse_error("no Ogg page found, the capture pattern is missing");
+    }
+    if header[4] != 0 {
+        return errors::unsupported_error("only version 0 of Ogg pages is supported");
+    }
+
+    let flags = header[5];
+    let mut granule = [0u8; 8];
+    granule.copy_from_slice(&header[6..14]);
+    let granule_position = u64::from_le_bytes(granule);
+    let field = |at: usize| {
+        u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+    };
+    let serial = field(14);
+    let sequence = field(18);
+    let stored_crc = field(22);
+
+    let mut segments = vec![0u8; header[26] as usize];
+    input.read_into(&mut segments)?;
+    let len = segments.iter().map(|&s| s as usize).sum();
+    let data = input.read_bytes(len)?;
+
+    header[22..26].copy_from_slice(&[0; 4]);
+    let crc = crc::crc32_update(crc::crc32_ogg(&header), &segments);
+    if crc::crc32_update(crc, &data) != stored_crc {
+        return errors::parse_error("Ogg page CRC mismatch");
+    }
+
+    Ok(Some(Page {
+        is_continued: flags & 0x01 != 0,
+        is_first: flags & 0x02 != 0,
+        is_last: flags & 0x04 != 0,
+        // -1 marks pages without a completed packet
+        granule_position: if granule_position == u64::MAX {
+            None
+        } else {
+            Some(granule_position)
+        },
+        serial,
+        sequence,
+        segments,
+        data,
+    }))
+}
+
+#[test]
+fn test_read_page() {
+    use crate::testing::OggPage;
+
+    let mut page = OggPage::new(7, 3);
+    page.is_first = true;
+    page.granule_position = Some(1_000);
+    page.packet(b"head").packet(&[1; 300]);
+    let bytes = page.encode();
+
+    let read = read_page(&mut &bytes[..]).unwrap().unwrap();
+    assert!(read.is_first && !read.is_last && !read.is_continued);
+    assert_eq!(read.granule_position, Some(1_000));
+    assert_eq!((read.serial, read.sequence), (7, 3));
+    assert_eq!(read.segments, vec![4, 255, 45]);
+    assert_eq!(read.packets(), (vec![&b"head"[..], &[1; 300][..]], false));
+
+    // nothing left, and a damaged page
+    assert_eq!(read_page(&mut &[][..]).unwrap(), None);
+    let mut damaged = bytes.clone();
+    damaged[30] ^= 1;
+    match read_page(&mut &damaged[..]) {
+        Err(errors::Error::ParseError(msg)) => assert!(msg.contains("CRC")),
+        other => panic!("expected a CRC mismatch, got {:?}", other),
+    }
+    assert!(read_page(&mut &bytes[1..]).is_err());
+}
+
+#[test]
+fn test_open_packet() {
+    use crate::testing::OggPage;
+
+    // a packet of exactly 510 bytes ends with a segment of 0 bytes
+    let mut page = OggPage::new(1, 0);
+    page.packet(&[2; 510]);
+    let read = read_page(&mut &page.encode()[..]).unwrap().unwrap();
+    assert_eq!(read.segments, vec![255, 255, 0]);
+    assert_eq!(read.packets(), (vec![&[2; 510][..]], false));
+
+    // a packet going on on the next page
+    let mut page = OggPage::new(1, 0);
+    page.open_packet(&[3; 255]);
+    let read = read_page(&mut &page.encode()[..]).unwrap().unwrap();
+    assert_eq!(read.packets(), (vec![&[3; 255][..]], true));
+}
@@ -0,0 +1,268 @@
+//! Min/max overviews of decoded audio, e.g. for drawing waveforms.
+//!
+//! An overview splits the audio into buckets of equal length and keeps the
+//! smallest and largest sample and the RMS of each channel in each bucket.
+//! The samples are reduced while they are decoded, nothing but the buckets
+//! and one buffer of a few thousand samples per channel is allocated.
+
+use crate::audio::{AudioSegment, SampleIterator};
+use crate::errors;
+use crate::io::Sample;
+use crate::Result;
+
+/// number of inter-channel samples read at once
+const CHUNK_FRAMES: usize = 4096;
+
+/// The samples of one channel in one bucket of an overview, relative to full scale
+///
+/// A bucket without samples, e.g. one of more buckets than the audio has
+/// inter-channel samples, is all zeros.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MinMax {
+    pub min: f32,
+    pub max: f32,
+    /// root mean square of the samples
+    pub rms: f32,
+}
+
+/// Returns the overview of `buckets` buckets of the `frames` inter-channel samples of `samples`
+///
+/// The samples have to be in `[-1.0, 1.0)`, like those of `Pipeline::build`,
+/// which trims the audio to the part of a zoomed view. The result holds
+/// `buckets * channels` entries, all channels of the first bucket first.
+/// Samples past `frames` are added to the last bucket.
+pub fn overview(
+    samples: SampleIterator<'_, f32>,
+    channels: usize,
+    frames: u64,
+    buckets: usize,
+) -> Result<Vec<MinMax>> {
+    reduce(samples, channels, frames, buckets, 0.0, 1.0)
+}
+
+/// `AudioSegment::overview`, integer samples are reduced as they are decoded
+pub(crate) fn segment_overview(segment: &mut AudioSegment, buckets: usize) -> Result<Vec<MinMax>> {
+    let channels = segment.number_channels();
+    let info = segment.info().clone();
+    // MP3 streams tell no length, scanning finds it for seekable inputs
+    let scanned = match info.total_samples {
+        0 => Some(segment.scan_length()?.frames),
+        _ => None,
+    };
+    let header_frames = info.total_samples / channels.max(1) as u64;
+
+    if !info.sample_format.is_float() {
+        let offset = info.sample_format.offset() as f64;
+        let scale = (1u64 << (info.bits_per_sample.clamp(1, 32) - 1)) as f64;
+        let samples = segment.samples::<i32>()?;
+        let frames = samples
+            .remaining_frames()
+            .or(scanned)
+            .unwrap_or(header_frames);
+        reduce(samples, channels, frames, buckets, offset, scale)
+    } else {
+        let samples = segment.samples::<f32>()?;
+        let frames = samples
+            .remaining_frames()
+            .or(scanned)
+            .unwrap_or(header_frames);
+        reduce(samples, channels, frames, buckets, 0.0, 1.0)
+    }
+}
+
+/// Running minimum, maximum and sum of squares of one channel in the current bucket
+#[derive(Clone, Copy)]
+struct Accumulator {
+    min: f64,
+    max: f64,
+    sum_of_squares: f64,
+}
+
+impl Accumulator {
+    const EMPTY: Accumulator = Accumulator {
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+        sum_of_squares: 0.0,
+    };
+
+    fn finish(self, frames: u64, offset: f64, scale: f64) -> MinMax {
+        if frames == 0 {
+            return MinMax::default();
+        }
+        let mean_square = self.sum_of_squares / frames as f64;
+        MinMax {
+            min: ((self.min - offset) / scale) as f32,
+            max: ((self.max - offset) / scale) as f32,
+            rms: (mean_square.sqrt() / scale) as f32,
+        }
+    }
+}
+
+/// Reduces `samples` to `buckets` buckets, mapping each sample `s` to `(s - offset) / scale`
+///
+/// Minimum and maximum are taken on the samples as they are, only the result
+/// of each bucket is mapped.
+fn reduce<S: Sample + Into<f64>>(
+    mut samples: SampleIterator<'_, S>,
+    channels: usize,
+    frames: u64,
+    buckets: usize,
+    offset: f64,
+    scale: f64,
+) -> Result<Vec<MinMax>> {
+    if channels == 0 {
+        return errors::unsupported_error("an overview needs at least one channel");
+    }
+    let mut overview = vec![MinMax::default(); buckets * channels];
+    if buckets == 0 {
+        return Ok(overview);
+    }
+
+    // the first inter-channel sample of the bucket following `bucket`
+    let bucket_end = |bucket: usize| -> u64 {
+        let end = (bucket as u128 + 1) * frames as u128;
+        end.div_ceil(buckets as u128) as u64
+    };
+    let mut bucket = 0;
+    let mut end = bucket_end(0);
+    let mut bucket_frames = 0u64;
+    let mut accumulators = vec![Accumulator::EMPTY; channels];
+    let mut flush = |bucket: usize, accumulators: &mut [Accumulator], frames: u64| {
+        for (channel, accumulator) in accumulators.iter_mut().enumerate() {
+            overview[bucket * channels + channel] = accumulator.finish(frames, offset, scale);
+            *accumulator = Accumulator::EMPTY;
+        }
+    };
+
+    let zero = S::from_i32(0, 32)?;
+    let mut buffers = vec![vec![zero; CHUNK_FRAMES]; channels];
+    let mut position = 0u64;
+    loop {
+        let read = {
+            let mut out: Vec<&mut [S]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+            samples.read_planar(&mut out)?
+        };
+        // split the chunk into the runs of its buckets
+        let mut start = 0;
+        while start < read {
+            while position >= end && bucket + 1 < buckets {
+                flush(bucket, &mut accumulators, bucket_frames);
+                bucket += 1;
+                end = bucket_end(bucket);
+                bucket_frames = 0;
+            }
+            let run = if bucket + 1 < buckets {
+                ((end - position) as usize).min(read - start)
+            } else {
+                read - start
+            };
+            for (buffer, accumulator) in buffers.iter().zip(accumulators.iter_mut()) {
+                for &sample in &buffer[start..start + run] {
+                    let value: f64 = sample.into();
+                    accumulator.min = accumulator.min.min(value);
+                    accumulator.max = accumulator.max.max(value);
+                    let centered = value - offset;
+                    accumulator.sum_of_squares += centered * centered;
+                }
+            }
+            start += run;
+            position += run as u64;
+            bucket_frames += run as u64;
+        }
+        if read < CHUNK_FRAMES {
+            break;
+        }
+    }
+    flush(bucket, &mut accumulators, bucket_frames);
+    Ok(overview)
+}
+
+#[cfg(all(test, feature = "wav"))]
+fn wav_segment(samples: &[i16], channels: u16) -> AudioSegment {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(channels, 8_000)),
+        riff_chunk(b"data", &data),
+    ]);
+    AudioSegment::from_seekable_reader(std::io::Cursor::new(file), crate::codecs::FormatFlag::WAV)
+        .unwrap()
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_wav_overview() {
+    // a ramp on the left channel and its negation on the right one, 10000 frames
+    let samples: Vec<i16> = (0..10_000i16).flat_map(|i| vec![i, -i]).collect();
+    let overview = wav_segment(&samples, 2).overview(4).unwrap();
+    assert_eq!(overview.len(), 8);
+    let scale = 32_768.0;
+    for bucket in 0..4 {
+        let left = overview[bucket * 2];
+        let right = overview[bucket * 2 + 1];
+        let first = (bucket * 2_500) as f32;
+        assert_eq!(
+            (left.min, left.max),
+            (first / scale, (first + 2_499.0) / scale)
+        );
+        assert_eq!((right.min, right.max), (-left.max, -left.min));
+        assert_eq!(left.rms, right.rms);
+        assert!(left.rms > left.min && left.rms < left.max);
+    }
+
+    // more buckets than frames leave some empty, the others hold one frame
+    let overview = wav_segment(&[100, 200, 300], 1).overview(5).unwrap();
+    let non_empty: Vec<f32> = overview
+        .iter()
+        .filter(|m| **m != MinMax::default())
+        .map(|m| m.max * 32_768.0)
+        .collect();
+    assert_eq!(non_empty, vec![100.0, 200.0, 300.0]);
+    assert_eq!(overview[0].rms * 32_768.0, 100.0);
+
+    assert!(wav_segment(&[1, 2], 1).overview(0).unwrap().is_empty());
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_flac_overview() {
+    use crate::testing::FlacStream;
+
+    // three frames of 16 bit samples, the middle one silent
+    let mut stream = FlacStream::new(44_100, 1, 16);
+    stream.frame(&[vec![-1_000, 1_000]]);
+    stream.frame(&[vec![0, 0]]);
+    stream.frame(&[vec![16_384, -16_384]]);
+    let data = stream.build();
+    let mut segment = AudioSegment::from_seekable_reader(
+        std::io::Cursor::new(data),
+        crate::codecs::FormatFlag::FLAC,
+    )
+    .unwrap();
+
+    let overview = segment.overview(3).unwrap();
+    let min_max: Vec<(f32, f32)> = overview.iter().map(|m| (m.min, m.max)).collect();
+    let scale = 32_768.0;
+    assert_eq!(
+        min_max,
+        vec![(-1_000.0 / scale, 1_000.0 / scale), (0.0, 0.0), (-0.5, 0.5)]
+    );
+    assert_eq!(overview[1].rms, 0.0);
+    assert_eq!(overview[2].rms, 0.5);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_overview_of_a_zoomed_view() {
+    let samples: Vec<i16> = (0..1_000).collect();
+    let mut segment = wav_segment(&samples, 1);
+    let zoomed = segment
+        .process()
+        .trim(500, Some(100))
+        .build::<f32>()
+        .unwrap();
+    let overview = overview(zoomed, 1, 100, 2).unwrap();
+    assert_eq!(overview[0].min * 32_768.0, 500.0);
+    assert_eq!(overview[1].max * 32_768.0, 599.0);
+}
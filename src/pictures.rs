@@ -0,0 +1,454 @@
+//! Exporting the pictures embedded in the metadata, e.g. the cover art, without
+//! decoding any audio.
+//!
+//! FLAC stores them in PICTURE metadata blocks, MP3 and WAV in the APIC frames
+//! of an ID3v2 tag, which WAV keeps in an `id3 ` chunk. The readers only note
+//! where these are while reading the header, the images are copied to their
+//! files when exported, a few kilobytes at a time.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::io::{AudioInputStream, AudioReader};
+use crate::{errors, Result};
+
+/// The metadata a `PictureSource` points at
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PictureContainer {
+    /// a FLAC PICTURE metadata block, without its 4 byte header
+    FlacPicture,
+
+    /// an ID3v2 tag with its header, holding APIC frames
+    Id3v2,
+}
+
+/// Where embedded pictures are stored in the input, see `AudioSegment::export_pictures`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PictureSource {
+    /// what is stored at `offset`
+    pub container: PictureContainer,
+
+    /// offset of the block or tag from the start of the stream
+    pub offset: u64,
+
+    /// length of the block or tag in bytes
+    pub len: u64,
+
+    /// the block or tag, if it was kept while reading the header of an input
+    /// which can not seek, see `DecodeOptions::max_buffered_pictures_len`
+    pub payload: Option<Vec<u8>>,
+}
+
+/// Returns true if a block or tag of `len` bytes is to be kept in memory
+///
+/// Only inputs which can not seek need to keep them, and only as long as all
+/// of the kept ones fit in `max_len` bytes.
+#[cfg(any(feature = "wav", feature = "flac"))]
+pub(crate) fn keep_payload(seekable: bool, kept: &[PictureSource], len: u64, max_len: u64) -> bool {
+    let kept_len: u64 = kept
+        .iter()
+        .filter_map(|source| source.payload.as_ref())
+        .map(|payload| payload.len() as u64)
+        .sum();
+    !seekable && kept_len + len <= max_len
+}
+
+/// Longest MIME type accepted, longer ones are taken as corrupt
+const MAX_MIME_LEN: usize = 256;
+
+/// Writes the pictures of `reader` to files in `dir`, see `AudioSegment::export_pictures`
+pub(crate) fn export_pictures(reader: &mut dyn AudioReader, dir: &Path) -> Result<Vec<PathBuf>> {
+    let sources = reader.picture_sources()?;
+    let input = reader.buffer();
+    let resume = input.position();
+    let mut written = Vec::new();
+    let result = export_sources(input, &sources, dir, &mut written);
+
+    if input.is_seekable() && input.position() != resume {
+        input.seek(io::SeekFrom::Start(resume))?;
+    }
+    result.map(|_| written)
+}
+
+fn export_sources(
+    input: &mut AudioInputStream,
+    sources: &[PictureSource],
+    dir: &Path,
+    written: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for source in sources {
+        if let Some(ref payload) = source.payload {
+            export_source(source.container, &mut &payload[..], dir, written)?;
+            continue;
+        }
+        let position = input.position();
+        if source.offset >= position {
+            // only metadata is ahead of the audio, e.g. the ID3 tag of MP3
+            input.skip_ahead(source.offset - position)?;
+        } else if input.is_seekable() {
+            input.seek(io::SeekFrom::Start(source.offset))?;
+        } else {
+            tracing::warn!(
+                offset = source.offset,
+                "picture was passed by an input which can not seek"
+            );
+            continue;
+        }
+        let mut block = io::Read::take(&mut *input, source.len);
+        export_source(source.container, &mut block, dir, written)?;
+    }
+    Ok(())
+}
+
+fn export_source<R: Read>(
+    container: PictureContainer,
+    input: &mut R,
+    dir: &Path,
+    written: &mut Vec<PathBuf>,
+) -> Result<()> {
+    match container {
+        PictureContainer::FlacPicture => export_flac_picture(input, dir, written),
+        PictureContainer::Id3v2 => export_id3v2_pictures(input, dir, written),
+    }
+}
+
+/// Exports the image of a FLAC PICTURE block
+///
+/// https://xiph.org/flac/format.html#metadata_block_picture
+fn export_flac_picture<R: Read>(
+    input: &mut R,
+    dir: &Path,
+    written: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let _picture_type = read_be_u32(input)?;
+    let mime_len = read_be_u32(input)? as usize;
+    if mime_len > MAX_MIME_LEN {
+        return errors::parse_error("MIME type of the picture is too long");
+    }
+    let mut mime = vec![0; mime_len];
+    input.read_exact(&mut mime)?;
+    let description_len = read_be_u32(input)?;
+    skip(input, description_len as u64)?;
+    // width, height, color depth and number of indexed colors
+    skip(input, 16)?;
+    let data_len = read_be_u32(input)?;
+    write_picture(
+        input,
+        data_len as u64,
+        &String::from_utf8_lossy(&mime),
+        dir,
+        written,
+    )
+}
+
+/// Exports the images of the APIC frames of an ID3v2 tag, or PIC frames of version 2.2
+///
+/// Tags and frames which are unsynchronised, compressed or encrypted are
+/// skipped with a warning.
+fn export_id3v2_pictures<R: Read>(
+    input: &mut R,
+    dir: &Path,
+    written: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut header = [0; 10];
+    input.read_exact(&mut header)?;
+    if &header[..3] != b"ID3" || !(2..=4).contains(&header[3]) {
+        return errors::parse_error("picture source is not an ID3v2 tag");
+    }
+    let version = header[3];
+    let flags = header[5];
+    let mut remaining = synchsafe(&header[6..10]);
+    if flags & 0x80 != 0 {
+        tracing::warn!("pictures of an unsynchronised ID3v2 tag are skipped");
+        return Ok(());
+    }
+    if flags & 0x40 != 0 && version >= 3 {
+        let mut size = [0; 4];
+        input.read_exact(&mut size)?;
+        // the size of version 2.4 includes its own 4 bytes
+        let extended = match version {
+            3 => u32::from_be_bytes(size) as u64,
+            _ => synchsafe(&size).saturating_sub(4),
+        };
+        skip(input, extended)?;
+        remaining = remaining.saturating_sub(extended + 4);
+    }
+
+    let header_len = if version == 2 { 6 } else { 10 };
+    while remaining >= header_len {
+        let mut header = [0; 10];
+        input.read_exact(&mut header[..header_len as usize])?;
+        remaining -= header_len;
+        // the padding after the last frame
+        if header[0] == 0 {
+            break;
+        }
+        let (id, mut size, format_flags) = match version {
+            2 => (
+                &header[..3],
+                u32::from_be_bytes([0, header[3], header[4], header[5]]) as u64,
+                0,
+            ),
+            3 => (
+                &header[..4],
+                u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as u64,
+                header[9],
+            ),
+            _ => (&header[..4], synchsafe(&header[4..8]), header[9]),
+        };
+        if size > remaining {
+            tracing::warn!("ID3v2 frame is longer than its tag, the tag is cut short");
+            break;
+        }
+        remaining -= size;
+        if id != b"APIC" && id != b"PIC" {
+            skip(input, size)?;
+            continue;
+        }
+
+        let (unsupported, extra) = match version {
+            2 => (false, 0),
+            // compression, encryption and the group id
+            3 => (
+                format_flags & 0xc0 != 0,
+                if format_flags & 0x20 != 0 { 1 } else { 0 },
+            ),
+            // compression, encryption, unsynchronisation, the group id and the data length
+            _ => (
+                format_flags & 0x0e != 0,
+                (format_flags & 0x40 != 0) as u64 + 4 * (format_flags & 0x01 != 0) as u64,
+            ),
+        };
+        if unsupported || extra > size {
+            tracing::warn!("compressed, encrypted or unsynchronised ID3v2 picture skipped");
+            skip(input, size)?;
+            continue;
+        }
+        skip(input, extra)?;
+        size -= extra;
+
+        let mut frame = io::Read::take(&mut *input, size);
+        let encoding = read_u8(&mut frame)?;
+        let mime = if version == 2 {
+            let mut format = [0; 3];
+            frame.read_exact(&mut format)?;
+            format.make_ascii_uppercase();
+            match &format {
+                b"PNG" => "image/png".to_string(),
+                b"JPG" => "image/jpeg".to_string(),
+                other => String::from_utf8_lossy(other).into_owned(),
+            }
+        } else {
+            let mime = read_terminated(&mut frame, MAX_MIME_LEN)?;
+            String::from_utf8_lossy(&mime).into_owned()
+        };
+        let _picture_type = read_u8(&mut frame)?;
+        // UTF-16 descriptions end with two zero bytes, the others with one
+        let unit = if encoding == 1 || encoding == 2 { 2 } else { 1 };
+        skip_terminated(&mut frame, unit)?;
+        let data_len = frame.limit();
+        write_picture(&mut frame, data_len, &mime, dir, written)?;
+    }
+    // the padding, so that the tag is read in full
+    skip(input, remaining)
+}
+
+/// Copies the `len` bytes of an image of type `mime` to the next file in `dir`
+///
+/// Pictures which only link to an image, with the MIME type `-->`, are left out.
+fn write_picture<R: Read>(
+    input: &mut R,
+    len: u64,
+    mime: &str,
+    dir: &Path,
+    written: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if mime == "-->" {
+        return skip(input, len);
+    }
+    let path = dir.join(format!("picture-{}.{}", written.len() + 1, extension(mime)));
+    let mut file = fs::File::create(&path)?;
+    let copied = io::copy(&mut io::Read::take(&mut *input, len), &mut file)?;
+    if copied < len {
+        return errors::parse_error("embedded picture is cut short");
+    }
+    written.push(path);
+    Ok(())
+}
+
+/// Returns the file extension of the MIME type of an image, `bin` if it is not known
+fn extension(mime: &str) -> &'static str {
+    match mime.trim().to_ascii_lowercase().as_str() {
+        "image/png" => "png",
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/gif" => "gif",
+        "image/bmp" | "image/x-ms-bmp" => "bmp",
+        "image/webp" => "webp",
+        "image/tiff" => "tif",
+        "image/svg+xml" => "svg",
+        _ => "bin",
+    }
+}
+
+fn synchsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |size, b| size << 7 | (b & 0x7f) as u64)
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_be_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn skip<R: Read>(input: &mut R, n: u64) -> Result<()> {
+    let skipped = io::copy(&mut io::Read::take(&mut *input, n), &mut io::sink())?;
+    if skipped < n {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(())
+}
+
+/// Reads up to a terminating zero byte, failing after `max_len` bytes
+fn read_terminated<R: Read>(input: &mut R, max_len: usize) -> Result<Vec<u8>> {
+    let mut text = Vec::new();
+    loop {
+        match read_u8(input)? {
+            0 => return Ok(text),
+            _ if text.len() == max_len => {
+                return errors::parse_error("text of the picture is too long")
+            }
+            byte => text.push(byte),
+        }
+    }
+}
+
+/// Skips up to a terminating zero of `unit` bytes
+fn skip_terminated<R: Read>(input: &mut R, unit: usize) -> Result<()> {
+    let mut code = [0; 2];
+    loop {
+        input.read_exact(&mut code[..unit])?;
+        if code[..unit] == [0, 0][..unit] {
+            return Ok(());
+        }
+    }
+}
+
+#[test]
+#[cfg(all(feature = "mp3", feature = "wav"))]
+fn test_export_id3v2_pictures() {
+    use crate::audio::AudioSegment;
+    use crate::codecs::FormatFlag;
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let frame = |id: &[u8], version: u8, payload: &[u8]| {
+        let size = payload.len() as u32;
+        let mut frame = id.to_vec();
+        match version {
+            3 => frame.extend_from_slice(&size.to_be_bytes()),
+            _ => frame.extend((0..4).rev().map(|i| (size >> (7 * i)) as u8 & 0x7f)),
+        }
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    };
+    let tag = |version: u8, frames: &[Vec<u8>]| {
+        let mut body = frames.concat();
+        // padding
+        body.extend_from_slice(&[0; 7]);
+        let size = body.len() as u32;
+        let mut tag = vec![b'I', b'D', b'3', version, 0, 0];
+        tag.extend((0..4).rev().map(|i| (size >> (7 * i)) as u8 & 0x7f));
+        tag.extend_from_slice(&body);
+        tag
+    };
+    let jpeg: Vec<u8> = (0..3000u32).map(|i| (i * 31 % 256) as u8).collect();
+    let png: Vec<u8> = (0..500u32).map(|i| (i % 7) as u8).collect();
+    // Latin-1 and UTF-16 descriptions
+    let mut apic_jpeg = b"\x00image/jpeg\x00\x03Front\x00".to_vec();
+    apic_jpeg.extend_from_slice(&jpeg);
+    let mut apic_png = b"\x01image/png\x00\x04\xff\xfeB\x00a\x00\x00\x00".to_vec();
+    apic_png.extend_from_slice(&png);
+    let link = b"\x00-->\x00\x03\x00http://example.com/cover.jpg".to_vec();
+
+    let dir = std::env::temp_dir().join(format!("cauldron-id3-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let export = |segment: &mut AudioSegment| -> Vec<(String, Vec<u8>)> {
+        let written = segment.export_pictures(&dir).unwrap();
+        written
+            .iter()
+            .map(|path| {
+                let data = fs::read(path).unwrap();
+                fs::remove_file(path).unwrap();
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    data,
+                )
+            })
+            .collect()
+    };
+
+    // MP3 starting with a tag, which an input that can not seek passes right away
+    let mut mp3 = tag(
+        3,
+        &[
+            frame(b"TIT2", 3, b"\x00Title"),
+            frame(b"APIC", 3, &apic_jpeg),
+            frame(b"APIC", 3, &link),
+        ],
+    );
+    let mp3_frame: Vec<u8> = [0xff, 0xfb, 0x90, 0xc0]
+        .iter()
+        .chain(&[0; 413])
+        .copied()
+        .collect();
+    for _ in 0..3 {
+        mp3.extend_from_slice(&mp3_frame);
+    }
+    let mut segment = AudioSegment::from_reader(io::Cursor::new(mp3), FormatFlag::MP3).unwrap();
+    assert_eq!(
+        export(&mut segment),
+        [("picture-1.jpg".to_string(), jpeg.clone())]
+    );
+    assert_eq!(segment.samples::<f32>().unwrap().count(), 3 * 1152);
+
+    // WAV with tags before and after the data
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"id3 ", &tag(4, &[frame(b"APIC", 4, &apic_png)])),
+        riff_chunk(b"data", &[1, 0, 2, 0]),
+        riff_chunk(b"ID3 ", &tag(3, &[frame(b"APIC", 3, &apic_jpeg)])),
+    ]);
+    let path = dir.join("tags.wav");
+    fs::write(&path, &file).unwrap();
+    let mut segment = AudioSegment::read(path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        export(&mut segment),
+        [
+            ("picture-1.png".to_string(), png.clone()),
+            ("picture-2.jpg".to_string(), jpeg)
+        ]
+    );
+    let samples: Vec<i16> = segment.samples().unwrap().map(Result::unwrap).collect();
+    assert_eq!(samples, [1, 2]);
+    fs::remove_file(&path).unwrap();
+
+    // only the tag kept while reading the header of an input which can not seek
+    let options = crate::audio::DecodeOptions {
+        max_buffered_pictures_len: 1 << 20,
+        ..Default::default()
+    };
+    let mut segment =
+        AudioSegment::from_reader_with_options(io::Cursor::new(file), FormatFlag::WAV, options)
+            .unwrap();
+    assert_eq!(export(&mut segment), [("picture-1.png".to_string(), png)]);
+    fs::remove_dir(&dir).unwrap();
+}
@@ -0,0 +1,249 @@
+//! The `playlist` module plays files one after another as a single stream.
+//!
+//! A `Player` reads the headers of all its tracks before decoding any, so that
+//! tracks which can not be joined are rejected up front. The tracks are then
+//! decoded one at a time with their encoder delay and padding dropped, so that
+//! tracks cut from one recording join sample exactly, without a gap. Each
+//! track start is reported by `Player::take_track_change`.
+//!
+//! ```no_run
+//! use cauldron::playlist::{Player, PlayerOptions};
+//!
+//! let paths = ["01.flac", "02.flac"];
+//! let mut player = Player::<f32>::open(&paths, PlayerOptions::default()).unwrap();
+//! while let Some(sample) = player.next() {
+//!     if let Some(change) = player.take_track_change() {
+//!         println!("now playing {}", change.track.path.display());
+//!     }
+//!     let _sample = sample.unwrap();
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use crate::audio::{AudioInfo, AudioSegment, DecodeOptions, SampleIterator};
+use crate::codecs::FormatFlag;
+use crate::errors::{Error, StreamParameters};
+use crate::io::{AudioSamplesIterator, Sample};
+use crate::{errors, process, Result};
+
+/// How a `Player` decodes and joins its tracks
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PlayerOptions {
+    /// the sample rate of the stream, `None` for the one of the first track
+    pub sample_rate: Option<u32>,
+
+    /// resample tracks of another sample rate, otherwise they are rejected
+    ///
+    /// The samples are interpolated linearly like `Pipeline::resample` does,
+    /// which needs float samples.
+    pub resample: bool,
+
+    /// how the tracks are decoded
+    ///
+    /// The joins are only gapless with `trim_encoder_padding`, which is on by default.
+    pub decode: DecodeOptions,
+}
+
+/// A track of a `Player`
+#[derive(Clone, Debug)]
+pub struct PlaylistTrack {
+    pub path: PathBuf,
+
+    /// the format the file was read as
+    pub format: FormatFlag,
+
+    /// the audio info from the header
+    pub info: AudioInfo,
+
+    /// the sample rate of the track, found by `AudioSegment::scan_length` if
+    /// the header tells none
+    pub sample_rate: u32,
+}
+
+/// A track starting in the stream of a `Player`
+#[derive(Clone, Debug)]
+pub struct TrackChange {
+    /// index of the track among the paths given to `Player::open`
+    pub index: usize,
+
+    /// the inter-channel sample of the stream the track starts at
+    pub frame: u64,
+
+    pub track: PlaylistTrack,
+}
+
+/// Decodes a list of files as one continuous, channel interleaved stream
+///
+/// The stream has the channels of the first track and the sample rate of
+/// `PlayerOptions::sample_rate`. Errors of a track are returned by `next`,
+/// decoding goes on with the track after it once the failed one ends.
+pub struct Player<S: Sample + 'static> {
+    tracks: Vec<PlaylistTrack>,
+    options: PlayerOptions,
+    sample_rate: u32,
+    channels: usize,
+    /// the samples of the track played now, `None` before the first one
+    samples: Option<SampleIterator<'static, S>>,
+    /// index of the track following the one played now
+    next_track: usize,
+    /// number of samples returned so far, over all channels
+    returned: u64,
+    /// a track change not yet taken by `take_track_change`
+    track_change: Option<TrackChange>,
+}
+
+impl<S: Sample + 'static> Player<S> {
+    /// Reads the headers of the files at `paths`, which are played in this order
+    ///
+    /// The format of each file is the one of its extension. A track
+    /// of other channels than the first one, or of another sample rate unless
+    /// it is resampled, fails with `Error::TrackMismatch`. So do MP3 tracks for
+    /// integer samples, as MP3 decodes to floats. An empty list is rejected
+    /// with `Error::Unsupported`.
+    pub fn open<P: AsRef<Path>>(paths: &[P], options: PlayerOptions) -> Result<Player<S>> {
+        let mut tracks = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let mut segment = open_segment(path, options.decode)?;
+            let sample_rate = match segment.info().sample_rate {
+                0 => segment.scan_length()?.sample_rate,
+                sample_rate => sample_rate,
+            };
+            tracks.push(PlaylistTrack {
+                path: path.to_path_buf(),
+                format: segment.format(),
+                info: segment.info().clone(),
+                sample_rate,
+            });
+        }
+
+        let first = match tracks.first() {
+            Some(first) => first,
+            None => return errors::unsupported_error("a playlist needs at least one track"),
+        };
+        let channels = first.info.channels.count();
+        let sample_rate = options.sample_rate.unwrap_or(first.sample_rate);
+        if sample_rate == 0 {
+            return errors::unsupported_error("the sample rate of the first track is unknown");
+        }
+        let is_float = S::from_f32(0.0).is_ok();
+        let expected = StreamParameters {
+            sample_rate,
+            channels: channels as u32,
+            bits_per_sample: 0,
+        };
+        for (index, track) in tracks.iter().enumerate() {
+            let found = StreamParameters {
+                sample_rate: track.sample_rate,
+                channels: track.info.channels.count() as u32,
+                bits_per_sample: 0,
+            };
+            let is_resampled = track.sample_rate != sample_rate;
+            let needs_float = is_resampled || track.info.sample_format.is_float();
+            if found.channels != expected.channels
+                || track.sample_rate == 0
+                || (is_resampled && !options.resample)
+                || (needs_float && !is_float)
+            {
+                return Err(Error::TrackMismatch {
+                    track: index,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        Ok(Player {
+            tracks,
+            options,
+            sample_rate,
+            channels,
+            samples: None,
+            next_track: 0,
+            returned: 0,
+            track_change: None,
+        })
+    }
+
+    /// Returns the tracks in the order they are played
+    pub fn tracks(&self) -> &[PlaylistTrack] {
+        &self.tracks
+    }
+
+    /// Returns the number of channels of the stream
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Returns the track which started with the sample returned last by `next`
+    ///
+    /// Every change is returned once, the first one with the first sample.
+    /// Tracks holding no samples start no change of their own.
+    pub fn take_track_change(&mut self) -> Option<TrackChange> {
+        self.track_change.take()
+    }
+
+    /// Starts decoding `track`, resampled to the sample rate of the stream
+    fn start_track(&self, track: &PlaylistTrack) -> Result<SampleIterator<'static, S>> {
+        let segment = open_segment(&track.path, self.options.decode)?;
+        if track.sample_rate == self.sample_rate {
+            return Ok(Box::new(segment.into_samples::<S>()?));
+        }
+        let samples: SampleIterator<'static, f32> = Box::new(segment.into_samples::<f32>()?);
+        Ok(process::resample(
+            samples,
+            self.channels,
+            track.sample_rate,
+            self.sample_rate,
+        ))
+    }
+}
+
+impl<S: Sample + 'static> AudioSamplesIterator<S> for Player<S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        loop {
+            if let Some(ref mut samples) = self.samples {
+                if let Some(sample) = samples.next() {
+                    if sample.is_ok() {
+                        self.returned += 1;
+                    }
+                    return Some(sample);
+                }
+            }
+
+            let index = self.next_track;
+            let track = self.tracks.get(index)?.clone();
+            self.next_track += 1;
+            // the file of the previous track is closed first
+            self.samples = None;
+            self.samples = Some(otry!(self.start_track(&track)));
+            self.track_change = Some(TrackChange {
+                index,
+                frame: self.returned / self.channels as u64,
+                track,
+            });
+        }
+    }
+
+    fn sample_rate(&self) -> Option<u32> {
+        Some(self.sample_rate)
+    }
+}
+
+impl<S: Sample + 'static> Iterator for Player<S> {
+    type Item = Result<S>;
+
+    fn next(&mut self) -> Option<Result<S>> {
+        AudioSamplesIterator::next(self)
+    }
+}
+
+/// Reads the header of the file at `path`, in the format of its extension
+fn open_segment(path: &Path, options: DecodeOptions) -> Result<AudioSegment> {
+    let filename = match path.to_str() {
+        Some(filename) => filename,
+        None => return errors::unsupported_error("path is not valid unicode"),
+    };
+    AudioSegment::read_with_options(filename, AudioSegment::get_format_flag(filename)?, options)
+}
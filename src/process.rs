@@ -0,0 +1,689 @@
+//! The `process` module chains processing stages onto decoded samples.
+//!
+//! A `Pipeline` is built by `AudioSegment::process`. Whatever order the stages
+//! are selected in, they are applied in this one:
+//!
+//! 1. `trim`, so that no work is spent on audio which is dropped anyway
+//! 2. `dc_block`, so that no offset is mixed into other channels
+//! 3. `downmix`, so that fewer channels are resampled
+//! 4. `resample`
+//! 5. `gain_db`, on the final signal
+//! 6. `dither`, last, so that no stage scales or filters its noise
+//!
+//! All stages work on `f32` samples in `[-1.0, 1.0)`, the output is converted
+//! to the sample type given to `Pipeline::build`.
+
+use crate::audio::{AudioSegment, ChannelLayout, Channels, SampleIterator};
+use crate::codecs;
+use crate::dither::{Dither, Requantizer};
+use crate::errors;
+use crate::io::{AudioSamplesIterator, Sample};
+use crate::Result;
+
+/// seed of the dither noise, so that the output is reproducible
+const DITHER_SEED: u32 = 0x6b43_a9b5;
+
+/// cutoff frequency of the DC blocking filter in Hz
+const DC_BLOCK_CUTOFF: f64 = 5.0;
+
+/// Selects the processing stages applied to the samples of a segment
+///
+/// Setting a stage again replaces its previous setting.
+pub struct Pipeline<'a> {
+    segment: &'a mut AudioSegment,
+    trim: Option<(u64, Option<u64>)>,
+    dc_block: bool,
+    downmix: Option<ChannelLayout>,
+    resample: Option<u32>,
+    gain_db: Option<f32>,
+    dither: Option<(u32, Dither)>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub(crate) fn new(segment: &'a mut AudioSegment) -> Pipeline<'a> {
+        Pipeline {
+            segment,
+            trim: None,
+            dc_block: false,
+            downmix: None,
+            resample: None,
+            gain_db: None,
+            dither: None,
+        }
+    }
+
+    /// Drops the inter-channel samples before `start`, and keeps `frames` of them, or all if `None`
+    ///
+    /// The samples before `start` are decoded all the same.
+    pub fn trim(mut self, start: u64, frames: Option<u64>) -> Pipeline<'a> {
+        self.trim = Some((start, frames));
+        self
+    }
+
+    /// Removes the DC offset of every channel with a highpass filter
+    ///
+    /// The filter is a single pole highpass with a cutoff at 5 Hz, which needs
+    /// about 100 ms to settle on an offset present from the start. Its group
+    /// delay is about `5 / (2π f²)` seconds at a frequency `f` well above the
+    /// cutoff, e.g. 80 µs at 100 Hz and below 1 µs from 1 kHz on.
+    pub fn dc_block(mut self) -> Pipeline<'a> {
+        self.dc_block = true;
+        self
+    }
+
+    /// Mixes the channels down (or up) to `layout`, which is `Mono` or `Stereo`
+    ///
+    /// Left and right channels go to their side, center channels to both sides
+    /// at -3 dB and LFE channels are dropped. Each output channel is scaled so
+    /// that it can not clip. Mono input is copied to both sides of stereo.
+    pub fn downmix(mut self, layout: ChannelLayout) -> Pipeline<'a> {
+        self.downmix = Some(layout);
+        self
+    }
+
+    /// Converts the samples to `sample_rate` by linear interpolation
+    pub fn resample(mut self, sample_rate: u32) -> Pipeline<'a> {
+        self.resample = Some(sample_rate);
+        self
+    }
+
+    /// Scales the samples by `db` decibels, clipping them at full scale
+    pub fn gain_db(mut self, db: f32) -> Pipeline<'a> {
+        self.gain_db = Some(db);
+        self
+    }
+
+    /// Requantizes the samples to integers of `bits` bits per sample
+    ///
+    /// This is required for integer output samples, and rejected for float ones.
+    pub fn dither(mut self, bits: u32, dither: Dither) -> Pipeline<'a> {
+        self.dither = Some((bits, dither));
+        self
+    }
+
+    /// Checks the stages and returns an interleaved iterator on the processed samples
+    ///
+    /// Fails with `Error::Unsupported` describing the problem if a stage can not
+    /// be applied, before any sample is decoded.
+    pub fn build<S: Sample + 'a>(self) -> Result<SampleIterator<'a, S>> {
+        let info = self.segment.info();
+        let mut channels = info.channels.count();
+        let sample_rate = info.sample_rate;
+
+        let is_float = S::from_f32(0.0).is_ok();
+        match self.dither {
+            Some(_) if is_float => {
+                return errors::unsupported_error("dithering needs integer output samples")
+            }
+            None if !is_float => {
+                return errors::unsupported_error(
+                    "integer output samples need a dither stage selecting their bit depth",
+                )
+            }
+            Some((bits, _)) if S::from_i32(0, bits).is_err() => {
+                return errors::unsupported_error(
+                    "output sample type is too narrow for the dithered bit depth",
+                )
+            }
+            _ => {}
+        }
+        if self.dc_block && sample_rate == 0 {
+            return errors::unsupported_error("the sample rate of the stream is unknown");
+        }
+        let matrix = match self.downmix {
+            Some(layout) => Some(downmix_matrix(&self.segment.channel_order(), layout)?),
+            None => None,
+        };
+        if let Some(rate) = self.resample {
+            if rate == 0 {
+                return errors::unsupported_error("resampling needs a sample rate above 0");
+            }
+            if sample_rate == 0 {
+                return errors::unsupported_error("the sample rate of the stream is unknown");
+            }
+        }
+        let gain = match self.gain_db {
+            Some(db) if !db.is_finite() => return errors::unsupported_error("gain must be finite"),
+            Some(db) => Some(10f32.powf(db / 20.0)),
+            None => None,
+        };
+        let output_channels = matrix.as_ref().map_or(channels, |matrix| matrix.len());
+        let requantizer = match self.dither {
+            Some((bits, dither)) => Some(Requantizer::new(
+                dither,
+                32,
+                bits,
+                output_channels,
+                DITHER_SEED,
+            )?),
+            None => None,
+        };
+
+        let mut samples = self.segment.samples::<f32>()?;
+        if let Some((start, frames)) = self.trim {
+            let channels = channels as u64;
+            samples = Box::new(Trimmed {
+                samples,
+                skip: start.saturating_mul(channels),
+                remaining: frames.map(|frames| frames.saturating_mul(channels)),
+            });
+        }
+        if self.dc_block {
+            samples = Box::new(DcBlocked {
+                samples,
+                pole: (-2.0 * std::f64::consts::PI * DC_BLOCK_CUTOFF / sample_rate as f64).exp()
+                    as f32,
+                previous: vec![(0.0, 0.0); channels],
+                channel: 0,
+            });
+        }
+        if let Some(matrix) = matrix {
+            let output_channels = matrix.len();
+            samples = Box::new(Downmixed {
+                samples,
+                input: Vec::with_capacity(channels),
+                output: vec![0.0; output_channels],
+                next_output: output_channels,
+                matrix,
+            });
+            channels = output_channels;
+        }
+        if let Some(rate) = self.resample {
+            if rate != sample_rate {
+                samples = Box::new(Resampled {
+                    samples,
+                    channels,
+                    step: sample_rate as f64 / rate as f64,
+                    position: 0.0,
+                    current: None,
+                    next: None,
+                    output: vec![0.0; channels],
+                    next_output: channels,
+                });
+            }
+        }
+        if let Some(gain) = gain {
+            samples = Box::new(Gained { samples, gain });
+        }
+
+        Ok(match (requantizer, self.dither) {
+            (Some(requantizer), Some((bits, _))) => Box::new(Quantized {
+                samples,
+                requantizer,
+                bits,
+                channels: output_channels,
+                channel: 0,
+                phantom: std::marker::PhantomData,
+            }),
+            _ => Box::new(Converted {
+                samples,
+                phantom: std::marker::PhantomData,
+            }),
+        })
+    }
+}
+
+/// Resamples the interleaved `samples` of `channels` channels from `from` to `to` Hz
+///
+/// This is the `resample` stage alone, converted to float output samples.
+pub(crate) fn resample<'a, S: Sample + 'a>(
+    samples: SampleIterator<'a, f32>,
+    channels: usize,
+    from: u32,
+    to: u32,
+) -> SampleIterator<'a, S> {
+    let samples: SampleIterator<'a, f32> = Box::new(Resampled {
+        samples,
+        channels,
+        step: from as f64 / to as f64,
+        position: 0.0,
+        current: None,
+        next: None,
+        output: vec![0.0; channels],
+        next_output: channels,
+    });
+    Box::new(Converted {
+        samples,
+        phantom: std::marker::PhantomData,
+    })
+}
+
+/// Returns the gains of every input channel for each output channel.
+///
+/// `input` holds the input channels in the order of their samples.
+fn downmix_matrix(input: &[Channels], layout: ChannelLayout) -> Result<Vec<Vec<f32>>> {
+    const CENTER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    let stereo: Vec<Vec<f32>> = if input.len() == 1 {
+        vec![vec![1.0], vec![1.0]]
+    } else {
+        let (left, right): (Vec<f32>, Vec<f32>) = input
+            .iter()
+            .map(|channel| stereo_gains(*channel, CENTER))
+            .unzip();
+        vec![left, right]
+    };
+
+    let mut matrix = match layout {
+        ChannelLayout::Stereo => stereo,
+        ChannelLayout::Mono => vec![stereo[0]
+            .iter()
+            .zip(&stereo[1])
+            .map(|(l, r)| (l + r) / 2.0)
+            .collect()],
+        _ => return errors::unsupported_error("only downmixing to mono or stereo is supported"),
+    };
+    for gains in &mut matrix {
+        let sum: f32 = gains.iter().sum();
+        if sum > 1.0 {
+            gains.iter_mut().for_each(|g| *g /= sum);
+        }
+    }
+    Ok(matrix)
+}
+
+/// Returns the gains of `channel` for the left and right side.
+fn stereo_gains(channel: Channels, center: f32) -> (f32, f32) {
+    let left = Channels::BACK_LEFT
+        | Channels::SIDE_LEFT
+        | Channels::FRONT_LEFT_CENTRE
+        | Channels::BACK_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_BACK_LEFT;
+    let right = Channels::BACK_RIGHT
+        | Channels::SIDE_RIGHT
+        | Channels::FRONT_RIGHT_CENTRE
+        | Channels::BACK_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_BACK_RIGHT;
+
+    if channel == Channels::FRONT_LEFT {
+        (1.0, 0.0)
+    } else if channel == Channels::FRONT_RIGHT {
+        (0.0, 1.0)
+    } else if left.contains(channel) {
+        (center, 0.0)
+    } else if right.contains(channel) {
+        (0.0, center)
+    } else if channel == Channels::LFE1 || channel == Channels::LFE2 {
+        (0.0, 0.0)
+    } else {
+        (center, center)
+    }
+}
+
+/// Drops samples from the start and the end
+struct Trimmed<'a> {
+    samples: SampleIterator<'a, f32>,
+    /// number of samples still to drop
+    skip: u64,
+    /// number of samples still to return, `None` for all
+    remaining: Option<u64>,
+}
+
+impl<'a> AudioSamplesIterator<f32> for Trimmed<'a> {
+    fn next(&mut self) -> Option<Result<f32>> {
+        while self.skip > 0 {
+            self.skip -= 1;
+            otry!(self.samples.next()?);
+        }
+        if let Some(ref mut remaining) = self.remaining {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        self.samples.next()
+    }
+}
+
+/// Filters every channel with `y[n] = x[n] - x[n - 1] + pole * y[n - 1]`
+struct DcBlocked<'a> {
+    samples: SampleIterator<'a, f32>,
+    pole: f32,
+    /// the last input and output sample of each channel
+    previous: Vec<(f32, f32)>,
+    /// channel of the next sample
+    channel: usize,
+}
+
+impl<'a> AudioSamplesIterator<f32> for DcBlocked<'a> {
+    fn next(&mut self) -> Option<Result<f32>> {
+        let sample = otry!(self.samples.next()?);
+        let (input, output) = self.previous[self.channel];
+        let output = sample - input + self.pole * output;
+        self.previous[self.channel] = (sample, output);
+        self.channel = (self.channel + 1) % self.previous.len();
+        Some(Ok(output))
+    }
+}
+
+/// Reads the next inter-channel sample, `None` at the end of the stream
+fn read_frame(
+    samples: &mut SampleIterator<f32>,
+    frame: &mut Vec<f32>,
+    channels: usize,
+) -> Option<Result<()>> {
+    frame.clear();
+    for channel in 0..channels {
+        match samples.next() {
+            Some(sample) => frame.push(otry!(sample)),
+            None if channel == 0 => return None,
+            None => {
+                return Some(errors::parse_error(
+                    "stream ended within an inter-channel sample",
+                ))
+            }
+        }
+    }
+    Some(Ok(()))
+}
+
+/// Applies a downmix matrix to every inter-channel sample
+struct Downmixed<'a> {
+    samples: SampleIterator<'a, f32>,
+    /// gains of the input channels for each output channel
+    matrix: Vec<Vec<f32>>,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    /// index of the next channel of `output` to return
+    next_output: usize,
+}
+
+impl<'a> AudioSamplesIterator<f32> for Downmixed<'a> {
+    fn next(&mut self) -> Option<Result<f32>> {
+        if self.next_output == self.output.len() {
+            let channels = self.matrix[0].len();
+            otry!(read_frame(&mut self.samples, &mut self.input, channels)?);
+            for (output, gains) in self.output.iter_mut().zip(&self.matrix) {
+                *output = gains.iter().zip(&self.input).map(|(g, s)| g * s).sum();
+            }
+            self.next_output = 0;
+        }
+        self.next_output += 1;
+        Some(Ok(self.output[self.next_output - 1]))
+    }
+}
+
+/// Interpolates linearly between the inter-channel samples
+struct Resampled<'a> {
+    samples: SampleIterator<'a, f32>,
+    channels: usize,
+    /// input samples per output sample
+    step: f64,
+    /// position of the next output sample after `current`
+    position: f64,
+    /// the last two input samples, `next` is `None` after the last one
+    current: Option<Vec<f32>>,
+    next: Option<Vec<f32>>,
+    output: Vec<f32>,
+    /// index of the next channel of `output` to return
+    next_output: usize,
+}
+
+impl<'a> Resampled<'a> {
+    fn read(&mut self) -> Option<Result<Vec<f32>>> {
+        let mut frame = Vec::with_capacity(self.channels);
+        otry!(read_frame(&mut self.samples, &mut frame, self.channels)?);
+        Some(Ok(frame))
+    }
+}
+
+impl<'a> AudioSamplesIterator<f32> for Resampled<'a> {
+    fn next(&mut self) -> Option<Result<f32>> {
+        if self.next_output == self.channels {
+            if self.current.is_none() {
+                self.current = Some(otry!(self.read()?));
+                self.next = match self.read() {
+                    Some(frame) => Some(otry!(frame)),
+                    None => None,
+                };
+            }
+            while self.position >= 1.0 {
+                let next = self.next.take()?;
+                self.current = Some(next);
+                self.next = match self.read() {
+                    Some(frame) => Some(otry!(frame)),
+                    None => None,
+                };
+                self.position -= 1.0;
+            }
+
+            // past the last input sample
+            if self.next.is_none() && self.position > 0.0 {
+                return None;
+            }
+            let current = self.current.as_ref().unwrap();
+            let next = self.next.as_ref().unwrap_or(current);
+            let position = self.position as f32;
+            for (channel, output) in self.output.iter_mut().enumerate() {
+                *output = current[channel] + (next[channel] - current[channel]) * position;
+            }
+            self.position += self.step;
+            self.next_output = 0;
+        }
+        self.next_output += 1;
+        Some(Ok(self.output[self.next_output - 1]))
+    }
+}
+
+/// Scales the samples, clipping at full scale
+struct Gained<'a> {
+    samples: SampleIterator<'a, f32>,
+    gain: f32,
+}
+
+impl<'a> AudioSamplesIterator<f32> for Gained<'a> {
+    fn next(&mut self) -> Option<Result<f32>> {
+        let sample = otry!(self.samples.next()?);
+        Some(Ok((sample * self.gain).clamp(-1.0, 1.0)))
+    }
+}
+
+/// Converts the samples to float output samples
+struct Converted<'a, S: Sample> {
+    samples: SampleIterator<'a, f32>,
+    phantom: std::marker::PhantomData<S>,
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for Converted<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        let sample = otry!(self.samples.next()?);
+        Some(S::from_f32(sample))
+    }
+}
+
+/// Requantizes the samples to integer output samples
+struct Quantized<'a, S: Sample> {
+    samples: SampleIterator<'a, f32>,
+    requantizer: Requantizer,
+    bits: u32,
+    channels: usize,
+    /// channel of the next sample
+    channel: usize,
+    phantom: std::marker::PhantomData<S>,
+}
+
+impl<'a, S: Sample> AudioSamplesIterator<S> for Quantized<'a, S> {
+    fn next(&mut self) -> Option<Result<S>> {
+        let sample = otry!(self.samples.next()?);
+        let scaled = (sample as f64 * 2_147_483_648.0)
+            .round()
+            .clamp(i32::MIN as f64, i32::MAX as f64) as i32;
+        let mut value = self.requantizer.requantize(scaled, self.channel);
+        self.channel = (self.channel + 1) % self.channels;
+        // 8 bit samples are unsigned
+        if std::mem::size_of::<S>() == 1 {
+            value += codecs::SampleFormat::U8.offset();
+        }
+        Some(S::from_i32(value, self.bits))
+    }
+}
+
+#[cfg(all(test, feature = "flac"))]
+fn stereo_segment(left: &[i32], right: &[i32]) -> AudioSegment {
+    use crate::codecs::FormatFlag;
+    use crate::testing::FlacStream;
+
+    let mut flac = FlacStream::new(8_000, 2, 16);
+    flac.frame(&[left.to_vec(), right.to_vec()]);
+    AudioSegment::from_reader(std::io::Cursor::new(flac.build()), FormatFlag::FLAC).unwrap()
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_pipeline_stages() {
+    let left: Vec<i32> = (0..16).map(|i| i * 1024).collect();
+    let right: Vec<i32> = (0..16).map(|i| -i * 512).collect();
+    let collect =
+        |samples: SampleIterator<f32>| -> Vec<f32> { samples.map(|s| s.unwrap()).collect() };
+
+    // stages are applied in their order, not in the order they are selected
+    let mut segment = stereo_segment(&left, &right);
+    let samples = segment
+        .process()
+        .gain_db(-20.0 * 2f32.log10())
+        .resample(16_000)
+        .downmix(ChannelLayout::Mono)
+        .trim(2, Some(4))
+        .build::<f32>()
+        .unwrap();
+    // the mono samples of frames 2 to 5 are 256 * frame, halved by the gain
+    let expected: Vec<f32> = [2.0, 2.5, 3.0, 3.5, 4.0, 4.5, 5.0]
+        .iter()
+        .map(|frame| frame * 256.0 / 2.0 / 32_768.0)
+        .collect();
+    let samples = collect(samples);
+    assert_eq!(samples.len(), expected.len());
+    for (a, b) in samples.iter().zip(&expected) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    // halving the sample rate keeps every other frame
+    let mut segment = stereo_segment(&left, &right);
+    let samples = collect(segment.process().resample(4_000).build::<f32>().unwrap());
+    assert_eq!(samples.len(), 16);
+    assert_eq!(samples[2], 2048.0 / 32_768.0);
+    assert_eq!(samples[3], -1024.0 / 32_768.0);
+
+    // mono input is copied to both sides
+    let matrix = downmix_matrix(&[Channels::FRONT_LEFT], ChannelLayout::Stereo).unwrap();
+    assert_eq!(matrix, vec![vec![1.0], vec![1.0]]);
+    // 5.1 is scaled to not clip, the LFE is dropped
+    let order = crate::audio::stream_channel_order(ChannelLayout::FivePointOne.into_channels());
+    let matrix = downmix_matrix(&order, ChannelLayout::Stereo).unwrap();
+    let sum: f32 = matrix[0].iter().sum();
+    assert!((sum - 1.0).abs() < 1e-6);
+    // the channels are FL, FR, FC, LFE, BL and BR
+    assert_eq!(matrix[0][1], 0.0);
+    assert_eq!(matrix[0][3], 0.0);
+    assert_eq!(matrix[0][5], 0.0);
+    assert_eq!(matrix[0][2], matrix[1][2]);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_pipeline_dc_block() {
+    // one second of a constant offset on the left and a 1 kHz sine on the right
+    let left = vec![8_192; 8_000];
+    let right: Vec<i32> = (0..8_000)
+        .map(|i| (16_384.0 * (i as f64 * 2.0 * std::f64::consts::PI / 8.0).sin()).round() as i32)
+        .collect();
+    let mut segment = stereo_segment(&left, &right);
+    let samples: Vec<f32> = segment
+        .process()
+        .dc_block()
+        .build::<f32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(samples.len(), 16_000);
+
+    let (filtered_left, filtered_right): (Vec<f32>, Vec<f32>) =
+        samples.chunks(2).map(|frame| (frame[0], frame[1])).unzip();
+    assert!(filtered_left[7_000..].iter().all(|s| s.abs() < 1e-6));
+
+    let rms = |samples: &[f32]| {
+        (samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+    };
+    let input: Vec<f32> = right[4_000..]
+        .iter()
+        .map(|s| *s as f32 / 32_768.0)
+        .collect();
+    let db = 20.0 * (rms(&filtered_right[4_000..]) / rms(&input)).log10();
+    assert!(db.abs() < 0.1, "the sine is changed by {} dB", db);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_pipeline_dither() {
+    let left: Vec<i32> = (0..16).map(|i| i * 1000 - 8_000).collect();
+    let right: Vec<i32> = (0..16).map(|i| i * 37).collect();
+
+    let mut segment = stereo_segment(&left, &right);
+    let samples: Vec<i16> = segment
+        .process()
+        .dither(16, Dither::None)
+        .build::<i16>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    let expected: Vec<i16> = (0..16)
+        .flat_map(|i| [left[i] as i16, right[i] as i16])
+        .collect();
+    assert_eq!(samples, expected);
+
+    let mut segment = stereo_segment(&left, &right);
+    let samples: Vec<i32> = segment
+        .process()
+        .dither(8, Dither::Tpdf)
+        .build::<i32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    for (sample, ideal) in samples.iter().zip(expected) {
+        assert!((*sample as f32 - ideal as f32 / 256.0).abs() <= 1.5);
+    }
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn test_pipeline_rejects_incompatible_stages() {
+    let build_error = |pipeline: fn(Pipeline) -> Result<SampleIterator<i16>>| {
+        let mut segment = stereo_segment(&[0; 16], &[0; 16]);
+        let msg = match pipeline(segment.process()) {
+            Err(errors::Error::Unsupported(msg)) => msg,
+            _ => panic!("expected the pipeline to be rejected"),
+        };
+        msg
+    };
+
+    assert!(build_error(|p| p.build()).contains("need a dither stage"));
+    assert!(build_error(|p| p.dither(24, Dither::None).build()).contains("too narrow"));
+    assert!(build_error(|p| p.dither(0, Dither::None).build()).contains("between 1 and 32"));
+    assert!(
+        build_error(|p| p.dither(16, Dither::None).resample(0).build()).contains("sample rate")
+    );
+    assert!(build_error(|p| p
+        .dither(16, Dither::None)
+        .downmix(ChannelLayout::Quad)
+        .build())
+    .contains("mono or stereo"));
+    assert!(
+        build_error(|p| p.dither(16, Dither::None).gain_db(f32::INFINITY).build())
+            .contains("finite")
+    );
+
+    let mut segment = stereo_segment(&[0; 16], &[0; 16]);
+    assert!(segment
+        .process()
+        .dither(16, Dither::None)
+        .build::<f32>()
+        .is_err());
+    // a rejected pipeline leaves the samples to be read
+    assert!(segment.samples::<f32>().is_ok());
+}
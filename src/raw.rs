@@ -0,0 +1,82 @@
+//! The `raw` module exposes the low-level building blocks of the decoders.
+//!
+//! These are meant for embedding the decoders behind an own demuxer, which
+//! feeds them frame by frame. Most users want `audio::AudioSegment` instead.
+//!
+//! All functions read from a `ReadBuffer`, which is implemented for every
+//! `std::io::Read`, so a byte slice can be decoded directly. FLAC frames are
+//! read from a `Rewind`, e.g. a `std::io::Cursor`, to return to a corrupt
+//! frame.
+//!
+//! Each submodule is only available if the feature of its format is enabled.
+
+pub use crate::io::{ReadBuffer, Rewind};
+
+/// FLAC frame decoding
+///
+/// # Example
+///
+/// Decoding a single frame from a byte slice:
+///
+/// ```
+/// use cauldron::audio::{AudioInfo, ChannelLayout};
+/// use cauldron::codecs::{CodecType, SampleFormat};
+/// use cauldron::raw::flac;
+///
+/// // a mono frame of 16 samples with the constant value 5
+/// let frame: &[u8] = &[
+///     0xff, 0xf8, 0x79, 0x08, 0x00, 0x00, 0x0f, 0x4c, 0x00, 0x00, 0x05, 0xb5, 0xb0,
+/// ];
+/// // the stream properties, normally read from STREAMINFO
+/// let info = AudioInfo {
+///     codec_type: CodecType::CODEC_TYPE_FLAC,
+///     sample_rate: 44_100,
+///     total_samples: 16,
+///     bits_per_sample: 16,
+///     container_bits: 0,
+///     sample_format: SampleFormat::S16,
+///     channels: ChannelLayout::Mono.into_channels(),
+///     channel_layout: ChannelLayout::Mono,
+///     flac_stream_info: None,
+///     encoder_delay: None,
+///     encoder_padding: None,
+/// };
+///
+/// let mut input = std::io::Cursor::new(frame);
+/// let block = flac::decode_next_frame(&mut input, Vec::new(), &info, flac::ALL_CHANNELS, None)
+///     .expect("a frame")
+///     .expect("a valid frame");
+/// assert_eq!(block.total_samples(), 16);
+/// assert_eq!(block.get_sample(0, 15), 5);
+///
+/// // the input is now positioned at the next frame
+/// assert_eq!(input.position(), frame.len() as u64);
+/// ```
+#[cfg(feature = "flac")]
+pub mod flac {
+    pub use crate::flac::frame::{
+        decode_next_frame, decode_next_frame_lenient, Block, ALL_CHANNELS,
+    };
+}
+
+/// MP3 frame decoding, and cutting streams into pieces of whole frames
+#[cfg(feature = "mp3")]
+pub mod mp3 {
+    pub use crate::mp3::frame::{decode_next_frame, Block, DecoderState};
+    pub use crate::mp3::split::split;
+}
+
+/// Ogg demuxing, of chained and multiplexed logical streams
+///
+/// No codec is attached to the packets yet.
+#[cfg(feature = "ogg")]
+pub mod ogg {
+    pub use crate::ogg::page::{read_page, Page};
+    pub use crate::ogg::{LogicalStream, OggDemuxer, OggEvent, OggPacket};
+}
+
+/// WAV chunk parsing
+#[cfg(feature = "wav")]
+pub mod wav {
+    pub use crate::wav::chunks::{read_next_chunk, Chunk, Label};
+}
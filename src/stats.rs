@@ -0,0 +1,498 @@
+//! The `stats` module defines the statistics collected while decoding.
+//!
+//! Collection is opt-in. `DecodeOptions::frame_stats` keeps the statistics of
+//! every FLAC frame, `DecodeOptions::decode_stats` only sums them up into a
+//! `DecodeStats`, for FLAC and MP3. `DecodeOptions::instrumentation` counts the
+//! throughput of decoding in an `Instrumentation`. `AudioSegment::verify`
+//! reports the checks of a decode without samples in a `VerifyReport`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The coding of a FLAC subframe
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubframeKind {
+    /// all samples have the same value
+    Constant,
+    /// samples are stored unencoded
+    Verbatim,
+    /// fixed polynomial prediction of the given order
+    Fixed(u8),
+    /// linear prediction of the given order
+    Lpc(u8),
+}
+
+pub use cauldron_flac_core::RicePartition;
+
+/// Statistics of a single decoded subframe
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubframeStats {
+    /// how the subframe is coded
+    pub kind: SubframeKind,
+
+    /// number of wasted bits per sample
+    pub wasted_bits: u32,
+
+    /// partition order of the residual, `None` for constant and verbatim subframes
+    pub partition_order: Option<u8>,
+
+    /// coding of each residual partition, in stream order
+    pub rice_partitions: Vec<RicePartition>,
+}
+
+impl SubframeStats {
+    #[cfg(feature = "flac")]
+    pub(crate) fn new(kind: SubframeKind, wasted_bits: u32) -> SubframeStats {
+        SubframeStats {
+            kind,
+            wasted_bits,
+            partition_order: None,
+            rice_partitions: Vec::new(),
+        }
+    }
+}
+
+/// Statistics of a single decoded frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// index of the first inter-channel sample of the frame
+    pub first_sample_index: u64,
+
+    /// number of inter-channel samples in the frame
+    pub block_size: u32,
+
+    /// one entry per subframe, in stream order
+    pub subframes: Vec<SubframeStats>,
+
+    /// bits per sample of each decorrelated channel without its wasted bits
+    pub effective_bits_per_sample: Vec<u32>,
+}
+
+/// A FLAC frame not starting at the sample following the previous frame
+///
+/// Field recorders may drop frames, which leaves a gap in the sample numbers of
+/// the frames.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SampleGap {
+    /// index of the inter-channel sample the frame should start with
+    pub expected: u64,
+
+    /// index of the inter-channel sample the frame starts with
+    pub found: u64,
+}
+
+impl SampleGap {
+    /// Returns the number of inter-channel samples missing
+    ///
+    /// This is 0 if the frame starts before the expected sample, e.g. if a
+    /// frame is repeated.
+    pub fn missing_samples(&self) -> u64 {
+        self.found.saturating_sub(self.expected)
+    }
+}
+
+/// Statistics aggregated over all decoded frames
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileStats {
+    /// number of decoded frames
+    pub frames: u64,
+
+    /// number of constant subframes
+    pub constant_subframes: u64,
+
+    /// number of verbatim subframes
+    pub verbatim_subframes: u64,
+
+    /// number of fixed prediction subframes
+    pub fixed_subframes: u64,
+
+    /// number of linear prediction subframes
+    pub lpc_subframes: u64,
+
+    /// number of subframes with at least one wasted bit
+    pub wasted_bits_subframes: u64,
+
+    /// highest residual partition order seen
+    pub max_partition_order: u8,
+
+    /// highest effective bits per sample of each channel in any frame
+    pub effective_bits_per_sample: Vec<u32>,
+}
+
+impl FileStats {
+    /// Aggregates the statistics of individual frames
+    pub fn from_frames(frames: &[FrameStats]) -> FileStats {
+        let mut stats = FileStats {
+            frames: frames.len() as u64,
+            ..Default::default()
+        };
+
+        for frame in frames {
+            let bits = &mut stats.effective_bits_per_sample;
+            if bits.len() < frame.effective_bits_per_sample.len() {
+                bits.resize(frame.effective_bits_per_sample.len(), 0);
+            }
+            for (max, b) in bits.iter_mut().zip(&frame.effective_bits_per_sample) {
+                *max = (*max).max(*b);
+            }
+        }
+
+        for subframe in frames.iter().flat_map(|f| &f.subframes) {
+            match subframe.kind {
+                SubframeKind::Constant => stats.constant_subframes += 1,
+                SubframeKind::Verbatim => stats.verbatim_subframes += 1,
+                SubframeKind::Fixed(_) => stats.fixed_subframes += 1,
+                SubframeKind::Lpc(_) => stats.lpc_subframes += 1,
+            }
+            if subframe.wasted_bits > 0 {
+                stats.wasted_bits_subframes += 1;
+            }
+            if let Some(order) = subframe.partition_order {
+                stats.max_partition_order = stats.max_partition_order.max(order);
+            }
+        }
+
+        stats
+    }
+}
+
+/// Histograms summed up over all decoded frames, e.g. to tune an encoder
+///
+/// Collected with `DecodeOptions::decode_stats`. The FLAC fields stay empty for
+/// MP3 and `bitrates` stays empty for FLAC. With the `serde` feature the stats
+/// can be serialized.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DecodeStats {
+    /// number of decoded frames
+    pub frames: u64,
+
+    /// number of frames of each block size, in inter-channel samples
+    pub block_sizes: BTreeMap<u32, u64>,
+
+    /// number of constant subframes
+    pub constant_subframes: u64,
+
+    /// number of verbatim subframes
+    pub verbatim_subframes: u64,
+
+    /// number of fixed prediction subframes of each order
+    pub fixed_orders: BTreeMap<u8, u64>,
+
+    /// number of linear prediction subframes of each order
+    pub lpc_orders: BTreeMap<u8, u64>,
+
+    /// number of residual partitions of each rice parameter
+    pub rice_parameters: BTreeMap<u32, u64>,
+
+    /// number of residual partitions stored unencoded
+    pub escaped_partitions: u64,
+
+    /// sum of the partition orders of all predicted subframes
+    pub partition_order_sum: u64,
+
+    /// number of predicted subframes, i.e. those with a residual
+    pub predicted_subframes: u64,
+
+    /// number of MP3 frames of each bitrate, in bits per second
+    pub bitrates: BTreeMap<u32, u64>,
+}
+
+impl DecodeStats {
+    /// Returns the mean partition order of the predicted subframes
+    pub fn average_partition_order(&self) -> Option<f64> {
+        if self.predicted_subframes == 0 {
+            return None;
+        }
+        Some(self.partition_order_sum as f64 / self.predicted_subframes as f64)
+    }
+
+    #[cfg(feature = "flac")]
+    pub(crate) fn add_flac_frame(&mut self, frame: &FrameStats) {
+        self.frames += 1;
+        *self.block_sizes.entry(frame.block_size).or_insert(0) += 1;
+
+        for subframe in &frame.subframes {
+            match subframe.kind {
+                SubframeKind::Constant => self.constant_subframes += 1,
+                SubframeKind::Verbatim => self.verbatim_subframes += 1,
+                SubframeKind::Fixed(order) => *self.fixed_orders.entry(order).or_insert(0) += 1,
+                SubframeKind::Lpc(order) => *self.lpc_orders.entry(order).or_insert(0) += 1,
+            }
+            if let Some(order) = subframe.partition_order {
+                self.partition_order_sum += order as u64;
+                self.predicted_subframes += 1;
+            }
+            for partition in &subframe.rice_partitions {
+                match partition {
+                    RicePartition::Rice(param) => {
+                        *self.rice_parameters.entry(*param).or_insert(0) += 1
+                    }
+                    RicePartition::Escaped(_) => self.escaped_partitions += 1,
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "mp3")]
+    pub(crate) fn add_mp3_frame(&mut self, block_size: u32, bitrate: u32) {
+        self.frames += 1;
+        *self.block_sizes.entry(block_size).or_insert(0) += 1;
+        *self.bitrates.entry(bitrate).or_insert(0) += 1;
+    }
+}
+
+/// Writes `histogram` as `key: count` pairs on one line
+fn write_histogram<K: fmt::Display>(
+    f: &mut fmt::Formatter,
+    name: &str,
+    histogram: &BTreeMap<K, u64>,
+) -> fmt::Result {
+    if histogram.is_empty() {
+        return Ok(());
+    }
+    write!(f, "| {:<23}", format!("{}:", name))?;
+    for (i, (key, count)) in histogram.iter().enumerate() {
+        let separator = if i == 0 { "" } else { ", " };
+        write!(f, "{}{}: {}", separator, key, count)?;
+    }
+    writeln!(f)
+}
+
+impl fmt::Display for DecodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "| Frames:                {}", self.frames)?;
+        write_histogram(f, "Block Sizes", &self.block_sizes)?;
+        write_histogram(f, "Bitrates", &self.bitrates)?;
+        if self.constant_subframes + self.verbatim_subframes + self.predicted_subframes > 0 {
+            writeln!(f, "| Constant Subframes:    {}", self.constant_subframes)?;
+            writeln!(f, "| Verbatim Subframes:    {}", self.verbatim_subframes)?;
+        }
+        write_histogram(f, "Fixed Orders", &self.fixed_orders)?;
+        write_histogram(f, "LPC Orders", &self.lpc_orders)?;
+        write_histogram(f, "Rice Parameters", &self.rice_parameters)?;
+        if self.escaped_partitions > 0 {
+            writeln!(f, "| Escaped Partitions:    {}", self.escaped_partitions)?;
+        }
+        if let Some(order) = self.average_partition_order() {
+            writeln!(f, "| Avg Partition Order:   {:.2}", order)?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of `AudioSegment::verify`
+///
+/// Decoding goes on after a corrupt FLAC frame, at the next frame header, so
+/// that all damage of a file is counted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VerifyReport {
+    /// number of frames checked, for WAV the inter-channel samples of the data chunk
+    pub frames: u64,
+
+    /// number of inter-channel samples decoded
+    pub samples: u64,
+
+    /// number of frames failing the CRC-8 of their header or their CRC-16, FLAC only
+    pub crc_failures: u64,
+
+    /// number of frames which are malformed otherwise
+    pub malformed_frames: u64,
+
+    /// number of MP3 frames changing the sample rate or channels, and of FLAC
+    /// frames disagreeing with STREAMINFO
+    pub inconsistent_frames: u64,
+
+    /// true if the input ends within a frame, or before the end of a WAV data chunk
+    pub truncated: bool,
+
+    /// true if the MD5 signature of STREAMINFO matches the decoded samples, FLAC only
+    ///
+    /// `None` if the encoder left the signature at zero. Chained streams only
+    /// match if each of them matches its own signature.
+    pub md5_matched: Option<bool>,
+}
+
+impl VerifyReport {
+    /// Returns true if no check failed
+    pub fn is_ok(&self) -> bool {
+        self.crc_failures == 0
+            && self.malformed_frames == 0
+            && self.inconsistent_frames == 0
+            && !self.truncated
+            && self.md5_matched != Some(false)
+    }
+}
+
+/// A parameter of a FLAC frame outside of the streamable subset
+///
+/// Hardware players often only decode the subset, see
+/// https://xiph.org/flac/format.html#subset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SubsetParameter {
+    /// the block size is above 16384, or above 4608 at up to 48 kHz
+    BlockSize(u32),
+
+    /// the LPC order of a subframe is above 12 at up to 48 kHz
+    LpcOrder { subframe: usize, order: u8 },
+
+    /// the rice partition order of a subframe is above 8
+    PartitionOrder { subframe: usize, order: u8 },
+
+    /// the frame header leaves the sample rate to STREAMINFO
+    SampleRateFromStreamInfo,
+
+    /// the frame header leaves the bits per sample to STREAMINFO
+    BitsPerSampleFromStreamInfo,
+}
+
+impl fmt::Display for SubsetParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubsetParameter::BlockSize(size) => write!(f, "block size {}", size),
+            SubsetParameter::LpcOrder { subframe, order } => {
+                write!(f, "LPC order {} in subframe {}", order, subframe)
+            }
+            SubsetParameter::PartitionOrder { subframe, order } => {
+                write!(f, "partition order {} in subframe {}", order, subframe)
+            }
+            SubsetParameter::SampleRateFromStreamInfo => write!(f, "sample rate from STREAMINFO"),
+            SubsetParameter::BitsPerSampleFromStreamInfo => {
+                write!(f, "bits per sample from STREAMINFO")
+            }
+        }
+    }
+}
+
+/// A subset violation found by `AudioSegment::check_subset`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SubsetViolation {
+    /// index of the frame in the stream, counting from 0
+    pub frame: u64,
+
+    /// the sample rate of the frame, which some limits depend on
+    pub sample_rate: u32,
+
+    pub parameter: SubsetParameter,
+}
+
+impl fmt::Display for SubsetViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frame {}: {}", self.frame, self.parameter)
+    }
+}
+
+/// The throughput of decoding, collected with `DecodeOptions::instrumentation`
+///
+/// The counters are updated once per decoded block, a frame of FLAC and MP3
+/// and 4096 samples of WAV. PCM samples are read one at a time, so the time
+/// of a WAV block runs from its first to its last sample, including what the
+/// caller does in between. Decoding the audio again, e.g. by `looped`, adds
+/// to the counters.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Instrumentation {
+    /// number of blocks decoded
+    pub blocks: u64,
+
+    /// number of inter-channel samples decoded
+    pub frames: u64,
+
+    /// number of bytes of the input decoded
+    pub bytes: u64,
+
+    /// wall time spent decoding the blocks
+    pub decode_time: Duration,
+
+    /// sample rate of the decoded frames, 0 if it is not known yet
+    pub sample_rate: u32,
+}
+
+impl Instrumentation {
+    /// Returns the playing time of the decoded frames
+    pub fn audio_duration(&self) -> Duration {
+        crate::io::frames_duration(self.frames, self.sample_rate)
+    }
+
+    /// Returns the decode time relative to the playing time, below 1 is faster than real time
+    ///
+    /// `None` before any frames of a known sample rate are decoded.
+    pub fn real_time_factor(&self) -> Option<f64> {
+        let audio = self.audio_duration().as_secs_f64();
+        if audio == 0.0 {
+            return None;
+        }
+        Some(self.decode_time.as_secs_f64() / audio)
+    }
+}
+
+impl fmt::Display for Instrumentation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} frames in {} blocks, {} bytes, decoded in {:.3} ms",
+            self.frames,
+            self.blocks,
+            self.bytes,
+            self.decode_time.as_secs_f64() * 1000.0
+        )?;
+        match self.real_time_factor() {
+            Some(factor) if factor > 0.0 => {
+                write!(f, ", real-time factor {:.4} ({:.0}x)", factor, 1.0 / factor)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The counters behind an `InstrumentationHandle`
+#[derive(Debug, Default)]
+struct Counters {
+    blocks: AtomicU64,
+    frames: AtomicU64,
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+    sample_rate: AtomicU32,
+}
+
+/// Reads the `Instrumentation` of a segment at any time, e.g. while its samples are iterated
+///
+/// The handle is cheap to clone and can be sent to another thread, e.g. one
+/// logging the throughput periodically.
+#[derive(Clone, Debug, Default)]
+pub struct InstrumentationHandle(Arc<Counters>);
+
+impl InstrumentationHandle {
+    /// Returns the counters as they are now
+    pub fn get(&self) -> Instrumentation {
+        let counters = &self.0;
+        Instrumentation {
+            blocks: counters.blocks.load(Ordering::Relaxed),
+            frames: counters.frames.load(Ordering::Relaxed),
+            bytes: counters.bytes.load(Ordering::Relaxed),
+            decode_time: Duration::from_nanos(counters.nanos.load(Ordering::Relaxed)),
+            sample_rate: counters.sample_rate.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counts a block of `frames` inter-channel samples taking `bytes` of the input
+    pub(crate) fn record(&self, frames: u64, bytes: u64, elapsed: Duration) {
+        let counters = &self.0;
+        counters.blocks.fetch_add(1, Ordering::Relaxed);
+        counters.frames.fetch_add(frames, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+        counters
+            .nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_sample_rate(&self, sample_rate: u32) {
+        self.0.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,305 @@
+//! Phase correlation and balance of the left and right channel, e.g. for
+//! checking a master before it is released.
+//!
+//! The statistics are accumulated one inter-channel sample at a time after
+//! Welford, so that long files and signals with an offset lose no precision
+//! to sums growing large.
+
+use std::time::Duration;
+
+use crate::audio::{AudioSegment, Channels, SampleIterator};
+use crate::errors;
+use crate::Result;
+
+/// number of inter-channel samples read at once
+const CHUNK_FRAMES: usize = 4096;
+
+/// audio whose correlation is lower than this cancels out in part when mixed to mono
+pub const MONO_COMPATIBLE_CORRELATION: f64 = 0.0;
+
+/// The relation of the left to the right channel, see `AudioSegment::stereo_analysis`
+#[derive(Clone, Debug, PartialEq)]
+pub struct StereoStats {
+    /// correlation coefficient of the channels, 1 for the same signal on
+    /// both, -1 for one inverted, 0 for unrelated ones or a silent channel
+    pub correlation: f64,
+
+    /// RMS of the left channel relative to the right one in dB, positive if
+    /// the left channel is louder
+    pub balance_db: f64,
+
+    /// largest difference of the left and the right sample, relative to full scale
+    pub max_difference: f64,
+
+    /// false if the whole audio or any of the windows correlates below
+    /// `MONO_COMPATIBLE_CORRELATION`
+    pub mono_compatible: bool,
+
+    /// statistics of each window, if a window length was given
+    pub windows: Vec<WindowStats>,
+}
+
+/// The statistics of one window of `StereoStats`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowStats {
+    /// index of the first inter-channel sample of the window
+    pub start: u64,
+    pub correlation: f64,
+    pub balance_db: f64,
+}
+
+/// Compares the left and the right channel of the interleaved `samples` of the channels `channels`
+///
+/// The front left and right channel are compared, or the first two channels
+/// if there are no such ones. With a `window` its statistics are kept for
+/// every part of that length too, the last one may be shorter. Fails with
+/// `Error::Unsupported` for mono audio.
+pub fn analyze(
+    mut samples: SampleIterator<'_, f64>,
+    channels: &[Channels],
+    sample_rate: u32,
+    window: Option<Duration>,
+) -> Result<StereoStats> {
+    if channels.len() < 2 {
+        return errors::unsupported_error("stereo analysis needs two channels, the audio is mono");
+    }
+    let find = |channel: Channels| channels.iter().position(|&c| c == channel);
+    let (left, right) = match (find(Channels::FRONT_LEFT), find(Channels::FRONT_RIGHT)) {
+        (Some(left), Some(right)) => (left, right),
+        _ => (0, 1),
+    };
+    let window_frames = match window {
+        Some(_) if sample_rate == 0 => {
+            return errors::unsupported_error("the sample rate of the stream is unknown")
+        }
+        Some(window) => {
+            let frames = (window.as_secs_f64() * sample_rate as f64).round() as u64;
+            if frames == 0 {
+                return errors::unsupported_error("the window is shorter than a sample");
+            }
+            Some(frames)
+        }
+        None => None,
+    };
+
+    let mut total = Accumulator::default();
+    let mut current = Accumulator::default();
+    let mut windows = Vec::new();
+    let mut max_difference = 0.0f64;
+    let mut position = 0u64;
+    let mut buffers = vec![vec![0.0f64; CHUNK_FRAMES]; channels.len()];
+    loop {
+        let read = {
+            let mut out: Vec<&mut [f64]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+            samples.read_planar(&mut out)?
+        };
+        for (&l, &r) in buffers[left][..read].iter().zip(&buffers[right][..read]) {
+            total.push(l, r);
+            max_difference = max_difference.max((l - r).abs());
+            if let Some(frames) = window_frames {
+                current.push(l, r);
+                if current.n == frames {
+                    windows.push(current.window(position + 1 - frames));
+                    current = Accumulator::default();
+                }
+            }
+            position += 1;
+        }
+        if read < CHUNK_FRAMES {
+            break;
+        }
+    }
+    if current.n > 0 {
+        windows.push(current.window(position - current.n));
+    }
+
+    let correlation = total.correlation();
+    let lowest = windows
+        .iter()
+        .map(|w| w.correlation)
+        .fold(correlation, f64::min);
+    Ok(StereoStats {
+        correlation,
+        balance_db: total.balance_db(),
+        max_difference,
+        mono_compatible: lowest >= MONO_COMPATIBLE_CORRELATION,
+        windows,
+    })
+}
+
+/// `AudioSegment::stereo_analysis`
+pub(crate) fn segment_analysis(
+    segment: &mut AudioSegment,
+    window: Option<Duration>,
+) -> Result<StereoStats> {
+    let channels = segment.channel_order();
+    let sample_rate = segment.info().sample_rate;
+    // before the stream is consumed
+    if channels.len() < 2 {
+        return errors::unsupported_error("stereo analysis needs two channels, the audio is mono");
+    }
+    analyze(segment.samples::<f64>()?, &channels, sample_rate, window)
+}
+
+/// Running means, squared deviations and co-moment of two channels
+#[derive(Clone, Copy, Default)]
+struct Accumulator {
+    n: u64,
+    mean: [f64; 2],
+    /// sums of the squared deviations from the means
+    deviations: [f64; 2],
+    /// sum of the products of the deviations of both channels
+    co_moment: f64,
+    /// means of the squared samples
+    mean_square: [f64; 2],
+}
+
+impl Accumulator {
+    fn push(&mut self, left: f64, right: f64) {
+        self.n += 1;
+        let n = self.n as f64;
+        let deltas = [left - self.mean[0], right - self.mean[1]];
+        self.mean[0] += deltas[0] / n;
+        self.mean[1] += deltas[1] / n;
+        self.deviations[0] += deltas[0] * (left - self.mean[0]);
+        self.deviations[1] += deltas[1] * (right - self.mean[1]);
+        self.co_moment += deltas[0] * (right - self.mean[1]);
+        self.mean_square[0] += (left * left - self.mean_square[0]) / n;
+        self.mean_square[1] += (right * right - self.mean_square[1]) / n;
+    }
+
+    fn correlation(&self) -> f64 {
+        let deviations = self.deviations[0] * self.deviations[1];
+        if deviations <= 0.0 {
+            return 0.0;
+        }
+        (self.co_moment / deviations.sqrt()).clamp(-1.0, 1.0)
+    }
+
+    /// Returns the balance, infinite if only one channel is silent and 0 if both are
+    fn balance_db(&self) -> f64 {
+        match (self.mean_square[0] > 0.0, self.mean_square[1] > 0.0) {
+            (false, false) => 0.0,
+            _ => 10.0 * (self.mean_square[0] / self.mean_square[1]).log10(),
+        }
+    }
+
+    fn window(&self, start: u64) -> WindowStats {
+        WindowStats {
+            start,
+            correlation: self.correlation(),
+            balance_db: self.balance_db(),
+        }
+    }
+}
+
+#[cfg(test)]
+fn analyze_pairs(pairs: &[(f64, f64)], window: Option<Duration>) -> Result<StereoStats> {
+    let samples: Vec<f64> = pairs.iter().flat_map(|&(l, r)| vec![l, r]).collect();
+    let samples: SampleIterator<'static, f64> = Box::new(crate::testing::VecSamples::new(samples));
+    let stereo = [Channels::FRONT_LEFT, Channels::FRONT_RIGHT];
+    analyze(samples, &stereo, 1_000, window)
+}
+
+#[cfg(test)]
+fn sine(frames: usize, amplitude: f64) -> impl Iterator<Item = f64> {
+    (0..frames).map(move |i| amplitude * (i as f64 * 0.05).sin())
+}
+
+#[test]
+fn test_in_and_out_of_phase() {
+    let in_phase: Vec<(f64, f64)> = sine(10_000, 0.5).map(|s| (s, s)).collect();
+    let stats = analyze_pairs(&in_phase, None).unwrap();
+    assert!((stats.correlation - 1.0).abs() < 1e-12);
+    assert!(stats.balance_db.abs() < 1e-9);
+    assert_eq!(stats.max_difference, 0.0);
+    assert!(stats.mono_compatible);
+    assert!(stats.windows.is_empty());
+
+    let out_of_phase: Vec<(f64, f64)> = sine(10_000, 0.5).map(|s| (s, -s)).collect();
+    let stats = analyze_pairs(&out_of_phase, None).unwrap();
+    assert!((stats.correlation + 1.0).abs() < 1e-12);
+    assert!((stats.max_difference - 1.0).abs() < 1e-6);
+    assert!(!stats.mono_compatible);
+
+    // the right channel at half the level, and a silent right channel
+    let panned: Vec<(f64, f64)> = sine(10_000, 0.5).map(|s| (s, s / 2.0)).collect();
+    let stats = analyze_pairs(&panned, None).unwrap();
+    assert!((stats.balance_db - 20.0 * 2f64.log10()).abs() < 1e-9);
+    assert!((stats.correlation - 1.0).abs() < 1e-12);
+    let hard_left: Vec<(f64, f64)> = sine(1_000, 0.5).map(|s| (s, 0.0)).collect();
+    let stats = analyze_pairs(&hard_left, None).unwrap();
+    assert_eq!(stats.correlation, 0.0);
+    assert_eq!(stats.balance_db, f64::INFINITY);
+    assert!(stats.mono_compatible);
+}
+
+#[test]
+fn test_precision_with_offset() {
+    // a tiny signal on a large offset, the offset is no part of the correlation
+    let pairs: Vec<(f64, f64)> = sine(100_000, 1e-6).map(|s| (0.75 + s, 0.75 - s)).collect();
+    let stats = analyze_pairs(&pairs, None).unwrap();
+    assert!(
+        (stats.correlation + 1.0).abs() < 1e-6,
+        "{}",
+        stats.correlation
+    );
+}
+
+#[test]
+fn test_windows() {
+    // in phase for 1.5 s, then out of phase for 1 s
+    let mut pairs: Vec<(f64, f64)> = sine(1_500, 0.5).map(|s| (s, s)).collect();
+    pairs.extend(sine(1_000, 0.5).map(|s| (s, -s)));
+    let stats = analyze_pairs(&pairs, Some(Duration::from_millis(500))).unwrap();
+    let starts: Vec<u64> = stats.windows.iter().map(|w| w.start).collect();
+    assert_eq!(starts, vec![0, 500, 1_000, 1_500, 2_000]);
+    let correlations: Vec<f64> = stats
+        .windows
+        .iter()
+        .map(|w| w.correlation.round())
+        .collect();
+    assert_eq!(correlations, vec![1.0, 1.0, 1.0, -1.0, -1.0]);
+    assert!(stats.correlation > 0.0);
+    assert!(!stats.mono_compatible);
+
+    // a short last window
+    let stats = analyze_pairs(&pairs[..1_200], Some(Duration::from_millis(500))).unwrap();
+    assert_eq!(stats.windows.len(), 3);
+    assert_eq!(stats.windows[2].start, 1_000);
+    assert!(analyze_pairs(&pairs, Some(Duration::from_micros(10))).is_err());
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn test_segment_analysis() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let segment = |channels: u16, samples: &[i16]| {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let file = wav_file(&[
+            riff_chunk(b"fmt ", &pcm16_fmt(channels, 8_000)),
+            riff_chunk(b"data", &data),
+        ]);
+        AudioSegment::from_seekable_reader(
+            std::io::Cursor::new(file),
+            crate::codecs::FormatFlag::WAV,
+        )
+        .unwrap()
+    };
+
+    let samples: Vec<i16> = sine(4_000, 16_000.0)
+        .flat_map(|s| vec![s as i16, -(s as i16)])
+        .collect();
+    let stats = segment(2, &samples)
+        .stereo_analysis(Some(Duration::from_millis(100)))
+        .unwrap();
+    assert!((stats.correlation + 1.0).abs() < 1e-9);
+    assert_eq!(stats.windows.len(), 5);
+    assert!(!stats.mono_compatible);
+
+    match segment(1, &[1, 2, 3]).stereo_analysis(None) {
+        Err(errors::Error::Unsupported(msg)) => assert!(msg.contains("mono")),
+        other => panic!("expected mono audio to be rejected, got {:?}", other),
+    }
+}
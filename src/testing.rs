@@ -0,0 +1,674 @@
+//! Helpers for the unit tests to build audio streams in memory.
+#![allow(dead_code)]
+
+use std::io;
+
+#[cfg(feature = "flac")]
+use crate::crc::{crc16_flac, crc8_flac};
+
+/// A reader which hands out its data in chunks of pseudo random size between
+/// 1 and 7 bytes, to shake out assumptions about the size of reads. Now and
+/// then a read is interrupted instead.
+pub struct ChunkedReader {
+    data: Vec<u8>,
+    pos: usize,
+    /// state of the linear congruential generator picking chunk sizes
+    seed: u32,
+}
+
+impl ChunkedReader {
+    pub fn new(data: Vec<u8>, seed: u32) -> ChunkedReader {
+        ChunkedReader { data, pos: 0, seed }
+    }
+}
+
+impl io::Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.seed = self.seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        if (self.seed >> 16) % 13 == 5 {
+            return Err(io::Error::from(io::ErrorKind::Interrupted));
+        }
+        let chunk = 1 + (self.seed >> 16) as usize % 7;
+        let n = chunk.min(buf.len()).min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A samples iterator returning the samples of a vector
+pub struct VecSamples<S> {
+    samples: std::vec::IntoIter<S>,
+}
+
+impl<S> VecSamples<S> {
+    pub fn new(samples: Vec<S>) -> VecSamples<S> {
+        VecSamples {
+            samples: samples.into_iter(),
+        }
+    }
+}
+
+impl<S: crate::io::Sample> crate::io::AudioSamplesIterator<S> for VecSamples<S> {
+    fn next(&mut self) -> Option<crate::Result<S>> {
+        self.samples.next().map(Ok)
+    }
+}
+
+/// Writes values most significant bit first.
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    /// number of bits used of the last byte, 0 if aligned
+    used: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        Default::default()
+    }
+
+    /// Writes the `bits` least significant bits of `value`.
+    pub fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Writes `n` zeros followed by a one.
+    pub fn write_unary(&mut self, n: u32) {
+        for _ in 0..n {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    /// Writes a signed value as two's complement in `bits` bits.
+    pub fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & mask(bits), bits);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write(*b as u64, 8);
+        }
+    }
+
+    /// Pads with zeros up to the next byte boundary.
+    pub fn align(&mut self) {
+        self.used = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    if bits == 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Writes `value` with the utf-8 like coding used for FLAC frame and sample numbers.
+#[cfg(feature = "flac")]
+fn write_utf8_coded_int(bw: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        bw.write(value, 8);
+        return;
+    }
+    // number of 6 bit continuation bytes needed
+    let mut extra = 1;
+    while value >> (6 * extra) >= 1 << (6 - extra) {
+        extra += 1;
+    }
+    let lead = (0xff00u32 >> (extra + 1)) as u64 & 0xff;
+    bw.write(lead | (value >> (6 * extra)), 8);
+    for i in (0..extra).rev() {
+        bw.write(0b1000_0000 | ((value >> (6 * i)) & 0b0011_1111), 8);
+    }
+}
+
+/// A subframe to encode into a test frame.
+#[cfg(feature = "flac")]
+pub enum Subframe {
+    /// every sample has the same value
+    Constant(i32),
+    /// samples are stored as-is
+    Verbatim(Vec<i32>),
+    /// samples are predicted with the fixed polynomial of `order`, the residual
+    /// is rice coded with `rice_param` in 2^`partition_order` partitions
+    Fixed {
+        order: u8,
+        partition_order: u8,
+        rice_param: u32,
+        samples: Vec<i32>,
+    },
+    /// samples are predicted with the quantized `coefficients`, the first one
+    /// applying to the previous sample, each of `precision` bits and shifted
+    /// right by `shift`; the residual is coded like the one of `Fixed`
+    Lpc {
+        coefficients: Vec<i32>,
+        precision: u32,
+        shift: u32,
+        partition_order: u8,
+        rice_param: u32,
+        samples: Vec<i32>,
+    },
+}
+
+/// Computes the residual of the fixed polynomial predictor of `order`.
+#[cfg(feature = "flac")]
+fn fixed_residual(order: u8, samples: &[i32]) -> Vec<i64> {
+    let coefficients: &[i64] = match order {
+        0 => &[],
+        1 => &[1],
+        2 => &[2, -1],
+        3 => &[3, -3, 1],
+        _ => &[4, -6, 4, -1],
+    };
+    (order as usize..samples.len())
+        .map(|i| {
+            let prediction: i64 = coefficients
+                .iter()
+                .enumerate()
+                .map(|(j, c)| c * samples[i - j - 1] as i64)
+                .sum();
+            samples[i] as i64 - prediction
+        })
+        .collect()
+}
+
+/// Computes the residual of the linear predictor with the quantized `coefficients`.
+#[cfg(feature = "flac")]
+fn lpc_residual(coefficients: &[i32], shift: u32, samples: &[i32]) -> Vec<i64> {
+    (coefficients.len()..samples.len())
+        .map(|i| {
+            let prediction: i64 = coefficients
+                .iter()
+                .enumerate()
+                .map(|(j, c)| *c as i64 * samples[i - j - 1] as i64)
+                .sum();
+            samples[i] as i64 - (prediction >> shift)
+        })
+        .collect()
+}
+
+/// Finds the quantized linear predictor of `order` for `samples`, and its shift.
+///
+/// The coefficients come from the autocorrelation by the Levinson-Durbin
+/// recursion, each is quantized to a signed integer of `precision` bits.
+#[cfg(feature = "flac")]
+pub fn lpc_coefficients(samples: &[i32], order: usize, precision: u32) -> (Vec<i32>, u32) {
+    let autocorrelation: Vec<f64> = (0..=order)
+        .map(|lag| {
+            (lag..samples.len())
+                .map(|i| samples[i] as f64 * samples[i - lag] as f64)
+                .sum()
+        })
+        .collect();
+
+    let mut lpc = vec![0.0f64; order];
+    let mut error = autocorrelation[0];
+    for i in 0..order {
+        if error <= 0.0 {
+            break;
+        }
+        let mut k = autocorrelation[i + 1];
+        for j in 0..i {
+            k -= lpc[j] * autocorrelation[i - j];
+        }
+        k /= error;
+        let previous = lpc.clone();
+        lpc[i] = k;
+        for j in 0..i {
+            lpc[j] = previous[j] - k * previous[i - 1 - j];
+        }
+        error *= 1.0 - k * k;
+    }
+
+    let max = lpc.iter().fold(0.0f64, |max, c| max.max(c.abs()));
+    let limit = (1i32 << (precision - 1)) - 1;
+    let shift = if max > 0.0 {
+        (precision as i32 - 2 - max.log2().floor() as i32).clamp(0, 15) as u32
+    } else {
+        0
+    };
+    let coefficients = lpc
+        .iter()
+        .map(|c| ((c * (1u32 << shift) as f64).round() as i32).clamp(-limit, limit))
+        .collect();
+    (coefficients, shift)
+}
+
+/// Returns a rice parameter fitting the size of `residual`.
+#[cfg(feature = "flac")]
+pub fn rice_param(residual: &[i64]) -> u32 {
+    let mean =
+        residual.iter().map(|r| r.unsigned_abs()).sum::<u64>() / residual.len().max(1) as u64;
+    (64 - mean.leading_zeros()).min(14)
+}
+
+/// Writes `residual` rice coded with a 4 bit parameter in 2^`partition_order` partitions.
+#[cfg(feature = "flac")]
+fn write_residual(
+    bw: &mut BitWriter,
+    block_size: usize,
+    order: usize,
+    partition_order: u8,
+    rice_param: u32,
+    residual: &[i64],
+) {
+    bw.write(0b00, 2);
+    bw.write(partition_order as u64, 4);
+    let per_partition = block_size >> partition_order;
+    let mut start = 0;
+    for p in 0..1 << partition_order {
+        bw.write(rice_param as u64, 4);
+        // a partition order too large for the block writes a truncated residual
+        let len = if p == 0 {
+            per_partition.saturating_sub(order)
+        } else {
+            per_partition
+        };
+        let end = (start + len).min(residual.len());
+        for r in &residual[start.min(end)..end] {
+            let zigzag = ((r << 1) ^ (r >> 63)) as u64;
+            bw.write_unary((zigzag >> rice_param) as u32);
+            bw.write(zigzag, rice_param);
+        }
+        start += len;
+    }
+}
+
+/// Describes a FLAC frame to craft, fields can be tweaked to build broken frames.
+#[cfg(feature = "flac")]
+pub struct FrameSpec {
+    /// block size of the frame
+    pub block_size: u16,
+    /// frame number for fixed blocking, sample number for variable blocking
+    pub number: u64,
+    /// whether the variable blocking strategy bit is set
+    pub variable: bool,
+    /// the 4 bit channel assignment
+    pub channel_assignment: u8,
+    /// bits per sample of the frame, coded in the header if it has a code
+    pub bits_per_sample: u32,
+    /// 4 bit sample rate code, 0 means "from STREAMINFO"
+    pub sample_rate_code: u8,
+    /// bits per sample of each subframe, defaults to `bits_per_sample` if empty
+    pub subframe_bps: Vec<u32>,
+    /// wasted bits of each subframe, defaults to 0 if empty, the samples must
+    /// have that many trailing zero bits
+    pub wasted_bits: Vec<u32>,
+    pub subframes: Vec<Subframe>,
+}
+
+#[cfg(feature = "flac")]
+impl FrameSpec {
+    /// A frame with independent channels holding the samples verbatim.
+    pub fn verbatim(number: u64, bits_per_sample: u32, channels: &[Vec<i32>]) -> FrameSpec {
+        FrameSpec {
+            block_size: channels[0].len() as u16,
+            number,
+            variable: false,
+            channel_assignment: channels.len() as u8 - 1,
+            bits_per_sample,
+            sample_rate_code: 0,
+            subframe_bps: Vec::new(),
+            wasted_bits: Vec::new(),
+            subframes: channels
+                .iter()
+                .map(|c| Subframe::Verbatim(c.clone()))
+                .collect(),
+        }
+    }
+
+    /// A frame with independent channels predicted by linear predictors of `order`.
+    ///
+    /// Each channel gets its own predictor and rice parameter, fitted to its samples.
+    pub fn lpc(
+        number: u64,
+        bits_per_sample: u32,
+        order: usize,
+        channels: &[Vec<i32>],
+    ) -> FrameSpec {
+        let mut spec = FrameSpec::verbatim(number, bits_per_sample, channels);
+        spec.subframes = channels
+            .iter()
+            .map(|samples| {
+                let (coefficients, shift) = lpc_coefficients(samples, order, 12);
+                let residual = lpc_residual(&coefficients, shift, samples);
+                Subframe::Lpc {
+                    rice_param: rice_param(&residual),
+                    coefficients,
+                    precision: 12,
+                    shift,
+                    partition_order: 0,
+                    samples: samples.clone(),
+                }
+            })
+            .collect();
+        spec
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_with_header(|_| {})
+    }
+
+    /// Encodes the frame, with the header before its CRC-8 changed by `edit`.
+    pub fn encode_with_header<F: FnOnce(&mut Vec<u8>)>(&self, edit: F) -> Vec<u8> {
+        let mut bw = BitWriter::new();
+        bw.write(0x7ffc, 15);
+        bw.write(self.variable as u64, 1);
+        // block size is always stored as 16 bit at the end of the header
+        bw.write(0b0111, 4);
+        bw.write(self.sample_rate_code as u64, 4);
+        bw.write(self.channel_assignment as u64, 4);
+        let bps_code = match self.bits_per_sample {
+            8 => 0b001,
+            12 => 0b010,
+            16 => 0b100,
+            20 => 0b101,
+            24 => 0b110,
+            _ => 0b000,
+        };
+        bw.write(bps_code, 3);
+        bw.write(0, 1);
+        write_utf8_coded_int(&mut bw, self.number);
+        bw.write(self.block_size as u64 - 1, 16);
+        let mut header = bw.into_bytes();
+        edit(&mut header);
+
+        let mut bw = BitWriter::new();
+        bw.write_bytes(&header);
+        bw.write(crc8_flac(&header) as u64, 8);
+
+        for (i, subframe) in self.subframes.iter().enumerate() {
+            let wasted = *self.wasted_bits.get(i).unwrap_or(&0);
+            let bps = *self.subframe_bps.get(i).unwrap_or(&self.bits_per_sample) - wasted;
+            let type_code = match subframe {
+                Subframe::Constant(_) => 0,
+                Subframe::Verbatim(_) => 1,
+                Subframe::Fixed { order, .. } => 0b00_1000 | *order as u64,
+                Subframe::Lpc { coefficients, .. } => 0b10_0000 | (coefficients.len() as u64 - 1),
+            };
+            bw.write(0, 1);
+            bw.write(type_code, 6);
+            if wasted > 0 {
+                bw.write(1, 1);
+                bw.write_unary(wasted - 1);
+            } else {
+                bw.write(0, 1);
+            }
+            match subframe {
+                Subframe::Constant(v) => bw.write_signed((*v >> wasted) as i64, bps),
+                Subframe::Verbatim(samples) => {
+                    for s in samples {
+                        bw.write_signed((*s >> wasted) as i64, bps);
+                    }
+                }
+                Subframe::Fixed {
+                    order,
+                    partition_order,
+                    rice_param,
+                    samples,
+                } => {
+                    let samples: Vec<i32> = samples.iter().map(|s| s >> wasted).collect();
+                    for s in &samples[..*order as usize] {
+                        bw.write_signed(*s as i64, bps);
+                    }
+                    let residual = fixed_residual(*order, &samples);
+                    write_residual(
+                        &mut bw,
+                        samples.len(),
+                        *order as usize,
+                        *partition_order,
+                        *rice_param,
+                        &residual,
+                    );
+                }
+                Subframe::Lpc {
+                    coefficients,
+                    precision,
+                    shift,
+                    partition_order,
+                    rice_param,
+                    samples,
+                } => {
+                    let samples: Vec<i32> = samples.iter().map(|s| s >> wasted).collect();
+                    for s in &samples[..coefficients.len()] {
+                        bw.write_signed(*s as i64, bps);
+                    }
+                    bw.write(*precision as u64 - 1, 4);
+                    bw.write(*shift as u64, 5);
+                    for c in coefficients {
+                        bw.write_signed(*c as i64, *precision);
+                    }
+                    let residual = lpc_residual(coefficients, *shift, &samples);
+                    write_residual(
+                        &mut bw,
+                        samples.len(),
+                        coefficients.len(),
+                        *partition_order,
+                        *rice_param,
+                        &residual,
+                    );
+                }
+            }
+        }
+        bw.align();
+        let mut frame = bw.into_bytes();
+        let crc = crc16_flac(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+}
+
+/// Builds a complete FLAC stream.
+#[cfg(feature = "flac")]
+pub struct FlacStream {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u32,
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    pub total_samples: u64,
+    pub md5: [u8; 16],
+    /// metadata blocks written after the STREAMINFO block
+    pub metadata: Vec<(u8, Vec<u8>)>,
+    pub frames: Vec<u8>,
+    frame_count: u64,
+}
+
+#[cfg(feature = "flac")]
+impl FlacStream {
+    pub fn new(sample_rate: u32, channels: u8, bits_per_sample: u32) -> FlacStream {
+        FlacStream {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            min_block_size: 16,
+            max_block_size: 0,
+            total_samples: 0,
+            md5: [0; 16],
+            metadata: Vec::new(),
+            frames: Vec::new(),
+            frame_count: 0,
+        }
+    }
+
+    /// Adds a metadata block of `block_type` after STREAMINFO.
+    pub fn metadata_block(&mut self, block_type: u8, payload: Vec<u8>) -> &mut FlacStream {
+        self.metadata.push((block_type, payload));
+        self
+    }
+
+    /// Appends a frame, holding the per channel `samples` verbatim.
+    pub fn frame(&mut self, samples: &[Vec<i32>]) -> &mut FlacStream {
+        let number = self.frame_count;
+        let spec = FrameSpec::verbatim(number, self.bits_per_sample, samples);
+        self.push_frame(&spec)
+    }
+
+    /// Appends an arbitrary, possibly broken, frame.
+    pub fn push_frame(&mut self, spec: &FrameSpec) -> &mut FlacStream {
+        self.frames.extend_from_slice(&spec.encode());
+        self.max_block_size = self.max_block_size.max(spec.block_size);
+        self.total_samples += spec.block_size as u64;
+        self.frame_count += 1;
+        self
+    }
+
+    pub fn stream_info(&self) -> Vec<u8> {
+        let mut bw = BitWriter::new();
+        bw.write(self.min_block_size as u64, 16);
+        bw.write(self.max_block_size.max(self.min_block_size) as u64, 16);
+        bw.write(0, 24);
+        bw.write(0, 24);
+        bw.write(self.sample_rate as u64, 20);
+        bw.write(self.channels as u64 - 1, 3);
+        bw.write(self.bits_per_sample as u64 - 1, 5);
+        bw.write(self.total_samples, 36);
+        bw.write_bytes(&self.md5);
+        bw.into_bytes()
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = b"fLaC".to_vec();
+        let mut blocks = vec![(0u8, self.stream_info())];
+        blocks.extend(self.metadata.iter().cloned());
+        let n = blocks.len();
+        for (i, (block_type, payload)) in blocks.into_iter().enumerate() {
+            let last = if i + 1 == n { 0x80 } else { 0 };
+            out.push(last | block_type);
+            out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+            out.extend_from_slice(&payload);
+        }
+        out.extend_from_slice(&self.frames);
+        out
+    }
+}
+
+/// Encodes a RIFF chunk, with the pad byte if `payload` has an odd length.
+pub fn riff_chunk(id: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// The payload of a fmt chunk for 16 bit PCM.
+pub fn pcm16_fmt(channels: u16, sample_rate: u32) -> Vec<u8> {
+    pcm_fmt(channels, sample_rate, 16)
+}
+
+/// The payload of a fmt chunk for PCM of `bits` bits per sample, a multiple of 8.
+pub fn pcm_fmt(channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    fmt
+}
+
+/// Builds a WAVE file from encoded chunks.
+pub fn wav_file(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let body: Vec<u8> = chunks.concat();
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    file.extend_from_slice(b"WAVE");
+    file.extend_from_slice(&body);
+    file
+}
+
+/// Builds an Ogg page, `encode` computes its lacing values and CRC.
+#[cfg(feature = "ogg")]
+#[derive(Clone)]
+pub struct OggPage {
+    pub is_continued: bool,
+    pub is_first: bool,
+    pub is_last: bool,
+    pub granule_position: Option<u64>,
+    pub serial: u32,
+    pub sequence: u32,
+    /// the packets, the last one continued on the next page if it is open
+    pub packets: Vec<Vec<u8>>,
+    pub is_open: bool,
+}
+
+#[cfg(feature = "ogg")]
+impl OggPage {
+    pub fn new(serial: u32, sequence: u32) -> OggPage {
+        OggPage {
+            is_continued: false,
+            is_first: false,
+            is_last: false,
+            granule_position: None,
+            serial,
+            sequence,
+            packets: Vec::new(),
+            is_open: false,
+        }
+    }
+
+    /// Appends a packet ending on this page.
+    pub fn packet(&mut self, data: &[u8]) -> &mut OggPage {
+        self.packets.push(data.to_vec());
+        self
+    }
+
+    /// Appends a packet continued on the next page, its length must be a multiple of 255.
+    pub fn open_packet(&mut self, data: &[u8]) -> &mut OggPage {
+        assert_eq!(data.len() % 255, 0);
+        self.packets.push(data.to_vec());
+        self.is_open = true;
+        self
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut segments = Vec::new();
+        for (i, packet) in self.packets.iter().enumerate() {
+            segments.resize(segments.len() + packet.len() / 255, 255);
+            if !(self.is_open && i + 1 == self.packets.len()) {
+                segments.push((packet.len() % 255) as u8);
+            }
+        }
+
+        let flags =
+            self.is_continued as u8 | (self.is_first as u8) << 1 | (self.is_last as u8) << 2;
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&self.granule_position.unwrap_or(u64::MAX).to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(segments.len() as u8);
+        page.extend_from_slice(&segments);
+        for packet in &self.packets {
+            page.extend_from_slice(packet);
+        }
+        let crc = crate::crc::crc32_ogg(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+}
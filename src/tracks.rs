@@ -0,0 +1,361 @@
+//! Splitting an album rip into its tracks at the cue points, e.g. of a FLAC
+//! file with a cue sheet.
+//!
+//! The tracks cover the audio without gaps, each one ends right where the
+//! next one starts, so that the tracks played one after another are the
+//! audio again sample by sample.
+
+use crate::audio::CuePoint;
+#[cfg(feature = "wav")]
+use crate::{audio::AudioSegment, codecs, errors, Result};
+
+/// Where the pregap between index 0 and index 1 of a cue sheet track goes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Pregap {
+    /// to the end of the previous track, so that tracks start at their index 1
+    #[default]
+    Previous,
+    /// to the start of the track itself, which starts at its index 0
+    Next,
+}
+
+/// The inter-channel samples of a track, see `AudioSegment::split_tracks`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackRange {
+    /// the id of the cue point starting the track, its number in a cue sheet
+    pub id: u32,
+
+    /// the label of the cue point starting the track, if any
+    pub label: Option<String>,
+
+    /// the first inter-channel sample of the track
+    pub start: u64,
+
+    /// the inter-channel sample after the last one of the track, `None` if
+    /// it runs to the end of a stream of unknown length
+    pub end: Option<u64>,
+}
+
+impl TrackRange {
+    /// Returns the number of inter-channel samples, `None` if the end is unknown
+    pub fn len(&self) -> Option<u64> {
+        self.end.map(|end| end - self.start)
+    }
+
+    /// Returns true if the track holds no samples
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+/// A track found among the cue points
+struct Track<'a> {
+    id: u32,
+    label: Option<&'a str>,
+    start: u64,
+    pregap: Option<u64>,
+}
+
+pub(crate) fn split_tracks(
+    cues: &[CuePoint],
+    pregap: Pregap,
+    total_frames: Option<u64>,
+) -> Vec<TrackRange> {
+    let mut tracks: Vec<Track> = Vec::new();
+    for cue in cues {
+        let offset = cue.sample_offset as u64;
+        let existing = tracks.iter().position(|t| t.id == cue.id);
+        match (cue.index, existing) {
+            (Some(0), Some(i)) => tracks[i].pregap = Some(offset),
+            (Some(0), None) => tracks.push(Track {
+                id: cue.id,
+                label: None,
+                start: u64::MAX,
+                pregap: Some(offset),
+            }),
+            // further indexes mark positions within the track
+            (None, Some(i)) | (Some(1), Some(i)) if tracks[i].start == u64::MAX => {
+                tracks[i].start = offset;
+                tracks[i].label = cue.label.as_deref();
+            }
+            (None, None) | (Some(1), None) => tracks.push(Track {
+                id: cue.id,
+                label: cue.label.as_deref(),
+                start: offset,
+                pregap: None,
+            }),
+            _ => {}
+        }
+    }
+    // a pregap without a track starts nothing
+    tracks.retain(|t| t.start != u64::MAX);
+    tracks.sort_by_key(|t| t.start);
+
+    let clamp = |frame: u64| total_frames.map_or(frame, |total| frame.min(total));
+    let mut starts: Vec<u64> = tracks
+        .iter()
+        .map(|t| match (pregap, t.pregap) {
+            (Pregap::Next, Some(pregap)) => clamp(pregap.min(t.start)),
+            _ => clamp(t.start),
+        })
+        .collect();
+    // the audio before the first track belongs to it, so nothing is lost
+    if let Some(first) = starts.first_mut() {
+        *first = 0;
+    }
+    for i in 1..starts.len() {
+        starts[i] = starts[i].max(starts[i - 1]);
+    }
+
+    tracks
+        .iter()
+        .enumerate()
+        .map(|(i, t)| TrackRange {
+            id: t.id,
+            label: t.label.map(str::to_string),
+            start: starts[i],
+            end: starts.get(i + 1).copied().or(total_frames),
+        })
+        .collect()
+}
+
+#[cfg(feature = "wav")]
+pub(crate) fn segment_export_tracks<W, F>(
+    segment: &mut AudioSegment,
+    tracks: &[TrackRange],
+    mut create: F,
+) -> Result<Vec<u64>>
+where
+    W: std::io::Write + std::io::Seek,
+    F: FnMut(&TrackRange) -> std::io::Result<W>,
+{
+    use crate::io::{Sample, WriteBuffer};
+
+    let info = segment.info().clone();
+    // the samples are written as decoded, so their bits have to fill the container
+    let codec =
+        match (info.sample_format, info.bits_per_sample) {
+            (codecs::SampleFormat::F32, _) => codecs::CodecType::CODEC_TYPE_PCM_F32LE,
+            (codecs::SampleFormat::U8, _) => codecs::CodecType::CODEC_TYPE_PCM_U8,
+            // G.711 decodes to 16 bits
+            (codecs::SampleFormat::S16, 16) => codecs::CodecType::CODEC_TYPE_PCM_S16LE,
+            (codecs::SampleFormat::S24, 24) => codecs::CodecType::CODEC_TYPE_PCM_S24LE,
+            (codecs::SampleFormat::S32, 32) => codecs::CodecType::CODEC_TYPE_PCM_S32LE,
+            _ => return errors::unsupported_error(
+                "tracks are exported from 16, 24 or 32 bit, unsigned 8 bit or 32 bit float audio",
+            ),
+        };
+    if info.sample_rate == 0 {
+        return errors::unsupported_error("the sample rate of the stream is unknown");
+    }
+    for pair in tracks.windows(2) {
+        if pair[0].end.is_none_or(|end| end > pair[1].start) || pair[1].start < pair[0].start {
+            return errors::unsupported_error("the tracks must be in order and must not overlap");
+        }
+    }
+    let header = crate::wav::write::Header::new(
+        codec,
+        info.channels,
+        info.sample_rate,
+        crate::audio::WavOptions::default(),
+    )?;
+    let channels = segment.number_channels() as u64;
+    let mut export = TrackExport {
+        header: &header,
+        channels,
+        position: 0,
+    };
+
+    if codec == codecs::CodecType::CODEC_TYPE_PCM_F32LE {
+        let samples = segment.samples::<f32>()?;
+        export.write(samples, tracks, &mut create, |s, w| Ok(w.write_le_f32(s)?))
+    } else {
+        let bits = info.bits_per_sample.clamp(8, 32) as u16;
+        let bits = match codec {
+            codecs::CodecType::CODEC_TYPE_PCM_U8 => 8,
+            codecs::CodecType::CODEC_TYPE_PCM_S16LE => 16,
+            _ => bits,
+        };
+        let offset = info.sample_format.offset();
+        let samples = segment.samples::<i32>()?;
+        let write =
+            move |s: i32, w: &mut std::io::BufWriter<&mut W>| (s - offset).write_pcm(w, bits);
+        export.write(samples, tracks, &mut create, write)
+    }
+}
+
+/// The state of `segment_export_tracks` while it decodes the audio once
+#[cfg(feature = "wav")]
+struct TrackExport<'h> {
+    header: &'h crate::wav::write::Header,
+    channels: u64,
+    /// number of samples decoded so far
+    position: u64,
+}
+
+#[cfg(feature = "wav")]
+impl<'h> TrackExport<'h> {
+    fn write<S, W, F, E>(
+        &mut self,
+        mut samples: crate::audio::SampleIterator<'_, S>,
+        tracks: &[TrackRange],
+        mut create: F,
+        encode: E,
+    ) -> Result<Vec<u64>>
+    where
+        S: crate::io::Sample,
+        W: std::io::Write + std::io::Seek,
+        F: FnMut(&TrackRange) -> std::io::Result<W>,
+        E: Fn(S, &mut std::io::BufWriter<&mut W>) -> Result<()>,
+    {
+        use std::io::{SeekFrom, Write};
+
+        let mut lengths = Vec::with_capacity(tracks.len());
+        for track in tracks {
+            // samples between tracks are skipped
+            while self.position < track.start * self.channels {
+                match samples.next() {
+                    Some(sample) => sample.map(drop)?,
+                    None => break,
+                }
+                self.position += 1;
+            }
+            let end = track.end.map(|end| end * self.channels);
+
+            let mut writer = create(track)?;
+            let begin = writer.stream_position()?;
+            self.header.write(&mut writer, 0)?;
+            let mut written = 0u64;
+            {
+                let mut buffered = std::io::BufWriter::new(&mut writer);
+                while end.is_none_or(|end| self.position < end) {
+                    let sample = match samples.next() {
+                        Some(sample) => sample?,
+                        None => break,
+                    };
+                    encode(sample, &mut buffered)?;
+                    self.position += 1;
+                    written += 1;
+                }
+                buffered.flush()?;
+            }
+            let stop = writer.stream_position()?;
+            writer.seek(SeekFrom::Start(begin))?;
+            self.header.write(&mut writer, written)?;
+            writer.seek(SeekFrom::Start(stop))?;
+            self.header.write_padding(&mut writer, written)?;
+            lengths.push(written / self.channels);
+        }
+        Ok(lengths)
+    }
+}
+
+#[cfg(test)]
+fn cue(id: u32, index: Option<u8>, sample_offset: u32) -> CuePoint {
+    CuePoint {
+        id,
+        sample_offset,
+        index,
+        label: None,
+        note: None,
+    }
+}
+
+#[test]
+fn test_split_tracks() {
+    let ranges = |tracks: Vec<TrackRange>| -> Vec<(u32, u64, Option<u64>)> {
+        tracks.iter().map(|t| (t.id, t.start, t.end)).collect()
+    };
+    // the second track has a pregap, the third one none, given out of order
+    let cues = [
+        cue(3, Some(1), 700),
+        cue(1, Some(1), 150),
+        cue(2, Some(0), 400),
+        cue(2, Some(1), 450),
+        cue(2, Some(2), 500),
+    ];
+    assert_eq!(
+        ranges(split_tracks(&cues, Pregap::Previous, Some(1_000))),
+        [
+            (1, 0, Some(450)),
+            (2, 450, Some(700)),
+            (3, 700, Some(1_000))
+        ]
+    );
+    assert_eq!(
+        ranges(split_tracks(&cues, Pregap::Next, Some(1_000))),
+        [
+            (1, 0, Some(400)),
+            (2, 400, Some(700)),
+            (3, 700, Some(1_000))
+        ]
+    );
+    assert_eq!(
+        ranges(split_tracks(&cues, Pregap::Next, None)),
+        [(1, 0, Some(400)), (2, 400, Some(700)), (3, 700, None)]
+    );
+
+    // cue points of a WAV file have no index, a lone pregap starts no track
+    let mut chapter = cue(7, None, 20);
+    chapter.label = Some("Chapter".to_string());
+    let tracks = split_tracks(&[chapter, cue(8, Some(0), 30)], Pregap::Next, Some(10));
+    assert_eq!(ranges(tracks.clone()), [(7, 0, Some(10))]);
+    assert_eq!(tracks[0].label.as_deref(), Some("Chapter"));
+    assert_eq!(tracks[0].len(), Some(10));
+    assert!(split_tracks(&[], Pregap::Previous, Some(10)).is_empty());
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn test_export_tracks_concatenate_to_the_source() {
+    let left: Vec<i32> = (0..1_000).map(|i| (i * 37) % 20_000 - 10_000).collect();
+    let right: Vec<i32> = left.iter().map(|s| -s / 2).collect();
+    let mut stream = crate::testing::FlacStream::new(44_100, 2, 16);
+    for block in 0..4 {
+        let range = block * 250..(block + 1) * 250;
+        stream.frame(&[left[range.clone()].to_vec(), right[range].to_vec()]);
+    }
+    let flac = stream.build();
+    let segment = || {
+        let input = std::io::Cursor::new(flac.clone());
+        AudioSegment::from_reader(input, codecs::FormatFlag::FLAC).unwrap()
+    };
+    let source: Vec<i32> = segment().samples().unwrap().map(Result::unwrap).collect();
+
+    // joins inside and at the edges of the FLAC frames
+    let cues = [
+        cue(1, Some(1), 0),
+        cue(2, Some(0), 240),
+        cue(2, Some(1), 250),
+        cue(3, Some(0), 333),
+        cue(3, Some(1), 334),
+        cue(4, Some(1), 999),
+    ];
+    for &pregap in &[Pregap::Previous, Pregap::Next] {
+        let mut segment = segment();
+        let tracks = segment.split_tracks(&cues, pregap);
+        let path = |track: &TrackRange| {
+            let name = format!("cauldron-track-{}-{}.wav", std::process::id(), track.id);
+            std::env::temp_dir().join(name)
+        };
+        let lengths = segment
+            .export_tracks(&tracks, |track| std::fs::File::create(path(track)))
+            .unwrap();
+        let expected: Vec<u64> = tracks.iter().map(|t| t.len().unwrap()).collect();
+        assert_eq!(lengths, expected);
+
+        let mut joined = Vec::new();
+        for track in &tracks {
+            let file = path(track);
+            let mut track = AudioSegment::read(file.to_str().unwrap()).unwrap();
+            joined.extend(track.samples::<i32>().unwrap().map(Result::unwrap));
+            std::fs::remove_file(file).unwrap();
+        }
+        assert_eq!(joined, source, "{:?}", pregap);
+    }
+
+    let mut overlapping = segment().split_tracks(&cues, Pregap::Previous);
+    overlapping[1].start -= 1;
+    let result = segment().export_tracks(&overlapping, |_| Ok(std::io::Cursor::new(Vec::new())));
+    assert!(matches!(result, Err(errors::Error::Unsupported(_))));
+}
@@ -45,7 +45,7 @@ fn test_narrow_to_i16() {
 /// Tries to cast the sample to a 24-bit signed integer, returning an error on overflow.
 #[inline(always)]
 pub fn narrow_to_i24(x: i32) -> Result<i32> {
-    if x < -(1 << 23) || x > (1 << 23) - 1 {
+    if !(-(1 << 23)..=(1 << 23) - 1).contains(&x) {
         errors::parse_error::<i32>("Too Wide to cast to i24")
     } else {
         Ok(x)
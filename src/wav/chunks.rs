@@ -1,4 +1,5 @@
-use crate::audio::{AudioInfo, ChannelLayout, Channels};
+use crate::audio::{self, AudioInfo, ChannelLayout, Channels, CuePoint, SampleLoop};
+use crate::codecs::SampleFormat;
 use crate::io::ReadBuffer;
 use crate::{codecs, errors, Result};
 
@@ -6,39 +7,77 @@ use crate::{codecs, errors, Result};
 pub enum Chunk {
     /// format chunk, fully parsed into a AudioInfo
     Fmt(AudioInfo),
+    /// format chunk of an encoding which can not be decoded
+    UnsupportedFmt(codecs::WaveFormat),
     /// data chunk, where the samples are actually stored
     Data(u32),
+    /// fact chunk, the number of inter-channel samples of compressed audio
+    Fact(u32),
+    /// cue chunk, the cue points without their labels
+    Cue(Vec<CuePoint>),
+    /// associated data list chunk with the labels and notes of cue points
+    AssociatedData(Vec<Label>),
+    /// sampler chunk, only its loops are kept
+    Sampler(Vec<SampleLoop>),
     /// any other riff chunk
     Unknown([u8; 4], u32),
 }
 
+/// A `labl` or `note` text of a cue point from an `adtl` list.
+pub struct Label {
+    /// id of the cue point labeled
+    pub cue_id: u32,
+    /// true for a `note`, false for a `labl`
+    pub is_note: bool,
+    pub text: String,
+}
+
+/// Larger cue and list chunks are skipped instead of being read into memory.
+const MAX_METADATA_CHUNK_LEN: u32 = 1 << 24;
+
 // The different compression format definitions can be found in mmreg.h that is
 // part of the Windows SDK.
-const WAVE_FORMAT_PCM: u16 = 0x0001;
-const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
-const WAVE_FORMAT_ALAW: u16 = 0x0006;
-const WAVE_FORMAT_MULAW: u16 = 0x0007;
-const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+pub(super) const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub(super) const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub(super) const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub(super) const WAVE_FORMAT_MULAW: u16 = 0x0007;
+const WAVE_FORMAT_MPEGLAYER3: u16 = 0x0055;
+pub(super) const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
 
 // These GUIDs identify the format of the data chunks.
 // https://docs.microsoft.com/en-us/windows-hardware/drivers/audio/subformat-guids-for-compressed-audio-formats
-const KSDATAFORMAT_SUBTYPE_PCM: [u8; 16] = [
+pub(crate) const KSDATAFORMAT_SUBTYPE_PCM: [u8; 16] = [
     0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
 ];
-const KSDATAFORMAT_SUBTYPE_IEEE_FLOAT: [u8; 16] = [
+pub(crate) const KSDATAFORMAT_SUBTYPE_IEEE_FLOAT: [u8; 16] = [
     0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
 ];
-const KSDATAFORMAT_SUBTYPE_ALAW: [u8; 16] = [
+pub(crate) const KSDATAFORMAT_SUBTYPE_ALAW: [u8; 16] = [
     0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
 ];
-const KSDATAFORMAT_SUBTYPE_MULAW: [u8; 16] = [
-    0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+pub(crate) const KSDATAFORMAT_SUBTYPE_MULAW: [u8; 16] = [
+    0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
 ];
 
 /// Parse the next chunk from the reader.
 ///
 /// Returns None at end of file, or a `Chunk` instance depending on the chunk kind.
+///
+/// `reader` must be positioned at a chunk header, e.g. right after the `RIFF`
+/// header and `WAVE` tag. Every chunk but the data chunk is consumed completely,
+/// for `Chunk::Data` the reader is left at the first sample.
+///
+/// A fmt chunk whose redundant fields are wrong is rejected, see
+/// `DecodeOptions::lenient_fmt`.
 pub fn read_next_chunk<R: ReadBuffer>(reader: &mut R) -> Result<Option<Chunk>> {
+    read_chunk(reader, false)
+}
+
+/// `read_next_chunk`, only warning about wrong redundant fmt fields if `lenient_fmt`
+pub(crate) fn read_chunk<R: ReadBuffer>(
+    reader: &mut R,
+    lenient_fmt: bool,
+) -> Result<Option<Chunk>> {
     let mut chunk_type = [0; 4];
     // check for EOF
     if reader.read_into(&mut chunk_type).is_err() {
@@ -49,20 +88,167 @@ pub fn read_next_chunk<R: ReadBuffer>(reader: &mut R) -> Result<Option<Chunk>> {
     let len = reader.read_le_u32()?;
 
     match &chunk_type {
-        b"fmt " => {
-            let info = read_fmt_chunk(reader, len);
-            Ok(Some(Chunk::Fmt(info?)))
-        }
+        b"fmt " => Ok(Some(read_fmt_chunk(reader, len, lenient_fmt)?)),
         b"data" => Ok(Some(Chunk::Data(len))),
+        b"fact" if len >= 4 => {
+            let frames = reader.read_le_u32()?;
+            reader.skip_bytes((len - 4 + len % 2) as usize)?;
+            Ok(Some(Chunk::Fact(frames)))
+        }
+        b"cue " if len <= MAX_METADATA_CHUNK_LEN => {
+            let data = read_padded(reader, len)?;
+            Ok(Some(Chunk::Cue(parse_cue_chunk(&data)?)))
+        }
+        b"smpl" if len <= MAX_METADATA_CHUNK_LEN => {
+            let data = read_padded(reader, len)?;
+            Ok(Some(Chunk::Sampler(parse_smpl_chunk(&data)?)))
+        }
+        b"LIST" if len <= MAX_METADATA_CHUNK_LEN => {
+            let data = read_padded(reader, len)?;
+            match data.get(..4) {
+                Some(b"adtl") => Ok(Some(Chunk::AssociatedData(parse_adtl_list(&data[4..])?))),
+                _ => Ok(Some(Chunk::Unknown(chunk_type, len))),
+            }
+        }
         _ => {
-            reader.skip_bytes(len as usize)?;
+            // chunks of odd length are followed by a pad byte
+            reader.skip_bytes(len as usize + (len % 2) as usize)?;
             Ok(Some(Chunk::Unknown(chunk_type, len)))
         }
     }
 }
 
+/// Reads the `len` bytes of a chunk, and the pad byte following chunks of odd length.
+fn read_padded<R: ReadBuffer>(reader: &mut R, len: u32) -> Result<Vec<u8>> {
+    let data = reader.read_bytes(len as usize)?;
+    if len % 2 == 1 {
+        reader.skip_bytes(1)?;
+    }
+    Ok(data)
+}
+
+/// Parses the cue points of a `cue ` chunk.
+/// https://sites.google.com/site/musicgapi/technical-documents/wav-file-format#cue
+fn parse_cue_chunk(mut data: &[u8]) -> Result<Vec<CuePoint>> {
+    let count = data.read_le_u32()?;
+
+    // every cue point takes 24 bytes
+    if (data.len() as u64) < count as u64 * 24 {
+        return errors::parse_error("cue chunk too short for its number of cue points");
+    }
+
+    let mut cue_points = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let id = data.read_le_u32()?;
+        // play order position, data chunk id, chunk start and block start
+        // only matter for wave lists, which are not supported
+        data.skip_bytes(16)?;
+        let sample_offset = data.read_le_u32()?;
+        cue_points.push(CuePoint {
+            id,
+            sample_offset,
+            index: None,
+            label: None,
+            note: None,
+        });
+    }
+    Ok(cue_points)
+}
+
+/// Parses the loops of a `smpl` chunk.
+/// https://sites.google.com/site/musicgapi/technical-documents/wav-file-format#smpl
+fn parse_smpl_chunk(mut data: &[u8]) -> Result<Vec<SampleLoop>> {
+    // manufacturer, product, sample period, MIDI unity note and pitch fraction,
+    // SMPTE format and offset describe the sampler, not the audio
+    data.skip_bytes(28)?;
+    let count = data.read_le_u32()?;
+    let _sampler_data_len = data.read_le_u32()?;
+
+    // every loop takes 24 bytes
+    if (data.len() as u64) < count as u64 * 24 {
+        return errors::parse_error("smpl chunk too short for its number of loops");
+    }
+
+    let mut loops = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let cue_id = data.read_le_u32()?;
+        let loop_type = data.read_le_u32()?;
+        let start = data.read_le_u32()?;
+        let end = data.read_le_u32()?;
+        let _fraction = data.read_le_u32()?;
+        let play_count = data.read_le_u32()?;
+        loops.push(SampleLoop {
+            cue_id,
+            loop_type,
+            start,
+            end,
+            play_count,
+        });
+    }
+    Ok(loops)
+}
+
+/// Parses the `labl` and `note` chunks of an `adtl` list, other chunks are ignored.
+/// https://sites.google.com/site/musicgapi/technical-documents/wav-file-format#list
+fn parse_adtl_list(mut data: &[u8]) -> Result<Vec<Label>> {
+    let mut labels = Vec::new();
+    while data.len() >= 8 {
+        let mut chunk_type = [0; 4];
+        data.read_into(&mut chunk_type)?;
+        let len = data.read_le_u32()? as usize;
+        if len > data.len() {
+            return errors::parse_error("adtl sub-chunk exceeds its list");
+        }
+        let (chunk, rest) = data.split_at(len);
+        data = rest;
+        // sub-chunks are padded to an even length as well
+        if len % 2 == 1 && !data.is_empty() {
+            data = &data[1..];
+        }
+
+        let is_note = match &chunk_type {
+            b"labl" => false,
+            b"note" => true,
+            _ => continue,
+        };
+        if chunk.len() < 4 {
+            return errors::parse_error("adtl label too short");
+        }
+        let cue_id = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        // the text is zero terminated, but not every writer does so
+        let text = &chunk[4..];
+        let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+        labels.push(Label {
+            cue_id,
+            is_note,
+            text: String::from_utf8_lossy(&text[..end]).into_owned(),
+        });
+    }
+    Ok(labels)
+}
+
+/// Joins the labels to the cue points with the same id.
+pub fn join_labels(cue_points: &[CuePoint], labels: &[Label]) -> Vec<CuePoint> {
+    let mut cue_points = cue_points.to_vec();
+    for label in labels {
+        for cue_point in cue_points.iter_mut().filter(|c| c.id == label.cue_id) {
+            let text = if label.is_note {
+                &mut cue_point.note
+            } else {
+                &mut cue_point.label
+            };
+            *text = Some(label.text.clone());
+        }
+    }
+    cue_points
+}
+
 /// Reads the fmt chunk of the file, returns the information it provides.
-fn read_fmt_chunk<R: ReadBuffer>(reader: &mut R, chunk_len: u32) -> Result<AudioInfo> {
+///
+/// The fields of an unsupported encoding are returned as they are, without
+/// any validation, in `Chunk::UnsupportedFmt`. Their block align and byte
+/// rate have a meaning of their own, e.g. ADPCM blocks of many samples.
+fn read_fmt_chunk<R: ReadBuffer>(reader: &mut R, chunk_len: u32, lenient: bool) -> Result<Chunk> {
     // A minimum chunk length of at least 16 is assumed.
     // https://sites.google.com/site/musicgapi/technical-documents/wav-file-format#fmt
     if chunk_len < 16 {
@@ -75,6 +261,46 @@ fn read_fmt_chunk<R: ReadBuffer>(reader: &mut R, chunk_len: u32) -> Result<Audio
     let n_bytes_per_sec = reader.read_le_u32()?;
     let block_align = reader.read_le_u16()?;
     let bits_per_sample = reader.read_le_u16()?;
+    audio::check_sample_rate(sample_rate)?;
+
+    let mut audio_info = AudioInfo {
+        codec_type: codecs::CodecType::CODEC_TYPE_NULL,
+        sample_rate,
+        total_samples: 0,
+        bits_per_sample: bits_per_sample as u32,
+        container_bits: 0,
+        // set by `with_sample_format` once the codec is known
+        sample_format: SampleFormat::S32,
+        channels: Channels::FRONT_LEFT,
+        channel_layout: ChannelLayout::Mono,
+        flac_stream_info: None,
+        encoder_delay: None,
+        encoder_padding: None,
+    };
+
+    match format_tag {
+        WAVE_FORMAT_PCM
+        | WAVE_FORMAT_IEEE_FLOAT
+        | WAVE_FORMAT_ALAW
+        | WAVE_FORMAT_MULAW
+        | WAVE_FORMAT_EXTENSIBLE => {}
+        // the frames have no fixed size, so the fields checked below do not apply
+        WAVE_FORMAT_MPEGLAYER3 if cfg!(feature = "mp3") => {
+            let info = read_wave_format_mp3(reader, chunk_len, n_channels, audio_info);
+            return Ok(Chunk::Fmt(with_sample_format(info?)));
+        }
+        _ => {
+            reader.skip_bytes((chunk_len - 16) as usize)?;
+            return Ok(Chunk::UnsupportedFmt(codecs::WaveFormat {
+                format_tag,
+                channels: n_channels,
+                sample_rate,
+                bytes_per_second: n_bytes_per_sec,
+                block_align,
+                bits_per_sample,
+            }));
+        }
+    }
 
     if n_channels == 0 {
         return errors::parse_error("number channels is 0");
@@ -83,33 +309,70 @@ fn read_fmt_chunk<R: ReadBuffer>(reader: &mut R, chunk_len: u32) -> Result<Audio
     // Two of the stored fields are redundant, and may be ignored. We do
     // validate them to fail early for ill-formed files.
     //
-    // BlockAlign = SignificantBitsPerSample / 8 * NumChannels
+    // BlockAlign = ContainerBytesPerSample * NumChannels
     // AvgBytesPerSec = SampleRate * BlockAlign
-    if (Some(bits_per_sample) != (block_align / n_channels).checked_mul(8))
-        || (Some(n_bytes_per_sec) != (block_align as u32).checked_mul(sample_rate))
-    {
+    //
+    // The container of PCM samples is rounded up to full bytes, G.711 samples
+    // take one byte. Only WAVEFORMATEXTENSIBLE stores the container width
+    // apart from the valid bits, block align is all that tells it, so it can
+    // not be ignored there.
+    let container_bytes = match format_tag {
+        WAVE_FORMAT_ALAW | WAVE_FORMAT_MULAW => 1,
+        _ => (bits_per_sample as u32).div_ceil(8),
+    };
+    if format_tag != WAVE_FORMAT_EXTENSIBLE {
+        check_redundant_field(
+            block_align as u32 == container_bytes * n_channels as u32,
+            "block align",
+            lenient,
+        )?;
+        audio_info.container_bits = container_bytes * 8;
+    } else if block_align % n_channels != 0 {
         return errors::parse_error("inconsistent fmt chunk");
     }
+    check_redundant_field(
+        Some(n_bytes_per_sec) == (block_align as u32).checked_mul(sample_rate),
+        "byte rate",
+        lenient,
+    )?;
 
-    let audio_info = AudioInfo {
-        codec_type: codecs::CodecType::CODEC_TYPE_NULL,
-        sample_rate,
-        total_samples: 0,
-        bits_per_sample: bits_per_sample as u32,
-        channels: Channels::FRONT_LEFT,
-        channel_layout: ChannelLayout::Mono,
-    };
-
-    match format_tag {
+    let info = match format_tag {
         WAVE_FORMAT_PCM => read_wave_format_pcm(reader, chunk_len, n_channels, audio_info),
         WAVE_FORMAT_IEEE_FLOAT => read_wave_format_ieee(reader, chunk_len, n_channels, audio_info),
         WAVE_FORMAT_ALAW => read_wave_format_alaw(reader, chunk_len, n_channels, audio_info),
         WAVE_FORMAT_MULAW => read_wave_format_mulaw(reader, chunk_len, n_channels, audio_info),
-        WAVE_FORMAT_EXTENSIBLE => read_wave_format_ext(reader, chunk_len, audio_info),
-        _ => errors::unsupported_error("encoding format not supported"),
+        _ => read_wave_format_ext(
+            reader,
+            chunk_len,
+            n_channels,
+            block_align / n_channels,
+            audio_info,
+        ),
+    };
+    Ok(Chunk::Fmt(with_sample_format(info?)))
+}
+
+/// Fails with a parse error unless the redundant `field` `is_consistent`, only warns if `lenient`
+fn check_redundant_field(is_consistent: bool, field: &str, lenient: bool) -> Result<()> {
+    if is_consistent {
+        Ok(())
+    } else if lenient {
+        tracing::warn!(
+            "the {} of the fmt chunk is inconsistent, ignoring it",
+            field
+        );
+        Ok(())
+    } else {
+        errors::parse_error("inconsistent fmt chunk")
     }
 }
 
+/// Sets the sample format of `info` from its codec and bits per sample
+fn with_sample_format(mut info: AudioInfo) -> AudioInfo {
+    info.sample_format = SampleFormat::of_codec(info.codec_type, info.bits_per_sample);
+    info
+}
+
 fn read_wave_format_pcm<R: ReadBuffer>(
     reader: &mut R,
     chunk_len: u32,
@@ -133,11 +396,9 @@ fn read_wave_format_pcm<R: ReadBuffer>(
         }
     };
 
-    // The PCM format only supports max 2 channels.
-    audio_info.channel_layout = match n_channels {
-        1 => ChannelLayout::Mono,
-        2 => ChannelLayout::Stereo,
-        _ => return errors::parse_error("Only max two channels supported for fmt_pcm."),
+    audio_info.channel_layout = match ChannelLayout::of_count(n_channels as usize) {
+        Some(channel_layout) => channel_layout,
+        None => return errors::parse_error("Only up to eight channels supported for fmt_pcm."),
     };
     audio_info.channels = ChannelLayout::into_channels(audio_info.channel_layout);
 
@@ -167,11 +428,9 @@ fn read_wave_format_ieee<R: ReadBuffer>(
         _ => return errors::parse_error("Bits per sample for fmt_ieee must be 32 or 64 bits."),
     };
 
-    // IEEE format only supports max 2 channels.
-    audio_info.channel_layout = match n_channels {
-        1 => ChannelLayout::Mono,
-        2 => ChannelLayout::Stereo,
-        _ => return errors::parse_error("Max two channels supported for fmt_ieee."),
+    audio_info.channel_layout = match ChannelLayout::of_count(n_channels as usize) {
+        Some(channel_layout) => channel_layout,
+        None => return errors::parse_error("Only up to eight channels supported for fmt_ieee."),
     };
     audio_info.channels = ChannelLayout::into_channels(audio_info.channel_layout);
 
@@ -181,6 +440,8 @@ fn read_wave_format_ieee<R: ReadBuffer>(
 fn read_wave_format_ext<R: ReadBuffer>(
     reader: &mut R,
     chunk_len: u32,
+    n_channels: u16,
+    container_bytes: u16,
     mut audio_info: AudioInfo,
 ) -> Result<AudioInfo> {
     // https://docs.microsoft.com/en-us/windows-hardware/drivers/audio/extensible-wave-format-descriptors
@@ -194,38 +455,62 @@ fn read_wave_format_ext<R: ReadBuffer>(
     if extra_size != 22 {
         return errors::parse_error("Extra data size not 22 bytes for fmt_ext chunk.");
     }
+    // The extension is read as a whole first, the fields are validated together.
+    let valid_bits = reader.read_le_u16()? as u32;
+    let channel_mask = reader.read_le_u32()?;
+    let mut sub_format_guid = [0u8; 16];
+    reader.read_into(&mut sub_format_guid)?;
 
     if (audio_info.bits_per_sample & 0x7) != 0 {
         return errors::parse_error(
             "Bits per encoded sample for fmt_ext must be a multiple of 8 bits.",
         );
     }
-    audio_info.bits_per_sample = reader.read_le_u16()? as u32;
-
-    let channel_mask = reader.read_le_u32()?;
-    let mut sub_format_guid = [0u8; 16];
-    reader.read_into(&mut sub_format_guid)?;
+    // Some encoders store the valid bits as bits per sample, e.g. 24 for
+    // samples in 32 bit containers, but block align is always the container.
+    let container_bits = container_bytes as u32 * 8;
+    if container_bits == 0 {
+        return errors::parse_error("Block align of fmt_ext is 0.");
+    }
+    if valid_bits > container_bits || audio_info.bits_per_sample > container_bits {
+        return errors::parse_error("Bits per sample for fmt_ext exceed the block align.");
+    }
+    // The valid bits are the most significant ones of the container, which
+    // the samples are read from. 0 means all bits of the sample are valid.
+    audio_info.container_bits = container_bits;
+    audio_info.bits_per_sample = match valid_bits {
+        0 => audio_info.bits_per_sample,
+        valid_bits => valid_bits.min(audio_info.bits_per_sample),
+    };
 
     audio_info.codec_type = match sub_format_guid {
         KSDATAFORMAT_SUBTYPE_PCM => {
             // Only support up-to 32-bit integer samples.
-            if audio_info.bits_per_sample > 32 {
+            if container_bits > 32 {
                 return errors::parse_error(
                     "Bits per sample for fmt_ext PCM sub-type must be <= 32 bits.",
                 );
             }
+            // unsigned 8 bit samples can not be shifted down to fewer bits
+            if container_bits == 8 {
+                audio_info.bits_per_sample = 8;
+            }
 
-            // Use bits per coded sample to select the codec to use. If bits per sample is less than the bits per
-            // coded sample, the codec will expand the sample during decode.
+            // the codec of the valid bits, rounded up to whole bytes
             match audio_info.bits_per_sample {
                 8 => codecs::CodecType::CODEC_TYPE_PCM_U8,
-                16 => codecs::CodecType::CODEC_TYPE_PCM_S16LE,
-                24 => codecs::CodecType::CODEC_TYPE_PCM_S24LE,
-                32 => codecs::CodecType::CODEC_TYPE_PCM_S32LE,
-                _ => unreachable!(),
+                9..=16 => codecs::CodecType::CODEC_TYPE_PCM_S16LE,
+                17..=24 => codecs::CodecType::CODEC_TYPE_PCM_S24LE,
+                25..=32 => codecs::CodecType::CODEC_TYPE_PCM_S32LE,
+                _ => {
+                    return errors::parse_error(
+                        "Valid bits per sample for fmt_ext PCM sub-type must be 8 to 32 bits.",
+                    )
+                }
             }
         }
         KSDATAFORMAT_SUBTYPE_IEEE_FLOAT => {
+            audio_info.bits_per_sample = container_bits;
             if audio_info.bits_per_sample == 32 {
                 codecs::CodecType::CODEC_TYPE_PCM_F32LE
             } else if audio_info.bits_per_sample == 64 {
@@ -236,20 +521,77 @@ fn read_wave_format_ext<R: ReadBuffer>(
                 );
             }
         }
+        KSDATAFORMAT_SUBTYPE_ALAW | KSDATAFORMAT_SUBTYPE_MULAW if container_bits != 8 => {
+            return errors::parse_error("Samples for fmt_ext G.711 sub-types must be 8 bits.");
+        }
         KSDATAFORMAT_SUBTYPE_ALAW => codecs::CodecType::CODEC_TYPE_PCM_ALAW,
         KSDATAFORMAT_SUBTYPE_MULAW => codecs::CodecType::CODEC_TYPE_PCM_MULAW,
         _ => return errors::unsupported_error("Unsupported fmt_ext sub-type."),
     };
+    if is_g711(audio_info.codec_type) {
+        audio_info.bits_per_sample = 16;
+    }
+
+    let channels = decode_channel_mask(channel_mask);
+    if channels.count() == n_channels as usize {
+        // the speakers of the mask are kept even if no layout has that many,
+        // the layout of more than 8 channels is the largest one
+        audio_info.channels = channels;
+        audio_info.channel_layout = match ChannelLayout::of_channels(channels) {
+            Some(channel_layout) => channel_layout,
+            None => ChannelLayout::SevenPointOne,
+        };
+    } else {
+        if channel_mask != 0 {
+            tracing::warn!(
+                channel_mask,
+                n_channels,
+                "the channel mask of fmt_ext does not match the channels, ignoring it"
+            );
+        }
+        // no speaker positions, players assume the ones of a plain fmt chunk
+        audio_info.channel_layout = match ChannelLayout::of_count(n_channels as usize) {
+            Some(channel_layout) => channel_layout,
+            None => return errors::parse_error(
+                "Only up to eight channels supported for fmt_ext without a matching channel mask.",
+            ),
+        };
+        audio_info.channels = ChannelLayout::into_channels(audio_info.channel_layout);
+    }
 
-    audio_info.channels = decode_channel_mask(channel_mask);
-    audio_info.channel_layout = match audio_info.channels.count() {
+    Ok(audio_info)
+}
+
+fn read_wave_format_mp3<R: ReadBuffer>(
+    reader: &mut R,
+    chunk_len: u32,
+    n_channels: u16,
+    mut audio_info: AudioInfo,
+) -> Result<AudioInfo> {
+    // MPEGLAYER3WAVEFORMAT extends the fmt chunk by 12 bytes, see mmreg.h
+    if chunk_len < 30 || reader.read_le_u16()? < 12 {
+        return errors::parse_error("Malformed fmt_mp3 chunk.");
+    }
+    let _id = reader.read_le_u16()?;
+    let _flags = reader.read_le_u32()?;
+    // Frames are decoded one by one as they are found in the data chunk, the
+    // size of a block of frames is of no use.
+    let _block_size = reader.read_le_u16()?;
+    let _frames_per_block = reader.read_le_u16()?;
+    let codec_delay = reader.read_le_u16()?;
+    if chunk_len > 30 {
+        reader.skip_bytes((chunk_len - 30) as usize)?;
+    }
+
+    audio_info.codec_type = codecs::CodecType::CODEC_TYPE_MP3;
+    audio_info.bits_per_sample = 0;
+    audio_info.encoder_delay = Some(codec_delay as u32);
+    audio_info.channel_layout = match n_channels {
+        1 => ChannelLayout::Mono,
         2 => ChannelLayout::Stereo,
-        3 => ChannelLayout::ThreePointZero,
-        4 => ChannelLayout::Quad,
-        6 => ChannelLayout::FivePointOne,
-        8 => ChannelLayout::SevenPointOne,
-        _ => ChannelLayout::Mono,
+        _ => return errors::parse_error("Only one or two channels supported for fmt_mp3."),
     };
+    audio_info.channels = ChannelLayout::into_channels(audio_info.channel_layout);
 
     Ok(audio_info)
 }
@@ -264,10 +606,11 @@ fn read_wave_format_alaw<R: ReadBuffer>(
         reader.skip_bytes((chunk_len - 16) as usize)?;
     }
     audio_info.codec_type = codecs::CodecType::CODEC_TYPE_PCM_ALAW;
-    audio_info.channel_layout = match n_channels {
-        1 => ChannelLayout::Mono,
-        2 => ChannelLayout::Stereo,
-        _ => return errors::parse_error("Only max two channels supported for fmt_alaw."),
+    // every byte expands to a 16 bit sample
+    audio_info.bits_per_sample = 16;
+    audio_info.channel_layout = match ChannelLayout::of_count(n_channels as usize) {
+        Some(channel_layout) => channel_layout,
+        None => return errors::parse_error("Only up to eight channels supported for fmt_alaw."),
     };
     audio_info.channels = ChannelLayout::into_channels(audio_info.channel_layout);
     Ok(audio_info)
@@ -283,16 +626,28 @@ fn read_wave_format_mulaw<R: ReadBuffer>(
         reader.skip_bytes((chunk_len - 16) as usize)?;
     }
     audio_info.codec_type = codecs::CodecType::CODEC_TYPE_PCM_MULAW;
-    audio_info.channel_layout = match n_channels {
-        1 => ChannelLayout::Mono,
-        2 => ChannelLayout::Stereo,
-        _ => return errors::parse_error("Only max two channels supported for fmt_mulaw."),
+    // every byte expands to a 16 bit sample
+    audio_info.bits_per_sample = 16;
+    audio_info.channel_layout = match ChannelLayout::of_count(n_channels as usize) {
+        Some(channel_layout) => channel_layout,
+        None => return errors::parse_error("Only up to eight channels supported for fmt_mulaw."),
     };
     audio_info.channels = ChannelLayout::into_channels(audio_info.channel_layout);
 
     Ok(audio_info)
 }
 
+/// Returns true for A-law and µ-law, which store the 16 bit samples in a byte
+pub(crate) fn is_g711(codec: codecs::CodecType) -> bool {
+    codec == codecs::CodecType::CODEC_TYPE_PCM_ALAW
+        || codec == codecs::CodecType::CODEC_TYPE_PCM_MULAW
+}
+
+/// Returns the number of bytes a sample takes up in the data chunk
+pub(crate) fn bytes_per_sample(info: &AudioInfo) -> u32 {
+    info.container_bits / 8
+}
+
 fn decode_channel_mask(channel_mask: u32) -> Channels {
     const SPEAKER_FRONT_LEFT: u32 = 0x1;
     const SPEAKER_FRONT_RIGHT: u32 = 0x2;
@@ -1,72 +1,562 @@
-mod chunks;
+pub(crate) mod chunks;
+pub(crate) mod write;
 
-use super::io::{AudioInputStream, AudioReader, AudioSamplesIterator, ReadBuffer, Sample};
-use super::{audio, errors, Result};
+use super::format::{DecodeContext, Format, SampleType, TypedSamples};
+use super::io::{
+    self, AudioInputStream, AudioReader, AudioSamplesIterator, ReadBuffer, Rewind, Sample,
+};
+use super::pictures::{self, PictureContainer, PictureSource};
+use super::stats::{InstrumentationHandle, VerifyReport};
+use super::{audio, codecs, errors, Result};
+use std::time::Instant;
 
 use chunks::*;
 
 const RIFF_MARKER: &[u8; 4] = b"RIFF";
 const WAVE_MARKER: &[u8; 4] = b"WAVE";
 
+/// The WAV format, MP3 frames in the data chunk are decoded like an MP3 stream
+pub struct WavFormat;
+
+impl Format for WavFormat {
+    fn flag(&self) -> codecs::FormatFlag {
+        codecs::FormatFlag::WAV
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["wav"]
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        header.len() >= 12 && header[..4] == RIFF_MARKER[..] && header[8..12] == WAVE_MARKER[..]
+    }
+
+    fn create_reader(
+        &self,
+        input: AudioInputStream,
+        options: audio::DecodeOptions,
+    ) -> Result<Box<dyn AudioReader>> {
+        Ok(WavReader::new(input, options)?)
+    }
+
+    fn samples<'a>(
+        &self,
+        context: DecodeContext<'a>,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>> {
+        #[cfg(feature = "mp3")]
+        {
+            if context.info.codec_type == codecs::CodecType::CODEC_TYPE_MP3 {
+                return crate::mp3::Mp3Format.samples(context, sample_type);
+            }
+        }
+        Ok(
+            typed_samples!(sample_type, S => WavSamplesIterator::<S>::new(
+                context.reader,
+                context.info.clone(),
+                context.options.non_finite_samples,
+                context.instrumentation,
+            )),
+        )
+    }
+}
+
 pub struct WavReader {
     reader: AudioInputStream,
+    /// set once `read_header` was called
+    header_read: bool,
+    cue_points: Vec<audio::CuePoint>,
+    labels: Vec<Label>,
+    sample_loops: Vec<audio::SampleLoop>,
+    /// position of the first sample of the data chunk
+    data_start: u64,
+    /// length of the data chunk as stored in its header
+    stored_data_len: u32,
+    /// length of the data chunk, limited to the input if it is seekable
+    data_len: u32,
+    /// position right after the data chunk, including its pad byte
+    data_end: u64,
+    /// set once the chunks after the data were read
+    trailer_read: bool,
+    options: audio::DecodeOptions,
+    /// the chunks as stored, if `DecodeOptions::keep_raw_headers` is set
+    raw_headers: Option<audio::RawHeaders>,
+    /// the `id3 ` chunks read so far
+    pictures: Vec<PictureSource>,
 }
 
 impl WavReader {
-    pub fn new(reader: AudioInputStream) -> Result<Box<Self>> {
-        Ok(Box::new(WavReader { reader }))
+    pub fn new(reader: AudioInputStream, options: audio::DecodeOptions) -> Result<Box<Self>> {
+        Ok(Box::new(WavReader {
+            reader,
+            header_read: false,
+            cue_points: Vec::new(),
+            labels: Vec::new(),
+            sample_loops: Vec::new(),
+            data_start: 0,
+            stored_data_len: 0,
+            data_len: 0,
+            data_end: 0,
+            trailer_read: false,
+            options,
+            raw_headers: if options.keep_raw_headers {
+                Some(audio::RawHeaders::default())
+            } else {
+                None
+            },
+            pictures: Vec::new(),
+        }))
+    }
+
+    /// Keeps the cue points, labels and loops of a chunk, other chunks are handed back.
+    fn add_cue_chunk(&mut self, chunk: Chunk) -> Option<Chunk> {
+        match chunk {
+            Chunk::Cue(cue_points) => self.cue_points.extend(cue_points),
+            Chunk::AssociatedData(labels) => self.labels.extend(labels),
+            Chunk::Sampler(loops) => self.sample_loops.extend(loops),
+            chunk => return Some(chunk),
+        }
+        None
+    }
+
+    /// Limits the length of the data chunk to the whole frames the input holds
+    ///
+    /// Writers which were interrupted leave a length covering audio that was
+    /// never written. This is only detected on seekable inputs, the others end
+    /// where the input ends. Writers to a pipe can not go back to fill in the
+    /// length, a length of 0 on an input which can not seek is taken to last
+    /// until its end, unless the input already ends after the header.
+    fn clamp_data_len(&mut self, data_len: u32, info: &audio::AudioInfo) -> Result<u32> {
+        if !self.reader.is_seekable() {
+            if data_len == 0 && !self.reader.peek(1)?.is_empty() {
+                return Ok(u32::MAX);
+            }
+            return Ok(data_len);
+        }
+        let end = self.reader.seek(std::io::SeekFrom::End(0))?;
+        self.reader
+            .seek(std::io::SeekFrom::Start(self.data_start))?;
+
+        let available = end.saturating_sub(self.data_start);
+        if data_len as u64 <= available {
+            return Ok(data_len);
+        }
+        let block_align = (info.channels.count() as u64 * bytes_per_sample(info) as u64).max(1);
+        let clamped = (available / block_align * block_align) as u32;
+        tracing::warn!(
+            declared = data_len,
+            available,
+            "WAV data chunk is longer than the file, decoding the {} bytes present",
+            clamped
+        );
+        Ok(clamped)
+    }
+
+    fn read_trailing_chunks(&mut self) -> Result<()> {
+        let position = self.reader.position();
+        if position < self.data_end {
+            self.reader
+                .skip_bytes((self.data_end - position) as usize)?;
+        }
+        while let Some(chunk) = self.read_chunk()? {
+            self.add_cue_chunk(chunk);
+        }
+        Ok(())
+    }
+
+    /// Reads the next chunk, keeping its bytes if `raw_headers` is set
+    fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+        let raw = match self.raw_headers {
+            Some(_) => self.peek_raw_chunk()?,
+            None => None,
+        };
+        let chunk = match self.read_id3_chunk()? {
+            Some(chunk) => Some(chunk),
+            None => read_chunk(&mut self.reader, self.options.lenient_fmt)?,
+        };
+        if let (Some(raw_headers), Some(raw), Some(chunk)) = (&mut self.raw_headers, raw, &chunk) {
+            match chunk {
+                Chunk::Fmt(_) | Chunk::UnsupportedFmt(_) => raw_headers.fmt_chunk = raw.payload,
+                Chunk::Unknown(..) => raw_headers.unknown_chunks.push(raw),
+                _ => {}
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Notes where the next chunk is if it is an `id3 ` chunk, reading it if it is kept
+    ///
+    /// Returns `None` if the chunk is left to `read_chunk`.
+    fn read_id3_chunk(&mut self) -> Result<Option<Chunk>> {
+        let (id, len) = match id3_chunk_header(self.reader.peek(8)?) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let keep = pictures::keep_payload(
+            self.reader.is_seekable(),
+            &self.pictures,
+            len as u64,
+            self.options.max_buffered_pictures_len,
+        );
+        let mut source = PictureSource {
+            container: PictureContainer::Id3v2,
+            offset: self.reader.position() + 8,
+            len: len as u64,
+            payload: None,
+        };
+        if !keep {
+            self.pictures.push(source);
+            return Ok(None);
+        }
+        self.reader.skip_bytes(8)?;
+        source.payload = Some(self.reader.read_bytes(len as usize)?);
+        self.reader.skip_bytes((len & 1) as usize)?;
+        self.pictures.push(source);
+        Ok(Some(Chunk::Unknown(id, len)))
+    }
+
+    /// Returns the `id3 ` chunks after the data chunk, the position is kept
+    fn find_trailing_id3_chunks(&mut self) -> Result<Vec<PictureSource>> {
+        let resume = self.reader.position();
+        self.reader.seek(std::io::SeekFrom::Start(self.data_end))?;
+        let mut sources = Vec::new();
+        loop {
+            let header = self.reader.peek(8)?;
+            if header.len() < 8 {
+                break;
+            }
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            if id3_chunk_header(header).is_some() {
+                sources.push(PictureSource {
+                    container: PictureContainer::Id3v2,
+                    offset: self.reader.position() + 8,
+                    len: len as u64,
+                    payload: None,
+                });
+            }
+            // a truncated chunk ends the trailer
+            if self
+                .reader
+                .skip_ahead(8 + len as u64 + (len & 1) as u64)
+                .is_err()
+            {
+                break;
+            }
+        }
+        self.reader.seek(std::io::SeekFrom::Start(resume))?;
+        Ok(sources)
+    }
+
+    /// Returns the next chunk as stored, without consuming it
+    ///
+    /// The samples of the data chunk are not peeked at, it is never kept.
+    fn peek_raw_chunk(&mut self) -> Result<Option<audio::RawChunk>> {
+        let offset = self.reader.position();
+        let header = self.reader.peek(8)?;
+        if header.len() < 8 {
+            return Ok(None);
+        }
+        let id = [header[0], header[1], header[2], header[3]];
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let payload = if &id != b"data" && len <= audio::RawHeaders::MAX_PAYLOAD_LEN {
+            Some(self.reader.peek(8 + len as usize)?[8..].to_vec())
+        } else {
+            None
+        };
+        Ok(Some(audio::RawChunk {
+            id,
+            offset,
+            len,
+            payload,
+        }))
     }
 }
 
+/// Returns the id and length of the chunk `header` starts, if it is an `id3 ` chunk
+fn id3_chunk_header(header: &[u8]) -> Option<([u8; 4], u32)> {
+    if header.len() < 8 || !(header.starts_with(b"id3 ") || header.starts_with(b"ID3 ")) {
+        return None;
+    }
+    let id = [header[0], header[1], header[2], header[3]];
+    Some((
+        id,
+        u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+    ))
+}
+
 impl AudioReader for WavReader {
     fn read_header(&mut self) -> Result<audio::AudioInfo> {
-        // WAVE file starts with the four bytes 'RIFF' and a file length.
-        if RIFF_MARKER != &(self.reader.read_bytes(4)?)[..] {
-            return errors::parse_error("no RIFF tag Found");
+        if self.header_read {
+            return errors::unsupported_error("header has already been read");
+        }
+        self.header_read = true;
+
+        // WAVE file starts with the four bytes 'RIFF' and a file length. The
+        // markers are only peeked at, so that a different reader can still be
+        // tried on the same input if they are missing.
+        let magic = self.reader.peek(12)?;
+        if magic.len() < 12 || RIFF_MARKER[..] != magic[..4] {
+            return errors::missing_magic_error("no RIFF tag Found");
         }
-        let _chunk_size = self.reader.read_le_u32()?;
 
         // Next four bytes indicate the file type, which should be WAVE.
-        if WAVE_MARKER != &(self.reader.read_bytes(4)?)[..] {
-            return errors::parse_error("no WAVE tag found");
+        if WAVE_MARKER[..] != magic[8..] {
+            return errors::missing_magic_error("no WAVE tag found");
         }
+        let start = self.reader.position();
+        self.reader.skip_bytes(12)?;
 
         // read until data chunk to get full info
         let mut info: Option<audio::AudioInfo> = None;
-        while let Some(chunk) = read_next_chunk(&mut self.reader)? {
-            if let Chunk::Fmt(audio_info) = chunk {
-                info = Some(audio_info);
-            } else if let Chunk::Data(data_len) = chunk {
-                if let Some(mut inf) = info {
-                    inf.total_samples = (data_len / (inf.bits_per_sample / 8)) as u64;
-                    return Ok(inf);
+        let mut fact_frames = None;
+        let mut chunks = 0u32;
+        while let Some(chunk) = self.read_chunk()? {
+            chunks += 1;
+            if chunks > self.options.max_header_chunks {
+                return errors::parse_error("header has more chunks than max_header_chunks");
+            }
+            if self.reader.position() - start > self.options.max_header_len {
+                return errors::parse_error("header is longer than max_header_len");
+            }
+            match self.add_cue_chunk(chunk) {
+                Some(Chunk::Fmt(audio_info)) => info = Some(audio_info),
+                Some(Chunk::Fact(frames)) => fact_frames = Some(frames as u64),
+                Some(Chunk::UnsupportedFmt(format)) => {
+                    return Err(errors::Error::UnsupportedWaveFormat(format))
+                }
+                Some(Chunk::Data(data_len)) => {
+                    if let Some(mut inf) = info {
+                        self.data_start = self.reader.position();
+                        self.stored_data_len = data_len;
+                        let data_len = self.clamp_data_len(data_len, &inf)?;
+                        inf.total_samples = if inf.codec_type == codecs::CodecType::CODEC_TYPE_MP3 {
+                            // the fact chunk counts the samples after the codec delay
+                            fact_frames.map_or(0, |frames| {
+                                let delay = inf.encoder_delay.unwrap_or(0) as u64;
+                                (frames + delay) * inf.channels.count() as u64
+                            })
+                        } else {
+                            (data_len / bytes_per_sample(&inf)) as u64
+                        };
+                        self.data_len = data_len;
+                        self.data_end = self.data_start + data_len as u64 + (data_len & 1) as u64;
+                        return Ok(inf);
+                    }
                 }
+                _ => {}
             }
         }
+        if info.is_some() {
+            return errors::parse_error("no 'data' chunk found");
+        }
         errors::parse_error("no 'fmt' chunk found")
     }
 
     fn buffer(&mut self) -> &mut AudioInputStream {
         &mut self.reader
     }
+
+    fn data_remaining(&mut self) -> Option<u64> {
+        let data_end = self.data_start + self.data_len as u64;
+        Some(data_end.saturating_sub(self.reader.position()))
+    }
+
+    fn into_buffer(self: Box<Self>) -> AudioInputStream {
+        self.reader
+    }
+
+    fn picture_sources(&mut self) -> Result<Vec<PictureSource>> {
+        let mut sources = self.pictures.clone();
+        // chunks after the data are only read with the trailer
+        if !self.trailer_read && self.reader.is_seekable() {
+            sources.extend(self.find_trailing_id3_chunks()?);
+        }
+        Ok(sources)
+    }
+
+    fn read_trailer(&mut self) {
+        if self.trailer_read {
+            return;
+        }
+        self.trailer_read = true;
+        // Chunks after the data are optional, a truncated or broken trailer
+        // only means that its cue points are lost.
+        let _ = self.read_trailing_chunks();
+    }
+
+    fn scan_length(&mut self, info: &audio::AudioInfo) -> Result<audio::ScanResult> {
+        let end = self.reader.seek(std::io::SeekFrom::End(0))?;
+        let available = end.saturating_sub(self.data_start);
+
+        // Writers which stream leave the length at 0 or the maximum, since it is
+        // not known when the header is written.
+        let declared = self.data_len as u64;
+        let data_len = if declared == 0 || declared == u32::MAX as u64 || declared > available {
+            available
+        } else {
+            declared
+        };
+
+        let block_align = info.channels.count() as u64 * bytes_per_sample(info) as u64;
+        if block_align == 0 {
+            return errors::parse_error("invalid block alignment");
+        }
+        Ok(audio::ScanResult::new(
+            data_len / block_align,
+            info.sample_rate,
+        ))
+    }
+
+    fn verify(&mut self, info: &audio::AudioInfo) -> Result<VerifyReport> {
+        #[cfg(feature = "mp3")]
+        {
+            if info.codec_type == codecs::CodecType::CODEC_TYPE_MP3 {
+                let remaining = self.data_len as u64;
+                return crate::mp3::verify_frames(&mut std::io::Read::take(
+                    &mut self.reader,
+                    remaining,
+                ));
+            }
+        }
+        let block_align = info.channels.count() as u64 * bytes_per_sample(info) as u64;
+        if block_align == 0 {
+            return errors::parse_error("invalid block alignment");
+        }
+        // PCM has nothing to decode, all of the data has to be present
+        let data = &mut std::io::Read::take(&mut self.reader, self.data_len as u64);
+        let read = std::io::copy(data, &mut std::io::sink())?;
+        let stored = self.stored_data_len as u64;
+        // writers which stream leave the length at 0 or the maximum
+        let is_known = stored != 0 && stored != u32::MAX as u64;
+        Ok(VerifyReport {
+            frames: read / block_align,
+            samples: read / block_align,
+            malformed_frames: (read % block_align != 0) as u64,
+            truncated: is_known && read < stored,
+            ..VerifyReport::default()
+        })
+    }
+
+    fn cue_points(&self) -> Vec<audio::CuePoint> {
+        join_labels(&self.cue_points, &self.labels)
+    }
+
+    fn sample_loops(&self) -> Vec<audio::SampleLoop> {
+        self.sample_loops.clone()
+    }
+
+    fn raw_headers(&self) -> Option<audio::RawHeaders> {
+        self.raw_headers.clone()
+    }
+
+    fn seek_frame(&mut self, frame: u64, info: &audio::AudioInfo) -> Result<()> {
+        let block_align = info.channels.count() as u64 * bytes_per_sample(info) as u64;
+        let offset = frame * block_align;
+        if offset > self.data_len as u64 {
+            return errors::unsupported_error("seek beyond the end of the data chunk");
+        }
+        self.reader
+            .seek(std::io::SeekFrom::Start(self.data_start + offset))?;
+        Ok(())
+    }
 }
 
 pub struct WavSamplesIterator<'r, S: Sample> {
     reader: &'r mut Box<dyn AudioReader + 'static>,
-    audio_info: &'r audio::AudioInfo,
+    /// the parameters of the header
+    audio_info: audio::AudioInfo,
+    /// set if integer samples have fewer valid bits than their container
+    is_padded: bool,
+    /// true if the length of the data chunk was checked against the input
+    length_known: bool,
     samples_left: u64,
+    trailer_read: bool,
+    /// offset of the first sample of the data chunk in the input
+    data_start: u64,
+    /// set after an IO error, until `resume_after_io_error`
+    io_failed: bool,
+    /// what to do with NaN and infinite float samples
+    non_finite: audio::NonFinite,
+    /// set once a replaced sample was warned about
+    non_finite_warned: bool,
+    /// counts the samples read and the time spent reading them if set
+    instrumentation: Option<InstrumentationHandle>,
+    /// start of the block of samples timed now and the samples read since
+    block: Option<(Instant, u64)>,
     phantom: std::marker::PhantomData<S>,
 }
 
+/// number of samples timed at once, WAV has no frames to time
+const INSTRUMENTED_BLOCK: u64 = 4096;
+
+impl<'r, S: Sample> WavSamplesIterator<'r, S> {
+    /// Returns the index of the next sample and its offset in the input
+    fn next_sample(&self) -> (u64, u64) {
+        let read = self.audio_info.total_samples - self.samples_left;
+        let offset = self.data_start + read * bytes_per_sample(&self.audio_info) as u64;
+        (read, offset)
+    }
+
+    /// Counts a sample read into the block timed now, recording it when full
+    fn count_sample(&mut self) {
+        if self.instrumentation.is_none() {
+            return;
+        }
+        let (_, samples) = self.block.get_or_insert_with(|| (Instant::now(), 0));
+        *samples += 1;
+        if *samples == INSTRUMENTED_BLOCK {
+            self.record_block();
+        }
+    }
+
+    /// Records the block timed now, the time includes the caller's between two samples
+    fn record_block(&mut self) {
+        if let (Some(handle), Some((start, samples))) = (&self.instrumentation, self.block.take()) {
+            let channels = self.audio_info.channels.count().max(1) as u64;
+            let bytes = samples * bytes_per_sample(&self.audio_info) as u64;
+            handle.record(samples / channels, bytes, start.elapsed());
+        }
+    }
+}
+
+impl<'r, S: Sample> Drop for WavSamplesIterator<'r, S> {
+    fn drop(&mut self) {
+        self.record_block();
+    }
+}
+
+/// Returns if the integer samples of `info` are stored with padding below their valid bits
+fn is_padded(info: &audio::AudioInfo) -> bool {
+    let is_signed_pcm = matches!(
+        info.codec_type,
+        codecs::CodecType::CODEC_TYPE_PCM_S16LE
+            | codecs::CodecType::CODEC_TYPE_PCM_S24LE
+            | codecs::CodecType::CODEC_TYPE_PCM_S32LE
+    );
+    is_signed_pcm && info.container_bits > info.bits_per_sample
+}
+
 impl<'r, S: Sample + 'r> WavSamplesIterator<'r, S> {
     pub fn new(
         reader: &'r mut Box<dyn AudioReader + 'static>,
-        info: &'r audio::AudioInfo,
+        info: audio::AudioInfo,
+        non_finite: audio::NonFinite,
+        instrumentation: Option<InstrumentationHandle>,
     ) -> Box<Self> {
         Box::new(WavSamplesIterator {
+            // inputs which can not seek may end before the data chunk does
+            length_known: reader.buffer().is_seekable(),
+            data_start: reader.buffer().position(),
             reader,
-            audio_info: info,
             samples_left: info.total_samples,
+            is_padded: is_padded(&info),
+            audio_info: info,
+            trailer_read: false,
+            io_failed: false,
+            non_finite,
+            non_finite_warned: false,
+            instrumentation,
+            block: None,
             phantom: std::marker::PhantomData,
         })
     }
@@ -74,12 +564,932 @@ impl<'r, S: Sample + 'r> WavSamplesIterator<'r, S> {
 
 impl<'r, S: Sample> AudioSamplesIterator<S> for WavSamplesIterator<'r, S> {
     fn next(&mut self) -> Option<Result<S>> {
+        if self.io_failed {
+            return None;
+        }
         if self.samples_left > 0 {
-            let sample = Sample::read_pcm(&mut self.reader.buffer(), self.audio_info.codec_type);
+            let sample = if self.is_padded {
+                let info = &self.audio_info;
+                S::read_pcm_in_container(
+                    &mut self.reader.buffer(),
+                    info.container_bits,
+                    info.bits_per_sample,
+                )
+            } else {
+                Sample::read_pcm(&mut self.reader.buffer(), self.audio_info.codec_type)
+            };
+            // Inputs which can not seek may end before the length of the data
+            // chunk, which is all right between two frames.
+            if let Err(errors::Error::IoError(ref error)) = sample {
+                let channels = self.audio_info.channels.count().max(1) as u64;
+                let read = self.audio_info.total_samples - self.samples_left;
+                if error.kind() == std::io::ErrorKind::UnexpectedEof && read % channels == 0 {
+                    tracing::warn!(
+                        missing = self.samples_left,
+                        "WAV input ended before the end of the data chunk"
+                    );
+                    self.samples_left = 0;
+                    self.trailer_read = true;
+                    return None;
+                }
+                // the sample is read again by `resume_after_io_error`
+                self.io_failed = true;
+                return Some(sample);
+            }
             self.samples_left -= 1;
+            self.count_sample();
+            let replacement = match sample {
+                Ok(sample) => sample.non_finite_replacement(),
+                Err(_) => None,
+            };
+            if let Some(replacement) = replacement {
+                let index = self.audio_info.total_samples - self.samples_left - 1;
+                if self.non_finite == audio::NonFinite::Error {
+                    return Some(Err(errors::Error::NonFiniteSample { sample: index }));
+                }
+                // a broken export may have countless of them, once is enough
+                if !self.non_finite_warned {
+                    self.non_finite_warned = true;
+                    tracing::warn!(sample = index, "NaN or infinite WAV sample replaced");
+                }
+                return Some(Ok(replacement));
+            }
             return Some(sample);
         }
 
+        self.record_block();
+        if !self.trailer_read {
+            self.trailer_read = true;
+            self.reader.read_trailer();
+        }
         None
     }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.record_block();
+        self.reader.seek_frame(frame, &self.audio_info)?;
+        let channels = self.audio_info.channels.count() as u64;
+        self.samples_left = self.audio_info.total_samples - frame * channels;
+        self.io_failed = false;
+        Ok(())
+    }
+
+    fn samples_left(&self) -> Option<u64> {
+        if self.length_known {
+            Some(self.samples_left)
+        } else {
+            None
+        }
+    }
+
+    fn remaining_frames(&self) -> Option<u64> {
+        let channels = self.audio_info.channels.count() as u64;
+        Some(io::frames_of(self.samples_left()?, channels))
+    }
+
+    fn sample_rate(&self) -> Option<u32> {
+        Some(self.audio_info.sample_rate)
+    }
+
+    fn last_good_position(&self) -> Option<audio::StreamPosition> {
+        let (read, offset) = self.next_sample();
+        let channels = self.audio_info.channels.count().max(1) as u64;
+        Some(audio::StreamPosition {
+            frame: read / channels,
+            offset,
+        })
+    }
+
+    fn resume_after_io_error(&mut self) -> Result<()> {
+        if self.io_failed {
+            let (_, offset) = self.next_sample();
+            self.reader.buffer().rewind(offset)?;
+            self.io_failed = false;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cue_points() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let cue = |points: &[(u32, u32)]| {
+        let mut payload = (points.len() as u32).to_le_bytes().to_vec();
+        for &(id, offset) in points {
+            payload.extend_from_slice(&id.to_le_bytes());
+            payload.extend_from_slice(&[0; 4]);
+            payload.extend_from_slice(b"data");
+            payload.extend_from_slice(&[0; 8]);
+            payload.extend_from_slice(&offset.to_le_bytes());
+        }
+        riff_chunk(b"cue ", &payload)
+    };
+    let adtl = |labels: &[(&[u8; 4], u32, &str)]| {
+        let mut payload = b"adtl".to_vec();
+        for &(kind, id, text) in labels {
+            let mut label = id.to_le_bytes().to_vec();
+            label.extend_from_slice(text.as_bytes());
+            label.push(0);
+            payload.extend_from_slice(&riff_chunk(kind, &label));
+        }
+        riff_chunk(b"LIST", &payload)
+    };
+    let samples: Vec<u8> = (0..40i16).flat_map(|s| s.to_le_bytes().to_vec()).collect();
+    let fmt = riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000));
+    let data = riff_chunk(b"data", &samples);
+    let labels = adtl(&[
+        (b"labl", 2, "Chapter 2"),
+        (b"note", 1, "intro"),
+        (b"labl", 1, "Chapter 1"),
+    ]);
+    let cues = cue(&[(1, 0), (2, 25)]);
+
+    let read_cue_points = |file: Vec<u8>| {
+        let mut segment = audio::AudioSegment::from_reader(
+            std::io::Cursor::new(file),
+            crate::codecs::FormatFlag::WAV,
+        )
+        .unwrap();
+        let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, (0..40).collect::<Vec<i16>>());
+        segment.cue_points()
+    };
+    let expected = vec![
+        audio::CuePoint {
+            id: 1,
+            sample_offset: 0,
+            index: None,
+            label: Some("Chapter 1".to_string()),
+            note: Some("intro".to_string()),
+        },
+        audio::CuePoint {
+            id: 2,
+            sample_offset: 25,
+            index: None,
+            label: Some("Chapter 2".to_string()),
+            note: None,
+        },
+    ];
+
+    // labels before or after the cue chunk, both before or after the data
+    let orders = vec![
+        vec![fmt.clone(), cues.clone(), labels.clone(), data.clone()],
+        vec![fmt.clone(), labels.clone(), cues.clone(), data.clone()],
+        vec![fmt.clone(), data.clone(), cues.clone(), labels.clone()],
+        vec![fmt.clone(), data.clone(), labels.clone(), cues.clone()],
+        vec![fmt.clone(), labels.clone(), data.clone(), cues.clone()],
+    ];
+    for chunks in orders {
+        assert_eq!(read_cue_points(wav_file(&chunks)), expected);
+    }
+
+    // cue points without adtl list
+    let cue_points = read_cue_points(wav_file(&[fmt.clone(), data.clone(), cues]));
+    assert_eq!(cue_points.len(), 2);
+    assert!(cue_points
+        .iter()
+        .all(|c| c.label.is_none() && c.note.is_none()));
+
+    // no cue points at all
+    assert!(read_cue_points(wav_file(&[fmt, data])).is_empty());
+}
+
+#[test]
+fn test_read_header_twice() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"data", &[1, 0, 2, 0]),
+    ]);
+    let input = crate::io::seekable_stream(std::io::Cursor::new(file)).unwrap();
+    let mut reader = WavReader::new(input, Default::default()).unwrap();
+
+    assert_eq!(reader.read_header().unwrap().total_samples, 2);
+    match reader.read_header() {
+        Err(errors::Error::Unsupported(_)) => {}
+        _ => panic!("expected the second call to fail"),
+    }
+    // the audio data is left untouched
+    assert_eq!(reader.buffer().read_le_u16().unwrap(), 1);
+}
+
+#[test]
+fn test_scan_length() {
+    use crate::testing::{pcm16_fmt, wav_file};
+
+    // a streamed file, the data length was never filled in
+    let mut data = b"data".to_vec();
+    data.extend_from_slice(&u32::MAX.to_le_bytes());
+    data.extend_from_slice(&[0; 4 * 100]);
+    let file = wav_file(&[
+        crate::testing::riff_chunk(b"fmt ", &pcm16_fmt(2, 10_000)),
+        data,
+    ]);
+
+    let mut segment = audio::AudioSegment::from_seekable_reader(
+        std::io::Cursor::new(file),
+        crate::codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    let scan = segment.scan_length().unwrap();
+    assert_eq!(scan.frames, 100);
+    assert_eq!(scan.duration, std::time::Duration::from_millis(10));
+}
+
+#[test]
+fn test_piped_data_chunk_without_length() {
+    use crate::testing::{pcm16_fmt, wav_file};
+
+    // a writer to a pipe leaves the length at 0
+    let mut data = b"data".to_vec();
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend((0..8i16).flat_map(|s| s.to_le_bytes()));
+    let file = wav_file(&[
+        crate::testing::riff_chunk(b"fmt ", &pcm16_fmt(2, 8_000)),
+        data,
+    ]);
+
+    let mut segment = audio::AudioSegment::from_reader(
+        std::io::Cursor::new(file.clone()),
+        crate::codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, (0..8).collect::<Vec<i16>>());
+
+    // a seekable file with an empty data chunk stays empty
+    let mut segment = audio::AudioSegment::from_seekable_reader(
+        std::io::Cursor::new(file),
+        crate::codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    assert_eq!(segment.samples::<i16>().unwrap().count(), 0);
+}
+
+#[test]
+fn test_data_chunk_longer_than_file() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    // 10 stereo frames and half a frame, the header claims 100 frames
+    let data: Vec<u8> = (0..21i16).flat_map(|s| s.to_le_bytes()).collect();
+    let mut file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 8_000)),
+        riff_chunk(b"data", &data),
+    ]);
+    let data_len_offset = file.len() - data.len() - 4;
+    file[data_len_offset..data_len_offset + 4].copy_from_slice(&400u32.to_le_bytes());
+
+    let decode = |segment: &mut audio::AudioSegment| -> Vec<Result<i16>> {
+        segment.samples::<i16>().unwrap().collect()
+    };
+
+    // the length is known from seeking, only whole frames are decoded
+    let input = std::io::Cursor::new(file.clone());
+    let mut segment =
+        audio::AudioSegment::from_seekable_reader(input, crate::codecs::FormatFlag::WAV).unwrap();
+    assert_eq!(segment.info().total_samples, 20);
+    let samples: Vec<i16> = decode(&mut segment)
+        .into_iter()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(samples, (0..20).collect::<Vec<i16>>());
+
+    // otherwise the input ends within a frame, which is an error
+    let input = std::io::Cursor::new(file.clone());
+    let mut segment =
+        audio::AudioSegment::from_reader(input, crate::codecs::FormatFlag::WAV).unwrap();
+    assert_eq!(segment.info().total_samples, 200);
+    let samples = decode(&mut segment);
+    assert_eq!(samples.len(), 22);
+    assert!(samples[..21].iter().all(|s| s.is_ok()));
+    assert!(samples[21].is_err());
+
+    // but ending between two frames just ends the samples
+    file.truncate(file.len() - 2);
+    let input = std::io::Cursor::new(file);
+    let mut segment =
+        audio::AudioSegment::from_reader(input, crate::codecs::FormatFlag::WAV).unwrap();
+    let samples = decode(&mut segment);
+    assert_eq!(samples.len(), 20);
+    assert!(samples.iter().all(|s| s.is_ok()));
+}
+
+#[test]
+fn test_verify() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let data: Vec<u8> = (0..21i16).flat_map(|s| s.to_le_bytes()).collect();
+    let mut file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 8_000)),
+        riff_chunk(b"data", &data[..40]),
+    ]);
+    let verify = |file: &[u8], seekable: bool| {
+        let input = std::io::Cursor::new(file.to_vec());
+        let mut segment = if seekable {
+            audio::AudioSegment::from_seekable_reader(input, crate::codecs::FormatFlag::WAV)
+        } else {
+            audio::AudioSegment::from_reader(input, crate::codecs::FormatFlag::WAV)
+        }
+        .unwrap();
+        segment.verify().unwrap()
+    };
+    let report = verify(&file, false);
+    assert_eq!(report.frames, 10);
+    assert!(report.is_ok());
+
+    // 10 stereo frames and half a frame, the header claims 100 frames
+    file.truncate(file.len() - 40);
+    file.extend_from_slice(&data);
+    let data_len_offset = file.len() - data.len() - 4;
+    file[data_len_offset..data_len_offset + 4].copy_from_slice(&400u32.to_le_bytes());
+    for seekable in &[true, false] {
+        let report = verify(&file, *seekable);
+        assert_eq!(report.frames, 10);
+        assert!(report.truncated);
+    }
+    // only an input which can not seek reads the half frame
+    assert_eq!(verify(&file, false).malformed_frames, 1);
+}
+
+#[test]
+fn test_raw_headers() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let info = b"INFOISFT\x04\x00\x00\x00test".to_vec();
+    let large = vec![7; audio::RawHeaders::MAX_PAYLOAD_LEN as usize + 2];
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)),
+        riff_chunk(b"LIST", &info),
+        riff_chunk(b"JUNK", &large),
+        riff_chunk(b"data", &[1, 0, 2, 0]),
+        riff_chunk(b"bext", &[3; 5]),
+    ]);
+    let options = audio::DecodeOptions {
+        keep_raw_headers: true,
+        ..Default::default()
+    };
+    let mut segment = audio::AudioSegment::from_reader_with_options(
+        std::io::Cursor::new(file.clone()),
+        crate::codecs::FormatFlag::WAV,
+        options,
+    )
+    .unwrap();
+
+    let raw = segment.raw_headers().unwrap();
+    assert_eq!(raw.fmt_chunk, Some(pcm16_fmt(1, 8_000)));
+    let list = &raw.unknown_chunks[0];
+    assert_eq!(&list.id, b"LIST");
+    assert_eq!(list.offset, 12 + 8 + 16);
+    assert_eq!(list.payload.as_deref(), Some(&info[..]));
+    // too large to be kept, but still listed
+    let junk = &raw.unknown_chunks[1];
+    assert_eq!(&junk.id, b"JUNK");
+    assert_eq!(junk.len as usize, large.len());
+    assert_eq!(junk.payload, None);
+    assert_eq!(raw.unknown_chunks.len(), 2);
+
+    // chunks after the data are known once the samples are read
+    segment.samples::<i16>().unwrap().for_each(drop);
+    let raw = segment.raw_headers().unwrap();
+    let bext = &raw.unknown_chunks[2];
+    assert_eq!(&bext.id, b"bext");
+    assert_eq!(bext.offset as usize, file.len() - 14);
+    assert_eq!(bext.payload, Some(vec![3; 5]));
+}
+
+#[test]
+fn test_unsupported_format_tag() {
+    use crate::codecs::WaveFormat;
+    use crate::testing::{riff_chunk, wav_file};
+
+    let read = |fmt: &[u8]| {
+        let file = wav_file(&[riff_chunk(b"fmt ", fmt), riff_chunk(b"data", &[0; 65])]);
+        audio::AudioSegment::from_reader(std::io::Cursor::new(file), crate::codecs::FormatFlag::WAV)
+    };
+
+    // GSM 6.10, 8 kHz mono with 320 samples per block of 65 bytes
+    let mut gsm = Vec::new();
+    gsm.extend_from_slice(&0x0031u16.to_le_bytes());
+    gsm.extend_from_slice(&1u16.to_le_bytes());
+    gsm.extend_from_slice(&8_000u32.to_le_bytes());
+    gsm.extend_from_slice(&1_625u32.to_le_bytes());
+    gsm.extend_from_slice(&65u16.to_le_bytes());
+    gsm.extend_from_slice(&0u16.to_le_bytes());
+    gsm.extend_from_slice(&2u16.to_le_bytes());
+    gsm.extend_from_slice(&320u16.to_le_bytes());
+    let error = read(&gsm).err().expect("GSM is not supported");
+    assert_eq!(
+        error.to_string(),
+        "Unsupported codec encountered: WAV format tag 0x0031 (GSM 6.10), 8000 Hz, 1 channel(s)"
+    );
+    match error {
+        errors::Error::UnsupportedWaveFormat(format) => assert_eq!(
+            format,
+            WaveFormat {
+                format_tag: 0x0031,
+                channels: 1,
+                sample_rate: 8_000,
+                bytes_per_second: 1_625,
+                block_align: 65,
+                bits_per_sample: 0,
+            }
+        ),
+        _ => panic!("expected the fmt chunk in the error"),
+    }
+
+    // tags missing from the table are still reported
+    gsm[..2].copy_from_slice(&0x1234u16.to_le_bytes());
+    let error = read(&gsm).err().unwrap();
+    assert!(error.to_string().contains("tag 0x1234, 8000 Hz"));
+}
+
+#[test]
+#[cfg(feature = "mp3")]
+fn test_mp3_in_wav() {
+    use crate::testing::{riff_chunk, wav_file};
+
+    // MPEG1 layer 3, 128 kb/s, 44.1kHz, mono, frames of 417 bytes without main data
+    let frame = |data: &mut Vec<u8>| {
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        data.extend_from_slice(&[0; 413]);
+    };
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&0x0055u16.to_le_bytes());
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&44_100u32.to_le_bytes());
+    fmt.extend_from_slice(&16_000u32.to_le_bytes());
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&0u16.to_le_bytes());
+    // the MPEGLAYER3WAVEFORMAT extension, with a codec delay of 1105 samples
+    fmt.extend_from_slice(&12u16.to_le_bytes());
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&2u32.to_le_bytes());
+    fmt.extend_from_slice(&417u16.to_le_bytes());
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&1105u16.to_le_bytes());
+    let mut data = Vec::new();
+    for _ in 0..3 {
+        frame(&mut data);
+    }
+    // a frame after the data chunk must not be decoded
+    let mut trailer = b"junk".to_vec();
+    frame(&mut trailer);
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &fmt),
+        riff_chunk(b"fact", &2_000u32.to_le_bytes()),
+        riff_chunk(b"data", &data),
+        riff_chunk(b"LIST", &trailer),
+    ]);
+
+    let read = |trim_encoder_padding| {
+        let options = audio::DecodeOptions {
+            trim_encoder_padding,
+            ..Default::default()
+        };
+        audio::AudioSegment::from_reader_with_options(
+            std::io::Cursor::new(file.clone()),
+            codecs::FormatFlag::WAV,
+            options,
+        )
+        .unwrap()
+    };
+
+    let mut segment = read(true);
+    assert_eq!(segment.info().codec_type, codecs::CodecType::CODEC_TYPE_MP3);
+    assert_eq!(segment.info().sample_rate, 44_100);
+    assert_eq!(segment.info().total_samples, 3105);
+    assert_eq!(segment.info().encoder_delay, Some(1105));
+    assert_eq!(segment.samples::<f32>().unwrap().count(), 2000);
+
+    let mut segment = read(false);
+    assert_eq!(segment.samples::<f32>().unwrap().count(), 3 * 1152);
+}
+
+#[test]
+fn test_reorder_channels() {
+    use crate::audio::Channels;
+    use crate::testing::{riff_chunk, wav_file};
+
+    // an extensible WAV whose channels hold their index in the stream
+    let read = |channel_mask: u32, channels: u16, reorder_channels| {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&0xfffeu16.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&8_000u32.to_le_bytes());
+        fmt.extend_from_slice(&(8_000 * 2 * channels as u32).to_le_bytes());
+        fmt.extend_from_slice(&(2 * channels).to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&channel_mask.to_le_bytes());
+        fmt.extend_from_slice(&KSDATAFORMAT_SUBTYPE_PCM);
+        let data: Vec<u8> = (0..3)
+            .flat_map(|frame| (0..channels).map(move |c| (100 * frame + c) as i16))
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let file = wav_file(&[riff_chunk(b"fmt ", &fmt), riff_chunk(b"data", &data)]);
+        let options = audio::DecodeOptions {
+            reorder_channels,
+            ..Default::default()
+        };
+        audio::AudioSegment::from_reader_with_options(
+            std::io::Cursor::new(file),
+            crate::codecs::FormatFlag::WAV,
+            options,
+        )
+        .unwrap()
+    };
+    let frames = |segment: &mut audio::AudioSegment| -> Vec<i16> {
+        segment
+            .samples::<i16>()
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect()
+    };
+
+    // 5.1 is stored as FL, FR, FC, LFE, BL, BR
+    let mut segment = read(0x3f, 6, false);
+    assert_eq!(frames(&mut segment)[..6], [0, 1, 2, 3, 4, 5]);
+    assert_eq!(segment.channel_order()[3], Channels::LFE1);
+
+    // and returned as FL, FR, FC, BL, BR, LFE
+    let mut segment = read(0x3f, 6, true);
+    let order = segment.channel_order();
+    assert_eq!(
+        order,
+        vec![
+            Channels::FRONT_LEFT,
+            Channels::FRONT_RIGHT,
+            Channels::FRONT_CENTRE,
+            Channels::BACK_LEFT,
+            Channels::BACK_RIGHT,
+            Channels::LFE1
+        ]
+    );
+    let samples = frames(&mut segment);
+    assert_eq!(samples.len(), 18);
+    assert_eq!(samples[..6], [0, 1, 2, 4, 5, 3]);
+    assert_eq!(samples[12..], [200, 201, 202, 204, 205, 203]);
+
+    // 6.1 is stored as FL, FR, FC, LFE, BC, SL, SR
+    let mut segment = read(0x70f, 7, true);
+    assert_eq!(frames(&mut segment)[..7], [0, 1, 2, 4, 3, 5, 6]);
+
+    // selected channels are indexed and returned in the canonical order
+    let mut segment = read(0x3f, 6, true);
+    let samples: Vec<i16> = segment
+        .channel_samples::<i16>(&[5, 3])
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(samples[..4], [4, 3, 104, 103]);
+}
+
+#[test]
+fn test_plain_fmt_with_more_channels() {
+    use crate::audio::ChannelLayout;
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let read = |channels: u16| {
+        // each sample holds its frame and channel
+        let data: Vec<u8> = (0..4)
+            .flat_map(|frame| (0..channels).map(move |c| (100 * frame + c) as i16))
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let file = wav_file(&[
+            riff_chunk(b"fmt ", &pcm16_fmt(channels, 48_000)),
+            riff_chunk(b"data", &data),
+        ]);
+        audio::AudioSegment::from_reader(std::io::Cursor::new(file), codecs::FormatFlag::WAV)
+    };
+
+    let mut segment = read(6).unwrap();
+    assert_eq!(segment.info().channel_layout, ChannelLayout::FivePointOne);
+    assert_eq!(segment.info().channels.count(), 6);
+    assert_eq!(segment.info().total_samples, 4 * 6);
+    let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    let expected: Vec<i16> = (0..4)
+        .flat_map(|frame| (0..6).map(move |c| 100 * frame + c))
+        .collect();
+    assert_eq!(samples, expected);
+
+    for &(channels, layout) in &[
+        (3, ChannelLayout::ThreePointZero),
+        (4, ChannelLayout::Quad),
+        (8, ChannelLayout::SevenPointOne),
+    ] {
+        let segment = read(channels).unwrap();
+        assert_eq!(segment.info().channel_layout, layout);
+        assert_eq!(segment.info().channels.count(), channels as usize);
+    }
+    assert!(read(9).is_err());
+}
+
+#[test]
+fn test_header_limits() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let mut chunks = vec![riff_chunk(b"junk", &[0; 2]); 20];
+    chunks.push(riff_chunk(b"fmt ", &pcm16_fmt(1, 8_000)));
+    chunks.push(riff_chunk(b"data", &[1, 0, 2, 0]));
+    let file = wav_file(&chunks);
+    let read = |options| {
+        audio::AudioSegment::from_reader_with_options(
+            std::io::Cursor::new(file.clone()),
+            crate::codecs::FormatFlag::WAV,
+            options,
+        )
+    };
+    let error = |options| match read(options) {
+        Err(errors::Error::ParseError(msg)) => msg,
+        _ => panic!("expected the header to be rejected"),
+    };
+
+    assert!(read(Default::default()).is_ok());
+    let options = audio::DecodeOptions {
+        max_header_chunks: 21,
+        ..Default::default()
+    };
+    assert!(error(options).contains("max_header_chunks"));
+    let options = audio::DecodeOptions {
+        max_header_len: 100,
+        ..Default::default()
+    };
+    assert!(error(options).contains("max_header_len"));
+}
+
+#[test]
+fn test_sample_rate_bounds() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let read = |sample_rate: u32| {
+        let file = wav_file(&[
+            riff_chunk(b"fmt ", &pcm16_fmt(2, sample_rate)),
+            riff_chunk(b"data", &[0; 8]),
+        ]);
+        audio::AudioSegment::from_reader(std::io::Cursor::new(file), crate::codecs::FormatFlag::WAV)
+    };
+
+    for sample_rate in &[0, audio::MAX_SAMPLE_RATE + 1] {
+        match read(*sample_rate) {
+            Err(errors::Error::ParseError(_)) => {}
+            other => panic!("{} Hz gave {:?}", sample_rate, other.map(|s| s.duration())),
+        }
+    }
+
+    // unusual rates only warn
+    let segment = read(audio::WARN_SAMPLE_RATE * 2).unwrap();
+    assert_eq!(segment.info().sample_rate, 1_536_000);
+    assert_eq!(segment.duration().as_micros(), 1);
+}
+
+#[test]
+fn test_inconsistent_fmt_fields() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let read = |fmt: &[u8], lenient_fmt| {
+        let file = wav_file(&[riff_chunk(b"fmt ", fmt), riff_chunk(b"data", &[0; 16])]);
+        let options = audio::DecodeOptions {
+            lenient_fmt,
+            ..Default::default()
+        };
+        audio::AudioSegment::from_reader_with_options(
+            std::io::Cursor::new(file),
+            crate::codecs::FormatFlag::WAV,
+            options,
+        )
+    };
+
+    // a byte rate of 0 and a block align of one channel only
+    let mut byte_rate = pcm16_fmt(2, 44_100);
+    byte_rate[8..12].copy_from_slice(&0u32.to_le_bytes());
+    let mut block_align = pcm16_fmt(2, 44_100);
+    block_align[8..12].copy_from_slice(&(44_100u32 * 2).to_le_bytes());
+    block_align[12..14].copy_from_slice(&2u16.to_le_bytes());
+    for fmt in &[byte_rate, block_align] {
+        assert!(matches!(
+            read(fmt, false),
+            Err(errors::Error::ParseError(_))
+        ));
+        let segment = read(fmt, true).unwrap();
+        assert_eq!(segment.info().total_samples, 8);
+    }
+
+    // WAVEFORMATEXTENSIBLE with the valid bits as bits per sample, block align
+    // telling the 32 bit container
+    let mut fmt = pcm16_fmt(2, 44_100);
+    fmt[0..2].copy_from_slice(&0xfffeu16.to_le_bytes());
+    fmt[8..12].copy_from_slice(&(44_100u32 * 8).to_le_bytes());
+    fmt[12..16].copy_from_slice(&[8, 0, 24, 0]);
+    fmt.extend_from_slice(&[22, 0, 24, 0, 3, 0, 0, 0]);
+    fmt.extend_from_slice(&chunks::KSDATAFORMAT_SUBTYPE_PCM);
+    let segment = read(&fmt, false).unwrap();
+    assert_eq!(
+        segment.info().codec_type,
+        crate::codecs::CodecType::CODEC_TYPE_PCM_S24LE
+    );
+    assert_eq!(segment.info().bits_per_sample, 24);
+    assert_eq!(segment.info().container_bits, 32);
+    assert_eq!(segment.info().total_samples, 4);
+
+    // a container too small for the samples tells nothing to go by
+    fmt[12..14].copy_from_slice(&4u16.to_le_bytes());
+    assert!(read(&fmt, true).is_err());
+}
+
+#[test]
+fn test_extensible_valid_bits() {
+    use crate::codecs::CodecType;
+    use crate::testing::{riff_chunk, wav_file};
+
+    // a mono WAVEFORMATEXTENSIBLE fmt chunk of `bits` bits per sample in
+    // containers of `container` bytes, `valid` of them valid
+    let ext_fmt = |bits: u16, container: u16, valid: u16, sub_format: [u8; 16]| {
+        let mut fmt = 0xfffeu16.to_le_bytes().to_vec();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&44_100u32.to_le_bytes());
+        fmt.extend_from_slice(&(44_100 * container as u32).to_le_bytes());
+        fmt.extend_from_slice(&container.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&valid.to_le_bytes());
+        fmt.extend_from_slice(&4u32.to_le_bytes());
+        fmt.extend_from_slice(&sub_format);
+        fmt
+    };
+    let read = |fmt: &[u8], data: &[u8]| {
+        let file = wav_file(&[riff_chunk(b"fmt ", fmt), riff_chunk(b"data", data)]);
+        audio::AudioSegment::from_reader(std::io::Cursor::new(file), crate::codecs::FormatFlag::WAV)
+    };
+    let pcm = chunks::KSDATAFORMAT_SUBTYPE_PCM;
+
+    // 20 valid bits, the padding below them is dropped
+    let padded = (0x12345 << 4 | 0xf) as u32;
+    let mut segment = read(&ext_fmt(24, 3, 20, pcm), &padded.to_le_bytes()[..3]).unwrap();
+    let info = segment.info().clone();
+    assert_eq!(info.codec_type, CodecType::CODEC_TYPE_PCM_S24LE);
+    assert_eq!((info.bits_per_sample, info.container_bits), (20, 24));
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, [0x12345]);
+
+    // in a 32 bit container, and with 24 valid bits
+    let padded = (-0x12345 << 12 | 0xabc) as u32;
+    let mut segment = read(&ext_fmt(32, 4, 20, pcm), &padded.to_le_bytes()).unwrap();
+    assert_eq!(segment.info().codec_type, CodecType::CODEC_TYPE_PCM_S24LE);
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, [-0x12345]);
+    let mut segment = read(&ext_fmt(24, 3, 24, pcm), &[1, 2, 3]).unwrap();
+    assert_eq!(segment.info().codec_type, CodecType::CODEC_TYPE_PCM_S24LE);
+    assert_eq!(segment.info().bits_per_sample, 24);
+    let samples: Vec<i32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, [0x030201]);
+
+    // widths which can not be decoded fail to parse instead of panicking
+    let invalid = [
+        // fewer valid bits than the narrowest codec of the container
+        ext_fmt(16, 2, 4, pcm),
+        // bits per sample not filling whole bytes
+        ext_fmt(20, 3, 20, pcm),
+        // more valid bits than the container holds
+        ext_fmt(24, 3, 32, pcm),
+        // integer samples wider than 32 bits
+        ext_fmt(40, 5, 40, pcm),
+        ext_fmt(24, 3, 24, chunks::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT),
+        // a block align of 0, which sample counts would be divided by
+        ext_fmt(8, 0, 0, chunks::KSDATAFORMAT_SUBTYPE_ALAW),
+        ext_fmt(0, 0, 0, pcm),
+        // G.711 in other containers than 8 bits
+        ext_fmt(8, 2, 8, chunks::KSDATAFORMAT_SUBTYPE_ALAW),
+        ext_fmt(16, 2, 16, chunks::KSDATAFORMAT_SUBTYPE_MULAW),
+    ];
+    for fmt in &invalid {
+        assert!(matches!(
+            read(fmt, &[0; 120]),
+            Err(errors::Error::ParseError(_))
+        ));
+    }
+}
+
+#[test]
+fn test_extensible_channel_layouts() {
+    use crate::audio::ChannelLayout;
+    use crate::testing::{riff_chunk, wav_file};
+
+    // 16 bit PCM of `channels` for the speakers of `mask`, 10 ms at 44.1 kHz
+    let read = |channels: u16, mask: u32| {
+        let mut fmt = 0xfffeu16.to_le_bytes().to_vec();
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&44_100u32.to_le_bytes());
+        fmt.extend_from_slice(&(44_100 * 2 * channels as u32).to_le_bytes());
+        fmt.extend_from_slice(&(2 * channels).to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&mask.to_le_bytes());
+        fmt.extend_from_slice(&chunks::KSDATAFORMAT_SUBTYPE_PCM);
+        let data = vec![0; 441 * 2 * channels as usize];
+        let file = wav_file(&[riff_chunk(b"fmt ", &fmt), riff_chunk(b"data", &data)]);
+        audio::AudioSegment::from_reader(std::io::Cursor::new(file), crate::codecs::FormatFlag::WAV)
+    };
+
+    let layouts = [
+        // 2.1 is preferred over the 3.0 of 3 channels
+        (3, 0xb, ChannelLayout::TwoPointOne),
+        (5, 0x37, ChannelLayout::FivePointZero),
+        // 5.0 with side speakers
+        (5, 0x607, ChannelLayout::FivePointZero),
+        (7, 0x70f, ChannelLayout::SixPointOne),
+        (7, 0x13f, ChannelLayout::SixPointOneBack),
+        // without a mask the layout is the one of the count
+        (5, 0, ChannelLayout::FivePointZero),
+        (6, 0, ChannelLayout::FivePointOne),
+        // and so it is if the mask has other speakers than channels
+        (6, 0x3, ChannelLayout::FivePointOne),
+        (2, 0x3f, ChannelLayout::Stereo),
+        // or only bits of no speaker
+        (2, 0x8000_0000, ChannelLayout::Stereo),
+    ];
+    for &(channels, mask, layout) in &layouts {
+        let segment = read(channels, mask).unwrap();
+        assert_eq!(segment.info().channel_layout, layout, "mask {:#x}", mask);
+        assert_eq!(segment.number_channels(), channels as usize);
+        assert_eq!(segment.duration(), std::time::Duration::from_millis(10));
+    }
+    assert!(matches!(read(9, 0), Err(errors::Error::ParseError(_))));
+}
+
+#[test]
+fn test_instrumentation() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    // one full block of 4096 samples and a partial one
+    let samples: Vec<u8> = (0..5000i16)
+        .flat_map(|s| s.to_le_bytes().to_vec())
+        .collect();
+    let file = wav_file(&[
+        riff_chunk(b"fmt ", &pcm16_fmt(2, 8_000)),
+        riff_chunk(b"data", &samples),
+    ]);
+    let mut segment = audio::AudioSegment::from_reader_with_options(
+        std::io::Cursor::new(file),
+        codecs::FormatFlag::WAV,
+        audio::DecodeOptions {
+            instrumentation: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let handle = segment.instrumentation_handle().unwrap();
+    let mut iter = segment.samples::<i16>().unwrap();
+    for _ in 0..4096 {
+        iter.next();
+    }
+    assert_eq!(handle.get().blocks, 1);
+    assert_eq!(handle.get().frames, 2048);
+    iter.for_each(drop);
+
+    let instrumentation = segment.instrumentation().unwrap();
+    assert_eq!(instrumentation.blocks, 2);
+    assert_eq!(instrumentation.frames, 2500);
+    assert_eq!(instrumentation.bytes, 10_000);
+    assert_eq!(instrumentation.sample_rate, 8_000);
+}
+
+#[test]
+fn test_empty_and_header_only_files() {
+    use crate::testing::{pcm16_fmt, riff_chunk, wav_file};
+
+    let read = |file: Vec<u8>, seekable| {
+        let flag = crate::codecs::FormatFlag::WAV;
+        if seekable {
+            audio::AudioSegment::from_seekable_reader(std::io::Cursor::new(file), flag)
+        } else {
+            audio::AudioSegment::from_reader(std::io::Cursor::new(file), flag)
+        }
+    };
+    for &seekable in &[false, true] {
+        for file in &[Vec::new(), b"not a wav file at all".to_vec()] {
+            assert!(matches!(
+                read(file.clone(), seekable),
+                Err(errors::Error::MissingMagic(_))
+            ));
+        }
+
+        // an empty data chunk, which is not taken to last until the end of a pipe
+        let header = wav_file(&[
+            riff_chunk(b"fmt ", &pcm16_fmt(2, 44_100)),
+            riff_chunk(b"data", &[]),
+        ]);
+        let mut segment = read(header, seekable).unwrap();
+        assert_eq!(segment.info().total_samples, 0);
+        assert!(segment.samples::<i16>().unwrap().next().is_none());
+
+        let no_data = wav_file(&[riff_chunk(b"fmt ", &pcm16_fmt(2, 44_100))]);
+        match read(no_data, seekable) {
+            Err(errors::Error::ParseError(message)) => assert!(message.contains("'data'")),
+            _ => panic!("expected a parse error"),
+        }
+    }
 }
@@ -0,0 +1,474 @@
+use std::io;
+use std::time::Duration;
+
+use super::chunks::{
+    WAVE_FORMAT_ALAW, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_MULAW,
+    WAVE_FORMAT_PCM,
+};
+use crate::audio::{ChannelLayout, Channels, WavOptions, STREAM_CHANNEL_ORDER};
+use crate::codecs::CodecType;
+use crate::io::{Sample, WriteBuffer};
+use crate::{errors, g711, utils, Result};
+
+/// The chunks of a WAV file up to the header of the data chunk
+pub(crate) struct Header {
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+    /// the speaker positions of WAVEFORMATEXTENSIBLE, if the fmt chunk is one
+    channel_mask: Option<u32>,
+}
+
+impl Header {
+    /// Creates the header of a file of `codec`, which is integer PCM, 32 bit float, A-law or mu-law
+    ///
+    /// The fmt chunk is WAVEFORMATEXTENSIBLE for more than two channels, more
+    /// than 16 bits per sample or speakers other than the ones readers assume
+    /// for one or two channels, unless `options` force the basic one.
+    pub fn new(
+        codec: CodecType,
+        speakers: Channels,
+        sample_rate: u32,
+        options: WavOptions,
+    ) -> Result<Header> {
+        let (format_tag, bits_per_sample) = match codec {
+            CodecType::CODEC_TYPE_PCM_U8 => (WAVE_FORMAT_PCM, 8),
+            CodecType::CODEC_TYPE_PCM_S16LE => (WAVE_FORMAT_PCM, 16),
+            CodecType::CODEC_TYPE_PCM_S24LE => (WAVE_FORMAT_PCM, 24),
+            CodecType::CODEC_TYPE_PCM_S32LE => (WAVE_FORMAT_PCM, 32),
+            CodecType::CODEC_TYPE_PCM_F32LE => (WAVE_FORMAT_IEEE_FLOAT, 32),
+            CodecType::CODEC_TYPE_PCM_ALAW => (WAVE_FORMAT_ALAW, 8),
+            CodecType::CODEC_TYPE_PCM_MULAW => (WAVE_FORMAT_MULAW, 8),
+            _ => {
+                return errors::unsupported_error(
+                    "only integer PCM, 32 bit float, A-law and mu-law can be written",
+                )
+            }
+        };
+        let channels = speakers.count() as u16;
+        if channels == 0 {
+            return errors::unsupported_error("a WAV file needs at least one channel");
+        }
+
+        let default_speakers = match channels {
+            1 => Some(ChannelLayout::Mono.into_channels()),
+            2 => Some(ChannelLayout::Stereo.into_channels()),
+            _ => None,
+        };
+        let extensible = channels > 2 || bits_per_sample > 16 || Some(speakers) != default_speakers;
+        Ok(Header {
+            format_tag,
+            channels,
+            sample_rate,
+            bits_per_sample,
+            channel_mask: if extensible && !options.force_basic_fmt {
+                Some(channel_mask(speakers))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Returns the length of the fmt chunk
+    fn fmt_len(&self) -> u64 {
+        // formats other than PCM have the size of the WAVEFORMATEX extension and a fact chunk
+        match (self.channel_mask, self.format_tag != WAVE_FORMAT_PCM) {
+            (Some(_), _) => 40,
+            (None, true) => 18,
+            (None, false) => 16,
+        }
+    }
+
+    /// Returns the length the RIFF chunk of a file holding `samples` samples declares
+    fn riff_len(&self, samples: u64) -> u64 {
+        let data_len = samples * (self.bits_per_sample / 8) as u64;
+        let fact_len = if self.format_tag != WAVE_FORMAT_PCM {
+            12
+        } else {
+            0
+        };
+        4 + 8 + self.fmt_len() + fact_len + 8 + data_len + (data_len & 1)
+    }
+
+    /// Returns the most samples of whole frames a WAV file can hold
+    pub fn max_samples(&self) -> u64 {
+        let frame_len = self.channels as u64 * (self.bits_per_sample / 8) as u64;
+        let available = u32::MAX as u64 - self.riff_len(0) - 1;
+        available / frame_len * self.channels as u64
+    }
+
+    /// Writes the header of a file holding `samples` interleaved samples
+    pub fn write<W: io::Write>(&self, writer: &mut W, samples: u64) -> Result<()> {
+        let block_align = self.channels * (self.bits_per_sample / 8);
+        let data_len = samples * (self.bits_per_sample / 8) as u64;
+        let compressed = self.format_tag != WAVE_FORMAT_PCM;
+        let fmt_len = self.fmt_len();
+        let riff_len = self.riff_len(samples);
+        if riff_len > u32::MAX as u64 {
+            return errors::unsupported_error("audio is too long for a WAV file");
+        }
+
+        writer.write_all(b"RIFF")?;
+        writer.write_le_u32(riff_len as u32)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_le_u32(fmt_len as u32)?;
+        match self.channel_mask {
+            Some(_) => writer.write_le_u16(WAVE_FORMAT_EXTENSIBLE)?,
+            None => writer.write_le_u16(self.format_tag)?,
+        }
+        writer.write_le_u16(self.channels)?;
+        writer.write_le_u32(self.sample_rate)?;
+        writer.write_le_u32(self.sample_rate * block_align as u32)?;
+        writer.write_le_u16(block_align)?;
+        writer.write_le_u16(self.bits_per_sample)?;
+        if let Some(channel_mask) = self.channel_mask {
+            writer.write_le_u16(22)?;
+            // all bits of the samples are valid
+            writer.write_le_u16(self.bits_per_sample)?;
+            writer.write_le_u32(channel_mask)?;
+            // the sub-format GUID is the format tag followed by a fixed suffix
+            writer.write_le_u16(self.format_tag)?;
+            writer.write_all(&KSDATAFORMAT_SUBTYPE_SUFFIX)?;
+        } else if compressed {
+            writer.write_le_u16(0)?;
+        }
+        if compressed {
+            writer.write_all(b"fact")?;
+            writer.write_le_u32(4)?;
+            writer.write_le_u32((samples / self.channels as u64) as u32)?;
+        }
+
+        writer.write_all(b"data")?;
+        writer.write_le_u32(data_len as u32)?;
+        Ok(())
+    }
+
+    /// Writes the pad byte after a data chunk of `samples` samples, if it has an odd length
+    pub fn write_padding<W: io::Write>(&self, writer: &mut W, samples: u64) -> Result<()> {
+        if samples * (self.bits_per_sample / 8) as u64 % 2 == 1 {
+            writer.write_u8(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// bytes of samples buffered before they are written
+const BUFFER_LEN: usize = 64 * 1024;
+
+/// how much audio `WavWriter` writes between patches of the sizes, unless set in `WavOptions`
+const DEFAULT_PATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes a WAV file of audio whose length is unknown until it ends, e.g. a live capture
+///
+/// The header is written with the largest sizes a file can declare, which
+/// readers clamp to the audio present. The samples are buffered and written
+/// in blocks, and every `WavOptions::patch_interval` of audio the writer seeks
+/// back to patch the sizes to the whole frames written so far. A file whose
+/// writing was aborted is thus valid up to the last patch.
+///
+/// `finalize` writes the final sizes. Dropping the writer without it patches
+/// them too, ignoring any error.
+///
+/// ```
+/// use cauldron::audio::{ChannelLayout, WavOptions};
+/// use cauldron::codecs::CodecType;
+/// use cauldron::WavWriter;
+///
+/// let stereo = ChannelLayout::Stereo.into_channels();
+/// let out = std::io::Cursor::new(Vec::new());
+/// let mut writer = WavWriter::new(out, CodecType::CODEC_TYPE_PCM_S16LE, stereo, 44_100, WavOptions::default())?;
+/// writer.write_samples(&[0, 0, 1000, -1000])?;
+/// let file = writer.finalize()?.into_inner();
+/// assert_eq!(file.len(), 44 + 8);
+/// # Ok::<(), cauldron::errors::Error>(())
+/// ```
+pub struct WavWriter<W: io::Write + io::Seek> {
+    /// `None` once `finalize` took it
+    writer: Option<W>,
+    header: Header,
+    codec: CodecType,
+    /// position of the header in `writer`
+    start: u64,
+    buffer: Vec<u8>,
+    channels: u64,
+    written: u64,
+    /// samples written since the last patch
+    unpatched: u64,
+    patch_interval: u64,
+    max_samples: u64,
+}
+
+impl<W: io::Write + io::Seek> WavWriter<W> {
+    /// Writes the header of a file of `codec` to `writer` at its current position
+    ///
+    /// `codec` is one of the codecs of `AudioSegment::write_wav`, and
+    /// `speakers` holds the speaker positions of the channels.
+    pub fn new(
+        mut writer: W,
+        codec: CodecType,
+        speakers: Channels,
+        sample_rate: u32,
+        options: WavOptions,
+    ) -> Result<WavWriter<W>> {
+        if sample_rate == 0 {
+            return errors::unsupported_error("a WAV file needs a sample rate");
+        }
+        let header = Header::new(codec, speakers, sample_rate, options)?;
+        let start = writer.stream_position()?;
+        let max_samples = header.max_samples();
+        header.write(&mut writer, max_samples)?;
+
+        let channels = speakers.count() as u64;
+        let interval = options.patch_interval.unwrap_or(DEFAULT_PATCH_INTERVAL);
+        let frames = interval.as_nanos() * sample_rate as u128 / 1_000_000_000;
+        Ok(WavWriter {
+            writer: Some(writer),
+            header,
+            codec,
+            start,
+            buffer: Vec::with_capacity(BUFFER_LEN),
+            channels,
+            written: 0,
+            unpatched: 0,
+            patch_interval: (frames as u64).max(1) * channels,
+            max_samples,
+        })
+    }
+
+    /// Returns the number of samples written
+    pub fn samples_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Writes interleaved integer samples
+    ///
+    /// The samples have the bits per sample of the codec, 16 for A-law and
+    /// mu-law, and 8 bit samples are signed. Samples out of that range fail
+    /// with `Error::ParseError`. 32 bit float files take `write_f32_samples`.
+    pub fn write_samples(&mut self, samples: &[i32]) -> Result<()> {
+        self.reserve(samples.len())?;
+        match self.codec {
+            CodecType::CODEC_TYPE_PCM_F32LE => {
+                return errors::unsupported_error("float files are written by write_f32_samples")
+            }
+            CodecType::CODEC_TYPE_PCM_ALAW | CodecType::CODEC_TYPE_PCM_MULAW => {
+                let encode = if self.codec == CodecType::CODEC_TYPE_PCM_ALAW {
+                    g711::encode_alaw
+                } else {
+                    g711::encode_ulaw
+                };
+                for sample in samples {
+                    self.buffer.push(encode(utils::narrow_to_i16(*sample)?));
+                }
+            }
+            _ => {
+                let bits = self.header.bits_per_sample;
+                for sample in samples {
+                    sample.write_pcm(&mut self.buffer, bits)?;
+                }
+            }
+        }
+        self.wrote(samples.len())
+    }
+
+    /// Writes interleaved samples of a 32 bit float file
+    pub fn write_f32_samples(&mut self, samples: &[f32]) -> Result<()> {
+        if self.codec != CodecType::CODEC_TYPE_PCM_F32LE {
+            return errors::unsupported_error("only float files are written by write_f32_samples");
+        }
+        self.reserve(samples.len())?;
+        for sample in samples {
+            self.buffer.write_le_f32(*sample)?;
+        }
+        self.wrote(samples.len())
+    }
+
+    /// Writes the buffered samples and patches the sizes to the whole frames written
+    pub fn patch(&mut self) -> Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        writer.write_all(&self.buffer)?;
+        self.buffer.clear();
+        let end = writer.stream_position()?;
+        writer.seek(io::SeekFrom::Start(self.start))?;
+        self.header
+            .write(writer, self.written - self.written % self.channels)?;
+        writer.seek(io::SeekFrom::Start(end))?;
+        writer.flush()?;
+        self.unpatched = 0;
+        Ok(())
+    }
+
+    /// Writes the final sizes and returns the writer, positioned after the file
+    ///
+    /// Fails with `Error::Unsupported` if the last frame is incomplete.
+    pub fn finalize(mut self) -> Result<W> {
+        if self.written % self.channels != 0 {
+            return errors::unsupported_error("the last frame is incomplete");
+        }
+        self.patch()?;
+        let mut writer = self.writer.take().expect("the writer is only taken here");
+        self.header.write_padding(&mut writer, self.written)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Fails if `samples` more samples do not fit into a WAV file
+    fn reserve(&self, samples: usize) -> Result<()> {
+        if self.written + samples as u64 > self.max_samples {
+            return errors::unsupported_error("audio is too long for a WAV file");
+        }
+        Ok(())
+    }
+
+    /// Counts `samples` buffered samples, writing and patching when due
+    fn wrote(&mut self, samples: usize) -> Result<()> {
+        self.written += samples as u64;
+        self.unpatched += samples as u64;
+        if self.unpatched >= self.patch_interval {
+            return self.patch();
+        }
+        if self.buffer.len() >= BUFFER_LEN {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.write_all(&self.buffer)?;
+            }
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: io::Write + io::Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            if let Err(error) = self.patch() {
+                tracing::warn!("could not patch the sizes of the WAV file: {}", error);
+            }
+        }
+    }
+}
+
+/// the bytes of the KSDATAFORMAT_SUBTYPE GUIDs after the format tag
+const KSDATAFORMAT_SUBTYPE_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Returns the WAVEFORMATEXTENSIBLE channel mask, channels without a speaker position are left out
+fn channel_mask(speakers: Channels) -> u32 {
+    STREAM_CHANNEL_ORDER
+        .iter()
+        .enumerate()
+        .filter(|(_, speaker)| speakers.contains(**speaker))
+        .fold(0, |mask, (bit, _)| mask | 1 << bit)
+}
+
+#[cfg(test)]
+fn test_samples(from: usize, len: usize) -> Vec<i32> {
+    (from..from + len)
+        .map(|i| (i * 7 % 2000) as i32 - 1000)
+        .collect()
+}
+
+#[cfg(test)]
+fn read_samples(file: Vec<u8>) -> Vec<i32> {
+    let mut segment = crate::audio::AudioSegment::from_seekable_reader(
+        io::Cursor::new(file),
+        crate::codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    let samples: Vec<i32> = segment
+        .samples::<i32>()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(segment.info().total_samples, samples.len() as u64);
+    samples
+}
+
+#[test]
+fn test_wav_writer_abort() {
+    let stereo = ChannelLayout::Stereo.into_channels();
+    let codec = CodecType::CODEC_TYPE_PCM_S16LE;
+
+    // patched every 10 frames, aborted 5 frames after the second patch
+    let mut out = io::Cursor::new(Vec::new());
+    let options = WavOptions {
+        patch_interval: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let mut writer = WavWriter::new(&mut out, codec, stereo, 1000, options).unwrap();
+    for block in 0..5 {
+        writer.write_samples(&test_samples(block * 10, 10)).unwrap();
+    }
+    std::mem::forget(writer);
+    let mut file = out.into_inner();
+    assert_eq!(read_samples(file.clone()), test_samples(0, 40));
+    // some of the samples after it made it to the file
+    file.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+    assert_eq!(read_samples(file), test_samples(0, 40));
+
+    // aborted before the first patch, once a block of samples was written
+    let mut out = io::Cursor::new(Vec::new());
+    let mut writer =
+        WavWriter::new(&mut out, codec, stereo, 44_100, WavOptions::default()).unwrap();
+    for block in 0..20 {
+        writer
+            .write_samples(&test_samples(block * 2000, 2000))
+            .unwrap();
+    }
+    std::mem::forget(writer);
+    let file = out.into_inner();
+    let written = (file.len() - 44) / 2;
+    assert!(written >= BUFFER_LEN / 2);
+    assert_eq!(read_samples(file), test_samples(0, written));
+}
+
+#[test]
+fn test_wav_writer_finalize() {
+    let stereo = ChannelLayout::Stereo.into_channels();
+    let mono = ChannelLayout::Mono.into_channels();
+    let samples = test_samples(0, 30_000);
+
+    let out = io::Cursor::new(Vec::new());
+    let codec = CodecType::CODEC_TYPE_PCM_S16LE;
+    let mut writer = WavWriter::new(out, codec, stereo, 8000, WavOptions::default()).unwrap();
+    for block in samples.chunks(3000) {
+        writer.write_samples(block).unwrap();
+    }
+    assert_eq!(writer.samples_written(), 30_000);
+    let file = writer.finalize().unwrap().into_inner();
+    assert_eq!(file.len(), 44 + 60_000);
+    assert_eq!(read_samples(file), samples);
+
+    // dropping the writer patches the sizes, an odd length gets its pad byte on finalize only
+    let codec = CodecType::CODEC_TYPE_PCM_U8;
+    let small: Vec<i32> = samples[..5].iter().map(|s| s / 8).collect();
+    let mut out = io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut out, codec, mono, 8000, WavOptions::default()).unwrap();
+    writer.write_samples(&small).unwrap();
+    drop(writer);
+    assert_eq!(out.get_ref().len(), 44 + 5);
+    let expected: Vec<i32> = small.iter().map(|s| s + 128).collect();
+    assert_eq!(read_samples(out.into_inner()), expected);
+
+    let out = io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(out, codec, mono, 8000, WavOptions::default()).unwrap();
+    writer.write_samples(&small).unwrap();
+    assert_eq!(writer.finalize().unwrap().into_inner().len(), 44 + 6);
+
+    // an incomplete frame, and samples of the wrong type
+    let codec = CodecType::CODEC_TYPE_PCM_F32LE;
+    let out = io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(out, codec, stereo, 8000, WavOptions::default()).unwrap();
+    assert!(writer.write_samples(&[0, 0]).is_err());
+    writer.write_f32_samples(&[0.5, -0.5, 0.25]).unwrap();
+    assert!(matches!(
+        writer.finalize(),
+        Err(errors::Error::Unsupported(_))
+    ));
+}
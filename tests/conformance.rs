@@ -0,0 +1,135 @@
+//! Decodes the FLAC decoder test bench of the IETF CELLAR working group and
+//! checks the MD5 signature of every file against the decoded samples.
+//!
+//! The files are not part of the repository, `tests/fetch_flac_test_files.sh`
+//! clones them into `tests/flac-test-files`, or `CAULDRON_FLAC_TEST_FILES`
+//! points at another copy. The test is ignored by default, run it with
+//! `cargo test --test conformance -- --ignored --nocapture` to see the
+//! summary of every file.
+//!
+//! Every file of `subset` has to pass, those of `uncommon` use features
+//! decoders need not support and are only reported. The signature covers the
+//! samples as `samples::<i32>` returns them, so the whole decoding path is
+//! checked, not only the one of `verify`.
+
+#![cfg(feature = "flac")]
+
+extern crate cauldron;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cauldron::audio::AudioSegment;
+use cauldron::md5::Md5;
+
+/// The directories of the test bench, and whether all of their files have to pass
+const SUITES: [(&str, bool); 2] = [("subset", true), ("uncommon", false)];
+
+enum Outcome {
+    Pass,
+    /// the encoder left the signature at zero, the samples were decoded
+    NoSignature,
+    Mismatch,
+    Error(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "pass"),
+            Outcome::NoSignature => write!(f, "pass (no MD5)"),
+            Outcome::Mismatch => write!(f, "FAIL: MD5 mismatch"),
+            Outcome::Error(error) => write!(f, "FAIL: {}", error),
+        }
+    }
+}
+
+fn test_files_dir() -> PathBuf {
+    match std::env::var_os("CAULDRON_FLAC_TEST_FILES") {
+        Some(dir) => PathBuf::from(dir),
+        None => [env!("CARGO_MANIFEST_DIR"), "tests", "flac-test-files"]
+            .iter()
+            .collect(),
+    }
+}
+
+/// Decodes `file` and compares the MD5 signature of STREAMINFO with the samples
+///
+/// The signature is over the interleaved samples as little endian integers of
+/// the fewest whole bytes holding the bits per sample.
+fn check(file: &Path) -> Outcome {
+    let mut segment = match AudioSegment::read(file.to_str().unwrap()) {
+        Ok(segment) => segment,
+        Err(error) => return Outcome::Error(error.to_string()),
+    };
+    let expected = match segment.flac_stream_info() {
+        Some(stream_info) => stream_info.md5,
+        None => return Outcome::Error("no STREAMINFO".to_string()),
+    };
+    let width = (segment.info().bits_per_sample as usize).div_ceil(8);
+    let samples = match segment.samples::<i32>() {
+        Ok(samples) => samples,
+        Err(error) => return Outcome::Error(error.to_string()),
+    };
+
+    let mut md5 = Md5::new();
+    for (i, sample) in samples.enumerate() {
+        match sample {
+            Ok(sample) => md5.update(&sample.to_le_bytes()[..width]),
+            Err(error) => return Outcome::Error(format!("sample {}: {}", i, error)),
+        }
+    }
+    if expected == [0; 16] {
+        Outcome::NoSignature
+    } else if md5.finish() == expected {
+        Outcome::Pass
+    } else {
+        Outcome::Mismatch
+    }
+}
+
+#[test]
+#[ignore]
+fn flac_test_bench() {
+    let dir = test_files_dir();
+    if !dir.exists() {
+        eprintln!(
+            "skipping, {} is missing, run tests/fetch_flac_test_files.sh",
+            dir.display()
+        );
+        return;
+    }
+
+    let mut failed = Vec::new();
+    for (suite, required) in SUITES.iter() {
+        let mut files: Vec<PathBuf> = match fs::read_dir(dir.join(suite)) {
+            Ok(entries) => entries
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "flac"))
+                .collect(),
+            Err(_) => {
+                eprintln!("skipping {}, it is missing", suite);
+                continue;
+            }
+        };
+        files.sort();
+
+        let mut passed = 0;
+        println!("{}:", suite);
+        for file in &files {
+            let outcome = check(file);
+            let name = file.file_name().unwrap().to_string_lossy();
+            println!("  {:<60} {}", name, outcome);
+            match outcome {
+                Outcome::Pass | Outcome::NoSignature => passed += 1,
+                Outcome::Mismatch | Outcome::Error(_) if *required => {
+                    failed.push(format!("{}/{}", suite, name))
+                }
+                _ => {}
+            }
+        }
+        println!("{}: {} of {} passed", suite, passed, files.len());
+    }
+    assert!(failed.is_empty(), "failed: {:?}", failed);
+}
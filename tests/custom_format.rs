@@ -0,0 +1,144 @@
+//! Reads a format registered from outside the crate through `AudioSegment`.
+
+extern crate cauldron;
+
+use std::sync::Arc;
+
+use cauldron::audio::{AudioInfo, AudioSegment, ChannelLayout, DecodeOptions};
+use cauldron::codecs::{CodecType, FormatFlag, SampleFormat};
+use cauldron::errors::Error;
+use cauldron::format::{
+    self, AudioInputStream, AudioReader, AudioSamplesIterator, DecodeContext, Format, ReadBuffer,
+    SampleType, TypedSamples,
+};
+use cauldron::Result;
+
+/// `TOY2`, the number of channels as a byte and 16 bit samples
+struct ToyReader(AudioInputStream);
+
+impl AudioReader for ToyReader {
+    fn read_header(&mut self) -> Result<AudioInfo> {
+        if self.0.peek(4)? != b"TOY2" {
+            return Err(Error::MissingMagic("no TOY2 tag found"));
+        }
+        self.0.skip_bytes(4)?;
+        let channel_layout = match self.0.read_u8()? {
+            1 => ChannelLayout::Mono,
+            2 => ChannelLayout::Stereo,
+            _ => return Err(Error::ParseError("unsupported number of channels")),
+        };
+        Ok(AudioInfo {
+            codec_type: CodecType::CODEC_TYPE_PCM_S16LE,
+            sample_rate: 16_000,
+            total_samples: 0,
+            bits_per_sample: 16,
+            container_bits: 16,
+            sample_format: SampleFormat::S16,
+            channels: channel_layout.into_channels(),
+            channel_layout,
+            flac_stream_info: None,
+            encoder_delay: None,
+            encoder_padding: None,
+        })
+    }
+
+    fn buffer(&mut self) -> &mut AudioInputStream {
+        &mut self.0
+    }
+
+    fn into_buffer(self: Box<Self>) -> AudioInputStream {
+        self.0
+    }
+}
+
+struct ToySamples<'a>(&'a mut Box<dyn AudioReader>);
+
+impl<'a> AudioSamplesIterator<i32> for ToySamples<'a> {
+    fn next(&mut self) -> Option<Result<i32>> {
+        self.0.buffer().read_le_i16().ok().map(|s| Ok(s as i32))
+    }
+}
+
+struct Toy;
+
+impl Format for Toy {
+    fn flag(&self) -> FormatFlag {
+        FormatFlag::Custom(2)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["toy"]
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        header.starts_with(b"TOY2")
+    }
+
+    fn create_reader(
+        &self,
+        input: AudioInputStream,
+        _options: DecodeOptions,
+    ) -> Result<Box<dyn AudioReader>> {
+        Ok(Box::new(ToyReader(input)))
+    }
+
+    fn samples<'a>(
+        &self,
+        context: DecodeContext<'a>,
+        sample_type: SampleType,
+    ) -> Result<TypedSamples<'a>> {
+        TypedSamples::convert_i32(Box::new(ToySamples(context.reader)), 16, sample_type)
+    }
+}
+
+#[test]
+fn registered_format_is_decoded() {
+    format::register(Arc::new(Toy)).unwrap();
+    assert!(format::register(Arc::new(Toy)).is_err());
+
+    let mut data = b"TOY2\x02".to_vec();
+    for sample in &[100i16, -100, 200, -200, 300, -300] {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    let dir = std::env::temp_dir();
+    let file = dir.join(format!("cauldron-custom-{}.toy", std::process::id()));
+    std::fs::write(&file, &data).unwrap();
+    let segment = AudioSegment::read(file.to_str().unwrap());
+    std::fs::remove_file(&file).unwrap();
+
+    // the file extension selects the format
+    let mut segment = segment.unwrap();
+    assert_eq!(segment.format(), FormatFlag::Custom(2));
+    let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![100, -100, 200, -200, 300, -300]);
+
+    // and it is probed after the built-in formats, once WAV failed on a .wav file
+    #[cfg(feature = "wav")]
+    {
+        let mislabeled = dir.join(format!("cauldron-custom-{}.wav", std::process::id()));
+        std::fs::write(&mislabeled, &data).unwrap();
+        let probed = AudioSegment::read(mislabeled.to_str().unwrap());
+        std::fs::remove_file(&mislabeled).unwrap();
+        assert_eq!(probed.unwrap().format(), FormatFlag::Custom(2));
+    }
+
+    // it is detected by its magic bytes
+    let mut input = format::DynamicBufReader::new(&data[..]);
+    assert_eq!(
+        cauldron::detect_format(&mut input).unwrap(),
+        FormatFlag::Custom(2)
+    );
+
+    // channel selection works on it like on any other format
+    let mut segment =
+        AudioSegment::from_reader(std::io::Cursor::new(data), FormatFlag::Custom(2)).unwrap();
+    let right: Vec<f32> = segment
+        .channel_samples(&[1])
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(
+        right,
+        vec![-100.0 / 32_768.0, -200.0 / 32_768.0, -300.0 / 32_768.0]
+    );
+}
@@ -0,0 +1,909 @@
+//! Decodes the fixtures in `tests/samples` and compares them sample by sample
+//! against the reference output in `tests/reference`.
+//!
+//! Fixtures and references are written by `tests/generate_fixtures.py`. The
+//! references hold the interleaved samples as little endian 32 bit values.
+
+// the helpers are unused if a format is compiled out
+#![cfg_attr(
+    not(all(feature = "wav", feature = "flac")),
+    allow(dead_code, unused_imports)
+)]
+
+extern crate cauldron;
+
+use std::fs;
+use std::path::PathBuf;
+
+use cauldron::audio::{AudioSegment, DecodeOptions};
+use cauldron::codecs::FormatFlag;
+use cauldron::dither::Dither;
+use cauldron::Result;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+fn read_reference(name: &str) -> Vec<u32> {
+    let bytes = fs::read(path("reference", &format!("{}.pcm", name))).expect("reference output");
+    bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Decodes `fixture` and panics at the first sample differing from the reference.
+///
+/// `decode` returns the samples of the segment as the bit patterns stored in
+/// the reference.
+fn check(fixture: &str, name: &str, decode: fn(&mut AudioSegment) -> Vec<Result<u32>>) {
+    let file = path("samples", fixture);
+    let mut segment = AudioSegment::read(file.to_str().unwrap()).expect("a valid fixture");
+    let channels = segment.number_channels();
+    let expected = read_reference(name);
+
+    let decoded = decode(&mut segment);
+    for (i, sample) in decoded.iter().enumerate() {
+        let sample = match sample {
+            Ok(sample) => *sample,
+            Err(e) => panic!("{}: error at sample {}: {}", fixture, i, e),
+        };
+        match expected.get(i) {
+            Some(&reference) => assert!(
+                sample == reference,
+                "{}: sample {} (channel {} of frame {}) diverged, decoded {:#010x} but expected {:#010x}",
+                fixture,
+                i,
+                i % channels,
+                i / channels,
+                sample,
+                reference
+            ),
+            None => panic!("{}: decoded more than the {} reference samples", fixture, expected.len()),
+        }
+    }
+    assert_eq!(
+        decoded.len(),
+        expected.len(),
+        "{}: decoding stopped early",
+        fixture
+    );
+}
+
+fn decode_int(segment: &mut AudioSegment) -> Vec<Result<u32>> {
+    let samples = segment.samples::<i32>().unwrap();
+    samples.map(|s| s.map(|s| s as u32)).collect()
+}
+
+fn decode_float(segment: &mut AudioSegment) -> Vec<Result<u32>> {
+    let samples = segment.samples::<f32>().unwrap();
+    samples.map(|s| s.map(f32::to_bits)).collect()
+}
+
+/// Decodes through a pipeline of floats, quantized back to the 24 bits of the source
+fn decode_pipeline_24(segment: &mut AudioSegment) -> Vec<Result<u32>> {
+    let pipeline = segment.process().dither(24, Dither::None);
+    let samples = pipeline.build::<i32>().unwrap();
+    samples.map(|s| s.map(|s| s as u32)).collect()
+}
+
+fn check_int(fixture: &str, name: &str) {
+    check(fixture, name, decode_int);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s16_mono() {
+    check_int("wav/s16_mono.wav", "s16_mono");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s16_stereo() {
+    check_int("wav/s16_stereo.wav", "s16_stereo");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_with_inflated_data_len() {
+    // the data chunk claims 10000 frames more than the file holds
+    check_int("wav/s16_stereo_inflated.wav", "s16_stereo");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s24_mono() {
+    check_int("wav/s24_mono.wav", "s24_mono");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s24_stereo() {
+    check_int("wav/s24_stereo.wav", "s24_stereo");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s24_stereo_through_pipeline() {
+    // the float samples in between keep all 24 bits
+    check("wav/s24_stereo.wav", "s24_stereo", decode_pipeline_24);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s16_5_1() {
+    check_int("wav/s16_5_1.wav", "s16_5_1");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_g711_mono() {
+    for name in &["alaw_mono", "ulaw_mono"] {
+        let fixture = format!("wav/{}.wav", name);
+        check_int(&fixture, name);
+
+        // every byte expands to a 16 bit sample, in each sample type
+        let file = path("samples", &fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        assert_eq!(segment.info().bits_per_sample, 16);
+        assert_eq!(segment.info().total_samples, 256 + 2048);
+        let expected: Vec<i16> = read_reference(name).iter().map(|&s| s as i16).collect();
+        let samples: Vec<i16> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, expected, "{}", fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let samples: Vec<f64> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+        let scaled: Vec<f64> = expected.iter().map(|&s| s as f64 / 32_768.0).collect();
+        assert_eq!(samples, scaled, "{}", fixture);
+    }
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_f32_mono() {
+    check("wav/f32_mono.wav", "f32_mono", decode_float);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_f32_stereo() {
+    check("wav/f32_stereo.wav", "f32_stereo", decode_float);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_f32_non_finite() {
+    // NaN is decoded as 0 and infinities as full scale
+    check(
+        "wav/f32_stereo_non_finite.wav",
+        "f32_stereo_non_finite",
+        decode_float,
+    );
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn strict_decoding_rejects_non_finite_samples() {
+    use cauldron::audio::NonFinite;
+    use cauldron::errors::Error;
+
+    let options = DecodeOptions {
+        non_finite_samples: NonFinite::Error,
+        ..Default::default()
+    };
+    let file = path("samples", "wav/f32_stereo_non_finite.wav");
+    let mut segment =
+        AudioSegment::read_with_options(file.as_path(), FormatFlag::WAV, options).unwrap();
+    let expected = read_reference("f32_stereo");
+    let mut rejected = Vec::new();
+    let samples = segment.samples::<f32>().unwrap();
+    for (i, sample) in samples.enumerate() {
+        match sample {
+            Ok(sample) => assert_eq!(sample.to_bits(), expected[i], "sample {}", i),
+            Err(Error::NonFiniteSample { sample }) => {
+                assert_eq!(sample, i as u64);
+                rejected.push(sample);
+            }
+            Err(error) => panic!("sample {}: {}", i, error),
+        }
+    }
+    // decoding continues after each of them
+    assert_eq!(rejected, vec![100, 101, 2000, 4095]);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn analyses_of_non_finite_samples() {
+    use cauldron::audio::NonFinite;
+    use cauldron::errors::Error;
+
+    let open = |non_finite_samples| {
+        let options = DecodeOptions {
+            non_finite_samples,
+            ..Default::default()
+        };
+        let file = path("samples", "wav/f32_stereo_non_finite.wav");
+        AudioSegment::read_with_options(file.as_path(), FormatFlag::WAV, options).unwrap()
+    };
+
+    let overview = open(NonFinite::Replace).overview(16).unwrap();
+    assert!(overview
+        .iter()
+        .all(|m| m.min.is_finite() && m.max.is_finite() && m.rms.is_finite()));
+    assert_eq!(overview.iter().map(|m| m.max).fold(0.0, f32::max), 1.0);
+    assert_eq!(overview.iter().map(|m| m.min).fold(0.0, f32::min), -1.0);
+    let loudness = open(NonFinite::Replace).loudness().unwrap();
+    // the fixture is shorter than a gating block, so it has no integrated loudness
+    assert!(!loudness.integrated.is_nan() && loudness.true_peak_db.is_finite());
+    let stereo = open(NonFinite::Replace).stereo_analysis(None).unwrap();
+    assert!(stereo.correlation.is_finite() && stereo.balance_db.is_finite());
+
+    let is_rejected = |result: std::result::Result<(), Error>| match result {
+        Err(Error::NonFiniteSample { sample }) => sample == 100,
+        _ => false,
+    };
+    assert!(is_rejected(open(NonFinite::Error).overview(16).map(drop)));
+    assert!(is_rejected(open(NonFinite::Error).loudness().map(drop)));
+    assert!(is_rejected(
+        open(NonFinite::Error).stereo_analysis(None).map(drop)
+    ));
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_s24_in_32_extensible() {
+    // 24 valid bits in 32 bit containers, returned in the scale of the valid
+    // bits, without the padding below them
+    check_int("wav/s24in32_stereo.wav", "s24in32_stereo");
+    let file = path("samples", "wav/s24in32_stereo.wav");
+    let segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+    assert_eq!(segment.info().bits_per_sample, 24);
+    assert_eq!(segment.info().container_bits, 32);
+    assert_eq!(segment.info().total_samples, 2 * 2048);
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn decode_wav_valid_bits_below_the_container() {
+    for &(name, container, valid) in &[("s20in24_mono", 24, 20), ("s12in16_mono", 16, 12)] {
+        let fixture = format!("wav/{}.wav", name);
+        check_int(&fixture, name);
+        let file = path("samples", &fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        assert_eq!(segment.info().bits_per_sample, valid);
+        assert_eq!(segment.info().container_bits, container);
+
+        // floats are scaled by the valid bits
+        let expected = read_reference(name);
+        let samples: Vec<f32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert_eq!(samples.len(), expected.len());
+        for (sample, &reference) in samples.iter().zip(&expected) {
+            assert_eq!(*sample, reference as i32 as f32 / (1 << (valid - 1)) as f32);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn stereo_analysis_is_in_full_scale() {
+    // the largest difference of the channels, from the reference in [-1, 1)
+    fn max_difference(samples: impl Iterator<Item = f64>) -> f64 {
+        let samples: Vec<f64> = samples.collect();
+        samples
+            .chunks_exact(2)
+            .map(|frame| (frame[0] - frame[1]).abs())
+            .fold(0.0, f64::max)
+    }
+
+    let scale = |bits: u32| move |sample: u32| sample as i32 as f64 / (1u64 << (bits - 1)) as f64;
+    let cases = [
+        (
+            "s16_stereo",
+            max_difference(read_reference("s16_stereo").into_iter().map(scale(16))),
+        ),
+        (
+            "s24_stereo",
+            max_difference(read_reference("s24_stereo").into_iter().map(scale(24))),
+        ),
+        (
+            "f32_stereo",
+            max_difference(
+                read_reference("f32_stereo")
+                    .into_iter()
+                    .map(|s| f32::from_bits(s) as f64),
+            ),
+        ),
+    ];
+    for (name, expected) in cases {
+        let file = path("samples", &format!("wav/{}.wav", name));
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let stats = segment.stereo_analysis(None).unwrap();
+        assert!(expected > 0.1, "{}", name);
+        assert!(
+            (stats.max_difference - expected).abs() < 1e-6,
+            "{}: {} != {}",
+            name,
+            stats.max_difference,
+            expected
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn adpcm_wav_is_unsupported_not_malformed() {
+    use cauldron::errors::Error;
+
+    // block align and byte rate of ADPCM count whole blocks, not samples
+    let file = path("samples", "wav/adpcm_ima_mono.wav");
+    match AudioSegment::read(file.to_str().unwrap()) {
+        Err(Error::UnsupportedWaveFormat(format)) => {
+            assert_eq!(format.format_tag, 0x11);
+            assert_eq!((format.block_align, format.bits_per_sample), (1024, 4));
+        }
+        other => panic!("expected an unsupported format, got {:?}", other.err()),
+    }
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decode_flac_fixed_mono_16() {
+    check_int("flac/fixed_mono_16.flac", "fixed_mono_16");
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decode_flac_lpc_stereo_24() {
+    check_int("flac/lpc_stereo_24.flac", "lpc_stereo_24");
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decode_flac_fixed_mono_12() {
+    check_int("flac/fixed_mono_12.flac", "fixed_mono_12");
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decode_flac_lpc_stereo_20() {
+    check_int("flac/lpc_stereo_20.flac", "lpc_stereo_20");
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decode_flac_lpc32_stereo_16() {
+    check_int("flac/lpc32_stereo_16.flac", "lpc32_stereo_16");
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decode_flac_picture_mono_16() {
+    check_int("flac/picture_mono_16.flac", "picture_mono_16");
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn exports_the_flac_picture() {
+    let expected = fs::read(path("reference", "picture_mono_16.png")).unwrap();
+    let file = path("samples", "flac/picture_mono_16.flac");
+    let dir = std::env::temp_dir().join(format!("cauldron-pictures-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let export = |segment: &mut AudioSegment| {
+        let written = segment.export_pictures(&dir).unwrap();
+        let pictures: Vec<Vec<u8>> = written.iter().map(|p| fs::read(p).unwrap()).collect();
+        for path in &written {
+            fs::remove_file(path).unwrap();
+        }
+        (written, pictures)
+    };
+
+    // a file is read again where the picture is, and decodes as before
+    let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+    let (written, pictures) = export(&mut segment);
+    assert_eq!(written, [dir.join("picture-1.png")]);
+    assert_eq!(pictures.concat(), expected);
+    let samples: Vec<i32> = segment.samples().unwrap().map(Result::unwrap).collect();
+    let reference: Vec<i32> = read_reference("picture_mono_16")
+        .into_iter()
+        .map(|s| s as i32)
+        .collect();
+    assert_eq!(samples, reference);
+
+    // an input which can not seek only has the pictures kept while reading the header
+    let data = fs::read(&file).unwrap();
+    let read = |max_buffered_pictures_len| {
+        let options = DecodeOptions {
+            max_buffered_pictures_len,
+            ..Default::default()
+        };
+        let input = std::io::Cursor::new(data.clone());
+        AudioSegment::from_reader_with_options(input, FormatFlag::FLAC, options).unwrap()
+    };
+    assert_eq!(export(&mut read(0)).0, Vec::<std::path::PathBuf>::new());
+    assert_eq!(export(&mut read(100)).0, Vec::<std::path::PathBuf>::new());
+    assert_eq!(export(&mut read(1 << 20)).1, [expected]);
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn decodes_12_and_20_bit_flac_in_their_ranges() {
+    let read =
+        |fixture: &str| AudioSegment::read(path("samples", fixture).to_str().unwrap()).unwrap();
+    let peak = |samples: &[f32]| samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+
+    let mut mono = read("flac/fixed_mono_12.flac");
+    let narrow: Vec<i16> = mono.samples::<i16>().unwrap().map(Result::unwrap).collect();
+    let reference = read_reference("fixed_mono_12");
+    assert_eq!(narrow.len(), reference.len());
+    assert!(narrow.iter().all(|s| (-2048..2048).contains(s)));
+    assert!(narrow
+        .iter()
+        .zip(&reference)
+        .all(|(&s, &r)| s as i32 == r as i32));
+    let floats: Vec<f32> = read("flac/fixed_mono_12.flac")
+        .samples::<f32>()
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert!(peak(&floats) > 0.5 && peak(&floats) <= 1.0);
+    assert_eq!(floats[10], reference[10] as i32 as f32 / 2048.0);
+
+    // 20 bit samples do not fit an i16
+    let mut stereo = read("flac/lpc_stereo_20.flac");
+    assert!(stereo.samples::<i16>().unwrap().next().unwrap().is_err());
+    let floats: Vec<f32> = read("flac/lpc_stereo_20.flac")
+        .samples::<f32>()
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let reference = read_reference("lpc_stereo_20");
+    assert!(peak(&floats) > 0.5 && peak(&floats) <= 1.0);
+    assert_eq!(floats[10], reference[10] as i32 as f32 / 524_288.0);
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn flac_fixtures_cover_subframe_kinds() {
+    let options = DecodeOptions {
+        frame_stats: true,
+        ..Default::default()
+    };
+    let stats = |fixture: &str| {
+        let file = path("samples", fixture);
+        let mut segment =
+            AudioSegment::read_with_options(file.as_path(), FormatFlag::FLAC, options).unwrap();
+        segment.samples::<i32>().unwrap().for_each(drop);
+        segment.file_stats()
+    };
+
+    let mono = stats("flac/fixed_mono_16.flac");
+    assert_eq!(mono.frames, 10);
+    assert_eq!(mono.constant_subframes, 1);
+    assert_eq!(mono.verbatim_subframes, 1);
+    assert_eq!(mono.fixed_subframes, 8);
+    assert_eq!(mono.wasted_bits_subframes, 1);
+    assert_eq!(mono.max_partition_order, 5);
+
+    let stereo = stats("flac/lpc_stereo_24.flac");
+    assert_eq!(stereo.frames, 9);
+    assert_eq!(stereo.constant_subframes, 1);
+    assert_eq!(stereo.verbatim_subframes, 1);
+    assert_eq!(stereo.fixed_subframes, 2);
+    assert_eq!(stereo.lpc_subframes, 14);
+    assert_eq!(stereo.wasted_bits_subframes, 1);
+
+    let high_order = stats("flac/lpc32_stereo_16.flac");
+    assert_eq!(high_order.frames, 3);
+    assert_eq!(high_order.lpc_subframes, 6);
+}
+
+// MP3 is not covered yet, the decoder has no Huffman decoding of the main data.
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn planar_reads_match_interleaved_samples() {
+    let fixtures = [
+        "wav/s16_stereo.wav",
+        "wav/f32_mono.wav",
+        "flac/fixed_mono_16.flac",
+        "flac/lpc_stereo_24.flac",
+    ];
+    for fixture in &fixtures {
+        let file = path("samples", fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let interleaved: Vec<f32> = segment
+            .samples::<f32>()
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect();
+
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let channels = segment.number_channels();
+        let mut samples = segment.samples::<f32>().unwrap();
+        let mut buffers = vec![vec![0.0f32; 300]; channels];
+        let mut reshaped = Vec::new();
+        loop {
+            let mut out: Vec<&mut [f32]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+            let n = samples.read_planar_f32(&mut out).unwrap();
+            reshaped.extend((0..n).flat_map(|i| buffers.iter().map(move |b| b[i])));
+            if n == 0 {
+                break;
+            }
+        }
+        assert!(
+            reshaped == interleaved,
+            "{}: planar samples differ",
+            fixture
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "mp3")]
+fn mp3_reports_float_output() {
+    use cauldron::codecs::{CodecType, SampleFormat};
+
+    let file = path("samples", "mp3/silence_mono.mp3");
+    let mut segment = AudioSegment::read(file.to_str().unwrap()).expect("a valid fixture");
+    // a lossy codec has no bit depth, and the header tells no length
+    let info = segment.info().clone();
+    assert_eq!(info.codec_type, CodecType::CODEC_TYPE_MP3);
+    assert_eq!(info.bits_per_sample, 0);
+    assert_eq!(info.sample_format, SampleFormat::F32);
+    assert!(format!("{:#}", info).contains("Bits per Sample:       - (lossy)"));
+    assert!(format!("{:#}", info).contains("Sample Format:         F32"));
+    assert_eq!(segment.duration(), std::time::Duration::ZERO);
+    assert_eq!(segment.bitrate(), 0);
+    assert_eq!(segment.default_wav_codec(), CodecType::CODEC_TYPE_PCM_S16LE);
+    assert_eq!(segment.scan_length().unwrap().frames, 10 * 1152);
+
+    let samples: Vec<f32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![0.0; 10 * 1152]);
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn lossless_sources_keep_their_precision() {
+    use cauldron::codecs::{CodecType, SampleFormat};
+
+    let expected = [
+        (
+            "wav/s16_stereo.wav",
+            SampleFormat::S16,
+            CodecType::CODEC_TYPE_PCM_S16LE,
+        ),
+        (
+            "wav/s24_mono.wav",
+            SampleFormat::S24,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+        ),
+        // valid bits below the container
+        (
+            "wav/s12in16_mono.wav",
+            SampleFormat::S16,
+            CodecType::CODEC_TYPE_PCM_S16LE,
+        ),
+        (
+            "wav/s20in24_mono.wav",
+            SampleFormat::S24,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+        ),
+        (
+            "wav/s24in32_stereo.wav",
+            SampleFormat::S24,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+        ),
+        // G.711 expands to 16 bits, but is written as it is stored
+        (
+            "wav/alaw_mono.wav",
+            SampleFormat::S16,
+            CodecType::CODEC_TYPE_PCM_ALAW,
+        ),
+        (
+            "wav/ulaw_mono.wav",
+            SampleFormat::S16,
+            CodecType::CODEC_TYPE_PCM_MULAW,
+        ),
+        (
+            "wav/f32_stereo.wav",
+            SampleFormat::F32,
+            CodecType::CODEC_TYPE_PCM_F32LE,
+        ),
+        (
+            "flac/fixed_mono_12.flac",
+            SampleFormat::S16,
+            CodecType::CODEC_TYPE_PCM_S16LE,
+        ),
+        (
+            "flac/lpc_stereo_20.flac",
+            SampleFormat::S24,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+        ),
+        (
+            "flac/lpc_stereo_24.flac",
+            SampleFormat::S24,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+        ),
+    ];
+    for (fixture, sample_format, codec) in expected.iter() {
+        let file = path("samples", fixture);
+        let segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        assert_eq!(segment.info().sample_format, *sample_format, "{}", fixture);
+        assert_eq!(segment.default_wav_codec(), *codec, "{}", fixture);
+    }
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn sample_iterators_know_their_length() {
+    let fixtures = [
+        "wav/s16_stereo.wav",
+        "wav/s16_5_1.wav",
+        "wav/f32_mono.wav",
+        "flac/fixed_mono_16.flac",
+        "flac/lpc_stereo_24.flac",
+    ];
+    for fixture in &fixtures {
+        let file = path("samples", fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let total = segment.info().total_samples;
+        let mut samples = segment.samples::<f32>().unwrap();
+        assert_eq!(samples.samples_left(), Some(total), "{}", fixture);
+        samples.next().unwrap().unwrap();
+        assert_eq!(samples.samples_left(), Some(total - 1), "{}", fixture);
+        let left = (total - 1) as usize;
+        assert_eq!(samples.size_hint(), (left, Some(left)), "{}", fixture);
+        assert_eq!(samples.count(), left, "{}", fixture);
+
+        // the length of the selected channels only
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let channels = segment.number_channels() as u64;
+        let mut samples = segment.channel_samples::<f32>(&[0]).unwrap();
+        assert_eq!(
+            samples.samples_left(),
+            Some(total / channels),
+            "{}",
+            fixture
+        );
+        samples.next().unwrap().unwrap();
+        let left = total / channels - 1;
+        assert_eq!(samples.samples_left(), Some(left), "{}", fixture);
+        assert_eq!(samples.count() as u64, left, "{}", fixture);
+    }
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn remaining_frames_follow_consumption_and_seeks() {
+    use std::time::Duration;
+
+    let fixtures = [
+        "wav/s16_stereo.wav",
+        "wav/s16_5_1.wav",
+        "flac/fixed_mono_16.flac",
+        "flac/lpc_stereo_24.flac",
+    ];
+    for fixture in &fixtures {
+        let file = path("samples", fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let info = segment.info().clone();
+        let channels = info.channels.count() as u64;
+        let frames = info.total_samples / channels;
+        let mut samples = segment.samples::<i32>().unwrap();
+        assert_eq!(samples.remaining_frames(), Some(frames), "{}", fixture);
+
+        // half of the samples and one more, a frame returned in part still counts
+        let consumed = frames / 2 * channels + 1;
+        for _ in 0..consumed {
+            samples.next().unwrap().unwrap();
+        }
+        let half = frames - consumed / channels;
+        assert_eq!(samples.remaining_frames(), Some(half), "{}", fixture);
+        let expected = Duration::from_secs_f64(half as f64 / info.sample_rate as f64);
+        let duration = samples.remaining_duration().unwrap();
+        assert!(
+            (duration.as_secs_f64() - expected.as_secs_f64()).abs() < 1e-6,
+            "{}",
+            fixture
+        );
+
+        // FLAC streams without a seek table only seek to their start
+        let target = if fixture.ends_with(".wav") {
+            frames / 4
+        } else {
+            0
+        };
+        samples.seek_frame(target).unwrap();
+        assert_eq!(
+            samples.remaining_frames(),
+            Some(frames - target),
+            "{}",
+            fixture
+        );
+        assert_eq!(
+            samples.count() as u64,
+            (frames - target) * channels,
+            "{}",
+            fixture
+        );
+
+        // the selected channels only
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let mut samples = segment.channel_samples::<i32>(&[0]).unwrap();
+        for _ in 0..frames / 2 {
+            samples.next().unwrap().unwrap();
+        }
+        assert_eq!(
+            samples.remaining_frames(),
+            Some(frames - frames / 2),
+            "{}",
+            fixture
+        );
+    }
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "mp3"))]
+fn decode_wav_mp3_mono() {
+    use cauldron::codecs::{CodecType, SampleFormat};
+
+    let read = |fixture: &str, trim_encoder_padding| {
+        let file = path("samples", fixture);
+        let options = DecodeOptions {
+            trim_encoder_padding,
+            ..Default::default()
+        };
+        let filename = file.to_str().unwrap();
+        let format = if fixture.ends_with(".wav") {
+            FormatFlag::WAV
+        } else {
+            FormatFlag::MP3
+        };
+        AudioSegment::read_with_options(filename, format, options).expect("a valid fixture")
+    };
+
+    // the frames of mp3/silence_mono.mp3 in a WAVE_FORMAT_MPEGLAYER3 data chunk
+    let mut segment = read("wav/mp3_mono.wav", true);
+    let info = segment.info().clone();
+    assert_eq!(info.codec_type, CodecType::CODEC_TYPE_MP3);
+    assert_eq!(info.sample_format, SampleFormat::F32);
+    assert_eq!(info.sample_rate, 44_100);
+    assert_eq!(info.encoder_delay, Some(1105));
+    // the fact chunk counts the samples after the codec delay
+    assert_eq!(info.total_samples, 10 * 1152);
+    let samples: Vec<f32> = segment.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples.len(), 10 * 1152 - 1105);
+
+    let mut wrapped = read("wav/mp3_mono.wav", false);
+    let mut raw = read("mp3/silence_mono.mp3", false);
+    let wrapped: Vec<u32> = decode_float(&mut wrapped)
+        .into_iter()
+        .map(|s| s.unwrap())
+        .collect();
+    let raw: Vec<u32> = decode_float(&mut raw)
+        .into_iter()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(wrapped, raw);
+}
+
+#[test]
+#[cfg(feature = "mp3")]
+fn mp3_sample_iterators_have_no_length() {
+    let file = path("samples", "mp3/silence_mono.mp3");
+    let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+    let samples = segment.samples::<f32>().unwrap();
+    assert_eq!(samples.size_hint(), (0, None));
+    assert_eq!(samples.samples_left(), None);
+    assert_eq!(samples.remaining_frames(), None);
+    assert_eq!(samples.remaining_duration(), None);
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn fixtures_verify() {
+    let fixtures = [
+        ("wav/s16_stereo.wav", None),
+        ("wav/s24_mono.wav", None),
+        ("wav/f32_stereo.wav", None),
+        ("flac/fixed_mono_16.flac", Some(true)),
+        ("flac/lpc_stereo_24.flac", Some(true)),
+        ("flac/fixed_mono_12.flac", Some(true)),
+        ("flac/lpc_stereo_20.flac", Some(true)),
+        ("flac/picture_mono_16.flac", Some(true)),
+    ];
+    for (fixture, md5_matched) in &fixtures {
+        let file = path("samples", fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let frames = segment.info().total_samples / segment.number_channels() as u64;
+        let report = segment.verify().unwrap();
+        assert!(report.is_ok(), "{}: {:?}", fixture, report);
+        assert_eq!(report.samples, frames, "{}", fixture);
+        assert_eq!(report.md5_matched, *md5_matched, "{}", fixture);
+        // the samples are gone afterwards
+        assert!(segment.samples::<i32>().is_err());
+    }
+}
+
+/// A network-backed input timing out once, when a read reaches `fail_at`
+struct FlakyReader {
+    data: std::io::Cursor<Vec<u8>>,
+    fail_at: u64,
+    failed: bool,
+}
+
+impl std::io::Read for FlakyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let position = self.data.position();
+        if !self.failed && position <= self.fail_at {
+            let before = (self.fail_at - position) as usize;
+            if before == 0 {
+                self.failed = true;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "retryable timeout",
+                ));
+            }
+            // stop right before the failing byte
+            let len = buf.len().min(before);
+            return self.data.read(&mut buf[..len]);
+        }
+        self.data.read(buf)
+    }
+}
+
+impl std::io::Seek for FlakyReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+#[test]
+#[cfg(all(feature = "wav", feature = "flac"))]
+fn decoding_resumes_after_an_io_error() {
+    use cauldron::errors::Error;
+
+    let fixtures = [
+        ("wav/s16_stereo.wav", FormatFlag::WAV, "s16_stereo"),
+        ("wav/s24_mono.wav", FormatFlag::WAV, "s24_mono"),
+        ("flac/fixed_mono_16.flac", FormatFlag::FLAC, "fixed_mono_16"),
+        ("flac/lpc_stereo_24.flac", FormatFlag::FLAC, "lpc_stereo_24"),
+    ];
+    for &(fixture, format, name) in &fixtures {
+        let data = fs::read(path("samples", fixture)).unwrap();
+        // within a sample of WAV, within a frame of FLAC
+        let fail_at = data.len() as u64 / 2 + 1;
+        let reader = FlakyReader {
+            data: std::io::Cursor::new(data),
+            fail_at,
+            failed: false,
+        };
+        let mut segment = AudioSegment::from_seekable_reader(reader, format).unwrap();
+        let mut samples = segment.samples::<i32>().unwrap();
+        assert_eq!(samples.last_good_position().unwrap().frame, 0);
+
+        let mut decoded = Vec::new();
+        let mut failures = 0;
+        loop {
+            match samples.next() {
+                Some(Ok(sample)) => decoded.push(sample as u32),
+                Some(Err(Error::IoError(error))) => {
+                    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut, "{}", fixture);
+                    failures += 1;
+                    // nothing is returned until the read is retried
+                    assert!(samples.next().is_none(), "{}", fixture);
+                    let position = samples.last_good_position().unwrap();
+                    assert!(position.offset <= fail_at, "{}", fixture);
+                    assert!(position.frame > 0, "{}", fixture);
+                    samples.resume_after_io_error().unwrap();
+                }
+                Some(Err(error)) => panic!("{}: {}", fixture, error),
+                None => break,
+            }
+        }
+        assert_eq!(failures, 1, "{}", fixture);
+        assert!(decoded == read_reference(name), "{}", fixture);
+    }
+}
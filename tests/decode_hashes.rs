@@ -0,0 +1,118 @@
+//! Hashes the decoded samples of every fixture in `tests/samples` and compares
+//! the digests against `tests/reference/decode_hashes.txt`.
+//!
+//! Any change to the decoded samples changes a digest. If the change is
+//! intended, replace the line of the fixture with the one the failure prints.
+//! Fixtures listed as `unsupported` have to fail to decode.
+
+extern crate cauldron;
+
+use std::fs;
+use std::path::PathBuf;
+
+use cauldron::audio::AudioSegment;
+use cauldron::hash::HashAlgo;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+fn hex(digest: [u8; 32]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns if the format of `fixture`, e.g. `flac/fixed_mono_12.flac`, is compiled in
+fn is_enabled(fixture: &str) -> bool {
+    // the first prefix matching decides, MP3 in WAV needs both decoders
+    let formats = [
+        ("flac/", cfg!(feature = "flac")),
+        ("mp3/", cfg!(feature = "mp3")),
+        ("wav/mp3_", cfg!(feature = "wav") && cfg!(feature = "mp3")),
+        ("wav/", cfg!(feature = "wav")),
+    ];
+    formats
+        .iter()
+        .find(|&&(prefix, _)| fixture.starts_with(prefix))
+        .is_some_and(|&(_, enabled)| enabled)
+}
+
+/// Returns the fixtures and their digests listed in the reference
+fn read_reference() -> Vec<(String, String)> {
+    let text = fs::read_to_string(path("reference", "decode_hashes.txt")).expect("reference");
+    text.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let digest = fields.next().expect("a digest").to_string();
+            let fixture = fields.next().expect("a fixture").to_string();
+            (fixture, digest)
+        })
+        .collect()
+}
+
+#[test]
+fn decoded_samples_match_the_reference_digests() {
+    let mut failures = Vec::new();
+    for (fixture, expected) in read_reference() {
+        if !is_enabled(&fixture) {
+            continue;
+        }
+        let file = path("samples", &fixture);
+        let digest = match AudioSegment::read(file.to_str().unwrap())
+            .and_then(|mut segment| segment.decode_hash(HashAlgo::Sha256))
+        {
+            Ok(digest) => hex(digest),
+            Err(_) => "unsupported".to_string(),
+        };
+        if digest != expected {
+            failures.push(format!("{}  {}", digest, fixture));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "decoded samples changed, the new reference lines are:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn every_fixture_has_a_reference_digest() {
+    let listed: Vec<String> = read_reference().into_iter().map(|(f, _)| f).collect();
+    for dir in &["flac", "mp3", "wav"] {
+        for entry in fs::read_dir(path("samples", dir)).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            let fixture = format!("{}/{}", dir, name);
+            assert!(
+                listed.contains(&fixture),
+                "{} has no digest in decode_hashes.txt",
+                fixture
+            );
+        }
+    }
+}
+
+#[test]
+fn digests_differ_for_the_same_samples_in_other_formats() {
+    if !cfg!(feature = "wav") {
+        return;
+    }
+    // the same sample bytes, once as 16 bit stereo and once as 16 bit mono
+    let stereo = path("samples", "wav/s16_stereo.wav");
+    let mut stereo = AudioSegment::read(stereo.to_str().unwrap()).unwrap();
+    let stereo_digest = stereo.decode_hash(HashAlgo::Sha256).unwrap();
+    let bytes = fs::read(path("samples", "wav/s16_stereo.wav")).unwrap();
+    let mut mono = bytes.clone();
+    // channels 1, block align 2, byte rate halved
+    mono[22..24].copy_from_slice(&1u16.to_le_bytes());
+    let rate = u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]);
+    mono[28..32].copy_from_slice(&(rate * 2).to_le_bytes());
+    mono[32..34].copy_from_slice(&2u16.to_le_bytes());
+    let mut mono = AudioSegment::from_reader(
+        std::io::Cursor::new(mono),
+        cauldron::codecs::FormatFlag::WAV,
+    )
+    .unwrap();
+    assert_ne!(mono.decode_hash(HashAlgo::Sha256).unwrap(), stereo_digest);
+}
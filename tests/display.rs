@@ -0,0 +1,150 @@
+//! Snapshots of the `Display` output of the fixtures in `tests/samples`
+//!
+//! The one-line summaries and the `{:#}` forms are compared in full, so that
+//! a field added to them is a deliberate change of these tests.
+
+// the helpers are unused if a format is compiled out
+#![cfg_attr(
+    not(all(feature = "wav", feature = "flac", feature = "mp3")),
+    allow(dead_code, unused_imports)
+)]
+
+extern crate cauldron;
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use cauldron::audio::{AudioSegment, ChannelLayout, Channels};
+use cauldron::codecs::FormatFlag;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+fn read(fixture: &str) -> AudioSegment {
+    let file = path("samples", fixture);
+    AudioSegment::read(file.to_str().unwrap()).expect("a valid fixture")
+}
+
+/// The header of a WAV file claiming `data_len` bytes of audio, without any of them
+fn wav_header(channels: u16, sample_rate: u32, bits: u16, data_len: u32) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(36 + data_len).to_le_bytes());
+    file.extend_from_slice(b"WAVEfmt ");
+    file.extend_from_slice(&16u32.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&channels.to_le_bytes());
+    file.extend_from_slice(&sample_rate.to_le_bytes());
+    file.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    file.extend_from_slice(&block_align.to_le_bytes());
+    file.extend_from_slice(&bits.to_le_bytes());
+    file.extend_from_slice(b"data");
+    file.extend_from_slice(&data_len.to_le_bytes());
+    file
+}
+
+#[test]
+fn channels_by_name() {
+    assert_eq!(Channels::empty().to_string(), "none");
+    assert_eq!(ChannelLayout::Stereo.into_channels().to_string(), "FL+FR");
+    assert_eq!(
+        ChannelLayout::FivePointOne.into_channels().to_string(),
+        "FL+FR+FC+BL+BR+LFE"
+    );
+    assert_eq!(
+        (Channels::FRONT_CENTRE | Channels::TOP_BACK_RIGHT).to_string(),
+        "FC+TBR"
+    );
+    assert_eq!(ChannelLayout::SevenPointOne.to_string(), "7.1");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn wav_summaries() {
+    assert_eq!(
+        read("wav/s16_stereo.wav").to_string(),
+        "WAV 44.1 kHz 16-bit stereo, 0.05 s, 1408 kbps"
+    );
+    assert_eq!(
+        read("wav/s16_5_1.wav").to_string(),
+        "WAV 44.1 kHz 16-bit 5.1, 0.05 s, 4224 kbps"
+    );
+    assert_eq!(
+        read("wav/f32_mono.wav").to_string(),
+        "WAV 44.1 kHz 32-bit float mono, 0.05 s, 1408 kbps"
+    );
+    assert_eq!(
+        read("wav/s16_stereo.wav").info().to_string(),
+        "pcm_s16le 44.1 kHz 16-bit stereo"
+    );
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn long_durations() {
+    let header = wav_header(2, 44_100, 16, 205 * 176_400);
+    let segment = AudioSegment::from_reader(Cursor::new(header), FormatFlag::WAV).unwrap();
+    assert_eq!(
+        segment.to_string(),
+        "WAV 44.1 kHz 16-bit stereo, 3:25, 1408 kbps"
+    );
+
+    let header = wav_header(1, 8_000, 8, 3_723 * 8_000);
+    let segment = AudioSegment::from_reader(Cursor::new(header), FormatFlag::WAV).unwrap();
+    assert_eq!(
+        segment.to_string(),
+        "WAV 8 kHz 8-bit mono, 1:02:03, 64 kbps"
+    );
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn flac_summary() {
+    assert_eq!(
+        read("flac/lpc_stereo_24.flac").to_string(),
+        "FLAC 48 kHz 24-bit stereo, 0.10 s, 2304 kbps"
+    );
+}
+
+#[test]
+#[cfg(feature = "mp3")]
+fn mp3_summary() {
+    // the header tells neither the sample rate, the length nor the bitrate
+    assert_eq!(read("mp3/silence_mono.mp3").to_string(), "MP3 mono");
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn wav_verbose() {
+    assert_eq!(
+        format!("{:#}", read("wav/s16_5_1.wav")),
+        "AudioInfo:\n\
+         | CodecType:             pcm_s16le\n\
+         | Sample Rate:           44100\n\
+         | Bits per Sample:       16\n\
+         | Sample Format:         S16\n\
+         | Channel(s):            6 (FL+FR+FC+BL+BR+LFE)\n\
+         | Channel Layout:        FivePointOne\n\
+         \n\
+         duration: 0.046439909s, bitrate: 4224 kb/s"
+    );
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn flac_verbose() {
+    let verbose = format!("{:#}", read("flac/fixed_mono_16.flac"));
+    let expected = "AudioInfo:\n\
+         | CodecType:             flac\n\
+         | Sample Rate:           44100\n\
+         | Bits per Sample:       16\n\
+         | Sample Format:         S16\n\
+         | Channel(s):            1 (FL)\n\
+         | Channel Layout:        Mono\n\
+         \n\
+         FlacStreamInfo:\n";
+    assert!(verbose.starts_with(expected), "{}", verbose);
+}
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Clones the FLAC decoder test bench for `tests/conformance.rs`.
+#
+# usage: tests/fetch_flac_test_files.sh [git ref]
+#
+# The files are not vendored, pass a ref to check a fixed version of them.
+set -e
+
+url=https://github.com/ietf-wg-cellar/flac-test-files
+dir="$(dirname "$0")/flac-test-files"
+
+if [ ! -d "$dir" ]; then
+    git clone --depth 1 "$url" "$dir"
+fi
+if [ -n "$1" ]; then
+    git -C "$dir" fetch --depth 1 origin "$1"
+    git -C "$dir" checkout --detach FETCH_HEAD
+fi
+echo "run: cargo test --test conformance -- --ignored --nocapture"
@@ -0,0 +1,747 @@
+#!/usr/bin/env python3
+"""Generates the decoder fixtures in tests/samples and their reference output.
+
+The signals are synthesized with integer arithmetic only, so running the script
+again produces byte identical files. The reference output in tests/reference is
+written from the synthesized signal, not from a decoder, and holds the
+interleaved samples as little endian 32 bit values: signed integers for integer
+formats and the IEEE 754 bit pattern for float formats.
+
+Encoding FLAC is done by the small encoder below, which emits the different
+subframe types, predictor orders, residual codings and channel decorrelation
+modes the decoder has to handle.
+"""
+
+import hashlib
+import os
+import struct
+import zlib
+
+ROOT = os.path.dirname(os.path.abspath(__file__))
+SAMPLES = os.path.join(ROOT, "samples")
+REFERENCE = os.path.join(ROOT, "reference")
+
+
+def signal(length, bits, seed):
+    """A mix of a triangle, a slow sawtooth and some noise, spanning most of `bits`."""
+    peak = (1 << (bits - 1)) - 1
+    state = seed
+    out = []
+    for i in range(length):
+        state = (state * 1103515245 + 12345) & 0x7FFFFFFF
+        noise = (state >> 8) % 2001 - 1000
+        period = 100 + seed % 37
+        phase = i % period
+        tri = phase if phase < period // 2 else period - phase
+        tri = tri * 4 * peak // (period * 5) - peak // 5 * 2
+        saw = (i % 1500) * peak // 3000 - peak // 4
+        v = tri + saw + noise * peak // 40000
+        out.append(max(-peak - 1, min(peak, v)))
+    return out
+
+
+def interleave(channels):
+    return [s for frame in zip(*channels) for s in frame]
+
+
+def write(path, data):
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, "wb") as f:
+        f.write(data)
+
+
+def write_reference(name, samples, is_float=False):
+    if is_float:
+        data = b"".join(struct.pack("<f", s) for s in samples)
+    else:
+        data = b"".join(struct.pack("<i", s) for s in samples)
+    write(os.path.join(REFERENCE, name + ".pcm"), data)
+
+
+# ---------------------------------------------------------------------------
+# WAV
+
+
+def wav_file(channels, bits, format_tag, data, data_len=None):
+    """`data_len` overrides the length stored in the data chunk header."""
+    block_align = channels * bits // 8
+    fmt = struct.pack("<HHIIHH", format_tag, channels, 44100, 44100 * block_align, block_align, bits)
+    if format_tag == 3:
+        fmt += struct.pack("<H", 0)
+    if data_len is None:
+        data_len = len(data)
+    body = b"WAVE"
+    body += b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    body += b"data" + struct.pack("<I", data_len) + data
+    return b"RIFF" + struct.pack("<I", len(body)) + body
+
+
+# the KSDATAFORMAT_SUBTYPE GUIDs are the format tag followed by this suffix
+GUID_SUFFIX = bytes([0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71])
+
+
+def extensible_fmt(channels, bits, format_tag, channel_mask, valid_bits=None):
+    """A WAVEFORMATEXTENSIBLE fmt chunk body, with all bits of the samples valid by default."""
+    block_align = channels * bits // 8
+    fmt = struct.pack("<HHIIHH", 0xFFFE, channels, 44100, 44100 * block_align, block_align, bits)
+    fmt += struct.pack("<HHI", 22, bits if valid_bits is None else valid_bits, channel_mask)
+    return fmt + struct.pack("<H", format_tag) + GUID_SUFFIX
+
+
+def wav_header(channels, bits, format_tag, frames, channel_mask=None):
+    """The chunks a WAV writer emits up to the data chunk header, as specified by Microsoft.
+
+    The fmt chunk is WAVEFORMATEXTENSIBLE if `channel_mask` is given, formats
+    other than PCM have the cbSize field of WAVEFORMATEX and a fact chunk.
+    """
+    block_align = channels * bits // 8
+    if channel_mask is not None:
+        fmt = extensible_fmt(channels, bits, format_tag, channel_mask)
+    else:
+        fmt = struct.pack("<HHIIHH", format_tag, channels, 44100, 44100 * block_align, block_align, bits)
+        if format_tag != 1:
+            fmt += struct.pack("<H", 0)
+    data_len = frames * block_align
+    chunks = b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    if format_tag != 1:
+        chunks += b"fact" + struct.pack("<II", 4, frames)
+    chunks += b"data" + struct.pack("<I", data_len)
+    riff_len = 4 + len(chunks) + data_len + data_len % 2
+    return b"RIFF" + struct.pack("<I", riff_len) + b"WAVE" + chunks
+
+
+def generate_wav_headers():
+    """The headers `AudioSegment::write_wav_with_options` has to write for the fixtures."""
+    frames = 2048
+    headers = {
+        # (channels, bits, format tag, channel mask)
+        "basic_s16_stereo": (2, 16, 1, None),
+        "basic_s24_stereo": (2, 24, 1, None),
+        "ext_s24_stereo": (2, 24, 1, 0x3),
+        "ext_f32_stereo": (2, 32, 3, 0x3),
+        "ext_s16_mono_centre": (1, 16, 1, 0x4),
+        "ext_s16_5_1": (6, 16, 1, 0x3F),
+    }
+    for name, (channels, bits, format_tag, channel_mask) in sorted(headers.items()):
+        header = wav_header(channels, bits, format_tag, frames, channel_mask)
+        write(os.path.join(REFERENCE, "wav_headers", name + ".hdr"), header)
+
+
+# the samples of f32_stereo_non_finite.wav which are NaN or infinite, with
+# their index over all channels
+NON_FINITE_SAMPLES = [
+    (100, float("nan")),
+    (101, float("inf")),
+    (2000, float("-inf")),
+    (4095, float("nan")),
+]
+
+
+# The G.711 coder of the ITU-T reference implementation, as in Sun's g711.c
+SEG_AEND = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF]
+SEG_UEND = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF]
+
+
+def segment(value, ends):
+    return next((i for i, end in enumerate(ends) if value <= end), len(ends))
+
+
+def linear2alaw(sample):
+    value = sample >> 3
+    if value >= 0:
+        mask = 0xD5
+    else:
+        mask = 0x55
+        value = -value - 1
+    seg = segment(value, SEG_AEND)
+    if seg >= 8:
+        return 0x7F ^ mask
+    code = seg << 4
+    code |= (value >> 1) & 0x0F if seg < 2 else (value >> seg) & 0x0F
+    return code ^ mask
+
+
+def alaw2linear(code):
+    code ^= 0x55
+    value = (code & 0x0F) << 4
+    seg = (code & 0x70) >> 4
+    if seg == 0:
+        value += 8
+    elif seg == 1:
+        value += 0x108
+    else:
+        value = (value + 0x108) << (seg - 1)
+    return value if code & 0x80 else -value
+
+
+def linear2ulaw(sample):
+    value = sample >> 2
+    if value < 0:
+        value = -value
+        mask = 0x7F
+    else:
+        mask = 0xFF
+    value = min(value, 8159) + (0x84 >> 2)
+    seg = segment(value, SEG_UEND)
+    if seg >= 8:
+        return 0x7F ^ mask
+    return ((seg << 4) | ((value >> (seg + 1)) & 0x0F)) ^ mask
+
+
+def ulaw2linear(code):
+    code = ~code & 0xFF
+    value = (((code & 0x0F) << 3) + 0x84) << ((code & 0x70) >> 4)
+    return 0x84 - value if code & 0x80 else value - 0x84
+
+
+def generate_wav():
+    frames = 2048
+    for channels, layout in ((1, "mono"), (2, "stereo")):
+        for bits in (16, 24):
+            pcm = interleave([signal(frames, bits, 7 + 11 * c + bits) for c in range(channels)])
+            if bits == 16:
+                data = b"".join(struct.pack("<h", s) for s in pcm)
+            else:
+                data = b"".join(struct.pack("<i", s)[:3] for s in pcm)
+            name = "s%d_%s" % (bits, layout)
+            write(os.path.join(SAMPLES, "wav", name + ".wav"), wav_file(channels, bits, 1, data))
+            write_reference(name, pcm)
+
+            # a writer which never flushed the end of the audio, the data chunk
+            # claims more than the file holds and its last frame is incomplete
+            if bits == 16 and channels == 2:
+                inflated = wav_file(channels, bits, 1, data + b"\x01\x02\x03", len(data) + 40000)
+                write(os.path.join(SAMPLES, "wav", name + "_inflated.wav"), inflated)
+
+        # floats with 23 significant bits are represented exactly
+        pcm = interleave([signal(frames, 24, 3 + 5 * c) for c in range(channels)])
+        floats = [s / float(1 << 23) for s in pcm]
+        data = b"".join(struct.pack("<f", s) for s in floats)
+        name = "f32_%s" % layout
+        write(os.path.join(SAMPLES, "wav", name + ".wav"), wav_file(channels, 32, 3, data))
+        write_reference(name, floats, is_float=True)
+
+        # a broken export with NaN and infinities, the reference holds them
+        # replaced with 0 and full scale as decoded by default
+        if channels == 2:
+            broken = list(floats)
+            replaced = list(floats)
+            for i, value in NON_FINITE_SAMPLES:
+                broken[i] = value
+                replaced[i] = 0.0 if value != value else (1.0 if value > 0 else -1.0)
+            data = b"".join(struct.pack("<f", s) for s in broken)
+            write(os.path.join(SAMPLES, "wav", "f32_stereo_non_finite.wav"), wav_file(channels, 32, 3, data))
+            write_reference("f32_stereo_non_finite", replaced, is_float=True)
+
+    # 5.1 in the WAVEFORMATEXTENSIBLE speaker order FL FR FC LFE BL BR
+    pcm = interleave([signal(frames, 16, 50 + c) for c in range(6)])
+    data = b"".join(struct.pack("<h", s) for s in pcm)
+    fmt = extensible_fmt(6, 16, 1, 0x3F)
+    body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    body += b"data" + struct.pack("<I", len(data)) + data
+    write(os.path.join(SAMPLES, "wav", "s16_5_1.wav"), b"RIFF" + struct.pack("<I", len(body)) + body)
+    write_reference("s16_5_1", pcm)
+
+    # 24 bit samples in 32 bit containers, with the header ffmpeg writes for
+    # pcm_s32le of 24 valid bits: bits per sample 32, 24 valid bits. The
+    # padding below the valid bits is not zero, it must not be decoded
+    pcm = interleave([signal(frames, 24, 60 + c) for c in range(2)])
+    data = b"".join(struct.pack("<i", s << 8 | (i * 37) & 0xFF) for i, s in enumerate(pcm))
+    fmt = extensible_fmt(2, 32, 1, 0x3, valid_bits=24)
+    body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    body += b"data" + struct.pack("<I", len(data)) + data
+    write(os.path.join(SAMPLES, "wav", "s24in32_stereo.wav"), b"RIFF" + struct.pack("<I", len(body)) + body)
+    # the samples are returned in the scale of their valid bits
+    write_reference("s24in32_stereo", pcm)
+
+    # 20 valid bits in 24 bit containers and 12 in 16, padded the same way,
+    # with the front left speaker like the mono layout of the crate
+    for name, container, valid, pack in (("s20in24_mono", 24, 20, lambda v: struct.pack("<i", v)[:3]),
+                                         ("s12in16_mono", 16, 12, lambda v: struct.pack("<h", v))):
+        pcm = signal(frames, valid, 70 + valid)
+        shift = container - valid
+        data = b"".join(pack(s << shift | (i * 7) % (1 << shift)) for i, s in enumerate(pcm))
+        fmt = extensible_fmt(1, container, 1, 0x1, valid_bits=valid)
+        body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt
+        body += b"data" + struct.pack("<I", len(data)) + data
+        write(os.path.join(SAMPLES, "wav", name + ".wav"), b"RIFF" + struct.pack("<I", len(body)) + body)
+        write_reference(name, pcm)
+
+    # G.711 as telephony writes it: every code of the law, then a signal
+    # encoded by the reference coder, the reference holds the expanded samples
+    ramp = signal(frames, 16, 80)
+    for name, format_tag, encode, expand in (("alaw", 6, linear2alaw, alaw2linear),
+                                             ("ulaw", 7, linear2ulaw, ulaw2linear)):
+        codes = bytes(range(256)) + bytes(encode(s) for s in ramp)
+        write(os.path.join(SAMPLES, "wav", name + "_mono.wav"), wav_file(1, 8, format_tag, codes))
+        write_reference(name + "_mono", [expand(c) for c in codes])
+
+    # IMA ADPCM as ffmpeg writes it: 4 bits per sample, blocks of 1024 bytes
+    # holding 2041 samples, and a byte rate which is not rate * block align
+    samples_per_block = 2041
+    fmt = struct.pack("<HHIIHH", 0x11, 1, 44100, 44100 * 1024 // samples_per_block, 1024, 4)
+    fmt += struct.pack("<HH", 2, samples_per_block)
+    body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    body += b"fact" + struct.pack("<II", 4, 2 * samples_per_block)
+    body += b"data" + struct.pack("<I", 2048) + bytes(2048)
+    write(os.path.join(SAMPLES, "wav", "adpcm_ima_mono.wav"), b"RIFF" + struct.pack("<I", len(body)) + body)
+
+
+# ---------------------------------------------------------------------------
+# FLAC
+
+
+class BitWriter:
+    def __init__(self):
+        self.bits = []
+
+    def write(self, value, n):
+        for i in reversed(range(n)):
+            self.bits.append((value >> i) & 1)
+
+    def write_signed(self, value, n):
+        self.write(value & ((1 << n) - 1), n)
+
+    def write_unary(self, n):
+        self.bits.extend([0] * n)
+        self.bits.append(1)
+
+    def to_bytes(self):
+        bits = self.bits + [0] * (-len(self.bits) % 8)
+        out = bytearray()
+        for i in range(0, len(bits), 8):
+            byte = 0
+            for b in bits[i:i + 8]:
+                byte = byte << 1 | b
+            out.append(byte)
+        return bytes(out)
+
+
+def crc8(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x07) & 0xFF if crc & 0x80 else (crc << 1) & 0xFF
+    return crc
+
+
+def crc16(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x8005) & 0xFFFF if crc & 0x8000 else (crc << 1) & 0xFFFF
+    return crc
+
+
+def utf8_coded(value):
+    if value < 0x80:
+        return bytes([value])
+    extra = 1
+    while value >> (6 * extra) >= 1 << (6 - extra):
+        extra += 1
+    lead = (0xFF00 >> (extra + 1)) & 0xFF
+    out = [lead | (value >> (6 * extra))]
+    for i in reversed(range(extra)):
+        out.append(0x80 | ((value >> (6 * i)) & 0x3F))
+    return bytes(out)
+
+
+FIXED_COEFFICIENTS = [[], [1], [2, -1], [3, -3, 1], [4, -6, 4, -1]]
+
+
+def predict_residual(samples, coefficients, shift):
+    order = len(coefficients)
+    residual = []
+    for i in range(order, len(samples)):
+        prediction = sum(c * samples[i - j - 1] for j, c in enumerate(coefficients)) >> shift
+        residual.append(samples[i] - prediction)
+    return residual
+
+
+def lpc_coefficients(samples, order, precision):
+    """Levinson-Durbin on the autocorrelation, quantized to `precision` bits."""
+    n = len(samples)
+    autoc = [sum(samples[i] * samples[i - lag] for i in range(lag, n)) for lag in range(order + 1)]
+    if autoc[0] == 0:
+        return [0] * order, 0
+    lpc = [0.0] * order
+    err = float(autoc[0])
+    for i in range(order):
+        acc = autoc[i + 1] - sum(lpc[j] * autoc[i - j] for j in range(i))
+        k = acc / err if err else 0.0
+        new = lpc[:]
+        new[i] = k
+        for j in range(i):
+            new[j] = lpc[j] - k * lpc[i - 1 - j]
+        lpc = new
+        err *= 1.0 - k * k
+        if err <= 0:
+            break
+    peak = max(abs(c) for c in lpc) or 1.0
+    limit = (1 << (precision - 1)) - 1
+    shift = 0
+    while shift < 15 and peak * (1 << (shift + 1)) <= limit:
+        shift += 1
+    return [max(-limit - 1, min(limit, int(round(c * (1 << shift))))) for c in lpc], shift
+
+
+def write_partition(bw, residual, param_width, force_escape):
+    zigzag = [(r << 1) if r >= 0 else (-r << 1) - 1 for r in residual]
+    escape = (1 << param_width) - 1
+    best_param, best_len = 0, None
+    for k in range(escape):
+        length = sum((z >> k) + 1 + k for z in zigzag)
+        if best_len is None or length < best_len:
+            best_param, best_len = k, length
+    raw_bits = max([abs(r).bit_length() + 1 for r in residual] + [1])
+    if force_escape or raw_bits * len(residual) + 5 < best_len:
+        bw.write(escape, param_width)
+        bw.write(raw_bits, 5)
+        for r in residual:
+            bw.write_signed(r, raw_bits)
+        return
+    bw.write(best_param, param_width)
+    for z in zigzag:
+        bw.write_unary(z >> best_param)
+        bw.write(z & ((1 << best_param) - 1), best_param)
+
+
+def write_residual(bw, residual, block_size, order, partition_order, param_width, escape_partition):
+    bw.write(0 if param_width == 4 else 1, 2)
+    bw.write(partition_order, 4)
+    per_partition = block_size >> partition_order
+    start = 0
+    for p in range(1 << partition_order):
+        length = per_partition - order if p == 0 else per_partition
+        write_partition(bw, residual[start:start + length], param_width, p == escape_partition)
+        start += length
+
+
+def write_subframe(bw, samples, bps, spec):
+    """`spec` is a tuple of the subframe kind and its parameters."""
+    kind = spec[0]
+    wasted = spec[-1] if kind in ("fixed", "lpc") else 0
+    shifted = [s >> wasted for s in samples]
+    bps -= wasted
+
+    if kind == "constant":
+        bw.write(0, 8)
+        bw.write_signed(samples[0], bps)
+        return
+    if kind == "verbatim":
+        bw.write(0b0000_0010, 8)
+        for s in samples:
+            bw.write_signed(s, bps)
+        return
+
+    if kind == "fixed":
+        _, order, partition_order, param_width, escape_partition, _ = spec
+        coefficients, shift = FIXED_COEFFICIENTS[order], 0
+        type_code = 0b00_1000 | order
+    else:
+        _, order, precision, partition_order, param_width, escape_partition, _ = spec
+        coefficients, shift = lpc_coefficients(shifted, order, precision)
+        type_code = 0b10_0000 | (order - 1)
+
+    bw.write(0, 1)
+    bw.write(type_code, 6)
+    if wasted:
+        bw.write(1, 1)
+        bw.write_unary(wasted - 1)
+    else:
+        bw.write(0, 1)
+    for s in shifted[:order]:
+        bw.write_signed(s, bps)
+    if kind == "lpc":
+        bw.write(precision - 1, 4)
+        bw.write(shift, 5)
+        for c in coefficients:
+            bw.write_signed(c, precision)
+    residual = predict_residual(shifted, coefficients, shift)
+    write_residual(
+        bw, residual, len(samples), order, partition_order, param_width, escape_partition
+    )
+
+
+BLOCK_SIZE_CODES = {192: 1, 576: 2, 1152: 3, 2304: 4, 4608: 5}
+BLOCK_SIZE_CODES.update({256 << i: 8 + i for i in range(8)})
+
+
+def encode_frame(number, channels, bps, bps_code, rate_code, assignment, subframes):
+    block_size = len(channels[0])
+    bw = BitWriter()
+    bw.write(0x7FFC, 15)
+    bw.write(0, 1)
+    size_code = BLOCK_SIZE_CODES.get(block_size, 0b0111)
+    bw.write(size_code, 4)
+    bw.write(rate_code, 4)
+    bw.write(assignment, 4)
+    bw.write(bps_code, 3)
+    bw.write(0, 1)
+    header = bw.to_bytes() + utf8_coded(number)
+    if size_code == 0b0111:
+        header += struct.pack(">H", block_size - 1)
+    header += bytes([crc8(header)])
+
+    left, right = (channels + [None])[:2]
+    if assignment == 8:
+        coded = [(left, bps), ([l - r for l, r in zip(left, right)], bps + 1)]
+    elif assignment == 9:
+        coded = [([l - r for l, r in zip(left, right)], bps + 1), (right, bps)]
+    elif assignment == 10:
+        mid = [(l + r) >> 1 for l, r in zip(left, right)]
+        coded = [(mid, bps), ([l - r for l, r in zip(left, right)], bps + 1)]
+    else:
+        coded = [(c, bps) for c in channels]
+
+    bw = BitWriter()
+    for (samples, sf_bps), spec in zip(coded, subframes):
+        write_subframe(bw, samples, sf_bps, spec)
+    frame = header + bw.to_bytes()
+    return frame + struct.pack(">H", crc16(frame))
+
+
+BPS_CODES = {8: 0b001, 12: 0b010, 16: 0b100, 20: 0b101, 24: 0b110}
+
+
+def flac_file(sample_rate, bps, channels, frames, metadata=()):
+    """`frames` is a list of (channel samples, assignment, subframe specs).
+
+    `metadata` holds (block type, payload) pairs of the blocks following STREAMINFO.
+    """
+    encoded = b"".join(
+        encode_frame(i, chans, bps, BPS_CODES[bps], rate, assignment, subframes)
+        for i, (chans, rate, assignment, subframes) in enumerate(frames)
+    )
+    pcm = interleave([[s for f in frames for s in f[0][c]] for c in range(channels)])
+    width = (bps + 7) // 8
+    md5 = hashlib.md5(b"".join(s.to_bytes(width, "little", signed=True) for s in pcm))
+
+    sizes = [len(f[0][0]) for f in frames]
+    bw = BitWriter()
+    bw.write(min(sizes[:-1]), 16)
+    bw.write(max(sizes), 16)
+    bw.write(0, 24)
+    bw.write(0, 24)
+    bw.write(sample_rate, 20)
+    bw.write(channels - 1, 3)
+    bw.write(bps - 1, 5)
+    bw.write(sum(sizes), 36)
+    stream_info = bw.to_bytes() + md5.digest()
+    blocks = [(0, stream_info)] + list(metadata)
+    header = b"fLaC"
+    for i, (block_type, payload) in enumerate(blocks):
+        last = 0x80 if i == len(blocks) - 1 else 0
+        header += bytes([last | block_type]) + struct.pack(">I", len(payload))[1:] + payload
+    return header + encoded, pcm
+
+
+def png_image(width, height):
+    """A gray gradient as a PNG file, which zlib compresses the same every time."""
+
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    rows = b"".join(b"\x00" + bytes((x + y) * 255 // (width + height) for x in range(width))
+                    for y in range(height))
+    ihdr = struct.pack(">IIBBBBB", width, height, 8, 0, 0, 0, 0)
+    return (b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", ihdr) + chunk(b"IDAT", zlib.compress(rows, 9))
+            + chunk(b"IEND", b""))
+
+
+def flac_picture(picture_type, mime, description, width, height, data):
+    """The payload of a PICTURE metadata block."""
+    mime = mime.encode("ascii")
+    description = description.encode("utf-8")
+    return (struct.pack(">II", picture_type, len(mime)) + mime
+            + struct.pack(">I", len(description)) + description
+            + struct.pack(">IIIII", width, height, 8, 0, len(data)) + data)
+
+
+def split(samples, block_size):
+    return [samples[i:i + block_size] for i in range(0, len(samples), block_size)]
+
+
+def generate_flac():
+    # mono 16 bit: constant, verbatim and every fixed order, rice and escaped
+    # partitions, wasted bits and a short last frame
+    block_size = 512
+    base = signal(block_size * 9 + 300, 16, 23)
+    blocks = split(base, block_size)
+    blocks[0] = [0] * block_size
+    blocks[6] = [(s >> 3) << 3 for s in blocks[6]]
+    specs = [
+        ("constant",),
+        ("verbatim",),
+        ("fixed", 0, 0, 4, None, 0),
+        ("fixed", 1, 2, 4, None, 0),
+        ("fixed", 2, 3, 4, 2, 0),
+        ("fixed", 3, 4, 5, None, 0),
+        ("fixed", 2, 1, 4, None, 3),
+        ("fixed", 4, 5, 4, 7, 0),
+        ("fixed", 4, 0, 5, None, 0),
+        ("fixed", 2, 2, 4, None, 0),
+    ]
+    frames = [([b], 0b1001, 0, [spec]) for b, spec in zip(blocks, specs)]
+    data, pcm = flac_file(44100, 16, 1, frames)
+    write(os.path.join(SAMPLES, "flac", "fixed_mono_16.flac"), data)
+    write_reference("fixed_mono_16", pcm)
+
+    # stereo 24 bit: low and high order LPC, every channel decorrelation mode
+    block_size = 576
+    left = split(signal(block_size * 8 + 100, 24, 41), block_size)
+    right = split(signal(block_size * 8 + 100, 24, 42), block_size)
+    right[5] = [r >> 2 << 2 for r in left[5]]
+    left[5] = [l >> 2 << 2 for l in left[5]]
+    frames = [
+        (1, [("lpc", 8, 12, 3, 4, None, 0), ("lpc", 8, 12, 3, 4, None, 0)]),
+        (8, [("lpc", 1, 15, 0, 5, None, 0), ("lpc", 2, 10, 2, 5, None, 0)]),
+        (9, [("lpc", 12, 13, 4, 5, 3, 0), ("fixed", 2, 2, 4, None, 0)]),
+        (10, [("lpc", 32, 15, 2, 5, None, 0), ("lpc", 16, 14, 1, 5, None, 0)]),
+        (1, [("verbatim",), ("lpc", 4, 8, 5, 4, None, 0)]),
+        (10, [("lpc", 6, 12, 3, 5, None, 2), ("constant",)]),
+        (8, [("lpc", 20, 15, 0, 5, None, 0), ("lpc", 3, 12, 6, 4, 1, 0)]),
+        (9, [("lpc", 10, 12, 3, 5, None, 0), ("lpc", 7, 12, 2, 5, None, 0)]),
+        (1, [("lpc", 2, 12, 0, 4, None, 0), ("fixed", 1, 0, 4, None, 0)]),
+    ]
+    frames = [
+        ([l, r], 0, assignment, specs)
+        for l, r, (assignment, specs) in zip(left, right, frames)
+    ]
+    data, pcm = flac_file(48000, 24, 2, frames)
+    write(os.path.join(SAMPLES, "flac", "lpc_stereo_24.flac"), data)
+    write_reference("lpc_stereo_24", pcm)
+
+    # mono 12 bit: fixed and LPC subframes in the narrower depth
+    block_size = 1152
+    blocks = split(signal(block_size * 3 + 200, 12, 17), block_size)
+    specs = [
+        ("fixed", 2, 2, 4, None, 0),
+        ("lpc", 8, 12, 3, 4, None, 0),
+        ("verbatim",),
+        ("fixed", 1, 0, 4, None, 0),
+    ]
+    frames = [([b], 0b1010, 0, [spec]) for b, spec in zip(blocks, specs)]
+    data, pcm = flac_file(48000, 12, 1, frames)
+    write(os.path.join(SAMPLES, "flac", "fixed_mono_12.flac"), data)
+    write_reference("fixed_mono_12", pcm)
+
+    # stereo 20 bit: side channels need 21 bits
+    block_size = 1024
+    left = split(signal(block_size * 4, 20, 29), block_size)
+    right = split(signal(block_size * 4, 20, 30), block_size)
+    frames = [
+        (1, [("lpc", 8, 14, 3, 5, None, 0), ("lpc", 8, 14, 3, 5, None, 0)]),
+        (8, [("lpc", 12, 14, 2, 5, None, 0), ("fixed", 2, 3, 5, None, 0)]),
+        (9, [("fixed", 3, 2, 5, None, 0), ("lpc", 4, 12, 1, 5, None, 0)]),
+        (10, [("lpc", 16, 15, 4, 5, None, 0), ("verbatim",)]),
+    ]
+    frames = [
+        ([l, r], 0b1011, assignment, specs)
+        for l, r, (assignment, specs) in zip(left, right, frames)
+    ]
+    data, pcm = flac_file(96000, 20, 2, frames)
+    write(os.path.join(SAMPLES, "flac", "lpc_stereo_20.flac"), data)
+    write_reference("lpc_stereo_20", pcm)
+
+    # stereo 16 bit of non-subset order 32 LPC, as `flac -8 -e -l 32` writes it
+    block_size = 4096
+    left = split(signal(block_size * 2 + 500, 16, 61), block_size)
+    right = split(signal(block_size * 2 + 500, 16, 62), block_size)
+    frames = [
+        (1, [("lpc", 32, 15, 4, 4, None, 0), ("lpc", 32, 15, 4, 4, None, 0)]),
+        (10, [("lpc", 32, 15, 3, 4, None, 0), ("lpc", 32, 15, 3, 5, None, 0)]),
+        (8, [("lpc", 32, 15, 2, 4, None, 0), ("lpc", 13, 15, 2, 5, None, 0)]),
+    ]
+    frames = [
+        ([l, r], 0b1001, assignment, specs)
+        for l, r, (assignment, specs) in zip(left, right, frames)
+    ]
+    data, pcm = flac_file(44100, 16, 2, frames)
+    write(os.path.join(SAMPLES, "flac", "lpc32_stereo_16.flac"), data)
+    write_reference("lpc32_stereo_16", pcm)
+
+    # mono 16 bit with a front cover as a PNG in a PICTURE block, before padding
+    block_size = 256
+    blocks = split(signal(block_size * 2, 16, 53), block_size)
+    frames = [([b], 0b1001, 0, [("fixed", 2, 2, 4, None, 0)]) for b in blocks]
+    png = png_image(16, 12)
+    metadata = [(6, flac_picture(3, "image/png", "Cover", 16, 12, png)), (1, bytes(64))]
+    data, pcm = flac_file(44100, 16, 1, frames, metadata)
+    write(os.path.join(SAMPLES, "flac", "picture_mono_16.flac"), data)
+    write_reference("picture_mono_16", pcm)
+    write(os.path.join(REFERENCE, "picture_mono_16.png"), png)
+
+    # a stereo 16 bit recording, and the same cut within a frame into two
+    # tracks, which played one after another are the recording again
+    block_size = 4096
+    left = signal(block_size * 3 + 700, 16, 71)
+    right = signal(block_size * 3 + 700, 16, 72)
+    cut = block_size + 1500
+    spec = ("fixed", 2, 2, 4, None, 0)
+    for name, start, end in [("album", 0, len(left)), ("track1", 0, cut), ("track2", cut, len(left))]:
+        frames = [
+            ([l, r], 0b1001, 1, [spec, spec])
+            for l, r in zip(split(left[start:end], block_size), split(right[start:end], block_size))
+        ]
+        data, _ = flac_file(44100, 16, 2, frames)
+        write(os.path.join(SAMPLES, "flac", "gapless_%s_stereo_16.flac" % name), data)
+
+
+# ---------------------------------------------------------------------------
+# MP3
+
+
+def generate_mp3():
+    """Ten MPEG1 layer 3 frames, 128 kb/s at 44.1 kHz mono, after an ID3v2 tag of 10 bytes, and in a WAV file.
+
+    The side information of the frames is all zeros, so they hold no main data
+    and decode to silence.
+    """
+    tag = b"ID3" + bytes([4, 0, 0, 0, 0, 0, 10]) + bytes(10)
+    frame = bytes([0xFF, 0xFB, 0x90, 0xC0]) + bytes(413)
+    write(os.path.join(SAMPLES, "mp3", "silence_mono.mp3"), tag + frame * 10)
+
+    # the same frames in the data chunk of a WAV file, WAVE_FORMAT_MPEGLAYER3
+    # with the MPEGLAYER3WAVEFORMAT extension declaring a codec delay of 1105
+    # samples, which the fact chunk excludes
+    fmt = struct.pack("<HHIIHH", 0x0055, 1, 44100, 16000, 1, 0)
+    fmt += struct.pack("<HHIHHH", 12, 1, 2, 417, 1, 1105)
+    fact = struct.pack("<I", 10 * 1152 - 1105)
+    body = b"WAVE"
+    body += b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    body += b"fact" + struct.pack("<I", len(fact)) + fact
+    body += b"data" + struct.pack("<I", len(frame) * 10) + frame * 10
+    write(os.path.join(SAMPLES, "wav", "mp3_mono.wav"), b"RIFF" + struct.pack("<I", len(body)) + body)
+
+
+def lame_info_frame(frames, delay, padding):
+    """An Info frame of a CBR stream of `frames` frames, with the LAME extension."""
+    side_info = bytes(17)
+    info = b"Info" + struct.pack(">II", 1, frames)
+    lame = b"LAME3.100" + bytes(12) + bytes([delay >> 4, (delay & 0x0F) << 4 | padding >> 8, padding & 0xFF])
+    body = side_info + info + lame
+    return bytes([0xFF, 0xFB, 0x90, 0xC0]) + body + bytes(413 - len(body))
+
+
+def generate_mp3_gapless():
+    """Two tracks as LAME writes them, each an Info frame followed by silent frames.
+
+    The encoder delay and padding of the LAME extension leave 9944 and 7016
+    samples of the 10 and 8 frames.
+    """
+    frame = bytes([0xFF, 0xFB, 0x90, 0xC0]) + bytes(413)
+    data = lame_info_frame(10, 576, 1000) + frame * 10
+    write(os.path.join(SAMPLES, "mp3", "gapless_track1_mono.mp3"), data)
+    data = lame_info_frame(8, 576, 1624) + frame * 8
+    write(os.path.join(SAMPLES, "mp3", "gapless_track2_mono.mp3"), data)
+
+
+if __name__ == "__main__":
+    generate_wav()
+    generate_wav_headers()
+    generate_flac()
+    generate_mp3()
+    generate_mp3_gapless()
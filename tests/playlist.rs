@@ -0,0 +1,143 @@
+//! Plays fixtures of `tests/samples` one after another with `playlist::Player`.
+//!
+//! The gapless fixtures are written by `tests/generate_fixtures.py`.
+
+// the helpers are unused if a format is compiled out
+#![cfg_attr(
+    not(all(feature = "flac", feature = "mp3")),
+    allow(dead_code, unused_imports)
+)]
+
+extern crate cauldron;
+
+use std::path::PathBuf;
+
+use cauldron::audio::AudioSegment;
+use cauldron::errors::Error;
+use cauldron::playlist::{Player, PlayerOptions};
+use cauldron::Result;
+
+fn path(file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "samples", file]
+        .iter()
+        .collect()
+}
+
+/// Plays `files` and returns the samples and the frames the tracks start at
+fn play<S: cauldron::format::Sample + 'static>(
+    files: &[&str],
+    options: PlayerOptions,
+) -> Result<(Vec<S>, Vec<u64>)> {
+    let paths: Vec<PathBuf> = files.iter().map(|file| path(file)).collect();
+    let mut player = Player::<S>::open(&paths, options)?;
+    let mut samples = Vec::new();
+    let mut starts = Vec::new();
+    while let Some(sample) = player.next() {
+        samples.push(sample?);
+        if let Some(change) = player.take_track_change() {
+            assert_eq!(change.index, starts.len());
+            assert_eq!(change.track.path, paths[change.index]);
+            starts.push(change.frame);
+        }
+    }
+    Ok((samples, starts))
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn flac_tracks_join_bit_exactly() {
+    let tracks = [
+        "flac/gapless_track1_stereo_16.flac",
+        "flac/gapless_track2_stereo_16.flac",
+    ];
+    let (joined, starts) = play::<i32>(&tracks, PlayerOptions::default()).unwrap();
+    assert_eq!(starts, vec![0, 4096 + 1500]);
+
+    let album = path("flac/gapless_album_stereo_16.flac");
+    let mut album = AudioSegment::read(album.to_str().unwrap()).unwrap();
+    let album: Vec<i32> = album.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(joined.len(), album.len());
+    // the null test, the difference to the recording is silence
+    let first_difference = joined.iter().zip(&album).position(|(a, b)| a != b);
+    assert_eq!(first_difference, None);
+}
+
+#[test]
+#[cfg(feature = "mp3")]
+fn mp3_tracks_join_without_their_delay_and_padding() {
+    let track = path("mp3/gapless_track1_mono.mp3");
+    let segment = AudioSegment::read(track.to_str().unwrap()).unwrap();
+    // the Info frame and the delay of LAME and of the decoder
+    assert_eq!(segment.info().encoder_delay, Some(1152 + 576 + 529));
+    assert_eq!(segment.info().encoder_padding, Some(1000 - 529));
+    assert_eq!(segment.info().total_samples, 11 * 1152);
+    assert_eq!(segment.info().sample_rate, 44_100);
+
+    let tracks = ["mp3/gapless_track1_mono.mp3", "mp3/gapless_track2_mono.mp3"];
+    let (joined, starts) = play::<f32>(&tracks, PlayerOptions::default()).unwrap();
+    assert_eq!(starts, vec![0, 9944]);
+    assert_eq!(joined.len(), 9944 + 7016);
+    // silence throughout, neither the priming nor the padding click in between
+    assert!(joined.iter().all(|&s| s == 0.0));
+}
+
+#[test]
+#[cfg(all(feature = "flac", feature = "mp3"))]
+fn tracks_which_can_not_be_joined_are_rejected() {
+    let rejected = |tracks: &[&str], options: PlayerOptions| match play::<f32>(tracks, options) {
+        Err(Error::TrackMismatch {
+            track,
+            expected,
+            found,
+        }) => (track, expected.channels, found.channels),
+        other => panic!("{:?} are joined: {:?}", tracks, other.map(|_| ())),
+    };
+    let stereo = "flac/gapless_track1_stereo_16.flac";
+    let options = PlayerOptions::default();
+    assert_eq!(
+        rejected(&[stereo, "mp3/gapless_track1_mono.mp3"], options),
+        (1, 2, 1)
+    );
+    // 48 kHz after 44.1 kHz, unless resampled
+    assert_eq!(
+        rejected(&[stereo, "flac/lpc_stereo_24.flac"], options),
+        (1, 2, 2)
+    );
+    // MP3 decodes to floats only
+    let mp3 = [path("mp3/gapless_track1_mono.mp3")];
+    assert!(matches!(
+        Player::<i32>::open(&mp3, options),
+        Err(Error::TrackMismatch { track: 0, .. })
+    ));
+    assert!(matches!(
+        Player::<f32>::open(&[] as &[PathBuf], options),
+        Err(Error::Unsupported(_))
+    ));
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn tracks_are_resampled_to_the_sample_rate_of_the_stream() {
+    let options = PlayerOptions {
+        sample_rate: Some(44_100),
+        resample: true,
+        ..PlayerOptions::default()
+    };
+    // 4708 inter-channel samples at 48 kHz, then 5596 at 44.1 kHz
+    let tracks = [
+        "flac/lpc_stereo_24.flac",
+        "flac/gapless_track1_stereo_16.flac",
+    ];
+    let (joined, starts) = play::<f32>(&tracks, options).unwrap();
+    // the last output sample is at or before the last input sample
+    let resampled = 4707 * 44_100 / 48_000 + 1;
+    assert_eq!(starts, vec![0, resampled]);
+    assert_eq!(joined.len() as u64, (resampled + 5596) * 2);
+
+    // integer samples can not be resampled
+    let paths: Vec<PathBuf> = tracks.iter().map(|file| path(file)).collect();
+    assert!(matches!(
+        Player::<i32>::open(&paths, options),
+        Err(Error::TrackMismatch { track: 0, .. })
+    ));
+}
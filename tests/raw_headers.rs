@@ -0,0 +1,85 @@
+//! Reads the fixtures in `tests/samples` with `DecodeOptions::keep_raw_headers`
+//! and compares the kept bytes against the files byte for byte.
+
+// the helpers are unused if a format is compiled out
+#![cfg_attr(
+    not(all(feature = "wav", feature = "flac")),
+    allow(dead_code, unused_imports)
+)]
+
+extern crate cauldron;
+
+use std::fs;
+use std::path::PathBuf;
+
+use cauldron::audio::{AudioSegment, DecodeOptions, RawHeaders};
+use cauldron::codecs::FormatFlag;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+fn raw_headers(fixture: &str, flag: FormatFlag) -> (Vec<u8>, RawHeaders) {
+    let file = path("samples", fixture);
+    let options = DecodeOptions {
+        keep_raw_headers: true,
+        ..Default::default()
+    };
+    let segment = AudioSegment::read_with_options(file.as_path(), flag, options).unwrap();
+    let raw = segment.raw_headers().expect("raw headers are kept");
+    (fs::read(file).unwrap(), raw)
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn wav_fmt_chunks_are_kept() {
+    let fixtures = [
+        "wav/s16_mono.wav",
+        "wav/s24_stereo.wav",
+        "wav/f32_stereo.wav",
+        "wav/s16_5_1.wav",
+    ];
+    for fixture in &fixtures {
+        let (file, raw) = raw_headers(fixture, FormatFlag::WAV);
+        // the fixtures start with the fmt chunk
+        assert_eq!(&file[12..16], b"fmt ");
+        let len = u32::from_le_bytes([file[16], file[17], file[18], file[19]]) as usize;
+        let fmt = raw.fmt_chunk.as_ref().expect("a fmt chunk");
+        assert_eq!(fmt[..], file[20..20 + len], "{}", fixture);
+        assert!(raw.unknown_chunks.is_empty(), "{}", fixture);
+        assert!(raw.metadata_blocks.is_empty(), "{}", fixture);
+    }
+}
+
+#[test]
+#[cfg(feature = "flac")]
+fn flac_metadata_blocks_are_kept() {
+    for fixture in &["flac/fixed_mono_16.flac", "flac/lpc_stereo_24.flac"] {
+        let (file, raw) = raw_headers(fixture, FormatFlag::FLAC);
+        assert_eq!(raw.metadata_blocks[0].block_type, 0, "{}", fixture);
+        let mut offset = 4;
+        for block in &raw.metadata_blocks {
+            let header = &file[offset..offset + 4];
+            let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+            assert_eq!(block.offset, offset as u64, "{}", fixture);
+            assert_eq!(block.block_type, header[0] & 0x7f, "{}", fixture);
+            assert_eq!(block.len, len, "{}", fixture);
+            let payload = &file[offset + 4..offset + 4 + len as usize];
+            assert_eq!(block.payload.as_deref(), Some(payload), "{}", fixture);
+            offset += 4 + len as usize;
+        }
+        // every block is kept, the frames start with a sync code
+        assert_eq!(&file[offset..offset + 2], &[0xff, 0xf8], "{}", fixture);
+        assert!(raw.fmt_chunk.is_none());
+    }
+}
+
+#[test]
+#[cfg(feature = "wav")]
+fn raw_headers_are_off_by_default() {
+    let file = path("samples", "wav/s16_stereo.wav");
+    let segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+    assert_eq!(segment.raw_headers(), None);
+}
@@ -0,0 +1,124 @@
+//! Writes the WAV fixtures in `tests/samples` as FLAC files, reads them back
+//! and writes them as WAV files again. Integer audio has to come back with
+//! the same samples and the same WAV file, and the MD5 signatures have to match.
+
+#![cfg(all(feature = "wav", feature = "flac"))]
+
+extern crate cauldron;
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use cauldron::audio::AudioSegment;
+use cauldron::codecs::{CodecType, FormatFlag};
+use cauldron::dither::Dither;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+fn read(fixture: &str) -> AudioSegment {
+    let file = path("samples", fixture);
+    AudioSegment::read(file.to_str().unwrap()).expect("a valid fixture")
+}
+
+/// Writes `fixture` as FLAC and returns the stream
+fn to_flac(fixture: &str) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    read(fixture).write_flac(&mut out, Dither::None).unwrap();
+    out.into_inner()
+}
+
+/// Writes `segment` as a WAV file in `codec`
+fn to_wav(segment: &mut AudioSegment, codec: CodecType) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    segment.write_wav(&mut out, codec, Dither::None).unwrap();
+    out.into_inner()
+}
+
+/// Checks the round trip of the integer `fixture`, which is written as WAV in `codec`
+fn check_round_trip(fixture: &str, codec: CodecType) {
+    let flac = to_flac(fixture);
+    let mut original = read(fixture);
+    let mut decoded =
+        AudioSegment::from_reader(Cursor::new(flac.clone()), FormatFlag::FLAC).unwrap();
+    let (info, expected_info) = (decoded.info().clone(), original.info().clone());
+    assert_eq!(info.sample_rate, expected_info.sample_rate, "{}", fixture);
+    assert_eq!(
+        info.bits_per_sample, expected_info.bits_per_sample,
+        "{}",
+        fixture
+    );
+    assert_eq!(
+        info.channels.count(),
+        expected_info.channels.count(),
+        "{}",
+        fixture
+    );
+
+    let samples: Vec<i32> = decoded.samples().unwrap().map(|s| s.unwrap()).collect();
+    let expected: Vec<i32> = original.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected, "{}: samples differ", fixture);
+    assert_eq!(info.total_samples, expected.len() as u64, "{}", fixture);
+
+    let mut decoded =
+        AudioSegment::from_seekable_reader(Cursor::new(flac.clone()), FormatFlag::FLAC).unwrap();
+    let report = decoded.verify().unwrap();
+    assert_eq!(report.md5_matched, Some(true), "{}", fixture);
+
+    let mut decoded = AudioSegment::from_reader(Cursor::new(flac), FormatFlag::FLAC).unwrap();
+    let wav = to_wav(&mut decoded, codec);
+    assert!(
+        wav == to_wav(&mut read(fixture), codec),
+        "{}: WAV files differ",
+        fixture
+    );
+}
+
+#[test]
+fn integer_wav_round_trips_bit_exactly() {
+    let fixtures = [
+        ("wav/s16_mono.wav", CodecType::CODEC_TYPE_PCM_S16LE),
+        ("wav/s16_stereo.wav", CodecType::CODEC_TYPE_PCM_S16LE),
+        ("wav/s16_5_1.wav", CodecType::CODEC_TYPE_PCM_S16LE),
+        ("wav/s24_mono.wav", CodecType::CODEC_TYPE_PCM_S24LE),
+        ("wav/s24_stereo.wav", CodecType::CODEC_TYPE_PCM_S24LE),
+        ("wav/s24in32_stereo.wav", CodecType::CODEC_TYPE_PCM_S24LE),
+        ("wav/s20in24_mono.wav", CodecType::CODEC_TYPE_PCM_S24LE),
+        ("wav/s12in16_mono.wav", CodecType::CODEC_TYPE_PCM_S16LE),
+        ("wav/alaw_mono.wav", CodecType::CODEC_TYPE_PCM_S16LE),
+        ("wav/ulaw_mono.wav", CodecType::CODEC_TYPE_PCM_S16LE),
+    ];
+    for &(fixture, codec) in &fixtures {
+        check_round_trip(fixture, codec);
+    }
+}
+
+#[test]
+fn float_wav_is_written_with_24_bits() {
+    let flac = to_flac("wav/f32_stereo.wav");
+    let mut decoded = AudioSegment::from_reader(Cursor::new(flac), FormatFlag::FLAC).unwrap();
+    assert_eq!(decoded.info().bits_per_sample, 24);
+    let samples: Vec<f32> = decoded.samples().unwrap().map(|s| s.unwrap()).collect();
+    let expected: Vec<f32> = read("wav/f32_stereo.wav")
+        .samples()
+        .unwrap()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(samples.len(), expected.len());
+    for (sample, expected) in samples.iter().zip(&expected) {
+        assert!((sample - expected.clamp(-1.0, 1.0)).abs() <= 1.0 / (1 << 23) as f32);
+    }
+}
+
+#[test]
+fn export_flac_writes_a_file() {
+    let out = std::env::temp_dir().join(format!("cauldron-export-{}.flac", std::process::id()));
+    let written = read("wav/s16_stereo.wav").export_flac(&out).unwrap();
+    let mut exported = AudioSegment::read(out.to_str().unwrap()).unwrap();
+    let samples = exported.samples::<i16>().unwrap().count() as u64;
+    std::fs::remove_file(&out).unwrap();
+    assert_eq!(samples, written);
+}
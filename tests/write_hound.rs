@@ -0,0 +1,226 @@
+//! Writes the fixtures in `tests/samples` with `hound::WavWriter` and reads
+//! them back, they have to decode like the WAV files written by
+//! `AudioSegment::write_wav`.
+
+#![cfg(all(feature = "hound", feature = "wav", feature = "flac"))]
+
+extern crate cauldron;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use cauldron::audio::AudioSegment;
+use cauldron::codecs::{CodecType, FormatFlag};
+use cauldron::dither::Dither;
+use cauldron::errors::Error;
+use cauldron::hound::{wav_spec, write_with_hound};
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+/// A file in the temporary directory, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        let name = format!("cauldron-hound-{}-{}.wav", std::process::id(), name);
+        TempFile(std::env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn read(fixture: &str) -> AudioSegment {
+    let file = path("samples", fixture);
+    AudioSegment::read(file.to_str().unwrap()).expect("a valid fixture")
+}
+
+/// Writes `fixture` with hound and with `AudioSegment::write_wav` in `codec`
+///
+/// Both files are read back, they have to hold the same samples in the same
+/// format. Returns the file written by hound.
+fn check(fixture: &str, spec: Option<hound::WavSpec>, codec: CodecType) -> AudioSegment {
+    let file = TempFile::new(&fixture.replace('/', "-"));
+    write_with_hound(&mut read(fixture), &file.0, spec).unwrap();
+    let mut written = AudioSegment::read(file.0.to_str().unwrap()).unwrap();
+
+    let mut out = Cursor::new(Vec::new());
+    read(fixture)
+        .write_wav(&mut out, codec, Dither::None)
+        .unwrap();
+    out.set_position(0);
+    let mut expected = AudioSegment::from_reader(out, FormatFlag::WAV).unwrap();
+
+    let (info, expected_info) = (written.info(), expected.info());
+    assert_eq!(info.codec_type, expected_info.codec_type, "{}", fixture);
+    assert_eq!(info.sample_rate, expected_info.sample_rate, "{}", fixture);
+    assert_eq!(
+        info.bits_per_sample, expected_info.bits_per_sample,
+        "{}",
+        fixture
+    );
+    assert_eq!(
+        info.channels.count(),
+        expected_info.channels.count(),
+        "{}",
+        fixture
+    );
+    assert_eq!(
+        info.total_samples, expected_info.total_samples,
+        "{}",
+        fixture
+    );
+    if codec == CodecType::CODEC_TYPE_PCM_F32LE {
+        let samples: Vec<f32> = written.samples().unwrap().map(|s| s.unwrap()).collect();
+        let reference: Vec<f32> = expected.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert!(samples == reference, "{}", fixture);
+    } else {
+        let samples: Vec<i32> = written.samples().unwrap().map(|s| s.unwrap()).collect();
+        let reference: Vec<i32> = expected.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert!(samples == reference, "{}", fixture);
+    }
+    drop(file);
+    written
+}
+
+#[test]
+fn wav_spec_keeps_the_precision() {
+    let spec = |fixture: &str| wav_spec(read(fixture).info()).unwrap();
+    let int = |channels, bits_per_sample| hound::WavSpec {
+        channels,
+        sample_rate: 44_100,
+        bits_per_sample,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    assert_eq!(spec("wav/s16_stereo.wav"), int(2, 16));
+    assert_eq!(spec("wav/s24_stereo.wav"), int(2, 24));
+    assert_eq!(spec("wav/s16_5_1.wav"), int(6, 16));
+    assert_eq!(
+        spec("flac/lpc_stereo_24.flac"),
+        hound::WavSpec {
+            sample_rate: 48_000,
+            ..int(2, 24)
+        }
+    );
+    assert_eq!(
+        spec("wav/f32_stereo.wav"),
+        hound::WavSpec {
+            sample_format: hound::SampleFormat::Float,
+            bits_per_sample: 32,
+            ..int(2, 16)
+        }
+    );
+}
+
+#[test]
+fn hound_writes_the_samples_of_the_fixtures() {
+    check("wav/s16_stereo.wav", None, CodecType::CODEC_TYPE_PCM_S16LE);
+    check("wav/s24_stereo.wav", None, CodecType::CODEC_TYPE_PCM_S24LE);
+    check("wav/f32_stereo.wav", None, CodecType::CODEC_TYPE_PCM_F32LE);
+    check(
+        "flac/lpc_stereo_24.flac",
+        None,
+        CodecType::CODEC_TYPE_PCM_S24LE,
+    );
+    check(
+        "flac/fixed_mono_16.flac",
+        None,
+        CodecType::CODEC_TYPE_PCM_S16LE,
+    );
+
+    // hound writes WAVEFORMATEXTENSIBLE with the default channel mask
+    let segment = check("wav/s16_5_1.wav", None, CodecType::CODEC_TYPE_PCM_S16LE);
+    assert_eq!(
+        segment.info().channels,
+        read("wav/s16_5_1.wav").info().channels
+    );
+}
+
+#[test]
+fn hound_writes_in_the_spec_given() {
+    // the spec of the fixture in other bits per sample
+    let spec = |fixture: &str, bits_per_sample, sample_format| {
+        Some(hound::WavSpec {
+            bits_per_sample,
+            sample_format,
+            ..wav_spec(read(fixture).info()).unwrap()
+        })
+    };
+    let (int, float) = (hound::SampleFormat::Int, hound::SampleFormat::Float);
+
+    // requantized like the writer of this crate does
+    let checks = [
+        (
+            "wav/s24_stereo.wav",
+            16,
+            int,
+            CodecType::CODEC_TYPE_PCM_S16LE,
+        ),
+        (
+            "wav/s16_stereo.wav",
+            32,
+            int,
+            CodecType::CODEC_TYPE_PCM_S32LE,
+        ),
+        ("wav/s16_stereo.wav", 8, int, CodecType::CODEC_TYPE_PCM_U8),
+        (
+            "wav/f32_stereo.wav",
+            24,
+            int,
+            CodecType::CODEC_TYPE_PCM_S24LE,
+        ),
+        (
+            "flac/lpc_stereo_24.flac",
+            32,
+            float,
+            CodecType::CODEC_TYPE_PCM_F32LE,
+        ),
+    ];
+    for (fixture, bits_per_sample, sample_format, codec) in checks {
+        check(
+            fixture,
+            spec(fixture, bits_per_sample, sample_format),
+            codec,
+        );
+    }
+
+    // what hound can not write fails instead of panicking
+    let file = TempFile::new("unsupported");
+    let fixture = "wav/s16_stereo.wav";
+    let mono = Some(hound::WavSpec {
+        channels: 1,
+        ..spec(fixture, 16, int).unwrap()
+    });
+    for spec in [spec(fixture, 64, float), spec(fixture, 12, int), mono] {
+        let result = write_with_hound(&mut read(fixture), &file.0, spec);
+        assert!(matches!(result, Err(Error::Unsupported(_))), "{:?}", spec);
+    }
+}
+
+#[test]
+fn hound_writes_unsigned_samples_unchanged() {
+    let unsigned = TempFile::new("u8");
+    let mut out = fs::File::create(&unsigned.0).unwrap();
+    read("wav/s16_stereo.wav")
+        .write_wav(&mut out, CodecType::CODEC_TYPE_PCM_U8, Dither::None)
+        .unwrap();
+    drop(out);
+    let file = TempFile::new("u8-hound");
+    let read_temp = |file: &TempFile| AudioSegment::read(file.0.to_str().unwrap()).unwrap();
+    write_with_hound(&mut read_temp(&unsigned), &file.0, None).unwrap();
+
+    let (mut written, mut expected) = (read_temp(&file), read_temp(&unsigned));
+    assert_eq!(written.info().codec_type, CodecType::CODEC_TYPE_PCM_U8);
+    let samples: Vec<i32> = written.samples().unwrap().map(|s| s.unwrap()).collect();
+    let reference: Vec<i32> = expected.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert!(samples == reference);
+}
@@ -0,0 +1,282 @@
+//! Writes the fixtures in `tests/samples` as WAV files and compares their
+//! headers byte for byte against the ones in `tests/reference/wav_headers`.
+//!
+//! The reference headers are written by `tests/generate_fixtures.py` after the
+//! WAVEFORMATEX and WAVEFORMATEXTENSIBLE specifications, like FFmpeg and SoX
+//! expect them.
+
+#![cfg(feature = "wav")]
+
+extern crate cauldron;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use cauldron::audio::{AudioSegment, ChannelLayout, ChannelMap, Channels, WavOptions};
+use cauldron::codecs::{CodecType, FormatFlag};
+use cauldron::dither::Dither;
+use cauldron::WavWriter;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file]
+        .iter()
+        .collect()
+}
+
+/// Writes `fixture` in `codec` and checks the header against `reference`
+///
+/// The written file is read back, and has to hold the samples of the fixture.
+fn check(fixture: &str, codec: CodecType, options: WavOptions, reference: &str) {
+    let file = path("samples", fixture);
+    let mut segment = AudioSegment::read(file.to_str().unwrap()).expect("a valid fixture");
+    let mut out = Cursor::new(Vec::new());
+    segment
+        .write_wav_with_options(&mut out, codec, Dither::None, options)
+        .unwrap();
+    let out = out.into_inner();
+
+    let header = fs::read(path("reference", &format!("wav_headers/{}.hdr", reference)))
+        .expect("reference header");
+    assert!(
+        out.starts_with(&header),
+        "{}: header differs from {}",
+        fixture,
+        reference
+    );
+
+    let mut original = AudioSegment::read(file.to_str().unwrap()).unwrap();
+    let mut written = AudioSegment::from_reader(Cursor::new(out), FormatFlag::WAV).unwrap();
+    assert_eq!(
+        written.info().channels,
+        options.channels.unwrap_or(original.info().channels)
+    );
+    let expected: Vec<f32> = original.samples().unwrap().map(|s| s.unwrap()).collect();
+    let decoded: Vec<f32> = written.samples().unwrap().map(|s| s.unwrap()).collect();
+    assert_eq!(decoded, expected, "{}: samples differ", fixture);
+}
+
+#[test]
+fn basic_fmt_for_16_bit_stereo() {
+    let options = WavOptions::default();
+    check(
+        "wav/s16_stereo.wav",
+        CodecType::CODEC_TYPE_PCM_S16LE,
+        options,
+        "basic_s16_stereo",
+    );
+}
+
+#[test]
+fn extensible_fmt_for_24_bit() {
+    let options = WavOptions::default();
+    check(
+        "wav/s24_stereo.wav",
+        CodecType::CODEC_TYPE_PCM_S24LE,
+        options,
+        "ext_s24_stereo",
+    );
+}
+
+#[test]
+fn basic_fmt_forced_for_24_bit() {
+    let options = WavOptions {
+        force_basic_fmt: true,
+        ..WavOptions::default()
+    };
+    check(
+        "wav/s24_stereo.wav",
+        CodecType::CODEC_TYPE_PCM_S24LE,
+        options,
+        "basic_s24_stereo",
+    );
+}
+
+#[test]
+fn extensible_fmt_for_float() {
+    let options = WavOptions::default();
+    check(
+        "wav/f32_stereo.wav",
+        CodecType::CODEC_TYPE_PCM_F32LE,
+        options,
+        "ext_f32_stereo",
+    );
+}
+
+#[test]
+fn extensible_fmt_for_5_1() {
+    let options = WavOptions::default();
+    check(
+        "wav/s16_5_1.wav",
+        CodecType::CODEC_TYPE_PCM_S16LE,
+        options,
+        "ext_s16_5_1",
+    );
+}
+
+#[test]
+fn extensible_fmt_for_channel_mask() {
+    let options = WavOptions {
+        channels: Some(Channels::FRONT_CENTRE),
+        ..WavOptions::default()
+    };
+    check(
+        "wav/s16_mono.wav",
+        CodecType::CODEC_TYPE_PCM_S16LE,
+        options,
+        "ext_s16_mono_centre",
+    );
+}
+
+#[test]
+fn channel_mask_has_to_match() {
+    let file = path("samples", "wav/s16_stereo.wav");
+    let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+    let options = WavOptions {
+        channels: Some(Channels::FRONT_CENTRE),
+        ..WavOptions::default()
+    };
+    let mut out = Cursor::new(Vec::new());
+    let codec = CodecType::CODEC_TYPE_PCM_S16LE;
+    assert!(segment
+        .write_wav_with_options(&mut out, codec, Dither::None, options)
+        .is_err());
+}
+
+#[test]
+fn extensible_g711_is_read_back() {
+    let file = path("samples", "wav/s16_5_1.wav");
+    for codec in &[
+        CodecType::CODEC_TYPE_PCM_ALAW,
+        CodecType::CODEC_TYPE_PCM_MULAW,
+    ] {
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        segment.write_wav(&mut out, *codec, Dither::None).unwrap();
+        let written = AudioSegment::from_reader(Cursor::new(out.into_inner()), FormatFlag::WAV);
+        let written = written.unwrap();
+        assert_eq!(written.info().codec_type, *codec);
+        assert_eq!(written.number_channels(), 6);
+    }
+}
+
+#[test]
+fn float_sources_are_written_as_integer_pcm() {
+    let mut fixtures = vec!["wav/f32_mono.wav"];
+    if cfg!(feature = "mp3") {
+        fixtures.push("mp3/silence_mono.mp3");
+    }
+    for fixture in fixtures {
+        let file = path("samples", fixture);
+        let mut segment = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        segment
+            .write_wav(&mut out, CodecType::CODEC_TYPE_PCM_S16LE, Dither::None)
+            .unwrap();
+
+        let mut original = AudioSegment::read(file.to_str().unwrap()).unwrap();
+        let mut written = AudioSegment::from_reader(Cursor::new(out.into_inner()), FormatFlag::WAV)
+            .expect("a valid WAV file");
+        assert_eq!(written.info().bits_per_sample, 16);
+        let expected: Vec<f32> = original.samples().unwrap().map(|s| s.unwrap()).collect();
+        let decoded: Vec<f32> = written.samples().unwrap().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded.len(), expected.len(), "{}", fixture);
+        for (d, e) in decoded.iter().zip(&expected) {
+            // rounded to the nearest step of 16 bits, and clipped
+            assert!((d - e.clamp(-1.0, 32_767.0 / 32_768.0)).abs() <= 0.5 / 32_768.0);
+        }
+    }
+}
+
+/// Returns a 5.1 file of `frames` frames, each channel holding a DC value of 1000 times its number
+fn dc_5_1(frames: usize) -> Vec<u8> {
+    let speakers = ChannelLayout::FivePointOne.into_channels();
+    let codec = CodecType::CODEC_TYPE_PCM_S16LE;
+    let out = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(out, codec, speakers, 48_000, WavOptions::default()).unwrap();
+    let frame = [1000, 2000, 3000, 4000, 5000, 6000];
+    for _ in 0..frames {
+        writer.write_samples(&frame).unwrap();
+    }
+    writer.finalize().unwrap().into_inner()
+}
+
+/// Writes `file` with `options`, returning the file
+fn remap(file: &[u8], options: WavOptions) -> cauldron::Result<Vec<u8>> {
+    let mut segment = AudioSegment::from_reader(Cursor::new(file.to_vec()), FormatFlag::WAV)?;
+    let mut out = Cursor::new(Vec::new());
+    let codec = CodecType::CODEC_TYPE_PCM_S16LE;
+    segment.write_wav_with_options(&mut out, codec, Dither::None, options)?;
+    Ok(out.into_inner())
+}
+
+/// Returns the data chunk of `frames` frames of the DC `values`
+fn dc_data(values: &[i16], frames: usize) -> Vec<u8> {
+    let frame: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    frame.repeat(frames)
+}
+
+#[test]
+fn channel_map_reorders_channels() {
+    let file = dc_5_1(10);
+
+    // SMPTE to film order, with the speaker positions given
+    let options = WavOptions {
+        channels: Some(ChannelLayout::FivePointOne.into_channels()),
+        channel_map: Some(ChannelMap::new(&[0, 2, 1, 4, 5, 3]).unwrap()),
+        ..WavOptions::default()
+    };
+    let out = remap(&file, options).unwrap();
+    assert_eq!(out[..68], file[..68]);
+    let expected = dc_data(&[1000, 3000, 2000, 5000, 6000, 4000], 10);
+    assert_eq!(out[68..], expected[..]);
+
+    // the front channels only, which keep their speaker positions
+    let options = WavOptions {
+        channel_map: Some(ChannelMap::new(&[0, 1, 2]).unwrap()),
+        ..WavOptions::default()
+    };
+    let out = remap(&file, options).unwrap();
+    let written = AudioSegment::from_reader(Cursor::new(out.clone()), FormatFlag::WAV).unwrap();
+    assert_eq!(
+        written.info().channels,
+        Channels::FRONT_LEFT | Channels::FRONT_RIGHT | Channels::FRONT_CENTRE
+    );
+    assert_eq!(
+        u32::from_le_bytes([out[40], out[41], out[42], out[43]]),
+        0x7
+    );
+    assert_eq!(out[68..], dc_data(&[1000, 2000, 3000], 10)[..]);
+
+    // the centre twice, as stereo
+    let options = WavOptions {
+        channels: Some(ChannelLayout::Stereo.into_channels()),
+        channel_map: Some(ChannelMap::new(&[2, 2]).unwrap()),
+        ..WavOptions::default()
+    };
+    let out = remap(&file, options).unwrap();
+    assert_eq!(out[44..], dc_data(&[3000, 3000], 10)[..]);
+}
+
+#[test]
+fn channel_map_is_validated() {
+    let file = dc_5_1(1);
+    assert!(ChannelMap::new(&[]).is_err());
+    assert!(ChannelMap::new(&[0; 33]).is_err());
+
+    for sources in &[&[0, 6][..], &[1, 0], &[0, 0]] {
+        let options = WavOptions {
+            channel_map: Some(ChannelMap::new(sources).unwrap()),
+            ..WavOptions::default()
+        };
+        assert!(remap(&file, options).is_err(), "{:?}", sources);
+    }
+
+    // the speaker positions have to match the output channels
+    let options = WavOptions {
+        channels: Some(ChannelLayout::Stereo.into_channels()),
+        channel_map: Some(ChannelMap::new(&[2]).unwrap()),
+        ..WavOptions::default()
+    };
+    assert!(remap(&file, options).is_err());
+}